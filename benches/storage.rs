@@ -8,7 +8,7 @@ extern crate trackable;
 
 use cannyls::lump::{LumpData, LumpId};
 use cannyls::nvm::{FileNvm, MemoryNvm};
-use cannyls::storage::StorageBuilder;
+use cannyls::storage::{Storage, StorageBuilder};
 use tempdir::TempDir;
 use test::Bencher;
 
@@ -103,3 +103,63 @@ fn memory_put_and_get_and_delete_small(b: &mut Bencher) {
         i += 1;
     });
 }
+
+fn fragmented_storage(fragments: usize, fragment_threshold: u16) -> Storage<MemoryNvm> {
+    let nvm = MemoryNvm::new(vec![0; 256 * 1024 * 1024]);
+    let mut storage = track_try_unwrap!(StorageBuilder::new()
+        .journal_region_ratio(0.2)
+        .free_fragment_threshold(fragment_threshold)
+        .create(nvm));
+
+    // 1ブロックの空き領域が`fragments`個並ぶように、一つおきにlumpを削除する
+    let data = track_try_unwrap!(storage.allocate_lump_data_with_bytes(b"foo"));
+    for i in 0..fragments * 2 {
+        track_try_unwrap!(storage.put(&id(i), &data));
+    }
+    for i in (0..fragments * 2).step_by(2) {
+        track_try_unwrap!(storage.delete(&id(i)));
+    }
+    storage
+}
+
+fn bench_fragmented_put_and_delete(b: &mut Bencher, fragments: usize, fragment_threshold: u16) {
+    let mut storage = fragmented_storage(fragments, fragment_threshold);
+    let mut i = fragments * 2;
+    let data = track_try_unwrap!(storage.allocate_lump_data_with_bytes(&[0; 1000]));
+    b.iter(|| {
+        let id = id(i);
+        track_try_unwrap!(storage.put(&id, &data));
+        track_try_unwrap!(storage.delete(&id));
+        i += 1;
+    });
+}
+
+#[bench]
+fn memory_fragmented_put_and_delete_1k(b: &mut Bencher) {
+    bench_fragmented_put_and_delete(b, 1 << 10, 0);
+}
+
+#[bench]
+fn memory_fragmented_put_and_delete_8k(b: &mut Bencher) {
+    bench_fragmented_put_and_delete(b, 1 << 13, 0);
+}
+
+#[bench]
+fn memory_fragmented_put_and_delete_64k(b: &mut Bencher) {
+    bench_fragmented_put_and_delete(b, 1 << 16, 0);
+}
+
+#[bench]
+fn memory_fragmented_put_and_delete_1k_isolated(b: &mut Bencher) {
+    bench_fragmented_put_and_delete(b, 1 << 10, 2);
+}
+
+#[bench]
+fn memory_fragmented_put_and_delete_8k_isolated(b: &mut Bencher) {
+    bench_fragmented_put_and_delete(b, 1 << 13, 2);
+}
+
+#[bench]
+fn memory_fragmented_put_and_delete_64k_isolated(b: &mut Bencher) {
+    bench_fragmented_put_and_delete(b, 1 << 16, 2);
+}
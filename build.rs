@@ -0,0 +1,15 @@
+use std::env;
+
+/// `std`を必要とするフィーチャー群.
+const STD_FEATURES: &[&str] = &["DEVICE", "SERDE", "ARBITRARY", "TOML", "RECOVERY"];
+
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(cannyls_std)");
+
+    // `format-only`フィーチャーは、`std`を必要とするフィーチャーが一つも有効になっていない場合にのみ効果を持つ
+    // (i.e., フィーチャーの統合によって、他の依存クレートが必要とする機能が消えてしまうことはない)
+    let is_enabled = |name: &str| env::var_os(format!("CARGO_FEATURE_{}", name)).is_some();
+    if !is_enabled("FORMAT_ONLY") || STD_FEATURES.iter().any(|f| is_enabled(f)) {
+        println!("cargo:rustc-cfg=cannyls_std");
+    }
+}
@@ -15,6 +15,7 @@ impl AlignedBytes {
     /// 新しい`AlignedBytes`インスタンスを生成する.
     ///
     /// 結果のバイト列の初期値は未定義.
+    #[allow(clippy::uninit_vec)]
     pub fn new(size: usize, block_size: BlockSize) -> Self {
         // バッファの前後をブロック境界に合わせて十分なだけの領域を確保しておく
         let capacity =
@@ -82,7 +83,7 @@ impl AlignedBytes {
         if new_capacity > self.buf.len() - self.offset {
             let mut new_buf = vec![0; new_capacity + self.block_size.as_u16() as usize - 1];
             let new_offset = alignment_offset(&new_buf, self.block_size);
-            (&mut new_buf[new_offset..][..self.len]).copy_from_slice(self.as_ref());
+            new_buf[new_offset..][..self.len].copy_from_slice(self.as_ref());
 
             self.buf = new_buf;
             self.offset = new_offset;
@@ -108,7 +109,7 @@ impl std::ops::DerefMut for AlignedBytes {
 }
 impl AsRef<[u8]> for AlignedBytes {
     fn as_ref(&self) -> &[u8] {
-        &*self
+        self
     }
 }
 impl AsMut<[u8]> for AlignedBytes {
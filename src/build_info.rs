@@ -0,0 +1,135 @@
+//! ビルド時の情報(クレートのバージョンや有効なフィーチャー等)を、実行時に取得するための機能.
+use core::fmt;
+
+#[cfg(cannyls_std)]
+use crate::storage::journal::JournalRegionOptions;
+#[cfg(cannyls_std)]
+use crate::storage::{MAJOR_VERSION, MINOR_VERSION};
+
+/// クレートのバージョン(`major.minor.patch`).
+///
+/// ストレージのヘッダには、それを書き込んだクレートのバージョンとして記録される
+/// (`StorageHeader::writer_version`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CrateVersion {
+    /// メジャーバージョン.
+    pub major: u16,
+
+    /// マイナーバージョン.
+    pub minor: u16,
+
+    /// パッチバージョン.
+    pub patch: u16,
+}
+impl CrateVersion {
+    /// ビルドされたクレート自身のバージョン.
+    pub const CURRENT: CrateVersion = CrateVersion {
+        major: parse_u16(env!("CARGO_PKG_VERSION_MAJOR")),
+        minor: parse_u16(env!("CARGO_PKG_VERSION_MINOR")),
+        patch: parse_u16(env!("CARGO_PKG_VERSION_PATCH")),
+    };
+}
+impl fmt::Display for CrateVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// ビルド時の情報.
+///
+/// [`build_info`](./fn.build_info.html)関数で取得できる.
+#[cfg(cannyls_std)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BuildInfo {
+    /// クレートのバージョン.
+    pub version: CrateVersion,
+
+    /// 有効になっているフィーチャーの名前一覧.
+    ///
+    /// このクレート自身が定義しているフィーチャー(e.g., `device`や`serde`)のみが対象となる.
+    pub features: Vec<&'static str>,
+
+    /// ジャーナルの書き込みバッファのフラッシュ時に、書き出し順序を保証するかどうかのデフォルト値.
+    ///
+    /// 詳細は`StorageBuilder::journal_safe_flush`を参照のこと.
+    pub default_journal_safe_flush: bool,
+
+    /// 扱うことが可能なストレージフォーマット(lusf)のメジャーバージョン.
+    pub format_major_version: u16,
+
+    /// 扱うことが可能なストレージフォーマット(lusf)のマイナーバージョンの最大値.
+    ///
+    /// マイナーバージョンには後方互換性があるため、`0`からこの値までのストレージを扱うことができる.
+    /// また、新規に作成されるストレージのバージョンは、常にこの値となる.
+    pub format_minor_version: u16,
+}
+
+/// ビルド時の情報を返す.
+///
+/// 障害調査時等に、実行中のプログラムに組み込まれているクレートのバージョンや構成を確認するために使用する.
+///
+/// # Examples
+///
+/// ```
+/// let info = cannyls::build_info();
+/// assert_eq!(info.version.to_string(), env!("CARGO_PKG_VERSION"));
+/// assert_eq!(info.format_major_version, cannyls::storage::MAJOR_VERSION);
+/// ```
+#[cfg(cannyls_std)]
+pub fn build_info() -> BuildInfo {
+    let mut features = Vec::new();
+    if cfg!(feature = "device") {
+        features.push("device");
+    }
+    if cfg!(feature = "debug-payload") {
+        features.push("debug-payload");
+    }
+    if cfg!(feature = "serde") {
+        features.push("serde");
+    }
+    if cfg!(feature = "arbitrary") {
+        features.push("arbitrary");
+    }
+    if cfg!(feature = "toml") {
+        features.push("toml");
+    }
+    if cfg!(feature = "recovery") {
+        features.push("recovery");
+    }
+    BuildInfo {
+        version: CrateVersion::CURRENT,
+        features,
+        default_journal_safe_flush: JournalRegionOptions::default().safe_flush,
+        format_major_version: MAJOR_VERSION,
+        format_minor_version: MINOR_VERSION,
+    }
+}
+
+const fn parse_u16(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        n = n * 10 + (bytes[i] - b'0') as u16;
+        i += 1;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_info_works() {
+        let info = build_info();
+        assert_eq!(info.version.to_string(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.features.contains(&"device"), cfg!(feature = "device"));
+        assert_eq!(info.features.contains(&"serde"), cfg!(feature = "serde"));
+        assert!(!info.default_journal_safe_flush);
+        assert_eq!(info.format_major_version, MAJOR_VERSION);
+        assert_eq!(info.format_minor_version, MINOR_VERSION);
+    }
+}
@@ -1,5 +1,5 @@
 //! デバイスに対する各種操作のデッドライン.
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// 各種操作のデッドラインを表現するためのオブジェクト.
 ///
@@ -12,11 +12,13 @@ use std::time::Duration;
 /// (e.g., リソースに余裕がない場合には、デッドラインが近いものから優先的に処理される)。
 ///
 /// なお、デッドラインが等しい場合には、先にデバイスに到着したリクエストの方が優先される.
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Default)]
 pub enum Deadline {
     /// 可能な限り早急に処理して欲しいリクエストに指定するデッドライン.
     ///
     /// `Within(Duration::from_secs(0))`よりも優先度は高い.
+    ///
+    /// PUTに指定された場合には、応答前に必ずジャーナルの同期が行われる(`Storage::put_with_deadline`).
     Immediate,
 
     /// 指定された時間以内での実行を期待するリクエストに指定するデッドライン.
@@ -30,13 +32,32 @@ pub enum Deadline {
     /// 後者の方が優先度が高く、より早く実行されることとなる.
     Within(Duration),
 
+    /// 指定された時刻までの実行を期待するリクエストに指定するデッドライン.
+    ///
+    /// `Within`とは異なり、指定値がそのまま絶対時刻としてスケジューリングに使用されるため、
+    /// リトライを跨いで共通の期限を指定したい場合等に有用.
+    ///
+    /// また、他のデッドラインとは異なり、これはヒントではなく期限として扱われる.
+    /// デバイスがリクエストを処理しようとした時点で既に指定時刻を過ぎている場合には、
+    /// そのリクエストは実行されずに`ErrorKind::DeadlineExceeded`エラーで即座に失敗する.
+    At(Instant),
+
     /// 実行がいくら遅延されても問題がないようなリクエストに指定するデッドライン(デフォルト値).
     ///
-    /// `Immediate`ないし`Within(_)`が指定されたリクエストが一つでもある間は、そちらが優先される.
+    /// `Immediate`ないし`Within(_)`、`At(_)`が指定されたリクエストが一つでもある間は、そちらが優先される.
+    ///
+    /// PUTに指定された場合には、ジャーナルの定期的な同期が先送りされることがある(`Storage::put_with_deadline`).
+    #[default]
     Infinity,
 }
-impl Default for Deadline {
-    fn default() -> Self {
-        Deadline::Infinity
+impl Deadline {
+    /// `now`の時点で、このデッドラインが期限切れかどうかを判定する.
+    ///
+    /// 期限切れとなり得るのは`Deadline::At`のみで、それ以外の場合には常に`false`が返される.
+    pub fn is_expired_at(&self, now: Instant) -> bool {
+        match *self {
+            Deadline::At(t) => t <= now,
+            _ => false,
+        }
     }
 }
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::device::CommandKind;
+
+/// コマンドがキューに追加された時点で、デバイスが実行中だったコマンド.
+///
+/// 分割実行中の長時間コマンド(e.g., `DeviceRequest::list`)が存在する場合には、
+/// その合間に他のコマンドを処理している最中であっても、長時間コマンドの方が実行中のコマンドとして扱われる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockingCommand {
+    /// 実行中だったコマンドのID.
+    ///
+    /// `DeviceEvent::id`と同様に、デバイスがコマンドを受け付けた順に割り当てられる番号.
+    pub id: u64,
+
+    /// 実行中だったコマンドの種類.
+    pub kind: CommandKind,
+}
+
+/// 特定の種類のコマンドが、特定の種類のコマンドの実行中にキューに追加された際の、待ち時間の統計情報.
+///
+/// `DeviceMetrics::blocked_behind`で取得可能.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockedBehindStats {
+    /// 該当するコマンドの数.
+    pub commands: u64,
+
+    /// キューへの追加から、キューから取り出されるまでの時間の合計.
+    pub total_wait: Duration,
+
+    /// キューへの追加から、キューから取り出されるまでの時間の最大値.
+    pub max_wait: Duration,
+}
+impl BlockedBehindStats {
+    /// 待ち時間の平均値を返す.
+    ///
+    /// 該当するコマンドが存在しない場合には`None`が返される.
+    pub fn average_wait(&self) -> Option<Duration> {
+        if self.commands == 0 {
+            None
+        } else {
+            let nanos = self.total_wait.as_nanos() / u128::from(self.commands);
+            Some(Duration::from_nanos(nanos as u64))
+        }
+    }
+
+    fn observe(&mut self, wait: Duration) {
+        self.commands += 1;
+        self.total_wait += wait;
+        self.max_wait = self.max_wait.max(wait);
+    }
+}
+
+/// コマンドの種類と、そのキューへの追加時に実行中だったコマンドの種類の組をキーとする、待ち時間の統計情報のマップ.
+///
+/// `DeviceMetrics::blocked_behind`の返り値として使われる.
+pub type BlockedBehindMap = BTreeMap<(CommandKind, Option<CommandKind>), BlockedBehindStats>;
+
+/// コマンドの種類と、そのキューへの追加時に実行中だったコマンドの種類の組毎の、待ち時間の統計情報の表.
+///
+/// デバイススレッドとハンドル(メトリクス)の間で共有される.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockedBehindTable {
+    inner: Arc<Mutex<BlockedBehindMap>>,
+}
+impl BlockedBehindTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `kind`のコマンドが、`blocker`の実行中にキューに追加され、`wait`だけ待たされたことを記録する.
+    pub fn observe(&self, kind: CommandKind, blocker: Option<CommandKind>, wait: Duration) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.entry((kind, blocker)).or_default().observe(wait);
+    }
+
+    /// 記録されている統計情報のスナップショットを返す.
+    pub fn snapshot(&self) -> BlockedBehindMap {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_behind_table_works() {
+        let table = BlockedBehindTable::new();
+        let ms = Duration::from_millis;
+        table.observe(CommandKind::Get, Some(CommandKind::List), ms(30));
+        table.observe(CommandKind::Get, Some(CommandKind::List), ms(10));
+        table.observe(CommandKind::Get, None, ms(1));
+
+        let snapshot = table.clone().snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let stats = snapshot[&(CommandKind::Get, Some(CommandKind::List))];
+        assert_eq!(stats.commands, 2);
+        assert_eq!(stats.total_wait, ms(40));
+        assert_eq!(stats.max_wait, ms(30));
+        assert_eq!(stats.average_wait(), Some(ms(20)));
+        assert_eq!(snapshot[&(CommandKind::Get, None)].commands, 1);
+        assert_eq!(BlockedBehindStats::default().average_wait(), None);
+    }
+}
@@ -1,13 +1,21 @@
 use prometrics::metrics::MetricBuilder;
+use std::collections::BTreeSet;
+#[cfg(feature = "toml")]
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
+use super::clock::{Clock, SystemClock};
+use super::command::CommandKind;
 use super::long_queue_policy::LongQueuePolicy;
+use super::preset;
+use super::probabilistic::{Dropper, DropperFactory};
 use super::thread::DeviceThread;
-use super::{Device, DeviceHandle};
+use super::{Device, DeviceHandle, StorageKey};
 use crate::nvm::NonVolatileMemory;
-use slog::{Discard, Logger};
 use crate::storage::Storage;
 use crate::Result;
+use slog::{Discard, Logger};
 
 /// `Device`のビルダ.
 #[derive(Debug, Clone)]
@@ -19,6 +27,19 @@ pub struct DeviceBuilder {
     pub(crate) busy_threshold: usize,
     pub(crate) logger: Logger,
     pub(crate) long_queue_policy: LongQueuePolicy,
+    pub(crate) drop_exempt_commands: BTreeSet<CommandKind>,
+    pub(crate) dropper: Option<DropperFactory>,
+    pub(crate) dropper_seed: Option<u64>,
+    pub(crate) long_command_slice_size: usize,
+    pub(crate) max_side_job_duration: Option<Duration>,
+    pub(crate) background_gc_scan: bool,
+    pub(crate) journal_gc_queue_size: Option<usize>,
+    pub(crate) journal_gc_batch_size: Option<usize>,
+    pub(crate) max_consecutive_writes: Option<usize>,
+    pub(crate) event_log_capacity: usize,
+    pub(crate) idempotency_cache_size: usize,
+    pub(crate) reject_unreachable_deadlines: bool,
+    pub(crate) clock: Arc<dyn Clock>,
 }
 impl DeviceBuilder {
     /// デフォルト設定で`DeviceBuilder`インスタンスを生成する.
@@ -31,9 +52,78 @@ impl DeviceBuilder {
             busy_threshold: 1_000,
             logger: Logger::root(Discard, o!()),
             long_queue_policy: LongQueuePolicy::default(),
+            drop_exempt_commands: BTreeSet::new(),
+            dropper: None,
+            dropper_seed: None,
+            long_command_slice_size: 10_000,
+            max_side_job_duration: None,
+            background_gc_scan: false,
+            journal_gc_queue_size: None,
+            journal_gc_batch_size: None,
+            max_consecutive_writes: None,
+            event_log_capacity: 0,
+            idempotency_cache_size: 1024,
+            reject_unreachable_deadlines: false,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// 環境変数で指定された設定値を反映した`DeviceBuilder`インスタンスを生成する.
+    ///
+    /// 名前が`prefix`で始まる環境変数が対象となり、`prefix`を除いた部分を小文字にしたものが、
+    /// 設定項目(`DeviceBuilder`の同名のメソッド)の名前として扱われる.
+    /// 例えば`prefix`が`"CANNYLS_DEVICE_"`の場合には、`CANNYLS_DEVICE_IDLE_THRESHOLD=200ms`は
+    /// `idle_threshold(Duration::from_millis(200))`の呼び出しに相当する.
+    /// 指定されなかった項目は、デフォルト値のままとなる.
+    ///
+    /// 指定可能な項目と値の形式は以下の通り:
+    /// - 時間を表す項目(`idle_threshold`, `max_keep_busy_duration`, `max_side_job_duration`):
+    ///   - 整数と単位(`ms`, `s`, `m`, `h`)の組 (e.g., `"100ms"`, `"10m"`)
+    /// - `long_queue_policy`:
+    ///   - `"stop"`, `"refuse_new_requests:<RATIO>"`, `"drop:<RATIO>"`のいずれか
+    /// - 真偽値を表す項目(`background_gc_scan`, `reject_unreachable_deadlines`):
+    ///   - `"true"`ないし`"false"`
+    /// - それ以外の項目(`max_queue_len`, `busy_threshold`, `long_command_slice_size`,
+    ///   `journal_gc_queue_size`, `journal_gc_batch_size`, `max_consecutive_writes`,
+    ///   `event_log_capacity`, `idempotency_cache_size`):
+    ///   - 非負の整数
+    ///
+    /// 埋め込み先のアプリケーションを再コンパイルせずに、デバイスの設定を調整できるようにすることを目的としている.
+    ///
+    /// # Errors
+    ///
+    /// 未知の項目や、解釈できない値が指定された場合には、`ErrorKind::InvalidInput`エラーが返される.
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let mut builder = Self::new();
+        track!(preset::apply_env(&mut builder, prefix))?;
+        Ok(builder)
+    }
+
+    /// TOML形式の設定ファイルで指定された設定値を反映した`DeviceBuilder`インスタンスを生成する.
+    ///
+    /// ファイルのトップレベルのキーが、設定項目の名前として扱われる.
+    /// 指定可能な項目と値の形式は`from_env`と同様だが、整数や真偽値は、文字列ではなくTOMLの値として記述することもできる.
+    ///
+    /// ```toml
+    /// idle_threshold = "200ms"
+    /// busy_threshold = 2000
+    /// long_queue_policy = "refuse_new_requests:0.5"
+    /// background_gc_scan = true
+    /// ```
+    ///
+    /// このメソッドは`toml`フィーチャーが有効な場合にのみ利用可能.
+    ///
+    /// # Errors
+    ///
+    /// 未知の項目や、解釈できない値が指定された場合には、`ErrorKind::InvalidInput`エラーが返される.
+    #[cfg(feature = "toml")]
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let toml = track_io!(std::fs::read_to_string(path))?;
+        let mut builder = Self::new();
+        track!(preset::apply_toml(&mut builder, &toml))?;
+        Ok(builder)
+    }
+
     /// メトリクス用の共通設定を登録する.
     ///
     /// デフォルト値は`MetricBuilder::new()`.
@@ -48,6 +138,9 @@ impl DeviceBuilder {
     /// デバイス(用のスレッド)が空いていると判断されて、
     /// ストレージの補助タスクが実行されるようになる.
     ///
+    /// また、長時間のコマンド(e.g., `DeviceRequest::journal_gc`)を分割実行している間も、
+    /// 前回の補助タスクの実行からこの値以上が経過していれば、分割の合間に補助タスクが実行される.
+    ///
     /// デフォルト値は`Duration::from_millis(100)`.
     pub fn idle_threshold(&mut self, duration: Duration) -> &mut Self {
         self.idle_threshold = duration;
@@ -108,6 +201,185 @@ impl DeviceBuilder {
         self
     }
 
+    /// `LongQueuePolicy::Drop`によるドロップの対象外とするコマンドの種類を追加する.
+    ///
+    /// 例えば、容量確保のために発行される`delete`をドロップしてしまうと、
+    /// 過負荷時にストレージの空き容量が枯渇しかねないので、このメソッドで対象外とすることができる.
+    ///
+    /// なお`CommandKind::Stop`は、このメソッドの呼び出し有無に関わらず、常にドロップの対象外となる.
+    ///
+    /// デフォルトでは`CommandKind::Stop`以外の全てのコマンドがドロップの対象.
+    pub fn exempt_from_drop(&mut self, kind: CommandKind) -> &mut Self {
+        self.drop_exempt_commands.insert(kind);
+        self
+    }
+
+    /// 過負荷時にリクエストを拒否・ドロップするかどうかの判定に用いる`Dropper`を登録する.
+    ///
+    /// `f()`はデバイスの起動毎に呼び出され、生成された`Dropper`はそのデバイス専用に使われる.
+    /// 独自の`Dropper`を使う場合には、`LongQueuePolicy`に指定された`ratio`は参照されないので、
+    /// 判定の基準は`Dropper`の実装側で決める必要がある.
+    ///
+    /// デフォルトでは、`LongQueuePolicy::ratio`の確率で判定を行う`ProbabilisticDropper`が使われる.
+    pub fn dropper<F, D>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() -> D + Send + Sync + 'static,
+        D: Dropper + Send + 'static,
+    {
+        self.dropper = Some(DropperFactory::new(f));
+        self
+    }
+
+    /// デフォルトの`ProbabilisticDropper`に与えるシードを設定する.
+    ///
+    /// 同じシードを指定すれば、過負荷時にどのリクエストが拒否・ドロップされるかが再現可能となるため、
+    /// 負荷試験等での利用を想定している (詳細は`ProbabilisticDropper::with_seed`を参照).
+    ///
+    /// `DeviceBuilder::dropper`で独自の`Dropper`が登録されている場合には、この設定は無視される.
+    ///
+    /// デフォルトではシードは使われない (i.e., `ProbabilisticDropper::new`で生成される).
+    pub fn dropper_seed(&mut self, seed: u64) -> &mut Self {
+        self.dropper_seed = Some(seed);
+        self
+    }
+
+    /// 長時間かかりうるコマンドを分割実行する際の、一回あたりの最大処理lump数を設定する.
+    ///
+    /// `list`や`delete_range`等の対象lump数に比例した時間がかかるコマンドは、
+    /// この値の単位で分割して実行され、その合間には`GET`や`HEAD`等の軽量な読み込みコマンドが処理される.
+    /// これにより、巨大な範囲を対象とするコマンドが、後続コマンドのデッドラインを大幅に超過させることを防ぐ.
+    ///
+    /// なお、分割実行中であっても、コマンドの結果自体は分割しない場合と同様のものとなる.
+    /// ただし、`delete_range`はアトミックではなくなり、合間に処理された読み込みコマンドからは、
+    /// 範囲の一部のみが削除された途中状態が観測されうる.
+    ///
+    /// デフォルト値は`10_000`.
+    pub fn long_command_slice_size(&mut self, n: usize) -> &mut Self {
+        self.long_command_slice_size = n;
+        self
+    }
+
+    /// 補助タスク(e.g., ジャーナルGC)の一回あたりの最大実行時間を設定する.
+    ///
+    /// 補助タスクはデバイスが暇な時に実行されるが、その実行中に到着したコマンドは、
+    /// 補助タスクの完了を待たされることになる.
+    /// この値を指定することで、補助タスクによる後続コマンドの遅延を抑えることができる.
+    ///
+    /// なお、処理の最小単位の途中で打ち切られることはないため、
+    /// 実際の実行時間は、この値を多少超過する可能性がある.
+    /// また、GCが停滞しないように、ジャーナルGCはこの値に関わらず毎回最低でも一単位は実行される.
+    ///
+    /// デフォルトでは制限なし.
+    pub fn max_side_job_duration(&mut self, duration: Duration) -> &mut Self {
+        self.max_side_job_duration = Some(duration);
+        self
+    }
+
+    /// ジャーナル領域のGC対象エントリ群の読み込みを、専用のスレッドで行うかどうかを設定する.
+    ///
+    /// `true`が指定された場合には、GCのためのジャーナル領域の走査がデバイススレッドとは別のスレッドで行われ、
+    /// フォアグラウンドのI/Oと競合しにくくなる.
+    /// 詳細は`Storage::enable_background_gc_scan`を参照のこと.
+    ///
+    /// NVMが別スレッドからの読み込みをサポートしていない場合には、この設定は無視される.
+    ///
+    /// デフォルト値は`false`.
+    pub fn background_gc_scan(&mut self, enabled: bool) -> &mut Self {
+        self.background_gc_scan = enabled;
+        self
+    }
+
+    /// ストレージのジャーナル領域のGCキューの長さを上書きする.
+    ///
+    /// 指定された場合には、`init_storage()`で生成されたストレージの設定値
+    /// (`StorageBuilder::journal_gc_queue_size`)よりも、こちらが優先される.
+    ///
+    /// デフォルトでは、ストレージの設定値がそのまま使用される.
+    pub fn journal_gc_queue_size(&mut self, size: usize) -> &mut Self {
+        self.journal_gc_queue_size = Some(size);
+        self
+    }
+
+    /// 一回の補助タスクの中で実行される、ジャーナル領域のGCの単位処理の回数を上書きする.
+    ///
+    /// 指定された場合には、`init_storage()`で生成されたストレージの設定値
+    /// (`StorageBuilder::journal_gc_batch_size`)よりも、こちらが優先される.
+    ///
+    /// デフォルトでは、ストレージの設定値がそのまま使用される.
+    pub fn journal_gc_batch_size(&mut self, size: usize) -> &mut Self {
+        self.journal_gc_batch_size = Some(size);
+        self
+    }
+
+    /// 読み込み系のコマンド(GET, HEAD)を挟まずに連続して実行可能な、書き込み系のコマンドの最大数を設定する.
+    ///
+    /// 通常、キュー内のコマンドはデッドライン順に処理されるが、
+    /// 書き込み系のコマンドの連続実行数がこの値に達した場合には、
+    /// キュー内に読み込み系のコマンドがあれば、デッドラインに関わらずそちらが優先して処理される.
+    /// これにより、大きなPUTが大量に発行されている状況でも、GETが飢餓状態に陥ることを防げる.
+    ///
+    /// デフォルトでは制限なし(i.e., 純粋にデッドライン順に処理される).
+    pub fn max_consecutive_writes(&mut self, n: usize) -> &mut Self {
+        self.max_consecutive_writes = Some(n);
+        self
+    }
+
+    /// 直近に処理したコマンドを記録しておく件数を設定する.
+    ///
+    /// `0`より大きい値が指定された場合には、デバイスが処理したコマンドの種類・時刻・結果が、
+    /// 最大でその件数分だけメモリ上に保持され、`DeviceRequest::recent_events`で取得可能となる.
+    /// 記録はデバイスの停止後も参照可能なため、障害発生の直前にデバイスが何をしていたかの調査に利用できる.
+    ///
+    /// `0`の場合には記録は行われず、そのためのオーバヘッドも発生しない.
+    ///
+    /// デフォルト値は`0`.
+    pub fn event_log_capacity(&mut self, n: usize) -> &mut Self {
+        self.event_log_capacity = n;
+        self
+    }
+
+    /// 冪等性キーが付与されたリクエストの処理結果を、記録しておく件数の上限を設定する.
+    ///
+    /// 上限を超えた場合には、最も長く参照されていない記録から破棄される.
+    /// 詳細は`DeviceRequest::idempotency_key`を参照のこと.
+    ///
+    /// `0`の場合には記録は行われず、冪等性キーは無視される.
+    ///
+    /// デフォルト値は`1024`.
+    pub fn idempotency_cache_size(&mut self, n: usize) -> &mut Self {
+        self.idempotency_cache_size = n;
+        self
+    }
+
+    /// 期限までに処理が完了する見込みがないリクエストを、キューへの追加時に即座に拒否するかどうかを設定する.
+    ///
+    /// `true`が指定された場合には、デッドラインとして`Deadline::At`が指定されたリクエストがデバイスに届いた時点で、
+    /// キュー内の先に処理されるコマンドの数と、直近のコマンドの平均処理時間から、処理完了までの時間が見積もられる.
+    /// その見積もりが期限を超えている場合には、リクエストはキューに追加されずに
+    /// `ErrorKind::DeadlineUnreachable`エラーで即座に失敗する.
+    ///
+    /// これにより、いずれ`ErrorKind::DeadlineExceeded`で失敗することになるリクエストのために、
+    /// 呼び出し元が期限まで待たされることを避けられる.
+    ///
+    /// なお、見積もりはあくまでも目安であり、分割実行されるコマンド(e.g., `list`)の処理時間は考慮されない.
+    ///
+    /// デフォルト値は`false`.
+    pub fn reject_unreachable_deadlines(&mut self, enabled: bool) -> &mut Self {
+        self.reject_unreachable_deadlines = enabled;
+        self
+    }
+
+    /// デバイスの管理スレッドがスケジューリングに用いる時計を設定する.
+    ///
+    /// デッドラインの判定やビジー状態の継続時間の計測等は、この時計の時刻に基づいて行われる.
+    /// テストで`ManualClock`を指定すれば、実際に待機することなく時間の経過を模擬できる.
+    ///
+    /// デフォルト値は`SystemClock`.
+    pub fn clock<C: Clock + 'static>(&mut self, clock: C) -> &mut Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
     /// 指定されたストレージを扱う`Device`を起動する.
     ///
     /// 起動したデバイス用に、一つの専用OSスレッドが割り当てられる.
@@ -124,7 +396,58 @@ impl DeviceBuilder {
         F: FnOnce() -> Result<Storage<N>> + Send + 'static,
         N: NonVolatileMemory + Send + 'static,
     {
-        let (thread_handle, thread_monitor) = DeviceThread::spawn(self.clone(), init_storage);
+        let (thread_handle, thread_monitor) = DeviceThread::spawn(self.clone(), move || {
+            let storage = track!(init_storage())?;
+            Ok(vec![(StorageKey::new(0), storage)])
+        });
+        Device::new(thread_monitor, DeviceHandle(thread_handle))
+    }
+
+    /// 呼び出し元のスレッドで生成済みのストレージを扱う`Device`を起動する.
+    ///
+    /// `spawn`とは異なり、ストレージの生成はデバイススレッドの起動前に行われるので、
+    /// 借用した設定値等を用いてストレージを構築する場合でも、`'static`なクロージャを用意する必要がない.
+    /// また、ストレージの生成に失敗した場合には、デバイスを起動する前にそれを検知できる.
+    ///
+    /// 渡されたストレージは、そのままデバイススレッドに移動される.
+    ///
+    /// # 注意
+    ///
+    /// 返り値の`Device`インスタンスが破棄されると、
+    /// 起動したデバイススレッドも停止させられるので注意が必要.
+    pub fn spawn_with_storage<N>(&self, storage: Storage<N>) -> Device
+    where
+        N: NonVolatileMemory + Send + 'static,
+    {
+        self.spawn(move || Ok(storage))
+    }
+
+    /// 複数のストレージを扱う`Device`を起動する.
+    ///
+    /// `spawn`とは異なり、`init_storages()`が返した全てのストレージが、一つの専用OSスレッドによって管理される.
+    /// 一つの物理デバイス上に小さなストレージ(lusfファイル)が多数存在する場合に、
+    /// ストレージ毎にスレッドを起動することを避けるために使用する.
+    ///
+    /// 各リクエストの対象ストレージは`DeviceRequest::storage`で指定する.
+    /// キューやデッドラインに基づくスケジューリングは、全てのストレージで共有される.
+    ///
+    /// `init_storages()`が空のリストを返した場合や、キーが重複している場合には、
+    /// デバイスは`ErrorKind::InvalidInput`エラーで終了する.
+    ///
+    /// 一部のストレージで致命的なエラーが発生した場合には、そのストレージのみが使用不能となり、
+    /// 以後にそれを対象としたリクエストには、同じエラーが返されるようになる.
+    /// 全てのストレージが使用不能となった場合には、デバイスは停止する.
+    ///
+    /// # 注意
+    ///
+    /// デバイスのメトリクスの内で、ストレージに関するもの(`DeviceMetrics::storage`)は、
+    /// キーが最小のストレージのものとなる.
+    pub fn spawn_multi<F, N>(&self, init_storages: F) -> Device
+    where
+        F: FnOnce() -> Result<Vec<(StorageKey, Storage<N>)>> + Send + 'static,
+        N: NonVolatileMemory + Send + 'static,
+    {
+        let (thread_handle, thread_monitor) = DeviceThread::spawn(self.clone(), init_storages);
         Device::new(thread_monitor, DeviceHandle(thread_handle))
     }
 }
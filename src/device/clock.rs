@@ -0,0 +1,73 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// デバイスの管理スレッドがスケジューリングに用いる時計.
+///
+/// デッドラインの判定やビジー状態の継続時間の計測、アイドル判定等は、全てこの時計の時刻に基づいて行われる.
+///
+/// デフォルトでは実時間に従う`SystemClock`が使われるが、
+/// `DeviceBuilder::clock`で差し替えることで、テスト時に時間の経過を模擬することができる.
+///
+/// なお、スループットやイベントの所要時間といった、実際の処理時間の計測には使用されない.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// 現在時刻を返す.
+    fn now(&self) -> Instant;
+}
+
+/// 実時間に従う時計.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 明示的に進められた場合にのみ時刻が変化する時計.
+///
+/// 主にテスト用途で、複製されたインスタンス同士は同じ時刻を共有する.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+impl ManualClock {
+    /// 現在の実時刻から開始する`ManualClock`インスタンスを生成する.
+    pub fn new() -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// 時刻を`duration`だけ進める.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += duration;
+    }
+}
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_works() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        shared.advance(Duration::from_secs(3));
+        assert_eq!(clock.now(), start + Duration::from_secs(3));
+    }
+}
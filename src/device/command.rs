@@ -1,17 +1,84 @@
 //! デバイスに発行されるコマンド群の定義.
 use fibers::sync::oneshot;
 use futures::{Future, Poll};
-use std::ops::Range;
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
 use trackable::error::ErrorKindExt;
 
 use crate::deadline::Deadline;
-use crate::lump::{LumpData, LumpHeader, LumpId};
-use crate::storage::StorageUsage;
+use crate::device::{DeviceStats, StorageKey};
+use crate::lump::{LumpData, LumpHeader, LumpId, LumpRange};
+use crate::storage::{
+    CheckLevel, CheckReport, DeleteCondition, JournalCursor, JournalEntry, PutReport, StorageUsage,
+    WarmUpReport,
+};
 use crate::{Error, ErrorKind, Result};
 
-pub type CommandSender = Sender<Command>;
-pub type CommandReceiver = Receiver<Command>;
+/// コマンドの送受信用のチャンネル.
+///
+/// 各コマンドは、対象ストレージのキーと共に送信される(`None`はデフォルトのストレージを表す).
+pub type CommandSender = Sender<(Option<StorageKey>, Command)>;
+pub type CommandReceiver = Receiver<(Option<StorageKey>, Command)>;
+
+/// デバイスに発行されるコマンドの種類.
+///
+/// `DeviceBuilder::exempt_from_drop`等で、コマンドの種類を指定するために使用される.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CommandKind {
+    /// `DeviceRequest::put`
+    Put,
+
+    /// `DeviceRequest::get`
+    Get,
+
+    /// `DeviceRequest::head`
+    Head,
+
+    /// `DeviceRequest::delete` (および`DeviceRequest::delete_secure`)
+    Delete,
+
+    /// `DeviceRequest::delete_range`
+    DeleteRange,
+
+    /// `DeviceRequest::delete_many`
+    DeleteMany,
+
+    /// `DeviceRequest::link`
+    Link,
+
+    /// `DeviceRequest::rename`
+    Rename,
+
+    /// `DeviceRequest::list`
+    List,
+
+    /// `DeviceRequest::list_range`
+    ListRange,
+
+    /// `DeviceRequest::usage_range`
+    UsageRange,
+
+    /// `DeviceRequest::check`
+    Check,
+
+    /// `DeviceRequest::stats`
+    Stats,
+
+    /// `DeviceRequest::journal_snapshot_step`
+    JournalSnapshotStep,
+
+    /// `DeviceRequest::scan_step` (および`DeviceRequest::scan`)
+    ScanStep,
+
+    /// `DeviceRequest::warm_up`
+    WarmUp,
+
+    /// `DeviceRequest::journal_gc`
+    JournalGc,
+
+    /// `Device::stop`
+    Stop,
+}
 
 #[derive(Debug)]
 pub enum Command {
@@ -20,12 +87,43 @@ pub enum Command {
     Head(HeadLump),
     Delete(DeleteLump),
     DeleteRange(DeleteLumpRange),
+    DeleteMany(DeleteLumps),
+    Link(LinkLump),
+    Rename(RenameLump),
     List(ListLump),
     ListRange(ListLumpRange),
     UsageRange(UsageLumpRange),
+    Check(CheckStorage),
+    Stats(GetStats),
+    JournalSnapshotStep(JournalSnapshotStep),
+    ScanStep(ScanLumpRange),
+    WarmUp(WarmUpStorage),
+    JournalGc(JournalGc),
     Stop(StopDevice),
 }
 impl Command {
+    pub fn kind(&self) -> CommandKind {
+        match *self {
+            Command::Put(_) => CommandKind::Put,
+            Command::Get(_) => CommandKind::Get,
+            Command::Head(_) => CommandKind::Head,
+            Command::Delete(_) => CommandKind::Delete,
+            Command::DeleteRange(_) => CommandKind::DeleteRange,
+            Command::DeleteMany(_) => CommandKind::DeleteMany,
+            Command::Link(_) => CommandKind::Link,
+            Command::Rename(_) => CommandKind::Rename,
+            Command::List(_) => CommandKind::List,
+            Command::ListRange(_) => CommandKind::ListRange,
+            Command::UsageRange(_) => CommandKind::UsageRange,
+            Command::Check(_) => CommandKind::Check,
+            Command::Stats(_) => CommandKind::Stats,
+            Command::JournalSnapshotStep(_) => CommandKind::JournalSnapshotStep,
+            Command::ScanStep(_) => CommandKind::ScanStep,
+            Command::WarmUp(_) => CommandKind::WarmUp,
+            Command::JournalGc(_) => CommandKind::JournalGc,
+            Command::Stop(_) => CommandKind::Stop,
+        }
+    }
     pub fn deadline(&self) -> Deadline {
         match *self {
             Command::Put(ref c) => c.deadline,
@@ -33,9 +131,18 @@ impl Command {
             Command::Head(ref c) => c.deadline,
             Command::Delete(ref c) => c.deadline,
             Command::DeleteRange(ref c) => c.deadline,
+            Command::DeleteMany(ref c) => c.deadline,
+            Command::Link(ref c) => c.deadline,
+            Command::Rename(ref c) => c.deadline,
             Command::List(ref c) => c.deadline,
             Command::ListRange(ref c) => c.deadline,
             Command::UsageRange(ref c) => c.deadline,
+            Command::Check(ref c) => c.deadline,
+            Command::Stats(ref c) => c.deadline,
+            Command::JournalSnapshotStep(ref c) => c.deadline,
+            Command::ScanStep(ref c) => c.deadline,
+            Command::WarmUp(ref c) => c.deadline,
+            Command::JournalGc(ref c) => c.deadline,
             Command::Stop(ref c) => c.deadline,
         }
     }
@@ -46,12 +153,90 @@ impl Command {
             Command::Head(ref c) => c.prioritized,
             Command::Delete(ref c) => c.prioritized,
             Command::DeleteRange(ref c) => c.prioritized,
+            Command::DeleteMany(ref c) => c.prioritized,
+            Command::Link(ref c) => c.prioritized,
+            Command::Rename(ref c) => c.prioritized,
             Command::List(ref c) => c.prioritized,
             Command::ListRange(ref c) => c.prioritized,
             Command::UsageRange(ref c) => c.prioritized,
+            Command::Check(ref c) => c.prioritized,
+            Command::Stats(ref c) => c.prioritized,
+            Command::JournalSnapshotStep(ref c) => c.prioritized,
+            Command::ScanStep(ref c) => c.prioritized,
+            Command::WarmUp(ref c) => c.prioritized,
+            Command::JournalGc(ref c) => c.prioritized,
             Command::Stop(ref c) => c.prioritized,
         }
     }
+    /// 分割実行中の他のコマンドの合間に、割り込んで処理可能なコマンドかどうかを判定する.
+    ///
+    /// 割り込みが許されるのは、ストレージの状態を変更せず、かつ短時間で完了するコマンドのみ.
+    pub fn can_interleave(&self) -> bool {
+        matches!(
+            *self,
+            Command::Get(_) | Command::Head(_) | Command::UsageRange(_) | Command::Stats(_)
+        )
+    }
+    /// 読み込み系のコマンドかどうかを判定する.
+    ///
+    /// スケジューリングの公平性の制御(`DeadlineQueue::set_max_consecutive_writes`)に使われる.
+    pub fn is_read(&self) -> bool {
+        matches!(
+            *self,
+            Command::Get(_) | Command::Head(_) | Command::ScanStep(_)
+        )
+    }
+    /// 書き込み系のコマンドかどうかを判定する.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            *self,
+            Command::Put(_)
+                | Command::Delete(_)
+                | Command::DeleteRange(_)
+                | Command::DeleteMany(_)
+                | Command::Link(_)
+                | Command::Rename(_)
+        )
+    }
+    /// コマンドの種類を表す名前を返す.
+    ///
+    /// メトリクスの`command`ラベルの値と同様.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Command::Put(_) => "put",
+            Command::Get(_) => "get",
+            Command::Head(_) => "head",
+            Command::Delete(_) => "delete",
+            Command::DeleteRange(_) => "delete_range",
+            Command::DeleteMany(_) => "delete_many",
+            Command::Link(_) => "link",
+            Command::Rename(_) => "rename",
+            Command::List(_) => "list",
+            Command::ListRange(_) => "list_range",
+            Command::UsageRange(_) => "usage_range",
+            Command::Check(_) => "check",
+            Command::Stats(_) => "stats",
+            Command::JournalSnapshotStep(_) => "journal_snapshot_step",
+            Command::ScanStep(_) => "scan_step",
+            Command::WarmUp(_) => "warm_up",
+            Command::JournalGc(_) => "journal_gc",
+            Command::Stop(_) => "stop",
+        }
+    }
+    /// 単一のlumpを対象とするコマンドの場合には、そのIDを返す.
+    ///
+    /// `Link`の場合には作成される別名のIDが、`Rename`の場合には変更後のIDが、返される.
+    pub fn lump_id(&self) -> Option<LumpId> {
+        match *self {
+            Command::Put(ref c) => Some(c.lump_id),
+            Command::Get(ref c) => Some(c.lump_id),
+            Command::Head(ref c) => Some(c.lump_id),
+            Command::Delete(ref c) => Some(c.lump_id),
+            Command::Link(ref c) => Some(c.dst),
+            Command::Rename(ref c) => Some(c.new_id),
+            _ => None,
+        }
+    }
     pub fn failed(self, error: Error) {
         match self {
             Command::Put(c) => c.reply.send(Err(error)),
@@ -59,9 +244,18 @@ impl Command {
             Command::Head(c) => c.reply.send(Err(error)),
             Command::Delete(c) => c.reply.send(Err(error)),
             Command::DeleteRange(c) => c.reply.send(Err(error)),
+            Command::DeleteMany(c) => c.reply.send(Err(error)),
+            Command::Link(c) => c.reply.send(Err(error)),
+            Command::Rename(c) => c.reply.send(Err(error)),
             Command::List(c) => c.reply.send(Err(error)),
             Command::ListRange(c) => c.reply.send(Err(error)),
             Command::UsageRange(c) => c.reply.send(Err(error)),
+            Command::Check(c) => c.reply.send(Err(error)),
+            Command::Stats(c) => c.reply.send(Err(error)),
+            Command::JournalSnapshotStep(c) => c.reply.send(Err(error)),
+            Command::ScanStep(c) => c.reply.send(Err(error)),
+            Command::WarmUp(c) => c.reply.send(Err(error)),
+            Command::JournalGc(c) => c.reply.send(Err(error)),
             Command::Stop(_) => {}
         }
     }
@@ -74,7 +268,7 @@ impl<T> AsyncResult<T> {
     #[allow(clippy::new_ret_no_self)]
     fn new() -> (AsyncReply<T>, Self) {
         let (tx, rx) = oneshot::monitor();
-        (AsyncReply(tx), AsyncResult(rx))
+        (AsyncReply(Some(tx)), AsyncResult(rx))
     }
 }
 impl<T> Future for AsyncResult<T> {
@@ -90,11 +284,22 @@ impl<T> Future for AsyncResult<T> {
     }
 }
 
+/// コマンドの実行結果の送信口.
+///
+/// デタッチされたコマンドの場合には、送信先のチャンネル自体を持たない.
 #[derive(Debug)]
-struct AsyncReply<T>(oneshot::Monitored<T, Error>);
+struct AsyncReply<T>(Option<oneshot::Monitored<T, Error>>);
 impl<T> AsyncReply<T> {
+    fn detached() -> Self {
+        AsyncReply(None)
+    }
+    fn is_detached(&self) -> bool {
+        self.0.is_none()
+    }
     fn send(self, result: Result<T>) {
-        self.0.exit(result);
+        if let Some(tx) = self.0 {
+            tx.exit(result);
+        }
     }
 }
 
@@ -105,7 +310,10 @@ pub struct PutLump {
     deadline: Deadline,
     prioritized: bool,
     journal_sync: bool,
-    reply: AsyncReply<bool>,
+    max_sync_delay: Option<Duration>,
+    audit: Option<Vec<u8>>,
+    idempotency_key: Option<u128>,
+    reply: AsyncReply<PutReport>,
 }
 impl PutLump {
     #[allow(clippy::new_ret_no_self)]
@@ -115,7 +323,9 @@ impl PutLump {
         deadline: Deadline,
         prioritized: bool,
         journal_sync: bool,
-    ) -> (Self, AsyncResult<bool>) {
+        max_sync_delay: Option<Duration>,
+        audit: Option<Vec<u8>>,
+    ) -> (Self, AsyncResult<PutReport>) {
         let (reply, result) = AsyncResult::new();
         let command = PutLump {
             lump_id,
@@ -123,21 +333,70 @@ impl PutLump {
             deadline,
             prioritized,
             journal_sync,
+            max_sync_delay,
+            audit,
+            idempotency_key: None,
             reply,
         };
         (command, result)
     }
+
+    /// 実行結果を返さないコマンドを生成する.
+    pub fn detached(
+        lump_id: LumpId,
+        lump_data: LumpData,
+        deadline: Deadline,
+        prioritized: bool,
+        journal_sync: bool,
+        max_sync_delay: Option<Duration>,
+        audit: Option<Vec<u8>>,
+    ) -> Self {
+        PutLump {
+            lump_id,
+            lump_data,
+            deadline,
+            prioritized,
+            journal_sync,
+            max_sync_delay,
+            audit,
+            idempotency_key: None,
+            reply: AsyncReply::detached(),
+        }
+    }
     pub fn lump_id(&self) -> &LumpId {
         &self.lump_id
     }
     pub fn lump_data(&self) -> &LumpData {
         &self.lump_data
     }
+    pub fn deadline(&self) -> Deadline {
+        self.deadline
+    }
     pub fn do_sync_journal(&self) -> bool {
         self.journal_sync
     }
+    /// ジャーナルの同期を遅延させて良い時間の上限を返す.
+    pub fn max_sync_delay(&self) -> Option<Duration> {
+        self.max_sync_delay
+    }
+    /// 監査レコードに記録するメタデータを返す.
+    pub fn audit_metadata(&self) -> Option<&[u8]> {
+        self.audit.as_ref().map(|m| &m[..])
+    }
+    /// 冪等性キーを返す.
+    pub fn idempotency_key(&self) -> Option<u128> {
+        self.idempotency_key
+    }
+    /// 冪等性キーを設定する.
+    pub fn set_idempotency_key(&mut self, key: Option<u128>) {
+        self.idempotency_key = key;
+    }
+    /// 実行結果の送信先を持たないコマンドかどうかを返す.
+    pub fn is_detached(&self) -> bool {
+        self.reply.is_detached()
+    }
 
-    pub fn reply(self, result: Result<bool>) {
+    pub fn reply(self, result: Result<PutReport>) {
         self.reply.send(result)
     }
 }
@@ -210,6 +469,11 @@ pub struct DeleteLump {
     deadline: Deadline,
     prioritized: bool,
     journal_sync: bool,
+    max_sync_delay: Option<Duration>,
+    secure_fill: Option<u8>,
+    audit: Option<Vec<u8>>,
+    condition: Option<DeleteCondition>,
+    idempotency_key: Option<u128>,
     reply: AsyncReply<bool>,
 }
 impl DeleteLump {
@@ -219,6 +483,9 @@ impl DeleteLump {
         deadline: Deadline,
         prioritized: bool,
         journal_sync: bool,
+        max_sync_delay: Option<Duration>,
+        secure_fill: Option<u8>,
+        audit: Option<Vec<u8>>,
     ) -> (Self, AsyncResult<bool>) {
         let (reply, result) = AsyncResult::new();
         let command = DeleteLump {
@@ -226,16 +493,77 @@ impl DeleteLump {
             deadline,
             prioritized,
             journal_sync,
+            max_sync_delay,
+            secure_fill,
+            audit,
+            condition: None,
+            idempotency_key: None,
             reply,
         };
         (command, result)
     }
+
+    /// 実行結果を返さないコマンドを生成する.
+    pub fn detached(
+        lump_id: LumpId,
+        deadline: Deadline,
+        prioritized: bool,
+        journal_sync: bool,
+        max_sync_delay: Option<Duration>,
+        secure_fill: Option<u8>,
+        audit: Option<Vec<u8>>,
+    ) -> Self {
+        DeleteLump {
+            lump_id,
+            deadline,
+            prioritized,
+            journal_sync,
+            max_sync_delay,
+            secure_fill,
+            audit,
+            condition: None,
+            idempotency_key: None,
+            reply: AsyncReply::detached(),
+        }
+    }
     pub fn lump_id(&self) -> &LumpId {
         &self.lump_id
     }
+    /// 削除前にデータを上書きする場合には、その際に用いるバイトを返す.
+    pub fn secure_fill(&self) -> Option<u8> {
+        self.secure_fill
+    }
+    /// 条件付き削除の場合には、その条件を返す.
+    pub fn condition(&self) -> Option<DeleteCondition> {
+        self.condition
+    }
+    /// 削除を`condition`を満たす場合のみに限定する.
+    pub fn set_condition(&mut self, condition: Option<DeleteCondition>) {
+        self.condition = condition;
+    }
     pub fn do_sync_journal(&self) -> bool {
         self.journal_sync
     }
+    /// ジャーナルの同期を遅延させて良い時間の上限を返す.
+    pub fn max_sync_delay(&self) -> Option<Duration> {
+        self.max_sync_delay
+    }
+    /// 監査レコードに記録するメタデータを返す.
+    pub fn audit_metadata(&self) -> Option<&[u8]> {
+        self.audit.as_ref().map(|m| &m[..])
+    }
+    /// 冪等性キーを返す.
+    pub fn idempotency_key(&self) -> Option<u128> {
+        self.idempotency_key
+    }
+    /// 冪等性キーを設定する.
+    pub fn set_idempotency_key(&mut self, key: Option<u128>) {
+        self.idempotency_key = key;
+    }
+    /// 実行結果の送信先を持たないコマンドかどうかを返す.
+    pub fn is_detached(&self) -> bool {
+        self.reply.is_detached()
+    }
     pub fn reply(self, result: Result<bool>) {
         self.reply.send(result);
     }
@@ -243,19 +571,21 @@ impl DeleteLump {
 
 #[derive(Debug)]
 pub struct DeleteLumpRange {
-    range: Range<LumpId>,
+    range: LumpRange,
     deadline: Deadline,
     prioritized: bool,
     journal_sync: bool,
+    max_sync_delay: Option<Duration>,
     reply: AsyncReply<Vec<LumpId>>,
 }
 impl DeleteLumpRange {
     #[allow(clippy::new_ret_no_self)]
     pub fn new(
-        range: Range<LumpId>,
+        range: LumpRange,
         deadline: Deadline,
         prioritized: bool,
         journal_sync: bool,
+        max_sync_delay: Option<Duration>,
     ) -> (Self, AsyncResult<Vec<LumpId>>) {
         let (reply, result) = AsyncResult::new();
         let command = DeleteLumpRange {
@@ -263,21 +593,174 @@ impl DeleteLumpRange {
             deadline,
             prioritized,
             journal_sync,
+            max_sync_delay,
             reply,
         };
         (command, result)
     }
-    pub fn lump_range(&self) -> Range<LumpId> {
-        self.range.clone()
+    pub fn lump_range(&self) -> LumpRange {
+        self.range
     }
     pub fn do_sync_journal(&self) -> bool {
         self.journal_sync
     }
+    /// ジャーナルの同期を遅延させて良い時間の上限を返す.
+    pub fn max_sync_delay(&self) -> Option<Duration> {
+        self.max_sync_delay
+    }
     pub fn reply(self, result: Result<Vec<LumpId>>) {
         self.reply.send(result);
     }
 }
 
+#[derive(Debug)]
+pub struct DeleteLumps {
+    lump_ids: Vec<LumpId>,
+    deadline: Deadline,
+    prioritized: bool,
+    journal_sync: bool,
+    max_sync_delay: Option<Duration>,
+    reply: AsyncReply<Vec<bool>>,
+}
+impl DeleteLumps {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        lump_ids: Vec<LumpId>,
+        deadline: Deadline,
+        prioritized: bool,
+        journal_sync: bool,
+        max_sync_delay: Option<Duration>,
+    ) -> (Self, AsyncResult<Vec<bool>>) {
+        let (reply, result) = AsyncResult::new();
+        let command = DeleteLumps {
+            lump_ids,
+            deadline,
+            prioritized,
+            journal_sync,
+            max_sync_delay,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn lump_ids(&self) -> &[LumpId] {
+        &self.lump_ids
+    }
+    pub fn do_sync_journal(&self) -> bool {
+        self.journal_sync
+    }
+    /// ジャーナルの同期を遅延させて良い時間の上限を返す.
+    pub fn max_sync_delay(&self) -> Option<Duration> {
+        self.max_sync_delay
+    }
+    pub fn reply(self, result: Result<Vec<bool>>) {
+        self.reply.send(result);
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkLump {
+    src: LumpId,
+    dst: LumpId,
+    deadline: Deadline,
+    prioritized: bool,
+    journal_sync: bool,
+    max_sync_delay: Option<Duration>,
+    reply: AsyncReply<bool>,
+}
+impl LinkLump {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        src: LumpId,
+        dst: LumpId,
+        deadline: Deadline,
+        prioritized: bool,
+        journal_sync: bool,
+        max_sync_delay: Option<Duration>,
+    ) -> (Self, AsyncResult<bool>) {
+        let (reply, result) = AsyncResult::new();
+        let command = LinkLump {
+            src,
+            dst,
+            deadline,
+            prioritized,
+            journal_sync,
+            max_sync_delay,
+            reply,
+        };
+        (command, result)
+    }
+    /// 別名の参照元のlumpのIDを返す.
+    pub fn src(&self) -> &LumpId {
+        &self.src
+    }
+    /// 作成される別名のIDを返す.
+    pub fn dst(&self) -> &LumpId {
+        &self.dst
+    }
+    pub fn do_sync_journal(&self) -> bool {
+        self.journal_sync
+    }
+    /// ジャーナルの同期を遅延させて良い時間の上限を返す.
+    pub fn max_sync_delay(&self) -> Option<Duration> {
+        self.max_sync_delay
+    }
+    pub fn reply(self, result: Result<bool>) {
+        self.reply.send(result);
+    }
+}
+
+#[derive(Debug)]
+pub struct RenameLump {
+    old_id: LumpId,
+    new_id: LumpId,
+    deadline: Deadline,
+    prioritized: bool,
+    journal_sync: bool,
+    max_sync_delay: Option<Duration>,
+    reply: AsyncReply<bool>,
+}
+impl RenameLump {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        old_id: LumpId,
+        new_id: LumpId,
+        deadline: Deadline,
+        prioritized: bool,
+        journal_sync: bool,
+        max_sync_delay: Option<Duration>,
+    ) -> (Self, AsyncResult<bool>) {
+        let (reply, result) = AsyncResult::new();
+        let command = RenameLump {
+            old_id,
+            new_id,
+            deadline,
+            prioritized,
+            journal_sync,
+            max_sync_delay,
+            reply,
+        };
+        (command, result)
+    }
+    /// 変更前のlumpのIDを返す.
+    pub fn old_id(&self) -> &LumpId {
+        &self.old_id
+    }
+    /// 変更後のlumpのIDを返す.
+    pub fn new_id(&self) -> &LumpId {
+        &self.new_id
+    }
+    pub fn do_sync_journal(&self) -> bool {
+        self.journal_sync
+    }
+    /// ジャーナルの同期を遅延させて良い時間の上限を返す.
+    pub fn max_sync_delay(&self) -> Option<Duration> {
+        self.max_sync_delay
+    }
+    pub fn reply(self, result: Result<bool>) {
+        self.reply.send(result);
+    }
+}
+
 #[derive(Debug)]
 pub struct ListLump {
     deadline: Deadline,
@@ -302,7 +785,7 @@ impl ListLump {
 
 #[derive(Debug)]
 pub struct ListLumpRange {
-    range: Range<LumpId>,
+    range: LumpRange,
     deadline: Deadline,
     prioritized: bool,
     reply: AsyncReply<Vec<LumpId>>,
@@ -310,7 +793,7 @@ pub struct ListLumpRange {
 impl ListLumpRange {
     #[allow(clippy::new_ret_no_self)]
     pub fn new(
-        range: Range<LumpId>,
+        range: LumpRange,
         deadline: Deadline,
         prioritized: bool,
     ) -> (Self, AsyncResult<Vec<LumpId>>) {
@@ -323,8 +806,8 @@ impl ListLumpRange {
         };
         (command, result)
     }
-    pub fn lump_range(&self) -> Range<LumpId> {
-        self.range.clone()
+    pub fn lump_range(&self) -> LumpRange {
+        self.range
     }
     pub fn reply(self, result: Result<Vec<LumpId>>) {
         self.reply.send(result);
@@ -333,7 +816,7 @@ impl ListLumpRange {
 
 #[derive(Debug)]
 pub struct UsageLumpRange {
-    range: Range<LumpId>,
+    range: LumpRange,
     deadline: Deadline,
     prioritized: bool,
     reply: AsyncReply<StorageUsage>,
@@ -341,7 +824,7 @@ pub struct UsageLumpRange {
 impl UsageLumpRange {
     #[allow(clippy::new_ret_no_self)]
     pub fn new(
-        range: Range<LumpId>,
+        range: LumpRange,
         deadline: Deadline,
         prioritized: bool,
     ) -> (Self, AsyncResult<StorageUsage>) {
@@ -354,14 +837,200 @@ impl UsageLumpRange {
         };
         (command, result)
     }
-    pub fn lump_range(&self) -> Range<LumpId> {
-        self.range.clone()
+    pub fn lump_range(&self) -> LumpRange {
+        self.range
     }
     pub fn reply(self, result: Result<StorageUsage>) {
         self.reply.send(result);
     }
 }
 
+#[derive(Debug)]
+pub struct CheckStorage {
+    level: CheckLevel,
+    deadline: Deadline,
+    prioritized: bool,
+    reply: AsyncReply<CheckReport>,
+}
+impl CheckStorage {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        level: CheckLevel,
+        deadline: Deadline,
+        prioritized: bool,
+    ) -> (Self, AsyncResult<CheckReport>) {
+        let (reply, result) = AsyncResult::new();
+        let command = CheckStorage {
+            level,
+            deadline,
+            prioritized,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn level(&self) -> CheckLevel {
+        self.level
+    }
+    pub fn reply(self, result: Result<CheckReport>) {
+        self.reply.send(result);
+    }
+}
+
+#[derive(Debug)]
+pub struct GetStats {
+    deadline: Deadline,
+    prioritized: bool,
+    reply: AsyncReply<DeviceStats>,
+}
+impl GetStats {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(deadline: Deadline, prioritized: bool) -> (Self, AsyncResult<DeviceStats>) {
+        let (reply, result) = AsyncResult::new();
+        let command = GetStats {
+            deadline,
+            prioritized,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn reply(self, result: Result<DeviceStats>) {
+        self.reply.send(result);
+    }
+}
+
+/// `JournalSnapshotStep`の結果(読み込んだエントリ群と、次の読み込み開始位置を指すカーソル).
+pub type JournalEntries = (Vec<JournalEntry>, Option<JournalCursor>);
+
+#[derive(Debug)]
+pub struct JournalSnapshotStep {
+    cursor: JournalCursor,
+    max_entries: usize,
+    deadline: Deadline,
+    prioritized: bool,
+    reply: AsyncReply<JournalEntries>,
+}
+impl JournalSnapshotStep {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        cursor: JournalCursor,
+        max_entries: usize,
+        deadline: Deadline,
+        prioritized: bool,
+    ) -> (Self, AsyncResult<JournalEntries>) {
+        let (reply, result) = AsyncResult::new();
+        let command = JournalSnapshotStep {
+            cursor,
+            max_entries,
+            deadline,
+            prioritized,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn cursor(&self) -> JournalCursor {
+        self.cursor
+    }
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+    pub fn reply(self, result: Result<JournalEntries>) {
+        self.reply.send(result);
+    }
+}
+
+/// `ScanStep`の結果(読み込んだlump群と、未処理部分の範囲).
+pub type ScannedLumps = (Vec<(LumpId, LumpData)>, Option<LumpRange>);
+
+#[derive(Debug)]
+pub struct ScanLumpRange {
+    range: LumpRange,
+    max_lumps: usize,
+    deadline: Deadline,
+    prioritized: bool,
+    reply: AsyncReply<ScannedLumps>,
+}
+impl ScanLumpRange {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        range: LumpRange,
+        max_lumps: usize,
+        deadline: Deadline,
+        prioritized: bool,
+    ) -> (Self, AsyncResult<ScannedLumps>) {
+        let (reply, result) = AsyncResult::new();
+        let command = ScanLumpRange {
+            range,
+            max_lumps,
+            deadline,
+            prioritized,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn range(&self) -> LumpRange {
+        self.range
+    }
+    pub fn max_lumps(&self) -> usize {
+        self.max_lumps
+    }
+    pub fn reply(self, result: Result<ScannedLumps>) {
+        self.reply.send(result);
+    }
+}
+
+#[derive(Debug)]
+pub struct WarmUpStorage {
+    touch_regions: bool,
+    deadline: Deadline,
+    prioritized: bool,
+    reply: AsyncReply<WarmUpReport>,
+}
+impl WarmUpStorage {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        touch_regions: bool,
+        deadline: Deadline,
+        prioritized: bool,
+    ) -> (Self, AsyncResult<WarmUpReport>) {
+        let (reply, result) = AsyncResult::new();
+        let command = WarmUpStorage {
+            touch_regions,
+            deadline,
+            prioritized,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn touch_regions(&self) -> bool {
+        self.touch_regions
+    }
+    pub fn reply(self, result: Result<WarmUpReport>) {
+        self.reply.send(result);
+    }
+}
+
+#[derive(Debug)]
+pub struct JournalGc {
+    deadline: Deadline,
+    prioritized: bool,
+    reply: AsyncReply<()>,
+}
+impl JournalGc {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(deadline: Deadline, prioritized: bool) -> (Self, AsyncResult<()>) {
+        let (reply, result) = AsyncResult::new();
+        let command = JournalGc {
+            deadline,
+            prioritized,
+            reply,
+        };
+        (command, result)
+    }
+    pub fn reply(self, result: Result<()>) {
+        self.reply.send(result);
+    }
+}
+
 #[derive(Debug)]
 pub struct StopDevice {
     deadline: Deadline,
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use super::builder::DeviceBuilder;
+use super::long_queue_policy::LongQueuePolicy;
+
+/// デバイスに実際に適用されている設定値.
+///
+/// `DeviceBuilder`で指定された値やデフォルト値を解決した結果を保持する.
+/// 各値の意味は、`DeviceBuilder`の同名のメソッドのドキュメントを参照のこと.
+///
+/// `Device::config`ないし`DeviceHandle::config`によって取得される.
+/// `serde`フィーチャーが有効な場合には、シリアライズ可能となる.
+///
+/// なお、デバイスが扱うストレージの設定値は`Storage::config`で取得できる.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceConfig {
+    /// デバイスが暇だと判定するための閾値.
+    pub idle_threshold: Duration,
+
+    /// デバイスの最大キュー長.
+    pub max_queue_len: usize,
+
+    /// デバイスの最大継続ビジー時間.
+    pub max_keep_busy_duration: Duration,
+
+    /// デバイスがビジー状態かどうかを判定するための閾値.
+    pub busy_threshold: usize,
+
+    /// キューが長い場合の挙動.
+    pub long_queue_policy: LongQueuePolicy,
+
+    /// 長時間コマンドを分割実行する際の単位.
+    pub long_command_slice_size: usize,
+
+    /// 補助タスクの一回の実行時間の上限.
+    pub max_side_job_duration: Option<Duration>,
+
+    /// ジャーナル領域のGC対象エントリの読み込みを、専用のスレッドで行うかどうか.
+    pub background_gc_scan: bool,
+
+    /// ストレージの設定を上書きする、ジャーナル領域のGCキューの長さ.
+    pub journal_gc_queue_size: Option<usize>,
+
+    /// ストレージの設定を上書きする、一回の補助タスクで実行されるジャーナル領域のGCの回数.
+    pub journal_gc_batch_size: Option<usize>,
+
+    /// 読み込み系のコマンドを挟まずに連続して実行可能な、書き込み系のコマンドの最大数.
+    pub max_consecutive_writes: Option<usize>,
+
+    /// 直近に処理したコマンドを記録しておく件数.
+    pub event_log_capacity: usize,
+
+    /// 冪等性キーが付与されたリクエストの処理結果を記録しておく件数.
+    pub idempotency_cache_size: usize,
+
+    /// 期限までに処理が完了する見込みがないリクエストを、キューへの追加時に即座に拒否するかどうか.
+    pub reject_unreachable_deadlines: bool,
+}
+impl DeviceConfig {
+    pub(crate) fn new(builder: &DeviceBuilder) -> Self {
+        DeviceConfig {
+            idle_threshold: builder.idle_threshold,
+            max_queue_len: builder.max_queue_len,
+            max_keep_busy_duration: builder.max_keep_busy_duration,
+            busy_threshold: builder.busy_threshold,
+            long_queue_policy: builder.long_queue_policy.clone(),
+            long_command_slice_size: builder.long_command_slice_size,
+            max_side_job_duration: builder.max_side_job_duration,
+            background_gc_scan: builder.background_gc_scan,
+            journal_gc_queue_size: builder.journal_gc_queue_size,
+            journal_gc_batch_size: builder.journal_gc_batch_size,
+            max_consecutive_writes: builder.max_consecutive_writes,
+            event_log_capacity: builder.event_log_capacity,
+            idempotency_cache_size: builder.idempotency_cache_size,
+            reject_unreachable_deadlines: builder.reject_unreachable_deadlines,
+        }
+    }
+}
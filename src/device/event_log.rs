@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::device::BlockingCommand;
+use crate::lump::LumpId;
+use crate::ErrorKind;
+
+/// デバイスが処理したコマンドの記録.
+///
+/// `DeviceBuilder::event_log_capacity`で記録が有効にされている場合に、
+/// `DeviceRequest::recent_events`を通して、直近のものを取得可能.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceEvent {
+    /// デバイスがコマンドを受け付けた順に割り当てられる、コマンドのID.
+    pub id: u64,
+
+    /// コマンドの処理を開始した時刻.
+    pub time: SystemTime,
+
+    /// コマンドの種類(e.g., `"put"`).
+    ///
+    /// メトリクスの`command`ラベルの値と同様.
+    pub command: &'static str,
+
+    /// コマンドの対象lumpのID.
+    ///
+    /// 単一のlumpを対象としないコマンドの場合には`None`となる.
+    pub lump_id: Option<LumpId>,
+
+    /// コマンドの処理に要した時間.
+    ///
+    /// 分割実行されたコマンドの場合には、最初の処理開始から完了までの時間となる.
+    pub elapsed: Duration,
+
+    /// コマンドの処理結果.
+    pub outcome: DeviceEventOutcome,
+
+    /// コマンドがキューに追加された時点で、デバイスが実行中だったコマンド.
+    ///
+    /// デバイスがアイドル状態だった場合には`None`となる.
+    pub blocked_behind: Option<BlockingCommand>,
+}
+
+/// `DeviceEvent`が表すコマンドの処理結果.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEventOutcome {
+    /// 処理に成功した.
+    Succeeded,
+
+    /// 処理に失敗し、要求元にエラーが返された.
+    Failed,
+
+    /// デッドライン超過や過負荷により、処理されずに拒否された.
+    Rejected(ErrorKind),
+
+    /// 致命的なエラーが発生し、デバイスが停止した.
+    Fatal(ErrorKind),
+}
+
+/// 直近のコマンドの記録を、指定された件数まで保持するリングバッファ.
+///
+/// デバイススレッドとハンドルの間で共有されるため、
+/// デバイスが異常停止した後でも、その直前の記録を参照することができる.
+#[derive(Debug, Clone)]
+pub(crate) struct EventLog {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<DeviceEvent>>>,
+}
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        EventLog {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub fn push(&self, event: DeviceEvent) {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// 記録されているイベント群を、古いものから順に返す.
+    pub fn events(&self) -> Vec<DeviceEvent> {
+        let events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        events.iter().cloned().collect()
+    }
+}
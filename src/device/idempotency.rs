@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::device::{CommandKind, StorageKey};
+use crate::lump::LumpId;
+use crate::storage::PutReport;
+use crate::{ErrorKind, Result};
+
+/// 冪等性キーが付与されたコマンドの、記録済みの処理結果.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompletedCommand {
+    Put(LumpId, PutReport),
+    Delete(LumpId, bool),
+}
+impl CompletedCommand {
+    fn kind(&self) -> CommandKind {
+        match *self {
+            CompletedCommand::Put(..) => CommandKind::Put,
+            CompletedCommand::Delete(..) => CommandKind::Delete,
+        }
+    }
+
+    fn lump_id(&self) -> &LumpId {
+        match *self {
+            CompletedCommand::Put(ref lump_id, _) | CompletedCommand::Delete(ref lump_id, _) => {
+                lump_id
+            }
+        }
+    }
+}
+
+/// 直近に処理に成功したコマンドの結果を、冪等性キー毎に保持するLRUキャッシュ.
+///
+/// 同じキーを持つコマンドが再送された場合には、それを実行せずに、ここに記録されている結果を返すために使われる.
+/// 失敗したコマンドの結果は記録されないので、その再送は通常通りに実行される.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    capacity: usize,
+    entries: HashMap<(StorageKey, u128), (u64, CompletedCommand)>,
+    recency: BTreeMap<u64, (StorageKey, u128)>,
+    seqno: u64,
+}
+impl IdempotencyCache {
+    pub fn new(capacity: usize) -> Self {
+        IdempotencyCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            seqno: 0,
+        }
+    }
+
+    /// `key`を持つPUTコマンドの、記録済みの結果を返す.
+    ///
+    /// 記録されているコマンドと種類や対象lumpが異なる場合には、
+    /// 同じキーが別のコマンドに使い回されているので、`ErrorKind::InvalidInput`エラーが返される.
+    pub fn lookup_put(
+        &mut self,
+        storage: StorageKey,
+        key: u128,
+        lump_id: &LumpId,
+    ) -> Result<Option<PutReport>> {
+        let completed = track!(self.lookup(storage, key, CommandKind::Put, lump_id))?;
+        Ok(completed.and_then(|c| match c {
+            CompletedCommand::Put(_, report) => Some(report),
+            CompletedCommand::Delete(..) => None,
+        }))
+    }
+
+    /// `key`を持つDELETEコマンドの、記録済みの結果を返す.
+    ///
+    /// エラーについては`lookup_put`と同様.
+    pub fn lookup_delete(
+        &mut self,
+        storage: StorageKey,
+        key: u128,
+        lump_id: &LumpId,
+    ) -> Result<Option<bool>> {
+        let completed = track!(self.lookup(storage, key, CommandKind::Delete, lump_id))?;
+        Ok(completed.and_then(|c| match c {
+            CompletedCommand::Delete(_, deleted) => Some(deleted),
+            CompletedCommand::Put(..) => None,
+        }))
+    }
+
+    /// `key`を持つPUTコマンドの処理結果を記録する.
+    pub fn record_put(
+        &mut self,
+        storage: StorageKey,
+        key: u128,
+        lump_id: &LumpId,
+        report: PutReport,
+    ) {
+        self.insert(storage, key, CompletedCommand::Put(*lump_id, report));
+    }
+
+    /// `key`を持つDELETEコマンドの処理結果を記録する.
+    pub fn record_delete(
+        &mut self,
+        storage: StorageKey,
+        key: u128,
+        lump_id: &LumpId,
+        deleted: bool,
+    ) {
+        self.insert(storage, key, CompletedCommand::Delete(*lump_id, deleted));
+    }
+
+    fn lookup(
+        &mut self,
+        storage: StorageKey,
+        key: u128,
+        kind: CommandKind,
+        lump_id: &LumpId,
+    ) -> Result<Option<CompletedCommand>> {
+        let entry = match self.entries.get_mut(&(storage, key)) {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+        track_assert!(
+            entry.1.kind() == kind && entry.1.lump_id() == lump_id,
+            ErrorKind::InvalidInput,
+            "The idempotency key is reused for another command: key={}, recorded={:?}",
+            key,
+            entry.1
+        );
+
+        // 最近使われたものとして扱う
+        self.recency.remove(&entry.0);
+        entry.0 = self.seqno;
+        self.recency.insert(self.seqno, (storage, key));
+        self.seqno += 1;
+        Ok(Some(entry.1))
+    }
+
+    // 容量を超える場合には、最も長く参照されていないものが破棄される.
+    fn insert(&mut self, storage: StorageKey, key: u128, completed: CompletedCommand) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((seqno, _)) = self.entries.insert((storage, key), (self.seqno, completed)) {
+            self.recency.remove(&seqno);
+        }
+        self.recency.insert(self.seqno, (storage, key));
+        self.seqno += 1;
+
+        while self.entries.len() > self.capacity {
+            let (_, oldest) = self.recency.pop_first().expect("Never fails");
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::lump::LumpData;
+    use crate::nvm::MemoryNvm;
+    use crate::storage::StorageBuilder;
+
+    #[test]
+    fn idempotency_cache_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().create(nvm))?;
+        let data = track!(LumpData::new_embedded(vec![1]))?;
+        let report = track!(storage.put(&LumpId::new(0), &data))?;
+
+        let s = StorageKey::new(0);
+        let id = LumpId::new;
+        let mut cache = IdempotencyCache::new(2);
+        assert_eq!(track!(cache.lookup_put(s, 1, &id(0)))?, None);
+        cache.record_put(s, 1, &id(0), report);
+        cache.record_delete(s, 2, &id(0), true);
+        assert_eq!(track!(cache.lookup_put(s, 1, &id(0)))?, Some(report));
+        assert_eq!(track!(cache.lookup_delete(s, 2, &id(0)))?, Some(true));
+
+        // 種類や対象lumpが異なる場合はエラー
+        assert_eq!(
+            cache.lookup_delete(s, 1, &id(0)).err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert!(cache.lookup_put(s, 1, &id(1)).is_err());
+
+        // ストレージ毎に区別される
+        assert_eq!(
+            track!(cache.lookup_put(StorageKey::new(9), 1, &id(0)))?,
+            None
+        );
+
+        // 容量を超えた場合には、最も長く参照されていないものが破棄される
+        assert!(track!(cache.lookup_put(s, 1, &id(0)))?.is_some());
+        cache.record_put(s, 3, &id(3), report);
+        assert!(track!(cache.lookup_put(s, 1, &id(0)))?.is_some());
+        assert_eq!(track!(cache.lookup_delete(s, 2, &id(0)))?, None);
+        assert!(track!(cache.lookup_put(s, 3, &id(3)))?.is_some());
+        Ok(())
+    }
+}
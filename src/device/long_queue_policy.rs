@@ -1,6 +1,7 @@
 /// デバイスのキューが長い場合にどうするか
 /// default は RefuseNewRequests
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum LongQueuePolicy {
     /// 一定の割合で新しいリクエストを拒否する
     ///
@@ -14,6 +15,7 @@ pub enum LongQueuePolicy {
     },
 
     /// デバイスを止める
+    #[default]
     Stop,
 
     /// 一定の割合でリクエストをドロップする。
@@ -28,12 +30,6 @@ pub enum LongQueuePolicy {
     },
 }
 
-impl Default for LongQueuePolicy {
-    fn default() -> Self {
-        LongQueuePolicy::Stop
-    }
-}
-
 impl LongQueuePolicy {
     /// 過負荷時にリクエストが実行されない確率を返す。
     /// 「実行されない」は、「拒否される」あるいは「ドロップされる」のいずれかを意味する。
@@ -6,35 +6,66 @@
 //! 一つのデバイス(i.e., ストレージ)には、一つの管理スレッドが割り当てられて、
 //! そのデバイスに対するリクエストは全て直列化されて処理される.
 //!
+//! なお、小さなストレージを多数扱う場合のために、一つのデバイス(管理スレッド)に
+//! 複数のストレージを割り当てることも可能となっている(`DeviceBuilder::spawn_multi`).
+//! その場合には、各リクエストは`StorageKey`によって対象のストレージが指定される.
+//!
 //! 並行するリクエスト群が存在する場合には、指定された優先順位(デッドライン)に基づいて
 //! スケジューリングが行われる.
 //!
 //! [ストレージ]: ../storage/index.html
 //! [Device]: struct.Device.html
 use futures::{Async, Future, Poll};
+use std::fmt;
 use std::sync::Arc;
 
+pub use self::blocking::{BlockedBehindMap, BlockedBehindStats, BlockingCommand};
 pub use self::builder::DeviceBuilder;
+pub use self::clock::{Clock, ManualClock, SystemClock};
+pub use self::command::CommandKind;
+pub use self::config::DeviceConfig;
+pub use self::event_log::{DeviceEvent, DeviceEventOutcome};
 pub use self::long_queue_policy::LongQueuePolicy;
-pub use self::request::DeviceRequest;
-
+pub use self::namespace::{NamespacedDeviceHandle, NamespacedDeviceRequest};
+pub use self::probabilistic::{Dropper, ProbabilisticDropper};
+pub use self::request::{DetachedDeviceRequest, DeviceRequest, DeviceScan};
+pub use self::retry::{RetryPolicy, RetryingDeviceHandle, RetryingDeviceRequest};
+pub use self::runtime::DeviceRuntime;
+pub use self::stats::DeviceStats;
+pub use self::status_watch::{DeviceStatusEvent, DeviceStatusWatch};
+
+pub(crate) use self::blocking::BlockedBehindTable; // `metrics`モジュール用に公開されている
 pub(crate) use self::command::Command; // `metrics`モジュール用に公開されている
 
 use self::thread::{DeviceThreadHandle, DeviceThreadMonitor};
 use crate::deadline::Deadline;
-use crate::lump::{LumpData, LumpId};
+use crate::lump::{LumpData, LumpId, LumpRange};
 use crate::metrics::DeviceMetrics;
 use crate::nvm::NonVolatileMemory;
-use crate::storage::Storage;
+use crate::storage::{Storage, StorageUsage};
 use crate::{Error, Result};
 
+pub mod sim;
+
+mod blocking;
 mod builder;
+mod clock;
 mod command;
+mod config;
+mod event_log;
+mod idempotency;
 mod long_queue_policy;
+mod namespace;
+mod preset;
 mod probabilistic;
 mod queue;
 mod request;
+mod retry;
+mod runtime;
+mod stats;
+mod status_watch;
 mod thread;
+mod throughput;
 
 /// [Lump]群を格納するためのデバイス.
 ///
@@ -113,11 +144,18 @@ impl Device {
         self.handle.clone()
     }
 
+    /// デバイスに実際に適用されている設定値を返す.
+    pub fn config(&self) -> &DeviceConfig {
+        self.handle.config()
+    }
+
     /// デバイスに停止リクエストを発行する.
     ///
     /// このメソッドが返った時点でデバイスが停止している保証はないので、
     /// 確実に終了を検知したい場合には`Future::poll`メソッド経由で知る必要がある.
     ///
+    /// 停止時には`Storage::close`が呼び出され、次回のオープン用のチェックポイントが書き出される.
+    ///
     /// なお`Device`インスタンスのドロップ時点で、そのデバイスがまだ稼働中の場合には
     /// `stop(Deadline::Immediate)`が自動で呼び出される.
     /// ただし、その後にデバイスの終了を待機したりはしないので注意は必要.
@@ -173,15 +211,70 @@ impl Drop for Device {
 pub struct DeviceHandle(DeviceThreadHandle);
 impl DeviceHandle {
     /// デバイスの発行するリクエストのビルダを返す.
-    pub fn request(&self) -> DeviceRequest {
+    pub fn request(&self) -> DeviceRequest<'_> {
         DeviceRequest::new(&self.0)
     }
 
+    /// `prefix`を最上位バイトとする`LumpId`群のみを扱う、名前空間付きのハンドルを返す.
+    ///
+    /// 詳細は[`NamespacedDeviceHandle`]のドキュメントを参照のこと.
+    ///
+    /// [`NamespacedDeviceHandle`]: ./struct.NamespacedDeviceHandle.html
+    pub fn namespace(&self, prefix: u8) -> NamespacedDeviceHandle {
+        NamespacedDeviceHandle::new(self.clone(), prefix)
+    }
+
+    /// 失敗したリクエストを`policy`に従って自動でリトライする、リトライ付きのハンドルを返す.
+    ///
+    /// どのコマンドがどのエラーの場合にリトライ可能かは[`RetryPolicy::is_retriable`]を参照のこと.
+    ///
+    /// [`RetryPolicy::is_retriable`]: ./struct.RetryPolicy.html#method.is_retriable
+    pub fn with_retry(&self, policy: RetryPolicy) -> RetryingDeviceHandle {
+        RetryingDeviceHandle::new(self.clone(), policy)
+    }
+
     /// デバイスのメトリクスを返す.
     pub fn metrics(&self) -> &Arc<DeviceMetrics> {
         self.0.metrics()
     }
 
+    /// 指定された範囲のlump群が占有するバイト数を、コマンドを発行せずに返す.
+    ///
+    /// 結果は、デバイスのスレッドが逐次更新している`UsageSummary`から算出されるため、
+    /// 処理中のコマンドの結果は反映されていない可能性がある(詳細は[`UsageSummary`]を参照のこと).
+    ///
+    /// 範囲の両端が接頭辞(`LumpId`の最上位バイト)の境界に揃っていない場合や、
+    /// デバイスの状態が`Running`以外の場合には`None`が返されるので、
+    /// その際には`DeviceRequest::usage_range`を使用すること.
+    ///
+    /// [`UsageSummary`]: ../storage/struct.UsageSummary.html
+    pub fn cached_usage_range<R: Into<LumpRange>>(&self, range: R) -> Option<StorageUsage> {
+        if self.metrics().status() != DeviceStatus::Running {
+            return None;
+        }
+        self.metrics()
+            .usage_summary()
+            .and_then(|summary| summary.usage_range(range))
+    }
+
+    /// デバイスに実際に適用されている設定値を返す.
+    ///
+    /// デバイスが扱うストレージの設定値は含まれないので、
+    /// 必要であれば`Storage::config`を参照すること.
+    pub fn config(&self) -> &DeviceConfig {
+        self.0.config()
+    }
+
+    /// デバイスの状態遷移(過負荷状態への遷移・解消、停止)の通知を受け取るためのストリームを返す.
+    ///
+    /// アプリケーションは、これを購読することで、リクエストがエラーとなる前に負荷を調整することができる.
+    /// 詳細は[`DeviceStatusEvent`]を参照のこと.
+    ///
+    /// [`DeviceStatusEvent`]: ./enum.DeviceStatusEvent.html
+    pub fn watch_status(&self) -> DeviceStatusWatch {
+        self.0.watch_status()
+    }
+
     /// ストレージのブロック境界にアライメントされたメモリ領域を保持する`LumpData`インスタンスを返す.
     ///
     /// `LumpData::new`関数に比べて、このメソッドが返した`LumpData`インスタンスは、
@@ -196,6 +289,7 @@ impl DeviceHandle {
     /// # Errors
     ///
     /// 指定されたサイズが`MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
+    #[allow(clippy::uninit_vec)]
     pub fn allocate_lump_data(&self, size: usize) -> Result<LumpData> {
         if let Some(storage) = self.metrics().storage() {
             track!(LumpData::aligned_allocate(
@@ -255,18 +349,49 @@ pub enum DeviceStatus {
     Stopped = 0,
 }
 
+/// 一つのデバイスが複数のストレージを管理する場合に、各ストレージを識別するためのキー.
+///
+/// `DeviceBuilder::spawn_multi`で起動したデバイスに対しては、
+/// `DeviceRequest::storage`でキーを指定することで、リクエストの対象ストレージを選択する.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StorageKey(u64);
+impl StorageKey {
+    /// 新しい`StorageKey`インスタンスを生成する.
+    pub fn new(key: u64) -> Self {
+        StorageKey(key)
+    }
+
+    /// キーの値を返す.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+impl fmt::Display for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use fibers_global::execute;
+    use futures::Stream;
+    use std::io;
     use std::ops::Range;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tempdir::TempDir;
     use trackable::result::TestResult;
 
     use super::*;
-    use crate::lump::{LumpData, LumpId};
-    use crate::nvm::{MemoryNvm, SharedMemoryNvm};
-    use crate::storage::StorageBuilder;
+    use crate::block::BlockSize;
+    use crate::lump::{LumpData, LumpId, LumpRange};
+    use crate::nvm::{FileNvm, MemoryNvm, SharedMemoryNvm};
+    use crate::storage::{
+        AuditOperation, AuditRecord, CheckLevel, DeleteCondition, JournalCursor, JournalRecord,
+        Storage, StorageBuilder,
+    };
     use crate::ErrorKind;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn device_works() -> TestResult {
@@ -284,12 +409,282 @@ mod tests {
             vec![id(0), id(1), id(2)]
         );
 
-        assert_eq!(track!(execute(d.request().delete(id(1))))?, true);
-        assert_eq!(track!(execute(d.request().delete(id(1))))?, false);
+        assert!(track!(execute(d.request().delete(id(1))))?);
+        assert!(!track!(execute(d.request().delete(id(1))))?);
         assert_eq!(track!(execute(d.request().list()))?, vec![id(0), id(2)]);
         Ok(())
     }
 
+    #[test]
+    fn put_bytes_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        let report = track!(execute(d.request().put_bytes(id(0), b"foo")))?;
+        assert!(report.is_new());
+        assert!(!report.is_embedded());
+        assert_eq!(
+            track!(execute(d.request().get(id(0))))?.map(|d| d.into_bytes()),
+            Some(b"foo".to_vec())
+        );
+
+        let too_large = vec![0; LumpData::MAX_SIZE + 1];
+        let result = execute(d.request().put_bytes(id(1), &too_large));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn delete_secure_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm.clone()))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        let contains_pattern = || nvm.to_bytes().windows(5).any(|w| w == b"hello");
+        track!(execute(d.request().put(id(0), data(b"hello"))))?;
+        assert!(contains_pattern());
+
+        assert!(track!(execute(d.request().delete_secure(id(0))))?);
+        assert!(!contains_pattern());
+        assert!(!track!(execute(d.request().delete_secure(id(0))))?);
+        assert_eq!(track!(execute(d.request().list()))?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn delete_if_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        // 削除の判断後に上書きされた場合には、削除されない
+        track!(execute(d.request().put(id(0), data(b"foo"))))?;
+        let condition = DeleteCondition::checksum_of(b"foo");
+        track!(execute(d.request().put(id(0), data(b"bar"))))?;
+        assert!(!track!(execute(d.request().delete_if(id(0), condition)))?);
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(0)]);
+
+        let condition = DeleteCondition::checksum_of(b"bar");
+        assert!(track!(execute(d.request().delete_if(id(0), condition)))?);
+        assert_eq!(track!(execute(d.request().list()))?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn delete_many_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        for i in 0..5 {
+            track!(execute(d.request().put(id(i), data(b"foo"))))?;
+        }
+        let deleted = track!(execute(d.request().delete_many(vec![
+            id(3),
+            id(8),
+            id(0),
+            id(1)
+        ])))?;
+        assert_eq!(deleted, vec![true, false, true, true]);
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(2), id(4)]);
+        assert_eq!(d.metrics().failed_commands().delete_many(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn link_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(d.request().put(id(0), data(&[1; 1000]))))?;
+        assert!(track!(execute(
+            d.request().journal_sync().link(id(0), id(1))
+        ))?);
+        assert!(!track!(execute(d.request().link(id(2), id(3))))?);
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(0), id(1)]);
+
+        assert!(track!(execute(d.request().delete(id(0))))?);
+        let linked = track!(execute(d.request().get(id(1))))?;
+        assert_eq!(linked.map(|d| d.into_bytes()), Some(vec![1; 1000]));
+        assert_eq!(d.metrics().failed_commands().link(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rename_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(d.request().put(id(0), data(&[1; 1000]))))?;
+        assert!(track!(execute(
+            d.request().journal_sync().rename(id(0), id(1))
+        ))?);
+        assert!(!track!(execute(d.request().rename(id(2), id(3))))?);
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(1)]);
+
+        let renamed = track!(execute(d.request().get(id(1))))?;
+        assert_eq!(renamed.map(|d| d.into_bytes()), Some(vec![1; 1000]));
+        assert_eq!(d.metrics().failed_commands().rename(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn stats_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(d.request().put(id(0), data(b"foo"))))?;
+        track!(execute(d.request().put(id(1), data(&[1; 1000]))))?;
+        let stats = track!(execute(d.request().stats()))?;
+        assert_eq!(stats.status, DeviceStatus::Running);
+        assert_eq!(stats.queue_len, 0);
+        assert_eq!(stats.busy_duration, None);
+        assert_eq!(stats.long_command, None);
+        assert_eq!(stats.storage.lumps, 2);
+        assert_eq!(stats.storage.data.usage_bytes, 512 * 3);
+        assert_ne!(stats.storage.journal.tail, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn warm_up_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(d.request().put(id(0), data(b"foo"))))?;
+        let report = track!(execute(d.request().warm_up(true)))?;
+        assert_eq!(report.lumps, 1);
+        assert_ne!(report.touched_bytes, 0);
+        assert_eq!(d.metrics().enqueued_commands().warm_up(), 1);
+        assert_eq!(d.metrics().failed_commands().warm_up(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_snapshot_step_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        for i in 0..5 {
+            track!(execute(d.request().put(id(i), data(b"foo"))))?;
+        }
+
+        let mut cursor = Some(JournalCursor::default());
+        let mut entries = Vec::new();
+        while let Some(c) = cursor {
+            let (step, next) = track!(execute(d.request().journal_snapshot_step(c, 2)))?;
+            assert!(step.len() <= 2);
+            entries.extend(step);
+            cursor = next;
+        }
+        assert_eq!(entries.len(), 5);
+        assert!(entries
+            .iter()
+            .all(|e| matches!(e.record, JournalRecord::Put(..))));
+        Ok(())
+    }
+
+    #[test]
+    fn scan_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        for i in 0..5 {
+            track!(execute(d.request().put(id(i), data(b"foo"))))?;
+        }
+        track!(execute(d.request().put(LumpId::MAX, data(b"bar"))))?;
+
+        let (lumps, next) = track!(execute(d.request().scan_step(id(1)..id(4), 2)))?;
+        assert_eq!(
+            lumps.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            [id(1), id(2)]
+        );
+        assert_eq!(lumps[0].1.as_bytes(), b"foo");
+        assert_eq!(next, Some(LumpRange::new(id(3), id(4))));
+
+        let lumps = track!(execute(d.request().scan(.., 2).collect()))?;
+        assert_eq!(
+            lumps.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            [id(0), id(1), id(2), id(3), id(4), LumpId::MAX]
+        );
+        assert_eq!(lumps[5].1.as_bytes(), b"bar");
+        assert_eq!(d.metrics().enqueued_commands().scan_step(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().audit_trail(true).create(nvm.clone()))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        let metadata = b"user=alice,t=100".to_vec();
+        track!(execute(
+            d.request().audit(metadata.clone()).put(id(0), data(b"foo"))
+        ))?;
+        assert!(track!(execute(
+            d.request().audit(metadata.clone()).delete_secure(id(0))
+        ))?);
+
+        // メタデータが短すぎる
+        let result = execute(d.request().audit(vec![0; 4]).put(id(1), data(b"bar")));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+
+        device.stop(Deadline::Immediate);
+        track!(execute(device))?;
+
+        let mut storage = track!(Storage::open(nvm))?;
+        let audits = track!(storage.journal_snapshot())?
+            .entries
+            .iter()
+            .filter_map(|e| AuditRecord::from_journal_record(&e.record))
+            .map(|r| (r.operation, r.lump_id, r.metadata))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            audits,
+            vec![
+                (AuditOperation::Put, id(0), metadata.clone()),
+                (AuditOperation::Delete, id(0), metadata),
+            ]
+        );
+        Ok(())
+    }
+
     #[test]
     fn delete_range_all_data_works() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
@@ -375,6 +770,107 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn journal_gc_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().journal_gc_queue_size(2).create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        for i in 0..5 {
+            track!(execute(d.request().put(id(i), data(b"foo"))))?;
+        }
+        track!(execute(d.request().delete(id(0))))?;
+        track!(execute(d.request().journal_gc()))?;
+        assert_eq!(
+            track!(execute(d.request().list()))?,
+            (1..5).map(id).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn side_jobs_run_between_journal_gc_slices() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().journal_gc_queue_size(1).create(nvm))?;
+        storage.set_automatic_gc_mode(false);
+        for i in 0..10 {
+            track!(storage.put(&id(i), &data(b"foo")))?;
+        }
+        let clock = ManualClock::new();
+        let device = DeviceBuilder::new()
+            .clock(clock.clone())
+            .idle_threshold(Duration::from_secs(10))
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+        assert_eq!(d.metrics().side_jobs(), 0);
+
+        // デバイスが暇になることはないが、時計の上では閾値以上の時間が経過している
+        clock.advance(Duration::from_secs(20));
+        track!(execute(d.request().journal_gc()))?;
+        assert_eq!(d.metrics().side_jobs(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn long_command_slicing_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().journal_region_ratio(0.99).create(nvm))?;
+        let device = DeviceBuilder::new()
+            .long_command_slice_size(2)
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        for i in 0..7 {
+            track!(execute(d.request().put(id(i), data(b"foo"))))?;
+        }
+
+        // 分割実行されても、結果は分割しない場合と同じ
+        assert_eq!(
+            track!(execute(d.request().list()))?,
+            (0..7).map(id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            track!(execute(d.request().list_range(Range {
+                start: id(1),
+                end: id(6)
+            })))?,
+            vec![id(1), id(2), id(3), id(4), id(5)]
+        );
+        assert_eq!(
+            track!(execute(d.request().delete_range(Range {
+                start: id(1),
+                end: id(6)
+            })))?,
+            vec![id(1), id(2), id(3), id(4), id(5)]
+        );
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(0), id(6)]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().journal_region_ratio(0.5).create(nvm))?;
+        let device = DeviceBuilder::new()
+            .long_command_slice_size(2)
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        for i in 0..5 {
+            track!(execute(d.request().put(id(i), data(&[0; 1024]))))?;
+        }
+        let report = track!(execute(d.request().check(CheckLevel::Journal)))?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.checked_lumps, 5);
+        assert_eq!(d.metrics().dequeued_commands().check(), 1);
+        Ok(())
+    }
+
     #[test]
     fn list_range_works() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
@@ -430,6 +926,18 @@ mod tests {
             vec![id(2), id(3), id(4), id(5), id(6)]
         );
 
+        // 範囲取得: 終端を含む範囲
+        track!(execute(d.request().put(LumpId::MAX, data(b"max"))))?;
+        assert_eq!(
+            track!(execute(d.request().list_range(id(6)..=LumpId::MAX)))?,
+            vec![id(6), LumpId::MAX]
+        );
+        assert_eq!(
+            track!(execute(d.request().delete_range(LumpRange::full())))?,
+            vec![id(2), id(3), id(4), id(5), id(6), LumpId::MAX]
+        );
+        assert!(track!(execute(d.request().list()))?.is_empty());
+
         Ok(())
     }
 
@@ -482,6 +990,85 @@ mod tests {
             header.block_size.as_u16() * 4,
             usage.bytecount().unwrap() as u16
         );
+
+        // 接頭辞の境界に揃った範囲なら、コマンドを発行せずに取得できる
+        let prefix = track!(LumpRange::prefix(0, 8))?;
+        let usage = track_assert_some!(d.cached_usage_range(prefix), ErrorKind::Other);
+        assert_eq!(
+            usage.bytecount(),
+            Some(u64::from(header.block_size.as_u16()) * 4)
+        );
+        assert!(d.cached_usage_range(id(0)..id(13)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn command_latency_metrics_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().journal_region_ratio(0.99).create(nvm))?;
+        let device = DeviceBuilder::new()
+            .max_consecutive_writes(1)
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(d.request().put(id(0), data(b"foo"))))?;
+        track!(execute(d.request().delete(id(0))))?;
+        track!(execute(d.request().get(id(0))))?;
+
+        // メトリクスは応答の送信後に更新されるので、後続のリクエストの完了を待機する
+        track!(execute(d.request().list()))?;
+        assert_eq!(d.metrics().write_latency_seconds().count(), 2);
+        assert_eq!(d.metrics().read_latency_seconds().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn nvm_info_metrics_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let nvm = track!(FileNvm::create(dir.path().join("test.lusf"), 1024 * 1024))?;
+        let path = nvm.path().display().to_string();
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        track!(execute(d.request().wait_for_running().list()))?;
+
+        let labels = d.metrics().nvm_labels();
+        assert!(labels.contains(&("path".to_owned(), path)));
+        assert!(labels.contains(&("direct_io".to_owned(), "true".to_owned())));
+        assert!(!d.metrics().is_direct_io_degraded());
+        Ok(())
+    }
+
+    #[test]
+    fn expired_absolute_deadline_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().journal_region_ratio(0.99).create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        // 期限切れのリクエストは実行されない
+        let past = Instant::now();
+        let result = execute(
+            d.request()
+                .deadline(Deadline::At(past))
+                .put(id(0), data(b"foo")),
+        );
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::DeadlineExceeded)
+        );
+        assert_eq!(track!(execute(d.request().list()))?, vec![]);
+
+        // 期限内なら通常通り実行される
+        let future = Instant::now() + Duration::from_secs(60);
+        track!(execute(
+            d.request()
+                .deadline(Deadline::At(future))
+                .put(id(0), data(b"foo"))
+        ))?;
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(0)]);
         Ok(())
     }
 
@@ -508,7 +1095,8 @@ mod tests {
             let device = DeviceBuilder::new().spawn(|| Ok(storage));
             let d = device.handle();
             let _ = execute(d.request().wait_for_running().list());
-            track!(execute(d.request().put(id(1234), embedded_data(b"hoge"))))?;
+            let report = track!(execute(d.request().put(id(1234), embedded_data(b"hoge"))))?;
+            assert!(!report.journal_synced());
             assert_eq!(v, nvm.to_bytes()); // ジャーナルバッファ上に値があり、実際に書き込まれていない
         }
 
@@ -521,17 +1109,374 @@ mod tests {
             let device = DeviceBuilder::new().spawn(|| Ok(storage));
             let d = device.handle();
             let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
-            track!(execute(
+            let report = track!(execute(
                 d.request()
                     .journal_sync()
                     .put(id(1234), embedded_data(b"hoge"))
             ))?;
+            assert!(report.journal_synced());
             assert_ne!(v, nvm.to_bytes()); // `journal_sync` により、実際に書き込まれている
         }
 
         Ok(())
     }
 
+    #[test]
+    fn max_sync_delay_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.5)
+            .create(nvm.clone()))?;
+        let metrics = storage.metrics().clone();
+        let v = nvm.to_bytes();
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+        let syncs = metrics.journal_region().syncs();
+
+        // 遅延が許容された要求群の同期は、一度にまとめて行われる
+        let delay = Duration::from_millis(100);
+        let put0 = d
+            .request()
+            .max_sync_delay(delay)
+            .put(id(0), embedded_data(b"foo"));
+        let put1 = d
+            .request()
+            .max_sync_delay(delay)
+            .put(id(1), embedded_data(b"bar"));
+        let (report0, report1) = track!(execute(put0.join(put1)))?;
+        assert!(report0.journal_synced());
+        assert!(report1.journal_synced());
+        assert_ne!(v, nvm.to_bytes());
+        assert_eq!(metrics.journal_region().syncs(), syncs + 1);
+
+        assert!(track!(execute(
+            d.request().max_sync_delay(delay).delete(id(0))
+        ))?);
+        assert_eq!(metrics.journal_region().syncs(), syncs + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn detached_request_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = Device::spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        // デタッチされた要求は結果を返さないが、後続の要求よりも先に処理される
+        d.request().detach().put(id(0), embedded_data(b"foo"));
+        d.request().detach().put(id(1), embedded_data(b"bar"));
+        assert_eq!(
+            track!(execute(d.request().get(id(0))))?,
+            Some(embedded_data(b"foo"))
+        );
+
+        d.request().detach().delete(id(0));
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn recent_events_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new()
+            .event_log_capacity(3)
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+        assert_eq!(d.request().recent_events().len(), 1);
+
+        track!(execute(d.request().put(id(0), embedded_data(b"foo"))))?;
+        track!(execute(d.request().list_range(id(0)..id(10))))?;
+        let result = execute(
+            d.request()
+                .deadline(Deadline::At(Instant::now()))
+                .get(id(0)),
+        );
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::DeadlineExceeded)
+        );
+
+        // 古いものから順に、最大で指定件数分のみが保持される
+        let events = d.request().recent_events();
+        let commands = events.iter().map(|e| e.command).collect::<Vec<_>>();
+        assert_eq!(commands, ["put", "list_range", "get"]);
+        assert_eq!(events[0].lump_id, Some(id(0)));
+        assert_eq!(events[0].outcome, DeviceEventOutcome::Succeeded);
+        assert_eq!(events[1].lump_id, None);
+        assert_eq!(events[1].outcome, DeviceEventOutcome::Succeeded);
+        assert_eq!(
+            events[2].outcome,
+            DeviceEventOutcome::Rejected(ErrorKind::DeadlineExceeded)
+        );
+
+        // 記録が無効な場合には、常に空となる
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = Device::spawn(|| Ok(storage));
+        let d = device.handle();
+        track!(execute(d.request().wait_for_running().list()))?;
+        assert!(d.request().recent_events().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn idempotency_key_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = Device::spawn(|| Ok(storage));
+        let d = device.handle();
+
+        let report = track!(execute(
+            d.request()
+                .wait_for_running()
+                .idempotency_key(1)
+                .put(id(0), embedded_data(b"foo"))
+        ))?;
+        assert!(report.is_new());
+        assert!(track!(execute(
+            d.request().idempotency_key(2).delete(id(0))
+        ))?);
+        track!(execute(d.request().put(id(0), embedded_data(b"bar"))))?;
+
+        // 再送された要求は実行されずに、記録済みの結果が返される
+        let replayed = track!(execute(
+            d.request()
+                .idempotency_key(1)
+                .put(id(0), embedded_data(b"foo"))
+        ))?;
+        assert_eq!(replayed, report);
+        assert!(track!(execute(
+            d.request().idempotency_key(2).delete(id(0))
+        ))?);
+        assert_eq!(
+            track!(execute(d.request().get(id(0))))?,
+            Some(embedded_data(b"bar"))
+        );
+        let replayed = d.metrics().replayed_commands();
+        assert_eq!((replayed.put(), replayed.delete()), (1, 1));
+
+        // 別の要求に同じキーを使うことはできない
+        let result = execute(d.request().idempotency_key(2).delete(id(1)));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn spawn_with_storage_works() -> TestResult {
+        let mut builder = StorageBuilder::new();
+        builder.journal_region_ratio(0.1);
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(builder.create(nvm))?;
+
+        let device = DeviceBuilder::new().spawn_with_storage(storage);
+        let d = device.handle();
+        track!(execute(
+            d.request().wait_for_running().put(id(0), data(b"foo"))
+        ))?;
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn config_works() {
+        let device = DeviceBuilder::new()
+            .busy_threshold(10)
+            .long_queue_policy(LongQueuePolicy::Drop { ratio: 0.5 })
+            .spawn(|| track!(Storage::create(MemoryNvm::new(vec![0; 1024 * 1024]))));
+        let config = device.config();
+        assert_eq!(config.busy_threshold, 10);
+        assert_eq!(config.max_queue_len, 100_000);
+        assert_eq!(
+            config.long_queue_policy,
+            LongQueuePolicy::Drop { ratio: 0.5 }
+        );
+        assert_eq!(device.handle().config(), config);
+    }
+
+    #[test]
+    fn spawn_multi_works() -> TestResult {
+        let storages = (1..4)
+            .map(|i| {
+                let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+                track!(Storage::create(nvm)).map(|s| (StorageKey::new(i), s))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let device = DeviceBuilder::new().spawn_multi(move || Ok(storages));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        let key = StorageKey::new;
+        track!(execute(
+            d.request().storage(key(1)).put(id(0), data(b"foo"))
+        ))?;
+        track!(execute(
+            d.request().storage(key(2)).put(id(1), data(b"bar"))
+        ))?;
+        track!(execute(
+            d.request().storage(key(2)).put(id(2), data(b"baz"))
+        ))?;
+
+        // 各ストレージは独立している
+        assert_eq!(
+            track!(execute(d.request().storage(key(1)).list()))?,
+            vec![id(0)]
+        );
+        assert_eq!(
+            track!(execute(d.request().storage(key(2)).list()))?,
+            vec![id(1), id(2)]
+        );
+        assert_eq!(track!(execute(d.request().storage(key(3)).list()))?, vec![]);
+        assert_eq!(
+            track!(execute(d.request().storage(key(2)).get(id(0))))?,
+            None
+        );
+
+        // キー指定がない場合には、キーが最小のストレージが対象となる
+        assert_eq!(track!(execute(d.request().list()))?, vec![id(0)]);
+
+        // 存在しないストレージ
+        let result = execute(d.request().storage(key(4)).list());
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+
+        // 保留中の応答は、ストレージ毎に同期された上で返される
+        let delay = Duration::from_millis(10);
+        let report = track!(execute(
+            d.request()
+                .storage(key(3))
+                .max_sync_delay(delay)
+                .put(id(3), data(b"qux"))
+        ))?;
+        assert!(report.journal_synced());
+
+        device.stop(Deadline::Immediate);
+        track!(execute(device))?;
+
+        // キーが重複している場合には、起動に失敗する
+        let storages = (0..2)
+            .map(|_| {
+                let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+                track!(Storage::create(nvm)).map(|s| (StorageKey::new(0), s))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let device = DeviceBuilder::new().spawn_multi(move || Ok(storages));
+        assert_eq!(
+            execute(device).err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unreachable_deadlines_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new()
+            .reject_unreachable_deadlines(true)
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(d.request().put(id(0), data(b"foo"))))?;
+
+        // 期限までに処理できる見込みがない
+        let deadline = Deadline::At(Instant::now());
+        let result = execute(d.request().deadline(deadline).get(id(0)));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::DeadlineUnreachable)
+        );
+
+        // 十分に余裕がある
+        let deadline = Deadline::At(Instant::now() + Duration::from_secs(60));
+        let result = track!(execute(d.request().deadline(deadline).get(id(0))))?;
+        assert!(result.is_some());
+
+        // `Deadline::At`以外は対象外
+        let deadline = Deadline::Immediate;
+        assert!(track!(execute(d.request().deadline(deadline).get(id(0))))?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn manual_clock_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let clock = ManualClock::new();
+        let device = DeviceBuilder::new()
+            .clock(clock.clone())
+            .spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        let deadline = Deadline::At(Instant::now() + Duration::from_secs(60));
+        track!(execute(
+            d.request().deadline(deadline).put(id(0), data(b"foo"))
+        ))?;
+
+        // デバイスの時計の上では、期限を過ぎている
+        clock.advance(Duration::from_secs(3600));
+        let result = execute(d.request().deadline(deadline).get(id(0)));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::DeadlineExceeded)
+        );
+        assert!(track!(execute(d.request().get(id(0))))?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn watch_status_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new().busy_threshold(2).spawn(move || {
+            // 起動中に発行されたリクエスト群が、キューに溜まるようにする
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(storage)
+        });
+        let d = device.handle();
+        let watch = d.watch_status();
+
+        let futures = (0..10)
+            .map(|i| d.request().wait_for_running().put(id(i), data(b"foo")))
+            .collect::<Vec<_>>();
+        for future in futures {
+            track!(execute(future))?;
+        }
+        device.stop(Deadline::Immediate);
+        track!(execute(device))?;
+
+        let events = track!(execute(watch.collect()))?;
+        assert_eq!(events.len(), 3, "{:?}", events);
+        assert_eq!(
+            events[0],
+            DeviceStatusEvent::Busy {
+                queue_len: 2,
+                busy_threshold: 2
+            }
+        );
+        match events[1] {
+            DeviceStatusEvent::Running { queue_len, .. } => assert_eq!(queue_len, 1),
+            ref e => panic!("Unexpected event: {:?}", e),
+        }
+        assert_eq!(events[2], DeviceStatusEvent::Stopped { error: None });
+
+        // 停止後に購読した場合には、停止イベントのみが通知される
+        let events = track!(execute(d.watch_status().collect()))?;
+        assert_eq!(events, vec![DeviceStatusEvent::Stopped { error: None }]);
+        Ok(())
+    }
+
     #[test]
     fn device_stop_works() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
@@ -565,7 +1510,7 @@ mod tests {
                 .put(id(1234), embedded_data(b"hoge")),
         );
         // 新規に書かれたので true
-        assert_eq!(result.unwrap(), true);
+        assert!(result.unwrap().is_new());
         // 2 回目は busy という理由で失敗する
         let result = execute(
             handle
@@ -585,7 +1530,7 @@ mod tests {
                 .put(id(1234), embedded_data(b"hoge")),
         );
         // 上書きされたので false
-        assert_eq!(result.unwrap(), false);
+        assert!(!result.unwrap().is_new());
 
         Ok(())
     }
@@ -713,8 +1658,82 @@ mod tests {
                 .prioritized()
                 .put(id(1234), embedded_data(b"hoge")),
         );
-        assert_eq!(result.unwrap(), true);
+        assert!(result.unwrap().is_new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn device_long_queue_policy_drop_exemption_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new()
+            .busy_threshold(0)
+            .max_keep_busy_duration(Duration::from_secs(0))
+            .long_queue_policy(LongQueuePolicy::Drop { ratio: 1.0 })
+            .exempt_from_drop(CommandKind::Delete)
+            .spawn(|| Ok(storage));
+
+        let handle = device.handle();
+        let result = execute(
+            handle
+                .request()
+                .wait_for_running()
+                .put(id(1234), embedded_data(b"hoge")),
+        );
+        assert_eq!(*result.unwrap_err().kind(), ErrorKind::RequestDropped);
 
+        // 対象外に指定された種類のコマンドは drop されない
+        let result = execute(handle.request().wait_for_running().delete(id(1234)));
+        assert!(!result.unwrap());
+
+        let dropped = handle.metrics().dropped_commands();
+        assert_eq!(dropped.put(), 1);
+        assert_eq!(dropped.delete(), 0);
+
+        // STOP コマンドは常に drop されない
+        device.stop(Deadline::Immediate);
+        track!(execute(device))?;
+        assert_eq!(dropped.stop(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn device_custom_dropper_works() -> TestResult {
+        // 交互に drop する決定的な dropper
+        #[derive(Debug, Default)]
+        struct AlternateDropper(bool);
+        impl Dropper for AlternateDropper {
+            fn will_drop(&mut self) -> bool {
+                self.0 = !self.0;
+                self.0
+            }
+        }
+
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        let device = DeviceBuilder::new()
+            .busy_threshold(0)
+            .max_keep_busy_duration(Duration::from_secs(0))
+            .long_queue_policy(LongQueuePolicy::Drop { ratio: 0.0 })
+            .dropper(AlternateDropper::default)
+            .spawn(|| Ok(storage));
+
+        let handle = device.handle();
+        for i in 0..4 {
+            let result = execute(
+                handle
+                    .request()
+                    .wait_for_running()
+                    .put(id(i), embedded_data(b"hoge")),
+            );
+            if i % 2 == 0 {
+                assert_eq!(*result.unwrap_err().kind(), ErrorKind::RequestDropped);
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+        assert_eq!(handle.metrics().dropped_commands().put(), 2);
         Ok(())
     }
 
@@ -783,4 +1802,149 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn journal_is_synced_before_critical_stop() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let fail = Arc::new(AtomicBool::new(false));
+        let failing_nvm = FailingNvm {
+            inner: nvm.clone(),
+            fail: fail.clone(),
+        };
+        let storage = track!(Storage::create(failing_nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        // ジャーナルに埋め込まれる小さなlump(未同期)
+        track!(execute(d.request().put(id(0), data(b"foo"))))?;
+
+        // データ領域への書き込みに失敗すると、デバイスは停止する
+        fail.store(true, Ordering::SeqCst);
+        let result = execute(d.request().put(id(1), data(&[1; 64 * 1024])));
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::Other));
+        let error = execute(device).expect_err("device should fail");
+        assert!(error
+            .to_string()
+            .contains("Journal synced before stopping the device"));
+
+        // 停止前に同期されたジャーナルから、lumpを復元できる
+        // (致命的なエラーによる停止なので、クリーンシャットダウンとしては扱われない)
+        let mut storage = track!(Storage::open(nvm))?;
+        assert!(!storage.was_clean_shutdown());
+        assert_eq!(track!(storage.get(&id(0)))?, Some(data(b"foo")));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_storage_is_isolated_from_others() -> TestResult {
+        let key = StorageKey::new;
+        let fails = [
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        ];
+        let storages = fails
+            .iter()
+            .enumerate()
+            .map(|(i, fail)| {
+                let nvm = FailingNvm {
+                    inner: SharedMemoryNvm::new(vec![0; 1024 * 1024]),
+                    fail: fail.clone(),
+                };
+                track!(Storage::create(nvm)).map(|s| (key(i as u64), s))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let device = DeviceBuilder::new().spawn_multi(move || Ok(storages));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        track!(execute(
+            d.request().storage(key(1)).put(id(0), data(b"foo"))
+        ))?;
+
+        // 一方のストレージで致命的なエラーが発生する
+        fails[0].store(true, Ordering::SeqCst);
+        let result = execute(
+            d.request()
+                .storage(key(0))
+                .put(id(1), data(&[1; 64 * 1024])),
+        );
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::Other));
+
+        // 以後、そのストレージ宛てのリクエストには同じエラーが返される
+        fails[0].store(false, Ordering::SeqCst);
+        let result = execute(d.request().storage(key(0)).list());
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::Other));
+        let result = execute(d.request().get(id(1)));
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::Other));
+
+        // 他のストレージは引き続き使用可能
+        track!(execute(
+            d.request()
+                .storage(key(1))
+                .put(id(2), data(&[2; 64 * 1024]))
+        ))?;
+        assert_eq!(
+            track!(execute(d.request().storage(key(1)).list()))?,
+            vec![id(0), id(2)]
+        );
+
+        device.stop(Deadline::Immediate);
+        track!(execute(device))?;
+        Ok(())
+    }
+
+    /// 有効化されている間は、大きな書き込みに失敗するNVM.
+    #[derive(Debug)]
+    struct FailingNvm {
+        inner: SharedMemoryNvm,
+        fail: Arc<AtomicBool>,
+    }
+    impl NonVolatileMemory for FailingNvm {
+        fn sync(&mut self) -> Result<()> {
+            self.inner.sync()
+        }
+        fn position(&self) -> u64 {
+            self.inner.position()
+        }
+        fn capacity(&self) -> u64 {
+            self.inner.capacity()
+        }
+        fn block_size(&self) -> BlockSize {
+            self.inner.block_size()
+        }
+        fn split(self, position: u64) -> Result<(Self, Self)> {
+            let (left, right) = track!(self.inner.split(position))?;
+            let left = FailingNvm {
+                inner: left,
+                fail: self.fail.clone(),
+            };
+            let right = FailingNvm {
+                inner: right,
+                fail: self.fail,
+            };
+            Ok((left, right))
+        }
+    }
+    impl io::Seek for FailingNvm {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    impl io::Read for FailingNvm {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+    impl io::Write for FailingNvm {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail.load(Ordering::SeqCst) && buf.len() >= 64 * 1024 {
+                return Err(io::Error::other("injected failure"));
+            }
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
 }
@@ -0,0 +1,346 @@
+use futures::future::{self, Either};
+use futures::Future;
+use std::ops::Range;
+
+use super::{DeviceHandle, DeviceRequest};
+use crate::deadline::Deadline;
+use crate::lump::{LumpData, LumpHeader, LumpId};
+use crate::storage::{PutReport, StorageUsage};
+use crate::{Error, ErrorKind, Result};
+
+/// 名前空間内のローカルなIDに使用可能なビット数.
+const LOCAL_ID_BITS: u32 = 128 - 8;
+
+/// 特定の名前空間に限定された操作を行うためのデバイスのハンドル.
+///
+/// 名前空間は、`LumpId`の最上位バイト(プレフィックス)によって表現される.
+/// このハンドル経由で指定される`LumpId`は、名前空間内でのローカルなIDとして扱われ、
+/// デバイスに対する要求の発行時に、自動的にプレフィックスが付与される
+/// (また結果に含まれる`LumpId`からは、プレフィックスが取り除かれる).
+///
+/// ローカルなIDには、下位120ビットのみが使用可能であり、
+/// それ以上の値が指定された場合には`ErrorKind::InvalidInput`エラーとなる.
+///
+/// `DeviceHandle::namespace`メソッドを通して生成される.
+///
+/// # 注意
+///
+/// 名前空間の区別は、IDの割り当て上の規約に過ぎないので、
+/// 同じデバイスを`DeviceHandle`経由で直接操作した場合には、名前空間を跨いだ操作も可能となる.
+///
+/// また、範囲指定系の操作は`Range<LumpId>`で表現可能な範囲に限定されるため、
+/// 名前空間`0xFF`のローカルなIDの最大値(i.e., `LumpId::new(u128::MAX)`)は、
+/// `list`や`usage`の対象には含まれない.
+#[derive(Debug, Clone)]
+pub struct NamespacedDeviceHandle {
+    handle: DeviceHandle,
+    prefix: u8,
+}
+impl NamespacedDeviceHandle {
+    pub(crate) fn new(handle: DeviceHandle, prefix: u8) -> Self {
+        NamespacedDeviceHandle { handle, prefix }
+    }
+
+    /// 名前空間のプレフィックスを返す.
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// 名前空間内に限定されたリクエストのビルダを返す.
+    pub fn request(&self) -> NamespacedDeviceRequest<'_> {
+        NamespacedDeviceRequest {
+            request: self.handle.request(),
+            prefix: self.prefix,
+        }
+    }
+
+    /// 名前空間を持たない、元のデバイスのハンドルを返す.
+    pub fn device_handle(&self) -> &DeviceHandle {
+        &self.handle
+    }
+
+    /// `DeviceHandle::allocate_lump_data`と同様.
+    pub fn allocate_lump_data(&self, size: usize) -> Result<LumpData> {
+        track!(self.handle.allocate_lump_data(size))
+    }
+
+    /// `DeviceHandle::allocate_lump_data_with_bytes`と同様.
+    pub fn allocate_lump_data_with_bytes(&self, bytes: &[u8]) -> Result<LumpData> {
+        track!(self.handle.allocate_lump_data_with_bytes(bytes))
+    }
+}
+
+/// 名前空間内に限定されたリクエストを発行するためのビルダ.
+///
+/// 各メソッドの挙動は、IDの変換が行われる点を除いて`DeviceRequest`と同様.
+#[derive(Debug)]
+pub struct NamespacedDeviceRequest<'a> {
+    request: DeviceRequest<'a>,
+    prefix: u8,
+}
+impl<'a> NamespacedDeviceRequest<'a> {
+    /// Lumpを格納する.
+    pub fn put(
+        &self,
+        lump_id: LumpId,
+        lump_data: LumpData,
+    ) -> impl Future<Item = PutReport, Error = Error> {
+        match track!(to_global_id(self.prefix, lump_id)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(id) => Either::B(self.request.put(id, lump_data)),
+        }
+    }
+
+    /// Lumpを取得する.
+    pub fn get(&self, lump_id: LumpId) -> impl Future<Item = Option<LumpData>, Error = Error> {
+        match track!(to_global_id(self.prefix, lump_id)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(id) => Either::B(self.request.get(id)),
+        }
+    }
+
+    /// Lumpのヘッダを取得する.
+    pub fn head(&self, lump_id: LumpId) -> impl Future<Item = Option<LumpHeader>, Error = Error> {
+        match track!(to_global_id(self.prefix, lump_id)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(id) => Either::B(self.request.head(id)),
+        }
+    }
+
+    /// Lumpを削除する.
+    pub fn delete(&self, lump_id: LumpId) -> impl Future<Item = bool, Error = Error> {
+        match track!(to_global_id(self.prefix, lump_id)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(id) => Either::B(self.request.delete(id)),
+        }
+    }
+
+    /// 名前空間内のlumpを範囲オブジェクトを用いて削除する.
+    ///
+    /// `DeviceRequest::delete_range`と同様に、削除はアトミックには行われない.
+    pub fn delete_range(
+        &self,
+        range: Range<LumpId>,
+    ) -> impl Future<Item = Vec<LumpId>, Error = Error> {
+        let prefix = self.prefix;
+        match track!(to_global_range(prefix, range)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(range) => Either::B(
+                self.request
+                    .delete_range(range)
+                    .map(move |ids| to_local_ids(prefix, ids)),
+            ),
+        }
+    }
+
+    /// 名前空間内に保存されているlump一覧を取得する.
+    pub fn list(&self) -> impl Future<Item = Vec<LumpId>, Error = Error> {
+        let prefix = self.prefix;
+        self.request
+            .list_range(namespace_range(prefix))
+            .map(move |ids| to_local_ids(prefix, ids))
+    }
+
+    /// 名前空間内の範囲を指定してlump一覧を取得する.
+    pub fn list_range(
+        &self,
+        range: Range<LumpId>,
+    ) -> impl Future<Item = Vec<LumpId>, Error = Error> {
+        let prefix = self.prefix;
+        match track!(to_global_range(prefix, range)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(range) => Either::B(
+                self.request
+                    .list_range(range)
+                    .map(move |ids| to_local_ids(prefix, ids)),
+            ),
+        }
+    }
+
+    /// 名前空間全体のストレージ使用量を取得する.
+    pub fn usage(&self) -> impl Future<Item = StorageUsage, Error = Error> {
+        self.request.usage_range(namespace_range(self.prefix))
+    }
+
+    /// 名前空間内の範囲を指定してストレージ使用量を取得する.
+    pub fn usage_range(
+        &self,
+        range: Range<LumpId>,
+    ) -> impl Future<Item = StorageUsage, Error = Error> {
+        match track!(to_global_range(self.prefix, range)) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(range) => Either::B(self.request.usage_range(range)),
+        }
+    }
+
+    /// `DeviceRequest::deadline`と同様.
+    pub fn deadline(&mut self, deadline: Deadline) -> &mut Self {
+        self.request.deadline(deadline);
+        self
+    }
+
+    /// `DeviceRequest::journal_sync`と同様.
+    pub fn journal_sync(&mut self) -> &mut Self {
+        self.request.journal_sync();
+        self
+    }
+
+    /// `DeviceRequest::max_queue_len`と同様.
+    pub fn max_queue_len(&mut self, max: usize) -> &mut Self {
+        self.request.max_queue_len(max);
+        self
+    }
+
+    /// `DeviceRequest::wait_for_running`と同様.
+    pub fn wait_for_running(&mut self) -> &mut Self {
+        self.request.wait_for_running();
+        self
+    }
+
+    /// `DeviceRequest::prioritized`と同様.
+    pub fn prioritized(&mut self) -> &mut Self {
+        self.request.prioritized();
+        self
+    }
+}
+
+fn to_global_id(prefix: u8, lump_id: LumpId) -> Result<LumpId> {
+    let id = lump_id.as_u128();
+    track_assert_eq!(
+        id >> LOCAL_ID_BITS,
+        0,
+        ErrorKind::InvalidInput,
+        "Too large local lump id: {}",
+        lump_id
+    );
+    Ok(LumpId::new((u128::from(prefix) << LOCAL_ID_BITS) | id))
+}
+
+fn to_global_range(prefix: u8, range: Range<LumpId>) -> Result<Range<LumpId>> {
+    let start = track!(to_global_id(prefix, range.start))?;
+    let end = if range.end.as_u128() == 1 << LOCAL_ID_BITS {
+        // ローカルなIDの上限を終端に指定することは許容する
+        namespace_range(prefix).end
+    } else {
+        track!(to_global_id(prefix, range.end))?
+    };
+    Ok(Range { start, end })
+}
+
+fn to_local_ids(prefix: u8, ids: Vec<LumpId>) -> Vec<LumpId> {
+    let mask = (1 << LOCAL_ID_BITS) - 1;
+    ids.into_iter()
+        .inspect(|id| debug_assert_eq!((id.as_u128() >> LOCAL_ID_BITS) as u8, prefix))
+        .map(|id| LumpId::new(id.as_u128() & mask))
+        .collect()
+}
+
+fn namespace_range(prefix: u8) -> Range<LumpId> {
+    let start = u128::from(prefix) << LOCAL_ID_BITS;
+    let end = (u128::from(prefix) + 1)
+        .checked_shl(LOCAL_ID_BITS)
+        .filter(|&end| end != 0)
+        .unwrap_or(u128::MAX);
+    Range {
+        start: LumpId::new(start),
+        end: LumpId::new(end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fibers_global::execute;
+    use std::ops::Range;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::device::DeviceBuilder;
+    use crate::nvm::MemoryNvm;
+    use crate::storage::StorageBuilder;
+
+    #[test]
+    fn namespace_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new().journal_region_ratio(0.99).create(nvm))?;
+        let device = DeviceBuilder::new().spawn(|| Ok(storage));
+        let d = device.handle();
+        let _ = execute(d.request().wait_for_running().list()); // デバイスの起動を待機
+
+        let foo = d.namespace(1);
+        let bar = d.namespace(0xFF);
+        track!(execute(foo.request().put(id(0), data(b"foo0"))))?;
+        track!(execute(foo.request().put(id(1), data(b"foo1"))))?;
+        track!(execute(bar.request().put(id(0), data(b"bar0"))))?;
+
+        // 各名前空間は独立している
+        assert_eq!(track!(execute(foo.request().list()))?, vec![id(0), id(1)]);
+        assert_eq!(track!(execute(bar.request().list()))?, vec![id(0)]);
+        assert_eq!(
+            track!(execute(bar.request().get(id(0))))?,
+            Some(data(b"bar0"))
+        );
+        assert_eq!(track!(execute(bar.request().get(id(1))))?, None);
+
+        // 実際のIDにはプレフィックスが付与されている
+        assert_eq!(
+            track!(execute(d.request().list()))?,
+            vec![
+                LumpId::new(1 << LOCAL_ID_BITS),
+                LumpId::new((1 << LOCAL_ID_BITS) + 1),
+                LumpId::new(0xFF << LOCAL_ID_BITS),
+            ]
+        );
+
+        // 使用量は名前空間毎に集計される
+        assert_eq!(track!(execute(foo.request().usage()))?.bytecount(), Some(8));
+
+        // 範囲操作
+        assert_eq!(
+            track!(execute(foo.request().list_range(Range {
+                start: id(1),
+                end: LumpId::new(1 << LOCAL_ID_BITS),
+            })))?,
+            vec![id(1)]
+        );
+        assert_eq!(
+            track!(execute(foo.request().delete_range(Range {
+                start: id(0),
+                end: id(10),
+            })))?,
+            vec![id(0), id(1)]
+        );
+        assert_eq!(track!(execute(foo.request().list()))?, vec![]);
+        assert_eq!(track!(execute(bar.request().list()))?, vec![id(0)]);
+
+        // ローカルなIDとして使用できない値
+        let e = execute(foo.request().put(LumpId::new(u128::MAX), data(b"baz")));
+        assert_eq!(e.err().map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        Ok(())
+    }
+
+    #[test]
+    fn namespace_range_works() {
+        assert_eq!(
+            namespace_range(0),
+            Range {
+                start: LumpId::new(0),
+                end: LumpId::new(1 << LOCAL_ID_BITS)
+            }
+        );
+        assert_eq!(
+            namespace_range(0xFF),
+            Range {
+                start: LumpId::new(0xFF << LOCAL_ID_BITS),
+                end: LumpId::new(u128::MAX)
+            }
+        );
+    }
+
+    fn id(id: usize) -> LumpId {
+        LumpId::new(id as u128)
+    }
+
+    fn data(data: &[u8]) -> LumpData {
+        LumpData::new_embedded(Vec::from(data)).unwrap()
+    }
+}
@@ -0,0 +1,210 @@
+use std::env;
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
+
+use super::builder::DeviceBuilder;
+use super::long_queue_policy::LongQueuePolicy;
+use crate::{ErrorKind, Result};
+
+/// `prefix`で始まる名前の環境変数群を、`builder`に反映する.
+pub fn apply_env(builder: &mut DeviceBuilder, prefix: &str) -> Result<()> {
+    for (name, value) in env::vars_os() {
+        let name = match name.to_str() {
+            Some(name) if name.starts_with(prefix) => name.to_owned(),
+            _ => continue,
+        };
+        let value = track_assert_some!(
+            value.to_str(),
+            ErrorKind::InvalidInput,
+            "Non UTF-8 environment variable: {}",
+            name
+        );
+        let key = name[prefix.len()..].to_ascii_lowercase();
+        track!(apply(builder, &key, value), "env={}", name)?;
+    }
+    Ok(())
+}
+
+/// TOML形式の文字列のトップレベルのテーブルの内容を、`builder`に反映する.
+#[cfg(feature = "toml")]
+pub fn apply_toml(builder: &mut DeviceBuilder, toml: &str) -> Result<()> {
+    let table: toml::Table = track!(toml.parse().map_err(|e| ErrorKind::InvalidInput.cause(e)))?;
+    for (key, value) in table {
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(n) => n.to_string(),
+            toml::Value::Float(n) => n.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            _ => track_panic!(ErrorKind::InvalidInput, "Unsupported value: key={}", key),
+        };
+        track!(apply(builder, &key, &value))?;
+    }
+    Ok(())
+}
+
+/// 設定項目`key`の値を、文字列表現の`value`から解釈して`builder`に反映する.
+fn apply(builder: &mut DeviceBuilder, key: &str, value: &str) -> Result<()> {
+    let value = value.trim();
+    match key {
+        "idle_threshold" => builder.idle_threshold(track!(parse_duration(value))?),
+        "max_queue_len" => builder.max_queue_len(track!(parse(value))?),
+        "max_keep_busy_duration" => builder.max_keep_busy_duration(track!(parse_duration(value))?),
+        "busy_threshold" => builder.busy_threshold(track!(parse(value))?),
+        "long_queue_policy" => builder.long_queue_policy(track!(parse_long_queue_policy(value))?),
+        "long_command_slice_size" => builder.long_command_slice_size(track!(parse(value))?),
+        "max_side_job_duration" => builder.max_side_job_duration(track!(parse_duration(value))?),
+        "background_gc_scan" => builder.background_gc_scan(track!(parse(value))?),
+        "journal_gc_queue_size" => builder.journal_gc_queue_size(track!(parse(value))?),
+        "journal_gc_batch_size" => builder.journal_gc_batch_size(track!(parse(value))?),
+        "max_consecutive_writes" => builder.max_consecutive_writes(track!(parse(value))?),
+        "event_log_capacity" => builder.event_log_capacity(track!(parse(value))?),
+        "idempotency_cache_size" => builder.idempotency_cache_size(track!(parse(value))?),
+        "reject_unreachable_deadlines" => {
+            builder.reject_unreachable_deadlines(track!(parse(value))?)
+        }
+        _ => track_panic!(ErrorKind::InvalidInput, "Unknown setting: {:?}", key),
+    };
+    Ok(())
+}
+
+fn parse<T>(value: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    track!(value
+        .parse()
+        .map_err(|e| ErrorKind::InvalidInput.cause(e).into()))
+}
+
+/// `"100ms"`や`"10s"`といった、数値と単位(`ms`, `s`, `m`, `h`)から成る文字列を時間として解釈する.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (n, unit) = value.split_at(digits);
+    let n: u64 = track!(parse(n), "value={:?}", value)?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(n),
+        "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n * 60),
+        "h" => Duration::from_secs(n * 60 * 60),
+        _ => track_panic!(ErrorKind::InvalidInput, "Unknown duration: {:?}", value),
+    };
+    Ok(duration)
+}
+
+/// `"stop"`ないし`"refuse_new_requests:<RATIO>"`、`"drop:<RATIO>"`形式の文字列を`LongQueuePolicy`として解釈する.
+fn parse_long_queue_policy(value: &str) -> Result<LongQueuePolicy> {
+    let mut tokens = value.splitn(2, ':');
+    let name = tokens.next().expect("Never fails");
+    let ratio = tokens.next().map(parse::<f64>).transpose();
+    let ratio = track!(ratio, "value={:?}", value)?;
+    if let Some(ratio) = ratio {
+        track_assert!(
+            (0.0..=1.0).contains(&ratio),
+            ErrorKind::InvalidInput,
+            "Ratio out of range: {:?}",
+            value
+        );
+    }
+    let policy = match (name, ratio) {
+        ("stop", None) => LongQueuePolicy::Stop,
+        ("refuse_new_requests", Some(ratio)) => LongQueuePolicy::RefuseNewRequests { ratio },
+        ("drop", Some(ratio)) => LongQueuePolicy::Drop { ratio },
+        _ => track_panic!(
+            ErrorKind::InvalidInput,
+            "Unknown long queue policy: {:?}",
+            value
+        ),
+    };
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::device::DeviceConfig;
+
+    #[test]
+    fn from_env_works() -> TestResult {
+        env::set_var("CANNYLS_PRESET_TEST_IDLE_THRESHOLD", "250ms");
+        env::set_var("CANNYLS_PRESET_TEST_MAX_QUEUE_LEN", "500");
+        env::set_var("CANNYLS_PRESET_TEST_LONG_QUEUE_POLICY", "drop:0.25");
+        env::set_var("CANNYLS_PRESET_TEST_JOURNAL_GC_QUEUE_SIZE", "64");
+        env::set_var("CANNYLS_PRESET_TEST_BACKGROUND_GC_SCAN", "true");
+        let builder = track!(DeviceBuilder::from_env("CANNYLS_PRESET_TEST_"))?;
+        let config = DeviceConfig::new(&builder);
+        assert_eq!(config.idle_threshold, Duration::from_millis(250));
+        assert_eq!(config.max_queue_len, 500);
+        assert_eq!(
+            config.long_queue_policy,
+            LongQueuePolicy::Drop { ratio: 0.25 }
+        );
+        assert_eq!(config.journal_gc_queue_size, Some(64));
+        assert!(config.background_gc_scan);
+
+        // 指定されなかった項目はデフォルト値のまま
+        assert_eq!(config.busy_threshold, DeviceBuilder::new().busy_threshold);
+
+        // 未知の項目や不正な値はエラー
+        env::set_var("CANNYLS_PRESET_TEST2_IDLE_THRESHOLD", "10");
+        assert!(DeviceBuilder::from_env("CANNYLS_PRESET_TEST2_").is_err());
+        env::set_var("CANNYLS_PRESET_TEST3_UNKNOWN", "10");
+        assert!(DeviceBuilder::from_env("CANNYLS_PRESET_TEST3_").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn from_toml_works() -> TestResult {
+        let dir = track_io!(tempdir::TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("device.toml");
+        let toml = r#"
+idle_threshold = "1s"
+busy_threshold = 10
+long_queue_policy = "refuse_new_requests:0.5"
+max_side_job_duration = "20ms"
+reject_unreachable_deadlines = true
+"#;
+        track_io!(std::fs::write(&path, toml))?;
+        let builder = track!(DeviceBuilder::from_toml(&path))?;
+        let config = DeviceConfig::new(&builder);
+        assert_eq!(config.idle_threshold, Duration::from_secs(1));
+        assert_eq!(config.busy_threshold, 10);
+        assert_eq!(
+            config.long_queue_policy,
+            LongQueuePolicy::RefuseNewRequests { ratio: 0.5 }
+        );
+        assert_eq!(
+            config.max_side_job_duration,
+            Some(Duration::from_millis(20))
+        );
+        assert!(config.reject_unreachable_deadlines);
+
+        track_io!(std::fs::write(&path, "busy_threshold = -1"))?;
+        assert!(DeviceBuilder::from_toml(&path).is_err());
+        track_io!(std::fs::write(&path, "[device]\nbusy_threshold = 1"))?;
+        assert!(DeviceBuilder::from_toml(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_works() -> TestResult {
+        assert_eq!(track!(parse_duration("5m"))?, Duration::from_secs(300));
+        assert_eq!(track!(parse_duration("2h"))?, Duration::from_secs(7200));
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("1.5s").is_err());
+
+        assert_eq!(
+            track!(parse_long_queue_policy("stop"))?,
+            LongQueuePolicy::Stop
+        );
+        assert!(parse_long_queue_policy("stop:0.1").is_err());
+        assert!(parse_long_queue_policy("drop").is_err());
+        assert!(parse_long_queue_policy("drop:1.5").is_err());
+        Ok(())
+    }
+}
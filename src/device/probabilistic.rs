@@ -1,22 +1,58 @@
 use slog::Logger;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
 
 /// リクエストを落としたり落とさなかったりする決定を下すオブジェクト。
-pub(crate) trait Dropper: Debug {
+///
+/// デバイスが過負荷状態にある間、`LongQueuePolicy::RefuseNewRequests`や`LongQueuePolicy::Drop`の対象となる
+/// リクエスト毎に`will_drop`が呼び出される。
+///
+/// デフォルトでは`ProbabilisticDropper`が使われるが、`DeviceBuilder::dropper`を使うことで、
+/// 独自の実装 (e.g., トークンバケットに基づくもの) に差し替えることができる。
+pub trait Dropper: Debug {
     /// 次のリクエストを落とすなら true、落とさないなら false。
     /// 内部状態の変更も許されることに注意。
     fn will_drop(&mut self) -> bool;
 }
 
+/// デバイスの起動毎に `Dropper` を生成するための関数。
+#[derive(Clone)]
+pub(crate) struct DropperFactory(Arc<dyn Fn() -> Box<dyn Dropper + Send> + Send + Sync>);
+impl DropperFactory {
+    pub fn new<F, D>(f: F) -> Self
+    where
+        F: Fn() -> D + Send + Sync + 'static,
+        D: Dropper + Send + 'static,
+    {
+        DropperFactory(Arc::new(move || Box::new(f()) as Box<dyn Dropper + Send>))
+    }
+
+    pub fn create(&self) -> Box<dyn Dropper + Send> {
+        (self.0)()
+    }
+}
+impl Debug for DropperFactory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DropperFactory(_)")
+    }
+}
+
 /// あらかじめ指定した確率で落とす判定をする Dropper。
+///
+/// 乱数は使わずに、`will_drop` の呼び出し毎に内部のカウンタを ratio ずつ増やし、
+/// 1 以上になったら落とす、という決定的な方法で判定を行う。
+/// カウンタの初期値はシードによって決まるため、同じシードを与えれば、常に同じ判定列が得られる。
 #[derive(Debug)]
-pub(crate) struct ProbabilisticDropper {
+pub struct ProbabilisticDropper {
     logger: Logger,
     ratio: f64,
     counter: f64,
 }
 
 impl ProbabilisticDropper {
+    /// 落とす確率として `ratio` (0 以上 1 以下) を指定して、新しいインスタンスを生成する。
+    ///
+    /// カウンタの初期値は 0 となる。
     pub fn new(logger: Logger, ratio: f64) -> Self {
         Self {
             logger,
@@ -24,6 +60,27 @@ impl ProbabilisticDropper {
             counter: 0.0,
         }
     }
+
+    /// シードを指定して、新しいインスタンスを生成する。
+    ///
+    /// カウンタの初期値が、シードから導出された `[0, 1)` の範囲の値となる。
+    /// 負荷試験等で、複数のデバイス間で落とすタイミングをずらしつつ、結果の再現性を保ちたい場合に使用する。
+    pub fn with_seed(logger: Logger, ratio: f64, seed: u64) -> Self {
+        Self {
+            logger,
+            ratio,
+            counter: seed_to_unit(seed),
+        }
+    }
+}
+
+/// シードを `[0, 1)` の範囲の値に変換する (splitmix64)。
+fn seed_to_unit(seed: u64) -> f64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
 }
 
 impl Dropper for ProbabilisticDropper {
@@ -64,4 +121,23 @@ mod tests {
         assert!(2900 < dropped);
         assert!(dropped < 3100);
     }
+
+    #[test]
+    fn seeded_probabilistic_dropper_works() {
+        let logger = Logger::root(Discard, o!());
+        let decisions = |seed| {
+            let mut dropper = ProbabilisticDropper::with_seed(logger.clone(), 0.3, seed);
+            (0..1000).map(|_| dropper.will_drop()).collect::<Vec<_>>()
+        };
+
+        // 同じシードなら同じ判定列になる
+        assert_eq!(decisions(10), decisions(10));
+
+        // シードが異なっても、落とす割合はおよそ ratio に等しい
+        for seed in 0..10 {
+            let dropped = decisions(seed).into_iter().filter(|&d| d).count();
+            assert!((299..=301).contains(&dropped), "dropped={}", dropped);
+        }
+        assert!((0..10).any(|seed| decisions(seed) != decisions(10)));
+    }
 }
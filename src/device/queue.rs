@@ -1,9 +1,13 @@
 use std::cmp;
 use std::collections::BinaryHeap;
+use std::sync::Arc;
 use std::time::Instant;
 
 use crate::deadline::Deadline;
+use crate::device::blocking::BlockingCommand;
+use crate::device::clock::{Clock, SystemClock};
 use crate::device::command::Command;
+use crate::device::StorageKey;
 
 /// デバイスに発行されたコマンド群の管理キュー.
 ///
@@ -14,51 +18,165 @@ use crate::device::command::Command;
 /// デバイスに対して並行的に発行されたコマンド群は、
 /// そのデッドラインが近い順に実行される.
 ///
+/// ただし、書き込み系のコマンドの連続実行数の上限が指定されている場合には、
+/// 上限に達した時点で、デッドラインに関わらず読み込み系のコマンドが優先される
+/// (大きな書き込みが続く状況で、読み込みが飢餓状態に陥るのを防ぐため).
+///
 /// なお、これが行うのはあくまでも並び替えのみで、
 /// デッドラインを過ぎたコマンドの破棄は行わない.
 #[derive(Debug)]
 pub struct DeadlineQueue {
     seqno: u64,
-    heap: BinaryHeap<Item>,
+    reads: BinaryHeap<Item>,
+    others: BinaryHeap<Item>,
+    max_consecutive_writes: Option<usize>,
+    consecutive_writes: usize,
+    clock: Arc<dyn Clock>,
 }
 impl DeadlineQueue {
     /// 新しい`DeadlineQueue`インスタンスを生成する.
     pub fn new() -> Self {
         DeadlineQueue {
             seqno: 0,
-            heap: BinaryHeap::new(),
+            reads: BinaryHeap::new(),
+            others: BinaryHeap::new(),
+            max_consecutive_writes: None,
+            consecutive_writes: 0,
+            clock: Arc::new(SystemClock),
         }
     }
 
-    /// 新しいコマンドをキューに追加する.
-    pub fn push(&mut self, command: Command) {
-        let deadline = AbsoluteDeadline::new(command.deadline());
+    /// 相対的なデッドラインの解決や、キューへの追加時刻の記録に用いる時計を設定する.
+    ///
+    /// デフォルトは`SystemClock`.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// 書き込み系のコマンドの連続実行数の上限を設定する.
+    ///
+    /// `None`の場合には上限なし(i.e., 純粋なデッドライン順).
+    pub fn set_max_consecutive_writes(&mut self, max: Option<usize>) {
+        self.max_consecutive_writes = max;
+    }
+
+    /// 新しいコマンドを、その対象ストレージのキーと共にキューに追加する.
+    #[cfg(test)]
+    pub fn push(&mut self, storage: Option<StorageKey>, command: Command) {
+        self.push_with_trace(storage, command, CommandTrace::default());
+    }
+
+    /// 新しいコマンドを、その対象ストレージのキーおよび識別情報と共にキューに追加する.
+    pub fn push_with_trace(
+        &mut self,
+        storage: Option<StorageKey>,
+        command: Command,
+        trace: CommandTrace,
+    ) {
+        let now = self.clock.now();
+        let deadline = AbsoluteDeadline::new(command.deadline(), now);
+        let is_read = command.is_read();
         let item = Item {
             seqno: self.seqno,
+            storage,
             command,
             deadline,
+            enqueued_at: now,
+            trace,
         };
-        self.heap.push(item);
+        if is_read {
+            self.reads.push(item);
+        } else {
+            self.others.push(item);
+        }
         self.seqno += 1;
     }
 
     /// 次に処理するコマンドを取り出す.
+    #[cfg(test)]
     pub fn pop(&mut self) -> Option<Command> {
-        self.heap.pop().map(|t| t.command)
+        self.pop_with_trace().map(|(_, command, _, _)| command)
+    }
+
+    /// 次に処理するコマンドを、対象ストレージのキー・キューに追加された時刻・識別情報と共に取り出す.
+    pub fn pop_with_trace(
+        &mut self,
+    ) -> Option<(Option<StorageKey>, Command, Instant, CommandTrace)> {
+        let item = if self.next_is_read()? {
+            self.reads.pop()
+        } else {
+            self.others.pop()
+        }?;
+        if item.command.is_read() {
+            self.consecutive_writes = 0;
+        } else if item.command.is_write() {
+            self.consecutive_writes += 1;
+        }
+        Some((item.storage, item.command, item.enqueued_at, item.trace))
+    }
+
+    /// 次に処理されるコマンドを、キューから取り出さずに参照する.
+    pub fn peek(&self) -> Option<&Command> {
+        let heap = if self.next_is_read()? {
+            &self.reads
+        } else {
+            &self.others
+        };
+        heap.peek().map(|t| &t.command)
+    }
+
+    /// デッドラインが`deadline`以前の(i.e., それよりも先に処理される)コマンドの数を返す.
+    pub fn count_until(&self, deadline: Deadline) -> usize {
+        let deadline = AbsoluteDeadline::new(deadline, self.clock.now());
+        self.reads
+            .iter()
+            .chain(self.others.iter())
+            .filter(|item| item.deadline <= deadline)
+            .count()
     }
 
     /// キューに格納されている要素数を返す.
     pub fn len(&self) -> usize {
-        self.heap.len()
+        self.reads.len() + self.others.len()
+    }
+
+    /// 次に取り出す要素が、読み込み系のコマンド用のヒープにあるかどうかを判定する.
+    ///
+    /// キューが空の場合には`None`が返される.
+    fn next_is_read(&self) -> Option<bool> {
+        match (self.reads.peek(), self.others.peek()) {
+            (None, None) => None,
+            (Some(_), None) => Some(true),
+            (None, Some(_)) => Some(false),
+            (Some(read), Some(other)) => {
+                let starved = self
+                    .max_consecutive_writes
+                    .is_some_and(|max| self.consecutive_writes >= max);
+                Some(starved || read > other)
+            }
+        }
     }
 }
 
+/// キューに追加されたコマンドの識別情報.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandTrace {
+    /// デバイスがコマンドを受け付けた順に割り当てられる番号.
+    pub id: u64,
+
+    /// キューへの追加時に実行中だったコマンド.
+    pub blocked_behind: Option<BlockingCommand>,
+}
+
 /// ヒープに格納する要素.
 #[derive(Debug)]
 struct Item {
     seqno: u64, // デッドラインが同じ要素をFIFO順で扱うためのシーケンス番号
+    storage: Option<StorageKey>,
     command: Command,
     deadline: AbsoluteDeadline,
+    enqueued_at: Instant,
+    trace: CommandTrace,
 }
 impl PartialEq for Item {
     fn eq(&self, other: &Self) -> bool {
@@ -87,10 +205,11 @@ enum AbsoluteDeadline {
     Infinity,
 }
 impl AbsoluteDeadline {
-    fn new(relative: Deadline) -> Self {
+    fn new(relative: Deadline, now: Instant) -> Self {
         match relative {
             Deadline::Immediate => AbsoluteDeadline::Immediate,
-            Deadline::Within(d) => AbsoluteDeadline::Until(Instant::now() + d),
+            Deadline::Within(d) => AbsoluteDeadline::Until(now + d),
+            Deadline::At(t) => AbsoluteDeadline::Until(t),
             Deadline::Infinity => AbsoluteDeadline::Infinity,
         }
     }
@@ -98,24 +217,26 @@ impl AbsoluteDeadline {
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
     use std::time::Duration;
 
     use super::*;
     use crate::deadline::Deadline;
-    use crate::device::command::{Command, GetLump};
+    use crate::device::clock::ManualClock;
+    use crate::device::command::{Command, DeleteLump, GetLump};
     use crate::lump::LumpId;
 
     #[test]
     fn deadline_works() {
+        let clock = ManualClock::new();
         let mut queue = DeadlineQueue::new();
+        queue.set_clock(Arc::new(clock.clone()));
 
-        queue.push(command(0, Deadline::Infinity));
-        queue.push(command(1, Deadline::Immediate));
-        queue.push(command(2, Deadline::Within(Duration::from_millis(1))));
-        thread::sleep(Duration::from_millis(5));
-        queue.push(command(3, Deadline::Within(Duration::from_millis(0))));
-        queue.push(command(4, Deadline::Immediate));
+        queue.push(None, command(0, Deadline::Infinity));
+        queue.push(None, command(1, Deadline::Immediate));
+        queue.push(None, command(2, Deadline::Within(Duration::from_millis(1))));
+        clock.advance(Duration::from_millis(5));
+        queue.push(None, command(3, Deadline::Within(Duration::from_millis(0))));
+        queue.push(None, command(4, Deadline::Immediate));
 
         assert_eq!(queue.len(), 5);
         assert_eq!(lump_id(queue.pop()), Some(1));
@@ -127,17 +248,110 @@ mod tests {
         assert_eq!(queue.len(), 0);
     }
 
+    #[test]
+    fn absolute_deadline_works() {
+        let mut queue = DeadlineQueue::new();
+        let now = Instant::now();
+
+        queue.push(None, command(0, Deadline::Within(Duration::from_secs(10))));
+        queue.push(
+            None,
+            command(1, Deadline::At(now + Duration::from_secs(20))),
+        );
+        queue.push(None, command(2, Deadline::At(now + Duration::from_secs(5))));
+        queue.push(None, command(3, Deadline::Infinity));
+        queue.push(None, command(4, Deadline::At(now)));
+
+        assert_eq!(lump_id(queue.pop()), Some(4));
+        assert_eq!(lump_id(queue.pop()), Some(2));
+        assert_eq!(lump_id(queue.pop()), Some(0));
+        assert_eq!(lump_id(queue.pop()), Some(1));
+        assert_eq!(lump_id(queue.pop()), Some(3));
+    }
+
+    #[test]
+    fn count_until_works() {
+        let mut queue = DeadlineQueue::new();
+        let now = Instant::now();
+
+        queue.push(None, command(0, Deadline::Immediate));
+        queue.push(None, command(1, Deadline::At(now + Duration::from_secs(5))));
+        queue.push(
+            None,
+            delete_command(2, Deadline::At(now + Duration::from_secs(10))),
+        );
+        queue.push(None, command(3, Deadline::Infinity));
+
+        assert_eq!(queue.count_until(Deadline::Immediate), 1);
+        assert_eq!(queue.count_until(Deadline::At(now)), 1);
+        assert_eq!(
+            queue.count_until(Deadline::At(now + Duration::from_secs(10))),
+            3
+        );
+        assert_eq!(queue.count_until(Deadline::Infinity), 4);
+    }
+
+    #[test]
+    fn max_consecutive_writes_works() {
+        let mut queue = DeadlineQueue::new();
+        queue.set_max_consecutive_writes(Some(2));
+
+        queue.push(None, command(0, Deadline::Infinity));
+        for i in 1..5 {
+            queue.push(None, delete_command(i, Deadline::Immediate));
+        }
+        queue.push(None, command(5, Deadline::Infinity));
+
+        // 書き込みが二回続いたら、デッドラインが遅くても読み込みが優先される
+        assert_eq!(lump_id(queue.pop()), Some(1));
+        assert_eq!(lump_id(queue.pop()), Some(2));
+        assert_eq!(queue.peek().map(lump_id_of), Some(0));
+        assert_eq!(lump_id(queue.pop()), Some(0));
+        assert_eq!(lump_id(queue.pop()), Some(3));
+        assert_eq!(lump_id(queue.pop()), Some(4));
+        assert_eq!(lump_id(queue.pop()), Some(5));
+        assert_eq!(lump_id(queue.pop()), None);
+
+        // 上限を指定しない場合は、デッドライン順
+        let mut queue = DeadlineQueue::new();
+        queue.push(None, command(0, Deadline::Infinity));
+        for i in 1..5 {
+            queue.push(None, delete_command(i, Deadline::Immediate));
+        }
+        for i in 1..5 {
+            assert_eq!(lump_id(queue.pop()), Some(i));
+        }
+        assert_eq!(lump_id(queue.pop()), Some(0));
+    }
+
     fn command(lump_id: u128, deadline: Deadline) -> Command {
         Command::Get(GetLump::new(LumpId::new(lump_id), deadline, false).0)
     }
 
+    fn delete_command(lump_id: u128, deadline: Deadline) -> Command {
+        Command::Delete(
+            DeleteLump::new(
+                LumpId::new(lump_id),
+                deadline,
+                false,
+                false,
+                None,
+                None,
+                None,
+            )
+            .0,
+        )
+    }
+
     fn lump_id(command: Option<Command>) -> Option<u128> {
-        command.map(|c| {
-            if let Command::Get(c) = c {
-                c.lump_id().as_u128()
-            } else {
-                unreachable!()
-            }
-        })
+        command.map(|c| lump_id_of(&c))
+    }
+
+    fn lump_id_of(command: &Command) -> u128 {
+        match *command {
+            Command::Get(ref c) => c.lump_id().as_u128(),
+            Command::Delete(ref c) => c.lump_id().as_u128(),
+            _ => unreachable!(),
+        }
     }
 }
@@ -1,13 +1,18 @@
-use futures::Future;
-use std::ops::Range;
+use futures::future::{self, Either};
+use futures::{Async, Future, Poll, Stream};
+use std::time::Duration;
+use std::vec;
 use trackable::error::ErrorKindExt;
 
 use super::thread::DeviceThreadHandle;
 use crate::deadline::Deadline;
 use crate::device::command::{self, Command};
-use crate::device::DeviceStatus;
-use crate::lump::{LumpData, LumpHeader, LumpId};
-use crate::storage::StorageUsage;
+use crate::device::{DeviceEvent, DeviceStats, DeviceStatus, StorageKey};
+use crate::lump::{LumpData, LumpDataInner, LumpHeader, LumpId, LumpRange};
+use crate::storage::{
+    CheckLevel, CheckReport, DeleteCondition, JournalCursor, JournalEntry, PutReport, StorageUsage,
+    WarmUpReport,
+};
 use crate::{Error, ErrorKind, Result};
 
 /// デバイスに対してリクエストを発行するためのビルダ.
@@ -25,7 +30,11 @@ pub struct DeviceRequest<'a> {
     max_queue_len: Option<usize>,
     wait_for_running: bool,
     enforce_journal_sync: bool,
+    max_sync_delay: Option<Duration>,
     prioritized: bool,
+    audit: Option<Vec<u8>>,
+    idempotency_key: Option<u128>,
+    storage: Option<StorageKey>,
 }
 impl<'a> DeviceRequest<'a> {
     pub(crate) fn new(device: &'a DeviceThreadHandle) -> Self {
@@ -35,13 +44,18 @@ impl<'a> DeviceRequest<'a> {
             max_queue_len: None,
             wait_for_running: false,
             enforce_journal_sync: false,
+            max_sync_delay: None,
             prioritized: false,
+            audit: None,
+            idempotency_key: None,
+            storage: None,
         }
     }
 
     /// Lumpを格納する.
     ///
-    /// 新規追加の場合には`true`が、上書きの場合は`false`が、結果として返される.
+    /// 結果として、書き込み内容に関する情報を保持する`PutReport`が返される.
+    /// 新規追加か上書きかは`PutReport::is_new`で判定可能.
     ///
     /// # 性能上の注意
     ///
@@ -49,22 +63,62 @@ impl<'a> DeviceRequest<'a> {
     /// デバイスが管理しているストレージへの書き込み時に、
     /// データをストレージのブロック境界にアライメントするためのメモリコピーが余分に発生してしまう.
     /// それを避けたい場合には、`DeviceHandle::allocate_lump_data`メソッドを使用して`LumpData`を生成すると良い.
+    ///
+    /// # Errors
+    ///
+    /// ジャーナル領域に埋め込まれるデータのサイズが`LumpData::MAX_EMBEDDED_SIZE`を超えている場合には、
+    /// コマンドはデバイスに送信されずに、`ErrorKind::InvalidInput`エラーで即座に失敗する.
     pub fn put(
         &self,
         lump_id: LumpId,
         lump_data: LumpData,
-    ) -> impl Future<Item = bool, Error = Error> {
+    ) -> impl Future<Item = PutReport, Error = Error> {
+        if let Err(e) = track!(check_embedding(&lump_data)) {
+            return Either::A(future::err(e));
+        }
         let deadline = self.deadline.unwrap_or_default();
         let prioritized = self.prioritized;
-        let (command, response) = command::PutLump::new(
+        let (mut command, response) = command::PutLump::new(
             lump_id,
             lump_data,
             deadline,
             prioritized,
             self.enforce_journal_sync,
+            self.max_sync_delay,
+            self.audit.clone(),
         );
+        command.set_idempotency_key(self.idempotency_key);
         self.send_command(Command::Put(command));
-        response
+        Either::B(response)
+    }
+
+    /// バイト列を、`LumpData`を経由せずにLumpとして格納する.
+    ///
+    /// `bytes`は、デバイスが管理しているストレージのブロック境界にアライメントされたバッファに
+    /// 一度だけコピーされるため、`LumpData::new`で生成したデータを`put`する場合とは異なり、
+    /// ストレージへの書き込み時に余分なメモリコピーは発生しない.
+    ///
+    /// その他の挙動は`put`と同様.
+    ///
+    /// `bytes`のサイズが`LumpData::MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーとなる.
+    pub fn put_bytes(
+        &self,
+        lump_id: LumpId,
+        bytes: &[u8],
+    ) -> impl Future<Item = PutReport, Error = Error> {
+        let lump_data = if let Some(storage) = self.device.metrics().storage() {
+            LumpData::aligned_allocate(bytes.len(), storage.header().block_size).map(|mut data| {
+                data.as_bytes_mut().copy_from_slice(bytes);
+                data
+            })
+        } else {
+            // ストレージが未起動の場合には、ブロックサイズが分からないので、書き込み時にアライメントされる
+            LumpData::new(bytes.to_owned())
+        };
+        match track!(lump_data) {
+            Err(e) => Either::A(future::err(e)),
+            Ok(lump_data) => Either::B(self.put(lump_id, lump_data)),
+        }
     }
 
     /// Lumpを取得する.
@@ -91,12 +145,93 @@ impl<'a> DeviceRequest<'a> {
     ///
     /// 指定されたlumpが存在した場合には`true`が、しなかった場合には`false`が、結果として返される.
     pub fn delete(&self, lump_id: LumpId) -> impl Future<Item = bool, Error = Error> {
+        self.delete_with(lump_id, None, None)
+    }
+
+    /// Lumpが存在し、かつその内容が`condition`を満たす場合にのみ、それを削除する.
+    ///
+    /// 条件の判定と削除は、他のコマンドを挟むことなく一つのコマンドとして処理されるので、
+    /// 同じデバイスに対して並行して発行された`put`による上書き後のlumpを、誤って削除してしまうことを防げる.
+    ///
+    /// lumpが削除された場合には`true`が、存在しないか条件を満たさなかった場合には`false`が、結果として返される.
+    ///
+    /// 詳細は`Storage::delete_if`を参照のこと.
+    pub fn delete_if(
+        &self,
+        lump_id: LumpId,
+        condition: DeleteCondition,
+    ) -> impl Future<Item = bool, Error = Error> {
+        self.delete_with(lump_id, None, Some(condition))
+    }
+
+    /// Lumpのデータをゼロで上書きした上で、それを削除する.
+    ///
+    /// 上書きは同期的に行われ、それが永続化された後に、削除のジャーナルへの記録が行われる.
+    /// ストレージ全体でのスクラブを有効にせずに、特定のlumpのデータのみを確実に消去したい場合に使用する.
+    ///
+    /// 詳細は`Storage::delete_secure`を参照のこと.
+    pub fn delete_secure(&self, lump_id: LumpId) -> impl Future<Item = bool, Error = Error> {
+        self.delete_with(lump_id, Some(0), None)
+    }
+
+    /// `delete_secure`の上書きに用いるバイトを指定可能にしたバージョン.
+    pub fn delete_secure_with_pattern(
+        &self,
+        lump_id: LumpId,
+        fill: u8,
+    ) -> impl Future<Item = bool, Error = Error> {
+        self.delete_with(lump_id, Some(fill), None)
+    }
+
+    /// `src`のlumpと同じデータを参照する別名として、`dst`のlumpを作成する.
+    ///
+    /// データ領域に格納されているlumpの場合には、データのコピーは行われず、
+    /// 共有されている部分領域は、それを参照する全てのlumpが削除されるまで解放されない.
+    ///
+    /// 別名が作成された場合には`true`が、`src`のlumpが存在しなかった場合には`false`が、結果として返される.
+    ///
+    /// 詳細は`Storage::link`を参照のこと.
+    pub fn link(&self, src: LumpId, dst: LumpId) -> impl Future<Item = bool, Error = Error> {
         let deadline = self.deadline.unwrap_or_default();
         let prioritized = self.prioritized;
 
-        let (command, response) =
-            command::DeleteLump::new(lump_id, deadline, prioritized, self.enforce_journal_sync);
-        self.send_command(Command::Delete(command));
+        let (command, response) = command::LinkLump::new(
+            src,
+            dst,
+            deadline,
+            prioritized,
+            self.enforce_journal_sync,
+            self.max_sync_delay,
+        );
+        self.send_command(Command::Link(command));
+        response
+    }
+
+    /// `old_id`のlumpのIDを`new_id`に変更する.
+    ///
+    /// データ領域に格納されているlumpの場合には、データの読み書きは行われず、
+    /// 単一のジャーナルレコードの追記のみでIDが付け替えられる.
+    ///
+    /// IDが変更された場合には`true`が、`old_id`のlumpが存在しなかった場合には`false`が、結果として返される.
+    ///
+    /// 詳細は`Storage::rename`を参照のこと.
+    pub fn rename(
+        &self,
+        old_id: LumpId,
+        new_id: LumpId,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) = command::RenameLump::new(
+            old_id,
+            new_id,
+            deadline,
+            prioritized,
+            self.enforce_journal_sync,
+            self.max_sync_delay,
+        );
+        self.send_command(Command::Rename(command));
         response
     }
 
@@ -104,19 +239,53 @@ impl<'a> DeviceRequest<'a> {
     ///
     /// 返り値のvectorは、引数rangeに含まれるlump idのうち、
     /// 対応するlump dataが存在して実際に削除されたもの全体を表す。
-    pub fn delete_range(
+    ///
+    /// 削除は一つの範囲削除としてジャーナルに記録され、範囲内のlumpはその時点で全て参照不可能になる.
+    /// その後のインデックスからの除去およびデータ領域の解放は、`DeviceBuilder::long_command_slice_size`単位に分割して行われ、
+    /// その合間には`GET`や`HEAD`等が処理される(詳細は`Storage::start_sliced_delete_range`を参照のこと).
+    pub fn delete_range<R: Into<LumpRange>>(
         &self,
-        range: Range<LumpId>,
+        range: R,
     ) -> impl Future<Item = Vec<LumpId>, Error = Error> {
         let deadline = self.deadline.unwrap_or_default();
         let prioritized = self.prioritized;
 
-        let (command, response) =
-            command::DeleteLumpRange::new(range, deadline, prioritized, self.enforce_journal_sync);
+        let (command, response) = command::DeleteLumpRange::new(
+            range.into(),
+            deadline,
+            prioritized,
+            self.enforce_journal_sync,
+            self.max_sync_delay,
+        );
         self.send_command(Command::DeleteRange(command));
         response
     }
 
+    /// 指定されたIDのlump群を削除する.
+    ///
+    /// 結果は`lump_ids`と同じ順番で、各lumpが存在して削除されたかどうかを示す.
+    ///
+    /// 連続していないID群を削除する場合に、`delete`を個別に発行するよりも効率的で、
+    /// 全ての削除は一つのコマンドとして(他のコマンドに割り込まれることなく)処理される.
+    /// ジャーナルへの記録方法等の詳細は`Storage::delete_many`を参照のこと.
+    pub fn delete_many(
+        &self,
+        lump_ids: Vec<LumpId>,
+    ) -> impl Future<Item = Vec<bool>, Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) = command::DeleteLumps::new(
+            lump_ids,
+            deadline,
+            prioritized,
+            self.enforce_journal_sync,
+            self.max_sync_delay,
+        );
+        self.send_command(Command::DeleteMany(command));
+        response
+    }
+
     /// 保存されているlump一覧を取得する.
     ///
     /// # 注意
@@ -134,32 +303,169 @@ impl<'a> DeviceRequest<'a> {
 
     /// 範囲を指定してlump一覧を取得する.
     ///
-    pub fn list_range(
+    pub fn list_range<R: Into<LumpRange>>(
         &self,
-        range: Range<LumpId>,
+        range: R,
     ) -> impl Future<Item = Vec<LumpId>, Error = Error> {
         let deadline = self.deadline.unwrap_or_default();
         let prioritized = self.prioritized;
 
-        let (command, response) = command::ListLumpRange::new(range, deadline, prioritized);
+        let (command, response) = command::ListLumpRange::new(range.into(), deadline, prioritized);
         self.send_command(Command::ListRange(command));
         response
     }
 
+    /// 範囲を指定して、lumpのIDとデータを最大`max_lumps`個取得する.
+    ///
+    /// 結果として、取得したlump群(ID順)と、未処理部分の範囲が返される.
+    /// 範囲内の全てのlumpを取得し終えた場合には、未処理部分は`None`となる.
+    ///
+    /// データ領域に格納されているlump群は、隣接しているもの同士がまとめて読み込まれるので、
+    /// `list_range`の結果に対して個々に`get`を発行するよりも効率が良い.
+    /// 詳細は`Storage::scan_step`を参照のこと.
+    pub fn scan_step<R: Into<LumpRange>>(
+        &self,
+        range: R,
+        max_lumps: usize,
+    ) -> impl Future<Item = (Vec<(LumpId, LumpData)>, Option<LumpRange>), Error = Error> {
+        self.scan_step_command(range.into(), max_lumps)
+    }
+
+    /// 範囲内のlumpのIDとデータを、ID順に返すストリームを生成する.
+    ///
+    /// ストリームは、内部的に`scan_step`コマンドを`batch_size`個単位で繰り返し発行する
+    /// (ある一回の結果を返している間に、次のコマンドが先行して発行される).
+    /// このリクエストに指定されたデッドラインやストレージ等の設定は、各コマンドに引き継がれる.
+    ///
+    /// 一回毎のコマンドの処理量が制限されているので、巨大な範囲を走査する場合でも、
+    /// 他のリクエストの処理が長時間妨げられることはない.
+    /// ただし、走査の途中で行われた更新が結果に反映されるかどうかは、その位置に依存する.
+    ///
+    /// ストリームがエラーを返した場合には、以後の走査は行われない.
+    pub fn scan<R: Into<LumpRange>>(&self, range: R, batch_size: usize) -> DeviceScan {
+        DeviceScan {
+            device: self.device.clone(),
+            deadline: self.deadline,
+            max_queue_len: self.max_queue_len,
+            wait_for_running: self.wait_for_running,
+            prioritized: self.prioritized,
+            storage: self.storage,
+            batch_size,
+            range: Some(range.into()),
+            pending: None,
+            buffered: Vec::new().into_iter(),
+        }
+    }
+
+    fn scan_step_command(
+        &self,
+        range: LumpRange,
+        max_lumps: usize,
+    ) -> command::AsyncResult<command::ScannedLumps> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) =
+            command::ScanLumpRange::new(range, max_lumps, deadline, prioritized);
+        self.send_command(Command::ScanStep(command));
+        response
+    }
+
     /// 範囲を指定してlump数を取得する.
     ///
-    pub fn usage_range(
+    pub fn usage_range<R: Into<LumpRange>>(
         &self,
-        range: Range<LumpId>,
+        range: R,
     ) -> impl Future<Item = StorageUsage, Error = Error> {
         let deadline = self.deadline.unwrap_or_default();
         let prioritized = self.prioritized;
 
-        let (command, response) = command::UsageLumpRange::new(range, deadline, prioritized);
+        let (command, response) = command::UsageLumpRange::new(range.into(), deadline, prioritized);
         self.send_command(Command::UsageRange(command));
         response
     }
 
+    /// ストレージの整合性検査を行う.
+    ///
+    /// 検査は複数回に分割して実行され、その合間には他の軽量な読み込み系のリクエストが処理される.
+    /// 検査内容については[`CheckLevel`]を参照のこと.
+    ///
+    /// [`CheckLevel`]: ../storage/enum.CheckLevel.html
+    pub fn check(&self, level: CheckLevel) -> impl Future<Item = CheckReport, Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) = command::CheckStorage::new(level, deadline, prioritized);
+        self.send_command(Command::Check(command));
+        response
+    }
+
+    /// デバイスおよびストレージの統計情報のスナップショットを取得する.
+    ///
+    /// 統計情報はデバイスの管理スレッド上で一度に収集されるため、
+    /// 各種メトリクスを個別に読み出す場合とは異なり、値同士の整合性が保たれる.
+    /// 収集は軽量なので、分割実行中の他のコマンドの合間にも処理される.
+    pub fn stats(&self) -> impl Future<Item = DeviceStats, Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) = command::GetStats::new(deadline, prioritized);
+        self.send_command(Command::Stats(command));
+        response
+    }
+
+    /// `cursor`が指す位置から、最大`max_entries`個のジャーナルエントリを読み込む.
+    ///
+    /// 結果として、読み込んだエントリ群と、次の読み込み開始位置を指すカーソルが返される.
+    /// 末尾位置まで読み込んだ場合には、カーソルは`None`となる.
+    ///
+    /// ジャーナル全体を一度に読み込むのではなく、少しずつ読み込みを進めることで、
+    /// 巨大なジャーナル領域を走査する場合でも、他のリクエストの処理が長時間妨げられることを防ぐ.
+    /// 整合性に関する注意点は`Storage::journal_snapshot_step`を参照のこと.
+    pub fn journal_snapshot_step(
+        &self,
+        cursor: JournalCursor,
+        max_entries: usize,
+    ) -> impl Future<Item = (Vec<JournalEntry>, Option<JournalCursor>), Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) =
+            command::JournalSnapshotStep::new(cursor, max_entries, deadline, prioritized);
+        self.send_command(Command::JournalSnapshotStep(command));
+        response
+    }
+
+    /// デバイスのウォームアップを行う.
+    ///
+    /// 起動直後に発行することで、後続のリクエストがコールドスタートによる遅延の影響を受けることを防ぐ.
+    /// `touch_regions`が`true`の場合には、ジャーナル領域およびデータ領域の先頭ブロックの読み込みも行われる.
+    ///
+    /// 結果には、ウォームアップに要した時間等が含まれる.
+    /// 詳細は`Storage::warm_up`を参照のこと.
+    pub fn warm_up(&self, touch_regions: bool) -> impl Future<Item = WarmUpReport, Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) = command::WarmUpStorage::new(touch_regions, deadline, prioritized);
+        self.send_command(Command::WarmUp(command));
+        response
+    }
+
+    /// ジャーナル領域の全体GCを行う.
+    ///
+    /// GCはGCキュー一杯分(`StorageBuilder::journal_gc_queue_size`)ずつに分割して実行され、
+    /// その合間には他の軽量な読み込み系のリクエストや、補助的な処理(サイドジョブ)が行われる.
+    /// 詳細は`Storage::journal_gc`を参照のこと.
+    pub fn journal_gc(&self) -> impl Future<Item = (), Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (command, response) = command::JournalGc::new(deadline, prioritized);
+        self.send_command(Command::JournalGc(command));
+        response
+    }
+
     /// デバイスを停止する.
     ///
     /// 停止は重要な操作であり、実行は`Device`インスタンスの保持者に制限したいので、
@@ -194,6 +500,23 @@ impl<'a> DeviceRequest<'a> {
         self
     }
 
+    /// ジャーナルの同期を、最大で`delay`だけ遅延させることを許容した上で、書き込みの永続化を要求する.
+    ///
+    /// 書き込み系の要求(e.g., `put`, `delete`)にのみ適用される.
+    /// デバイスは、この時間内に発行された他の要求と同期をまとめて行うことができ、
+    /// 要求に対する応答は、ジャーナルの同期が完了した後(遅くとも`delay`の経過後)に返される.
+    /// これによって、永続化の保証と同期コストのトレードオフを、要求毎に明示的に制御することができる.
+    ///
+    /// `journal_sync`も指定されている場合には、そちらが優先され、即座に同期が行われる.
+    ///
+    /// デフォルトでは、同期は[journal_sync_interval]にのみ基づいて行われ、応答は同期を待たずに返される.
+    ///
+    /// [journal_sync_interval]: ../storage/struct.StorageBuilder.html#method.journal_sync_interval
+    pub fn max_sync_delay(&mut self, delay: Duration) -> &mut Self {
+        self.max_sync_delay = Some(delay);
+        self
+    }
+
     /// デバイスのキューの最大長を指定する.
     ///
     /// もし要求発行時に、デバイスのキューの長さがこの値を超えている場合には、
@@ -217,6 +540,50 @@ impl<'a> DeviceRequest<'a> {
         self
     }
 
+    /// PUTおよびDELETEの監査レコードに記録するメタデータを指定する.
+    ///
+    /// ストレージの監査証跡が有効な場合には、
+    /// このメタデータは操作自体と同じジャーナルレコードに(アトミックに)書き込まれる.
+    /// メタデータの長さに関する制約等は`Storage::put_with_audit`を参照のこと.
+    pub fn audit(&mut self, metadata: Vec<u8>) -> &mut Self {
+        self.audit = Some(metadata);
+        self
+    }
+
+    /// PUTおよびDELETEのリクエストに、冪等性キーを付与する.
+    ///
+    /// デバイスは、キーが付与されたリクエストの処理に成功すると、その結果を記録しておく.
+    /// 以後に同じキーを持つリクエストが届いた場合には、それを実行せずに、記録済みの結果を返す.
+    /// これによって、タイムアウト後のリトライ等でリクエストが重複して届いた場合でも、
+    /// 操作が二重に適用される(e.g., 削除の後に行われたPUTを、遅れて届いた古いPUTが上書きする)ことを防げる.
+    ///
+    /// キーはデバイス(ストレージ)毎に管理され、呼び出し側が衝突しないように生成する必要がある
+    /// (e.g., ランダムな128ビット値).
+    /// 記録済みのものとは種類や対象lumpが異なるリクエストに同じキーが使われた場合には、
+    /// `ErrorKind::InvalidInput`エラーが返される.
+    ///
+    /// 記録は`DeviceBuilder::idempotency_cache_size`で指定された件数まで、メモリ上にのみ保持される.
+    /// 失敗したリクエストの結果は記録されないので、そのリトライは通常通りに実行される.
+    ///
+    /// `delete_range`や`delete_many`等、単一のlumpを対象としない書き込み系のリクエストには適用されない.
+    pub fn idempotency_key(&mut self, key: u128) -> &mut Self {
+        self.idempotency_key = Some(key);
+        self
+    }
+
+    /// リクエストの対象となるストレージを指定する.
+    ///
+    /// `DeviceBuilder::spawn_multi`で起動したデバイスに対してのみ意味を持つ.
+    /// デバイスが指定されたキーのストレージを管理していない場合には、
+    /// `ErrorKind::InvalidInput`エラーが返される.
+    ///
+    /// デフォルトでは、デバイスが管理するストレージの内で、キーが最小のものが対象となる.
+    /// なお`stop`の場合には、指定に関わらずデバイス全体(i.e., 全てのストレージ)が停止される.
+    pub fn storage(&mut self, key: StorageKey) -> &mut Self {
+        self.storage = Some(key);
+        self
+    }
+
     /// リクエストを優先的に処理する。
     ///
     /// デフォルトでは、全てのリクエストは、過負荷時に無視される。
@@ -227,6 +594,47 @@ impl<'a> DeviceRequest<'a> {
         self
     }
 
+    /// デバイスが直近に処理したコマンドの記録を、古いものから順に返す.
+    ///
+    /// 記録はデバイススレッドを経由せずに取得されるため、デバイスが停止した後でも参照可能.
+    /// `DeviceBuilder::event_log_capacity`で記録が有効にされていない場合には、常に空となる.
+    pub fn recent_events(&self) -> Vec<DeviceEvent> {
+        self.device.recent_events()
+    }
+
+    /// 応答を待たない(fire-and-forget)モードでリクエストを発行するためのオブジェクトを返す.
+    ///
+    /// デタッチされたリクエストは、結果を受け取るためのチャンネルを生成しないため、通常のリクエストよりも軽量である.
+    /// その代わりに、処理に失敗した場合でも呼び出し側には通知されず、
+    /// デバイスのメトリクスに計上され、ログに出力されるのみとなる.
+    pub fn detach(&self) -> DetachedDeviceRequest<'_, 'a> {
+        DetachedDeviceRequest { request: self }
+    }
+
+    fn delete_with(
+        &self,
+        lump_id: LumpId,
+        secure_fill: Option<u8>,
+        condition: Option<DeleteCondition>,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let deadline = self.deadline.unwrap_or_default();
+        let prioritized = self.prioritized;
+
+        let (mut command, response) = command::DeleteLump::new(
+            lump_id,
+            deadline,
+            prioritized,
+            self.enforce_journal_sync,
+            self.max_sync_delay,
+            secure_fill,
+            self.audit.clone(),
+        );
+        command.set_condition(condition);
+        command.set_idempotency_key(self.idempotency_key);
+        self.send_command(Command::Delete(command));
+        response
+    }
+
     fn send_command(&self, command: Command) {
         if !self.wait_for_running && self.device.metrics().status() == DeviceStatus::Starting {
             let e = track!(ErrorKind::DeviceBusy.cause("The device is starting up"));
@@ -237,7 +645,7 @@ impl<'a> DeviceRequest<'a> {
             self.device.metrics().busy_commands.increment(&command);
             command.failed(e)
         } else {
-            self.device.send_command(command);
+            self.device.send_command(self.storage, command);
         }
     }
 
@@ -255,3 +663,123 @@ impl<'a> DeviceRequest<'a> {
         Ok(())
     }
 }
+
+/// 応答を待たずにリクエストを発行するためのオブジェクト.
+///
+/// `DeviceRequest::detach`によって生成される.
+#[derive(Debug)]
+pub struct DetachedDeviceRequest<'b, 'a: 'b> {
+    request: &'b DeviceRequest<'a>,
+}
+impl<'b, 'a: 'b> DetachedDeviceRequest<'b, 'a> {
+    /// Lumpを格納する.
+    ///
+    /// 結果は返されない. 詳細は`DeviceRequest::put`を参照のこと.
+    pub fn put(&self, lump_id: LumpId, lump_data: LumpData) {
+        let r = self.request;
+        let mut command = command::PutLump::detached(
+            lump_id,
+            lump_data,
+            r.deadline.unwrap_or_default(),
+            r.prioritized,
+            r.enforce_journal_sync,
+            r.max_sync_delay,
+            r.audit.clone(),
+        );
+        command.set_idempotency_key(r.idempotency_key);
+        r.send_command(Command::Put(command));
+    }
+
+    /// Lumpを削除する.
+    ///
+    /// 結果は返されない. 詳細は`DeviceRequest::delete`を参照のこと.
+    pub fn delete(&self, lump_id: LumpId) {
+        let r = self.request;
+        let mut command = command::DeleteLump::detached(
+            lump_id,
+            r.deadline.unwrap_or_default(),
+            r.prioritized,
+            r.enforce_journal_sync,
+            r.max_sync_delay,
+            None,
+            r.audit.clone(),
+        );
+        command.set_idempotency_key(r.idempotency_key);
+        r.send_command(Command::Delete(command));
+    }
+}
+
+/// 範囲内のlumpのIDとデータを、ID順に返すストリーム.
+///
+/// `DeviceRequest::scan`によって生成される.
+#[derive(Debug)]
+pub struct DeviceScan {
+    device: DeviceThreadHandle,
+    deadline: Option<Deadline>,
+    max_queue_len: Option<usize>,
+    wait_for_running: bool,
+    prioritized: bool,
+    storage: Option<StorageKey>,
+    batch_size: usize,
+    range: Option<LumpRange>,
+    pending: Option<command::AsyncResult<command::ScannedLumps>>,
+    buffered: vec::IntoIter<(LumpId, LumpData)>,
+}
+impl DeviceScan {
+    fn issue(&mut self) {
+        if let Some(range) = self.range.take() {
+            let mut request = DeviceRequest::new(&self.device);
+            request.deadline = self.deadline;
+            request.max_queue_len = self.max_queue_len;
+            request.wait_for_running = self.wait_for_running;
+            request.prioritized = self.prioritized;
+            request.storage = self.storage;
+            self.pending = Some(request.scan_step_command(range, self.batch_size));
+        }
+    }
+}
+impl Stream for DeviceScan {
+    type Item = (LumpId, LumpData);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(lump) = self.buffered.next() {
+                return Ok(Async::Ready(Some(lump)));
+            }
+            if self.pending.is_none() {
+                if self.range.is_none() {
+                    return Ok(Async::Ready(None));
+                }
+                self.issue();
+            }
+            let polled = self.pending.as_mut().expect("Never fails").poll();
+            match polled {
+                Err(e) => {
+                    self.pending = None;
+                    return Err(track!(e));
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready((lumps, next))) => {
+                    self.pending = None;
+                    self.buffered = lumps.into_iter();
+                    self.range = next;
+                    self.issue();
+                }
+            }
+        }
+    }
+}
+
+/// ジャーナル領域に埋め込まれるデータのサイズが上限以下かどうかを検証する.
+fn check_embedding(lump_data: &LumpData) -> Result<()> {
+    track_assert!(
+        !matches!(lump_data.as_inner(), LumpDataInner::JournalRegion(_))
+            || lump_data.fits_embedding(),
+        ErrorKind::InvalidInput,
+        "Too large embedded lump data: {} bytes (max={})",
+        lump_data.as_bytes().len(),
+        LumpData::MAX_EMBEDDED_SIZE
+    );
+    Ok(())
+}
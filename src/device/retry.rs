@@ -0,0 +1,485 @@
+use fibers::time::timer::{self, Timeout};
+use futures::{Async, Future, Poll};
+use std::cmp;
+use std::fmt;
+use std::time::{Duration, Instant};
+use trackable::error::ErrorKindExt;
+
+use super::{CommandKind, DeviceHandle, DeviceRequest, StorageKey};
+use crate::deadline::Deadline;
+use crate::lump::{LumpData, LumpHeader, LumpId};
+use crate::storage::PutReport;
+use crate::{Error, ErrorKind};
+
+type BoxFuture<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
+type IssueFn<T> = Box<dyn FnMut(&DeviceRequest) -> BoxFuture<T> + Send>;
+
+/// 失敗したリクエストのリトライ方針.
+///
+/// リトライの可否は、コマンドの種類とエラーの種類の組み合わせによって判定される
+/// (詳細は`RetryPolicy::is_retriable`を参照のこと).
+///
+/// リトライの間隔は、`initial_backoff`から始まり、リトライの度に`max_backoff`を上限として倍になっていく.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+impl RetryPolicy {
+    /// デフォルト設定で`RetryPolicy`インスタンスを生成する.
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// リトライ回数の上限を設定する.
+    ///
+    /// `0`の場合にはリトライは行われない.
+    ///
+    /// デフォルト値は`3`.
+    pub fn max_retries(&mut self, n: usize) -> &mut Self {
+        self.max_retries = n;
+        self
+    }
+
+    /// 最初のリトライまでの待機時間を設定する.
+    ///
+    /// デフォルト値は`10ms`.
+    pub fn initial_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// リトライまでの待機時間の上限を設定する.
+    ///
+    /// デフォルト値は`1s`.
+    pub fn max_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// `command`の種類のリクエストが、`error`の種類のエラーで失敗した場合に、リトライ可能かどうかを判定する.
+    ///
+    /// 判定基準は以下の通り:
+    ///
+    /// - `DeviceBusy`, `RequestDropped`, `RequestRefused`, `JournalGcLagging`:
+    ///   - コマンドは実行されていないことが保証されるので、全てのコマンドでリトライ可能
+    /// - `Other`:
+    ///   - ストレージの状態を変更しない読み込み系のコマンドのみリトライ可能
+    ///   - 更新系のコマンドの場合には、処理が途中まで反映されている可能性があるため、リトライは行わない
+    /// - それ以外:
+    ///   - 入力の修正やデバイスの再起動等が必要であり、単純なリトライでは解決しないため、リトライ不可
+    ///   - `DeadlineExceeded`と`DeadlineUnreachable`も、期限までの残り時間は減る一方なので、リトライ不可
+    pub fn is_retriable(command: CommandKind, error: ErrorKind) -> bool {
+        match error {
+            ErrorKind::DeviceBusy
+            | ErrorKind::RequestDropped
+            | ErrorKind::RequestRefused
+            | ErrorKind::JournalGcLagging => command != CommandKind::Stop,
+            ErrorKind::Other => matches!(
+                command,
+                CommandKind::Get
+                    | CommandKind::Head
+                    | CommandKind::List
+                    | CommandKind::ListRange
+                    | CommandKind::UsageRange
+                    | CommandKind::Check
+                    | CommandKind::Stats
+                    | CommandKind::JournalSnapshotStep
+                    | CommandKind::ScanStep
+                    | CommandKind::WarmUp
+                    | CommandKind::JournalGc
+            ),
+            _ => false,
+        }
+    }
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 失敗したリクエストを、`RetryPolicy`に従って自動でリトライするデバイスのハンドル.
+///
+/// `DeviceHandle::with_retry`メソッドを通して生成される.
+#[derive(Debug, Clone)]
+pub struct RetryingDeviceHandle {
+    handle: DeviceHandle,
+    policy: RetryPolicy,
+}
+impl RetryingDeviceHandle {
+    pub(crate) fn new(handle: DeviceHandle, policy: RetryPolicy) -> Self {
+        RetryingDeviceHandle { handle, policy }
+    }
+
+    /// リトライ方針を返す.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// リトライ付きのリクエストのビルダを返す.
+    pub fn request(&self) -> RetryingDeviceRequest<'_> {
+        RetryingDeviceRequest {
+            handle: self,
+            options: RequestOptions::default(),
+        }
+    }
+
+    /// リトライを行わない、元のデバイスのハンドルを返す.
+    pub fn device_handle(&self) -> &DeviceHandle {
+        &self.handle
+    }
+}
+
+/// リトライ付きのリクエストを発行するためのビルダ.
+///
+/// 各メソッドの挙動は、リトライが行われる点を除いて`DeviceRequest`と同様.
+///
+/// # デッドラインとの関係
+///
+/// `Deadline::Within`ないし`Deadline::At`が指定された場合には、その期限がリトライ全体の期限となり、
+/// 期限までにリトライを開始できない場合には、最後に発生したエラーが返される.
+/// `Deadline::Within`の場合には、リトライ時のリクエストには、期限までの残り時間が指定される.
+#[derive(Debug)]
+pub struct RetryingDeviceRequest<'a> {
+    handle: &'a RetryingDeviceHandle,
+    options: RequestOptions,
+}
+impl<'a> RetryingDeviceRequest<'a> {
+    /// Lumpを格納する.
+    pub fn put(
+        &self,
+        lump_id: LumpId,
+        lump_data: LumpData,
+    ) -> impl Future<Item = PutReport, Error = Error> {
+        self.retry(CommandKind::Put, move |r| {
+            Box::new(r.put(lump_id, lump_data.clone()))
+        })
+    }
+
+    /// Lumpを取得する.
+    pub fn get(&self, lump_id: LumpId) -> impl Future<Item = Option<LumpData>, Error = Error> {
+        self.retry(CommandKind::Get, move |r| Box::new(r.get(lump_id)))
+    }
+
+    /// Lumpのヘッダを取得する.
+    pub fn head(&self, lump_id: LumpId) -> impl Future<Item = Option<LumpHeader>, Error = Error> {
+        self.retry(CommandKind::Head, move |r| Box::new(r.head(lump_id)))
+    }
+
+    /// Lumpを削除する.
+    pub fn delete(&self, lump_id: LumpId) -> impl Future<Item = bool, Error = Error> {
+        self.retry(CommandKind::Delete, move |r| Box::new(r.delete(lump_id)))
+    }
+
+    /// `DeviceRequest::deadline`と同様.
+    pub fn deadline(&mut self, deadline: Deadline) -> &mut Self {
+        self.options.deadline = deadline;
+        self
+    }
+
+    /// `DeviceRequest::journal_sync`と同様.
+    pub fn journal_sync(&mut self) -> &mut Self {
+        self.options.journal_sync = true;
+        self
+    }
+
+    /// `DeviceRequest::wait_for_running`と同様.
+    pub fn wait_for_running(&mut self) -> &mut Self {
+        self.options.wait_for_running = true;
+        self
+    }
+
+    /// `DeviceRequest::storage`と同様.
+    pub fn storage(&mut self, key: StorageKey) -> &mut Self {
+        self.options.storage = Some(key);
+        self
+    }
+
+    /// `DeviceRequest::prioritized`と同様.
+    pub fn prioritized(&mut self) -> &mut Self {
+        self.options.prioritized = true;
+        self
+    }
+
+    fn retry<T, F>(&self, command: CommandKind, issue: F) -> Retry<T>
+    where
+        F: FnMut(&DeviceRequest) -> BoxFuture<T> + Send + 'static,
+    {
+        Retry::new(
+            self.handle.handle.clone(),
+            self.handle.policy.clone(),
+            self.options.clone(),
+            command,
+            Box::new(issue),
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct RequestOptions {
+    deadline: Deadline,
+    journal_sync: bool,
+    wait_for_running: bool,
+    storage: Option<StorageKey>,
+    prioritized: bool,
+}
+impl RequestOptions {
+    fn apply(&self, request: &mut DeviceRequest, deadline: Deadline) {
+        request.deadline(deadline);
+        if self.journal_sync {
+            request.journal_sync();
+        }
+        if self.wait_for_running {
+            request.wait_for_running();
+        }
+        if let Some(key) = self.storage {
+            request.storage(key);
+        }
+        if self.prioritized {
+            request.prioritized();
+        }
+    }
+}
+
+enum RetryState<T> {
+    Running(BoxFuture<T>),
+    Waiting(Timeout),
+}
+
+struct Retry<T> {
+    handle: DeviceHandle,
+    policy: RetryPolicy,
+    options: RequestOptions,
+    command: CommandKind,
+    issue: IssueFn<T>,
+    limit: Option<Instant>,
+    retries: usize,
+    backoff: Duration,
+    state: RetryState<T>,
+}
+impl<T> Retry<T> {
+    fn new(
+        handle: DeviceHandle,
+        policy: RetryPolicy,
+        options: RequestOptions,
+        command: CommandKind,
+        mut issue: IssueFn<T>,
+    ) -> Self {
+        let now = Instant::now();
+        let limit = match options.deadline {
+            Deadline::Within(d) => Some(now + d),
+            Deadline::At(t) => Some(t),
+            Deadline::Immediate | Deadline::Infinity => None,
+        };
+        let future = {
+            let mut request = handle.request();
+            options.apply(&mut request, options.deadline);
+            issue(&request)
+        };
+        let backoff = policy.initial_backoff;
+        Retry {
+            handle,
+            policy,
+            options,
+            command,
+            issue,
+            limit,
+            retries: 0,
+            backoff,
+            state: RetryState::Running(future),
+        }
+    }
+
+    fn can_retry(&self, error: &Error) -> bool {
+        self.retries < self.policy.max_retries
+            && RetryPolicy::is_retriable(self.command, *error.kind())
+            && self
+                .limit
+                .is_none_or(|limit| Instant::now() + self.backoff < limit)
+    }
+
+    fn reissue(&mut self) -> BoxFuture<T> {
+        let deadline = match (self.options.deadline, self.limit) {
+            (Deadline::Within(_), Some(limit)) => {
+                Deadline::Within(limit.saturating_duration_since(Instant::now()))
+            }
+            (deadline, _) => deadline,
+        };
+        let mut request = self.handle.request();
+        self.options.apply(&mut request, deadline);
+        (self.issue)(&request)
+    }
+}
+impl<T> Future for Retry<T> {
+    type Item = T;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                RetryState::Running(ref mut f) => match f.poll() {
+                    Err(e) => {
+                        if !self.can_retry(&e) {
+                            return Err(track!(e));
+                        }
+                        let backoff = self.backoff;
+                        self.retries += 1;
+                        self.backoff = cmp::min(backoff * 2, self.policy.max_backoff);
+                        RetryState::Waiting(timer::timeout(backoff))
+                    }
+                    Ok(result) => return Ok(result),
+                },
+                RetryState::Waiting(ref mut f) => {
+                    let ready =
+                        track!(f.poll().map_err(|e| Error::from(ErrorKind::Other.cause(e))))?;
+                    if ready.is_not_ready() {
+                        return Ok(Async::NotReady);
+                    }
+                    RetryState::Running(self.reissue())
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+impl<T> fmt::Debug for Retry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retry")
+            .field("command", &self.command)
+            .field("retries", &self.retries)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fibers_global::execute;
+    use std::thread;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::device::DeviceBuilder;
+    use crate::nvm::MemoryNvm;
+    use crate::storage::Storage;
+
+    #[test]
+    fn is_retriable_works() {
+        // 実行されていないことが保証されるエラー
+        assert!(RetryPolicy::is_retriable(
+            CommandKind::Put,
+            ErrorKind::DeviceBusy
+        ));
+        assert!(RetryPolicy::is_retriable(
+            CommandKind::Delete,
+            ErrorKind::RequestDropped
+        ));
+        assert!(RetryPolicy::is_retriable(
+            CommandKind::Get,
+            ErrorKind::RequestRefused
+        ));
+
+        // 読み込み系のみリトライ可能なエラー
+        assert!(RetryPolicy::is_retriable(
+            CommandKind::Get,
+            ErrorKind::Other
+        ));
+        assert!(!RetryPolicy::is_retriable(
+            CommandKind::Put,
+            ErrorKind::Other
+        ));
+        assert!(!RetryPolicy::is_retriable(
+            CommandKind::Delete,
+            ErrorKind::Other
+        ));
+
+        // リトライでは解決しないエラー
+        assert!(!RetryPolicy::is_retriable(
+            CommandKind::Get,
+            ErrorKind::InvalidInput
+        ));
+        assert!(!RetryPolicy::is_retriable(
+            CommandKind::Put,
+            ErrorKind::StorageFull
+        ));
+        assert!(!RetryPolicy::is_retriable(
+            CommandKind::Get,
+            ErrorKind::DeviceTerminated
+        ));
+        assert!(!RetryPolicy::is_retriable(
+            CommandKind::Get,
+            ErrorKind::DeadlineExceeded
+        ));
+    }
+
+    #[test]
+    fn retry_works() -> TestResult {
+        let device = DeviceBuilder::new().spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+            track!(Storage::create(nvm))
+        });
+        let d = device.handle();
+
+        // リトライ回数が不足している場合には、起動中のデバイスへの要求は失敗する
+        let mut policy = RetryPolicy::new();
+        policy
+            .max_retries(1)
+            .initial_backoff(Duration::from_millis(1));
+        let result = execute(d.with_retry(policy).request().get(id(0)));
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::DeviceBusy));
+
+        // 起動が完了するまでリトライする
+        let mut policy = RetryPolicy::new();
+        policy
+            .max_retries(100)
+            .initial_backoff(Duration::from_millis(10))
+            .max_backoff(Duration::from_millis(10));
+        let retrying = d.with_retry(policy);
+        track!(execute(retrying.request().put(id(0), data(b"foo"))))?;
+        assert_eq!(
+            track!(execute(retrying.request().get(id(0))))?,
+            Some(data(b"foo"))
+        );
+        assert!(track!(execute(retrying.request().delete(id(0))))?);
+        Ok(())
+    }
+
+    #[test]
+    fn retry_respects_deadline() {
+        let device = DeviceBuilder::new().spawn(|| {
+            thread::sleep(Duration::from_millis(500));
+            let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+            track!(Storage::create(nvm))
+        });
+        let d = device.handle();
+
+        // 期限を過ぎてまでリトライは行わない
+        let mut policy = RetryPolicy::new();
+        policy
+            .max_retries(1000)
+            .initial_backoff(Duration::from_millis(10))
+            .max_backoff(Duration::from_millis(10));
+        let start = Instant::now();
+        let result = execute(
+            d.with_retry(policy)
+                .request()
+                .deadline(Deadline::Within(Duration::from_millis(50)))
+                .get(id(0)),
+        );
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::DeviceBusy));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    fn id(id: usize) -> LumpId {
+        LumpId::new(id as u128)
+    }
+
+    fn data(data: &[u8]) -> LumpData {
+        LumpData::new(data.to_owned()).unwrap()
+    }
+}
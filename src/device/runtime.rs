@@ -0,0 +1,343 @@
+use std::any::Any;
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+use trackable::error::ErrorKindExt;
+
+use crate::device::thread::{DeviceTask, Progress, Task};
+use crate::device::{Device, DeviceBuilder, DeviceHandle, StorageKey};
+use crate::nvm::NonVolatileMemory;
+use crate::storage::Storage;
+use crate::{ErrorKind, Result};
+
+/// 複数のデバイスを、固定数のOSスレッド(ワーカ)群で多重化して実行するためのランタイム.
+///
+/// `DeviceBuilder::spawn`を使った場合には、デバイス毎に一つの専用OSスレッドが割り当てられるが、
+/// 一つのマシンに多数の物理デバイスが接続されている場合(e.g., JBOD)には、スレッド数が過剰となり得る.
+/// `DeviceRuntime`を使うと、`M`個のデバイスを`K`個のスレッドで実行することができる.
+///
+/// 各デバイスは、起動時に割り当てられた一つのワーカによってのみ処理されるため、
+/// デバイス単位でのリクエストの直列化は、専用スレッドの場合と同様に保証される.
+/// 一方で、同じワーカに割り当てられたデバイス同士は、処理時間を共有することになるので、
+/// 一つのデバイスでの時間の掛かる処理(e.g., ストレージの初期化)は、他のデバイスのレイテンシにも影響する.
+///
+/// デバイスの処理中(ストレージの初期化を含む)にパニックが発生した場合には、そのデバイスのみが
+/// `ErrorKind::Other`エラーで停止し、同じワーカに割り当てられた他のデバイスの処理は継続される.
+/// なお、パニックしたデバイスのストレージはクローズされずに破棄される.
+///
+/// `DeviceRuntime`インスタンスが破棄されると、ワーカスレッド群は、
+/// 割り当てられている全てのデバイスが停止した時点で終了する.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate cannyls;
+/// # extern crate fibers_global;
+/// use cannyls::device::{DeviceBuilder, DeviceRuntime};
+/// use cannyls::lump::{LumpData, LumpId};
+/// use cannyls::nvm::MemoryNvm;
+/// use cannyls::storage::Storage;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let runtime = DeviceRuntime::new(2)?;
+/// let devices = (0..4)
+///     .map(|_| {
+///         let storage = Storage::create(MemoryNvm::new(vec![0; 1024 * 1024]))?;
+///         Ok(runtime.spawn_device(&DeviceBuilder::new(), || Ok(storage)))
+///     })
+///     .collect::<cannyls::Result<Vec<_>>>()?;
+///
+/// for device in &devices {
+///     let request = device.handle().request().wait_for_running().put(
+///         LumpId::new(1),
+///         LumpData::new(b"foo".to_vec())?,
+///     );
+///     fibers_global::execute(request)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DeviceRuntime {
+    workers: Vec<Worker>,
+    next_worker: AtomicUsize,
+}
+impl DeviceRuntime {
+    /// `threads`個のワーカスレッドを持つランタイムを起動する.
+    ///
+    /// # Errors
+    ///
+    /// `threads`が`0`の場合には`ErrorKind::InvalidInput`エラーが返される.
+    pub fn new(threads: usize) -> Result<Self> {
+        track_assert!(threads > 0, ErrorKind::InvalidInput);
+        let workers = (0..threads)
+            .map(|_| {
+                let (task_tx, task_rx) = mpsc::channel();
+                let waker = Arc::new(Waker::default());
+                let worker_waker = Arc::clone(&waker);
+                thread::spawn(move || run_worker(&task_rx, &worker_waker));
+                Worker { task_tx, waker }
+            })
+            .collect();
+        Ok(DeviceRuntime {
+            workers,
+            next_worker: AtomicUsize::new(0),
+        })
+    }
+
+    /// ワーカスレッドの数を返す.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// `builder`の設定に従い、指定されたストレージを扱う`Device`を、このランタイム上で起動する.
+    ///
+    /// デバイスは、ワーカスレッド群にラウンドロビンで割り当てられる.
+    /// `init_storage()`は、割り当てられたワーカスレッド上で呼び出される.
+    ///
+    /// その他の挙動は`DeviceBuilder::spawn`と同様.
+    pub fn spawn_device<F, N>(&self, builder: &DeviceBuilder, init_storage: F) -> Device
+    where
+        F: FnOnce() -> Result<Storage<N>> + Send + 'static,
+        N: NonVolatileMemory + Send + 'static,
+    {
+        let i = self.next_worker.fetch_add(1, Ordering::SeqCst) % self.workers.len();
+        let worker = &self.workers[i];
+        let init_storages = move || {
+            let storage = track!(init_storage())?;
+            Ok(vec![(StorageKey::new(0), storage)])
+        };
+        let (handle, monitor, task) = DeviceTask::new(
+            builder.clone(),
+            init_storages,
+            Some(Arc::clone(&worker.waker)),
+        );
+        if worker.task_tx.send(Box::new(task)).is_ok() {
+            worker.waker.wake();
+        }
+        Device::new(monitor, DeviceHandle(handle))
+    }
+}
+impl Drop for DeviceRuntime {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            let Worker { task_tx, waker } = worker;
+            drop(task_tx);
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Worker {
+    task_tx: Sender<Box<dyn Task>>,
+    waker: Arc<Waker>,
+}
+
+/// ワーカスレッドのメインループ.
+///
+/// 割り当てられたデバイス群を順番に一単位ずつ処理し、全てのデバイスが待機中の場合には、
+/// 新しいコマンドが届くか、いずれかのデバイスの待機期限が来るまで休止する.
+fn run_worker(task_rx: &Receiver<Box<dyn Task>>, waker: &Waker) {
+    let mut tasks = Vec::new();
+    loop {
+        let mut disconnected = false;
+        loop {
+            match task_rx.try_recv() {
+                Ok(task) => tasks.push(task),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+        if disconnected && tasks.is_empty() {
+            break;
+        }
+
+        let mut busy = false;
+        let mut wake_at: Option<Instant> = None;
+        tasks.retain_mut(
+            |task| match panic::catch_unwind(AssertUnwindSafe(|| task.poll())) {
+                Ok(Progress::Busy) => {
+                    busy = true;
+                    true
+                }
+                Ok(Progress::Idle(t)) => {
+                    wake_at = Some(wake_at.map_or(t, |w| cmp::min(w, t)));
+                    true
+                }
+                Ok(Progress::Done) => false,
+                Err(panic) => {
+                    // パニックしたデバイスのみを停止させ、他のデバイスの処理は継続する
+                    let message = panic_message(&*panic);
+                    task.abort(ErrorKind::Other.cause(message).into());
+                    false
+                }
+            },
+        );
+        if !busy {
+            waker.wait_until(wake_at);
+        }
+    }
+}
+
+/// パニックの内容を表す文字列を返す.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    let message = if let Some(s) = panic.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown"
+    };
+    format!("The device task panicked: {}", message)
+}
+
+/// ワーカスレッドを休止状態から起こすためのオブジェクト.
+#[derive(Debug, Default)]
+pub struct Waker {
+    notified: Mutex<bool>,
+    condvar: Condvar,
+}
+impl Waker {
+    /// 休止中のワーカスレッドを起こす.
+    ///
+    /// ワーカが休止中ではない場合には、次の休止が即座に解除される.
+    pub fn wake(&self) {
+        let mut notified = self.notified.lock().unwrap_or_else(|e| e.into_inner());
+        *notified = true;
+        self.condvar.notify_one();
+    }
+
+    /// `wake`が呼び出されるか、`deadline`に達するまで休止する.
+    fn wait_until(&self, deadline: Option<Instant>) {
+        let mut notified = self.notified.lock().unwrap_or_else(|e| e.into_inner());
+        while !*notified {
+            notified = match deadline {
+                None => self
+                    .condvar
+                    .wait(notified)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if deadline <= now {
+                        break;
+                    }
+                    let result = self.condvar.wait_timeout(notified, deadline - now);
+                    result.unwrap_or_else(|e| e.into_inner()).0
+                }
+            };
+        }
+        *notified = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fibers_global::execute;
+    use std::time::Duration;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::deadline::Deadline;
+    use crate::lump::{LumpData, LumpId};
+    use crate::nvm::MemoryNvm;
+
+    #[test]
+    fn device_runtime_works() -> TestResult {
+        let runtime = track!(DeviceRuntime::new(2))?;
+        assert_eq!(runtime.threads(), 2);
+
+        let mut builder = DeviceBuilder::new();
+        builder.idle_threshold(Duration::from_millis(1));
+        let devices = (0..5)
+            .map(|_| {
+                let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+                let storage = track!(Storage::create(nvm))?;
+                Ok(runtime.spawn_device(&builder, || Ok(storage)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (i, device) in devices.iter().enumerate() {
+            let d = device.handle();
+            let data = track!(LumpData::new(vec![i as u8; 10]))?;
+            track!(execute(
+                d.request()
+                    .wait_for_running()
+                    .put(LumpId::new(i as u128), data)
+            ))?;
+        }
+        for (i, device) in devices.iter().enumerate() {
+            let d = device.handle();
+            assert_eq!(
+                track!(execute(d.request().list()))?,
+                vec![LumpId::new(i as u128)]
+            );
+            let data = track!(execute(d.request().get(LumpId::new(i as u128))))?;
+            assert_eq!(data.map(|d| d.into_bytes()), Some(vec![i as u8; 10]));
+        }
+
+        // ジャーナルの同期待ちの応答は、多重化されていても期限内に返される
+        let d = devices[0].handle();
+        let data = track!(LumpData::new(b"foo".to_vec()))?;
+        let report = track!(execute(
+            d.request()
+                .max_sync_delay(Duration::from_millis(10))
+                .put(LumpId::new(100), data)
+        ))?;
+        assert!(report.journal_synced());
+
+        // 各デバイスは独立して停止できる
+        for device in devices {
+            device.stop(Deadline::Immediate);
+            track!(execute(device))?;
+        }
+
+        assert!(DeviceRuntime::new(0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn panicked_device_does_not_affect_others() -> TestResult {
+        let runtime = track!(DeviceRuntime::new(1))?;
+        let builder = DeviceBuilder::new();
+        let spawn = |id: u128| -> Result<Device> {
+            let storage = track!(Storage::create(MemoryNvm::new(vec![0; 1024 * 1024])))?;
+            let device = runtime.spawn_device(&builder, || Ok(storage));
+            let data = track!(LumpData::new(b"foo".to_vec()))?;
+            track!(execute(
+                device
+                    .handle()
+                    .request()
+                    .wait_for_running()
+                    .put(LumpId::new(id), data)
+            ))?;
+            Ok(device)
+        };
+        let before = track!(spawn(0))?;
+
+        // ストレージの初期化中にパニックしたデバイスは、エラーで停止する
+        let panicked = runtime.spawn_device(&builder, || -> Result<Storage<MemoryNvm>> {
+            panic!("Initialization failed")
+        });
+        let error = track!(execute(panicked)).expect_err("device should fail");
+        assert_eq!(*error.kind(), ErrorKind::Other);
+        assert!(error.to_string().contains("Initialization failed"));
+
+        // 同じワーカに割り当てられた他のデバイスは、引き続き使用可能
+        let after = track!(spawn(1))?;
+        for (id, device) in [(0, before), (1, after)] {
+            let d = device.handle();
+            assert_eq!(track!(execute(d.request().list()))?, vec![LumpId::new(id)]);
+            device.stop(Deadline::Immediate);
+            track!(execute(device))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+//! OSスレッドを使わずにデバイスを実行するためのシミュレーション用のモジュール.
+//!
+//! [`SimDevice`]は、デバイスの管理スレッドが行う処理(コマンドのスケジューリングや過負荷の判定等)を、
+//! 呼び出し元のスレッド上で一単位ずつ実行する.
+//! 処理の進行は呼び出し元が完全に制御するため、`ManualClock`と組み合わせることで、
+//! スケジューリングや過負荷時の挙動を決定的に検証することができる.
+//!
+//! [`SimDevice`]: ./struct.SimDevice.html
+use futures::Future;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::device::runtime::Waker;
+use crate::device::thread::{DeviceTask, Progress, Task};
+use crate::device::{Device, DeviceBuilder, DeviceHandle, StorageKey};
+use crate::nvm::NonVolatileMemory;
+use crate::storage::Storage;
+use crate::Result;
+
+/// 呼び出し元のスレッド上で、一単位ずつ処理が進められるデバイス.
+///
+/// `DeviceBuilder::spawn`とは異なり、専用のOSスレッドは起動されず、
+/// `step`ないし`run_until_idle`が呼び出された時にのみ処理が行われる.
+///
+/// デバイスがアイドル状態かどうかの判定は、`DeviceBuilder::clock`で指定された時計に基づいて行われ、
+/// 処理すべきコマンドがない場合でも、ブロックせずに制御が呼び出し元に返される.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate cannyls;
+/// # extern crate futures;
+/// use cannyls::device::DeviceBuilder;
+/// use cannyls::device::sim::SimDevice;
+/// use cannyls::lump::{LumpData, LumpId};
+/// use cannyls::nvm::MemoryNvm;
+/// use cannyls::storage::Storage;
+/// use futures::Future;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let storage = Storage::create(MemoryNvm::new(vec![0; 1024 * 1024]))?;
+/// let mut device = SimDevice::new(&DeviceBuilder::new(), || Ok(storage));
+/// let handle = device.handle();
+///
+/// let put = handle
+///     .request()
+///     .wait_for_running()
+///     .put(LumpId::new(1), LumpData::new(b"foo".to_vec())?);
+/// assert!(device.run_until_idle()?);
+/// assert!(put.wait()?.is_new());
+/// # Ok(())
+/// # }
+/// ```
+pub struct SimDevice {
+    device: Device,
+    task: Option<Box<dyn Task>>,
+}
+impl SimDevice {
+    /// `builder`の設定に従い、指定されたストレージを扱うデバイスを生成する.
+    ///
+    /// `init_storage()`は、最初に`step`が呼び出された時に実行される.
+    pub fn new<F, N>(builder: &DeviceBuilder, init_storage: F) -> Self
+    where
+        F: FnOnce() -> Result<Storage<N>> + Send + 'static,
+        N: NonVolatileMemory + Send + 'static,
+    {
+        let init_storages = move || {
+            let storage = track!(init_storage())?;
+            Ok(vec![(StorageKey::new(0), storage)])
+        };
+
+        // ワーカスレッドは存在しないので、起こされることはない
+        let waker = Arc::new(Waker::default());
+        let (handle, monitor, task) = DeviceTask::new(builder.clone(), init_storages, Some(waker));
+        SimDevice {
+            device: Device::new(monitor, DeviceHandle(handle)),
+            task: Some(Box::new(task)),
+        }
+    }
+
+    /// デバイスを操作するためのハンドルを返す.
+    pub fn handle(&self) -> DeviceHandle {
+        self.device.handle()
+    }
+
+    /// 一単位分だけ処理を進める.
+    ///
+    /// 何らかの処理(e.g., コマンドの受信や実行、補助タスクの実行)が行われた場合には`true`が返される.
+    /// 処理すべきものがなかった場合や、デバイスが既に停止している場合には`false`が返される.
+    pub fn step(&mut self) -> bool {
+        let progress = match self.task {
+            None => return false,
+            Some(ref mut task) => task.poll(),
+        };
+        match progress {
+            Progress::Busy => true,
+            Progress::Idle(_) => false,
+            Progress::Done => {
+                self.task = None;
+                true
+            }
+        }
+    }
+
+    /// 処理すべきものがなくなるまで、処理を進める.
+    ///
+    /// デバイスが稼働中の場合には`true`が、正常に停止した場合には`false`が返される.
+    ///
+    /// # Errors
+    ///
+    /// デバイスが異常終了した場合には、その理由を示すエラーが返される.
+    pub fn run_until_idle(&mut self) -> Result<bool> {
+        while self.step() {}
+        if self.is_stopped() {
+            track!((&mut self.device).wait())?;
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// デバイスが停止済みかどうかを判定する.
+    pub fn is_stopped(&self) -> bool {
+        self.task.is_none()
+    }
+}
+impl fmt::Debug for SimDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimDevice")
+            .field("device", &self.device)
+            .field("is_stopped", &self.is_stopped())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+    use std::time::Duration;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::block::BlockSize;
+    use crate::deadline::Deadline;
+    use crate::device::{BlockingCommand, Clock, CommandKind, LongQueuePolicy, ManualClock};
+    use crate::lump::{LumpData, LumpId};
+    use crate::nvm::MemoryNvm;
+    use crate::storage::StorageBuilder;
+    use crate::ErrorKind;
+
+    #[test]
+    fn sim_device_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 1024 * 1024])))?;
+        let mut device = SimDevice::new(&DeviceBuilder::new(), || Ok(storage));
+        let handle = device.handle();
+
+        let put = handle
+            .request()
+            .wait_for_running()
+            .put(id(0), data(b"foo")?);
+        assert!(track!(device.run_until_idle())?);
+        assert!(track!(put.wait())?.is_new());
+
+        let get = handle.request().get(id(0));
+        assert!(track!(device.run_until_idle())?);
+        assert_eq!(
+            track!(get.wait())?.map(|d| d.as_bytes().to_owned()),
+            Some(b"foo".to_vec())
+        );
+
+        handle.request().stop();
+        assert!(!track!(device.run_until_idle())?);
+        assert!(device.is_stopped());
+        assert!(!device.step());
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_scheduling_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 1024 * 1024])))?;
+        let clock = ManualClock::new();
+        let mut device = SimDevice::new(DeviceBuilder::new().clock(clock.clone()), || Ok(storage));
+        let handle = device.handle();
+        assert!(track!(device.run_until_idle())?);
+
+        let late = handle
+            .request()
+            .deadline(Deadline::At(clock.now() + Duration::from_secs(10)))
+            .head(id(0));
+        let expired = handle
+            .request()
+            .deadline(Deadline::At(clock.now() + Duration::from_secs(1)))
+            .head(id(0));
+
+        // 二つのコマンドを受信して、キューに追加する
+        assert!(device.step());
+        assert!(device.step());
+
+        // 期限の早い方を処理する前に、時刻を進める
+        clock.advance(Duration::from_secs(5));
+        assert!(track!(device.run_until_idle())?);
+        assert_eq!(
+            expired.wait().err().map(|e| *e.kind()),
+            Some(ErrorKind::DeadlineExceeded)
+        );
+        assert!(track!(late.wait())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn overload_stop_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 1024 * 1024])))?;
+        let clock = ManualClock::new();
+        let mut builder = DeviceBuilder::new();
+        builder
+            .clock(clock.clone())
+            .busy_threshold(1)
+            .max_keep_busy_duration(Duration::from_secs(10))
+            .long_queue_policy(LongQueuePolicy::Stop);
+        let mut device = SimDevice::new(&builder, || Ok(storage));
+        let handle = device.handle();
+        assert!(track!(device.run_until_idle())?);
+
+        // キューにコマンドを溜めて、ビジー状態にする
+        let _ = handle.request().head(id(0));
+        let _ = handle.request().head(id(1));
+        assert!(device.step());
+        assert!(device.step());
+
+        // ビジー状態が上限を超えて継続すると、デバイスが停止する
+        clock.advance(Duration::from_secs(11));
+        let _ = handle.request().head(id(2));
+        assert_eq!(
+            device.run_until_idle().err().map(|e| *e.kind()),
+            Some(ErrorKind::DeviceBusy)
+        );
+        assert!(device.is_stopped());
+        Ok(())
+    }
+
+    #[test]
+    fn blocked_behind_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 1024 * 1024])))?;
+        let clock = ManualClock::new();
+        let mut builder = DeviceBuilder::new();
+        builder
+            .clock(clock.clone())
+            .long_command_slice_size(1)
+            .event_log_capacity(16);
+        let mut device = SimDevice::new(&builder, || Ok(storage));
+        let handle = device.handle();
+        for i in 0..5 {
+            let _ = handle
+                .request()
+                .wait_for_running()
+                .put(id(i), data(b"foo")?);
+        }
+        assert!(track!(device.run_until_idle())?);
+
+        // LISTの分割実行中に受け付けたGETは、LISTに待たされたものとして記録される
+        let list = handle.request().list();
+        assert!(device.step()); // LISTの受信
+        assert!(device.step()); // LISTの分割実行の開始
+        let get = handle.request().get(id(0));
+        assert!(device.step()); // GETの受信
+        clock.advance(Duration::from_secs(5));
+        assert!(track!(device.run_until_idle())?);
+        assert_eq!(track!(list.wait())?.len(), 5);
+        assert!(track!(get.wait())?.is_some());
+
+        let events = handle.request().recent_events();
+        let list_event = events
+            .iter()
+            .find(|e| e.command == "list")
+            .expect("Never fails");
+        let get_event = events
+            .iter()
+            .find(|e| e.command == "get")
+            .expect("Never fails");
+        assert_eq!(list_event.blocked_behind, None);
+        assert_eq!(
+            get_event.blocked_behind,
+            Some(BlockingCommand {
+                id: list_event.id,
+                kind: CommandKind::List
+            })
+        );
+        assert!(list_event.id < get_event.id);
+
+        let stats = handle.metrics().blocked_behind();
+        let blocked = stats[&(CommandKind::Get, Some(CommandKind::List))];
+        assert_eq!(blocked.commands, 1);
+        assert_eq!(blocked.max_wait, Duration::from_secs(5));
+        assert_eq!(stats[&(CommandKind::List, None)].commands, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn sliced_put_works() -> TestResult {
+        let storage = track!(StorageBuilder::new()
+            .write_slice_size(1024)
+            .create(MemoryNvm::new(vec![0; 1024 * 1024])))?;
+        let metrics = storage.metrics().data_region().clone();
+        let mut device = SimDevice::new(&DeviceBuilder::new(), || Ok(storage));
+        let handle = device.handle();
+        let _ = handle
+            .request()
+            .wait_for_running()
+            .put(id(1), data(b"foo")?);
+        assert!(track!(device.run_until_idle())?);
+
+        let mut large = track!(LumpData::aligned_allocate(8192, BlockSize::min()))?;
+        large.as_bytes_mut().copy_from_slice(&[1; 8192]);
+        let put = handle.request().put(id(0), large);
+        assert!(device.step()); // PUTの受信
+        assert!(device.step()); // 最初のスライスの書き込み
+        assert_eq!(metrics.written_slices(), 1);
+
+        let get0 = handle.request().get(id(0));
+        let get1 = handle.request().get(id(1));
+        assert!(device.step()); // GETの受信
+        assert!(device.step()); // GETの受信
+
+        // スライスの合間に割り込めるのは、一つのコマンドのみ
+        assert!(device.step());
+        assert_eq!(metrics.written_slices(), 1);
+        assert!(device.step());
+        assert_eq!(metrics.written_slices(), 2);
+        assert!(device.step());
+        assert_eq!(metrics.written_slices(), 2);
+
+        // PUTの完了前に処理されたGETには、書き込み中のデータは見えない
+        assert!(track!(get0.wait())?.is_none());
+        assert_eq!(
+            track!(get1.wait())?.map(|d| d.into_bytes()),
+            Some(b"foo".to_vec())
+        );
+
+        assert!(track!(device.run_until_idle())?);
+        assert!(track!(put.wait())?.is_new());
+        assert_eq!(metrics.written_slices(), 9);
+        let get = handle.request().get(id(0));
+        assert!(track!(device.run_until_idle())?);
+        assert_eq!(
+            track!(get.wait())?.map(|d| d.into_bytes()),
+            Some(vec![1; 8192])
+        );
+        Ok(())
+    }
+
+    fn id(id: usize) -> LumpId {
+        LumpId::new(id as u128)
+    }
+
+    fn data(bytes: &[u8]) -> Result<LumpData> {
+        track!(LumpData::new(bytes.to_owned()))
+    }
+}
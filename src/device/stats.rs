@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use crate::device::DeviceStatus;
+use crate::storage::StorageStats;
+
+/// デバイスの統計情報のスナップショット.
+///
+/// `DeviceRequest::stats`によって取得される.
+/// 全ての値はデバイスの管理スレッド上で一度に収集されるため、
+/// 各種メトリクスを個別に読み出した場合とは異なり、互いに整合していることが保証される.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceStats {
+    /// デバイスの稼働状態.
+    pub status: DeviceStatus,
+
+    /// 収集時点でのキューの長さ.
+    ///
+    /// 統計情報の取得要求自体は含まれない.
+    pub queue_len: usize,
+
+    /// キューの長さの上限(`DeviceBuilder::max_queue_len`).
+    pub max_queue_len: usize,
+
+    /// 過負荷と判定されるキューの長さの閾値(`DeviceBuilder::busy_threshold`).
+    pub busy_threshold: usize,
+
+    /// デバイスが過負荷状態の場合には、その状態が続いている時間.
+    pub busy_duration: Option<Duration>,
+
+    /// 分割実行中の長時間コマンド(e.g., `DeviceRequest::check`)が存在する場合には、その種類を表す名前.
+    pub long_command: Option<&'static str>,
+
+    /// 要求の対象となったストレージの統計情報.
+    pub storage: StorageStats,
+}
@@ -0,0 +1,105 @@
+use fibers::sync::mpsc;
+use futures::{Poll, Stream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
+
+use crate::{Error, ErrorKind};
+
+/// デバイスの状態遷移を表すイベント.
+///
+/// `DeviceHandle::watch_status`を通して通知される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceStatusEvent {
+    /// キューの長さが閾値(`DeviceBuilder::busy_threshold`)以上となり、デバイスが過負荷状態になった.
+    ///
+    /// この状態が`DeviceBuilder::max_keep_busy_duration`以上続くと、
+    /// `DeviceBuilder::long_queue_policy`に従って、リクエストの拒否等が行われるようになる.
+    Busy {
+        /// 遷移時点でのキューの長さ.
+        queue_len: usize,
+
+        /// 過負荷と判定されるキューの長さの閾値.
+        busy_threshold: usize,
+    },
+
+    /// キューの長さが閾値未満に戻り、過負荷状態が解消された.
+    Running {
+        /// 遷移時点でのキューの長さ.
+        queue_len: usize,
+
+        /// 過負荷状態が続いていた時間.
+        busy_duration: Duration,
+    },
+
+    /// デバイスが停止した.
+    ///
+    /// これが最後のイベントとなり、以後ストリームは終端する.
+    Stopped {
+        /// デバイスが異常終了した場合には、その原因となったエラーの種類.
+        error: Option<ErrorKind>,
+    },
+}
+
+/// デバイスの状態遷移の通知を受け取るためのストリーム.
+///
+/// `DeviceHandle::watch_status`によって生成される.
+/// 受け取れるのは購読開始以降に発生したイベントのみだが、
+/// 既にデバイスが停止している場合には、`DeviceStatusEvent::Stopped`のみが通知される.
+#[derive(Debug)]
+pub struct DeviceStatusWatch(mpsc::Receiver<DeviceStatusEvent>);
+impl Stream for DeviceStatusWatch {
+    type Item = DeviceStatusEvent;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.0.poll().map_err(|()| {
+            ErrorKind::DeviceTerminated
+                .cause("status channel disconnected")
+                .into()
+        })
+    }
+}
+
+/// 状態遷移の購読者群.
+///
+/// デバイススレッドとハンドルの間で共有される.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StatusWatchers(Arc<Mutex<WatchersInner>>);
+impl StatusWatchers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 新しい購読者を登録する.
+    pub fn subscribe(&self) -> DeviceStatusWatch {
+        let (tx, rx) = mpsc::channel();
+        let mut inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ref stopped) = inner.stopped {
+            let _ = tx.send(stopped.clone());
+        } else {
+            inner.senders.push(tx);
+        }
+        DeviceStatusWatch(rx)
+    }
+
+    /// 全ての購読者にイベントを通知する.
+    ///
+    /// `DeviceStatusEvent::Stopped`の場合には、通知後に全ての購読を終了する.
+    pub fn notify(&self, event: DeviceStatusEvent) {
+        let mut inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.stopped.is_some() {
+            return;
+        }
+        inner.senders.retain(|tx| tx.send(event.clone()).is_ok());
+        if let DeviceStatusEvent::Stopped { .. } = event {
+            inner.senders.clear();
+            inner.stopped = Some(event);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WatchersInner {
+    senders: Vec<mpsc::Sender<DeviceStatusEvent>>,
+    stopped: Option<DeviceStatusEvent>,
+}
@@ -1,22 +1,47 @@
 use fibers::sync::oneshot;
 use futures::{Future, Poll};
+use prometrics::metrics::Counter;
 use slog::Logger;
-use std::fmt::Debug;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug};
+use std::mem;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::ops::Range;
 use std::sync::mpsc as std_mpsc;
-use std::sync::mpsc::{RecvTimeoutError, SendError};
-use std::sync::Arc;
+use std::sync::mpsc::{RecvTimeoutError, SendError, TryRecvError};
+use std::sync::{Arc, PoisonError};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use trackable::error::ErrorKindExt;
 
-use crate::device::command::{Command, CommandReceiver, CommandSender};
+use crate::deadline::Deadline;
+use crate::device::blocking::BlockingCommand;
+use crate::device::clock::Clock;
+use crate::device::command::{
+    CheckStorage, Command, CommandKind, CommandReceiver, CommandSender, DeleteLump,
+    DeleteLumpRange, DeleteLumps, JournalGc, LinkLump, ListLump, ListLumpRange, PutLump,
+    RenameLump,
+};
+use crate::device::config::DeviceConfig;
+use crate::device::event_log::EventLog;
+use crate::device::idempotency::IdempotencyCache;
 use crate::device::long_queue_policy::LongQueuePolicy;
 use crate::device::probabilistic::{Dropper, ProbabilisticDropper};
-use crate::device::queue::DeadlineQueue;
-use crate::device::{DeviceBuilder, DeviceStatus};
+use crate::device::queue::{CommandTrace, DeadlineQueue};
+use crate::device::runtime::Waker;
+use crate::device::status_watch::StatusWatchers;
+use crate::device::throughput::ThroughputEstimator;
+use crate::device::{
+    DeviceBuilder, DeviceEvent, DeviceEventOutcome, DeviceStats, DeviceStatus, DeviceStatusEvent,
+    DeviceStatusWatch, StorageKey,
+};
+use crate::lump::LumpId;
 use crate::metrics::DeviceMetrics;
 use crate::nvm::NonVolatileMemory;
-use crate::storage::Storage;
+use crate::storage::{
+    PutReport, SlicedDeleteRange, SlicedJournalGc, SlicedPut, Storage, StorageChecker,
+};
 use crate::{Error, ErrorKind, Result};
 
 /// デバイスの実行スレッド.
@@ -27,85 +52,132 @@ where
 {
     metrics: DeviceMetrics,
     queue: DeadlineQueue,
-    storage: Storage<N>,
+    storages: BTreeMap<StorageKey, Storage<N>>,
+    failed_storages: BTreeMap<StorageKey, Error>,
+    side_job_cursor: Option<StorageKey>,
     idle_threshold: Duration,
     max_queue_len: usize,
     max_keep_busy_duration: Duration,
     busy_threshold: usize,
     start_busy_time: Option<Instant>,
+    #[allow(dead_code)] // スレッド稼働中はチャンネルを閉じないために保持する
     command_tx: CommandSender,
     command_rx: CommandReceiver,
     logger: Logger,
     long_queue_policy: LongQueuePolicy,
-    dropper: Box<dyn Dropper>,
+    dropper: Box<dyn Dropper + Send>,
+    drop_exempt_commands: BTreeSet<CommandKind>,
+    long_command: Option<(StorageKey, LongCommand)>,
+    long_command_slice_size: usize,
+    interleaved_commands: usize,
+    max_side_job_duration: Option<Duration>,
+    last_side_job_at: Instant,
+    side_job_between_slices: bool,
+    deferred_replies: BTreeMap<StorageKey, DeferredReplies>,
+    event_log: Option<EventLog>,
+    idempotency: IdempotencyCache,
+    long_command_event: Option<PendingEvent>,
+    multiplexed: bool,
+    idle_since: Option<Instant>,
+    idle_until: Option<Instant>,
+    reject_unreachable_deadlines: bool,
+    throughput: ThroughputEstimator,
+    status_watchers: StatusWatchers,
+    clock: Arc<dyn Clock>,
+    next_command_id: u64,
+    running_command: Option<BlockingCommand>,
+    long_running_command: Option<BlockingCommand>,
 }
 impl<N> DeviceThread<N>
 where
     N: NonVolatileMemory + Send + 'static,
 {
     /// デバイスの実行スレッドを起動する.
+    ///
+    /// `init_storages()`が返した全てのストレージが、このスレッドによって管理される.
     pub fn spawn<F>(
         builder: DeviceBuilder,
-        init_storage: F,
+        init_storages: F,
     ) -> (DeviceThreadHandle, DeviceThreadMonitor)
     where
-        F: FnOnce() -> Result<Storage<N>> + Send + 'static,
+        F: FnOnce() -> Result<Vec<(StorageKey, Storage<N>)>> + Send + 'static,
     {
-        let mut metrics = DeviceMetrics::new(&builder.metrics);
-        metrics.status.set(f64::from(DeviceStatus::Starting as u8));
-
-        let (command_tx, command_rx) = std_mpsc::channel();
-        let (monitored, monitor) = oneshot::monitor();
-        let handle = DeviceThreadHandle {
-            command_tx: command_tx.clone(),
-            metrics: Arc::new(metrics.clone()),
-        };
-        thread::spawn(move || {
-            let result = track!(init_storage()).and_then(|storage| {
-                metrics.storage = Some(storage.metrics().clone());
-                metrics.status.set(f64::from(DeviceStatus::Running as u8));
-                // LongQueuePolicy が RefuseNewRequests か Drop だったら、この後 run_once で使うため、dropper を作っておく。
-                // Stop の場合も実装を簡単にするためにプレイスホルダーの dropper を作る。
-                let ratio = builder.long_queue_policy.ratio();
-                let dropper = Box::new(ProbabilisticDropper::new(builder.logger.clone(), ratio))
-                    as Box<dyn Dropper>;
-                let mut device = DeviceThread {
-                    metrics: metrics.clone(),
-                    queue: DeadlineQueue::new(),
-                    storage,
-                    idle_threshold: builder.idle_threshold,
-                    max_queue_len: builder.max_queue_len,
-                    max_keep_busy_duration: builder.max_keep_busy_duration,
-                    busy_threshold: builder.busy_threshold,
-                    start_busy_time: None,
-                    command_tx,
-                    command_rx,
-                    logger: builder.logger,
-                    long_queue_policy: builder.long_queue_policy,
-                    dropper,
-                };
-                loop {
-                    match track!(device.run_once()) {
-                        Err(e) => break Err(e),
-                        Ok(false) => break Ok(()),
-                        Ok(true) => {}
-                    }
-                }
-            });
-            metrics.status.set(f64::from(DeviceStatus::Stopped as u8));
-            metrics.storage = None;
-            monitored.exit(result);
-        });
+        let (handle, monitor, mut task) = DeviceTask::new(builder, init_storages, None);
+        thread::spawn(move || while task.poll() != Progress::Done {});
+        (handle, monitor)
+    }
 
-        (handle, DeviceThreadMonitor(monitor))
+    /// 一単位分の処理を実行し、その結果としてのデバイスの進行状況を返す.
+    ///
+    /// 多重化されて実行されている場合には、処理すべきものがなければブロックせずに`Progress::Idle`を返す.
+    fn poll_once(&mut self) -> Result<Progress> {
+        if !track!(self.run_once())? {
+            return Ok(Progress::Done);
+        }
+        if let Some(wake_at) = self.idle_until.take() {
+            Ok(Progress::Idle(wake_at))
+        } else {
+            self.idle_since = None;
+            Ok(Progress::Busy)
+        }
     }
 
     fn run_once(&mut self) -> Result<bool> {
-        if let Ok(command) = self.command_rx.try_recv() {
-            return self.push_to_queue(command);
+        track!(self.reply_deferred(false))?;
+        if let Ok((storage, command)) = self.command_rx.try_recv() {
+            return self.push_to_queue(storage, command);
         }
-        if let Some(command) = self.queue.pop() {
+        if self.long_command.is_some() {
+            // 分割実行中のコマンドがある場合には、その合間に処理可能なコマンドのみを先に処理する
+            let interleave = self.queue.peek().is_some_and(Command::can_interleave)
+                && self.long_command.as_ref().is_some_and(|(_, c)| {
+                    c.max_interleaved_commands()
+                        .is_none_or(|max| self.interleaved_commands < max)
+                });
+            if !interleave && !self.side_job_between_slices && self.is_side_job_due() {
+                // 分割実行中のコマンドによってサイドジョブが長時間実行されなくなることを防ぐ
+                // (ただし、コマンド自体の進行を妨げないように、一つの合間で実行するのは一単位のみ)
+                self.side_job_between_slices = true;
+                return track!(self.run_side_job());
+            }
+            if !interleave {
+                self.interleaved_commands = 0;
+                self.side_job_between_slices = false;
+                let event = self.long_command_event.take();
+                let key = self.long_command.as_ref().map(|(key, _)| *key);
+                let result = track!(self.resume_long_command());
+                if self.long_command.is_none() {
+                    self.long_running_command = None;
+                }
+                self.end_event(event, &result);
+                return track!(self.isolate_storage_failure(key.expect("Never fails"), result));
+            }
+            self.interleaved_commands += 1;
+        }
+        if let Some((storage, command, enqueued_at, trace)) = self.queue.pop_with_trace() {
             self.metrics.dequeued_commands.increment(&command);
+            self.metrics.blocked_behind.observe(
+                command.kind(),
+                trace.blocked_behind.map(|b| b.kind),
+                self.clock.now().saturating_duration_since(enqueued_at),
+            );
+            if command.deadline().is_expired_at(self.clock.now())
+                && !matches!(command, Command::Stop(_))
+            {
+                debug!(self.logger, "Request expired: {:?}", command);
+                let result = self.handle_command_with_error(
+                    command,
+                    trace,
+                    ErrorKind::DeadlineExceeded
+                        .cause("The deadline has passed before processing")
+                        .into(),
+                );
+                return Ok(result);
+            }
+            let key = match track!(self.resolve_storage(storage, &command)) {
+                Ok(key) => key,
+                Err(e) => return Ok(self.handle_command_with_error(command, trace, e)),
+            };
             let result = track!(self.check_overload());
             let prioritized = command.prioritized();
             // 過負荷になっていたら、long_queue_policy に応じて挙動を変える
@@ -115,9 +187,9 @@ where
                     LongQueuePolicy::RefuseNewRequests { .. } => {}
                     LongQueuePolicy::Stop => return track!(Err(e)),
                     LongQueuePolicy::Drop { .. } => {
-                        // 確率 ratio で drop する
-                        if self.dropper.will_drop() {
-                            let elapsed = self.start_busy_time.map(|t| t.elapsed().as_secs());
+                        // 確率 ratio で drop する (ただし、対象外の種類のコマンドは除く)
+                        if !self.is_drop_exempt(&command) && self.dropper.will_drop() {
+                            let elapsed = self.busy_duration().map(|d| d.as_secs());
                             warn!(
                                 self.logger,
                                 "Request dropped: {:?}",
@@ -125,8 +197,10 @@ where
                                 "queue_len" => self.queue.len(),
                                 "from_busy (sec)" => elapsed,
                             );
+                            self.metrics.dropped_commands.increment(&command);
                             let result = self.handle_command_with_error(
                                 command,
+                                trace,
                                 ErrorKind::RequestDropped.cause(e).into(),
                             );
                             return Ok(result);
@@ -134,28 +208,126 @@ where
                     }
                 }
             }
-            return track!(self.handle_command(command));
+            let (is_read, is_write) = (command.is_read(), command.is_write());
+            let event = self.begin_event(&command, trace);
+            let start = Instant::now();
+            self.running_command = Some(BlockingCommand {
+                id: trace.id,
+                kind: command.kind(),
+            });
+            let result = track!(self.handle_command(key, command));
+            if self.long_command.is_none() {
+                self.throughput.observe(start.elapsed());
+            } else if self.long_running_command.is_none() {
+                // 分割実行の完了までは、このコマンドが実行中のものとして扱われる
+                self.long_running_command = self.running_command;
+            }
+            self.end_event(event, &result);
+            let latency = self
+                .clock
+                .now()
+                .saturating_duration_since(enqueued_at)
+                .as_secs_f64();
+            if is_read {
+                self.metrics.read_latency_seconds.observe(latency);
+            } else if is_write {
+                self.metrics.write_latency_seconds.observe(latency);
+            }
+            return track!(self.isolate_storage_failure(key, result));
         }
 
-        match self.command_rx.recv_timeout(self.idle_threshold) {
+        // キューが空になったので、以降に受け付けたコマンドは何にも待たされていないことになる
+        self.running_command = None;
+
+        // 保留中の応答がある場合には、同期の期限を過ぎて待機しないようにする
+        let timeout = self.sync_deadline().map_or(self.idle_threshold, |d| {
+            cmp::min(
+                self.idle_threshold,
+                d.saturating_duration_since(self.clock.now()),
+            )
+        });
+        let received = if self.multiplexed {
+            // 他のデバイスとスレッドを共有しているので、ブロックせずに待機すべき期限を呼び出し元に伝える
+            match self.command_rx.try_recv() {
+                Ok(command) => Ok(command),
+                Err(TryRecvError::Disconnected) => unreachable!(),
+                Err(TryRecvError::Empty) => {
+                    let now = self.clock.now();
+                    let idle_deadline = *self.idle_since.get_or_insert(now) + self.idle_threshold;
+                    let wake_at = self
+                        .sync_deadline()
+                        .map_or(idle_deadline, |d| cmp::min(d, idle_deadline));
+                    if now < wake_at {
+                        // 呼び出し元は実時間で待機するので、時計の時刻から実時刻に換算して伝える
+                        self.idle_until = Some(Instant::now() + (wake_at - now));
+                        return Ok(true);
+                    }
+                    Err(RecvTimeoutError::Timeout)
+                }
+            }
+        } else {
+            self.command_rx.recv_timeout(timeout)
+        };
+        match received {
             Err(RecvTimeoutError::Disconnected) => unreachable!(),
             Err(RecvTimeoutError::Timeout) => {
-                self.metrics.side_jobs.increment();
-                track!(self.storage.run_side_job_once())?;
-                Ok(true)
+                if self.sync_deadline().is_some_and(|d| d <= self.clock.now()) {
+                    track!(self.reply_deferred(false))?;
+                    return Ok(true);
+                }
+                track!(self.run_side_job())
             }
-            Ok(command) => self.push_to_queue(command),
+            Ok((storage, command)) => self.push_to_queue(storage, command),
         }
     }
 
+    /// サイドジョブを一単位実行する.
+    fn run_side_job(&mut self) -> Result<bool> {
+        self.metrics.side_jobs.increment();
+        self.last_side_job_at = self.clock.now();
+        let start = Instant::now();
+        let max_duration = self.max_side_job_duration;
+        let storage = self.next_side_job_storage();
+        let result = if let Some(limit) = max_duration {
+            track!(storage.run_side_job_once_within(limit))
+        } else {
+            track!(storage.run_side_job_once())
+        };
+        self.metrics
+            .side_job_duration_seconds
+            .observe(start.elapsed().as_secs_f64());
+        let key = self.side_job_cursor.expect("Never fails");
+        track!(self.isolate_storage_failure(key, result.map(|()| true)))
+    }
+
+    /// 分割実行中のコマンドの合間に、サイドジョブを実行すべきかどうかを判定する.
+    ///
+    /// 合間での実行が許されているコマンドの場合に限り、前回のサイドジョブの実行から
+    /// `DeviceBuilder::idle_threshold`以上が経過していれば、実行すべきと判定される.
+    fn is_side_job_due(&self) -> bool {
+        self.long_command
+            .as_ref()
+            .is_some_and(|(_, c)| c.allows_side_jobs())
+            && self
+                .clock
+                .now()
+                .saturating_duration_since(self.last_side_job_at)
+                >= self.idle_threshold
+    }
+
     /// ここでも command の処理をせざるを得ない都合上、終了しないかどうかの bool 値を返す。
-    fn push_to_queue(&mut self, command: Command) -> Result<bool> {
+    fn push_to_queue(&mut self, storage: Option<StorageKey>, command: Command) -> Result<bool> {
+        let trace = CommandTrace {
+            id: self.next_command_id,
+            blocked_behind: self.long_running_command.or(self.running_command),
+        };
+        self.next_command_id += 1;
         let result = track!(self.check_overload());
         let prioritized = command.prioritized();
         if let Err(e) = track!(self.check_queue_limit()) {
             // queue length の hard limit を突破しているので、prioritized かどうかに関係なくエラーを返す。
             // 常にリクエストを拒否すれば問題ない。
-            let elapsed = self.start_busy_time.map(|t| t.elapsed().as_secs());
+            let elapsed = self.busy_duration().map(|d| d.as_secs());
             error!(
                 self.logger, "Request refused (hard limit): {:?}",
                 command;
@@ -164,8 +336,20 @@ where
                 "from_busy (sec)" => elapsed,
             );
             self.metrics.dequeued_commands.increment(&command);
-            let result =
-                self.handle_command_with_error(command, ErrorKind::RequestRefused.cause(e).into());
+            let result = self.handle_command_with_error(
+                command,
+                trace,
+                ErrorKind::RequestRefused.cause(e).into(),
+            );
+            return Ok(result);
+        }
+        if let Err(e) = track!(self.check_deadline_reachable(&command)) {
+            debug!(
+                self.logger,
+                "Request rejected (unreachable deadline): {:?}", command
+            );
+            self.metrics.dequeued_commands.increment(&command);
+            let result = self.handle_command_with_error(command, trace, e);
             return Ok(result);
         }
         if let (Err(e), false) = (result, prioritized) {
@@ -173,7 +357,7 @@ where
                 LongQueuePolicy::RefuseNewRequests { .. } => {
                     // 確率 ratio で refuse する
                     if self.dropper.will_drop() {
-                        let elapsed = self.start_busy_time.map(|t| t.elapsed().as_secs());
+                        let elapsed = self.busy_duration().map(|d| d.as_secs());
                         warn!(
                             self.logger, "Request refused: {:?}",
                             command;
@@ -183,6 +367,7 @@ where
                         self.metrics.dequeued_commands.increment(&command);
                         let result = self.handle_command_with_error(
                             command,
+                            trace,
                             ErrorKind::RequestRefused.cause(e).into(),
                         );
                         return Ok(result);
@@ -195,14 +380,194 @@ where
                 LongQueuePolicy::Drop { .. } => {}
             }
         }
-        self.queue.push(command);
+        self.queue.push_with_trace(storage, command, trace);
         Ok(true)
     }
 
-    fn handle_command(&mut self, command: Command) -> Result<bool> {
+    /// コマンドが`LongQueuePolicy::Drop`によるドロップの対象外かどうかを判定する.
+    ///
+    /// `Stop`コマンドは、設定に関わらず常に対象外となる.
+    fn is_drop_exempt(&self, command: &Command) -> bool {
+        let kind = command.kind();
+        kind == CommandKind::Stop || self.drop_exempt_commands.contains(&kind)
+    }
+
+    /// コマンドの対象ストレージのキーを解決する.
+    ///
+    /// キーが指定されていない場合には、デフォルト(キーが最小)のストレージが対象となる.
+    ///
+    /// 対象のストレージが致命的なエラーによって使用不能となっている場合には、そのエラーが返される.
+    fn resolve_storage(
+        &self,
+        storage: Option<StorageKey>,
+        command: &Command,
+    ) -> Result<StorageKey> {
+        if matches!(*command, Command::Stop(_)) {
+            // 停止はデバイス全体が対象
+            return Ok(*self.storages.keys().next().expect("Never fails"));
+        }
+        let default = self
+            .storages
+            .keys()
+            .chain(self.failed_storages.keys())
+            .min();
+        let key = storage.unwrap_or(*default.expect("Never fails"));
+        if let Some(e) = self.failed_storages.get(&key) {
+            return Err(track!(e.clone(), "The storage has failed: {}", key));
+        }
+        match storage {
+            None => Ok(key),
+            Some(key) => {
+                track_assert!(
+                    self.storages.contains_key(&key),
+                    ErrorKind::InvalidInput,
+                    "Unknown storage: {}",
+                    key
+                );
+                Ok(key)
+            }
+        }
+    }
+
+    /// ストレージ`key`に対する処理の結果を、デバイスの実行スレッド全体の結果に変換する.
+    ///
+    /// エラーの場合には、そのストレージのみを使用不能とし(`fail_storage`)、
+    /// 他のストレージを管理するためにスレッドの実行は継続する.
+    fn isolate_storage_failure(&mut self, key: StorageKey, result: Result<bool>) -> Result<bool> {
+        match result {
+            Err(e) => track!(self.fail_storage(key, e)).map(|()| true),
+            result => result,
+        }
+    }
+
+    /// 致命的なエラーが発生したストレージを、以後の処理対象から外す.
+    ///
+    /// そのストレージ宛ての保留中の応答にはエラーが返され、
+    /// 以後(キュー内で待機中のものを含む)のコマンドにも同じエラーが返されるようになる.
+    ///
+    /// 使用可能なストレージが一つも残っていない場合には、`error`が返され、デバイスは停止する.
+    fn fail_storage(&mut self, key: StorageKey, error: Error) -> Result<()> {
+        if self.storages.len() <= 1 {
+            return Err(error);
+        }
+        error!(
+            self.logger,
+            "Storage failed; stops serving it: storage={}, error={}", key, error
+        );
+        if let Some(mut storage) = self.storages.remove(&key) {
+            // デバイス全体の停止時と同様に、可能な範囲でジャーナルを永続化しておく
+            // (クローズはしないので、クリーンシャットダウンの印は書き込まれない)
+            if let Err(e) = track!(storage.sync_journal_before_stop()) {
+                error!(
+                    self.logger,
+                    "Failed to sync journal of the failed storage: storage={}, error={}", key, e
+                );
+            }
+        }
+        if self.side_job_cursor == Some(key) {
+            self.side_job_cursor = None;
+        }
+        if self.long_command.as_ref().is_some_and(|(k, _)| *k == key) {
+            let (_, command) = self.long_command.take().expect("Never fails");
+            command.reply_error(error.clone());
+            self.long_running_command = None;
+            let event = self.long_command_event.take();
+            self.end_event(event, &Err(error.clone()));
+        }
+        if let Some(deferred) = self.deferred_replies.remove(&key) {
+            for reply in deferred.replies {
+                reply.reply(Err(error.clone()));
+            }
+        }
+        self.failed_storages.insert(key, error);
+        Ok(())
+    }
+
+    fn storage(&mut self, key: StorageKey) -> &mut Storage<N> {
+        self.storages.get_mut(&key).expect("Never fails")
+    }
+
+    /// サイドジョブを実行するストレージを、ラウンドロビンで選択する.
+    fn next_side_job_storage(&mut self) -> &mut Storage<N> {
+        let next = self
+            .side_job_cursor
+            .and_then(|last| self.storages.range((Excluded(last), Unbounded)).next())
+            .or_else(|| self.storages.iter().next())
+            .map(|(key, _)| *key)
+            .expect("Never fails");
+        self.side_job_cursor = Some(next);
+        self.storage(next)
+    }
+
+    /// 全てのストレージをクローズする.
+    ///
+    /// 途中でエラーが発生した場合でも、残りのストレージのクローズは試みられ、最初のエラーが返される.
+    /// 致命的なエラーによって停止する前に、各ストレージのジャーナルの同期を試みる.
+    ///
+    /// 同期はベストエフォートで行われ、その成否は`error`の追跡情報に記録される.
+    fn sync_storages_before_stop(&mut self, error: Error) -> Error {
+        let failures = self
+            .storages
+            .iter_mut()
+            .filter_map(|(key, storage)| {
+                track!(storage.sync_journal_before_stop())
+                    .err()
+                    .map(|e| (*key, e))
+            })
+            .collect::<Vec<_>>();
+        if failures.is_empty() {
+            track!(error, "Journal synced before stopping the device")
+        } else {
+            for (key, e) in &failures {
+                error!(
+                    self.logger,
+                    "Failed to sync journal before stopping: storage={:?}, error={}", key, e
+                );
+            }
+            track!(
+                error,
+                "Failed to sync journal before stopping the device: {:?}",
+                failures
+                    .iter()
+                    .map(|(key, e)| (*key, *e.kind()))
+                    .collect::<Vec<_>>()
+            )
+        }
+    }
+
+    fn delete_lump(&mut self, key: StorageKey, c: &DeleteLump) -> Result<bool> {
+        let satisfied = match c.condition() {
+            None => true,
+            Some(condition) => track!(self.storage(key).satisfies(c.lump_id(), condition))?,
+        };
+        if !satisfied {
+            return Ok(false);
+        }
+        match (c.secure_fill(), c.audit_metadata()) {
+            (Some(fill), Some(metadata)) => {
+                track!(self
+                    .storage(key)
+                    .delete_secure_with_audit(c.lump_id(), fill, metadata))
+            }
+            (Some(fill), None) => track!(self.storage(key).delete_secure(c.lump_id(), fill)),
+            (None, Some(metadata)) => {
+                track!(self.storage(key).delete_with_audit(c.lump_id(), metadata))
+            }
+            (None, None) => track!(self.storage(key).delete(c.lump_id())),
+        }
+    }
+
+    fn close_storages(self) -> Result<()> {
+        self.storages
+            .into_values()
+            .map(|storage| track!(storage.close()))
+            .fold(Ok(()), Result::and)
+    }
+
+    fn handle_command(&mut self, key: StorageKey, command: Command) -> Result<bool> {
         match command {
             Command::Get(c) => {
-                let result = track!(self.storage.get(c.lump_id()));
+                let result = track!(self.storage(key).get(c.lump_id()));
                 if result.is_err() {
                     self.metrics.failed_commands.get.increment();
                 }
@@ -210,91 +575,532 @@ where
                 Ok(true)
             }
             Command::Head(c) => {
-                let value = self.storage.head(c.lump_id());
+                let value = self.storage(key).head(c.lump_id());
                 c.reply(Ok(value));
                 Ok(true)
             }
             Command::List(c) => {
-                let value = self.storage.list();
-                c.reply(Ok(value));
-                Ok(true)
+                let start = Some(LumpId::new(0));
+                self.long_command = Some((key, LongCommand::List(c, start, Vec::new())));
+                track!(self.resume_long_command())
             }
             Command::ListRange(c) => {
-                let value = self.storage.list_range(c.lump_range());
-                c.reply(Ok(value));
-                Ok(true)
+                let (range, _) = c.lump_range().split_max();
+                self.long_command = Some((key, LongCommand::ListRange(c, range, Vec::new())));
+                track!(self.resume_long_command())
             }
             Command::Put(c) => {
                 debug!(self.logger, "Put LumpId=(\"{}\")", c.lump_id());
-                let result = track!(self.storage.put(c.lump_id(), c.lump_data()));
-                if result.is_err() {
-                    self.metrics.failed_commands.put.increment();
+                let replayed = match c.idempotency_key() {
+                    None => Ok(None),
+                    Some(k) => track!(self.idempotency.lookup_put(key, k, c.lump_id())),
+                };
+                let result = match replayed {
+                    Err(e) => Err(e),
+                    Ok(Some(report)) => {
+                        self.metrics.replayed_commands.put.increment();
+                        Ok(report)
+                    }
+                    Ok(None) => {
+                        let storage = self.storage(key);
+                        let result = if let Some(metadata) = c.audit_metadata() {
+                            track!(storage.put_with_audit(c.lump_id(), c.lump_data(), metadata))
+                        } else {
+                            match track!(storage.start_sliced_put(
+                                c.lump_id(),
+                                c.lump_data(),
+                                c.deadline()
+                            )) {
+                                Ok(Some(put)) => {
+                                    // 大きなデータは分割して書き込み、その合間に読み込み系のコマンドを処理する
+                                    self.long_command = Some((key, LongCommand::Put(c, put)));
+                                    return track!(self.resume_long_command());
+                                }
+                                Ok(None) => track!(storage.put_with_deadline(
+                                    c.lump_id(),
+                                    c.lump_data(),
+                                    c.deadline()
+                                )),
+                                Err(e) => Err(e),
+                            }
+                        };
+                        if let (Some(k), Ok(report)) = (c.idempotency_key(), &result) {
+                            self.idempotency.record_put(key, k, c.lump_id(), *report);
+                        }
+                        result
+                    }
+                };
+                track!(self.reply_put(key, c, result))
+            }
+            Command::Delete(c) => {
+                let replayed = match c.idempotency_key() {
+                    None => Ok(None),
+                    Some(k) => track!(self.idempotency.lookup_delete(key, k, c.lump_id())),
+                };
+                let result = match replayed {
+                    Err(e) => Err(e),
+                    Ok(Some(deleted)) => {
+                        self.metrics.replayed_commands.delete.increment();
+                        Ok(deleted)
+                    }
+                    Ok(None) => {
+                        let result = track!(self.delete_lump(key, &c));
+                        if let (Some(k), Ok(deleted)) = (c.idempotency_key(), &result) {
+                            self.idempotency
+                                .record_delete(key, k, c.lump_id(), *deleted);
+                        }
+                        result
+                    }
+                };
+                if let Err(ref e) = result {
+                    self.metrics.failed_commands.delete.increment();
+                    if c.is_detached() {
+                        warn!(
+                            self.logger,
+                            "Detached delete failed: LumpId=(\"{}\"), {}",
+                            c.lump_id(),
+                            e
+                        );
+                    }
                 }
                 if let Some(e) = maybe_critical_error(&result) {
                     c.reply(result);
                     Err(e)
-                } else {
-                    let do_sync = c.do_sync_journal();
+                } else if c.do_sync_journal() {
                     c.reply(result);
-                    if do_sync {
-                        let sync_result = track!(self.storage.journal_sync());
-                        sync_result.map(|_| true)
-                    } else {
-                        Ok(true)
+                    let sync_result = track!(self.storage(key).journal_sync());
+                    sync_result.map(|_| true)
+                } else {
+                    match (c.max_sync_delay(), result) {
+                        (Some(delay), Ok(deleted)) => {
+                            self.defer_reply(key, DeferredReply::Delete(c, deleted), delay)
+                        }
+                        (_, result) => c.reply(result),
                     }
+                    Ok(true)
                 }
             }
-            Command::Delete(c) => {
-                let result = track!(self.storage.delete(c.lump_id()));
+            Command::DeleteRange(c) => {
+                match track!(self.storage(key).start_sliced_delete_range(c.lump_range())) {
+                    Ok(delete) => {
+                        self.long_command = Some((key, LongCommand::DeleteRange(c, delete)));
+                        track!(self.resume_long_command())
+                    }
+                    Err(e) => track!(self.reply_delete_range(key, c, Err(e))),
+                }
+            }
+            Command::DeleteMany(c) => {
+                let result = track!(self.storage(key).delete_many(c.lump_ids()));
                 if result.is_err() {
-                    self.metrics.failed_commands.delete.increment();
+                    self.metrics.failed_commands.delete_many.increment();
                 }
                 if let Some(e) = maybe_critical_error(&result) {
                     c.reply(result);
                     Err(e)
-                } else {
-                    let do_sync = c.do_sync_journal();
+                } else if c.do_sync_journal() {
                     c.reply(result);
-                    if do_sync {
-                        let sync_result = track!(self.storage.journal_sync());
-                        sync_result.map(|_| true)
-                    } else {
-                        Ok(true)
+                    let sync_result = track!(self.storage(key).journal_sync());
+                    sync_result.map(|_| true)
+                } else {
+                    match (c.max_sync_delay(), result) {
+                        (Some(delay), Ok(deleted)) => {
+                            self.defer_reply(key, DeferredReply::DeleteMany(c, deleted), delay)
+                        }
+                        (_, result) => c.reply(result),
                     }
+                    Ok(true)
                 }
             }
-            Command::DeleteRange(c) => {
-                let result = track!(self.storage.delete_range(c.lump_range()));
+            Command::Link(c) => {
+                debug!(
+                    self.logger,
+                    "Link LumpId=(\"{}\") -> LumpId=(\"{}\")",
+                    c.src(),
+                    c.dst()
+                );
+                let result = track!(self.storage(key).link(c.src(), c.dst()));
                 if result.is_err() {
-                    self.metrics.failed_commands.delete_range.increment();
+                    self.metrics.failed_commands.link.increment();
                 }
                 if let Some(e) = maybe_critical_error(&result) {
                     c.reply(result);
                     Err(e)
+                } else if c.do_sync_journal() {
+                    c.reply(result);
+                    let sync_result = track!(self.storage(key).journal_sync());
+                    sync_result.map(|_| true)
                 } else {
-                    let do_sync = c.do_sync_journal();
+                    match (c.max_sync_delay(), result) {
+                        (Some(delay), Ok(linked)) => {
+                            self.defer_reply(key, DeferredReply::Link(c, linked), delay)
+                        }
+                        (_, result) => c.reply(result),
+                    }
+                    Ok(true)
+                }
+            }
+            Command::Rename(c) => {
+                debug!(
+                    self.logger,
+                    "Rename LumpId=(\"{}\") -> LumpId=(\"{}\")",
+                    c.old_id(),
+                    c.new_id()
+                );
+                let result = track!(self.storage(key).rename(c.old_id(), c.new_id()));
+                if result.is_err() {
+                    self.metrics.failed_commands.rename.increment();
+                }
+                if let Some(e) = maybe_critical_error(&result) {
                     c.reply(result);
-                    if do_sync {
-                        let sync_result = track!(self.storage.journal_sync());
-                        sync_result.map(|_| true)
-                    } else {
-                        Ok(true)
+                    Err(e)
+                } else if c.do_sync_journal() {
+                    c.reply(result);
+                    let sync_result = track!(self.storage(key).journal_sync());
+                    sync_result.map(|_| true)
+                } else {
+                    match (c.max_sync_delay(), result) {
+                        (Some(delay), Ok(renamed)) => {
+                            self.defer_reply(key, DeferredReply::Rename(c, renamed), delay)
+                        }
+                        (_, result) => c.reply(result),
                     }
+                    Ok(true)
                 }
             }
             Command::UsageRange(c) => {
-                let usage = self.storage.usage_range(c.lump_range());
+                let usage = self.storage(key).usage_range(c.lump_range());
                 c.reply(Ok(usage));
                 Ok(true)
             }
-            Command::Stop(_) => Ok(false),
+            Command::Stats(c) => {
+                let stats = DeviceStats {
+                    status: self.metrics.status(),
+                    queue_len: self.queue.len(),
+                    max_queue_len: self.max_queue_len,
+                    busy_threshold: self.busy_threshold,
+                    busy_duration: self.busy_duration(),
+                    long_command: self.long_command.as_ref().map(|(_, c)| c.name()),
+                    storage: self.storage(key).stats(),
+                };
+                c.reply(Ok(stats));
+                Ok(true)
+            }
+            Command::JournalSnapshotStep(c) => {
+                let mut cursor = Some(c.cursor());
+                let result = track!(self
+                    .storage(key)
+                    .journal_snapshot_step(&mut cursor, c.max_entries()));
+                if result.is_err() {
+                    self.metrics
+                        .failed_commands
+                        .journal_snapshot_step
+                        .increment();
+                }
+                c.reply(result.map(|entries| (entries, cursor)));
+                Ok(true)
+            }
+            Command::ScanStep(c) => {
+                let mut range = Some(c.range());
+                let result = track!(self.storage(key).scan_step(&mut range, c.max_lumps()));
+                if result.is_err() {
+                    self.metrics.failed_commands.scan_step.increment();
+                }
+                c.reply(result.map(|lumps| (lumps, range)));
+                Ok(true)
+            }
+            Command::WarmUp(c) => {
+                let result = track!(self.storage(key).warm_up(c.touch_regions()));
+                if result.is_err() {
+                    self.metrics.failed_commands.warm_up.increment();
+                }
+                c.reply(result);
+                Ok(true)
+            }
+            Command::Check(c) => {
+                let checker = StorageChecker::new(c.level());
+                self.long_command = Some((key, LongCommand::Check(c, checker)));
+                track!(self.resume_long_command())
+            }
+            Command::JournalGc(c) => match track!(self.storage(key).start_sliced_journal_gc()) {
+                Ok(gc) => {
+                    self.long_command = Some((key, LongCommand::JournalGc(c, gc)));
+                    track!(self.resume_long_command())
+                }
+                Err(e) => track!(self.reply_journal_gc(c, Err(e))),
+            },
+            Command::Stop(_) => {
+                // 停止前に、保留中の応答を返しておく
+                track!(self.reply_deferred(true))?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// PUTの結果を返答する.
+    ///
+    /// 必要に応じて、ジャーナルの同期を行ったり、同期が完了するまで返答を保留したりする.
+    fn reply_put(
+        &mut self,
+        key: StorageKey,
+        c: PutLump,
+        result: Result<PutReport>,
+    ) -> Result<bool> {
+        if let Err(ref e) = result {
+            self.metrics.failed_commands.put.increment();
+            if c.is_detached() {
+                warn!(
+                    self.logger,
+                    "Detached put failed: LumpId=(\"{}\"), {}",
+                    c.lump_id(),
+                    e
+                );
+            }
+        }
+        if let Some(e) = maybe_critical_error(&result) {
+            c.reply(result);
+            Err(e)
+        } else if c.do_sync_journal() {
+            // 同期の実施有無を結果に含めるため、応答は同期の完了後に返す
+            match track!(self.storage(key).journal_sync()) {
+                Ok(()) => {
+                    c.reply(result.map(PutReport::with_journal_synced));
+                    Ok(true)
+                }
+                Err(e) => {
+                    c.reply(Err(e.clone()));
+                    Err(e)
+                }
+            }
+        } else {
+            match (c.max_sync_delay(), result) {
+                (Some(delay), Ok(report)) => {
+                    self.defer_reply(key, DeferredReply::Put(c, report), delay)
+                }
+                (_, result) => c.reply(result),
+            }
+            Ok(true)
         }
     }
 
+    /// 分割実行中のコマンドの処理を一単位分だけ進める.
+    ///
+    /// コマンドが完了した場合には、その結果を返答する.
+    fn resume_long_command(&mut self) -> Result<bool> {
+        let slice_size = self.long_command_slice_size;
+        let (key, command) = self.long_command.take().expect("Never fails");
+        match command {
+            LongCommand::Put(c, mut put) => {
+                match track!(self.storage(key).sliced_put_step(&mut put, c.lump_data())) {
+                    Ok(None) => {
+                        self.long_command = Some((key, LongCommand::Put(c, put)));
+                        Ok(true)
+                    }
+                    result => {
+                        let result = result.map(|report| report.expect("Never fails"));
+                        if let (Some(k), Ok(report)) = (c.idempotency_key(), &result) {
+                            self.idempotency.record_put(key, k, c.lump_id(), *report);
+                        }
+                        track!(self.reply_put(key, c, result))
+                    }
+                }
+            }
+            LongCommand::List(c, mut start, mut ids) => {
+                ids.extend(self.storage(key).list_step(&mut start, slice_size));
+                if start.is_some() {
+                    self.long_command = Some((key, LongCommand::List(c, start, ids)));
+                } else {
+                    c.reply(Ok(ids));
+                }
+                Ok(true)
+            }
+            LongCommand::ListRange(c, mut range, mut ids) => {
+                ids.extend(self.storage(key).list_range_step(&mut range, slice_size));
+                if range.start < range.end {
+                    self.long_command = Some((key, LongCommand::ListRange(c, range, ids)));
+                } else {
+                    // `LumpId::MAX`は分割後の範囲に含まれないため、個別に扱う
+                    if c.lump_range().contains(&LumpId::MAX)
+                        && self.storage(key).head(&LumpId::MAX).is_some()
+                    {
+                        ids.push(LumpId::MAX);
+                    }
+                    c.reply(Ok(ids));
+                }
+                Ok(true)
+            }
+            LongCommand::DeleteRange(c, mut delete) => {
+                match self
+                    .storage(key)
+                    .sliced_delete_range_step(&mut delete, slice_size)
+                {
+                    None => {
+                        self.long_command = Some((key, LongCommand::DeleteRange(c, delete)));
+                        Ok(true)
+                    }
+                    Some(ids) => track!(self.reply_delete_range(key, c, Ok(ids))),
+                }
+            }
+            LongCommand::JournalGc(c, mut gc) => {
+                match track!(self.storage(key).sliced_journal_gc_step(&mut gc)) {
+                    Ok(false) => {
+                        self.long_command = Some((key, LongCommand::JournalGc(c, gc)));
+                        Ok(true)
+                    }
+                    result => track!(self.reply_journal_gc(c, result.map(|_| ()))),
+                }
+            }
+            LongCommand::Check(c, mut checker) => {
+                match track!(self.storage(key).check_step(&mut checker, slice_size)) {
+                    Ok(false) => {
+                        self.long_command = Some((key, LongCommand::Check(c, checker)));
+                        Ok(true)
+                    }
+                    result => {
+                        let result = result.map(|_| checker.into_report());
+                        if result.is_err() {
+                            self.metrics.failed_commands.check.increment();
+                        }
+                        if let Some(e) = maybe_critical_error(&result) {
+                            c.reply(result);
+                            Err(e)
+                        } else {
+                            c.reply(result);
+                            Ok(true)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 範囲削除の結果を返答する.
+    ///
+    /// 必要に応じて、ジャーナルの同期を行ったり、同期が完了するまで返答を保留したりする.
+    fn reply_delete_range(
+        &mut self,
+        key: StorageKey,
+        c: DeleteLumpRange,
+        result: Result<Vec<LumpId>>,
+    ) -> Result<bool> {
+        if result.is_err() {
+            self.metrics.failed_commands.delete_range.increment();
+        }
+        if let Some(e) = maybe_critical_error(&result) {
+            c.reply(result);
+            Err(e)
+        } else if c.do_sync_journal() {
+            c.reply(result);
+            let sync_result = track!(self.storage(key).journal_sync());
+            sync_result.map(|_| true)
+        } else {
+            match (c.max_sync_delay(), result) {
+                (Some(delay), Ok(ids)) => {
+                    self.defer_reply(key, DeferredReply::DeleteRange(c, ids), delay)
+                }
+                (_, result) => c.reply(result),
+            }
+            Ok(true)
+        }
+    }
+
+    /// ジャーナルの全体GCの結果を返答する.
+    fn reply_journal_gc(&mut self, c: JournalGc, result: Result<()>) -> Result<bool> {
+        if result.is_err() {
+            self.metrics.failed_commands.journal_gc.increment();
+        }
+        if let Some(e) = maybe_critical_error(&result) {
+            c.reply(result);
+            Err(e)
+        } else {
+            c.reply(result);
+            Ok(true)
+        }
+    }
+
+    /// 書き込み系のコマンドへの応答を、ジャーナルの同期が完了するまで(最大で`delay`の間)保留する.
+    fn defer_reply(&mut self, key: StorageKey, reply: DeferredReply, delay: Duration) {
+        let syncs = self.storage(key).metrics().journal_region().syncs();
+        let deadline = self.clock.now() + delay;
+        let deferred = self
+            .deferred_replies
+            .entry(key)
+            .or_insert_with(|| DeferredReplies {
+                replies: Vec::new(),
+                syncs_at_deferral: syncs,
+                sync_deadline: deadline,
+            });
+        deferred.sync_deadline = cmp::min(deferred.sync_deadline, deadline);
+        deferred.replies.push(reply);
+    }
+
+    /// 保留中の応答群の内で、最も早い同期の期限を返す.
+    fn sync_deadline(&self) -> Option<Instant> {
+        self.deferred_replies
+            .values()
+            .map(|d| d.sync_deadline)
+            .min()
+    }
+
+    /// 保留中の応答群を返す.
+    ///
+    /// 保留の開始以降にジャーナルの同期が既に行われている場合には、そのまま応答する.
+    /// そうではない場合には、同期の期限を過ぎているか`force`が`true`の場合にのみ、同期を行った上で応答する.
+    ///
+    /// これらの判定および同期は、ストレージ毎に行われ、同期に失敗したストレージは使用不能となる(`fail_storage`).
+    fn reply_deferred(&mut self, force: bool) -> Result<()> {
+        if self.deferred_replies.is_empty() {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        let storages = &mut self.storages;
+        let mut failures = Vec::new();
+        self.deferred_replies.retain(|key, deferred| {
+            let storage = storages.get_mut(key).expect("Never fails");
+            let synced = storage.metrics().journal_region().syncs() != deferred.syncs_at_deferral;
+            let expired = deferred.sync_deadline <= now;
+            if !(synced || expired || force) {
+                return true;
+            }
+
+            let sync_result = if synced {
+                Ok(())
+            } else {
+                track!(storage.journal_sync())
+            };
+            for reply in deferred.replies.drain(..) {
+                reply.reply(sync_result.clone());
+            }
+            if let Err(e) = sync_result {
+                failures.push((*key, e));
+            }
+            false
+        });
+        for (key, e) in failures {
+            track!(self.fail_storage(key, e))?;
+        }
+        Ok(())
+    }
+
     // command に対し、常に指定されたエラーを返答する。
     // この関数自身は常に成功するため、handle_command と違い bool を返す。
-    fn handle_command_with_error(&mut self, command: Command, error: Error) -> bool {
+    fn handle_command_with_error(
+        &mut self,
+        command: Command,
+        trace: CommandTrace,
+        error: Error,
+    ) -> bool {
         self.metrics.failed_commands.increment(&command);
+        if let Some(ref log) = self.event_log {
+            log.push(DeviceEvent {
+                id: trace.id,
+                time: SystemTime::now(),
+                command: command.name(),
+                lump_id: command.lump_id(),
+                elapsed: Duration::default(),
+                outcome: DeviceEventOutcome::Rejected(*error.kind()),
+                blocked_behind: trace.blocked_behind,
+            });
+        }
         match command {
             Command::Get(c) => c.reply(track!(Err(error))),
             Command::Head(c) => c.reply(track!(Err(error))),
@@ -303,7 +1109,16 @@ where
             Command::Put(c) => c.reply(track!(Err(error))),
             Command::Delete(c) => c.reply(track!(Err(error))),
             Command::DeleteRange(c) => c.reply(track!(Err(error))),
+            Command::DeleteMany(c) => c.reply(track!(Err(error))),
+            Command::Link(c) => c.reply(track!(Err(error))),
+            Command::Rename(c) => c.reply(track!(Err(error))),
             Command::UsageRange(c) => c.reply(track!(Err(error))),
+            Command::Check(c) => c.reply(track!(Err(error))),
+            Command::Stats(c) => c.reply(track!(Err(error))),
+            Command::JournalSnapshotStep(c) => c.reply(track!(Err(error))),
+            Command::ScanStep(c) => c.reply(track!(Err(error))),
+            Command::WarmUp(c) => c.reply(track!(Err(error))),
+            Command::JournalGc(c) => c.reply(track!(Err(error))),
             Command::Stop(_) => {
                 // ここに来た場合だけ false を返し、残りのパスは全て true を返す。
                 return false;
@@ -312,16 +1127,97 @@ where
         true
     }
 
+    /// コマンドの処理の開始を記録する.
+    ///
+    /// 記録が無効な場合には`None`を返す.
+    fn begin_event(&self, command: &Command, trace: CommandTrace) -> Option<PendingEvent> {
+        self.event_log.as_ref()?;
+        let failures = self.metrics.failed_commands.counter(command).clone();
+        Some(PendingEvent {
+            trace,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            command: command.name(),
+            lump_id: command.lump_id(),
+            failures_at_start: failures.value() as u64,
+            failures,
+        })
+    }
+
+    /// コマンドの処理の完了を記録する.
+    ///
+    /// コマンドが分割実行中の場合には、その完了時まで記録を保留する.
+    fn end_event(&mut self, event: Option<PendingEvent>, result: &Result<bool>) {
+        let event = match event {
+            None => return,
+            Some(event) => event,
+        };
+        if self.long_command.is_some() && self.long_command_event.is_none() && result.is_ok() {
+            // 分割実行の合間に処理されたコマンドの記録で、分割実行中のコマンドのものを上書きしないようにする
+            self.long_command_event = Some(event);
+            return;
+        }
+        let outcome = match *result {
+            Err(ref e) => DeviceEventOutcome::Fatal(*e.kind()),
+            Ok(_) if event.failures.value() as u64 != event.failures_at_start => {
+                DeviceEventOutcome::Failed
+            }
+            Ok(_) => DeviceEventOutcome::Succeeded,
+        };
+        if let Some(ref log) = self.event_log {
+            log.push(DeviceEvent {
+                id: event.trace.id,
+                time: event.time,
+                command: event.command,
+                lump_id: event.lump_id,
+                elapsed: event.start.elapsed(),
+                outcome,
+                blocked_behind: event.trace.blocked_behind,
+            });
+        }
+    }
+
+    /// ビジー状態が継続している時間を返す.
+    ///
+    /// ビジー状態ではない場合には`None`を返す.
+    fn busy_duration(&self) -> Option<Duration> {
+        self.start_busy_time
+            .map(|t| self.clock.now().saturating_duration_since(t))
+    }
+
     fn check_overload(&mut self) -> Result<()> {
         if self.queue.len() < self.busy_threshold {
-            if self.start_busy_time.is_some() {
-                self.start_busy_time = None;
+            if let Some(start) = self.start_busy_time.take() {
+                self.status_watchers.notify(DeviceStatusEvent::Running {
+                    queue_len: self.queue.len(),
+                    busy_duration: self.clock.now().saturating_duration_since(start),
+                });
             }
-        } else if let Some(elapsed) = self.start_busy_time.map(|t| t.elapsed()) {
+        } else if let Some(elapsed) = self.busy_duration() {
             track_assert!(elapsed <= self.max_keep_busy_duration, ErrorKind::DeviceBusy;
                               elapsed, self.max_keep_busy_duration, self.busy_threshold);
         } else {
-            self.start_busy_time = Some(Instant::now());
+            self.start_busy_time = Some(self.clock.now());
+            self.status_watchers.notify(DeviceStatusEvent::Busy {
+                queue_len: self.queue.len(),
+                busy_threshold: self.busy_threshold,
+            });
+        }
+        Ok(())
+    }
+
+    /// コマンドの期限(`Deadline::At`)までに、その処理が完了する見込みがあるかどうかを検査する.
+    fn check_deadline_reachable(&self, command: &Command) -> Result<()> {
+        if !self.reject_unreachable_deadlines || matches!(*command, Command::Stop(_)) {
+            return Ok(());
+        }
+        if let Deadline::At(deadline) = command.deadline() {
+            let ahead = self.queue.count_until(command.deadline());
+            if let Some(estimated) = self.throughput.estimate(ahead + 1) {
+                let available = deadline.saturating_duration_since(self.clock.now());
+                track_assert!(estimated <= available, ErrorKind::DeadlineUnreachable;
+                              estimated, available, ahead);
+            }
         }
         Ok(())
     }
@@ -333,6 +1229,409 @@ where
     }
 }
 
+/// デバイスの進行状況.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// 何らかの処理が行われた.
+    Busy,
+
+    /// 処理すべきものがなかった.
+    ///
+    /// 新しいコマンドが届かない限り、保持している時刻までは処理が不要となる.
+    Idle(Instant),
+
+    /// デバイスが(正常ないし異常に)停止した.
+    Done,
+}
+
+/// 一単位ずつ処理を進めることが可能な、デバイスの実行タスク.
+///
+/// 専用のスレッドで実行される場合(`DeviceThread::spawn`)と、
+/// `DeviceRuntime`のワーカスレッドで、他のデバイスと多重化されて実行される場合がある.
+pub trait Task: Send {
+    /// 一単位分だけ処理を進める.
+    fn poll(&mut self) -> Progress;
+
+    /// `poll`中のパニック等によって処理を継続できなくなったタスクを、`error`で停止させる.
+    fn abort(&mut self, error: Error);
+}
+
+/// `Task`の実装.
+///
+/// 最初の`poll`呼び出し時に、ストレージの初期化を行う.
+#[derive(Debug)]
+pub struct DeviceTask<N, F>
+where
+    N: NonVolatileMemory + Send + 'static,
+{
+    metrics: DeviceMetrics,
+    monitored: Option<oneshot::Monitored<(), Error>>,
+    status_watchers: StatusWatchers,
+    state: TaskState<N, F>,
+}
+impl<N, F> DeviceTask<N, F>
+where
+    N: NonVolatileMemory + Send + 'static,
+    F: FnOnce() -> Result<Vec<(StorageKey, Storage<N>)>> + Send + 'static,
+{
+    /// 新しいタスクを生成する.
+    ///
+    /// `waker`が指定された場合には、多重化されて実行されるものとして扱われ、
+    /// コマンドの送信時には、それを使ってワーカスレッドが起こされる.
+    pub fn new(
+        builder: DeviceBuilder,
+        init_storages: F,
+        waker: Option<Arc<Waker>>,
+    ) -> (DeviceThreadHandle, DeviceThreadMonitor, Self) {
+        let metrics = DeviceMetrics::new(&builder.metrics);
+        metrics.status.set(f64::from(DeviceStatus::Starting as u8));
+
+        let (command_tx, command_rx) = std_mpsc::channel();
+        let (monitored, monitor) = oneshot::monitor();
+        let event_log = if builder.event_log_capacity > 0 {
+            Some(EventLog::new(builder.event_log_capacity))
+        } else {
+            None
+        };
+        let multiplexed = waker.is_some();
+        let status_watchers = StatusWatchers::new();
+        let handle = DeviceThreadHandle {
+            command_tx: command_tx.clone(),
+            metrics: Arc::new(metrics.clone()),
+            config: Arc::new(DeviceConfig::new(&builder)),
+            event_log: event_log.clone(),
+            waker,
+            status_watchers: status_watchers.clone(),
+        };
+        let task = DeviceTask {
+            metrics,
+            monitored: Some(monitored),
+            status_watchers,
+            state: TaskState::Starting(Box::new(Starting {
+                builder,
+                init_storages,
+                command_tx,
+                command_rx,
+                event_log,
+                multiplexed,
+            })),
+        };
+        (handle, DeviceThreadMonitor(monitor), task)
+    }
+
+    fn start(&mut self, starting: Starting<F>) -> Result<DeviceThread<N>> {
+        let Starting {
+            builder,
+            init_storages,
+            command_tx,
+            command_rx,
+            event_log,
+            multiplexed,
+        } = starting;
+        let metrics = &mut self.metrics;
+        info!(builder.logger, "Device configuration"; "config" => ?DeviceConfig::new(&builder));
+        let list = track!(init_storages())?;
+        track_assert!(!list.is_empty(), ErrorKind::InvalidInput, "No storage");
+        let mut storages = BTreeMap::new();
+        for (key, mut storage) in list {
+            if builder.background_gc_scan && !track!(storage.enable_background_gc_scan())? {
+                warn!(
+                    builder.logger,
+                    "Background GC scan is not supported by the NVM; falls back to the foreground scan";
+                    "storage" => key.as_u64()
+                );
+            }
+            if let Some(size) = builder.journal_gc_queue_size {
+                storage.set_journal_gc_queue_size(size);
+            }
+            if let Some(size) = builder.journal_gc_batch_size {
+                storage.set_journal_gc_batch_size(size);
+            }
+            if storage.is_direct_io_degraded() {
+                warn!(
+                    builder.logger,
+                    "Direct I/O is not used by the NVM; falls back to buffered I/O";
+                    "storage" => key.as_u64()
+                );
+                metrics.direct_io_degraded.set(1.0);
+            }
+            info!(
+                builder.logger,
+                "Storage configuration";
+                "storage" => key.as_u64(),
+                "config" => ?storage.config()
+            );
+            track_assert!(
+                storages.insert(key, storage).is_none(),
+                ErrorKind::InvalidInput,
+                "Duplicate storage key: {}",
+                key
+            );
+        }
+
+        // ストレージに関するメトリクスには、デフォルト(キーが最小)のストレージのものを使う
+        let storage = storages.values().next().expect("Never fails");
+        for (name, value) in storage.nvm_metric_labels() {
+            if let Err(e) = metrics.nvm_info.labels_mut().insert(name, &value) {
+                warn!(builder.logger, "Cannot set the NVM label: {}", e);
+            }
+        }
+        metrics.nvm_info.set(1.0);
+        metrics.storage = Some(storage.metrics().clone());
+        *metrics
+            .usage_summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) =
+            Some(storage.metrics().usage_summary().clone());
+        metrics.status.set(f64::from(DeviceStatus::Running as u8));
+        // LongQueuePolicy が RefuseNewRequests か Drop だったら、この後 run_once で使うため、dropper を作っておく。
+        // Stop の場合も実装を簡単にするためにプレイスホルダーの dropper を作る。
+        // 独自の dropper が登録されている場合には、そちらを使う。
+        let ratio = builder.long_queue_policy.ratio();
+        let logger = builder.logger.clone();
+        let dropper = match (&builder.dropper, builder.dropper_seed) {
+            (Some(factory), _) => factory.create(),
+            (None, Some(seed)) => Box::new(ProbabilisticDropper::with_seed(logger, ratio, seed)),
+            (None, None) => Box::new(ProbabilisticDropper::new(logger, ratio)),
+        };
+        let mut queue = DeadlineQueue::new();
+        queue.set_max_consecutive_writes(builder.max_consecutive_writes);
+        queue.set_clock(builder.clock.clone());
+        Ok(DeviceThread {
+            metrics: metrics.clone(),
+            queue,
+            storages,
+            failed_storages: BTreeMap::new(),
+            side_job_cursor: None,
+            idle_threshold: builder.idle_threshold,
+            max_queue_len: builder.max_queue_len,
+            max_keep_busy_duration: builder.max_keep_busy_duration,
+            busy_threshold: builder.busy_threshold,
+            start_busy_time: None,
+            command_tx,
+            command_rx,
+            logger: builder.logger,
+            long_queue_policy: builder.long_queue_policy,
+            dropper,
+            drop_exempt_commands: builder.drop_exempt_commands,
+            long_command: None,
+            long_command_slice_size: builder.long_command_slice_size,
+            interleaved_commands: 0,
+            max_side_job_duration: builder.max_side_job_duration,
+            last_side_job_at: builder.clock.now(),
+            side_job_between_slices: false,
+            deferred_replies: BTreeMap::new(),
+            event_log,
+            idempotency: IdempotencyCache::new(builder.idempotency_cache_size),
+            long_command_event: None,
+            multiplexed,
+            idle_since: None,
+            idle_until: None,
+            reject_unreachable_deadlines: builder.reject_unreachable_deadlines,
+            throughput: ThroughputEstimator::new(),
+            status_watchers: self.status_watchers.clone(),
+            clock: builder.clock,
+            next_command_id: 0,
+            running_command: None,
+            long_running_command: None,
+        })
+    }
+
+    fn finish(&mut self, result: Result<()>) {
+        self.metrics
+            .status
+            .set(f64::from(DeviceStatus::Stopped as u8));
+        self.metrics.nvm_info.set(0.0);
+        self.metrics.storage.take();
+        self.status_watchers.notify(DeviceStatusEvent::Stopped {
+            error: result.as_ref().err().map(|e| *e.kind()),
+        });
+        if let Some(monitored) = self.monitored.take() {
+            monitored.exit(result);
+        }
+    }
+}
+impl<N, F> Task for DeviceTask<N, F>
+where
+    N: NonVolatileMemory + Send + 'static,
+    F: FnOnce() -> Result<Vec<(StorageKey, Storage<N>)>> + Send + 'static,
+{
+    fn poll(&mut self) -> Progress {
+        let result = match mem::replace(&mut self.state, TaskState::Stopped) {
+            TaskState::Starting(starting) => track!(self.start(*starting)).map(|device| {
+                self.state = TaskState::Running(Box::new(device));
+                Progress::Busy
+            }),
+            TaskState::Running(mut device) => match track!(device.poll_once()) {
+                Ok(Progress::Done) => track!(device.close_storages()).map(|()| Progress::Done),
+                Ok(progress) => {
+                    self.state = TaskState::Running(device);
+                    Ok(progress)
+                }
+                Err(e) => Err(device.sync_storages_before_stop(e)),
+            },
+            TaskState::Stopped => return Progress::Done,
+        };
+        match result {
+            Ok(Progress::Done) => {
+                self.finish(Ok(()));
+                Progress::Done
+            }
+            Ok(progress) => progress,
+            Err(e) => {
+                self.finish(Err(e));
+                Progress::Done
+            }
+        }
+    }
+
+    fn abort(&mut self, error: Error) {
+        // 処理途中の状態は信頼できないので、ストレージのクローズ等は行わずに破棄する
+        self.state = TaskState::Stopped;
+        self.finish(Err(error));
+    }
+}
+
+#[derive(Debug)]
+enum TaskState<N, F>
+where
+    N: NonVolatileMemory + Send + 'static,
+{
+    Starting(Box<Starting<F>>),
+    Running(Box<DeviceThread<N>>),
+    Stopped,
+}
+
+/// 起動前のデバイスが保持する情報.
+struct Starting<F> {
+    builder: DeviceBuilder,
+    init_storages: F,
+    command_tx: CommandSender,
+    command_rx: CommandReceiver,
+    event_log: Option<EventLog>,
+    multiplexed: bool,
+}
+impl<F> fmt::Debug for Starting<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Starting")
+            .field("builder", &self.builder)
+            .field("multiplexed", &self.multiplexed)
+            .finish()
+    }
+}
+
+/// 処理中のコマンドの記録.
+#[derive(Debug)]
+struct PendingEvent {
+    trace: CommandTrace,
+    time: SystemTime,
+    start: Instant,
+    command: &'static str,
+    lump_id: Option<LumpId>,
+    failures: Counter,
+    failures_at_start: u64,
+}
+
+/// 複数回に分割して実行中のコマンド.
+///
+/// 各バリアントは、元のコマンド・未処理部分の位置・それまでに得られた結果、を保持する.
+///
+/// 分割実行の合間に処理されるのは、ストレージの状態を変更しないコマンドのみなので、
+/// `List`や`ListRange`の結果は、分割しない場合と同様に一貫したものとなる.
+/// `DeleteRange`も、範囲内のlumpは開始時点で全て参照不可能になるので、削除途中の状態が観測されることはない.
+#[derive(Debug)]
+enum LongCommand {
+    Put(PutLump, SlicedPut),
+    List(ListLump, Option<LumpId>, Vec<LumpId>),
+    ListRange(ListLumpRange, Range<LumpId>, Vec<LumpId>),
+    DeleteRange(DeleteLumpRange, SlicedDeleteRange),
+    Check(CheckStorage, StorageChecker),
+    JournalGc(JournalGc, SlicedJournalGc),
+}
+impl LongCommand {
+    /// コマンドの種類を表す名前を返す(`Command::name`と同様).
+    fn name(&self) -> &'static str {
+        match *self {
+            LongCommand::Put(..) => "put",
+            LongCommand::List(..) => "list",
+            LongCommand::ListRange(..) => "list_range",
+            LongCommand::DeleteRange(..) => "delete_range",
+            LongCommand::Check(..) => "check",
+            LongCommand::JournalGc(..) => "journal_gc",
+        }
+    }
+
+    /// 分割実行を中断して、元のコマンドにエラーを返答する.
+    fn reply_error(self, error: Error) {
+        match self {
+            LongCommand::Put(c, _) => c.reply(Err(error)),
+            LongCommand::List(c, ..) => c.reply(Err(error)),
+            LongCommand::ListRange(c, ..) => c.reply(Err(error)),
+            LongCommand::DeleteRange(c, ..) => c.reply(Err(error)),
+            LongCommand::Check(c, _) => c.reply(Err(error)),
+            LongCommand::JournalGc(c, _) => c.reply(Err(error)),
+        }
+    }
+
+    /// 一単位分の処理の合間に割り込ませることが可能なコマンドの最大数を返す.
+    ///
+    /// `PUT`の分割書き込みは、書き込みのレイテンシを抑えつつ進める必要があるため、
+    /// 合間に処理するのは(キューの先頭にある、最も優先度の高い)一つのコマンドのみとする.
+    fn max_interleaved_commands(&self) -> Option<usize> {
+        match *self {
+            LongCommand::Put(..) => Some(1),
+            _ => None,
+        }
+    }
+
+    /// 分割実行の合間に、サイドジョブ(`Storage::run_side_job_once`)を実行しても構わないかどうかを返す.
+    ///
+    /// サイドジョブはlumpの集合を変更しないので、lumpのIDのみを扱うコマンドは影響を受けない.
+    /// 一方で、割当済みの部分領域やジャーナルとインデックスとの対応を扱うコマンドでは、途中の状態が
+    /// (e.g., インデックスのシャドウファイルとして)書き出されたり、検査結果に影響したりする可能性があるため、許可しない.
+    fn allows_side_jobs(&self) -> bool {
+        matches!(
+            *self,
+            LongCommand::List(..) | LongCommand::ListRange(..) | LongCommand::JournalGc(..)
+        )
+    }
+}
+
+/// 一つのストレージに関して、ジャーナルの同期待ちのために保留されている応答群.
+#[derive(Debug)]
+struct DeferredReplies {
+    replies: Vec<DeferredReply>,
+    syncs_at_deferral: u64,
+    sync_deadline: Instant,
+}
+
+/// ジャーナルの同期が完了するまで、応答が保留されている書き込み系のコマンド.
+///
+/// 各バリアントは、元のコマンドと、同期完了時に返す結果を保持する.
+#[derive(Debug)]
+enum DeferredReply {
+    Put(PutLump, PutReport),
+    Delete(DeleteLump, bool),
+    DeleteRange(DeleteLumpRange, Vec<LumpId>),
+    DeleteMany(DeleteLumps, Vec<bool>),
+    Link(LinkLump, bool),
+    Rename(RenameLump, bool),
+}
+impl DeferredReply {
+    fn reply(self, sync_result: Result<()>) {
+        match self {
+            DeferredReply::Put(c, report) => {
+                c.reply(sync_result.map(|()| report.with_journal_synced()))
+            }
+            DeferredReply::Delete(c, deleted) => c.reply(sync_result.map(|()| deleted)),
+            DeferredReply::DeleteRange(c, ids) => c.reply(sync_result.map(|()| ids)),
+            DeferredReply::DeleteMany(c, deleted) => c.reply(sync_result.map(|()| deleted)),
+            DeferredReply::Link(c, linked) => c.reply(sync_result.map(|()| linked)),
+            DeferredReply::Rename(c, renamed) => c.reply(sync_result.map(|()| renamed)),
+        }
+    }
+}
+
 /// ストレージのデータが壊れている可能性があるエラーかどうかを判定.
 fn maybe_critical_error<T>(result: &Result<T>) -> Option<Error> {
     result.as_ref().err().and_then(|e| match *e.kind() {
@@ -364,16 +1663,33 @@ impl Future for DeviceThreadMonitor {
 pub struct DeviceThreadHandle {
     command_tx: CommandSender,
     metrics: Arc<DeviceMetrics>, // 必須では無いが`Clone`時の効率を上げるために`Arc`で囲む.
+    config: Arc<DeviceConfig>,
+    event_log: Option<EventLog>,
+    waker: Option<Arc<Waker>>,
+    status_watchers: StatusWatchers,
 }
 impl DeviceThreadHandle {
-    pub fn send_command(&self, command: Command) {
+    pub fn send_command(&self, storage: Option<StorageKey>, command: Command) {
         self.metrics.enqueued_commands.increment(&command);
-        if let Err(SendError(command)) = self.command_tx.send(command) {
+        if let Err(SendError((_, command))) = self.command_tx.send((storage, command)) {
             self.metrics.dequeued_commands.increment(&command);
             self.metrics.failed_commands.increment(&command);
+        } else if let Some(ref waker) = self.waker {
+            waker.wake();
         }
     }
     pub fn metrics(&self) -> &Arc<DeviceMetrics> {
         &self.metrics
     }
+    pub fn config(&self) -> &DeviceConfig {
+        &self.config
+    }
+    pub fn watch_status(&self) -> DeviceStatusWatch {
+        self.status_watchers.subscribe()
+    }
+    pub fn recent_events(&self) -> Vec<DeviceEvent> {
+        self.event_log
+            .as_ref()
+            .map_or_else(Vec::new, EventLog::events)
+    }
 }
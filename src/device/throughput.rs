@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+/// 指数移動平均の平滑化係数.
+const SMOOTHING_FACTOR: f64 = 0.1;
+
+/// 直近に処理されたコマンド群の処理時間から、デバイスのスループットを推定するためのオブジェクト.
+///
+/// 推定は、コマンド一つ当たりの処理時間の指数移動平均に基づいて行われる.
+#[derive(Debug, Default)]
+pub struct ThroughputEstimator {
+    average_seconds: Option<f64>,
+}
+impl ThroughputEstimator {
+    /// 新しい`ThroughputEstimator`インスタンスを生成する.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// コマンド一つの処理に要した時間を記録する.
+    pub fn observe(&mut self, elapsed: Duration) {
+        let x = elapsed.as_secs_f64();
+        self.average_seconds = Some(
+            self.average_seconds
+                .map_or(x, |avg| avg + SMOOTHING_FACTOR * (x - avg)),
+        );
+    }
+
+    /// `n`個のコマンドの処理に要する時間の見込みを返す.
+    ///
+    /// まだ一つも処理時間が記録されていない場合には`None`が返される.
+    pub fn estimate(&self, n: usize) -> Option<Duration> {
+        self.average_seconds
+            .map(|avg| Duration::from_secs_f64(avg * n as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throughput_estimator_works() {
+        let mut estimator = ThroughputEstimator::new();
+        assert_eq!(estimator.estimate(10), None);
+
+        estimator.observe(Duration::from_millis(10));
+        assert_eq!(estimator.estimate(0), Some(Duration::from_secs(0)));
+        assert_eq!(estimator.estimate(10), Some(Duration::from_millis(100)));
+
+        // 直近の値の影響は緩やかに反映される
+        estimator.observe(Duration::from_millis(110));
+        let estimated = estimator.estimate(1).unwrap();
+        assert!(Duration::from_millis(19) < estimated);
+        assert!(estimated < Duration::from_millis(21));
+    }
+}
@@ -1,9 +1,15 @@
-use std::{self, str::FromStr};
+#[cfg(not(cannyls_std))]
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+#[cfg(cannyls_std)]
 use trackable::error::ErrorKindExt;
 
 /// crate固有のエラー型.
+#[cfg(cannyls_std)]
 #[derive(Debug, Clone, TrackableError)]
 pub struct Error(trackable::error::TrackableError<ErrorKind>);
+#[cfg(cannyls_std)]
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
         if let Some(e) = e.get_ref().and_then(|e| e.downcast_ref::<Error>()).cloned() {
@@ -15,21 +21,71 @@ impl From<std::io::Error> for Error {
         }
     }
 }
+#[cfg(cannyls_std)]
 impl From<Error> for std::io::Error {
     fn from(e: Error) -> Self {
-        if *e.kind() == ErrorKind::InvalidInput {
+        if matches!(
+            *e.kind(),
+            ErrorKind::InvalidInput | ErrorKind::BlockSizeMismatch | ErrorKind::CapacityTooSmall
+        ) {
             std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
         } else {
-            std::io::Error::new(std::io::ErrorKind::Other, e)
+            std::io::Error::other(e)
         }
     }
 }
+#[cfg(cannyls_std)]
 impl<T> From<std::sync::PoisonError<T>> for Error {
     fn from(e: std::sync::PoisonError<T>) -> Self {
         ErrorKind::Other.cause(e.to_string()).into()
     }
 }
 
+/// crate固有のエラー型(`no_std`環境用).
+///
+/// `trackable`が利用できないため、エラーの種別と理由のみを保持し、追跡情報は記録しない.
+#[cfg(not(cannyls_std))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    reason: Option<String>,
+}
+#[cfg(not(cannyls_std))]
+impl Error {
+    /// エラーの種別を返す.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// エラーの理由を返す.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub(crate) fn with_reason(kind: ErrorKind, reason: String) -> Self {
+        Error {
+            kind,
+            reason: Some(reason),
+        }
+    }
+}
+#[cfg(not(cannyls_std))]
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error { kind, reason: None }
+    }
+}
+#[cfg(not(cannyls_std))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref reason) = self.reason {
+            write!(f, "{} ({})", self.kind, reason)
+        } else {
+            write!(f, "{}", self.kind)
+        }
+    }
+}
+
 /// 発生し得るエラーの種別.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
@@ -79,6 +135,26 @@ pub enum ErrorKind {
     /// - 利用者側のプログラムを修正して入力を正しくする
     InvalidInput,
 
+    /// NVMとストレージ(ないしデータ)のブロックサイズが適合しない.
+    ///
+    /// E.g., NVMのブロック境界に揃っていないブロックサイズで、ストレージを生成・オープンしようとした.
+    ///
+    /// エラーの理由には、適合しなかったそれぞれのブロックサイズが含まれる.
+    ///
+    /// # 典型的な対応策
+    ///
+    /// - 利用者側で、ストレージ(`StorageBuilder::block_size`)ないしNVMのブロックサイズを見直す
+    BlockSizeMismatch,
+
+    /// NVMの容量が、ストレージの生成に必要なサイズに満たない.
+    ///
+    /// エラーの理由には、NVMの容量と必要なサイズが含まれる.
+    ///
+    /// # 典型的な対応策
+    ///
+    /// - より大きな容量のNVMを使用する
+    CapacityTooSmall,
+
     /// 内部状態が不整合に陥っている.
     ///
     /// プログラムにバグがあることを示している.
@@ -102,6 +178,36 @@ pub enum ErrorKind {
     /// - 負荷の高い時間を避けてもう一度試す
     RequestRefused,
 
+    /// リクエストに指定された期限(`Deadline::At`)までに、処理を開始できなかった.
+    ///
+    /// # 典型的な対応策
+    ///
+    /// - 期限を延ばしてもう一度試す
+    /// - 上位のレイヤーで、リクエスト全体を失敗として扱う
+    DeadlineExceeded,
+
+    /// リクエストに指定された期限(`Deadline::At`)までに、処理を完了できる見込みがないため、
+    /// キューに追加されずに拒否された.
+    ///
+    /// `DeviceBuilder::reject_unreachable_deadlines`が有効な場合にのみ発生する.
+    ///
+    /// # 典型的な対応策
+    ///
+    /// - 期限を延ばしてもう一度試す
+    /// - 負荷の低い別のデバイスに対してリクエストを発行する
+    DeadlineUnreachable,
+
+    /// ジャーナル領域の使用率が高く、かつ、GCが追記に追いついていないため、PUTが拒否された.
+    ///
+    /// `StorageBuilder::journal_admission_control`で`JournalAdmissionAction::Reject`が指定されている場合にのみ発生する.
+    /// PUTは実行されていない.
+    ///
+    /// # 典型的な対応策
+    ///
+    /// - 時間をおいて(GCが進むのを待って)もう一度試す
+    /// - 書き込みの流量を絞る
+    JournalGcLagging,
+
     /// その他エラー.
     ///
     /// E.g., I/Oエラー
@@ -112,19 +218,25 @@ pub enum ErrorKind {
     ///   - それでもダメなら、致命的な異常が発生していると判断
     Other,
 }
+#[cfg(cannyls_std)]
 impl trackable::error::ErrorKind for ErrorKind {}
 
-impl std::fmt::Display for ErrorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ErrorKind::StorageFull => write!(f, "StorageFull"),
             ErrorKind::StorageCorrupted => write!(f, "StorageCorrupted"),
             ErrorKind::DeviceBusy => write!(f, "DeviceBusy"),
             ErrorKind::DeviceTerminated => write!(f, "DeviceTerminated"),
             ErrorKind::InvalidInput => write!(f, "InvalidInput"),
+            ErrorKind::BlockSizeMismatch => write!(f, "BlockSizeMismatch"),
+            ErrorKind::CapacityTooSmall => write!(f, "CapacityTooSmall"),
             ErrorKind::InconsistentState => write!(f, "InconsistentState"),
             ErrorKind::RequestDropped => write!(f, "RequestDropped"),
             ErrorKind::RequestRefused => write!(f, "RequestRefused"),
+            ErrorKind::DeadlineExceeded => write!(f, "DeadlineExceeded"),
+            ErrorKind::DeadlineUnreachable => write!(f, "DeadlineUnreachable"),
+            ErrorKind::JournalGcLagging => write!(f, "JournalGcLagging"),
             ErrorKind::Other => write!(f, "Other"),
         }
     }
@@ -139,8 +251,13 @@ impl FromStr for ErrorKind {
             "DeviceBusy" => ErrorKind::DeviceBusy,
             "DeviceTerminated" => ErrorKind::DeviceTerminated,
             "InvalidInput" => ErrorKind::InvalidInput,
+            "BlockSizeMismatch" => ErrorKind::BlockSizeMismatch,
+            "CapacityTooSmall" => ErrorKind::CapacityTooSmall,
             "RequestDropped" => ErrorKind::RequestDropped,
             "RequestRefused" => ErrorKind::RequestRefused,
+            "DeadlineExceeded" => ErrorKind::DeadlineExceeded,
+            "DeadlineUnreachable" => ErrorKind::DeadlineUnreachable,
+            "JournalGcLagging" => ErrorKind::JournalGcLagging,
             "InconsistentState" => ErrorKind::InconsistentState,
             "Other" => ErrorKind::Other,
             _ => return Err(()),
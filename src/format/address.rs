@@ -1,4 +1,4 @@
-use std::ops::{Add, Sub};
+use core::ops::{Add, Sub};
 
 /// ストレージ内のアドレス表現に使われている40bit幅の整数値.
 ///
@@ -28,6 +28,12 @@ impl Address {
         }
     }
 }
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Address {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Address(u.int_in_range(0..=Self::MAX)?))
+    }
+}
 impl From<u32> for Address {
     fn from(from: u32) -> Self {
         Address(u64::from(from))
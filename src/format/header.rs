@@ -0,0 +1,483 @@
+use alloc::string::String;
+use uuid::Uuid;
+
+use crate::build_info::CrateVersion;
+use crate::format::io::{self, ReadBytes, WriteBytes};
+use crate::format::{
+    BlockSize, JournalChecksum, MAGIC_NUMBER, MAJOR_VERSION, MAX_DATA_REGION_SIZE,
+    MAX_JOURNAL_REGION_SIZE, MAX_LOGICAL_NAME_LEN, MINOR_VERSION,
+};
+use crate::{ErrorKind, Result};
+
+/// ヘッダを表現するのに必要なバイト数.
+pub(crate) const HEADER_SIZE: u16 =
+    2 /* major_version */ +
+    2 /* minor_version */ +
+    2 /* block_size */ +
+    16 /* UUID */ +
+    8 /* journal_region_size */ +
+    8 /* data_region_size */ +
+    1 /* journal_checksum */ +
+    1 /* journal_header_slots */ +
+    1 /* backup_header */ +
+    6 /* writer_version */ +
+    1 /* logical_name length */ +
+    MAX_LOGICAL_NAME_LEN as u16 /* logical_name */ +
+    1 /* sparse_lumps */ +
+    1 /* creation_params flag */ +
+    8 /* creation_params.journal_region_ratio */ +
+    8 /* creation_params.journal_gc_queue_size */ +
+    8 /* creation_params.journal_sync_interval */;
+
+/// **マジックナンバー** と **ヘッダサイズ** も含めたサイズ.
+pub(crate) const FULL_HEADER_SIZE: u16 = 4 + 2 + HEADER_SIZE;
+
+/// ヘッダを書き込んだクレートのバージョンが不明であることを表す値.
+const UNKNOWN_WRITER_VERSION: CrateVersion = CrateVersion {
+    major: 0,
+    minor: 0,
+    patch: 0,
+};
+
+/// ストレージのヘッダ情報.
+///
+/// # 参考
+///
+/// - [ストレージフォーマット(v1.0)][format]
+///
+/// [format]: https://github.com/frugalos/cannyls/wiki/Storage-Format
+#[derive(Debug, Clone)]
+pub struct StorageHeader {
+    /// メジャーバージョン.
+    ///
+    /// メジャーバージョンが異なるストレージ同士のデータ形式には互換性が無い.
+    ///
+    /// 現在の最新バージョンは[`MAJOR_VERSION`](./constant.MAJOR_VERSION.html).
+    pub major_version: u16,
+
+    /// マイナーバージョン.
+    ///
+    /// マイナーバージョンには、後方互換性がある
+    /// (i.e., 古い形式で作成されたストレージを、新しいプログラムで扱うことが可能).
+    ///
+    /// 現在の最新バージョンは[`MINOR_VERSION`](./constant.MINOR_VERSION.html).
+    pub minor_version: u16,
+
+    /// ストレージから読み込まれた時点でのマイナーバージョン.
+    ///
+    /// オープン時にマイナーバージョンが最新のものに更新された場合(`StorageBuilder::upgrade_minor_version`)には、
+    /// `minor_version`とは異なり、更新前の値を保持している.
+    ///
+    /// この値はストレージには書き込まれず、新規作成時には`minor_version`と等しくなる.
+    pub original_minor_version: u16,
+
+    /// ストレージのブロックサイズ.
+    pub block_size: BlockSize,
+
+    /// ストレージの特定のインスタンスを識別するためのUUID.
+    pub instance_uuid: Uuid,
+
+    /// ジャーナル領域のサイズ(バイト単位).
+    pub journal_region_size: u64,
+
+    /// データ領域のサイズ(バイト単位).
+    pub data_region_size: u64,
+
+    /// ジャーナルレコードのチェックサムの計算に用いるアルゴリズム.
+    ///
+    /// バージョン`1.4`より前に作成されたストレージでは、常に`JournalChecksum::Adler32`となる.
+    pub journal_checksum: JournalChecksum,
+
+    /// ジャーナルのヘッダ用のスロット(ブロック)の数.
+    ///
+    /// `2`の場合には、ヘッダは二つのスロットに交互に書き込まれ、
+    /// 読み込み時には有効なものの内で最新のものが使用される.
+    /// これにより、ヘッダの書き込み中にクラッシュした場合でも、ジャーナルを復元可能となる.
+    ///
+    /// バージョン`1.5`より前に作成されたストレージでは、常に`1`となる.
+    pub journal_header_slots: u8,
+
+    /// ストレージの末尾に、ヘッダのバックアップが格納されているかどうか.
+    ///
+    /// `true`の場合には、データ領域の後ろに **バックアップヘッダ領域** が配置され、
+    /// 先頭のヘッダ領域と同じ内容が書き込まれる.
+    /// 先頭のヘッダが壊れている場合には、オープン時にバックアップが代わりに使用される.
+    ///
+    /// バージョン`1.6`より前に作成されたストレージでは、常に`false`となる.
+    pub backup_header: bool,
+
+    /// ヘッダを書き込んだクレートのバージョン.
+    ///
+    /// ヘッダはストレージの作成時と、オープン時のマイナーバージョンの更新時に書き込まれるため、
+    /// 障害調査時に、ストレージがどのバージョンのプログラムによって作成(ないし更新)されたかを特定するのに利用できる.
+    ///
+    /// バージョン`1.7`より前に書き込まれたヘッダでは、常に`None`となる.
+    pub writer_version: Option<CrateVersion>,
+
+    /// ストレージの論理名(e.g., クラスタIDやサーバ内のスロット名).
+    ///
+    /// オープン時に期待する名前を指定することで、誤ったディスクのストレージを使用してしまうことを防ぐことができる.
+    /// 詳細は`StorageBuilder::logical_name`を参照のこと.
+    ///
+    /// 名前の長さは最大で[`MAX_LOGICAL_NAME_LEN`](./constant.MAX_LOGICAL_NAME_LEN.html)バイト.
+    /// 名前が付与されていないストレージ、およびバージョン`1.7`より前に書き込まれたヘッダでは、常に`None`となる.
+    pub logical_name: Option<String>,
+
+    /// データ領域に、ゼロブロックを省いた疎な形式でlumpデータを格納するかどうか.
+    ///
+    /// 詳細は`StorageBuilder::sparse_lumps`を参照のこと.
+    ///
+    /// バージョン`1.7`より前に作成されたストレージでは、常に`false`となる.
+    pub sparse_lumps: bool,
+
+    /// ストレージの作成時に使用されたパラメータ.
+    ///
+    /// 情報提供のみを目的としたもので、オープン時の挙動には影響しない.
+    /// 運用者が、既存のストレージが元々どのような設定で作成されたのかを確認するために利用できる.
+    ///
+    /// バージョン`1.8`より前に作成されたストレージでは、
+    /// (マイナーバージョンの更新後であっても)常に`None`となる.
+    pub creation_params: Option<StorageCreationParams>,
+}
+impl StorageHeader {
+    /// ストレージが使用する領域全体のサイズを返す.
+    ///
+    /// 内訳としては **ヘッダ領域** と **ジャーナル領域** 、 **データ領域** 、
+    /// **バックアップヘッダ領域** のサイズの合計となる.
+    pub fn storage_size(&self) -> u64 {
+        self.region_size()
+            + self.journal_region_size
+            + self.data_region_size
+            + self.backup_region_size()
+    }
+
+    /// バックアップヘッダ領域のサイズを返す.
+    ///
+    /// バックアップを持たないストレージの場合には`0`となる.
+    pub fn backup_region_size(&self) -> u64 {
+        if self.backup_header {
+            self.region_size()
+        } else {
+            0
+        }
+    }
+
+    /// バックアップヘッダ領域の開始位置(ストレージの先頭からのオフセット)を返す.
+    ///
+    /// バックアップを持たないストレージの場合には`None`となる.
+    pub fn backup_region_position(&self) -> Option<u64> {
+        if self.backup_header {
+            Some(self.region_size() + self.journal_region_size + self.data_region_size)
+        } else {
+            None
+        }
+    }
+
+    /// ヘッダ領域のサイズを返す.
+    ///
+    /// **ヘッダ領域** には、以下が含まれる:
+    ///
+    /// - マジックナンバー
+    /// - ヘッダ長
+    /// - `StorageHeader`
+    /// - 領域のサイズをブロック境界に揃えるためのパディング
+    pub fn region_size(&self) -> u64 {
+        Self::calc_region_size(self.block_size)
+    }
+
+    /// ヘッダ情報を`reader`から読み込む.
+    pub fn read_from<R: ReadBytes>(mut reader: R) -> Result<Self> {
+        // magic number
+        let mut magic_number = [0; 4];
+        track!(reader.read_bytes(&mut magic_number))?;
+        track_assert_eq!(magic_number, MAGIC_NUMBER, ErrorKind::InvalidInput);
+
+        // header size
+        let header_size = track!(io::read_u16(&mut reader))?;
+
+        let mut reader = io::Take::new(reader, u64::from(header_size));
+
+        // versions
+        let major_version = track!(io::read_u16(&mut reader))?;
+        let minor_version = track!(io::read_u16(&mut reader))?;
+        track_assert_eq!(
+            major_version,
+            MAJOR_VERSION,
+            ErrorKind::InvalidInput,
+            "Unsupported major version",
+        );
+        track_assert!(
+            minor_version <= MINOR_VERSION,
+            ErrorKind::InvalidInput,
+            "Unsupported minor version: actual={}, supported={}",
+            minor_version,
+            MINOR_VERSION
+        );
+
+        // block_size
+        let block_size = track!(io::read_u16(&mut reader))?;
+        let block_size = track!(BlockSize::new(block_size), "block_size:{}", block_size)?;
+
+        // UUID
+        let mut instance_uuid = [0; 16];
+        track!(reader.read_bytes(&mut instance_uuid))?;
+        let instance_uuid = Uuid::from_bytes(instance_uuid);
+
+        // region sizes
+        let journal_region_size = track!(io::read_u64(&mut reader))?;
+        let data_region_size = track!(io::read_u64(&mut reader))?;
+        track_assert!(
+            journal_region_size <= MAX_JOURNAL_REGION_SIZE,
+            ErrorKind::InvalidInput,
+            "journal_region_size:{}",
+            journal_region_size
+        );
+        track_assert!(
+            data_region_size <= MAX_DATA_REGION_SIZE,
+            ErrorKind::InvalidInput,
+            "data_region_size:{}",
+            data_region_size
+        );
+
+        // チェックサムのアルゴリズム (古いヘッダには存在しない)
+        let journal_checksum = if reader.limit() == 0 {
+            JournalChecksum::Adler32
+        } else {
+            let n = track!(io::read_u8(&mut reader))?;
+            track!(JournalChecksum::from_u8(n))?
+        };
+
+        // ジャーナルのヘッダのスロット数 (古いヘッダには存在しない)
+        let journal_header_slots = if reader.limit() == 0 {
+            1
+        } else {
+            let n = track!(io::read_u8(&mut reader))?;
+            track_assert!(
+                n == 1 || n == 2,
+                ErrorKind::InvalidInput,
+                "journal_header_slots:{}",
+                n
+            );
+            n
+        };
+
+        // バックアップの有無 (古いヘッダには存在しない)
+        let backup_header = if reader.limit() == 0 {
+            false
+        } else {
+            let n = track!(io::read_u8(&mut reader))?;
+            track_assert!(n <= 1, ErrorKind::InvalidInput, "backup_header:{}", n);
+            n == 1
+        };
+
+        // ヘッダを書き込んだクレートのバージョン (古いヘッダには存在しない)
+        let writer_version = if reader.limit() == 0 {
+            None
+        } else {
+            let version = CrateVersion {
+                major: track!(io::read_u16(&mut reader))?,
+                minor: track!(io::read_u16(&mut reader))?,
+                patch: track!(io::read_u16(&mut reader))?,
+            };
+            Some(version).filter(|v| *v != UNKNOWN_WRITER_VERSION)
+        };
+
+        // ストレージの論理名 (古いヘッダには存在しない)
+        let logical_name = if reader.limit() == 0 {
+            None
+        } else {
+            let len = track!(io::read_u8(&mut reader))? as usize;
+            let mut buf = [0; MAX_LOGICAL_NAME_LEN];
+            track!(reader.read_bytes(&mut buf))?;
+            track_assert!(
+                len <= MAX_LOGICAL_NAME_LEN,
+                ErrorKind::InvalidInput,
+                "logical_name_len:{}",
+                len
+            );
+            if len == 0 {
+                None
+            } else {
+                match String::from_utf8(buf[..len].to_vec()) {
+                    Ok(name) => Some(name),
+                    Err(e) => track_panic!(ErrorKind::InvalidInput, "logical_name:{}", e),
+                }
+            }
+        };
+
+        // 疎な形式のlumpデータの使用有無 (古いヘッダには存在しない)
+        let sparse_lumps = if reader.limit() == 0 {
+            false
+        } else {
+            let n = track!(io::read_u8(&mut reader))?;
+            track_assert!(n <= 1, ErrorKind::InvalidInput, "sparse_lumps:{}", n);
+            n == 1
+        };
+
+        // 作成時のパラメータ (古いヘッダには存在しない)
+        let creation_params = if reader.limit() == 0 {
+            None
+        } else {
+            let flag = track!(io::read_u8(&mut reader))?;
+            let journal_region_ratio = f64::from_bits(track!(io::read_u64(&mut reader))?);
+            let journal_gc_queue_size = track!(io::read_u64(&mut reader))?;
+            let journal_sync_interval = track!(io::read_u64(&mut reader))?;
+            track_assert!(
+                flag <= 1,
+                ErrorKind::InvalidInput,
+                "creation_params:{}",
+                flag
+            );
+            if flag == 0 {
+                None
+            } else {
+                track_assert!(
+                    (0.0..=1.0).contains(&journal_region_ratio),
+                    ErrorKind::InvalidInput,
+                    "journal_region_ratio:{}",
+                    journal_region_ratio
+                );
+                Some(StorageCreationParams {
+                    journal_region_ratio,
+                    journal_gc_queue_size: journal_gc_queue_size as usize,
+                    journal_sync_interval: journal_sync_interval as usize,
+                })
+            }
+        };
+
+        track_assert_eq!(reader.limit(), 0, ErrorKind::InvalidInput);
+        Ok(StorageHeader {
+            major_version,
+            minor_version,
+            original_minor_version: minor_version,
+            instance_uuid,
+            block_size,
+            journal_region_size,
+            data_region_size,
+            journal_checksum,
+            journal_header_slots,
+            backup_header,
+            writer_version,
+            logical_name,
+            sparse_lumps,
+            creation_params,
+        })
+    }
+
+    /// ヘッダ情報を`writer`に書き込む.
+    pub fn write_to<W: WriteBytes>(&self, mut writer: W) -> Result<()> {
+        track!(writer.write_bytes(&MAGIC_NUMBER[..]))?;
+        track!(io::write_u16(&mut writer, HEADER_SIZE))?;
+        track!(io::write_u16(&mut writer, self.major_version))?;
+        track!(io::write_u16(&mut writer, self.minor_version))?;
+        track!(io::write_u16(&mut writer, self.block_size.as_u16()))?;
+        track!(writer.write_bytes(self.instance_uuid.as_bytes()))?;
+        track!(io::write_u64(&mut writer, self.journal_region_size))?;
+        track!(io::write_u64(&mut writer, self.data_region_size))?;
+        track!(io::write_u8(&mut writer, self.journal_checksum.as_u8()))?;
+        track!(io::write_u8(&mut writer, self.journal_header_slots))?;
+        track!(io::write_u8(&mut writer, self.backup_header as u8))?;
+
+        let version = self.writer_version.unwrap_or(UNKNOWN_WRITER_VERSION);
+        track!(io::write_u16(&mut writer, version.major))?;
+        track!(io::write_u16(&mut writer, version.minor))?;
+        track!(io::write_u16(&mut writer, version.patch))?;
+
+        let name = self
+            .logical_name
+            .as_ref()
+            .map_or(&[][..], |name| name.as_bytes());
+        track_assert!(
+            name.len() <= MAX_LOGICAL_NAME_LEN,
+            ErrorKind::InvalidInput,
+            "Too long logical name: {} bytes (max={})",
+            name.len(),
+            MAX_LOGICAL_NAME_LEN
+        );
+        let mut buf = [0; MAX_LOGICAL_NAME_LEN];
+        buf[..name.len()].copy_from_slice(name);
+        track!(io::write_u8(&mut writer, name.len() as u8))?;
+        track!(writer.write_bytes(&buf))?;
+        track!(io::write_u8(&mut writer, self.sparse_lumps as u8))?;
+
+        let params = self.creation_params.unwrap_or_default();
+        track!(io::write_u8(
+            &mut writer,
+            self.creation_params.is_some() as u8
+        ))?;
+        track!(io::write_u64(
+            &mut writer,
+            params.journal_region_ratio.to_bits()
+        ))?;
+        track!(io::write_u64(
+            &mut writer,
+            params.journal_gc_queue_size as u64
+        ))?;
+        track!(io::write_u64(
+            &mut writer,
+            params.journal_sync_interval as u64
+        ))?;
+        Ok(())
+    }
+
+    /// 指定されたブロックサイズを有するストレージのために必要な、ヘッダ領域のサイズを計算する.
+    pub(crate) fn calc_region_size(block_size: BlockSize) -> u64 {
+        block_size.ceil_align(u64::from(FULL_HEADER_SIZE))
+    }
+}
+
+/// ストレージの作成時に使用されたパラメータ.
+///
+/// `StorageHeader::creation_params`としてヘッダに記録される.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StorageCreationParams {
+    /// ジャーナル領域の割合(`StorageBuilder::journal_region_ratio`).
+    pub journal_region_ratio: f64,
+
+    /// ジャーナルのGCキューのサイズ(`StorageBuilder::journal_gc_queue_size`).
+    pub journal_gc_queue_size: usize,
+
+    /// ジャーナルの同期間隔(`StorageBuilder::journal_sync_interval`).
+    pub journal_sync_interval: usize,
+}
+
+/// 読み込み時にエラーとならない(i.e., `write_to`と`read_from`で往復可能な)ヘッダを生成する.
+///
+/// メジャーバージョンは常に`MAJOR_VERSION`となり、マイナーバージョンは`MINOR_VERSION`以下の値となる.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for StorageHeader {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let minor_version = u.int_in_range(0..=MINOR_VERSION)?;
+        Ok(StorageHeader {
+            major_version: MAJOR_VERSION,
+            minor_version,
+            original_minor_version: minor_version,
+            block_size: u.arbitrary()?,
+            instance_uuid: Uuid::from_bytes(u.arbitrary()?),
+            journal_region_size: u.int_in_range(0..=MAX_JOURNAL_REGION_SIZE)?,
+            data_region_size: u.int_in_range(0..=MAX_DATA_REGION_SIZE)?,
+            journal_checksum: u.arbitrary()?,
+            journal_header_slots: u.int_in_range(1..=2)?,
+            backup_header: u.arbitrary()?,
+            writer_version: u
+                .arbitrary::<Option<CrateVersion>>()?
+                .filter(|v| *v != UNKNOWN_WRITER_VERSION),
+            logical_name: u.arbitrary::<Option<String>>()?.and_then(|mut name| {
+                while name.len() > MAX_LOGICAL_NAME_LEN {
+                    name.pop();
+                }
+                Some(name).filter(|name| !name.is_empty())
+            }),
+            sparse_lumps: u.arbitrary()?,
+            creation_params: if u.arbitrary()? {
+                Some(StorageCreationParams {
+                    journal_region_ratio: f64::from(u.arbitrary::<u32>()?) / f64::from(u32::MAX),
+                    journal_gc_queue_size: u.arbitrary::<u32>()? as usize,
+                    journal_sync_interval: u.arbitrary::<u32>()? as usize,
+                })
+            } else {
+                None
+            },
+        })
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(not(cannyls_std))]
+use alloc::vec::Vec;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{ErrorKind, Result};
+
+/// フォーマット上の構造体(e.g., `JournalRecord`)の読み込み元.
+///
+/// 通常は、全ての`std::io::Read`の実装に対して実装されている.
+/// `format-only`フィーチャーが有効な場合には、バイト列(`&[u8]`)に対してのみ実装されている.
+pub trait ReadBytes {
+    /// `buf`の長さ分のバイト列を読み込む.
+    ///
+    /// # Errors
+    ///
+    /// 途中で入力が尽きた場合には、種類が`ErrorKind::Other`のエラーが返される.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()>;
+}
+
+/// フォーマット上の構造体(e.g., `JournalRecord`)の書き込み先.
+///
+/// 通常は、全ての`std::io::Write`の実装に対して実装されている.
+/// `format-only`フィーチャーが有効な場合には、`Vec<u8>`に対してのみ実装されている.
+pub trait WriteBytes {
+    /// `buf`の内容を全て書き込む.
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<()>;
+}
+
+#[cfg(cannyls_std)]
+impl<R: std::io::Read + ?Sized> ReadBytes for R {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        track_io!(self.read_exact(buf))
+    }
+}
+
+#[cfg(cannyls_std)]
+impl<W: std::io::Write + ?Sized> WriteBytes for W {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<()> {
+        track_io!(self.write_all(buf))
+    }
+}
+
+#[cfg(not(cannyls_std))]
+impl ReadBytes for &[u8] {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        track_assert!(
+            buf.len() <= self.len(),
+            ErrorKind::Other,
+            "Unexpected end of input: required={}, remaining={}",
+            buf.len(),
+            self.len()
+        );
+        let (head, tail) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+}
+
+#[cfg(not(cannyls_std))]
+impl<R: ReadBytes + ?Sized> ReadBytes for &mut R {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        (**self).read_bytes(buf)
+    }
+}
+
+#[cfg(not(cannyls_std))]
+impl WriteBytes for Vec<u8> {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<()> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(not(cannyls_std))]
+impl<W: WriteBytes + ?Sized> WriteBytes for &mut W {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<()> {
+        (**self).write_bytes(buf)
+    }
+}
+
+/// 読み込み可能なバイト数を制限するためのラッパー(`std::io::Take`に相当).
+pub(crate) struct Take<R> {
+    inner: R,
+    limit: u64,
+}
+impl<R: ReadBytes> Take<R> {
+    pub fn new(inner: R, limit: u64) -> Self {
+        Take { inner, limit }
+    }
+
+    /// 残りの読み込み可能なバイト数を返す.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+impl<R: ReadBytes> ReadBytes for Take<R> {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        track_assert!(
+            buf.len() as u64 <= self.limit,
+            ErrorKind::Other,
+            "Unexpected end of input: required={}, remaining={}",
+            buf.len(),
+            self.limit
+        );
+        track!(self.inner.read_bytes(buf))?;
+        self.limit -= buf.len() as u64;
+        Ok(())
+    }
+}
+
+pub(crate) fn read_u8<R: ReadBytes>(reader: &mut R) -> Result<u8> {
+    let mut buf = [0; 1];
+    track!(reader.read_bytes(&mut buf))?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u16<R: ReadBytes>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0; 2];
+    track!(reader.read_bytes(&mut buf))?;
+    Ok(BigEndian::read_u16(&buf))
+}
+
+pub(crate) fn read_u32<R: ReadBytes>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    track!(reader.read_bytes(&mut buf))?;
+    Ok(BigEndian::read_u32(&buf))
+}
+
+pub(crate) fn read_u64<R: ReadBytes>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    track!(reader.read_bytes(&mut buf))?;
+    Ok(BigEndian::read_u64(&buf))
+}
+
+pub(crate) fn read_u128<R: ReadBytes>(reader: &mut R) -> Result<u128> {
+    let mut buf = [0; 16];
+    track!(reader.read_bytes(&mut buf))?;
+    Ok(BigEndian::read_u128(&buf))
+}
+
+/// `nbytes`バイトの符号なし整数を読み込む.
+pub(crate) fn read_uint<R: ReadBytes>(reader: &mut R, nbytes: usize) -> Result<u64> {
+    let mut buf = [0; 8];
+    track!(reader.read_bytes(&mut buf[..nbytes]))?;
+    Ok(BigEndian::read_uint(&buf, nbytes))
+}
+
+pub(crate) fn write_u8<W: WriteBytes>(writer: &mut W, n: u8) -> Result<()> {
+    track!(writer.write_bytes(&[n]))
+}
+
+pub(crate) fn write_u16<W: WriteBytes>(writer: &mut W, n: u16) -> Result<()> {
+    track!(writer.write_bytes(&n.to_be_bytes()))
+}
+
+pub(crate) fn write_u32<W: WriteBytes>(writer: &mut W, n: u32) -> Result<()> {
+    track!(writer.write_bytes(&n.to_be_bytes()))
+}
+
+pub(crate) fn write_u64<W: WriteBytes>(writer: &mut W, n: u64) -> Result<()> {
+    track!(writer.write_bytes(&n.to_be_bytes()))
+}
+
+pub(crate) fn write_u128<W: WriteBytes>(writer: &mut W, n: u128) -> Result<()> {
+    track!(writer.write_bytes(&n.to_be_bytes()))
+}
+
+/// `n`を`nbytes`バイトの符号なし整数として書き込む.
+pub(crate) fn write_uint<W: WriteBytes>(writer: &mut W, n: u64, nbytes: usize) -> Result<()> {
+    let mut buf = [0; 8];
+    BigEndian::write_uint(&mut buf, n, nbytes);
+    track!(writer.write_bytes(&buf[..nbytes]))
+}
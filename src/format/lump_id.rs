@@ -0,0 +1,43 @@
+/// Lumpの識別子(128bit幅).
+#[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct LumpId(u128);
+impl LumpId {
+    /// 識別子のバイト幅.
+    pub const SIZE: usize = 16;
+
+    /// 識別子の最小値.
+    pub const MIN: LumpId = LumpId(0);
+
+    /// 識別子の最大値.
+    pub const MAX: LumpId = LumpId(u128::MAX);
+
+    /// 新しい`LumpId`インスタンスを生成する.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cannyls::lump::LumpId;
+    ///
+    /// assert_eq!(LumpId::new(0x12_3456).to_string(), "00000000000000000000000000123456");
+    ///
+    /// // 16進数文字列からも生成可能
+    /// assert_eq!("123456".parse::<LumpId>().unwrap(), LumpId::new(0x12_3456));
+    /// ```
+    pub fn new(id: u128) -> Self {
+        LumpId(id)
+    }
+
+    /// 識別子の値(128bit整数)を返す.
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+/// `no_std`環境では、識別子の表記を変換するための`lump::codec`が利用できないため、常に32桁の16進数で出力する.
+#[cfg(not(cannyls_std))]
+impl core::fmt::Debug for LumpId {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, r#"LumpId("{:032x}")"#, self.0)
+    }
+}
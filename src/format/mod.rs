@@ -0,0 +1,284 @@
+//! ストレージフォーマット("lusf")の構造体群のパースおよびシリアライズ.
+//!
+//! このモジュールは、ヘッダやジャーナルレコード等のフォーマット上の構造体と、
+//! それらをバイト列との間で変換するための関数群を、一箇所にまとめて提供する.
+//!
+//! ここで提供される型および関数群は、NVMや[`Storage`]、[`device`]モジュール(i.e., `futures`や`fibers`)には依存せず、
+//! 単なるバイト列のみを扱う.
+//! また、`format-only`フィーチャーを有効にした場合には、このクレートは`no_std`(要`alloc`)となり、
+//! このモジュールのみが利用可能となる.
+//! そのため、外部ツール(e.g., ダンプツールやファジングのハーネス)や組み込み環境から、
+//! デバイス関連の依存を引き込むことなく、lusfの構造体を直接パースすることが可能.
+//!
+//! `no_std`環境では、エラー型(`Error`)は種別と理由のみを保持する簡易なものとなり(`trackable`による追跡情報を持たない)、
+//! CRC-32Cの計算にはソフトウェア実装が使用される.
+//! それ以外の挙動(i.e., 読み書きされるバイト列)は、`format-only`フィーチャーの有無に依らず同一.
+//!
+//! 不正な入力に対しては、パニックではなく、常にエラーが返される.
+//!
+//! `arbitrary`フィーチャーを有効にすると、このモジュールの各構造体に`arbitrary::Arbitrary`が実装され、
+//! ファジングやプロパティテストの入力を簡潔に生成できるようになる.
+//!
+//! # Examples
+//!
+//! ```
+//! use cannyls::format::{self, JournalChecksum, JournalRecord, LumpId};
+//!
+//! let record = JournalRecord::Embed(LumpId::new(3), b"foo".to_vec());
+//! let bytes = format::encode_journal_record(&record, None, JournalChecksum::Crc32c).unwrap();
+//!
+//! let (decoded, size) =
+//!     format::decode_journal_record(&bytes, None, JournalChecksum::Crc32c).unwrap();
+//! assert_eq!(decoded, record);
+//! assert_eq!(size, bytes.len());
+//! ```
+//!
+//! [`Storage`]: ../storage/struct.Storage.html
+//! [`device`]: ../device/index.html
+pub use self::address::Address;
+pub use self::block_size::BlockSize;
+pub use self::header::{StorageCreationParams, StorageHeader};
+pub use self::io::{ReadBytes, WriteBytes};
+pub use self::lump_id::LumpId;
+pub use self::portion::DataPortion;
+pub use self::record::{JournalChecksum, JournalRecord};
+pub use crate::build_info::CrateVersion;
+
+use alloc::vec::Vec;
+
+use crate::Result;
+
+pub(crate) mod header;
+pub(crate) mod io;
+// 一部の定数(e.g., 監査レコードのタグ)は`storage`モジュールからのみ使用される
+#[cfg_attr(not(cannyls_std), allow(dead_code))]
+pub(crate) mod record;
+
+mod address;
+mod block_size;
+mod lump_id;
+mod portion;
+#[cfg(any(test, not(cannyls_std)))]
+mod soft_crc32c;
+
+/// ストレージの先頭に書き込まれるマジックナンバー.
+///
+/// "**LU**mp **S**torage **F**ormat"の略.
+pub const MAGIC_NUMBER: [u8; 4] = *b"lusf";
+
+/// ストレージフォーマットの現在のメジャーバージョン.
+///
+/// メジャーバージョンが異なるストレージ同士のデータ形式には互換性が無い.
+pub const MAJOR_VERSION: u16 = 1;
+
+/// ストレージフォーマットの現在のマイナーバージョン.
+///
+/// マイナーバージョンには、後方互換性がある.
+///
+/// バージョン`1.2`以降では、ジャーナルに長さ付きの拡張レコードが含まれる可能性がある.
+///
+/// バージョン`1.3`以降では、ジャーナルのレコードのチェックサムに周回の番号(エポック)が混ぜ込まれる可能性がある.
+///
+/// バージョン`1.4`以降では、ヘッダにジャーナルのレコードのチェックサムのアルゴリズムが記録される.
+///
+/// バージョン`1.5`以降では、ジャーナルのヘッダが二つのスロットに交互に書き込まれる可能性がある.
+///
+/// バージョン`1.6`以降では、ストレージの末尾にヘッダのバックアップが格納される可能性がある.
+///
+/// バージョン`1.7`以降では、ヘッダにそれを書き込んだクレートのバージョンと、ストレージの論理名が記録される.
+/// また、データ領域にゼロブロックを省いた疎な形式のlumpデータが格納される可能性がある.
+///
+/// バージョン`1.8`以降では、ヘッダにストレージの作成時のパラメータが記録される.
+pub const MINOR_VERSION: u16 = 8;
+
+/// ジャーナル領域の最大サイズ(バイト単位).
+///
+/// およそ1TB.
+pub const MAX_JOURNAL_REGION_SIZE: u64 = Address::MAX;
+
+/// データ領域の最大サイズ(バイト単位).
+///
+/// およそ512TB.
+pub const MAX_DATA_REGION_SIZE: u64 = Address::MAX * BlockSize::MIN as u64;
+
+/// ヘッダに記録可能な、ストレージの論理名の最大長(バイト単位).
+///
+/// 詳細は`StorageBuilder::logical_name`を参照のこと.
+pub const MAX_LOGICAL_NAME_LEN: usize = 64;
+
+/// バイト列の先頭から、ストレージのヘッダを読み込む.
+///
+/// `bytes`は、ストレージの先頭(i.e., マジックナンバーの位置)から始まっている必要がある.
+/// ヘッダ以降の部分は無視される.
+pub fn decode_storage_header(bytes: &[u8]) -> Result<StorageHeader> {
+    track!(StorageHeader::read_from(bytes))
+}
+
+/// ストレージのヘッダを、バイト列に変換する.
+///
+/// 結果は、マジックナンバーから始まり、ヘッダの終端で終わる
+/// (i.e., ブロック境界へのパディングは含まれない).
+pub fn encode_storage_header(header: &StorageHeader) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    track!(header.write_to(&mut bytes))?;
+    Ok(bytes)
+}
+
+/// バイト列の先頭から、ジャーナルレコードを一つ読み込む.
+///
+/// `epoch`には、レコードが書き込まれた時点の周回番号を指定する
+/// (周回番号を使用していないジャーナルの場合には`None`).
+/// `checksum`には、ヘッダ(`StorageHeader::journal_checksum`)に記録されているアルゴリズムを指定する.
+///
+/// 結果は、読み込まれたレコードと、その読み込みに消費されたバイト数のペア.
+pub fn decode_journal_record(
+    bytes: &[u8],
+    epoch: Option<u8>,
+    checksum: JournalChecksum,
+) -> Result<(JournalRecord<Vec<u8>>, usize)> {
+    let mut reader = bytes;
+    let record = track!(JournalRecord::read_from_with(&mut reader, epoch, checksum))?;
+    Ok((record, bytes.len() - reader.len()))
+}
+
+/// ジャーナルレコードを、バイト列に変換する.
+///
+/// `epoch`および`checksum`の意味は[`decode_journal_record`]と同様.
+///
+/// [`decode_journal_record`]: ./fn.decode_journal_record.html
+pub fn encode_journal_record<T: AsRef<[u8]>>(
+    record: &JournalRecord<T>,
+    epoch: Option<u8>,
+    checksum: JournalChecksum,
+) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(record.external_size());
+    track!(record.write_to_with(&mut bytes, epoch, checksum))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::lump::LumpId;
+    use crate::ErrorKind;
+
+    #[test]
+    fn storage_header_works() -> TestResult {
+        let header = StorageHeader {
+            major_version: MAJOR_VERSION,
+            minor_version: MINOR_VERSION,
+            original_minor_version: MINOR_VERSION,
+            block_size: BlockSize::min(),
+            instance_uuid: Uuid::new_v4(),
+            journal_region_size: 1024,
+            data_region_size: 4096,
+            journal_checksum: JournalChecksum::Crc32c,
+            journal_header_slots: 2,
+            backup_header: true,
+            writer_version: Some(crate::CrateVersion::CURRENT),
+            logical_name: Some("foo".to_owned()),
+            sparse_lumps: false,
+            creation_params: None,
+        };
+        let bytes = track!(encode_storage_header(&header))?;
+        assert_eq!(&bytes[..4], &MAGIC_NUMBER[..]);
+
+        let decoded = track!(decode_storage_header(&bytes))?;
+        assert_eq!(decoded.instance_uuid, header.instance_uuid);
+        assert_eq!(decoded.data_region_size, header.data_region_size);
+        assert_eq!(decoded.journal_checksum, header.journal_checksum);
+        assert!(decoded.backup_header);
+
+        // 途中で切れている
+        let e = decode_storage_header(&bytes[..bytes.len() - 1])
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::Other);
+
+        // マジックナンバーが異なる
+        let e = decode_storage_header(&bytes[1..]).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_record_works() -> TestResult {
+        let records = vec![
+            JournalRecord::Put(
+                LumpId::new(1),
+                DataPortion {
+                    start: Address::from(10),
+                    len: 3,
+                },
+            ),
+            JournalRecord::Embed(LumpId::new(2), b"foo".to_vec()),
+            JournalRecord::Delete(LumpId::new(3)),
+            JournalRecord::DeleteRange(LumpId::new(4)..LumpId::new(5)),
+            JournalRecord::GoToFront,
+            JournalRecord::EndOfRecords,
+        ];
+        let mut bytes = Vec::new();
+        for record in &records {
+            bytes.extend(track!(encode_journal_record(
+                record,
+                Some(7),
+                JournalChecksum::Crc32c
+            ))?);
+        }
+
+        let mut rest = &bytes[..];
+        for record in &records {
+            let (decoded, size) = track!(decode_journal_record(
+                rest,
+                Some(7),
+                JournalChecksum::Crc32c
+            ))?;
+            assert_eq!(&decoded, record);
+            rest = &rest[size..];
+        }
+        assert!(rest.is_empty());
+
+        // エポックが異なる
+        let e = decode_journal_record(&bytes, Some(8), JournalChecksum::Crc32c)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::StorageCorrupted);
+
+        // 途中で切れている
+        assert!(decode_journal_record(&bytes[..10], Some(7), JournalChecksum::Crc32c).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_values_round_trip() -> TestResult {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        // 疑似乱数列を入力として、生成された値が往復変換可能なことを確認する
+        let mut seed = 1u64;
+        let mut input = vec![0u8; 64 * 1024];
+        for b in &mut input {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            *b = (seed >> 56) as u8;
+        }
+        let mut u = Unstructured::new(&input);
+        while !u.is_empty() {
+            let record = JournalRecord::<Vec<u8>>::arbitrary(&mut u).unwrap();
+            let epoch = Option::<u8>::arbitrary(&mut u).unwrap();
+            let checksum = JournalChecksum::arbitrary(&mut u).unwrap();
+            let bytes = track!(encode_journal_record(&record, epoch, checksum))?;
+            let (decoded, size) = track!(decode_journal_record(&bytes, epoch, checksum))?;
+            assert_eq!(decoded, record);
+            assert_eq!(size, bytes.len());
+
+            let header = StorageHeader::arbitrary(&mut u).unwrap();
+            let bytes = track!(encode_storage_header(&header))?;
+            let decoded = track!(decode_storage_header(&bytes))?;
+            assert_eq!(track!(encode_storage_header(&decoded))?, bytes);
+        }
+        Ok(())
+    }
+}
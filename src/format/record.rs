@@ -0,0 +1,398 @@
+use adler32::RollingAdler32;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{BigEndian, ByteOrder};
+use core::ops::Range;
+#[cfg(cannyls_std)]
+use crc32c::crc32c_append;
+
+use crate::format::io::{self, ReadBytes, WriteBytes};
+#[cfg(not(cannyls_std))]
+use crate::format::soft_crc32c::crc32c_append;
+use crate::format::{Address, DataPortion, LumpId};
+use crate::{ErrorKind, Result};
+
+pub(crate) const TAG_SIZE: usize = 1;
+pub(crate) const CHECKSUM_SIZE: usize = 4;
+pub(crate) const LENGTH_SIZE: usize = 2;
+pub(crate) const PORTION_SIZE: usize = 5;
+pub(crate) const END_OF_RECORDS_SIZE: usize = CHECKSUM_SIZE + TAG_SIZE;
+pub(crate) const EMBEDDED_DATA_OFFSET: usize =
+    CHECKSUM_SIZE + TAG_SIZE + LumpId::SIZE + LENGTH_SIZE;
+
+const TAG_END_OF_RECORDS: u8 = 0;
+const TAG_GO_TO_FRONT: u8 = 1;
+const TAG_PUT: u8 = 3;
+const TAG_EMBED: u8 = 4;
+const TAG_DELETE: u8 = 5;
+const TAG_DELETE_RANGE: u8 = 6;
+
+/// 拡張レコード用のタグの下限.
+///
+/// このタグ以降のレコードは、タグの直後にペイロード長(2バイト)が付与された形式で書き込まれるため、
+/// 内容を解釈できない読み手でも、レコードの終端位置を知ることができる.
+pub(crate) const TAG_EXTENSION_MIN: u8 = 0x80;
+
+/// 単独の監査レコード(`AuditRecord`)用の拡張レコードのタグ.
+///
+/// インデックスの内容には影響を与えないため必須レコードではなく、監査レコードに対応していない読み手は、これを読み飛ばす.
+pub(crate) const TAG_AUDIT: u8 = TAG_EXTENSION_MIN;
+
+/// クリーンシャットダウンの印(`CleanShutdownRecord`)用の拡張レコードのタグ.
+///
+/// 必須レコードではないため、対応していない読み手は、これを読み飛ばす.
+pub(crate) const TAG_CLEAN_SHUTDOWN: u8 = TAG_AUDIT + 1;
+
+/// 上書き更新の印(`InPlacePutRecord`)用の拡張レコードのタグ.
+///
+/// インデックスの内容には影響を与えないため必須レコードではなく、対応していない読み手は、これを読み飛ばす.
+pub(crate) const TAG_IN_PLACE_PUT: u8 = TAG_CLEAN_SHUTDOWN + 1;
+
+/// 拡張レコードのタグに、このビットが立っている場合には「必須」レコードであることを示す.
+///
+/// 必須レコードを解釈できない読み手は、読み飛ばしを行わずにエラーとする必要がある.
+pub(crate) const TAG_ESSENTIAL_FLAG: u8 = 0x40;
+
+/// 重複排除付きのPUTレコード(`DedupPutRecord`)用の拡張レコードのタグ.
+///
+/// 読み飛ばされるとlumpが失われる上に、共有されている部分領域が誤って解放されかねないため、必須レコードとする.
+pub(crate) const TAG_DEDUP_PUT: u8 = TAG_EXTENSION_MIN | TAG_ESSENTIAL_FLAG;
+
+/// 別名作成レコード(`LinkRecord`)用の拡張レコードのタグ.
+///
+/// 重複排除付きのPUTレコードと同様の理由で、必須レコードとする.
+pub(crate) const TAG_LINK: u8 = TAG_DEDUP_PUT + 1;
+
+/// 改名レコード(`RenameRecord`)用の拡張レコードのタグ.
+///
+/// 読み飛ばされると、改名前のlumpが復活した上に、改名後のlumpが失われるため、必須レコードとする.
+pub(crate) const TAG_RENAME: u8 = TAG_LINK + 1;
+
+/// 埋め込みデータを持つlumpの改名レコード(`EmbeddedRenameRecord`)用の拡張レコードのタグ.
+///
+/// 改名レコードと同様の理由で、必須レコードとする.
+pub(crate) const TAG_EMBEDDED_RENAME: u8 = TAG_RENAME + 1;
+
+/// 監査付きのPUTレコード用の拡張レコードのタグ.
+///
+/// 通常のPUTレコードと同様にインデックスの内容を変更するため、必須レコードとする.
+pub(crate) const TAG_AUDITED_PUT: u8 = TAG_EMBEDDED_RENAME + 1;
+
+/// 監査付きのDELETEレコード用の拡張レコードのタグ.
+///
+/// 読み飛ばされると、削除されたlumpが復活するため、必須レコードとする.
+pub(crate) const TAG_AUDITED_DELETE: u8 = TAG_AUDITED_PUT + 1;
+
+/// ジャーナル領域のリングバッファに追記されていくレコード.
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum JournalRecord<T> {
+    EndOfRecords,
+    GoToFront,
+    Put(LumpId, DataPortion),
+    Embed(LumpId, T),
+    Delete(LumpId),
+    DeleteRange(Range<LumpId>),
+
+    /// 長さ付きの拡張レコード.
+    ///
+    /// 一つ目の要素はタグで、常に`0x80`以上の値となる.
+    /// 二つ目の要素はペイロード.
+    ///
+    /// 読み込み時に未知のタグを持つ拡張レコードに遭遇した場合には、
+    /// それが必須(タグの`0x40`ビットが立っている)でなければ、このバリアントとして読み込まれる.
+    /// 復元時には、このバリアントは単に無視される.
+    Extension(u8, T),
+}
+impl<T: AsRef<[u8]>> JournalRecord<T> {
+    /// 読み書き時のサイズ（バイト数）を返す.
+    pub(crate) fn external_size(&self) -> usize {
+        let record_size = match *self {
+            JournalRecord::EndOfRecords | JournalRecord::GoToFront => 0,
+            JournalRecord::Put(..) => LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE,
+            JournalRecord::Embed(_, ref data) => LumpId::SIZE + LENGTH_SIZE + data.as_ref().len(),
+            JournalRecord::Delete(..) => LumpId::SIZE,
+            JournalRecord::DeleteRange(..) => LumpId::SIZE * 2,
+            JournalRecord::Extension(_, ref payload) => LENGTH_SIZE + payload.as_ref().len(),
+        };
+        CHECKSUM_SIZE + TAG_SIZE + record_size
+    }
+
+    /// `writer`にレコードを書き込む.
+    #[cfg(test)]
+    pub(crate) fn write_to<W: WriteBytes>(&self, writer: W) -> Result<()> {
+        track!(self.write_to_with(writer, None, JournalChecksum::Adler32))
+    }
+
+    /// 周回番号(エポック)とチェックサムのアルゴリズムを指定して、`writer`にレコードを書き込む.
+    ///
+    /// エポックはレコード内には格納されず、チェックサムに混ぜ込まれる.
+    /// そのため、読み込み時には同じエポックおよびアルゴリズムを指定する必要がある.
+    pub(crate) fn write_to_with<W: WriteBytes>(
+        &self,
+        mut writer: W,
+        epoch: Option<u8>,
+        checksum: JournalChecksum,
+    ) -> Result<()> {
+        track!(io::write_u32(&mut writer, self.checksum(epoch, checksum)))?;
+        match *self {
+            JournalRecord::EndOfRecords => {
+                track!(io::write_u8(&mut writer, TAG_END_OF_RECORDS))?;
+            }
+            JournalRecord::GoToFront => {
+                track!(io::write_u8(&mut writer, TAG_GO_TO_FRONT))?;
+            }
+            JournalRecord::Put(ref lump_id, portion) => {
+                track!(io::write_u8(&mut writer, TAG_PUT))?;
+                track!(io::write_u128(&mut writer, lump_id.as_u128()))?;
+                track!(io::write_u16(&mut writer, portion.len))?;
+                track!(io::write_uint(
+                    &mut writer,
+                    portion.start.as_u64(),
+                    PORTION_SIZE
+                ))?;
+            }
+            JournalRecord::Embed(ref lump_id, ref data) => {
+                debug_assert!(data.as_ref().len() <= 0xFFFF);
+                track!(io::write_u8(&mut writer, TAG_EMBED))?;
+                track!(io::write_u128(&mut writer, lump_id.as_u128()))?;
+                track!(io::write_u16(&mut writer, data.as_ref().len() as u16))?;
+                track!(writer.write_bytes(data.as_ref()))?;
+            }
+            JournalRecord::Delete(ref lump_id) => {
+                track!(io::write_u8(&mut writer, TAG_DELETE))?;
+                track!(io::write_u128(&mut writer, lump_id.as_u128()))?;
+            }
+            JournalRecord::DeleteRange(ref range) => {
+                track!(io::write_u8(&mut writer, TAG_DELETE_RANGE))?;
+                track!(io::write_u128(&mut writer, range.start.as_u128()))?;
+                track!(io::write_u128(&mut writer, range.end.as_u128()))?;
+            }
+            JournalRecord::Extension(tag, ref payload) => {
+                debug_assert!(tag >= TAG_EXTENSION_MIN);
+                debug_assert!(payload.as_ref().len() <= 0xFFFF);
+                track!(io::write_u8(&mut writer, tag))?;
+                track!(io::write_u16(&mut writer, payload.as_ref().len() as u16))?;
+                track!(writer.write_bytes(payload.as_ref()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn checksum(&self, epoch: Option<u8>, checksum: JournalChecksum) -> u32 {
+        let mut hasher = Hasher::new(checksum);
+        match *self {
+            JournalRecord::EndOfRecords => {
+                hasher.update(TAG_END_OF_RECORDS);
+            }
+            JournalRecord::GoToFront => {
+                hasher.update(TAG_GO_TO_FRONT);
+            }
+            JournalRecord::Put(ref lump_id, portion) => {
+                hasher.update(TAG_PUT);
+                hasher.update_buffer(&lump_id_to_u128(lump_id)[..]);
+                let mut buf = [0; 7];
+                BigEndian::write_u16(&mut buf, portion.len);
+                BigEndian::write_uint(&mut buf[2..], portion.start.as_u64(), PORTION_SIZE);
+                hasher.update_buffer(&buf);
+            }
+            JournalRecord::Embed(ref lump_id, ref data) => {
+                debug_assert!(data.as_ref().len() <= 0xFFFF);
+                hasher.update(TAG_EMBED);
+                hasher.update_buffer(&lump_id_to_u128(lump_id)[..]);
+                let mut buf = [0; 2];
+                BigEndian::write_u16(&mut buf, data.as_ref().len() as u16);
+                hasher.update_buffer(&buf);
+                hasher.update_buffer(data.as_ref());
+            }
+            JournalRecord::Delete(ref lump_id) => {
+                hasher.update(TAG_DELETE);
+                hasher.update_buffer(&lump_id_to_u128(lump_id)[..]);
+            }
+            JournalRecord::DeleteRange(ref range) => {
+                hasher.update(TAG_DELETE_RANGE);
+                hasher.update_buffer(&lump_id_to_u128(&range.start)[..]);
+                hasher.update_buffer(&lump_id_to_u128(&range.end)[..]);
+            }
+            JournalRecord::Extension(tag, ref payload) => {
+                hasher.update(tag);
+                let mut buf = [0; 2];
+                BigEndian::write_u16(&mut buf, payload.as_ref().len() as u16);
+                hasher.update_buffer(&buf);
+                hasher.update_buffer(payload.as_ref());
+            }
+        }
+        hasher.finish() ^ epoch_mask(epoch)
+    }
+}
+impl JournalRecord<Vec<u8>> {
+    /// `reader`からレコードを読み込む.
+    #[cfg(test)]
+    pub(crate) fn read_from<R: ReadBytes>(reader: R) -> Result<Self> {
+        track!(Self::read_from_with(reader, None, JournalChecksum::Adler32))
+    }
+
+    /// 周回番号(エポック)とチェックサムのアルゴリズムを指定して、`reader`からレコードを読み込む.
+    ///
+    /// 書き込み時とは異なるエポックないしアルゴリズムが指定された場合には、チェックサムの検証に失敗する.
+    pub(crate) fn read_from_with<R: ReadBytes>(
+        mut reader: R,
+        epoch: Option<u8>,
+        checksum: JournalChecksum,
+    ) -> Result<Self> {
+        let expected = track!(io::read_u32(&mut reader))?;
+        let tag = track!(io::read_u8(&mut reader))?;
+        let record = match tag {
+            TAG_END_OF_RECORDS => JournalRecord::EndOfRecords,
+            TAG_GO_TO_FRONT => JournalRecord::GoToFront,
+            TAG_PUT => {
+                let lump_id = track!(read_lump_id(&mut reader))?;
+                let data_len = track!(io::read_u16(&mut reader))?;
+                let data_offset = track!(io::read_uint(&mut reader, PORTION_SIZE))?;
+                let portion = DataPortion {
+                    start: Address::from_u64(data_offset).unwrap(),
+                    len: data_len,
+                };
+                JournalRecord::Put(lump_id, portion)
+            }
+            TAG_EMBED => {
+                let lump_id = track!(read_lump_id(&mut reader))?;
+                let data_len = track!(io::read_u16(&mut reader))?;
+                let mut data = vec![0; data_len as usize];
+                track!(reader.read_bytes(&mut data))?;
+                JournalRecord::Embed(lump_id, data)
+            }
+            TAG_DELETE => {
+                let lump_id = track!(read_lump_id(&mut reader))?;
+                JournalRecord::Delete(lump_id)
+            }
+            TAG_DELETE_RANGE => {
+                let start = track!(read_lump_id(&mut reader))?;
+                let end = track!(read_lump_id(&mut reader))?;
+                JournalRecord::DeleteRange(Range { start, end })
+            }
+            _ if tag >= TAG_EXTENSION_MIN => {
+                track_assert!(
+                    tag & TAG_ESSENTIAL_FLAG == 0
+                        || tag == TAG_DEDUP_PUT
+                        || tag == TAG_LINK
+                        || tag == TAG_RENAME
+                        || tag == TAG_EMBEDDED_RENAME
+                        || tag == TAG_AUDITED_PUT
+                        || tag == TAG_AUDITED_DELETE,
+                    ErrorKind::StorageCorrupted,
+                    "Unsupported essential journal record: tag={}",
+                    tag
+                );
+                let payload_len = track!(io::read_u16(&mut reader))?;
+                let mut payload = vec![0; payload_len as usize];
+                track!(reader.read_bytes(&mut payload))?;
+                JournalRecord::Extension(tag, payload)
+            }
+            _ => track_panic!(
+                ErrorKind::StorageCorrupted,
+                "Unknown journal record tag: {}",
+                tag
+            ),
+        };
+        track_assert_eq!(
+            record.checksum(epoch, checksum),
+            expected,
+            ErrorKind::StorageCorrupted
+        );
+        Ok(record)
+    }
+}
+
+/// ジャーナルレコードのチェックサムの計算に用いるアルゴリズム.
+///
+/// どちらのアルゴリズムでも、チェックサムのサイズは4バイトとなる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum JournalChecksum {
+    /// Adler-32.
+    ///
+    /// バージョン`1.3`以前のストレージでは、常にこのアルゴリズムが使われている.
+    Adler32,
+
+    /// CRC-32C (Castagnoli).
+    ///
+    /// Adler-32よりも短いデータに対する誤り検出能力が高く、
+    /// 多くのCPUでハードウェア(e.g., SSE4.2)による高速化が効く.
+    ///
+    /// 新規に作成されるストレージでは、デフォルトでこのアルゴリズムが使われる.
+    #[default]
+    Crc32c,
+}
+impl JournalChecksum {
+    pub(crate) fn from_u8(n: u8) -> Result<Self> {
+        match n {
+            0 => Ok(JournalChecksum::Adler32),
+            1 => Ok(JournalChecksum::Crc32c),
+            _ => track_panic!(
+                ErrorKind::InvalidInput,
+                "Unknown journal checksum algorithm: {}",
+                n
+            ),
+        }
+    }
+
+    pub(crate) fn as_u8(self) -> u8 {
+        match self {
+            JournalChecksum::Adler32 => 0,
+            JournalChecksum::Crc32c => 1,
+        }
+    }
+}
+
+enum Hasher {
+    Adler32(RollingAdler32),
+    Crc32c(u32),
+}
+impl Hasher {
+    fn new(checksum: JournalChecksum) -> Self {
+        match checksum {
+            JournalChecksum::Adler32 => Hasher::Adler32(RollingAdler32::new()),
+            JournalChecksum::Crc32c => Hasher::Crc32c(0),
+        }
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.update_buffer(&[byte]);
+    }
+
+    fn update_buffer(&mut self, buf: &[u8]) {
+        match *self {
+            Hasher::Adler32(ref mut h) => h.update_buffer(buf),
+            Hasher::Crc32c(ref mut crc) => *crc = crc32c_append(*crc, buf),
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        match *self {
+            Hasher::Adler32(ref h) => h.hash(),
+            Hasher::Crc32c(crc) => crc,
+        }
+    }
+}
+
+/// チェックサムに混ぜ込むためのエポックのマスクを返す.
+///
+/// エポックが`0`ないし未指定の場合には、マスクは`0`となり、従来のチェックサムと一致する.
+/// 一方で、異なるエポックで書き込まれた(i.e., 前の周回の)レコードは、
+/// 内容が壊れていなくても、チェックサムの検証に必ず失敗するようになる.
+fn epoch_mask(epoch: Option<u8>) -> u32 {
+    u32::from(epoch.unwrap_or(0)) * 0x0101_0101
+}
+
+fn read_lump_id<R: ReadBytes>(reader: &mut R) -> Result<LumpId> {
+    let id = track!(io::read_u128(reader))?;
+    Ok(LumpId::new(id))
+}
+
+fn lump_id_to_u128(id: &LumpId) -> [u8; LumpId::SIZE] {
+    let mut bytes = [0; LumpId::SIZE];
+    BigEndian::write_u128(&mut bytes, id.as_u128());
+    bytes
+}
@@ -0,0 +1,59 @@
+//! CRC-32C (Castagnoli)のソフトウェア実装.
+//!
+//! `no_std`環境では`crc32c`クレートが利用できないため、その代わりに使用される.
+//! 結果は`crc32c::crc32c_append`と一致する.
+
+/// CRC-32Cの生成多項式(ビット反転表現).
+const POLYNOMIAL: u32 = 0x82F6_3B78;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// `crc`(それまでのデータのCRC値)に、`buf`の内容を追加した結果のCRC値を返す.
+pub fn crc32c_append(crc: u32, buf: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in buf {
+        crc = TABLE[((crc ^ u32::from(b)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(crc32c_append(0, b""), 0);
+        assert_eq!(crc32c_append(0, b"123456789"), 0xE306_9283);
+
+        // `crc32c`クレートの結果と一致する
+        let data = (0..1024).map(|i| (i * 7 + 3) as u8).collect::<Vec<_>>();
+        for len in [0, 1, 3, 15, 16, 17, 255, 1024] {
+            let expected = crc32c::crc32c_append(0, &data[..len]);
+            assert_eq!(crc32c_append(0, &data[..len]), expected);
+
+            let (head, tail) = data[..len].split_at(len / 2);
+            assert_eq!(crc32c_append(crc32c_append(0, head), tail), expected);
+        }
+    }
+}
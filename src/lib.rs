@@ -38,10 +38,23 @@
 //!   - 現時点では未実装だが、ブロックデバイスを直接操作する[NonVolatileMemory]実装を用意することで、
 //!     OS層を完全にバイパスすることも可能
 //!
+//! 典型的な用途で必要となる型群は[prelude]モジュールにまとめられている.
+//!
 //! # アーキテクチャの詳細
 //!
 //! [Wiki]を参照のこと。
 //!
+//! # `no_std`環境での利用
+//!
+//! `format-only`フィーチャー(デフォルトでは無効)を有効にした場合には、このクレートは`no_std`(要`alloc`)となり、
+//! ストレージフォーマットのパースおよびシリアライズを行う[`format`]モジュールのみが提供される.
+//! ただし、`std`を必要とするフィーチャー(e.g., `device`や`serde`)が同時に有効になっている場合には、このフィーチャーは無視される.
+//!
+//! ```toml
+//! [dependencies]
+//! cannyls = { version = "0.10", default-features = false, features = ["format-only"] }
+//! ```
+//!
 //! [lump]: ./lump/index.html
 //! [deadline]: ./deadline/index.html
 //! [device]: ./device/index.html
@@ -49,46 +62,115 @@
 //! [storage]: ./storage/index.html
 //! [Storage]: ./storage/struct.Storage.html
 //! [nvm]: ./nvm/index.html
+//! [prelude]: ./prelude/index.html
 //! [NonVolatileMemory]: ./nvm/trait.NonVolatileMemory.html
 //! [FileNvm]: ./nvm/struct.FileNvm.html
 //! [format]: https://github.com/frugalos/cannyls/wiki/Storage-Format
+//! [`format`]: ./format/index.html
 //! [Wiki]: https://github.com/frugalos/cannyls/wiki/
+#![cfg_attr(not(cannyls_std), no_std)]
 #![warn(missing_docs)]
 extern crate adler32;
+extern crate alloc;
 extern crate byteorder;
+#[cfg(cannyls_std)]
+extern crate crc32c;
 #[cfg(test)]
 extern crate fibers_global;
 #[cfg(feature = "device")]
 extern crate futures;
+#[cfg(cannyls_std)]
 extern crate libc;
+#[cfg(cannyls_std)]
 extern crate prometrics;
 #[cfg(test)]
 extern crate tempdir;
+#[cfg(cannyls_std)]
 #[macro_use]
 extern crate trackable;
 extern crate uuid;
+#[cfg(cannyls_std)]
 #[macro_use]
-#[cfg(feature = "device")]
 extern crate slog;
 
+pub use crate::build_info::CrateVersion;
+#[cfg(cannyls_std)]
+pub use crate::build_info::{build_info, BuildInfo};
 pub use crate::error::{Error, ErrorKind};
 
+#[cfg(cannyls_std)]
 macro_rules! track_io {
     ($expr:expr) => {
         $expr.map_err(|e: ::std::io::Error| track!(crate::Error::from(e)))
     };
 }
 
+// `no_std`環境では`trackable`が利用できないため、`format`モジュールが使用するマクロ群を、
+// 追跡情報を記録しない簡易な形で定義する.
+#[cfg(not(cannyls_std))]
+macro_rules! track {
+    ($target:expr) => {
+        $target
+    };
+    ($target:expr, $($arg:tt)*) => {
+        $target
+    };
+}
+
+#[cfg(not(cannyls_std))]
+macro_rules! track_panic {
+    ($kind:expr) => {
+        return Err(crate::Error::from($kind))
+    };
+    ($kind:expr, $($arg:tt)+) => {
+        return Err(crate::Error::with_reason($kind, alloc::format!($($arg)+)))
+    };
+}
+
+#[cfg(not(cannyls_std))]
+macro_rules! track_assert {
+    ($cond:expr, $kind:expr) => {
+        if !$cond {
+            track_panic!($kind, "assertion failed: `{}`", stringify!($cond))
+        }
+    };
+    ($cond:expr, $kind:expr, $($arg:tt)+) => {
+        if !$cond {
+            track_panic!($kind, $($arg)+)
+        }
+    };
+}
+
+#[cfg(not(cannyls_std))]
+macro_rules! track_assert_eq {
+    ($left:expr, $right:expr, $kind:expr) => {
+        track_assert!($left == $right, $kind)
+    };
+    ($left:expr, $right:expr, $kind:expr, $($arg:tt)+) => {
+        track_assert!($left == $right, $kind, $($arg)+)
+    };
+}
+
+#[cfg(cannyls_std)]
 pub mod block;
+#[cfg(cannyls_std)]
 pub mod deadline;
 #[cfg(feature = "device")]
 pub mod device;
+pub mod format;
+#[cfg(cannyls_std)]
 pub mod lump;
+#[cfg(cannyls_std)]
 pub mod metrics;
+#[cfg(cannyls_std)]
 pub mod nvm;
+#[cfg(cannyls_std)]
+pub mod prelude;
+#[cfg(cannyls_std)]
 pub mod storage;
 
+mod build_info;
 mod error;
 
 /// crate固有の`Result`型.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
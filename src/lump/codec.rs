@@ -0,0 +1,102 @@
+//! `LumpId`の表記を、利用側のアプリケーションに合わせて差し替えるための仕組み.
+//!
+//! デフォルトでは`LumpId`は32桁の16進数で表記されるが、それでは上位レイヤのオブジェクトとの対応が分かり難い.
+//! [`set_codec`]で[`LumpIdCodec`]の実装を登録すると、`LumpId`の`Display`および`Debug`の出力(e.g., ログ)と、
+//! `FromStr`による文字列からの変換に、その実装が使用されるようになる.
+//!
+//! 登録はプロセス全体で共有され、ストレージ上での`LumpId`の表現には一切影響しない.
+//! また実装が対象外とした識別子や文字列については、従来通りの16進数表記が使用される.
+//!
+//! # Examples
+//!
+//! ```
+//! use cannyls::lump::codec::{self, LumpIdCodec};
+//! use cannyls::lump::LumpId;
+//! use cannyls::Result;
+//!
+//! // 上位64bitをバケット番号、下位64bitをオブジェクト番号とみなす
+//! struct BucketCodec;
+//! impl LumpIdCodec for BucketCodec {
+//!     fn format(&self, lump_id: &LumpId) -> Option<String> {
+//!         let id = lump_id.as_u128();
+//!         Some(format!("{}:{}", id >> 64, id as u64))
+//!     }
+//!     fn parse(&self, s: &str) -> Result<Option<LumpId>> {
+//!         let mut tokens = s.splitn(2, ':');
+//!         match (tokens.next(), tokens.next()) {
+//!             (Some(bucket), Some(object)) => {
+//!                 let bucket: u64 = bucket.parse().map_err(|_| cannyls::ErrorKind::InvalidInput)?;
+//!                 let object: u64 = object.parse().map_err(|_| cannyls::ErrorKind::InvalidInput)?;
+//!                 Ok(Some(LumpId::new((u128::from(bucket) << 64) | u128::from(object))))
+//!             }
+//!             _ => Ok(None),
+//!         }
+//!     }
+//! }
+//!
+//! codec::set_codec(BucketCodec);
+//! let lump_id = LumpId::new((3 << 64) | 10);
+//! assert_eq!(lump_id.to_string(), "3:10");
+//! assert_eq!(format!("{:?}", lump_id), r#"LumpId("3:10")"#);
+//! assert_eq!("3:10".parse::<LumpId>().unwrap(), lump_id);
+//!
+//! // 16進数表記は常に利用可能
+//! assert_eq!(lump_id.hex().to_string(), "0000000000000003000000000000000a");
+//! assert_eq!("ab".parse::<LumpId>().unwrap(), LumpId::new(0xab));
+//!
+//! codec::clear_codec();
+//! assert_eq!(lump_id.to_string(), "0000000000000003000000000000000a");
+//! ```
+//!
+//! [`set_codec`]: ./fn.set_codec.html
+//! [`LumpIdCodec`]: ./trait.LumpIdCodec.html
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::lump::LumpId;
+use crate::Result;
+
+/// 現在登録されている`LumpIdCodec`.
+static CODEC: RwLock<Option<Arc<dyn LumpIdCodec>>> = RwLock::new(None);
+
+/// `LumpId`と、人間向けの文字列表記との相互変換を行うためのトレイト.
+pub trait LumpIdCodec: Send + Sync + 'static {
+    /// `lump_id`を文字列に変換する.
+    ///
+    /// `None`が返された場合には、デフォルトの16進数表記が使用される.
+    fn format(&self, lump_id: &LumpId) -> Option<String>;
+
+    /// 文字列を`LumpId`に変換する.
+    ///
+    /// `s`がこの実装の対象とする表記ではない場合には`Ok(None)`を返す.
+    /// その場合には、デフォルトの16進数表記として解釈される.
+    ///
+    /// 対象とする表記ではあるが内容が不正な場合には、エラーを返すこと.
+    fn parse(&self, s: &str) -> Result<Option<LumpId>>;
+}
+
+/// `LumpId`の表記に使用される`LumpIdCodec`を登録する.
+///
+/// 既に登録されているものがある場合には、それを置き換える.
+pub fn set_codec<C: LumpIdCodec>(codec: C) {
+    *CODEC.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(codec));
+}
+
+/// 登録されている`LumpIdCodec`を解除し、デフォルトの16進数表記に戻す.
+pub fn clear_codec() {
+    *CODEC.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+fn current() -> Option<Arc<dyn LumpIdCodec>> {
+    CODEC.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+pub(crate) fn format(lump_id: &LumpId) -> Option<String> {
+    current().and_then(|codec| codec.format(lump_id))
+}
+
+pub(crate) fn parse(s: &str) -> Result<Option<LumpId>> {
+    match current() {
+        None => Ok(None),
+        Some(codec) => track!(codec.parse(s)),
+    }
+}
@@ -0,0 +1,541 @@
+//! Lump関連のデータ構造群.
+//!
+//! "lump"とは、`cannyls`におけるデータの格納単位.
+//! 各lumpは「128bit幅のID」と「最大30MB程度のデータ(任意のバイト列)」から構成される.
+//!
+//! `cannyls`のレイヤでは、保存されているlumpの整合性の保証や検証は行わないため、
+//! 必要であれば、利用側で冗長化やチェックサム検証等を施す必要がある.
+use std::cmp;
+use std::fmt;
+use std::ops::{Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive};
+use std::str::FromStr;
+use trackable::error::ErrorKindExt;
+
+use crate::block::BlockSize;
+use crate::storage::DataRegionLumpData;
+use crate::{Error, ErrorKind, Result};
+
+pub use crate::format::LumpId;
+
+pub mod codec;
+pub mod sharding;
+
+impl LumpId {
+    /// 16進数表記の数値から`LumpId`を生成する.
+    ///
+    /// `FromStr`とは異なり、[`codec`]で登録された表記は考慮されない.
+    ///
+    /// [`codec`]: ./codec/index.html
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let id = track!(u128::from_str_radix(s, 16).map_err(|e| ErrorKind::InvalidInput.cause(e)))?;
+        Ok(LumpId::new(id))
+    }
+
+    /// 識別子を32桁の16進数で表記するオブジェクトを返す.
+    ///
+    /// `Display`とは異なり、[`codec`]で登録された表記は使用されない.
+    ///
+    /// [`codec`]: ./codec/index.html
+    pub fn hex(&self) -> impl fmt::Display {
+        HexLumpId(self.as_u128())
+    }
+}
+impl FromStr for LumpId {
+    type Err = Error;
+
+    /// 文字列から`LumpId`を生成する.
+    ///
+    /// [`codec`]で`LumpIdCodec`が登録されている場合には、まずその表記として解釈される.
+    /// それ以外の場合には、16進数表記の数値として扱われる.
+    ///
+    /// 16進数表記の数値は"128bit整数"として扱われ、先頭のゼロは省略可能（`"ab12"`と`"00ab12"`は等価）.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には、種類が`ErrorKind::InvalidInput`のエラーが返される:
+    ///
+    /// - 文字列が16進数表記の整数値を表していない
+    /// - 文字列の長さが32文字を超えている
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::str::{self, FromStr};
+    /// use cannyls::ErrorKind;
+    /// use cannyls::lump::LumpId;
+    ///
+    /// assert_eq!(LumpId::from_str("00ab12").ok(),
+    ///            Some(LumpId::new(0xab12)));
+    ///
+    /// assert_eq!(LumpId::from_str("foo_bar").err().map(|e| *e.kind()),
+    ///            Some(ErrorKind::InvalidInput));
+    ///
+    /// let large_input = str::from_utf8(&[b'a', 33][..]).unwrap();
+    /// assert_eq!(LumpId::from_str(large_input).err().map(|e| *e.kind()),
+    ///            Some(ErrorKind::InvalidInput));
+    /// ```
+    ///
+    /// [`codec`]: ./codec/index.html
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(lump_id) = track!(codec::parse(s))? {
+            return Ok(lump_id);
+        }
+        track!(LumpId::from_hex(s))
+    }
+}
+impl fmt::Debug for LumpId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, r#"LumpId("{}")"#, self)
+    }
+}
+impl fmt::Display for LumpId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(s) = codec::format(self) {
+            f.write_str(&s)
+        } else {
+            HexLumpId(self.as_u128()).fmt(f)
+        }
+    }
+}
+
+/// `LumpId::hex`の返り値.
+struct HexLumpId(u128);
+impl fmt::Display for HexLumpId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in (0..LumpId::SIZE).rev() {
+            let b = (self.0 >> (8 * i)) as u8;
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lumpの識別子の範囲.
+///
+/// `Range<LumpId>`とは異なり、終端を含む範囲も表現可能なため、
+/// `LumpId::MAX`を含む範囲も指定することができる.
+///
+/// `Range<LumpId>`や`RangeInclusive<LumpId>`等の標準の範囲オブジェクトからも変換可能.
+///
+/// # Examples
+///
+/// ```
+/// use cannyls::lump::{LumpId, LumpRange};
+///
+/// let range = LumpRange::inclusive(LumpId::new(1), LumpId::MAX);
+/// assert!(range.contains(&LumpId::MAX));
+/// assert!(!range.contains(&LumpId::new(0)));
+///
+/// let range = LumpRange::from(LumpId::new(1)..LumpId::new(3));
+/// assert!(range.contains(&LumpId::new(2)));
+/// assert!(!range.contains(&LumpId::new(3)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LumpRange {
+    start: LumpId,
+    end: LumpId,
+    end_inclusive: bool,
+}
+impl LumpRange {
+    /// 終端を含まない範囲`[start, end)`を生成する.
+    pub fn new(start: LumpId, end: LumpId) -> Self {
+        LumpRange {
+            start,
+            end: cmp::max(start, end),
+            end_inclusive: false,
+        }
+    }
+
+    /// 終端を含む範囲`[start, end]`を生成する.
+    ///
+    /// `start`が`end`よりも大きい場合には、空の範囲となる.
+    pub fn inclusive(start: LumpId, end: LumpId) -> Self {
+        if start <= end {
+            LumpRange {
+                start,
+                end,
+                end_inclusive: true,
+            }
+        } else {
+            LumpRange::new(start, start)
+        }
+    }
+
+    /// 全ての識別子を含む範囲を生成する.
+    pub fn full() -> Self {
+        LumpRange::inclusive(LumpId::MIN, LumpId::MAX)
+    }
+
+    /// 上位`bits`ビットが`prefix`と一致する識別子全体からなる範囲を生成する.
+    ///
+    /// `prefix`は下位`bits`ビットのみが使用される値として解釈される.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には、種類が`ErrorKind::InvalidInput`のエラーが返される:
+    ///
+    /// - `bits`が`128`を超えている
+    /// - `prefix`が`bits`ビットで表現できない
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cannyls::lump::{LumpId, LumpRange};
+    ///
+    /// let range = LumpRange::prefix(0xab, 8).unwrap();
+    /// assert!(range.contains(&"ab000000000000000000000000000000".parse().unwrap()));
+    /// assert!(range.contains(&"abffffffffffffffffffffffffffffff".parse().unwrap()));
+    /// assert!(!range.contains(&"ac000000000000000000000000000000".parse().unwrap()));
+    ///
+    /// assert_eq!(LumpRange::prefix(0, 0).unwrap(), LumpRange::full());
+    /// assert!(LumpRange::prefix(0x100, 8).is_err());
+    /// ```
+    pub fn prefix(prefix: u128, bits: u32) -> Result<Self> {
+        track_assert!(bits <= 128, ErrorKind::InvalidInput; bits);
+        if bits == 0 {
+            track_assert_eq!(prefix, 0, ErrorKind::InvalidInput; bits);
+            return Ok(LumpRange::full());
+        }
+        let shift = 128 - bits;
+        track_assert!(
+            prefix <= u128::MAX >> shift,
+            ErrorKind::InvalidInput,
+            "Too large prefix: prefix={:#x}, bits={}",
+            prefix,
+            bits
+        );
+        let start = prefix << shift;
+        let end = start | u128::MAX.checked_shr(bits).unwrap_or(0);
+        Ok(LumpRange::inclusive(LumpId::new(start), LumpId::new(end)))
+    }
+
+    /// 範囲の始端(範囲に含まれる)を返す.
+    pub fn start(&self) -> LumpId {
+        self.start
+    }
+
+    /// 範囲の終端を返す.
+    pub fn end(&self) -> Bound<LumpId> {
+        if self.end_inclusive {
+            Bound::Included(self.end)
+        } else {
+            Bound::Excluded(self.end)
+        }
+    }
+
+    /// 範囲が空かどうかを返す.
+    pub fn is_empty(&self) -> bool {
+        !self.end_inclusive && self.start == self.end
+    }
+
+    /// 指定された識別子が範囲に含まれるかどうかを返す.
+    pub fn contains(&self, lump_id: &LumpId) -> bool {
+        RangeBounds::contains(self, lump_id)
+    }
+
+    /// 終端はそのままに、始端を`start`に置き換えた範囲を返す.
+    pub(crate) fn with_start(&self, start: LumpId) -> Self {
+        if self.end_inclusive {
+            LumpRange::inclusive(start, self.end)
+        } else {
+            LumpRange::new(start, self.end)
+        }
+    }
+
+    /// 範囲を、終端を含まない`Range<LumpId>`と、`LumpId::MAX`を含むかどうかのフラグ、に分割する.
+    ///
+    /// `LumpId::MAX`以外の識別子は、全て返り値の`Range<LumpId>`側に含まれる.
+    pub(crate) fn split_max(&self) -> (Range<LumpId>, bool) {
+        if !self.end_inclusive {
+            (self.start..self.end, false)
+        } else if self.end < LumpId::MAX {
+            (self.start..LumpId::new(self.end.as_u128() + 1), false)
+        } else {
+            (self.start..LumpId::MAX, true)
+        }
+    }
+}
+impl RangeBounds<LumpId> for LumpRange {
+    fn start_bound(&self) -> Bound<&LumpId> {
+        Bound::Included(&self.start)
+    }
+    fn end_bound(&self) -> Bound<&LumpId> {
+        if self.end_inclusive {
+            Bound::Included(&self.end)
+        } else {
+            Bound::Excluded(&self.end)
+        }
+    }
+}
+impl From<Range<LumpId>> for LumpRange {
+    fn from(f: Range<LumpId>) -> Self {
+        LumpRange::new(f.start, f.end)
+    }
+}
+impl From<RangeInclusive<LumpId>> for LumpRange {
+    fn from(f: RangeInclusive<LumpId>) -> Self {
+        let (start, end) = f.into_inner();
+        LumpRange::inclusive(start, end)
+    }
+}
+impl From<RangeFrom<LumpId>> for LumpRange {
+    fn from(f: RangeFrom<LumpId>) -> Self {
+        LumpRange::inclusive(f.start, LumpId::MAX)
+    }
+}
+impl From<RangeFull> for LumpRange {
+    fn from(_: RangeFull) -> Self {
+        LumpRange::full()
+    }
+}
+
+/// Lumpのデータ.
+///
+/// 最大で`MAX_SIZE`までのバイト列を保持可能.
+#[derive(Clone)]
+pub struct LumpData(LumpDataInner);
+impl LumpData {
+    /// データの最大長（バイト単位）.
+    ///
+    /// 最小ブロックサイズを用いた場合に表現可能な最大サイズまでのデータが保持可能.
+    /// 最後の`-2`は、内部的に付与されるメタ情報のサイズ分.
+    ///
+    /// # 蛇足
+    ///
+    /// 現状は簡単のために、最小のブロックサイズに合わせた最大サイズ、となっている。
+    ///
+    /// ただし、仕組み上は、ストレージが採用したブロックサイズがそれよりも大きい場合には、
+    /// 保存可能なデータサイズを比例して大きくすることは可能.
+    ///
+    /// 一つ一つのlumpのサイズをあまり巨大にしてしまうと、
+    /// 一つのlumpの読み書き処理が全体のレイテンシを阻害してしまう可能性もあるので、
+    /// 現状くらいの制限でちょうど良いのではないかとも思うが、
+    /// もし最大サイズをどうしても上げたい場合には、それも不可能ではない、
+    /// ということは記しておく.
+    pub const MAX_SIZE: usize = 0xFFFF * (BlockSize::MIN as usize) - 2;
+
+    /// ジャーナル領域に埋め込み可能なデータの最大長（バイト単位）.
+    pub const MAX_EMBEDDED_SIZE: usize = 0xFFFF;
+
+    /// 引数で指定されたデータを保持する`LumpData`インスタンスを生成する.
+    ///
+    /// # 性能上の注意
+    ///
+    /// この関数で生成された`LumpData`インスタンスは、保存先ストレージのブロック境界に合わせた
+    /// アライメントが行われていないため、PUTによる保存時に必ず一度、
+    /// アライメント用にデータサイズ分のメモリコピーが発生してしまう.
+    ///
+    /// 保存時のコピーを避けたい場合には[`Storage`]ないし[`DeviceHandle`]が提供している
+    /// `allocate_lump_data_with_bytes`メソッドを使用して、
+    /// アライメント済みの`LumpData`インスタンスを生成すると良い.
+    ///
+    /// [`Storage`]: ../storage/struct.Storage.html
+    /// [`DeviceHandle`]: ../device/struct.DeviceHandle.html
+    ///
+    /// # Errors
+    ///
+    /// データのサイズが`MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(data: Vec<u8>) -> Result<Self> {
+        track_assert!(
+            data.len() <= LumpData::MAX_SIZE,
+            ErrorKind::InvalidInput,
+            "Too large lump data: {} bytes",
+            data.len()
+        );
+        Ok(LumpData(LumpDataInner::DataRegionUnaligned(data)))
+    }
+
+    /// ジャーナル領域埋め込み用の`LumpData`インスタンスを生成する.
+    ///
+    /// # Errors
+    ///
+    /// `data`の長さが`MAX_EMBEDDED_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
+    ///
+    pub fn new_embedded(data: Vec<u8>) -> Result<Self> {
+        track_assert!(
+            data.len() <= LumpData::MAX_EMBEDDED_SIZE,
+            ErrorKind::InvalidInput,
+            "Too large embedded lump data: {} bytes",
+            data.len()
+        );
+        Ok(LumpData(LumpDataInner::JournalRegion(data)))
+    }
+
+    /// データがジャーナル領域に埋め込み可能なサイズ(`MAX_EMBEDDED_SIZE`以下)かどうかを判定する.
+    ///
+    /// `new_embedded`と`new`のどちらでインスタンスを生成したかには依存しない.
+    pub fn fits_embedding(&self) -> bool {
+        self.as_bytes().len() <= LumpData::MAX_EMBEDDED_SIZE
+    }
+
+    /// データを表すバイト列への参照を返す.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.as_ref()
+    }
+
+    /// データを表すバイト列への破壊的な参照を返す.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.as_mut()
+    }
+
+    /// 所有権を放棄して、内部のバイト列を返す.
+    ///
+    /// なお、このインスタンスが確保しているのがアライメントされたメモリ領域の場合には、
+    /// それを通常の`Vec<u8>`に変換するためのメモリコピーが、本メソッド呼び出し時に発生することになる.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self.0 {
+            LumpDataInner::JournalRegion(d) => d,
+            LumpDataInner::DataRegion(d) => Vec::from(d.as_bytes()),
+            LumpDataInner::DataRegionUnaligned(d) => d,
+        }
+    }
+
+    /// アライメント済みの`LumpData`インスタンス用のメモリ領域を割り当てる.
+    ///
+    /// この関数によって割当てられたメモリ領域の初期値は未定義であり、
+    /// 利用者は事前にゼロ埋めされていることを仮定してはいけない.
+    ///
+    /// # Errors
+    ///
+    /// 指定されたサイズが`MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
+    pub(crate) fn aligned_allocate(data_len: usize, block_size: BlockSize) -> Result<Self> {
+        track_assert!(
+            data_len <= LumpData::MAX_SIZE,
+            ErrorKind::InvalidInput,
+            "Too large lump data: {} bytes",
+            data_len
+        );
+        Ok(LumpData::from_data_region(DataRegionLumpData::new(
+            data_len, block_size,
+        )))
+    }
+
+    pub(crate) fn as_inner(&self) -> &LumpDataInner {
+        &self.0
+    }
+
+    pub(crate) fn from_data_region(data: DataRegionLumpData) -> Self {
+        LumpData(LumpDataInner::DataRegion(data))
+    }
+}
+impl AsRef<[u8]> for LumpData {
+    fn as_ref(&self) -> &[u8] {
+        match self.0 {
+            LumpDataInner::JournalRegion(ref d) => d,
+            LumpDataInner::DataRegion(ref d) => d.as_bytes(),
+            LumpDataInner::DataRegionUnaligned(ref d) => d,
+        }
+    }
+}
+impl AsMut<[u8]> for LumpData {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match self.0 {
+            LumpDataInner::JournalRegion(ref mut d) => d,
+            LumpDataInner::DataRegion(ref mut d) => d.as_bytes_mut(),
+            LumpDataInner::DataRegionUnaligned(ref mut d) => d,
+        }
+    }
+}
+/// データの長さや格納先の種類等を出力する.
+///
+/// データの中身は出力されないが、`debug-payload`フィーチャーが有効な場合には、
+/// 先頭の最大`16`バイトが16進数で出力される.
+impl fmt::Debug for LumpData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (storage, block_size) = match self.0 {
+            LumpDataInner::JournalRegion(_) => ("Embedded", None),
+            LumpDataInner::DataRegion(ref x) => ("DataRegion", Some(x.block_size())),
+            LumpDataInner::DataRegionUnaligned(_) => ("DataRegionUnaligned", None),
+        };
+        let mut s = f.debug_struct("LumpData");
+        s.field("len", &self.as_bytes().len())
+            .field("storage", &format_args!("{}", storage))
+            .field("block_size", &block_size);
+        #[cfg(feature = "debug-payload")]
+        {
+            let bytes = self.as_bytes();
+            let prefix = &bytes[..cmp::min(16, bytes.len())];
+            let hex = prefix
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            let omitted = if prefix.len() < bytes.len() { ".." } else { "" };
+            s.field("payload", &format_args!("{}{}", hex, omitted));
+        }
+        s.finish()
+    }
+}
+impl PartialEq for LumpData {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+impl Eq for LumpData {}
+
+#[derive(Clone)]
+pub(crate) enum LumpDataInner {
+    JournalRegion(Vec<u8>),
+    DataRegion(DataRegionLumpData),
+    DataRegionUnaligned(Vec<u8>),
+}
+
+/// Lumpの概要情報.
+///
+/// "ヘッダ"という用語は若干不正確だが、
+/// `HEAD`リクエストの結果として取得可能なものなので、
+/// このような名前にしている.
+#[derive(Debug, Clone)]
+pub struct LumpHeader {
+    /// データサイズの近似値(バイト単位).
+    ///
+    /// 実際のデータサイズに管理用のメタ情報を追加した上で、
+    /// 次のブロックサイズ境界への切り上げたサイズ、となるため、
+    /// 最大でストレージのブロックサイズ二つ分だけ、
+    /// 実際よりも大きな値となることがある.
+    ///
+    /// なお、対象lumpのデータがジャーナル領域に埋め込まれている場合には、
+    /// 常に正確なサイズが返される.
+    pub approximate_data_size: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn lump_data_debug_works() -> TestResult {
+        let data = track!(LumpData::new_embedded(b"foo".to_vec()))?;
+        let debug = format!("{:?}", data);
+        assert!(debug.starts_with("LumpData { len: 3, storage: Embedded, block_size: None"));
+
+        let block_size = BlockSize::min();
+        let mut data = track!(LumpData::aligned_allocate(20, block_size))?;
+        data.as_bytes_mut().copy_from_slice(&[0xab; 20]);
+        let debug = format!("{:?}", data);
+        assert!(debug.starts_with(&format!(
+            "LumpData {{ len: 20, storage: DataRegion, block_size: Some({:?})",
+            block_size
+        )));
+        if cfg!(feature = "debug-payload") {
+            assert!(debug.contains(&format!("payload: {}..", "ab".repeat(16))));
+        } else {
+            assert!(!debug.contains("payload"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fits_embedding_works() -> TestResult {
+        let max = LumpData::MAX_EMBEDDED_SIZE;
+        assert!(track!(LumpData::new_embedded(vec![0; max]))?.fits_embedding());
+        assert!(LumpData::new_embedded(vec![0; max + 1]).is_err());
+
+        // 埋め込み用に生成されたかどうかには依存しない
+        assert!(track!(LumpData::new(vec![0; max]))?.fits_embedding());
+        assert!(!track!(LumpData::new(vec![0; max + 1]))?.fits_embedding());
+        Ok(())
+    }
+}
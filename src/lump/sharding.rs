@@ -0,0 +1,260 @@
+//! `LumpId`の空間を、複数のシャードに分割するためのユーティリティ.
+//!
+//! 複数のデバイスにlumpを振り分ける上位レイヤ向けに、
+//! 128bit幅の識別子の空間全体を`N`個の連続した範囲(シャード)に等分割し、
+//! 各識別子がどのシャードに属するかを安定的に判定する機能を提供する.
+//!
+//! 同じシャード数に対しては、常に同じ分割結果が得られることが保証される.
+//!
+//! # Examples
+//!
+//! ```
+//! use cannyls::lump::LumpId;
+//! use cannyls::lump::sharding::Sharding;
+//!
+//! let sharding = Sharding::new(3).unwrap();
+//! assert_eq!(sharding.shard_of(&LumpId::MIN), 0);
+//! assert_eq!(sharding.shard_of(&LumpId::new(u128::MAX / 2)), 1);
+//! assert_eq!(sharding.shard_of(&LumpId::MAX), 2);
+//!
+//! // 各シャードの範囲は連続しており、全体で識別子の空間全体を覆う
+//! let ranges = sharding.ranges().collect::<Vec<_>>();
+//! assert_eq!(ranges.len(), 3);
+//! assert!(ranges[1].contains(&LumpId::new(u128::MAX / 2)));
+//! ```
+use crate::lump::{LumpId, LumpRange};
+use crate::{ErrorKind, Result};
+
+/// 識別子の空間を、指定された個数のシャードに等分割した結果を表す.
+///
+/// `i`番目のシャードの範囲は`[floor(i * 2^128 / N), floor((i + 1) * 2^128 / N))`となり、
+/// 各シャードのサイズの差は高々`1`に収まる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sharding {
+    shards: usize,
+    quotient: u128,
+    remainder: u128,
+}
+impl Sharding {
+    /// 識別子の空間を`shards`個に分割する`Sharding`インスタンスを生成する.
+    ///
+    /// # Errors
+    ///
+    /// `shards`が`0`の場合には、種類が`ErrorKind::InvalidInput`のエラーが返される.
+    pub fn new(shards: usize) -> Result<Self> {
+        track_assert_ne!(shards, 0, ErrorKind::InvalidInput);
+
+        // `2^128 = quotient * shards + remainder`
+        let n = shards as u128;
+        let mut quotient = u128::MAX / n;
+        let mut remainder = u128::MAX % n + 1;
+        if remainder == n {
+            // `shards == 1`の場合には`quotient`が溢れて`0`となるが、
+            // その場合の`shard_start`の引数は常に`0`なので問題はない
+            quotient = quotient.wrapping_add(1);
+            remainder = 0;
+        }
+        Ok(Sharding {
+            shards,
+            quotient,
+            remainder,
+        })
+    }
+
+    /// シャードの数を返す.
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+
+    /// 指定された識別子が属するシャードの番号を返す.
+    ///
+    /// 返り値は、常に`0`以上`self.shards()`未満となる.
+    pub fn shard_of(&self, lump_id: &LumpId) -> usize {
+        // 候補は`floor(id * N / 2^128)`ないしその次のシャードのいずれか
+        let candidate = mul_high(lump_id.as_u128(), self.shards as u128) as usize;
+        let next = candidate + 1;
+        if next < self.shards && lump_id.as_u128() >= self.shard_start(next) {
+            next
+        } else {
+            candidate
+        }
+    }
+
+    /// `shard`番目のシャードに属する識別子の範囲を返す.
+    ///
+    /// 範囲は常に終端を含む形式(`LumpRange::inclusive`)で表現される.
+    ///
+    /// # Errors
+    ///
+    /// `shard`が`self.shards()`以上の場合には、種類が`ErrorKind::InvalidInput`のエラーが返される.
+    pub fn range(&self, shard: usize) -> Result<LumpRange> {
+        track_assert!(
+            shard < self.shards,
+            ErrorKind::InvalidInput,
+            "Out of range shard: shard={}, shards={}",
+            shard,
+            self.shards
+        );
+
+        let start = LumpId::new(self.shard_start(shard));
+        let end = if shard + 1 == self.shards {
+            LumpId::MAX
+        } else {
+            LumpId::new(self.shard_start(shard + 1) - 1)
+        };
+        Ok(LumpRange::inclusive(start, end))
+    }
+
+    /// 全てのシャードの範囲を、番号順に返す.
+    pub fn ranges(&self) -> impl Iterator<Item = LumpRange> + '_ {
+        (0..self.shards).map(move |shard| self.range(shard).expect("never fails"))
+    }
+
+    /// `shard`番目のシャードの始端(`floor(shard * 2^128 / N)`)を返す.
+    ///
+    /// `shard`は`self.shards()`未満である必要がある.
+    fn shard_start(&self, shard: usize) -> u128 {
+        // `shard * 2^128 / N = shard * quotient + shard * remainder / N`
+        let shard = shard as u128;
+        shard * self.quotient + shard * self.remainder / self.shards as u128
+    }
+}
+
+/// `x * y`(256bit)の上位128bitを返す.
+///
+/// `y`は64bitに収まる値である必要がある.
+fn mul_high(x: u128, y: u128) -> u128 {
+    debug_assert!(y <= u128::from(u64::MAX));
+    let x_high = x >> 64;
+    let x_low = x & u128::from(u64::MAX);
+    let low = x_low * y;
+    let high = x_high * y;
+    (high + (low >> 64)) >> 64
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_works() -> TestResult {
+        assert!(Sharding::new(0).is_err());
+
+        let sharding = track!(Sharding::new(1))?;
+        assert_eq!(sharding.shard_of(&LumpId::MIN), 0);
+        assert_eq!(sharding.shard_of(&LumpId::MAX), 0);
+        assert_eq!(track!(sharding.range(0))?, LumpRange::full());
+        assert!(sharding.range(1).is_err());
+
+        let sharding = track!(Sharding::new(2))?;
+        let half = LumpId::new(1 << 127);
+        assert_eq!(sharding.shard_of(&LumpId::new(half.as_u128() - 1)), 0);
+        assert_eq!(sharding.shard_of(&half), 1);
+        assert_eq!(
+            track!(sharding.range(0))?,
+            LumpRange::inclusive(LumpId::MIN, LumpId::new(half.as_u128() - 1))
+        );
+        assert_eq!(
+            track!(sharding.range(1))?,
+            LumpRange::inclusive(half, LumpId::MAX)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ranges_are_contiguous_and_balanced() -> TestResult {
+        for &shards in &[1, 2, 3, 5, 7, 10, 16, 100, 255, 1000, 65_537, usize::MAX] {
+            let sharding = track!(Sharding::new(shards))?;
+            let ranges = sharding.ranges().take(1000).collect::<Vec<_>>();
+            assert_eq!(ranges[0].start(), LumpId::MIN);
+
+            let mut sizes = Vec::new();
+            for (i, range) in ranges.iter().enumerate() {
+                let (r, includes_max) = range.split_max();
+                if let Some(next) = ranges.get(i + 1) {
+                    // 隣のシャードと隙間なく連続している
+                    assert_eq!(r.end, next.start());
+                    assert!(!includes_max);
+                } else if i + 1 == shards {
+                    assert!(includes_max);
+                }
+                // (`2^128`は表現できないので、サイズから`1`を引いた値を比較する)
+                let last = if includes_max {
+                    u128::MAX
+                } else {
+                    r.end.as_u128() - 1
+                };
+                sizes.push(last - r.start.as_u128());
+            }
+
+            // 各シャードのサイズの差は、高々`1`
+            let min = sizes.iter().min().cloned().unwrap_or(0);
+            let max = sizes.iter().max().cloned().unwrap_or(0);
+            assert!(
+                max - min <= 1,
+                "shards={}, min={}, max={}",
+                shards,
+                min,
+                max
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shard_of_is_consistent_with_ranges() -> TestResult {
+        for &shards in &[1, 3, 7, 16, 100, 1000, 65_537] {
+            let sharding = track!(Sharding::new(shards))?;
+            for (i, range) in sharding.ranges().enumerate() {
+                // 境界の前後
+                let start = range.start();
+                assert_eq!(sharding.shard_of(&start), i);
+                if i > 0 {
+                    assert_eq!(sharding.shard_of(&LumpId::new(start.as_u128() - 1)), i - 1);
+                }
+            }
+            assert_eq!(sharding.shard_of(&LumpId::MAX), shards - 1);
+
+            // 擬似乱数で生成した識別子
+            let mut x = 0x1234_5678_9abc_def0_u128;
+            for _ in 0..1000 {
+                x = x.wrapping_mul(0x2360_ed05_1fc6_5da4_4385_df64_9fcc_f645) + 1;
+                let id = LumpId::new(x);
+                let shard = sharding.shard_of(&id);
+                assert!(track!(sharding.range(shard))?.contains(&id));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shard_of_is_balanced() -> TestResult {
+        // 一様に分布する識別子は、各シャードにほぼ均等に振り分けられる
+        let shards = 7;
+        let sharding = track!(Sharding::new(shards))?;
+        let mut counts = vec![0; shards];
+        let mut x = 0xfeed_u128;
+        for _ in 0..70_000 {
+            x = x.wrapping_mul(0x2360_ed05_1fc6_5da4_4385_df64_9fcc_f645) + 1;
+            counts[sharding.shard_of(&LumpId::new(x))] += 1;
+        }
+        for &count in &counts {
+            assert!(9_000 < count && count < 11_000, "counts={:?}", counts);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn power_of_two_shards_match_prefix_ranges() -> TestResult {
+        let sharding = track!(Sharding::new(256))?;
+        for prefix in 0..256 {
+            assert_eq!(
+                track!(sharding.range(prefix))?,
+                track!(LumpRange::prefix(prefix as u128, 8))?
+            );
+        }
+        Ok(())
+    }
+}
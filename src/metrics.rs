@@ -1,12 +1,20 @@
 //! [Prometheus][prometheus]用のメトリクス.
 //!
 //! [prometheus]: https://prometheus.io/
+#[cfg(feature = "device")]
+use prometrics::metrics::Histogram;
 use prometrics::metrics::{Counter, Gauge, MetricBuilder};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "device")]
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::block::BlockSize;
 #[cfg(feature = "device")]
-use crate::device::{Command, DeviceStatus};
-use crate::storage::{JournalRecord, StorageHeader};
+use crate::device::{BlockedBehindMap, BlockedBehindTable, Command, DeviceStatus};
+use crate::storage::{JournalRecord, StorageHeader, UsageSummary};
 
 /// ジャーナル領域のキュー（リングバッファ）のメトリクス.
 #[derive(Debug, Clone)]
@@ -172,6 +180,21 @@ pub struct JournalRegionMetrics {
     pub(crate) gc_enqueued_records: Counter,
     pub(crate) gc_dequeued_records: Counter,
     pub(crate) syncs: Counter,
+    pub(crate) deferred_syncs: Counter,
+    pub(crate) sync_interval: Gauge,
+    pub(crate) unsynced_bytes: Gauge,
+    pub(crate) oldest_unsynced_record_timestamp: Gauge,
+    pub(crate) wrap_arounds: Counter,
+    pub(crate) last_wrap_around_timestamp: Gauge,
+    pub(crate) write_buffer_high_water_bytes: Gauge,
+    pub(crate) written_bytes: Counter,
+    pub(crate) gc_rewritten_bytes: Counter,
+    pub(crate) gc_debt_bytes: Gauge,
+    pub(crate) admission_rejections: Counter,
+    pub(crate) admission_forced_gcs: Counter,
+    pub(crate) restore_throughput_bytes_per_second: Gauge,
+    pub(crate) nvm_read_bytes: NvmIoCounter,
+    pub(crate) nvm_written_bytes: NvmIoCounter,
     queue: JournalQueueMetrics,
 }
 impl JournalRegionMetrics {
@@ -208,6 +231,221 @@ impl JournalRegionMetrics {
         self.syncs.value() as u64
     }
 
+    /// `Deadline::Infinity`が指定されたPUTによって、定期的な同期が先送りされた回数.
+    ///
+    /// 詳細は`Storage::put_with_deadline`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_deferred_syncs_total <COUNTER>
+    /// ```
+    pub fn deferred_syncs(&self) -> u64 {
+        self.deferred_syncs.value() as u64
+    }
+
+    /// 現在の同期間隔(同期命令の発行までに追記されるレコード数).
+    ///
+    /// 同期間隔の自動調整(`StorageBuilder::adaptive_journal_sync`)が有効な場合には、実行中に変化する.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_sync_interval <GAUGE>
+    /// ```
+    pub fn sync_interval(&self) -> u64 {
+        self.sync_interval.value() as u64
+    }
+
+    /// ジャーナルに追記されたが、まだ同期命令が発行されていない(i.e., 永続化が保証されていない)バイト数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_unsynced_bytes <GAUGE>
+    /// ```
+    pub fn unsynced_bytes(&self) -> u64 {
+        self.unsynced_bytes.value() as u64
+    }
+
+    /// 未同期のレコードの内で、最も古いものが追記されてからの経過時間.
+    ///
+    /// 未同期のレコードが存在しない場合には`None`が返される.
+    ///
+    /// # Prometheus
+    ///
+    /// 追記時刻がUNIXタイムスタンプ(秒)として公開される(未同期のレコードが存在しない場合は`0`).
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_oldest_unsynced_record_timestamp_seconds <GAUGE>
+    ///
+    /// # 経過時間
+    /// time() - (cannyls_journal_region_oldest_unsynced_record_timestamp_seconds > 0)
+    /// ```
+    pub fn oldest_unsynced_record_age(&self) -> Option<Duration> {
+        let timestamp = self.oldest_unsynced_record_timestamp.value();
+        if timestamp == 0.0 {
+            return None;
+        }
+        let written_at = UNIX_EPOCH + Duration::from_secs_f64(timestamp);
+        Some(
+            SystemTime::now()
+                .duration_since(written_at)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// 追記によって、リングバッファの終端位置が先頭に戻った(`GoToFront`レコードが書き込まれた)回数.
+    ///
+    /// 先頭に戻る際には、レイテンシが悪化する傾向があるため、その原因の特定に使用できる.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_wrap_arounds_total <COUNTER>
+    /// ```
+    pub fn wrap_arounds(&self) -> u64 {
+        self.wrap_arounds.value() as u64
+    }
+
+    /// 最後にリングバッファの終端位置が先頭に戻った時刻.
+    ///
+    /// 起動後に一度も先頭に戻っていない場合には`None`が返される.
+    ///
+    /// # Prometheus
+    ///
+    /// 時刻がUNIXタイムスタンプ(秒)として公開される(一度も先頭に戻っていない場合は`0`).
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_last_wrap_around_timestamp_seconds <GAUGE>
+    /// ```
+    pub fn last_wrap_around_time(&self) -> Option<SystemTime> {
+        let timestamp = self.last_wrap_around_timestamp.value();
+        if timestamp == 0.0 {
+            return None;
+        }
+        Some(UNIX_EPOCH + Duration::from_secs_f64(timestamp))
+    }
+
+    /// ジャーナルの書き込みバッファのサイズの最大値(バイト単位).
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_write_buffer_high_water_bytes <GAUGE>
+    /// ```
+    pub fn write_buffer_high_water_bytes(&self) -> u64 {
+        self.write_buffer_high_water_bytes.value() as u64
+    }
+
+    /// ジャーナルに追記されたレコードの合計バイト数(GCによる再追記分も含む).
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_written_bytes_total <COUNTER>
+    /// ```
+    pub fn written_bytes(&self) -> u64 {
+        self.written_bytes.value() as u64
+    }
+
+    /// GCによってジャーナルの末尾に再追記されたレコードの合計バイト数.
+    ///
+    /// この値は`written_bytes`にも含まれている.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_gc_rewritten_bytes_total <COUNTER>
+    /// ```
+    pub fn gc_rewritten_bytes(&self) -> u64 {
+        self.gc_rewritten_bytes.value() as u64
+    }
+
+    /// GCの負債(バイト単位).
+    ///
+    /// 起動以降にジャーナルに追記されたバイト数(GCによる再追記分も含む)から、
+    /// GCによって解放されたバイト数を引いた値.
+    /// この値が増え続ける場合には、GCが書き込みに追いついていないことを意味する
+    /// (`StorageBuilder::adaptive_journal_gc`も参照のこと).
+    ///
+    /// 起動前に追記されていたレコードが解放された場合には、負の値となることもある.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_gc_debt_bytes <GAUGE>
+    /// ```
+    pub fn gc_debt_bytes(&self) -> i64 {
+        self.gc_debt_bytes.value() as i64
+    }
+
+    /// GCが追記に追いついていないために、拒否されたPUTの数.
+    ///
+    /// 詳細は`StorageBuilder::journal_admission_control`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_admission_rejections_total <COUNTER>
+    /// ```
+    pub fn admission_rejections(&self) -> u64 {
+        self.admission_rejections.value() as u64
+    }
+
+    /// GCが追記に追いついていないために、PUTの前に同期的に実行された全体GCの回数.
+    ///
+    /// 詳細は`StorageBuilder::journal_admission_control`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_admission_forced_gcs_total <COUNTER>
+    /// ```
+    pub fn admission_forced_gcs(&self) -> u64 {
+        self.admission_forced_gcs.value() as u64
+    }
+
+    /// 起動時にジャーナルからエントリ群を復元した際の、NVMからの読み込みのスループット(バイト毎秒).
+    ///
+    /// `StorageBuilder::journal_restore_buffer_size`の調整に使用できる.
+    /// チェックポイントから復元した場合等、ジャーナルの読み込みが行われなかった場合には`0`となる.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_restore_throughput_bytes_per_second <GAUGE>
+    /// ```
+    pub fn restore_throughput_bytes_per_second(&self) -> f64 {
+        self.restore_throughput_bytes_per_second.value()
+    }
+
+    /// リングバッファ部分に対して、NVMから読み込まれたバイト数(発生元別).
+    ///
+    /// ブロック境界へのアライメント分も含まれる.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_nvm_read_bytes_total { origin="foreground|gc|restore|scrub" } <COUNTER>
+    /// ```
+    pub fn nvm_read_bytes(&self) -> &NvmIoCounter {
+        &self.nvm_read_bytes
+    }
+
+    /// リングバッファ部分に対して、書き込まれたバイト数(発生元別).
+    ///
+    /// 書き込みは、NVMへのフラッシュ時ではなく、書き込みバッファへの追記時に計上される.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_journal_region_nvm_written_bytes_total { origin="foreground|gc|restore|scrub" } <COUNTER>
+    /// ```
+    pub fn nvm_written_bytes(&self) -> &NvmIoCounter {
+        &self.nvm_written_bytes
+    }
+
     /// リングバッファのメトリクスを返す.
     pub fn queue(&self) -> &JournalQueueMetrics {
         &self.queue
@@ -232,6 +470,81 @@ impl JournalRegionMetrics {
                 .help("Number of synchronization instructions issued to the physical device")
                 .finish()
                 .expect("Never fails"),
+            deferred_syncs: builder
+                .counter("deferred_syncs_total")
+                .help("Number of periodic journal syncs deferred by PUTs with an infinite deadline")
+                .finish()
+                .expect("Never fails"),
+            sync_interval: builder
+                .gauge("sync_interval")
+                .help("Effective number of records appended between synchronizations")
+                .finish()
+                .expect("Never fails"),
+            unsynced_bytes: builder
+                .gauge("unsynced_bytes")
+                .help("Number of journal bytes written but not yet synchronized")
+                .finish()
+                .expect("Never fails"),
+            oldest_unsynced_record_timestamp: builder
+                .gauge("oldest_unsynced_record_timestamp_seconds")
+                .help("UNIX timestamp of the oldest unsynchronized journal record (0 if none)")
+                .finish()
+                .expect("Never fails"),
+            wrap_arounds: builder
+                .counter("wrap_arounds_total")
+                .help("Number of times the tail of the journal ring buffer wrapped around to the front")
+                .finish()
+                .expect("Never fails"),
+            last_wrap_around_timestamp: builder
+                .gauge("last_wrap_around_timestamp_seconds")
+                .help("UNIX timestamp of the last wrap-around of the journal ring buffer (0 if none)")
+                .finish()
+                .expect("Never fails"),
+            write_buffer_high_water_bytes: builder
+                .gauge("write_buffer_high_water_bytes")
+                .help("Maximum size of the journal write buffer observed so far")
+                .finish()
+                .expect("Never fails"),
+            written_bytes: builder
+                .counter("written_bytes_total")
+                .help("Number of bytes of records appended to the journal (including GC rewrites)")
+                .finish()
+                .expect("Never fails"),
+            gc_rewritten_bytes: builder
+                .counter("gc_rewritten_bytes_total")
+                .help("Number of bytes of records rewritten to the journal by GC")
+                .finish()
+                .expect("Never fails"),
+            restore_throughput_bytes_per_second: builder
+                .gauge("restore_throughput_bytes_per_second")
+                .help("Throughput of reading journal entries from the NVM on startup")
+                .finish()
+                .expect("Never fails"),
+            gc_debt_bytes: builder
+                .gauge("gc_debt_bytes")
+                .help("Number of journal bytes appended minus bytes reclaimed by GC since startup")
+                .finish()
+                .expect("Never fails"),
+            admission_rejections: builder
+                .counter("admission_rejections_total")
+                .help("Number of PUTs rejected since journal GC could not keep up with appends")
+                .finish()
+                .expect("Never fails"),
+            admission_forced_gcs: builder
+                .counter("admission_forced_gcs_total")
+                .help("Number of full journal GCs forced before PUTs since GC could not keep up")
+                .finish()
+                .expect("Never fails"),
+            nvm_read_bytes: NvmIoCounter::new(
+                &builder,
+                "nvm_read_bytes_total",
+                "Number of bytes read from the journal ring buffer on the NVM",
+            ),
+            nvm_written_bytes: NvmIoCounter::new(
+                &builder,
+                "nvm_written_bytes_total",
+                "Number of bytes written to the journal ring buffer on the NVM",
+            ),
             queue,
         }
     }
@@ -271,7 +584,9 @@ impl JournalRecordCounter {
     pub(crate) fn increment<B>(&self, record: &JournalRecord<B>) {
         match *record {
             JournalRecord::Delete { .. } => self.delete.increment(),
-            JournalRecord::EndOfRecords | JournalRecord::GoToFront => {}
+            JournalRecord::EndOfRecords
+            | JournalRecord::GoToFront
+            | JournalRecord::Extension(..) => {}
             JournalRecord::Put { .. } => self.put.increment(),
             JournalRecord::Embed { .. } => self.embed.increment(),
             JournalRecord::DeleteRange { .. } => self.delete_range.increment(),
@@ -302,6 +617,181 @@ impl JournalRecordCounter {
     }
 }
 
+/// NVMへの読み書きの発生元.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IoOrigin {
+    /// 利用者のリクエスト.
+    Foreground,
+
+    /// ジャーナル領域のGC.
+    Gc,
+
+    /// ストレージを開く際の状態の復元.
+    Restore,
+
+    /// 解放済み部分領域のゼロ埋め(スクラブ).
+    Scrub,
+}
+
+/// NVMへの読み書きのバイト数を、その発生元毎に数えるカウンタ.
+#[derive(Debug, Clone)]
+pub struct NvmIoCounter {
+    foreground: Counter,
+    gc: Counter,
+    restore: Counter,
+    scrub: Counter,
+}
+impl NvmIoCounter {
+    /// 利用者のリクエストによって発生したバイト数.
+    pub fn foreground(&self) -> u64 {
+        self.foreground.value() as u64
+    }
+
+    /// ジャーナル領域のGCによって発生したバイト数.
+    pub fn gc(&self) -> u64 {
+        self.gc.value() as u64
+    }
+
+    /// ストレージを開く際の状態の復元によって発生したバイト数.
+    pub fn restore(&self) -> u64 {
+        self.restore.value() as u64
+    }
+
+    /// スクラブによって発生したバイト数.
+    pub fn scrub(&self) -> u64 {
+        self.scrub.value() as u64
+    }
+
+    /// 全ての発生元の合計バイト数.
+    pub fn total(&self) -> u64 {
+        self.foreground() + self.gc() + self.restore() + self.scrub()
+    }
+
+    pub(crate) fn add(&self, origin: IoOrigin, bytes: u64) {
+        let counter = match origin {
+            IoOrigin::Foreground => &self.foreground,
+            IoOrigin::Gc => &self.gc,
+            IoOrigin::Restore => &self.restore,
+            IoOrigin::Scrub => &self.scrub,
+        };
+        counter.add_u64(bytes);
+    }
+
+    fn new(builder: &MetricBuilder, name: &str, help: &str) -> Self {
+        let counter = |origin| {
+            builder
+                .counter(name)
+                .help(help)
+                .label("origin", origin)
+                .finish()
+                .expect("Never fails")
+        };
+        NvmIoCounter {
+            foreground: counter("foreground"),
+            gc: counter("gc"),
+            restore: counter("restore"),
+            scrub: counter("scrub"),
+        }
+    }
+}
+
+/// データ領域を等分割したバケット毎に、アクセス回数を数えるヒートマップ.
+///
+/// 頻繁にアクセスされる部分の特定や、コンパクション・デフラグの判断材料として、可視化に用いることを想定している.
+///
+/// 利用者のリクエストによるlumpデータの読み書きのみが対象で、
+/// 一回の読み書きにつき、その範囲が重なる各バケットのカウンタが一つずつ増加する.
+///
+/// 複製されたインスタンス同士はカウンタを共有する.
+#[derive(Debug, Clone)]
+pub struct AccessHeatmap {
+    inner: Arc<AccessHeatmapInner>,
+}
+#[derive(Debug)]
+struct AccessHeatmapInner {
+    capacity: u64,
+    reads: Vec<AtomicU64>,
+    writes: Vec<AtomicU64>,
+}
+impl AccessHeatmap {
+    /// バケットの数を返す.
+    pub fn buckets(&self) -> usize {
+        self.inner.reads.len()
+    }
+
+    /// `index`番目のバケットが担当する、データ領域内のバイト範囲を返す.
+    pub fn bucket_range(&self, index: usize) -> Range<u64> {
+        let start = self.bucket_start(index);
+        let end = self.bucket_start(index + 1);
+        start..end
+    }
+
+    /// 各バケットの読み込み回数を返す.
+    pub fn reads(&self) -> Vec<u64> {
+        Self::load(&self.inner.reads)
+    }
+
+    /// 各バケットの書き込み回数を返す.
+    pub fn writes(&self) -> Vec<u64> {
+        Self::load(&self.inner.writes)
+    }
+
+    /// 全てのカウンタをゼロに戻す.
+    pub fn reset(&self) {
+        for c in self.inner.reads.iter().chain(self.inner.writes.iter()) {
+            c.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn new(capacity: u64, buckets: usize) -> Self {
+        let buckets = buckets.max(1);
+        let counters = || (0..buckets).map(|_| AtomicU64::new(0)).collect();
+        AccessHeatmap {
+            inner: Arc::new(AccessHeatmapInner {
+                capacity,
+                reads: counters(),
+                writes: counters(),
+            }),
+        }
+    }
+
+    /// データ領域の`offset`から`len`バイト分の範囲へのアクセスを記録する.
+    pub(crate) fn record(&self, offset: u64, len: u64, is_write: bool) {
+        if len == 0 || offset >= self.inner.capacity {
+            return;
+        }
+        let counters = if is_write {
+            &self.inner.writes
+        } else {
+            &self.inner.reads
+        };
+        let first = self.bucket_index(offset);
+        let last = self.bucket_index(offset + len - 1);
+        for c in &counters[first..=last] {
+            c.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn bucket_index(&self, offset: u64) -> usize {
+        let offset = offset.min(self.inner.capacity - 1);
+        let index = u128::from(offset) * self.buckets() as u128 / u128::from(self.inner.capacity);
+        index as usize
+    }
+
+    fn bucket_start(&self, index: usize) -> u64 {
+        let buckets = self.buckets() as u128;
+        let index = (index as u128).min(buckets);
+
+        // `bucket_index`の逆変換(i.e., `bucket_index(start) == index`となる最小の`start`)
+        let start = (index * u128::from(self.inner.capacity)).div_ceil(buckets);
+        start as u64
+    }
+
+    fn load(counters: &[AtomicU64]) -> Vec<u64> {
+        counters.iter().map(|c| c.load(Ordering::Relaxed)).collect()
+    }
+}
+
 /// データ領域用のアロケータのメトリクス.
 #[derive(Debug, Clone)]
 pub struct DataAllocatorMetrics {
@@ -314,6 +804,10 @@ pub struct DataAllocatorMetrics {
     pub(crate) released_portions: Counter,
     pub(crate) released_bytes: Counter,
     pub(crate) nospace_failures: Counter,
+    pub(crate) fragment_portions: Gauge,
+    pub(crate) fragment_bytes: Gauge,
+    pub(crate) aligned_allocations: Counter,
+    pub(crate) alignment_fallbacks: Counter,
     pub(crate) block_size: BlockSize,
     pub(crate) capacity_bytes: u64,
 }
@@ -411,6 +905,56 @@ impl DataAllocatorMetrics {
         self.nospace_failures.value() as u64
     }
 
+    /// 断片として、割当時の探索対象から隔離されている空き領域の数.
+    ///
+    /// この数はフリーリストの長さ(`free_list_len`)には含まれない.
+    /// 詳細は`StorageBuilder::free_fragment_threshold`および`StorageBuilder::max_free_portions`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_allocator_fragment_portions <GAUGE>
+    /// ```
+    pub fn fragment_portions(&self) -> u64 {
+        self.fragment_portions.value() as u64
+    }
+
+    /// 断片として隔離されているために、割当に使用されない空き領域の合計バイト数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_allocator_fragment_bytes <GAUGE>
+    /// ```
+    pub fn fragment_bytes(&self) -> u64 {
+        self.fragment_bytes.value() as u64
+    }
+
+    /// アライメント境界に揃えて割り当てられた部分領域の数.
+    ///
+    /// 詳細は`StorageBuilder::large_lump_alignment`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_allocator_aligned_allocations_total <COUNTER>
+    /// ```
+    pub fn aligned_allocations(&self) -> u64 {
+        self.aligned_allocations.value() as u64
+    }
+
+    /// アライメントの対象となるサイズだったが、境界に揃った空き領域が見つからなかったために、
+    /// 境界を無視して割り当てられた部分領域の数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_allocator_alignment_fallbacks_total <COUNTER>
+    /// ```
+    pub fn alignment_fallbacks(&self) -> u64 {
+        self.alignment_fallbacks.value() as u64
+    }
+
     pub(crate) fn new(builder: &MetricBuilder, capacity_bytes: u64, block_size: BlockSize) -> Self {
         let mut builder = builder.clone();
         builder.namespace("cannyls").subsystem("data_allocator");
@@ -464,13 +1008,33 @@ impl DataAllocatorMetrics {
                 .help("Number of allocation failures caused by no available space")
                 .finish()
                 .expect("Never fails"),
-            capacity_bytes,
-            block_size,
-        }
-    }
-
-    #[cfg(test)]
-    pub(crate) fn usage_bytes(&self) -> u64 {
+            fragment_portions: builder
+                .gauge("fragment_portions")
+                .help("Number of free portions isolated from allocation as fragments")
+                .finish()
+                .expect("Never fails"),
+            fragment_bytes: builder
+                .gauge("fragment_bytes")
+                .help("Number of bytes of free portions isolated from allocation as fragments")
+                .finish()
+                .expect("Never fails"),
+            aligned_allocations: builder
+                .counter("aligned_allocations_total")
+                .help("Number of portions allocated on alignment boundaries")
+                .finish()
+                .expect("Never fails"),
+            alignment_fallbacks: builder
+                .counter("alignment_fallbacks_total")
+                .help("Number of large portions allocated without alignment due to lack of aligned space")
+                .finish()
+                .expect("Never fails"),
+            capacity_bytes,
+            block_size,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn usage_bytes(&self) -> u64 {
         // NOTE: 以下の順番で値を取得しないとアンダーフローする可能性がある
         let dec = self.released_bytes();
         let inc = self.allocated_bytes();
@@ -488,6 +1052,18 @@ impl DataAllocatorMetrics {
         self.released_bytes
             .add_u64(u64::from(self.block_size.as_u16()) * u64::from(size));
     }
+
+    pub(crate) fn count_fragment_insertion(&self, size: u32) {
+        self.fragment_portions.increment();
+        self.fragment_bytes
+            .add(f64::from(self.block_size.as_u16()) * f64::from(size));
+    }
+
+    pub(crate) fn count_fragment_removal(&self, size: u32) {
+        self.fragment_portions.decrement();
+        self.fragment_bytes
+            .subtract(f64::from(self.block_size.as_u16()) * f64::from(size));
+    }
 }
 
 /// [`Device`]のメトリクス.
@@ -501,8 +1077,17 @@ pub struct DeviceMetrics {
     pub(crate) dequeued_commands: DeviceCommandCounter,
     pub(crate) failed_commands: DeviceCommandCounter,
     pub(crate) busy_commands: DeviceCommandCounter,
+    pub(crate) dropped_commands: DeviceCommandCounter,
+    pub(crate) replayed_commands: DeviceCommandCounter,
     pub(crate) side_jobs: Counter,
+    pub(crate) side_job_duration_seconds: Histogram,
+    pub(crate) read_latency_seconds: Histogram,
+    pub(crate) write_latency_seconds: Histogram,
+    pub(crate) direct_io_degraded: Gauge,
+    pub(crate) nvm_info: Gauge,
     pub(crate) storage: Option<StorageMetrics>,
+    pub(crate) usage_summary: Arc<Mutex<Option<UsageSummary>>>,
+    pub(crate) blocked_behind: BlockedBehindTable,
 }
 #[cfg(feature = "device")]
 impl DeviceMetrics {
@@ -590,6 +1175,36 @@ impl DeviceMetrics {
         &self.busy_commands
     }
 
+    /// `LongQueuePolicy::Drop`により、実行されずにドロップされたコマンドの数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_device_dropped_commands_total { command="put" } = <COUNTER>
+    /// cannyls_device_dropped_commands_total { command="get" } = <COUNTER>
+    /// cannyls_device_dropped_commands_total { command="head" } = <COUNTER>
+    /// cannyls_device_dropped_commands_total { command="delete" } = <COUNTER>
+    /// cannyls_device_dropped_commands_total { command="list" } = <COUNTER>
+    /// ```
+    pub fn dropped_commands(&self) -> &DeviceCommandCounter {
+        &self.dropped_commands
+    }
+
+    /// 同じ冪等性キーを持つ処理済みのコマンドが存在したために、
+    /// 実行されずに記録済みの結果が返されたコマンドの数.
+    ///
+    /// 詳細は`DeviceRequest::idempotency_key`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_device_replayed_commands_total { command="put" } = <COUNTER>
+    /// cannyls_device_replayed_commands_total { command="delete" } = <COUNTER>
+    /// ```
+    pub fn replayed_commands(&self) -> &DeviceCommandCounter {
+        &self.replayed_commands
+    }
+
     /// 補助タスクの実行回数.
     ///
     /// # Prometheus
@@ -601,6 +1216,81 @@ impl DeviceMetrics {
         self.side_jobs.value() as u64
     }
 
+    /// 補助タスクの一回の実行に要した時間(秒)の分布.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_device_side_job_duration_seconds_bucket { le="..." } <COUNTER>
+    /// cannyls_device_side_job_duration_seconds_sum <COUNTER>
+    /// cannyls_device_side_job_duration_seconds_count <COUNTER>
+    /// ```
+    pub fn side_job_duration_seconds(&self) -> &Histogram {
+        &self.side_job_duration_seconds
+    }
+
+    /// 読み込み系のコマンド(GET, HEAD)の、キューへの追加から処理完了までに要した時間(秒)の分布.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_device_read_latency_seconds_bucket { le="..." } <COUNTER>
+    /// cannyls_device_read_latency_seconds_sum <COUNTER>
+    /// cannyls_device_read_latency_seconds_count <COUNTER>
+    /// ```
+    pub fn read_latency_seconds(&self) -> &Histogram {
+        &self.read_latency_seconds
+    }
+
+    /// 書き込み系のコマンド(PUT, DELETE, DELETE_RANGE)の、キューへの追加から処理完了までに要した時間(秒)の分布.
+    ///
+    /// 分割実行されるコマンドの場合には、最初の単位処理の完了までの時間となる.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_device_write_latency_seconds_bucket { le="..." } <COUNTER>
+    /// cannyls_device_write_latency_seconds_sum <COUNTER>
+    /// cannyls_device_write_latency_seconds_count <COUNTER>
+    /// ```
+    pub fn write_latency_seconds(&self) -> &Histogram {
+        &self.write_latency_seconds
+    }
+
+    /// デバイスが使用しているNVMで、Direct I/Oが要求されたが使用できずに、
+    /// 通常のI/Oに切り替えられているかどうか.
+    ///
+    /// 詳細は`FileNvmBuilder::direct_io_fallback`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// # 0=false, 1=true
+    /// cannyls_device_direct_io_degraded <GAUGE>
+    /// ```
+    pub fn is_direct_io_degraded(&self) -> bool {
+        self.direct_io_degraded.value() != 0.0
+    }
+
+    /// デバイスが使用しているNVMを識別するための情報(ラベル).
+    ///
+    /// ラベルの内容はNVMの実装に依存する(`NonVolatileMemory::metric_labels`を参照).
+    /// 例えば`FileNvm`の場合には、ファイルのパスや、実際に適用されたフラグ群が含まれる.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// # 値は、デバイスの稼働中は`1`、それ以外は`0`
+    /// cannyls_device_nvm_info { path="...", direct_io="true|false", ... } <GAUGE>
+    /// ```
+    pub fn nvm_labels(&self) -> Vec<(String, String)> {
+        self.nvm_info
+            .labels()
+            .iter()
+            .map(|l| (l.name().to_owned(), l.value().to_owned()))
+            .collect()
+    }
+
     /// デバイスキューの長さ(i.e., 実行待ちのコマンド数).
     ///
     /// # Prometheus
@@ -622,6 +1312,29 @@ impl DeviceMetrics {
         self.storage.as_ref()
     }
 
+    /// デフォルトのストレージの、`LumpId`の接頭辞毎の使用量の要約を返す.
+    ///
+    /// `storage`とは異なり、`DeviceHandle::metrics`経由で取得したメトリクスからも参照可能.
+    /// デバイスの起動が完了するまでは`None`が返る.
+    pub fn usage_summary(&self) -> Option<UsageSummary> {
+        self.usage_summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// コマンドの種類と、そのキューへの追加時にデバイスが実行中だったコマンドの種類の組毎の、待ち時間の統計情報を返す.
+    ///
+    /// キーの二番目の要素が`None`の要素は、キューへの追加時にデバイスがアイドル状態だったコマンド群の統計情報を表す.
+    /// 例えば`(CommandKind::Get, Some(CommandKind::List))`の待ち時間が他と比べて突出していれば、
+    /// GETのテイルレイテンシの原因が、長時間のLISTによるヘッドオブラインブロッキングであることが分かる.
+    ///
+    /// キューから取り出された時点で記録されるため、処理されずに拒否されたコマンドは含まれない.
+    /// なお、この統計情報はPrometheus形式では公開されない.
+    pub fn blocked_behind(&self) -> BlockedBehindMap {
+        self.blocked_behind.snapshot()
+    }
+
     pub(crate) fn new(builder: &MetricBuilder) -> Self {
         let mut builder = builder.clone();
         builder.namespace("cannyls").subsystem("device");
@@ -651,12 +1364,52 @@ impl DeviceMetrics {
                 "busy_commands_total",
                 "Number of commands gave up to execute due to the device is busy",
             ),
+            dropped_commands: DeviceCommandCounter::new(
+                &builder,
+                "dropped_commands_total",
+                "Number of commands dropped due to the long queue policy",
+            ),
+            replayed_commands: DeviceCommandCounter::new(
+                &builder,
+                "replayed_commands_total",
+                "Number of commands answered with the recorded result of the same idempotency key",
+            ),
             side_jobs: builder
                 .counter("side_jobs_total")
                 .help("Number of exeuction of side jobs")
                 .finish()
                 .expect("Never fails"),
+            side_job_duration_seconds: builder
+                .histogram("side_job_duration_seconds")
+                .help("Duration of each side job execution")
+                .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0])
+                .finish()
+                .expect("Never fails"),
+            read_latency_seconds: builder
+                .histogram("read_latency_seconds")
+                .help("Latency of read commands from enqueue to completion")
+                .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0])
+                .finish()
+                .expect("Never fails"),
+            write_latency_seconds: builder
+                .histogram("write_latency_seconds")
+                .help("Latency of write commands from enqueue to completion")
+                .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0])
+                .finish()
+                .expect("Never fails"),
+            direct_io_degraded: builder
+                .gauge("direct_io_degraded")
+                .help("Whether the NVM fell back to buffered I/O from direct I/O (0=false, 1=true)")
+                .finish()
+                .expect("Never fails"),
+            nvm_info: builder
+                .gauge("nvm_info")
+                .help("Information about the NVM used by the device (labels)")
+                .finish()
+                .expect("Never fails"),
             storage: None,
+            usage_summary: Arc::new(Mutex::new(None)),
+            blocked_behind: BlockedBehindTable::new(),
         }
     }
 }
@@ -670,9 +1423,18 @@ pub struct DeviceCommandCounter {
     pub(crate) head: Counter,
     pub(crate) delete: Counter,
     pub(crate) delete_range: Counter,
+    pub(crate) delete_many: Counter,
+    pub(crate) link: Counter,
+    pub(crate) rename: Counter,
     pub(crate) list: Counter,
     pub(crate) list_range: Counter,
     pub(crate) usage_range: Counter,
+    pub(crate) check: Counter,
+    pub(crate) stats: Counter,
+    pub(crate) journal_snapshot_step: Counter,
+    pub(crate) scan_step: Counter,
+    pub(crate) warm_up: Counter,
+    pub(crate) journal_gc: Counter,
     pub(crate) stop: Counter,
 }
 #[cfg(feature = "device")]
@@ -702,6 +1464,21 @@ impl DeviceCommandCounter {
         self.delete_range.value() as u64
     }
 
+    /// DELETE_MANYコマンド用のカウンタの値を返す.
+    pub fn delete_many(&self) -> u64 {
+        self.delete_many.value() as u64
+    }
+
+    /// LINKコマンド用のカウンタの値を返す.
+    pub fn link(&self) -> u64 {
+        self.link.value() as u64
+    }
+
+    /// RENAMEコマンド用のカウンタの値を返す.
+    pub fn rename(&self) -> u64 {
+        self.rename.value() as u64
+    }
+
     /// LISTコマンド用のカウンタの値を返す.
     pub fn list(&self) -> u64 {
         self.list.value() as u64
@@ -717,6 +1494,36 @@ impl DeviceCommandCounter {
         self.usage_range.value() as u64
     }
 
+    /// CHECKコマンド用のカウンタの値を返す.
+    pub fn check(&self) -> u64 {
+        self.check.value() as u64
+    }
+
+    /// STATSコマンド用のカウンタの値を返す.
+    pub fn stats(&self) -> u64 {
+        self.stats.value() as u64
+    }
+
+    /// JOURNAL_SNAPSHOT_STEPコマンド用のカウンタの値を返す.
+    pub fn journal_snapshot_step(&self) -> u64 {
+        self.journal_snapshot_step.value() as u64
+    }
+
+    /// SCAN_STEPコマンド用のカウンタの値を返す.
+    pub fn scan_step(&self) -> u64 {
+        self.scan_step.value() as u64
+    }
+
+    /// WARM_UPコマンド用のカウンタの値を返す.
+    pub fn warm_up(&self) -> u64 {
+        self.warm_up.value() as u64
+    }
+
+    /// JOURNAL_GCコマンド用のカウンタの値を返す.
+    pub fn journal_gc(&self) -> u64 {
+        self.journal_gc.value() as u64
+    }
+
     /// STOPコマンド用のカウンタの値を返す.
     pub fn stop(&self) -> u64 {
         self.stop.value() as u64
@@ -737,25 +1544,49 @@ impl DeviceCommandCounter {
             head: counter("head"),
             delete: counter("delete"),
             delete_range: counter("delete_range"),
+            delete_many: counter("delete_many"),
+            link: counter("link"),
+            rename: counter("rename"),
             list: counter("list"),
             list_range: counter("list_range"),
             usage_range: counter("usage_range"),
+            check: counter("check"),
+            stats: counter("stats"),
+            journal_snapshot_step: counter("journal_snapshot_step"),
+            scan_step: counter("scan_step"),
+            warm_up: counter("warm_up"),
+            journal_gc: counter("journal_gc"),
             stop: counter("stop"),
         }
     }
 
     #[cfg(feature = "device")]
     pub(crate) fn increment(&self, command: &Command) {
+        self.counter(command).increment();
+    }
+
+    /// 指定されたコマンドに対応するカウンタを返す.
+    #[cfg(feature = "device")]
+    pub(crate) fn counter(&self, command: &Command) -> &Counter {
         match *command {
-            Command::Put { .. } => self.put.increment(),
-            Command::Get { .. } => self.get.increment(),
-            Command::Head { .. } => self.head.increment(),
-            Command::Delete { .. } => self.delete.increment(),
-            Command::DeleteRange { .. } => self.delete_range.increment(),
-            Command::List { .. } => self.list.increment(),
-            Command::ListRange { .. } => self.list_range.increment(),
-            Command::UsageRange { .. } => self.usage_range.increment(),
-            Command::Stop { .. } => self.stop.increment(),
+            Command::Put { .. } => &self.put,
+            Command::Get { .. } => &self.get,
+            Command::Head { .. } => &self.head,
+            Command::Delete { .. } => &self.delete,
+            Command::DeleteRange { .. } => &self.delete_range,
+            Command::DeleteMany { .. } => &self.delete_many,
+            Command::Link { .. } => &self.link,
+            Command::Rename { .. } => &self.rename,
+            Command::List { .. } => &self.list,
+            Command::ListRange { .. } => &self.list_range,
+            Command::UsageRange { .. } => &self.usage_range,
+            Command::Check { .. } => &self.check,
+            Command::Stats { .. } => &self.stats,
+            Command::JournalSnapshotStep { .. } => &self.journal_snapshot_step,
+            Command::ScanStep { .. } => &self.scan_step,
+            Command::WarmUp { .. } => &self.warm_up,
+            Command::JournalGc { .. } => &self.journal_gc,
+            Command::Stop { .. } => &self.stop,
         }
     }
 
@@ -765,8 +1596,17 @@ impl DeviceCommandCounter {
             + self.get()
             + self.head()
             + self.delete()
+            + self.delete_many()
+            + self.link()
+            + self.rename()
             + self.list()
             + self.usage_range()
+            + self.check()
+            + self.stats()
+            + self.journal_snapshot_step()
+            + self.scan_step()
+            + self.warm_up()
+            + self.journal_gc()
             + self.stop()
     }
 }
@@ -789,10 +1629,23 @@ pub struct StorageMetrics {
     pub(crate) delete_lumps: Counter,
     pub(crate) get_journal_lumps: Counter,
     pub(crate) get_data_lumps: Counter,
+    pub(crate) logical_written_bytes: Counter,
+    pub(crate) deduplicated_lumps: Counter,
+    pub(crate) linked_lumps: Counter,
+    pub(crate) renamed_lumps: Counter,
+    pub(crate) unchanged_overwrites: Counter,
+    pub(crate) in_place_overwrites: Counter,
+    pub(crate) reembedded_lumps: Counter,
+    pub(crate) index_shadow_writes: Counter,
+    pub(crate) index_shadow_restores: Counter,
+    pub(crate) checkpoint_failures: Counter,
+    pub(crate) index_shadow_failures: Counter,
+    #[allow(dead_code)] // 登録したメトリクスを保持し続けるためのフィールド
     header: Gauge,
     original_header: StorageHeader, // `header`からも復元できるが効率のためにこちらも保持しておく
     journal_region: JournalRegionMetrics,
     data_region: DataRegionMetrics,
+    usage_summary: UsageSummary,
 }
 impl StorageMetrics {
     /// ストレージに追加されたlumpの数.
@@ -853,6 +1706,176 @@ impl StorageMetrics {
         (inc - dec) as usize
     }
 
+    /// ストレージに追加されたlumpのデータの合計バイト数(i.e., 論理的な書き込み量).
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_logical_written_bytes_total <COUNTER>
+    /// ```
+    pub fn logical_written_bytes(&self) -> u64 {
+        self.logical_written_bytes.value() as u64
+    }
+
+    /// 重複排除によって、既存のlumpとデータを共有する形で追加されたlumpの数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_deduplicated_lumps_total <COUNTER>
+    /// ```
+    pub fn deduplicated_lumps(&self) -> u64 {
+        self.deduplicated_lumps.value() as u64
+    }
+
+    /// `Storage::link`によって作成された別名の数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_linked_lumps_total <COUNTER>
+    /// ```
+    pub fn linked_lumps(&self) -> u64 {
+        self.linked_lumps.value() as u64
+    }
+
+    /// `Storage::rename`によってIDが変更されたlumpの数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_renamed_lumps_total <COUNTER>
+    /// ```
+    pub fn renamed_lumps(&self) -> u64 {
+        self.renamed_lumps.value() as u64
+    }
+
+    /// 既存のlumpと同一の内容での上書きであったために、書き込みが省略されたPUTの数.
+    ///
+    /// 詳細は`StorageBuilder::skip_identical_overwrites`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_unchanged_overwrites_total <COUNTER>
+    /// ```
+    pub fn unchanged_overwrites(&self) -> u64 {
+        self.unchanged_overwrites.value() as u64
+    }
+
+    /// 既存のlumpのデータ部分領域への上書き更新が行われたPUTの数.
+    ///
+    /// 詳細は`StorageBuilder::overwrite_in_place`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_in_place_overwrites_total <COUNTER>
+    /// ```
+    pub fn in_place_overwrites(&self) -> u64 {
+        self.in_place_overwrites.value() as u64
+    }
+
+    /// データ領域からジャーナル領域に移し替えられたlumpの数.
+    ///
+    /// 詳細は`StorageBuilder::reembed_threshold`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_reembedded_lumps_total <COUNTER>
+    /// ```
+    pub fn reembedded_lumps(&self) -> u64 {
+        self.reembedded_lumps.value() as u64
+    }
+
+    /// インデックスのシャドウファイルが書き出された回数.
+    ///
+    /// 詳細は`StorageBuilder::index_shadow_file`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_index_shadow_writes_total <COUNTER>
+    /// ```
+    pub fn index_shadow_writes(&self) -> u64 {
+        self.index_shadow_writes.value() as u64
+    }
+
+    /// オープン時に、ジャーナルを再生する代わりにインデックスのシャドウファイルから状態が復元された回数.
+    ///
+    /// 詳細は`StorageBuilder::index_shadow_file`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_index_shadow_restores_total <COUNTER>
+    /// ```
+    pub fn index_shadow_restores(&self) -> u64 {
+        self.index_shadow_restores.value() as u64
+    }
+
+    /// オープン時に、ジャーナルヘッダに記録されていたチェックポイントが、
+    /// 読み込めなかったか不整合があったために使用されなかった回数.
+    ///
+    /// この場合には、ジャーナルの再生によって状態が復元される.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_checkpoint_failures_total <COUNTER>
+    /// ```
+    pub fn checkpoint_failures(&self) -> u64 {
+        self.checkpoint_failures.value() as u64
+    }
+
+    /// オープン時に、インデックスのシャドウファイルが存在したにも関わらず、
+    /// 読み込めなかったか、書き出し以降にジャーナルが更新されていたために使用されなかった回数.
+    ///
+    /// この場合には、ジャーナルの再生によって状態が復元される.
+    /// 詳細は`StorageBuilder::index_shadow_file`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_storage_index_shadow_failures_total <COUNTER>
+    /// ```
+    pub fn index_shadow_failures(&self) -> u64 {
+        self.index_shadow_failures.value() as u64
+    }
+
+    /// NVMに書き込まれた合計バイト数(i.e., 物理的な書き込み量).
+    ///
+    /// データ領域に書き込まれたブロック群と、ジャーナル領域に追記されたレコード群(GCによる再追記分を含む)の合計.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_written_bytes_total + cannyls_journal_region_written_bytes_total
+    /// ```
+    pub fn physical_written_bytes(&self) -> u64 {
+        self.data_region.written_bytes() + self.journal_region.written_bytes()
+    }
+
+    /// 書き込み増幅率(i.e., 物理的な書き込み量 / 論理的な書き込み量).
+    ///
+    /// まだ何も書き込まれていない場合には`None`が返される.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// (cannyls_data_region_written_bytes_total + cannyls_journal_region_written_bytes_total)
+    ///   / cannyls_storage_logical_written_bytes_total
+    /// ```
+    pub fn write_amplification(&self) -> Option<f64> {
+        let logical = self.logical_written_bytes();
+        if logical == 0 {
+            None
+        } else {
+            Some(self.physical_written_bytes() as f64 / logical as f64)
+        }
+    }
+
     /// ストレージのヘッダ情報.
     ///
     /// # Prometheus
@@ -874,11 +1897,19 @@ impl StorageMetrics {
         &self.data_region
     }
 
+    /// `LumpId`の接頭辞毎のストレージ使用量の要約を返す.
+    ///
+    /// Prometheusには公開されない.
+    pub fn usage_summary(&self) -> &UsageSummary {
+        &self.usage_summary
+    }
+
     pub(crate) fn new(
         builder: &MetricBuilder,
         header: &StorageHeader,
         journal_region: JournalRegionMetrics,
         data_region: DataRegionMetrics,
+        usage_summary: UsageSummary,
     ) -> Self {
         let mut builder = builder.clone();
         builder.namespace("cannyls").subsystem("storage");
@@ -929,9 +1960,67 @@ impl StorageMetrics {
                 .label("region", "data")
                 .finish()
                 .expect("Never fails"),
+            logical_written_bytes: builder
+                .counter("logical_written_bytes_total")
+                .help("Number of bytes of lump data putted on the storage")
+                .finish()
+                .expect("Never fails"),
+            deduplicated_lumps: builder
+                .counter("deduplicated_lumps_total")
+                .help("Number of lumps putted by sharing the data of an existing identical lump")
+                .finish()
+                .expect("Never fails"),
+            linked_lumps: builder
+                .counter("linked_lumps_total")
+                .help("Number of lump aliases created by linking to an existing lump")
+                .finish()
+                .expect("Never fails"),
+            renamed_lumps: builder
+                .counter("renamed_lumps_total")
+                .help("Number of lumps whose IDs were changed without copying their data")
+                .finish()
+                .expect("Never fails"),
+            unchanged_overwrites: builder
+                .counter("unchanged_overwrites_total")
+                .help("Number of PUTs skipped since they overwrite a lump with identical content")
+                .finish()
+                .expect("Never fails"),
+            in_place_overwrites: builder
+                .counter("in_place_overwrites_total")
+                .help("Number of PUTs that overwrote the existing data portion of a lump")
+                .finish()
+                .expect("Never fails"),
+            reembedded_lumps: builder
+                .counter("reembedded_lumps_total")
+                .help("Number of lumps moved from the data region into the journal region")
+                .finish()
+                .expect("Never fails"),
+            index_shadow_writes: builder
+                .counter("index_shadow_writes_total")
+                .help("Number of times the index shadow file was written")
+                .finish()
+                .expect("Never fails"),
+            index_shadow_restores: builder
+                .counter("index_shadow_restores_total")
+                .help(
+                    "Number of times the state was restored from the index shadow file at opening",
+                )
+                .finish()
+                .expect("Never fails"),
+            checkpoint_failures: builder
+                .counter("checkpoint_failures_total")
+                .help("Number of checkpoints that were unreadable or inconsistent at opening")
+                .finish()
+                .expect("Never fails"),
+            index_shadow_failures: builder
+                .counter("index_shadow_failures_total")
+                .help("Number of index shadow files that were unreadable or stale at opening")
+                .finish()
+                .expect("Never fails"),
             original_header: header.clone(),
             journal_region,
             data_region,
+            usage_summary,
         }
     }
 }
@@ -940,6 +2029,19 @@ impl StorageMetrics {
 #[derive(Debug, Clone)]
 pub struct DataRegionMetrics {
     pub(crate) capacity_bytes: Gauge,
+    pub(crate) written_bytes: Counter,
+    pub(crate) pending_scrub_bytes: Gauge,
+    pub(crate) scrubbed_bytes: Counter,
+    pub(crate) write_verification_failures: Counter,
+    pub(crate) sparse_lumps: Counter,
+    pub(crate) sparse_hole_bytes: Counter,
+    pub(crate) nvm_read_bytes: NvmIoCounter,
+    pub(crate) nvm_written_bytes: NvmIoCounter,
+    pub(crate) read_ahead_bytes: Counter,
+    pub(crate) read_ahead_hits: Counter,
+    pub(crate) read_ahead_misses: Counter,
+    pub(crate) written_slices: Counter,
+    pub(crate) access_heatmap: Option<AccessHeatmap>,
     allocator: DataAllocatorMetrics,
 }
 impl DataRegionMetrics {
@@ -968,6 +2070,155 @@ impl DataRegionMetrics {
         inc - dec
     }
 
+    /// データ領域に書き込まれたlumpの合計バイト数(ブロック境界へのアライメント分も含む).
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_written_bytes_total <COUNTER>
+    /// ```
+    pub fn written_bytes(&self) -> u64 {
+        self.written_bytes.value() as u64
+    }
+
+    /// ゼロ埋め(スクラブ)待ちとなっている解放済み部分領域の合計バイト数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_pending_scrub_bytes <GAUGE>
+    /// ```
+    pub fn pending_scrub_bytes(&self) -> u64 {
+        self.pending_scrub_bytes.value() as u64
+    }
+
+    /// 解放済み部分領域に対してゼロ埋め(スクラブ)を行ったバイト数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_scrubbed_bytes_total <COUNTER>
+    /// ```
+    pub fn scrubbed_bytes(&self) -> u64 {
+        self.scrubbed_bytes.value() as u64
+    }
+
+    /// 書き込み後の読み戻し検証(`StorageBuilder::verify_data_writes`)に失敗した回数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_write_verification_failures_total <COUNTER>
+    /// ```
+    pub fn write_verification_failures(&self) -> u64 {
+        self.write_verification_failures.value() as u64
+    }
+
+    /// ゼロブロックを省いた疎な形式(`StorageBuilder::sparse_lumps`)で格納されたlumpの数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_sparse_lumps_total <COUNTER>
+    /// ```
+    pub fn sparse_lumps(&self) -> u64 {
+        self.sparse_lumps.value() as u64
+    }
+
+    /// 疎な形式で格納したことによって、書き込みを省略できたバイト数.
+    ///
+    /// 通常の形式で格納した場合の書き込みサイズとの差分.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_sparse_hole_bytes_total <COUNTER>
+    /// ```
+    pub fn sparse_hole_bytes(&self) -> u64 {
+        self.sparse_hole_bytes.value() as u64
+    }
+
+    /// データ領域に対して、NVMから読み込まれたバイト数(発生元別).
+    ///
+    /// 書き込み後の読み戻し検証による読み込みは、利用者のリクエストによるものとして計上される.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_nvm_read_bytes_total { origin="foreground|gc|restore|scrub" } <COUNTER>
+    /// ```
+    pub fn nvm_read_bytes(&self) -> &NvmIoCounter {
+        &self.nvm_read_bytes
+    }
+
+    /// データ領域に対して、NVMに書き込まれたバイト数(発生元別).
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_nvm_written_bytes_total { origin="foreground|gc|restore|scrub" } <COUNTER>
+    /// ```
+    pub fn nvm_written_bytes(&self) -> &NvmIoCounter {
+        &self.nvm_written_bytes
+    }
+
+    /// 先読み(`StorageBuilder::read_ahead_size`)によって読み込まれたバイト数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_read_ahead_bytes_total <COUNTER>
+    /// ```
+    pub fn read_ahead_bytes(&self) -> u64 {
+        self.read_ahead_bytes.value() as u64
+    }
+
+    /// 先読みした内容から、lumpデータの読み込みが行われた回数.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_read_ahead_hits_total <COUNTER>
+    /// ```
+    pub fn read_ahead_hits(&self) -> u64 {
+        self.read_ahead_hits.value() as u64
+    }
+
+    /// 直前の読み込み範囲に連続するlumpデータの読み込みの内で、先読みした内容から読み込めなかった回数.
+    ///
+    /// 先読みのヒット率は`read_ahead_hits / (read_ahead_hits + read_ahead_misses)`で求められる.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_read_ahead_misses_total <COUNTER>
+    /// ```
+    pub fn read_ahead_misses(&self) -> u64 {
+        self.read_ahead_misses.value() as u64
+    }
+
+    /// 分割して書き込まれたlumpデータのスライスの総数.
+    ///
+    /// 詳細は`StorageBuilder::write_slice_size`を参照のこと.
+    ///
+    /// # Prometheus
+    ///
+    /// ```prometheus
+    /// cannyls_data_region_written_slices_total <COUNTER>
+    /// ```
+    pub fn written_slices(&self) -> u64 {
+        self.written_slices.value() as u64
+    }
+
+    /// データ領域へのアクセスのヒートマップを返す.
+    ///
+    /// `StorageBuilder::access_heatmap`で有効にされていない場合には`None`が返される.
+    ///
+    /// Prometheus用のメトリクスとしては公開されない.
+    pub fn access_heatmap(&self) -> Option<&AccessHeatmap> {
+        self.access_heatmap.as_ref()
+    }
+
     /// アロケータのメトリクスを返す.
     pub fn allocator(&self) -> &DataAllocatorMetrics {
         &self.allocator
@@ -987,6 +2238,67 @@ impl DataRegionMetrics {
                 .initial_value(capacity as f64)
                 .finish()
                 .expect("Never fails"),
+            written_bytes: builder
+                .counter("written_bytes_total")
+                .help("Number of bytes of lumps written to the data region (including alignment padding)")
+                .finish()
+                .expect("Never fails"),
+            pending_scrub_bytes: builder
+                .gauge("pending_scrub_bytes")
+                .help("Number of bytes of released portions waiting to be scrubbed")
+                .finish()
+                .expect("Never fails"),
+            scrubbed_bytes: builder
+                .counter("scrubbed_bytes_total")
+                .help("Number of bytes of released portions scrubbed (zero-filled)")
+                .finish()
+                .expect("Never fails"),
+            write_verification_failures: builder
+                .counter("write_verification_failures_total")
+                .help("Number of data region writes whose read-back verification failed")
+                .finish()
+                .expect("Never fails"),
+            sparse_lumps: builder
+                .counter("sparse_lumps_total")
+                .help("Number of lumps stored in the sparse format (without zero blocks)")
+                .finish()
+                .expect("Never fails"),
+            sparse_hole_bytes: builder
+                .counter("sparse_hole_bytes_total")
+                .help("Number of bytes whose writes were skipped by storing lumps in the sparse format")
+                .finish()
+                .expect("Never fails"),
+            nvm_read_bytes: NvmIoCounter::new(
+                &builder,
+                "nvm_read_bytes_total",
+                "Number of bytes read from the data region on the NVM",
+            ),
+            nvm_written_bytes: NvmIoCounter::new(
+                &builder,
+                "nvm_written_bytes_total",
+                "Number of bytes written to the data region on the NVM",
+            ),
+            read_ahead_bytes: builder
+                .counter("read_ahead_bytes_total")
+                .help("Number of bytes read ahead from the data region")
+                .finish()
+                .expect("Never fails"),
+            read_ahead_hits: builder
+                .counter("read_ahead_hits_total")
+                .help("Number of lump data reads served from the read-ahead buffer")
+                .finish()
+                .expect("Never fails"),
+            read_ahead_misses: builder
+                .counter("read_ahead_misses_total")
+                .help("Number of sequential lump data reads not served from the read-ahead buffer")
+                .finish()
+                .expect("Never fails"),
+            written_slices: builder
+                .counter("written_slices_total")
+                .help("Number of slices written by split lump data writes")
+                .finish()
+                .expect("Never fails"),
+            access_heatmap: None,
             allocator,
         }
     }
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+use crate::block::AlignedBytes;
+use crate::{ErrorKind, Result};
+
+/// ファイルへの書き込みを、専用のスレッドで行うためのオブジェクト.
+///
+/// 書き込みは位置指定I/O(`pwrite`)で行われるため、元のファイルのオフセットには影響を与えない.
+/// 発行された書き込みは、発行順に処理される.
+#[derive(Debug)]
+pub struct BackgroundWriter {
+    request_tx: Option<Sender<(u64, AlignedBytes)>>,
+    result_rx: Receiver<Result<AlignedBytes>>,
+    thread: Option<JoinHandle<()>>,
+    in_flight: usize,
+    completed: Vec<AlignedBytes>,
+}
+impl BackgroundWriter {
+    /// 書き込み用のスレッドを起動する.
+    ///
+    /// `file`には、書き込み対象のファイルを複製したハンドルを渡す.
+    pub fn spawn(file: File) -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<(u64, AlignedBytes)>();
+        let (result_tx, result_rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Ok((offset, buf)) = request_rx.recv() {
+                let result = track_io!(file.write_all_at(&buf, offset)).map(|()| buf);
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        BackgroundWriter {
+            request_tx: Some(request_tx),
+            result_rx,
+            thread: Some(thread),
+            in_flight: 0,
+            completed: Vec::new(),
+        }
+    }
+
+    /// 完了していない書き込みが存在するかどうかを判定する.
+    pub fn is_busy(&self) -> bool {
+        self.in_flight != 0
+    }
+
+    /// ファイル上の`offset`の位置に`buf`を書き込む要求を発行する.
+    pub fn request(&mut self, offset: u64, buf: AlignedBytes) -> Result<()> {
+        let request_tx = self.request_tx.as_ref().expect("Never fails");
+        track_assert!(
+            request_tx.send((offset, buf)).is_ok(),
+            ErrorKind::Other,
+            "Background writer thread has terminated"
+        );
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// 完了済みの書き込みの結果を、ブロックせずに回収する.
+    ///
+    /// 全ての書き込みが完了している場合には`true`が返される.
+    pub fn poll(&mut self) -> Result<bool> {
+        while self.in_flight != 0 {
+            match self.result_rx.try_recv() {
+                Ok(result) => track!(self.handle_result(result))?,
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(TryRecvError::Disconnected) => {
+                    track_panic!(ErrorKind::Other, "Background writer thread has terminated")
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// 全ての書き込みが完了するまで待機して、書き込みに使用されたバッファ群を返す.
+    pub fn wait(&mut self) -> Result<Vec<AlignedBytes>> {
+        while self.in_flight != 0 {
+            let result = track_assert_some!(
+                self.result_rx.recv().ok(),
+                ErrorKind::Other,
+                "Background writer thread has terminated"
+            );
+            track!(self.handle_result(result))?;
+        }
+        Ok(std::mem::take(&mut self.completed))
+    }
+
+    fn handle_result(&mut self, result: Result<AlignedBytes>) -> Result<()> {
+        self.in_flight -= 1;
+        let buf = track!(result)?;
+        self.completed.push(buf);
+        Ok(())
+    }
+}
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        // 要求用のチャンネルを閉じてスレッドを停止させる.
+        //
+        // 発行済みの書き込みが全て反映されるように、スレッドの終了を待機する.
+        self.request_tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
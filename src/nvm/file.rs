@@ -1,28 +1,48 @@
+use slog::{Discard, Logger};
 use std::cmp;
 use std::fs::{self, File};
 use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
 
+#[cfg(unix)]
+use super::background_writer::BackgroundWriter;
+#[cfg(unix)]
+use crate::block::AlignedBytes;
 use crate::block::BlockSize;
-use crate::nvm::NonVolatileMemory;
+#[cfg(unix)]
+use crate::nvm::AsyncNonVolatileMemory;
+use crate::nvm::{AccessPattern, NonVolatileMemory};
 use crate::storage::StorageHeader;
 use crate::{ErrorKind, Result};
 
 /// `FileNvm`のビルダ
 ///
-/// `FileNvm`には二つのオプション`direct_io`と`exclusive_lock`が存在する。  
-/// デフォルトでは`direct_io=true`かつ`exclusive_lock=true`の振る舞いをする。  
+/// `FileNvm`には六つのオプション`direct_io`、`direct_io_self_test`、`direct_io_fallback`、`preallocate`、`exclusive_lock`および`async_write`が存在する。  
+/// デフォルトでは`direct_io_fallback=false`、`preallocate=false`かつ`async_write=false`で、それ以外は`true`の振る舞いをする。  
 /// それぞれのオプション内容については個別のメソッドを参照せよ。
 pub struct FileNvmBuilder {
     direct_io: bool,
+    direct_io_self_test: bool,
+    direct_io_fallback: bool,
+    preallocate: bool,
     exclusive_lock: bool,
+    async_write: bool,
+    logger: Logger,
+    window: Option<(u64, u64)>,
 }
 
 impl Default for FileNvmBuilder {
     fn default() -> Self {
         FileNvmBuilder {
             direct_io: true,
+            direct_io_self_test: true,
+            direct_io_fallback: false,
+            preallocate: false,
             exclusive_lock: true,
+            async_write: false,
+            logger: Logger::root(Discard, o!()),
+            window: None,
         }
     }
 }
@@ -72,6 +92,89 @@ impl FileNvmBuilder {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    fn check_direct_io_if_flag_is_on(&self, file: &File) -> Result<()> {
+        if self.direct_io && self.direct_io_self_test {
+            track!(check_aligned_io(file, BlockSize::min()))?;
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn check_direct_io_if_flag_is_on(&self, _file: &File) -> Result<()> {
+        Ok(())
+    }
+
+    /// 既に開かれているファイルに対して、`O_DIRECT`フラグの付与ないし除去を行う.
+    #[cfg(target_os = "linux")]
+    fn set_direct_io_flag(&self, file: &File, enabled: bool) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = file.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let new_flags = if enabled {
+            flags | libc::O_DIRECT
+        } else {
+            flags & !libc::O_DIRECT
+        };
+        if new_flags != flags && unsafe { libc::fcntl(fd, libc::F_SETFL, new_flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn set_direct_io_flag(&self, _file: &File, _enabled: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn preallocate_if_flag_is_on(&self, file: &File, capacity: u64) -> Result<()> {
+        let start = self.window_start();
+        use std::os::unix::io::AsRawFd;
+
+        if !self.preallocate {
+            return Ok(());
+        }
+
+        // ファイルサイズを変更してしまうと、`create_if_absent`での新規作成判定に影響するので、サイズは維持する
+        let mode = libc::FALLOC_FL_KEEP_SIZE;
+        let (offset, len) = (start as libc::off_t, capacity as libc::off_t);
+        if unsafe { libc::fallocate(file.as_raw_fd(), mode, offset, len) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                warn!(
+                    self.logger,
+                    "Preallocation is not supported by the filesystem; skipped"
+                );
+                return Ok(());
+            }
+
+            // 部分的に確保された領域を解放しておく
+            // (ただし、ファイル内の一部のみを使用している場合には、他の部分を壊さないように何もしない)
+            if start == 0 {
+                let _ = file.set_len(0);
+            }
+            if e.raw_os_error() == Some(libc::ENOSPC) {
+                track_panic!(
+                    ErrorKind::StorageFull,
+                    "Cannot preallocate {} bytes: no space left on the filesystem",
+                    capacity
+                );
+            }
+            track_io!(Err(e))?;
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn preallocate_if_flag_is_on(&self, _file: &File, _capacity: u64) -> Result<()> {
+        Ok(())
+    }
+
     #[cfg(unix)]
     fn set_exclusive_file_lock_if_flag_is_on(&self, file: &File) -> Result<()> {
         use std::os::unix::io::AsRawFd;
@@ -103,6 +206,58 @@ impl FileNvmBuilder {
         self
     }
 
+    /// Direct I/Oを行う場合に、ファイルのオープン時にアライメントの検査を行うかどうかを設定する。  
+    /// デフォルトでは検査を行う。
+    ///
+    /// ファイルシステムや物理デバイスによっては、`BlockSize::min()`(512バイト)境界に揃ったDirect I/Oを受け付けず、
+    /// より大きなアライメント(e.g., 4096バイト)を要求するものが存在する。  
+    /// 検査が有効な場合には、オープン時に先頭ブロックの読み込みと(同じ内容の)書き戻しを試行し、
+    /// それが拒否された場合には、後続の読み書きの途中で失敗するのではなく、その時点でエラーを返す。
+    ///
+    /// 現状ではLinuxのみで有効なオプション(`direct_io=false`の場合は無視される)。
+    pub fn direct_io_self_test(&mut self, enabled: bool) -> &mut Self {
+        self.direct_io_self_test = enabled;
+        self
+    }
+
+    /// Direct I/Oが使用できない場合に、通常の(バッファリングありの)I/Oに切り替えるかどうかを設定する。  
+    /// デフォルトでは切り替えを行わない。
+    ///
+    /// ファイルシステムによっては(e.g., 古いカーネルでのtmpfs、一部のネットワークファイルシステム)、
+    /// `O_DIRECT`付きでのオープンや、それに続くアライメント検査(`direct_io_self_test`を参照)が`EINVAL`で失敗する。  
+    /// 切り替えが有効な場合には、その時点でエラーとはせずに、`O_DIRECT`なしでファイルを開き直す。
+    ///
+    /// 切り替えが行われた場合には、警告ログが出力され、`FileNvm::is_direct_io_degraded`が`true`を返すようになる。
+    ///
+    /// 現状ではLinuxのみで有効なオプション(`direct_io=false`の場合は無視される)。
+    pub fn direct_io_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.direct_io_fallback = enabled;
+        self
+    }
+
+    /// ファイルの新規作成時に、容量分の領域を事前に確保するかどうかを設定する。  
+    /// デフォルトでは事前確保を行わない。
+    ///
+    /// 事前確保を行わない場合には、ストレージの作成後しばらく経ってから、
+    /// ファイルシステムの空き容量不足によって書き込みに失敗する(`ENOSPC`)可能性がある。  
+    /// 事前確保が有効な場合には、作成時点で容量分の領域を確保し、
+    /// 空き容量が不足している場合には、種類が`ErrorKind::StorageFull`のエラーを返す。
+    ///
+    /// なお、ファイルサイズ自体は変更されない(`fallocate`の`FALLOC_FL_KEEP_SIZE`を使用)。  
+    /// 現状ではLinuxのみで有効なオプションで、ファイルシステムが対応していない場合には何も行わない。
+    pub fn preallocate(&mut self, enabled: bool) -> &mut Self {
+        self.preallocate = enabled;
+        self
+    }
+
+    /// ログ出力に使用する logger を登録する。
+    ///
+    /// デフォルトでは何も出力しない。
+    pub fn logger(&mut self, logger: Logger) -> &mut Self {
+        self.logger = logger;
+        self
+    }
+
     /// ファイルに対する排他ロックを行うかどうかを設定する。  
     /// デフォルトでは排他ロックを行う。
     /// - `enabled=true`で排他ロックを行う。
@@ -115,6 +270,95 @@ impl FileNvmBuilder {
         self
     }
 
+    /// 非同期書き込み(`AsyncNonVolatileMemory`)を有効にするかどうかを設定する。  
+    /// デフォルトでは有効にしない。
+    /// - `enabled=true`で、書き込み専用のスレッドを用いた非同期書き込みを行えるようにする。
+    /// - `enabled=false`で、非同期書き込みを行わない(`as_async_mut`は`None`を返す)。
+    ///
+    /// 有効にした場合には、ジャーナル領域の書き込みバッファのフラッシュが非同期に行われるようになり、
+    /// ディスクへの書き込み中にも、次のジャーナルレコードの準備を進められるようになる。
+    /// 書き込みバッファは同期(`sync`)時にしかフラッシュされないため、
+    /// 効果を得るには`StorageBuilder::journal_max_write_buffer_size`を併せて指定する必要がある。
+    ///
+    /// 現状ではUnix系でのみ有効なオプション。
+    pub fn async_write(&mut self, enabled: bool) -> &mut Self {
+        self.async_write = enabled;
+        self
+    }
+
+    /// ファイル全体ではなく、`start`から`len`バイト分の範囲のみを`FileNvm`として使用するように設定する。  
+    /// デフォルトではファイルの先頭から使用する。
+    ///
+    /// 独自のヘッダ(スーパーブロック)を先頭に持つコンテナファイルやパーティションの内部に、
+    /// ストレージを配置したい場合に使用する。
+    /// ストレージのヘッダの読み込みや、容量の判定は、全てこの範囲を基準に行われる:
+    /// - `create`: 既存のファイルも(範囲外の内容を保持したまま)使用可能となる
+    /// - `create_if_absent`: ファイルのサイズが`start`以下の場合に、新規作成とみなされる
+    /// - `open`: `start`の位置からストレージのヘッダが読み込まれる
+    ///
+    /// `start`は`BlockSize::min()`の境界に揃っている必要があり、
+    /// ストレージの容量は`len`以下である必要がある(そうではない場合には、作成ないしオープン時に、
+    /// それぞれ`ErrorKind::BlockSizeMismatch`と`ErrorKind::CapacityTooSmall`エラーとなる)。
+    pub fn with_offset(&mut self, start: u64, len: u64) -> &mut Self {
+        self.window = Some((start, len));
+        self
+    }
+
+    fn window_start(&self) -> u64 {
+        self.window.map_or(0, |(start, _)| start)
+    }
+
+    fn check_window_start(&self) -> Result<()> {
+        let start = self.window_start();
+        track_assert!(
+            BlockSize::min().is_aligned(start),
+            ErrorKind::BlockSizeMismatch,
+            "Unaligned window start: start={}, block_size={}",
+            start,
+            BlockSize::MIN
+        );
+        Ok(())
+    }
+
+    /// ファイルに格納されているストレージのヘッダを読み込む.
+    ///
+    /// 先頭のヘッダが壊れている場合には、ストレージの末尾に格納されているバックアップを使用する.
+    fn read_saved_header<P: AsRef<Path>>(&self, filepath: P) -> Result<StorageHeader> {
+        match StorageHeader::read_from_file_at(&filepath, self.window_start()) {
+            Ok(header) => Ok(header),
+            Err(e) => {
+                let window_len = self.window.map(|(_, len)| len);
+                if let Ok(Some(header)) = StorageHeader::read_backup_from_file_at(
+                    &filepath,
+                    self.window_start(),
+                    window_len,
+                ) {
+                    warn!(
+                        self.logger,
+                        "The storage header is broken; uses the backup header instead: {}", e
+                    );
+                    Ok(header)
+                } else {
+                    Err(track!(e))
+                }
+            }
+        }
+    }
+
+    fn check_window(&self, capacity: u64) -> Result<()> {
+        track!(self.check_window_start())?;
+        if let Some((_, len)) = self.window {
+            track_assert!(
+                capacity <= len,
+                ErrorKind::CapacityTooSmall,
+                "Too large capacity for the window: capacity={}, window_len={}",
+                capacity,
+                len
+            );
+        }
+        Ok(())
+    }
+
     #[cfg(target_os = "linux")]
     fn file_open_with_error_info<P: AsRef<Path>>(
         &self,
@@ -124,7 +368,7 @@ impl FileNvmBuilder {
     ) -> Result<File> {
         use std::os::unix::fs::OpenOptionsExt;
 
-        let open_result = track_io!(options.open(&filepath));
+        let open_result = track_io!(options.open(filepath));
 
         // If we succeed on opening the file `filepath`, we return it.
         if open_result.is_ok() {
@@ -156,7 +400,7 @@ impl FileNvmBuilder {
         let mut options = fs::OpenOptions::new();
         options.read(true).write(true).create(false);
 
-        let file = track_io!(options.open(&filepath));
+        let file = track_io!(options.open(filepath));
         if file.is_err() {
             return track!(file, "We cannot open the file {:?}.", filepath.as_ref());
         }
@@ -164,7 +408,7 @@ impl FileNvmBuilder {
         // Finally, we check if the file `filepath` can be opened with `O_DIRECT` option.
         if self.direct_io {
             options.custom_flags(libc::O_DIRECT);
-            let file = track_io!(options.open(&filepath));
+            let file = track_io!(options.open(filepath));
             if file.is_err() {
                 return track!(
                     file,
@@ -188,6 +432,48 @@ impl FileNvmBuilder {
         track_io!(options.open(&filepath))
     }
 
+    /// ファイルを開く.
+    ///
+    /// 結果の二番目の値は、Direct I/Oが使用できずに、通常のI/Oでファイルを開き直したかどうかを示す.
+    fn open_file<P: AsRef<Path>>(
+        &self,
+        do_create: bool,
+        options: &fs::OpenOptions,
+        filepath: &P,
+    ) -> Result<(File, bool)> {
+        #[cfg(target_os = "linux")]
+        {
+            if self.direct_io && self.direct_io_fallback {
+                match options.open(filepath) {
+                    Ok(file) => return Ok((file, false)),
+                    Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                        let file = track!(self.open_without_direct_io(do_create, filepath))?;
+                        return Ok((file, true));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        let file = track!(self.file_open_with_error_info(do_create, options, filepath))?;
+        Ok((file, false))
+    }
+
+    /// Direct I/Oが使用できなかった場合に、通常のI/Oでファイルを開き直す.
+    fn open_without_direct_io<P: AsRef<Path>>(
+        &self,
+        do_create: bool,
+        filepath: &P,
+    ) -> Result<File> {
+        warn!(
+            self.logger,
+            "Direct I/O is not supported by the file; falls back to buffered I/O";
+            "path" => format!("{:?}", filepath.as_ref())
+        );
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create(do_create);
+        track_io!(options.open(filepath))
+    }
+
     /// 新しい`FileNvm`インスタンスを生成する.
     ///
     /// `filepath`が既に存在する場合にはそれを開き、存在しない場合には新規にファイルを作成する.
@@ -205,19 +491,23 @@ impl FileNvmBuilder {
         // OpenOptions::createはファイルが既に存在する場合はそれを開き
         // 存在しない場合は作成する
         options.create(true);
-        let file = track!(self.file_open_with_error_info(true, &options, &filepath))?;
+        let (file, degraded) = track!(self.open_file(true, &options, &filepath))?;
 
         // metadataのファイルサイズの非ゼロ検査で
         // 新規作成されたファイルかどうかを判断する
+        // (ファイル内の一部のみを使用する場合には、その開始位置以降に内容があるかどうかで判断する)
         let metadata = track_io!(fs::metadata(&filepath))?;
-        if metadata.len() == 0 {
+        if metadata.len() <= self.window_start() {
             // ファイルが新しく作成された
-            self.initialize(file, capacity).map(|s| (s, true))
+            let nvm = track!(self.initialize(file, Some(filepath.as_ref()), capacity, degraded))?;
+            track!(self.preallocate_if_flag_is_on(&nvm.file, capacity))?;
+            Ok((nvm, true))
         } else {
             // 既に存在するファイルなので、格納されているcapacity値を使う
-            let saved_header = track!(StorageHeader::read_from_file(&filepath))?;
+            let saved_header = track!(self.read_saved_header(&filepath))?;
             let capacity = saved_header.storage_size();
-            self.initialize(file, capacity).map(|s| (s, false))
+            self.initialize(file, Some(filepath.as_ref()), capacity, degraded)
+                .map(|s| (s, false))
         }
     }
 
@@ -231,9 +521,16 @@ impl FileNvmBuilder {
         let mut options = self.open_options();
         // OpenOptions::create_newはファイルが存在しない場合だけ作成し
         // 存在しない場合はエラーとなる。
-        options.create_new(true);
-        let file = self.file_open_with_error_info(true, &options, &filepath)?;
-        self.initialize(file, capacity)
+        // ただしファイル内の一部のみを使用する場合には、既存のコンテナファイルの使用を許容する。
+        if self.window.is_some() {
+            options.create(true);
+        } else {
+            options.create_new(true);
+        }
+        let (file, degraded) = self.open_file(true, &options, &filepath)?;
+        let nvm = track!(self.initialize(file, Some(filepath.as_ref()), capacity, degraded))?;
+        track!(self.preallocate_if_flag_is_on(&nvm.file, capacity))?;
+        Ok(nvm)
     }
 
     /// 既存のファイルを開いて`FileNvm`インスタンスを生成する。
@@ -244,20 +541,114 @@ impl FileNvmBuilder {
     /// lusfファイルにはcapacity情報が埋め込まれているので
     /// createとは異なりcapacity引数を要求しない。
     pub fn open<P: AsRef<Path>>(&mut self, filepath: P) -> Result<FileNvm> {
-        let saved_header = track!(StorageHeader::read_from_file(&filepath))?;
+        // 開始位置が不正な場合には、ヘッダの読み込みエラーではなく、その旨のエラーを返す
+        track!(self.check_window_start())?;
+        let saved_header = track!(self.read_saved_header(&filepath))?;
         let capacity = saved_header.storage_size();
         let options = self.open_options();
-        let file = self.file_open_with_error_info(false, &options, &filepath)?;
-        self.initialize(file, capacity)
+        let (file, degraded) = self.open_file(false, &options, &filepath)?;
+        self.initialize(file, Some(filepath.as_ref()), capacity, degraded)
     }
 
-    fn initialize(&self, file: File, capacity: u64) -> Result<FileNvm> {
+    /// 既に開かれているファイルから`FileNvm`インスタンスを生成する.
+    ///
+    /// `FileNvm::from_file`の実装.
+    fn open_handle(&self, file: File, capacity: u64) -> Result<FileNvm> {
+        // `EINVAL`は`io::ErrorKind::InvalidInput`に対応する
+        let degraded = match self.set_direct_io_flag(&file, self.direct_io) {
+            Ok(()) => false,
+            Err(ref e) if self.direct_io_fallback && e.kind() == io::ErrorKind::InvalidInput => {
+                warn!(
+                    self.logger,
+                    "Direct I/O is not supported by the file; falls back to buffered I/O"
+                );
+                true
+            }
+            Err(e) => track_io!(Err(e))?,
+        };
+        self.initialize(file, None, capacity, degraded)
+    }
+
+    fn initialize(
+        &self,
+        mut file: File,
+        filepath: Option<&Path>,
+        capacity: u64,
+        mut direct_io_degraded: bool,
+    ) -> Result<FileNvm> {
+        track!(self.check_window(capacity))?;
         track!(self.set_exclusive_file_lock_if_flag_is_on(&file))?;
         track!(self.set_fnocache_if_flag_is_on(&file))?;
-        Ok(FileNvm::with_range(file, 0, capacity))
+
+        // 他のプロセスとの競合を避けるために、検査は排他ロックの取得後に行う
+        if !direct_io_degraded {
+            if let Err(e) = self.check_direct_io_if_flag_is_on(&file) {
+                if !(self.direct_io_fallback && *e.kind() == ErrorKind::InvalidInput) {
+                    return Err(track!(e));
+                }
+
+                if let Some(filepath) = filepath {
+                    // 開き直す前に、排他ロックを解放しておく
+                    mem::drop(file);
+                    file = track!(self.open_without_direct_io(false, &filepath))?;
+                    track!(self.set_exclusive_file_lock_if_flag_is_on(&file))?;
+                } else {
+                    // パスが不明なので、開き直す代わりにフラグを除去する
+                    warn!(
+                        self.logger,
+                        "Direct I/O is not supported by the file; falls back to buffered I/O"
+                    );
+                    track_io!(self.set_direct_io_flag(&file, false))?;
+                }
+                direct_io_degraded = true;
+            }
+        }
+
+        let flags = FileNvmFlags {
+            direct_io: self.direct_io
+                && !direct_io_degraded
+                && cfg!(any(target_os = "linux", target_os = "macos")),
+            direct_io_degraded,
+            exclusive_lock: self.exclusive_lock && cfg!(unix),
+            async_write: self.async_write && cfg!(unix),
+        };
+        let path = match filepath {
+            Some(filepath) => fs::canonicalize(filepath).unwrap_or_else(|_| filepath.to_path_buf()),
+            None => file_handle_path(&file),
+        };
+        let start = self.window_start();
+        if start != 0 {
+            track_io!(file.seek(SeekFrom::Start(start)))?;
+        }
+        Ok(FileNvm::with_range(
+            file,
+            path,
+            flags,
+            start,
+            start + capacity,
+        ))
     }
 }
 
+/// `FileNvm`のオープン時に実際に適用されたフラグ群.
+///
+/// `FileNvmBuilder`で指定された値とは異なり、プラットフォームによる制約や、
+/// Direct I/Oからの切り替え(`FileNvmBuilder::direct_io_fallback`)の結果が反映されている.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileNvmFlags {
+    /// Direct I/O(LinuxではO_DIRECT、MacではF_NOCACHE)が有効かどうか.
+    pub direct_io: bool,
+
+    /// Direct I/Oが要求されたが使用できずに、通常のI/Oに切り替えられたかどうか.
+    pub direct_io_degraded: bool,
+
+    /// ファイルに対する排他ロックを取得しているかどうか.
+    pub exclusive_lock: bool,
+
+    /// 非同期書き込みが有効かどうか.
+    pub async_write: bool,
+}
+
 /// ファイルベースの`NonVolatileMemory`の実装.
 ///
 /// 現状の実装ではブロックサイズは`BlockSize::min()`に固定.
@@ -276,6 +667,23 @@ pub struct FileNvm {
     cursor_position: u64,
     view_start: u64,
     view_end: u64,
+
+    // `true`の場合には、ファイルのオフセットを変更しない位置指定I/O(`pread`)で読み込みを行う.
+    //
+    // `try_clone_reader`で生成された読み込み専用のインスタンスで使用される.
+    positioned_read: bool,
+
+    // オープンしたファイルのパス
+    path: PathBuf,
+
+    // オープン時に実際に適用されたフラグ群
+    flags: FileNvmFlags,
+
+    // 非同期書き込み用のスレッド
+    //
+    // 非同期書き込みが有効な場合に、最初の書き込み要求時に起動される.
+    #[cfg(unix)]
+    writer: Option<BackgroundWriter>,
 }
 impl FileNvm {
     /// デフォルト設定で新しい`FileNvm`インスタンスを生成する.
@@ -312,15 +720,91 @@ impl FileNvm {
         FileNvmBuilder::new().open(filepath)
     }
 
-    fn with_range(file: File, start: u64, end: u64) -> Self {
+    /// 既に開かれているファイルから`FileNvm`インスタンスを生成する.
+    ///
+    /// systemdによるファイルディスクリプタの受け渡しや、`O_TMPFILE`で作成した無名ファイル等、
+    /// パスを指定してファイルを開くことができない場合に使用する.
+    ///
+    /// `file`は読み書き可能なモードで開かれている必要がある.
+    /// `options`で指定された設定の内で、`direct_io`(Linuxでは`fcntl`で`O_DIRECT`を付与する)、
+    /// `direct_io_self_test`、`direct_io_fallback`、`exclusive_lock`および`with_offset`が反映される.
+    /// なお、ファイルの作成は行われないので、`preallocate`は無視される.
+    ///
+    /// 生成されたインスタンスの`path`は、Linuxでは`/proc/self/fd`から解決されたパスとなり、
+    /// それ以外の環境(ないし解決に失敗した場合)では空となる.
+    pub fn from_file(file: File, capacity: u64, options: &FileNvmBuilder) -> Result<Self> {
+        options.open_handle(file, capacity)
+    }
+
+    /// ファイルディスクリプタから`FileNvm`インスタンスを生成する.
+    ///
+    /// 詳細は`FileNvm::from_file`を参照のこと.
+    ///
+    /// # Safety
+    ///
+    /// `fd`は有効なファイルディスクリプタであり、その所有権が`FileNvm`に移譲される必要がある
+    /// (i.e., 呼び出し元で別途クローズしてはならない).
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(
+        fd: std::os::unix::io::RawFd,
+        capacity: u64,
+        options: &FileNvmBuilder,
+    ) -> Result<Self> {
+        use std::os::unix::io::FromRawFd;
+        Self::from_file(File::from_raw_fd(fd), capacity, options)
+    }
+
+    fn with_range(file: File, path: PathBuf, flags: FileNvmFlags, start: u64, end: u64) -> Self {
         FileNvm {
             file,
             cursor_position: start,
             view_start: start,
             view_end: end,
+            positioned_read: false,
+            path,
+            flags,
+            #[cfg(unix)]
+            writer: None,
         }
     }
 
+    /// オープンしたファイルのパスを返す.
+    ///
+    /// 可能であれば、正規化された絶対パスが返される.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// オープン時に実際に適用されたフラグ群を返す.
+    pub fn flags(&self) -> FileNvmFlags {
+        self.flags
+    }
+
+    /// Direct I/Oが要求されたが使用できずに、通常の(バッファリングありの)I/Oに切り替えられているかどうかを返す.
+    ///
+    /// 詳細は`FileNvmBuilder::direct_io_fallback`を参照のこと.
+    pub fn is_direct_io_degraded(&self) -> bool {
+        self.flags.direct_io_degraded
+    }
+
+    /// 非同期に発行された書き込みが全て完了するまで待機する.
+    ///
+    /// 同期的な読み書き等の前に呼び出すことで、それらが発行済みの書き込みを追い越さないようにする.
+    #[cfg(unix)]
+    fn wait_async_writes(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            if writer.is_busy() {
+                track!(writer.wait())?;
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn wait_async_writes(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     fn seek_impl(&mut self, position: u64) -> Result<()> {
         track_assert!(
             self.block_size().is_aligned(position),
@@ -328,7 +812,9 @@ impl FileNvm {
         );
 
         let file_position = self.view_start + position;
-        track_io!(self.file.seek(io::SeekFrom::Start(file_position)))?;
+        if !self.positioned_read {
+            track_io!(self.file.seek(io::SeekFrom::Start(file_position)))?;
+        }
         self.cursor_position = file_position;
         Ok(())
     }
@@ -338,10 +824,17 @@ impl FileNvm {
             ErrorKind::InvalidInput
         );
 
+        track!(self.wait_async_writes())?;
         let max_len = (self.capacity() - self.position()) as usize;
         let len = cmp::min(max_len, buf.len());
         let new_cursor_position = self.cursor_position + len as u64;
 
+        if self.positioned_read {
+            track!(self.positioned_read_impl(&mut buf[..len]))?;
+            self.cursor_position = new_cursor_position;
+            return Ok(len);
+        }
+
         let read_size = track_io!(self.file.read(&mut buf[..len]))?;
         if read_size < len {
             // まだ未書き込みの末尾部分から読み込みを行った場合には、
@@ -351,12 +844,27 @@ impl FileNvm {
         self.cursor_position = new_cursor_position;
         Ok(len)
     }
+    #[cfg(unix)]
+    fn positioned_read_impl(&mut self, buf: &mut [u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        // 未書き込みの末尾部分から読み込みを行った場合には、読み込みサイズが短くなりうるが、
+        // 通常の読み込みと同様に、その場合もエラーとはしない
+        track_io!(self.file.read_at(buf, self.cursor_position))?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    fn positioned_read_impl(&mut self, _buf: &mut [u8]) -> Result<()> {
+        track_panic!(ErrorKind::Other, "Positioned reads are not supported")
+    }
     fn write_impl(&mut self, buf: &[u8]) -> Result<usize> {
+        track_assert!(!self.positioned_read, ErrorKind::InvalidInput; "read-only");
         track_assert!(
             self.block_size().is_aligned(buf.len() as u64),
             ErrorKind::InvalidInput
         );
 
+        track!(self.wait_async_writes())?;
         let max_len = (self.capacity() - self.position()) as usize;
         let len = cmp::min(max_len, buf.len());
         let new_cursor_position = self.cursor_position + len as u64;
@@ -372,6 +880,7 @@ impl FileNvm {
 }
 impl NonVolatileMemory for FileNvm {
     fn sync(&mut self) -> Result<()> {
+        track!(self.wait_async_writes())?;
         track_io!(self.file.sync_data())?;
         Ok(())
     }
@@ -384,7 +893,8 @@ impl NonVolatileMemory for FileNvm {
     fn block_size(&self) -> BlockSize {
         BlockSize::min()
     }
-    fn split(self, position: u64) -> Result<(Self, Self)> {
+    fn split(mut self, position: u64) -> Result<(Self, Self)> {
+        track!(self.wait_async_writes())?;
         track_assert_eq!(
             position,
             self.block_size().ceil_align(position),
@@ -394,13 +904,147 @@ impl NonVolatileMemory for FileNvm {
         let left_file = track_io!(self.file.try_clone())?;
         let left_start = self.view_start;
         let left_end = left_start + position;
-        let left = Self::with_range(left_file, left_start, left_end);
+        let left = Self::with_range(
+            left_file,
+            self.path.clone(),
+            self.flags,
+            left_start,
+            left_end,
+        );
 
         let right_start = left_end;
         let right_end = self.view_end;
-        let right = Self::with_range(self.file, right_start, right_end);
+        let right = Self::with_range(self.file, self.path, self.flags, right_start, right_end);
         Ok((left, right))
     }
+    fn is_direct_io_degraded(&self) -> bool {
+        self.flags.direct_io_degraded
+    }
+    fn metric_labels(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("path", self.path.display().to_string()),
+            ("direct_io", self.flags.direct_io.to_string()),
+            ("exclusive_lock", self.flags.exclusive_lock.to_string()),
+        ]
+    }
+    #[cfg(target_os = "linux")]
+    fn discard(&mut self, offset: u64, size: u64) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        track_assert!(
+            offset.saturating_add(size) <= self.capacity(),
+            ErrorKind::InvalidInput
+        );
+        track!(self.wait_async_writes())?;
+        let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        let offset = (self.view_start + offset) as libc::off_t;
+        if unsafe { libc::fallocate(self.file.as_raw_fd(), mode, offset, size as libc::off_t) } != 0
+        {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                // ファイルシステムが穴あけをサポートしていない場合には、何もしない
+                return Ok(());
+            }
+            track_io!(Err(e))?;
+        }
+        Ok(())
+    }
+    #[cfg(target_os = "linux")]
+    fn advise(&mut self, offset: u64, size: u64, pattern: AccessPattern) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        track_assert!(
+            offset.saturating_add(size) <= self.capacity(),
+            ErrorKind::InvalidInput
+        );
+        let size = if size == 0 {
+            self.capacity() - offset
+        } else {
+            size
+        };
+        let advice = match pattern {
+            AccessPattern::Normal => libc::POSIX_FADV_NORMAL,
+            AccessPattern::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            AccessPattern::Random => libc::POSIX_FADV_RANDOM,
+            AccessPattern::WillNeed => libc::POSIX_FADV_WILLNEED,
+        };
+
+        // NOTE: Linuxでは`SEQUENTIAL`や`RANDOM`は(範囲に関わらず)オープンファイル記述単位で適用されるため、
+        // `split`で分割されたインスタンス間でも影響し合う点には注意が必要
+        let offset = (self.view_start + offset) as libc::off_t;
+        let e = unsafe {
+            libc::posix_fadvise(self.file.as_raw_fd(), offset, size as libc::off_t, advice)
+        };
+        if e != 0 {
+            track_io!(Err(io::Error::from_raw_os_error(e)))?;
+        }
+        Ok(())
+    }
+    #[cfg(unix)]
+    fn try_clone_reader(&self) -> Result<Option<Self>> {
+        // 複製されたファイルはオフセットを共有するため、位置指定I/Oを用いて元のインスタンスへの影響を避ける
+        let file = track_io!(self.file.try_clone())?;
+        let mut reader = Self::with_range(
+            file,
+            self.path.clone(),
+            self.flags,
+            self.view_start,
+            self.view_end,
+        );
+        reader.positioned_read = true;
+        Ok(Some(reader))
+    }
+    #[cfg(unix)]
+    fn as_async_mut(&mut self) -> Option<&mut dyn AsyncNonVolatileMemory> {
+        if self.flags.async_write && !self.positioned_read {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+#[cfg(unix)]
+impl AsyncNonVolatileMemory for FileNvm {
+    fn start_write(&mut self, offset: u64, buf: AlignedBytes) -> Result<()> {
+        track_assert!(self.flags.async_write, ErrorKind::InvalidInput);
+        track_assert!(!self.positioned_read, ErrorKind::InvalidInput; "read-only");
+        track_assert!(
+            self.block_size().is_aligned(offset) && self.block_size().is_aligned(buf.len() as u64),
+            ErrorKind::InvalidInput
+        );
+        track_assert!(
+            offset + buf.len() as u64 <= self.capacity(),
+            ErrorKind::InvalidInput,
+            "offset={}, len={}, capacity={}",
+            offset,
+            buf.len(),
+            self.capacity()
+        );
+        if self.writer.is_none() {
+            let file = track_io!(self.file.try_clone())?;
+            self.writer = Some(BackgroundWriter::spawn(file));
+        }
+        let writer = self.writer.as_mut().expect("Never fails");
+        track!(writer.request(self.view_start + offset, buf))
+    }
+    fn poll_writes(&mut self) -> Result<bool> {
+        match self.writer.as_mut() {
+            None => Ok(true),
+            Some(writer) => track!(writer.poll()),
+        }
+    }
+    fn wait_writes(&mut self) -> Result<Vec<AlignedBytes>> {
+        match self.writer.as_mut() {
+            None => Ok(Vec::new()),
+            Some(writer) => track!(writer.wait()),
+        }
+    }
+}
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for FileNvm {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.file.as_raw_fd()
+    }
 }
 impl Seek for FileNvm {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
@@ -425,7 +1069,50 @@ impl Write for FileNvm {
     }
 }
 
+/// `file`に対して、`block_size`境界に揃ったI/Oが発行可能かどうかを検査する。
+///
+/// 具体的には、先頭ブロックを読み込んで、同じ内容をそのまま書き戻す。  
+/// ファイルサイズが一ブロックに満たない場合には、書き戻し後に元のサイズに切り詰める。
+#[cfg(target_os = "linux")]
+fn check_aligned_io(file: &File, block_size: BlockSize) -> Result<()> {
+    use crate::block::AlignedBytes;
+    use std::os::unix::fs::FileExt;
+
+    let file_size = track_io!(file.metadata())?.len();
+    let mut buf = AlignedBytes::from_bytes(&vec![0; block_size.as_u16() as usize], block_size);
+    let result = file
+        .read_at(&mut buf, 0)
+        .and_then(|_| file.write_all_at(&buf, 0));
+    if let Err(e) = result {
+        if e.raw_os_error() == Some(libc::EINVAL) {
+            track_panic!(
+                ErrorKind::InvalidInput,
+                "Direct I/O aligned to {} bytes was rejected by the underlying filesystem or device \
+                 (EINVAL); it may require a larger alignment (e.g., 4096 bytes). \
+                 Consider disabling direct I/O by `FileNvmBuilder::direct_io(false)`",
+                block_size.as_u16()
+            );
+        }
+        track_io!(Err(e))?;
+    }
+    if file_size < u64::from(block_size.as_u16()) {
+        track_io!(file.set_len(file_size))?;
+    }
+    Ok(())
+}
+
 /// 親ディレクトリの作成が必要な場合は作成する。
+/// 開かれているファイルのパスを解決する.
+#[cfg(target_os = "linux")]
+fn file_handle_path(file: &File) -> PathBuf {
+    use std::os::unix::io::AsRawFd;
+    fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap_or_default()
+}
+#[cfg(not(target_os = "linux"))]
+fn file_handle_path(_file: &File) -> PathBuf {
+    PathBuf::new()
+}
+
 fn create_parent_directories<P: AsRef<Path>>(filepath: P) -> Result<()> {
     if let Some(dir) = filepath.as_ref().parent() {
         track_io!(fs::create_dir_all(dir))?;
@@ -443,7 +1130,8 @@ mod tests {
 
     use super::*;
     use crate::block::{AlignedBytes, BlockSize};
-    use crate::storage::{StorageHeader, MAJOR_VERSION, MINOR_VERSION};
+    use crate::lump::{LumpData, LumpId};
+    use crate::storage::{JournalChecksum, Storage, StorageHeader, MAJOR_VERSION, MINOR_VERSION};
 
     #[test]
     fn create_parent_directories_is_idempotent() -> TestResult {
@@ -492,6 +1180,61 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn from_file_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("foo");
+        let capacity = 1024 * 1024;
+
+        let file = track_io!(fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path))?;
+        let nvm = track!(FileNvm::from_file(file, capacity, &FileNvmBuilder::new()))?;
+        assert_eq!(nvm.capacity(), capacity);
+        #[cfg(target_os = "linux")]
+        assert_eq!(nvm.path(), track_io!(fs::canonicalize(&path))?);
+
+        let mut storage = track!(Storage::create(nvm))?;
+        track!(storage.put(&LumpId::new(0), &track!(LumpData::new(b"foo".to_vec()))?))?;
+
+        // ファイルハンドルから生成した場合にも、排他ロックは取得される
+        #[cfg(unix)]
+        assert!(FileNvm::open(&path).is_err());
+
+        mem::drop(storage);
+        let mut storage = track!(Storage::open(track!(FileNvm::open(&path))?))?;
+        assert_eq!(
+            track!(storage.get(&LumpId::new(0)))?.map(|d| d.into_bytes()),
+            Some(b"foo".to_vec())
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn from_raw_fd_works() -> TestResult {
+        use std::os::unix::io::IntoRawFd;
+
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let file = track_io!(fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(dir.path().join("foo")))?;
+        let mut options = FileNvmBuilder::new();
+        options.direct_io(false);
+        let nvm =
+            track!(unsafe { FileNvm::from_raw_fd(file.into_raw_fd(), 1024 * 1024, &options) })?;
+        assert!(!nvm.flags().direct_io);
+
+        let mut storage = track!(Storage::create(nvm))?;
+        let data = track!(LumpData::new(b"bar".to_vec()))?;
+        assert!(track!(storage.put(&LumpId::new(0), &data))?.is_new());
+        Ok(())
+    }
+
     #[test]
     fn open_and_create_works() -> TestResult {
         let dir = track_io!(TempDir::new("cannyls_test"))?;
@@ -573,6 +1316,65 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn with_offset_works() -> TestResult {
+        use crate::lump::{LumpData, LumpId};
+        use crate::storage::StorageBuilder;
+
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("container");
+        let start = 4096;
+        let len = 1024 * 1024;
+
+        // 先頭に独自の領域を持つコンテナファイルを用意する
+        track_io!(fs::write(&path, &[0xAB; 4096][..]))?;
+
+        // 作成
+        let nvm = track!(FileNvmBuilder::new()
+            .with_offset(start, len)
+            .create(&path, len))?;
+        let mut storage = track!(StorageBuilder::new().create(nvm))?;
+        let lump_id = LumpId::new(10);
+        track!(storage.put(&lump_id, &track!(LumpData::new(b"foo".to_vec()))?))?;
+        mem::drop(storage);
+
+        // 再オープン
+        let (nvm, created) = track!(FileNvmBuilder::new()
+            .with_offset(start, len)
+            .create_if_absent(&path, len))?;
+        assert!(!created);
+        let mut storage = track!(StorageBuilder::new().open(nvm))?;
+        assert_eq!(
+            track!(storage.get(&lump_id))?.map(|d| d.as_bytes().to_vec()),
+            Some(b"foo".to_vec())
+        );
+        mem::drop(storage);
+
+        // 範囲外の内容は保持されている
+        let mut head = vec![0; start as usize];
+        track_io!(File::open(&path).and_then(|mut f| f.read_exact(&mut head)))?;
+        assert!(head.iter().all(|&b| b == 0xAB));
+
+        // 不正な範囲指定
+        assert_eq!(
+            FileNvmBuilder::new()
+                .with_offset(start + 1, len)
+                .open(&path)
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::BlockSizeMismatch)
+        );
+        assert_eq!(
+            FileNvmBuilder::new()
+                .with_offset(start, len / 2)
+                .open(&path)
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::CapacityTooSmall)
+        );
+        Ok(())
+    }
+
     #[test]
     fn error_handlings_works() -> TestResult {
         let dir = track_io!(TempDir::new("cannyls_test"))?;
@@ -629,6 +1431,256 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn check_aligned_io_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        // 空のファイルのサイズは変わらない
+        let filepath = dir.path().join("empty");
+        let file = track_io!(fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&filepath))?;
+        track!(check_aligned_io(&file, BlockSize::min()))?;
+        assert_eq!(track_io!(file.metadata())?.len(), 0);
+
+        // 既存のデータは変わらない
+        let filepath = dir.path().join("nonempty");
+        track_io!(fs::write(&filepath, vec![7; 1024]))?;
+        let file = track_io!(fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&filepath))?;
+        track!(check_aligned_io(&file, BlockSize::min()))?;
+        mem::drop(file);
+        assert_eq!(track_io!(fs::read(&filepath))?, vec![7; 1024]);
+
+        // Direct I/Oでのオープン時にも検査が行われる
+        let mut nvm = track!(FileNvmBuilder::new()
+            .direct_io_self_test(true)
+            .create(dir.path().join("foo"), 1024))?;
+        track_io!(nvm.write_all(&aligned_bytes(&[1; 512][..])))?;
+        Ok(())
+    }
+
+    #[test]
+    fn direct_io_fallback_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        // Direct I/Oが使用可能な場合には、切り替えは行われない
+        let nvm = track!(FileNvmBuilder::new()
+            .direct_io_fallback(true)
+            .create(dir.path().join("foo"), 1024))?;
+        assert!(!nvm.is_direct_io_degraded());
+        mem::drop(nvm);
+
+        // 切り替えが行われた場合には、分割や複製後のインスタンスにも引き継がれる
+        let builder = FileNvmBuilder::new();
+        let filepath = dir.path().join("bar");
+        let file = track!(builder.open_without_direct_io(true, &filepath))?;
+        let mut nvm = track!(builder.initialize(file, Some(&filepath), 1024, true))?;
+        assert!(nvm.is_direct_io_degraded());
+        track_io!(nvm.write_all(&aligned_bytes(&[1; 1024][..])))?;
+
+        let reader = track_assert_some!(track!(nvm.try_clone_reader())?, ErrorKind::Other);
+        assert!(reader.is_direct_io_degraded());
+        let (left, right) = track!(nvm.split(512))?;
+        assert!(left.is_direct_io_degraded());
+        assert!(right.is_direct_io_degraded());
+        Ok(())
+    }
+
+    #[test]
+    fn path_and_flags_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let filepath = dir.path().join("foo");
+
+        let nvm = track!(FileNvmBuilder::new()
+            .direct_io(false)
+            .exclusive_lock(false)
+            .create(&filepath, 1024))?;
+        assert_eq!(nvm.path(), track_io!(fs::canonicalize(&filepath))?);
+        assert_eq!(
+            nvm.flags(),
+            FileNvmFlags {
+                direct_io: false,
+                direct_io_degraded: false,
+                exclusive_lock: false,
+                async_write: false,
+            }
+        );
+        mem::drop(nvm);
+
+        // デフォルト設定で開き直す(ファイルは空なので、新規作成扱いとなる)
+        let (nvm, _) = track!(FileNvm::create_if_absent(&filepath, 1024))?;
+        assert_eq!(
+            nvm.flags().direct_io,
+            cfg!(any(target_os = "linux", target_os = "macos"))
+        );
+        assert_eq!(nvm.flags().exclusive_lock, cfg!(unix));
+
+        // 分割後のインスタンスにも引き継がれる
+        let (left, right) = track!(nvm.split(512))?;
+        assert_eq!(left.path(), right.path());
+        assert_eq!(left.metric_labels(), right.metric_labels());
+        assert_eq!(left.metric_labels()[0].0, "path");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            assert!(left.as_raw_fd() >= 0);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocate_works() -> TestResult {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let capacity = 1024 * 1024;
+
+        let nvm = track!(FileNvmBuilder::new().create(dir.path().join("foo"), capacity))?;
+        let metadata = track_io!(nvm.inner().metadata())?;
+        assert_eq!(metadata.len(), 0);
+        assert_eq!(metadata.blocks(), 0);
+
+        let (nvm, created) = track!(FileNvmBuilder::new()
+            .preallocate(true)
+            .create_if_absent(dir.path().join("bar"), capacity))?;
+        assert!(created);
+        let metadata = track_io!(nvm.inner().metadata())?;
+        assert_eq!(metadata.len(), 0); // サイズは変わらない
+        assert!(metadata.blocks() * 512 >= capacity || metadata.blocks() == 0); // 非対応のファイルシステムでは確保されない
+
+        // 空き容量を超える確保は失敗する
+        let available = track_io!(available_bytes(dir.path()))?;
+        let result = FileNvmBuilder::new()
+            .preallocate(true)
+            .create(dir.path().join("buzz"), available + (1 << 30));
+        if metadata.blocks() != 0 {
+            let e = result.expect_err("must fail");
+            assert_eq!(*e.kind(), ErrorKind::StorageFull);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn available_bytes(path: &Path) -> io::Result<u64> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn advise_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let nvm = track!(FileNvm::create(dir.path().join("foo"), 2048))?;
+        let (mut left, mut right) = track!(nvm.split(1024))?;
+
+        for &pattern in &[
+            AccessPattern::Sequential,
+            AccessPattern::Random,
+            AccessPattern::WillNeed,
+            AccessPattern::Normal,
+        ] {
+            track!(left.advise(0, 0, pattern))?;
+            track!(right.advise(512, 512, pattern))?;
+        }
+
+        // 範囲外
+        assert!(right.advise(512, 1024, AccessPattern::Normal).is_err());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn discard_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let mut nvm = track!(FileNvmBuilder::new()
+            .direct_io(false)
+            .create(dir.path().join("foo"), 1024))?;
+        track_io!(nvm.write_all(&aligned_bytes(&[1; 1024][..])))?;
+
+        // 後半のみを破棄する
+        track!(nvm.discard(512, 512))?;
+
+        let mut buf = aligned_bytes_with_size(1024);
+        track_io!(nvm.seek(SeekFrom::Start(0)))?;
+        track_io!(nvm.read_exact(&mut buf))?;
+        assert_eq!(&buf[..512], &[1; 512][..]);
+        assert_eq!(&buf[512..], &[0; 512][..]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn async_write_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        // デフォルトでは無効
+        let mut nvm = track!(FileNvm::create(dir.path().join("foo"), 1024))?;
+        assert!(!nvm.flags().async_write);
+        assert!(nvm.as_async_mut().is_none());
+
+        let nvm = track!(FileNvmBuilder::new()
+            .direct_io(false)
+            .async_write(true)
+            .create(dir.path().join("bar"), 2048))?;
+        assert!(nvm.flags().async_write);
+        let (_, mut nvm) = track!(nvm.split(512))?;
+        {
+            let writer = nvm.as_async_mut().expect("async write is enabled");
+            track!(writer.start_write(0, aligned_bytes(&[1; 512][..])))?;
+            track!(writer.start_write(512, aligned_bytes(&[2; 1024][..])))?;
+
+            // 後から発行された書き込みが優先される
+            track!(writer.start_write(1024, aligned_bytes(&[3; 512][..])))?;
+
+            // 範囲外ないし非アライメント
+            assert!(writer
+                .start_write(1024, aligned_bytes(&[0; 1024][..]))
+                .is_err());
+            assert!(writer.start_write(1, aligned_bytes(&[0; 512][..])).is_err());
+
+            let bufs = track!(writer.wait_writes())?;
+            assert_eq!(bufs.len(), 3);
+            assert!(track!(writer.poll_writes())?);
+        }
+
+        // 分割位置以降に書き込まれている
+        let mut buf = aligned_bytes_with_size(1536);
+        track_io!(nvm.seek(SeekFrom::Start(0)))?;
+        track_io!(nvm.read_exact(&mut buf))?;
+        assert_eq!(&buf[..512], &[1; 512][..]);
+        assert_eq!(&buf[512..1024], &[2; 512][..]);
+        assert_eq!(&buf[1024..], &[3; 512][..]);
+
+        // 同期的な操作は、発行済みの書き込みの完了後に行われる
+        track!(nvm
+            .as_async_mut()
+            .expect("Never fails")
+            .start_write(0, aligned_bytes(&[4; 512][..])))?;
+        track_io!(nvm.seek(SeekFrom::Start(0)))?;
+        track_io!(nvm.read_exact(&mut buf))?;
+        assert_eq!(&buf[..512], &[4; 512][..]);
+
+        // 読み込み専用のインスタンスでは使用できない
+        let mut reader = track!(nvm.try_clone_reader())?.expect("Never fails");
+        assert!(reader.as_async_mut().is_none());
+        Ok(())
+    }
+
     #[cfg(target_os = "linux")]
     fn direct_io_flag() -> i32 {
         libc::O_DIRECT
@@ -740,10 +1792,18 @@ mod tests {
         StorageHeader {
             major_version: MAJOR_VERSION,
             minor_version: MINOR_VERSION,
+            original_minor_version: MINOR_VERSION,
             block_size: BlockSize::min(),
             instance_uuid: Uuid::new_v4(),
             journal_region_size: 1024,
             data_region_size: 4096,
+            journal_checksum: JournalChecksum::default(),
+            journal_header_slots: 2,
+            backup_header: true,
+            writer_version: Some(crate::CrateVersion::CURRENT),
+            logical_name: None,
+            sparse_lumps: false,
+            creation_params: None,
         }
     }
 }
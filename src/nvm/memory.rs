@@ -24,6 +24,7 @@ impl MemoryNvm {
         }
     }
 
+    /// 内部のバイト列を返す(テスト用).
     #[cfg(test)]
     pub fn as_bytes(&self) -> &[u8] {
         self.memory.get_ref()
@@ -80,6 +81,25 @@ impl NonVolatileMemory for MemoryNvm {
         self.memory.set_position(0);
         Ok((self, right))
     }
+    fn discard(&mut self, offset: u64, size: u64) -> Result<()> {
+        let end = offset.checked_add(size);
+        track_assert!(
+            end.is_some_and(|end| end <= self.capacity()),
+            ErrorKind::InvalidInput,
+            "offset={}, size={}, capacity={}",
+            offset,
+            size,
+            self.capacity()
+        );
+
+        // 穴あけされた領域と同様に、ゼロ埋めを行う
+        let end = end.expect("Never fails") as usize;
+        let memory = &mut self.memory.get_mut()[offset as usize..end];
+        for b in memory {
+            *b = 0;
+        }
+        Ok(())
+    }
 }
 impl Seek for MemoryNvm {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
@@ -152,4 +172,21 @@ mod tests {
         assert!(right.read_exact(&mut buf).is_err());
         Ok(())
     }
+
+    #[test]
+    fn discard_works() -> TestResult {
+        let mut nvm = MemoryNvm::new(vec![1; 1024]);
+        track!(nvm.discard(512, 512))?;
+        assert_eq!(&nvm.as_bytes()[..512], &[1; 512][..]);
+        assert_eq!(&nvm.as_bytes()[512..], &[0; 512][..]);
+
+        // 範囲外やオーバーフローする指定はエラーとなる
+        let e = nvm.discard(512, 1024).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let e = nvm.discard(512, u64::MAX).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let e = nvm.discard(2048, 0).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
 }
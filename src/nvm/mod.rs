@@ -4,17 +4,71 @@
 //! 永続化領域を提供する.
 use std::io::{Read, Seek, SeekFrom, Write};
 
-pub use self::file::{FileNvm, FileNvmBuilder};
+pub use self::file::{FileNvm, FileNvmBuilder, FileNvmFlags};
 pub use self::memory::MemoryNvm;
 pub use self::shared_memory::SharedMemoryNvm;
 
 use crate::block::{AlignedBytes, BlockSize};
 use crate::{ErrorKind, Result};
 
+#[cfg(unix)]
+mod background_writer;
 mod file;
 mod memory;
 mod shared_memory;
 
+/// `NonVolatileMemory::advise`で通知されるアクセスパターン.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessPattern {
+    /// 特に指定なし(デフォルト).
+    Normal,
+
+    /// 先頭から順番にアクセスされる(e.g., ジャーナルの復元や走査).
+    Sequential,
+
+    /// ランダムにアクセスされる(e.g., 通常運用時のデータ領域).
+    Random,
+
+    /// 近い将来にアクセスされる.
+    WillNeed,
+}
+
+/// 書き込みの完了を待たずに、次の処理に進むことが可能な不揮発性メモリを表すトレイト.
+///
+/// 書き込みの発行と完了の待機を分離することで、書き込みが物理デバイス上で実行されている間に、
+/// 呼び出し側では次のデータの準備(e.g., ジャーナルレコードのエンコードやチェックサムの計算)を進めることができる.
+///
+/// 発行済みの書き込みは、発行順に反映されなければならない.
+/// また、`NonVolatileMemory`としての各操作(e.g., 読み書きや同期)は、
+/// 発行済みの書き込みが全て完了した後の状態に対して行われなければならない.
+///
+/// 現在は、ジャーナル領域の書き込みバッファのフラッシュにのみ使用されている.
+/// データ領域への書き込みは、それを参照するジャーナルレコードが同期されるよりも前に
+/// 完了している必要があるため、従来通り同期的に行われる.
+pub trait AsyncNonVolatileMemory {
+    /// `offset`の位置に`buf`の内容を書き込む要求を発行する.
+    ///
+    /// 書き込みの完了は待たずに、すぐに返る.
+    /// `offset`および`buf`の長さは、ブロック境界に揃っている必要がある.
+    ///
+    /// # Errors
+    ///
+    /// 書き込み範囲が容量を超えている場合や、アライメントされていない場合には、
+    /// 種類が`ErrorKind::InvalidInput`のエラーが返される.
+    fn start_write(&mut self, offset: u64, buf: AlignedBytes) -> Result<()>;
+
+    /// 発行済みの書き込みが全て完了しているかどうかを、ブロックせずに返す.
+    ///
+    /// 完了した書き込みの中に失敗したものがある場合には、そのエラーが返される.
+    fn poll_writes(&mut self) -> Result<bool>;
+
+    /// 発行済みの書き込みが全て完了するまで待機する.
+    ///
+    /// 結果は、書き込みが完了したバッファ群(呼び出し側で再利用可能).
+    /// 完了した書き込みの中に失敗したものがある場合には、そのエラーが返される.
+    fn wait_writes(&mut self) -> Result<Vec<AlignedBytes>>;
+}
+
 /// 不揮発性メモリを表すトレイト.
 ///
 /// "不揮発性メモリ"は「永続化可能なバイト列(領域)」を意味し、lump群を保存するために使用される.
@@ -54,6 +108,68 @@ pub trait NonVolatileMemory: Sized + Read + Write + Seek {
     /// - `position`がブロック境界ではない
     fn split(self, position: u64) -> Result<(Self, Self)>;
 
+    /// 指定範囲のデータが不要になったことを、物理デバイスに通知する(e.g., TRIM, hole punching).
+    ///
+    /// 通知後の範囲の内容は不定となる.
+    /// `offset`および`size`は、ブロック境界に揃っている必要がある.
+    ///
+    /// 通知をサポートしない実装では、何も行わずに成功を返しても構わない.
+    /// デフォルト実装では何も行わない.
+    fn discard(&mut self, _offset: u64, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// 指定範囲に対する今後のアクセスパターンを、物理デバイス(ないしOS)に通知する(e.g., `posix_fadvise`).
+    ///
+    /// これはあくまでもヒントであり、読み書きの結果には影響を与えない.
+    /// `offset`および`size`は、開始地点からのバイト単位の位置と長さ.
+    /// `size`に`0`が指定された場合には、`offset`以降の全体が対象となる.
+    ///
+    /// 通知をサポートしない実装では、何も行わずに成功を返しても構わない.
+    /// デフォルト実装では何も行わない.
+    fn advise(&mut self, _offset: u64, _size: u64, _pattern: AccessPattern) -> Result<()> {
+        Ok(())
+    }
+
+    /// バッファリングなしI/O(e.g., `O_DIRECT`)が要求されたが使用できずに、
+    /// 通常のI/Oに切り替えられて動作しているかどうかを返す.
+    ///
+    /// デフォルト実装では常に`false`を返す.
+    fn is_direct_io_degraded(&self) -> bool {
+        false
+    }
+
+    /// インスタンスを識別するための情報を、メトリクスのラベル(名前と値の組)として返す.
+    ///
+    /// 例えばファイルベースの実装であれば、ファイルのパス等を返すことで、
+    /// 運用時にデバイスと実際のディスクとの対応付けが容易になる.
+    ///
+    /// デフォルト実装では空の配列を返す.
+    fn metric_labels(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// 別スレッドからの読み込みに使用するための、同じ領域を参照する独立したインスタンスを生成する.
+    ///
+    /// 生成されたインスタンスに対する操作(e.g., シーク)は、元のインスタンスに影響を与えてはならない.
+    /// また、生成されたインスタンスは読み込み専用として扱われる.
+    ///
+    /// この機能をサポートしない実装では`None`を返す.
+    /// デフォルト実装では常に`None`を返す.
+    fn try_clone_reader(&self) -> Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// 非同期書き込み用のインタフェースを返す.
+    ///
+    /// 詳細は`AsyncNonVolatileMemory`を参照のこと.
+    ///
+    /// この機能をサポートしない(ないし無効化されている)実装では`None`を返す.
+    /// デフォルト実装では常に`None`を返す.
+    fn as_async_mut(&mut self) -> Option<&mut dyn AsyncNonVolatileMemory> {
+        None
+    }
+
     /// `SeekFrom`形式で指定された位置を、開始地点からのオフセットに変換する.
     ///
     /// # Errors
@@ -28,6 +28,7 @@ impl SharedMemoryNvm {
         Self::with_block_size(memory, BlockSize::min())
     }
 
+    /// 内部のバイト列のコピーを返す(テスト用).
     #[cfg(test)]
     pub fn to_bytes(&self) -> Vec<u8> {
         let lock = self.memory.lock().unwrap();
@@ -79,7 +80,7 @@ impl SharedMemoryNvm {
 
         let size = track!(self.with_bytes_mut(|memory| {
             let len = cmp::min(memory.len(), buf.len());
-            (&mut buf[..len]).copy_from_slice(&memory[..len]);
+            buf[..len].copy_from_slice(&memory[..len]);
             len
         }))?;
         self.position += size;
@@ -94,7 +95,7 @@ impl SharedMemoryNvm {
 
         let size = track!(self.with_bytes_mut(|memory| {
             let len = cmp::min(memory.len(), buf.len());
-            (&mut memory[..len]).copy_from_slice(&buf[..len]);
+            memory[..len].copy_from_slice(&buf[..len]);
             len
         }))?;
         self.position += size;
@@ -132,6 +133,29 @@ impl NonVolatileMemory for SharedMemoryNvm {
 
         Ok((left, right))
     }
+    fn discard(&mut self, offset: u64, size: u64) -> Result<()> {
+        track_assert!(
+            offset.saturating_add(size) <= self.capacity(),
+            ErrorKind::InvalidInput
+        );
+
+        // 穴あけされた領域と同様に、ゼロ埋めを行う
+        let start = self.memory_start + offset as usize;
+        let end = start + size as usize;
+        match self.memory.lock() {
+            Ok(mut lock) => {
+                for b in &mut lock[start..end] {
+                    *b = 0;
+                }
+                Ok(())
+            }
+            Err(error) => Err(track!(Error::from(error))),
+        }
+    }
+    fn try_clone_reader(&self) -> Result<Option<Self>> {
+        // カーソル位置はインスタンス毎に独立しているので、単に複製すれば良い
+        Ok(Some(self.clone()))
+    }
 }
 impl Seek for SharedMemoryNvm {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
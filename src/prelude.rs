@@ -0,0 +1,23 @@
+//! `cannyls`を利用する際に頻繁に使用される型群をまとめて再エクスポートするためのモジュール.
+//!
+//! 以下のように`use`することで、典型的な用途に必要な型を一括でインポート可能.
+//!
+//! ```
+//! use cannyls::nvm::MemoryNvm;
+//! use cannyls::prelude::*;
+//!
+//! # fn main() -> Result<()> {
+//! let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+//! let mut storage = StorageBuilder::new().create(nvm)?;
+//! storage.put(&LumpId::new(1), &LumpData::new(b"foo".to_vec())?)?;
+//! assert_eq!(storage.get(&LumpId::new(1))?.map(|d| d.into_bytes()), Some(b"foo".to_vec()));
+//! # Ok(())
+//! # }
+//! ```
+pub use crate::deadline::Deadline;
+#[cfg(feature = "device")]
+pub use crate::device::{Device, DeviceBuilder, DeviceHandle};
+pub use crate::lump::{LumpData, LumpId};
+pub use crate::nvm::FileNvm;
+pub use crate::storage::{Storage, StorageBuilder};
+pub use crate::{Error, ErrorKind, Result};
@@ -1,11 +1,14 @@
 //! Data Portion Allocator.
 
 use std::cmp;
-use std::collections::BTreeSet;
+use std::collections::{btree_set, BTreeMap, BTreeSet};
+use std::fmt;
+use std::iter::Peekable;
 use std::ops::Bound::{Excluded, Included, Unbounded};
 
 use super::free_portion::{EndBasedFreePortion, FreePortion, SizeBasedFreePortion};
 use super::U24;
+use crate::lump::LumpId;
 use crate::metrics::DataAllocatorMetrics;
 use crate::storage::portion::DataPortion;
 use crate::storage::Address;
@@ -34,83 +37,443 @@ use crate::{ErrorKind, Result};
 ///
 /// 選択された空き領域は、その中から要求サイズ分だけの割当を行い、
 /// もしまだ余剰分がある場合には、再び空き領域リストに戻される.
+///
+/// # 断片の隔離
+///
+/// 空き領域のリストが非常に長くなると、その探索や更新のコストが増加する.
+/// `set_fragment_threshold`で閾値を指定した場合には、サイズが閾値未満の空き領域(断片)は、
+/// 空き領域のリストには含まれずに、別枠で管理されるようになる.
+/// また`set_max_free_portions`で上限を指定した場合には、リストの長さが上限を超えた時点で、
+/// リスト内の最小の空き領域が断片として別枠に移される.
+///
+/// 断片は、隣接する領域の解放時には通常通りに併合され、併合後のサイズが閾値以上であればリストに戻る.
+/// それ以外の場合には`reclaim_fragments`が呼ばれるまで、割当に使用されることはない.
+///
+/// # 大きな部分領域のアライメント
+///
+/// `set_alignment`で指定した場合には、閾値以上のサイズの割当要求に対しては、
+/// 開始位置がアライメント境界に揃っている部分領域が割り当てられる.
+/// 小さな部分領域と大きな部分領域とが入り混じって配置されることによる断片化を抑制するためのもの.
+///
+/// この場合には、要求サイズを満たす空き領域をサイズの昇順に走査して、
+/// 境界に揃えた上で要求サイズを収容可能な最初のものが選択される.
+/// そのような空き領域が存在しない場合には、通常通りの"BestFit"戦略での割当が行われる.
 #[derive(Debug)]
 pub struct DataPortionAllocator {
     size_to_free: BTreeSet<SizeBasedFreePortion>,
     end_to_free: BTreeSet<EndBasedFreePortion>,
+    fragments: BTreeSet<EndBasedFreePortion>,
+    fragment_threshold: U24,
+    max_free_portions: usize,
+    alignment: Option<Alignment>,
     metrics: DataAllocatorMetrics,
 }
 impl DataPortionAllocator {
     /// アロケータを構築する.
     ///
-    /// `portions`には、既に割当済みの部分領域群が列挙されている.
+    /// `portions`には、既に割当済みの部分領域群が、その所有者であるlumpのIDと共に列挙されている.
     ///
     /// アロケータが利用可能な領域のサイズ（キャパシティ）の情報は、`metrics`から取得される.
+    ///
+    /// # エラー
+    ///
+    /// 部分領域同士が重複していたり、部分領域がキャパシティの範囲外を指している場合には、
+    /// `ErrorKind::StorageCorrupted`エラーが返される.
+    /// エラーメッセージには、衝突しているlumpのID群が含まれる.
     pub fn build<I>(metrics: DataAllocatorMetrics, portions: I) -> Result<Self>
     where
-        I: Iterator<Item = DataPortion>,
+        I: Iterator<Item = (LumpId, DataPortion)> + Clone,
+    {
+        let (allocator, dropped) = Self::build_impl(metrics, portions.clone());
+        if !dropped.is_empty() {
+            let conflicts = resolve_conflicts(portions, &dropped);
+            track_panic!(
+                ErrorKind::StorageCorrupted,
+                "Overlapping data portions: count={}, conflicts=[{}]{}",
+                dropped.len(),
+                conflicts
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if dropped.len() > conflicts.len() {
+                    " ..."
+                } else {
+                    ""
+                }
+            );
+        }
+        Ok(allocator)
+    }
+
+    /// アロケータを構築する.
+    ///
+    /// `build`とは異なり、他の部分領域と重複している部分領域やキャパシティの範囲外を指している部分領域は、
+    /// エラーとはせずに、割当済みの部分領域群から除外する.
+    ///
+    /// 部分領域同士が重複している場合に、どちらを残すかは`order`によって決定される.
+    /// `order`には、重複に関与している部分領域群が渡され、それぞれの順位(e.g., ジャーナル上での登録順)を返す必要がある.
+    /// 順位が小さい方が残され、大きい方(順位が不明なものを含む)が除外される.
+    /// `order`は、重複が存在する場合にのみ呼び出される.
+    ///
+    /// 結果には、構築されたアロケータと、除外された部分領域を所有していたlumpのID群が含まれる.
+    pub fn build_dropping_conflicts<I, F>(
+        metrics: DataAllocatorMetrics,
+        portions: I,
+        order: F,
+    ) -> Result<(Self, Vec<LumpId>)>
+    where
+        I: Iterator<Item = (LumpId, DataPortion)> + Clone,
+        F: FnOnce(&BTreeMap<LumpId, DataPortion>) -> Result<BTreeMap<LumpId, u64>>,
+    {
+        let (mut allocator, mut dropped, mut allocated) =
+            Self::carve_all(metrics.clone(), portions.clone());
+        if !dropped.is_empty() {
+            // 重複に関与している部分領域群のみを順位順に並べ、それ以外の部分領域群の後に切り出し直す
+            // (関与していない部分領域同士は重複していないので、その順番は結果に影響しない)
+            let involved = involved_portions(portions.clone(), &dropped);
+            let order = track!(order(&involved))?;
+            let mut ordered = involved
+                .iter()
+                .map(|(&lump_id, &portion)| (lump_id, portion))
+                .collect::<Vec<_>>();
+            ordered.sort_by_key(|(lump_id, _)| order.get(lump_id).map_or(u64::MAX, |&n| n));
+            let rest = portions.filter(|(lump_id, _)| !involved.contains_key(lump_id));
+            (allocator, dropped, allocated) = Self::carve_all(metrics, rest.chain(ordered));
+        }
+        allocator.record_starting_metrics(allocated);
+        let dropped = dropped.into_iter().map(|(lump_id, _)| lump_id).collect();
+        Ok((allocator, dropped))
+    }
+
+    /// チェックポイントに保存されていた空き領域リストから、アロケータを復元する.
+    ///
+    /// `allocated_portions`には、割当済みの部分領域の数を指定する(メトリクス用).
+    ///
+    /// 空き領域同士が重複していたり、キャパシティの範囲外を指している場合には、
+    /// `ErrorKind::StorageCorrupted`エラーが返される.
+    pub fn restore<I>(
+        metrics: DataAllocatorMetrics,
+        free_portions: I,
+        allocated_portions: u64,
+    ) -> Result<Self>
+    where
+        I: Iterator<Item = FreePortion>,
     {
         let block_size = u64::from(metrics.block_size.as_u16());
-        let mut portions = portions.collect::<Vec<_>>();
+        let capacity = metrics.capacity_bytes / block_size;
+        let mut allocator = DataPortionAllocator {
+            size_to_free: BTreeSet::new(),
+            end_to_free: BTreeSet::new(),
+            fragments: BTreeSet::new(),
+            fragment_threshold: 0,
+            max_free_portions: 0,
+            alignment: None,
+            metrics,
+        };
+        let mut free_blocks = 0;
+        for free in free_portions {
+            track_assert!(free.len() > 0, ErrorKind::StorageCorrupted; free);
+            track_assert!(free.end().as_u64() <= capacity, ErrorKind::StorageCorrupted; free);
+            if let Some(next) = allocator.next_free_portion(free.start().as_u64()) {
+                // 既に登録済みの空き領域と重複していないかを確認する
+                track_assert!(free.end() <= next.start(), ErrorKind::StorageCorrupted; free, next);
+            }
+            allocator.insert_free_portion(free);
+            free_blocks += u64::from(free.len());
+        }
+
+        let metrics = &allocator.metrics;
         metrics
             .allocated_portions_at_starting
-            .add_u64(portions.len() as u64);
+            .add_u64(allocated_portions);
         metrics
             .allocated_bytes_at_starting
-            .add_u64(portions.iter().map(|p| u64::from(p.len) * block_size).sum());
+            .add_u64((capacity - free_blocks) * block_size);
+        metrics
+            .inserted_free_portions
+            .add_u64(allocator.size_to_free.len() as u64);
+        Ok(allocator)
+    }
 
-        let sentinel = DataPortion {
-            start: Address::from(0),
-            len: 0,
+    /// 断片を含む全ての空き領域を、終端位置の昇順で返す.
+    pub fn free_portions(&self) -> impl ExactSizeIterator<Item = FreePortion> + '_ {
+        FreePortions {
+            listed: self.end_to_free.iter().peekable(),
+            fragments: self.fragments.iter().peekable(),
+        }
+    }
+
+    /// 最大の空き領域を返す.
+    ///
+    /// 断片として隔離されている空き領域は対象外.
+    pub fn largest_free_portion(&self) -> Option<FreePortion> {
+        self.size_to_free.iter().next_back().map(|p| p.0)
+    }
+
+    /// 空き領域を断片として隔離するための閾値(ブロック数)を設定する.
+    ///
+    /// サイズがこの値未満の空き領域は、空き領域のリストから外されて、割当時の探索対象外となる.
+    /// `0`ないし`1`の場合には、サイズによる隔離は行われない(デフォルト).
+    ///
+    /// 既存の空き領域(断片を含む)も、新しい閾値に従って振り分け直される.
+    pub fn set_fragment_threshold(&mut self, threshold: u16) {
+        self.fragment_threshold = U24::from(threshold);
+        self.redistribute_free_portions();
+    }
+
+    /// 空き領域のリストの長さの上限を設定する.
+    ///
+    /// リストの長さが上限を超えた場合には、リスト内の最小の空き領域から順に断片として隔離される.
+    /// `0`の場合には、上限は設けられない(デフォルト).
+    ///
+    /// 既存の空き領域(断片を含む)も、新しい上限に従って振り分け直される.
+    pub fn set_max_free_portions(&mut self, max: usize) {
+        self.max_free_portions = max;
+        self.redistribute_free_portions();
+    }
+
+    /// 断片として隔離されている空き領域を、空き領域のリストに戻す.
+    ///
+    /// リストの長さの上限が設定されている場合には、上限に達するまで、サイズの大きいものから順に戻される.
+    /// 戻された空き領域の数が返される.
+    ///
+    /// リストに戻された空き領域は、以後の割当や併合によって変更されるまでは、閾値未満のサイズでもリストに残る.
+    pub fn reclaim_fragments(&mut self) -> usize {
+        let mut fragments = self.fragments.iter().map(|p| p.0).collect::<Vec<_>>();
+        fragments.sort_by_key(|p| cmp::Reverse(p.len()));
+        if self.max_free_portions > 0 {
+            let room = self
+                .max_free_portions
+                .saturating_sub(self.size_to_free.len());
+            fragments.truncate(room);
+        }
+        for &fragment in &fragments {
+            self.remove_free_portion(fragment);
+            self.insert_listed_free_portion(fragment);
+            self.metrics.inserted_free_portions.increment();
+        }
+        fragments.len()
+    }
+
+    /// サイズが`threshold`ブロック以上の部分領域を、`alignment`ブロックの境界に揃えて割り当てるように設定する.
+    ///
+    /// 境界はデータ領域の先頭からの相対位置で計算される.
+    /// `alignment`が`0`ないし`1`の場合には、アライメントは行われない(デフォルト).
+    pub fn set_alignment(&mut self, threshold: u16, alignment: u32) {
+        self.alignment = if alignment > 1 {
+            Some(Alignment {
+                threshold,
+                alignment: u64::from(alignment),
+            })
+        } else {
+            None
         };
-        portions.push(sentinel);
-        // DataPortionの終端を用いて降順ソートを行う。
-        // すなわち、ソート後は、先頭であればあるほどend()の値は大きい。
-        portions.sort_by_key(|&b| std::cmp::Reverse(b.end()));
-
-        // 変数tailの意味は次の通り:
-        // tail位置には値が書き込めない・書き込まれている、すなわち空いてはいない。
-        let mut tail = metrics.capacity_bytes / block_size;
+    }
+
+    fn build_impl<I>(
+        metrics: DataAllocatorMetrics,
+        portions: I,
+    ) -> (Self, Vec<(LumpId, DataPortion)>)
+    where
+        I: Iterator<Item = (LumpId, DataPortion)>,
+    {
+        let (allocator, dropped, allocated) = Self::carve_all(metrics, portions);
+        allocator.record_starting_metrics(allocated);
+        (allocator, dropped)
+    }
+
+    // データ領域全体から`portions`を順に切り出したアロケータを構築する.
+    //
+    // キャパシティの範囲外の部分領域や、既に切り出し済みの部分領域と重複している部分領域は、結果の二つ目の要素に含まれる.
+    // 三つ目の要素は、切り出せた部分領域の数.
+    // 起動時のメトリクスは更新されないので、`record_starting_metrics`を別途呼び出す必要がある.
+    fn carve_all<I>(
+        metrics: DataAllocatorMetrics,
+        portions: I,
+    ) -> (Self, Vec<(LumpId, DataPortion)>, u64)
+    where
+        I: Iterator<Item = (LumpId, DataPortion)>,
+    {
+        let block_size = u64::from(metrics.block_size.as_u16());
+        let capacity = metrics.capacity_bytes / block_size;
         let mut allocator = DataPortionAllocator {
             size_to_free: BTreeSet::new(),
             end_to_free: BTreeSet::new(),
+            fragments: BTreeSet::new(),
+            fragment_threshold: 0,
+            max_free_portions: 0,
+            alignment: None,
             metrics,
         };
+
+        // まずはデータ領域全体を空き領域として登録し、そこから割当済みの部分領域を順に切り出していく.
+        //
+        // 部分領域群を位置順に並び替える必要がないため、
+        // 全ての部分領域を一時的に保持するためのバッファは不要となる.
+        let mut start = 0;
+        while start < capacity {
+            let size = cmp::min(0xFF_FFFF, capacity - start) as U24; // 最大でも24バイト表現なので切り詰めを行う
+            allocator
+                .insert_free_portion(FreePortion::new(Address::from_u64(start).unwrap(), size));
+            start += u64::from(size);
+        }
+
+        let mut dropped = Vec::new();
+        let mut allocated_portions = 0;
+        for (lump_id, portion) in portions {
+            if portion.end().as_u64() > capacity || !allocator.carve_free_portions(portion) {
+                // キャパシティの範囲外、あるいは既に切り出し済みの部分領域と重複している
+                dropped.push((lump_id, portion));
+                continue;
+            }
+            allocated_portions += 1;
+        }
+        allocator.coalesce_free_portions();
+        (allocator, dropped, allocated_portions)
+    }
+
+    // 構築時点での割当状況を、起動時のメトリクスに反映する.
+    fn record_starting_metrics(&self, allocated_portions: u64) {
+        let block_size = u64::from(self.metrics.block_size.as_u16());
+        let capacity = self.metrics.capacity_bytes / block_size;
+        let free_blocks = self
+            .free_portions()
+            .map(|p| u64::from(p.len()))
+            .sum::<u64>();
+        let metrics = &self.metrics;
+        metrics
+            .allocated_portions_at_starting
+            .add_u64(allocated_portions);
+        metrics
+            .allocated_bytes_at_starting
+            .add_u64((capacity - free_blocks) * block_size);
+        metrics
+            .inserted_free_portions
+            .add_u64(self.size_to_free.len() as u64);
+    }
+
+    // `portion`に対応する範囲を空き領域群から取り除く.
+    //
+    // 範囲の一部でも空き領域に含まれていない場合には、何もせずに`false`を返す.
+    fn carve_free_portions(&mut self, portion: DataPortion) -> bool {
+        let start = portion.start.as_u64();
+        let end = portion.end().as_u64();
+
+        // 範囲全体が(連続する)空き領域群に覆われているかを確認する.
+        // なお、空き領域は24ビット長の制限により分割されている可能性があるため、
+        // 一つの部分領域に対応する空き領域が複数となることもある.
+        let mut cursor = start;
+        while cursor < end {
+            match self.next_free_portion(cursor) {
+                Some(free) if free.start().as_u64() <= cursor => cursor = free.end().as_u64(),
+                _ => return false,
+            }
+        }
+
+        let mut cursor = start;
+        while cursor < end {
+            let free = self.next_free_portion(cursor).expect("Never fails");
+            self.remove_free_portion(free);
+            if free.start().as_u64() < start {
+                let len = (start - free.start().as_u64()) as U24;
+                self.insert_free_portion(FreePortion::new(free.start(), len));
+            }
+            if end < free.end().as_u64() {
+                let len = (free.end().as_u64() - end) as U24;
+                self.insert_free_portion(FreePortion::new(portion.end(), len));
+            }
+            cursor = free.end().as_u64();
+        }
+        true
+    }
+
+    // 隣接している空き領域同士を(`U24`の範囲内で)併合する.
+    //
+    // 初期登録時の空き領域は24ビット長の制限によって分割されているため、
+    // 切り出し後に境界を挟んで残った空き領域同士は、併合しない限り一つの割当に使用することができない.
+    fn coalesce_free_portions(&mut self) {
+        let mut position = 0;
+        while let Some(mut free) = self.next_free_portion(position) {
+            match self.next_free_portion(free.end().as_u64()) {
+                Some(next) if next.start() == free.end() && free.checked_extend(next.len()) => {
+                    self.remove_free_portion(FreePortion::new(
+                        free.start(),
+                        free.len() - next.len(),
+                    ));
+                    self.remove_free_portion(next);
+                    self.insert_free_portion(free);
+                }
+                _ => position = free.end().as_u64(),
+            }
+        }
+    }
+
+    // 終端位置が`position`を超える空き領域(断片を含む)のうち、最小のものを返す.
+    fn next_free_portion(&self, position: u64) -> Option<FreePortion> {
+        let key = EndBasedFreePortion(FreePortion::new(Address::from_u64(position).unwrap(), 0));
+        let listed = self.end_to_free.range((Excluded(&key), Unbounded)).next();
+        let fragment = self.fragments.range((Excluded(&key), Unbounded)).next();
+        match (listed, fragment) {
+            (Some(listed), Some(fragment)) => Some(cmp::min(listed, fragment).0),
+            (listed, fragment) => listed.or(fragment).map(|p| p.0),
+        }
+    }
+
+    // 空き領域の全体を、現在の閾値と上限に従って振り分け直す.
+    fn redistribute_free_portions(&mut self) {
+        let portions = self.free_portions().collect::<Vec<_>>();
         for portion in portions {
-            track_assert!(portion.end().as_u64() <= tail, ErrorKind::InvalidInput);
-
-            // endはexclusiveであることに注意する。
-            // すなわち、endの手前まではデータが詰まっているが、endにはデータがない
-            while portion.end().as_u64() < tail {
-                let delta = tail - portion.end().as_u64(); // いま着目しているportionの後ろ側にある空きブロック数
-                let size = cmp::min(0xFF_FFFF, delta) as U24; // 最大でも24バイト表現なので切り詰めを行う
-
-                // tail-size位置 から size分 の空き容量があることが分かっているので
-                // これを追加する
-                tail -= u64::from(size);
-                let free = FreePortion::new(Address::from_u64(tail).unwrap(), size);
-                allocator.add_free_portion(free);
+            let was_listed = self.remove_free_portion(portion);
+            let is_listed = self.insert_free_portion(portion);
+            if was_listed && !is_listed {
+                self.metrics.removed_free_portions.increment();
+            } else if !was_listed && is_listed {
+                self.metrics.inserted_free_portions.increment();
             }
-            tail = portion.start.as_u64();
         }
-        Ok(allocator)
+        self.isolate_excess_free_portions();
+    }
+
+    // 空き領域のリストの長さが上限を超えている場合に、最小のものから順に断片として隔離する.
+    fn isolate_excess_free_portions(&mut self) {
+        if self.max_free_portions == 0 {
+            return;
+        }
+        while self.size_to_free.len() > self.max_free_portions {
+            let smallest = self.size_to_free.iter().next().expect("Never fails").0;
+            self.delete_free_portion(smallest);
+            self.insert_fragment(smallest);
+        }
     }
 
     /// `size`分の部分領域の割当を行う.
     ///
     /// 十分な領域が存在しない場合には`None`が返される.
     pub fn allocate(&mut self, size: u16) -> Option<DataPortion> {
-        let portion = SizeBasedFreePortion(FreePortion::new(Address::from(0), U24::from(size)));
-        if let Some(mut free) = self
-            .size_to_free
-            // `SizedBasedFreePortion`の全順序を用いて `size` を含むFreePortionを探す
-            .range((Included(&portion), Unbounded))
-            // 従って、next()では（存在すれば）size以上かつ最小のFreePortionを取得することになる
-            .next()
-            .map(|p| p.0)
-        {
-            debug_assert!(U24::from(size) <= free.len());
+        let found = if !self.requires_alignment(size) {
+            self.find_free_portion(size)
+        } else if let Some(found) = self.find_aligned_free_portion(size) {
+            self.metrics.aligned_allocations.increment();
+            Some(found)
+        } else {
+            // 境界に揃った空き領域がない場合には、アライメントを諦めて割り当てる
+            let found = self.find_free_portion(size);
+            if found.is_some() {
+                self.metrics.alignment_fallbacks.increment();
+            }
+            found
+        };
+
+        if let Some((free, offset)) = found {
+            debug_assert!(u64::from(offset) + u64::from(size) <= u64::from(free.len()));
             self.delete_free_portion(free);
+            if offset > 0 {
+                // アライメント境界までの前半部分は、空き領域として残す
+                self.add_free_portion(FreePortion::new(free.start(), offset));
+            }
+            let mut free =
+                FreePortion::new(free.start() + Address::from(offset), free.len() - offset);
             let allocated = free.allocate(size);
             if free.len() > 0 {
                 // まだfree portionに空きがある場合は再利用する
@@ -124,6 +487,44 @@ impl DataPortionAllocator {
         }
     }
 
+    // "BestFit"戦略で、`size`分の割当に使用する空き領域を探す.
+    //
+    // 結果の二番目の要素は、空き領域内での割当開始位置のオフセット(常に`0`).
+    fn find_free_portion(&self, size: u16) -> Option<(FreePortion, U24)> {
+        let portion = SizeBasedFreePortion(FreePortion::new(Address::from(0), U24::from(size)));
+        self.size_to_free
+            // `SizedBasedFreePortion`の全順序を用いて `size` を含むFreePortionを探す
+            .range((Included(&portion), Unbounded))
+            // 従って、next()では（存在すれば）size以上かつ最小のFreePortionを取得することになる
+            .next()
+            .map(|p| (p.0, 0))
+    }
+
+    // `size`分の割当要求が、アライメントの対象となるかどうかを判定する.
+    fn requires_alignment(&self, size: u16) -> bool {
+        self.alignment.is_some_and(|a| size >= a.threshold)
+    }
+
+    // 境界に揃えて`size`分を割当可能な空き領域を探す.
+    //
+    // 結果の二番目の要素は、空き領域の開始位置から境界までのオフセット.
+    fn find_aligned_free_portion(&self, size: u16) -> Option<(FreePortion, U24)> {
+        let alignment = self.alignment?.alignment;
+        let portion = SizeBasedFreePortion(FreePortion::new(Address::from(0), U24::from(size)));
+        self.size_to_free
+            .range((Included(&portion), Unbounded))
+            .map(|p| p.0)
+            .find_map(|free| {
+                let start = free.start().as_u64();
+                let aligned_start = start.div_ceil(alignment) * alignment;
+                if aligned_start + u64::from(size) <= free.end().as_u64() {
+                    Some((free, (aligned_start - start) as U24))
+                } else {
+                    None
+                }
+            })
+    }
+
     /// 割当済みの部分領域の解放を行う.
     ///
     /// # 事前条件
@@ -142,25 +543,68 @@ impl DataPortionAllocator {
     }
 
     fn add_free_portion(&mut self, portion: FreePortion) {
+        if self.insert_free_portion(portion) {
+            self.metrics.inserted_free_portions.increment();
+            self.isolate_excess_free_portions();
+        }
+    }
+
+    fn delete_free_portion(&mut self, portion: FreePortion) {
+        if self.remove_free_portion(portion) {
+            self.metrics.removed_free_portions.increment();
+        }
+    }
+
+    // メトリクス(断片関連のものを除く)を更新せずに、空き領域を追加する.
+    //
+    // サイズが閾値未満の場合には、フリーリストではなく断片として追加され、`false`が返される.
+    fn insert_free_portion(&mut self, portion: FreePortion) -> bool {
+        if portion.len() < self.fragment_threshold {
+            self.insert_fragment(portion);
+            false
+        } else {
+            self.insert_listed_free_portion(portion);
+            true
+        }
+    }
+
+    // メトリクス(断片関連のものを除く)を更新せずに、空き領域を削除する.
+    //
+    // 削除した空き領域が断片だった場合には`false`が返される.
+    fn remove_free_portion(&mut self, portion: FreePortion) -> bool {
+        if self.end_to_free.remove(&EndBasedFreePortion(portion)) {
+            assert!(self.size_to_free.remove(&SizeBasedFreePortion(portion)));
+            true
+        } else {
+            assert!(self.fragments.remove(&EndBasedFreePortion(portion)));
+            self.metrics.count_fragment_removal(portion.len());
+            false
+        }
+    }
+
+    fn insert_listed_free_portion(&mut self, portion: FreePortion) {
         assert!(self.size_to_free.insert(SizeBasedFreePortion(portion)));
         assert!(self.end_to_free.insert(EndBasedFreePortion(portion)));
-        self.metrics.inserted_free_portions.increment();
     }
 
-    fn delete_free_portion(&mut self, portion: FreePortion) {
-        assert!(self.size_to_free.remove(&SizeBasedFreePortion(portion)));
-        assert!(self.end_to_free.remove(&EndBasedFreePortion(portion)));
-        self.metrics.removed_free_portions.increment();
+    fn insert_fragment(&mut self, portion: FreePortion) {
+        assert!(self.fragments.insert(EndBasedFreePortion(portion)));
+        self.metrics.count_fragment_insertion(portion.len());
     }
 
-    // `portion`と隣接する領域がフリーリスト内に存在する場合には、それらをまとめてしまう.
+    // `portion`と隣接する空き領域(断片を含む)が存在する場合には、それらをまとめてしまう.
     fn merge_free_portions_if_possible(&mut self, mut portion: FreePortion) -> FreePortion {
         // 「`portion`の始端」に一致する終端を持つportion `prev`を探す。
         // もし存在するなら、 prev portion の並びでmerge可能である。
         // 注意: BTreeSetのgetでは、EqではなくOrd traitが用いられる。
         // 従ってendが一致する場合に限りOrdering::Equalとなる。
-        let key = FreePortion::new(portion.start(), 0);
-        if let Some(prev) = self.end_to_free.get(&EndBasedFreePortion(key)).map(|p| p.0) {
+        let key = EndBasedFreePortion(FreePortion::new(portion.start(), 0));
+        if let Some(prev) = self
+            .end_to_free
+            .get(&key)
+            .or_else(|| self.fragments.get(&key))
+            .map(|p| p.0)
+        {
             if portion.checked_extend(prev.len()) {
                 // trueの場合は副作用が発生するが、次で捨てる
                 portion = FreePortion::new(prev.start(), portion.len());
@@ -170,13 +614,7 @@ impl DataPortionAllocator {
 
         // 「`portion`の終端」に一致する始端を持つportion `next` を探す。
         // もし存在するなら、 portion next の並びでmerge可能である。
-        let key = FreePortion::new(portion.end(), 0);
-        if let Some(next) = self
-            .end_to_free
-            .range((Excluded(&EndBasedFreePortion(key)), Unbounded))
-            .next()
-            .map(|p| p.0)
-        {
+        if let Some(next) = self.next_free_portion(portion.end().as_u64()) {
             // `next`については`portion.end < next.end`を満たす最小のポーションということしか分かっていない。
             // portion.end == next.start かどうかを確認する必要がある。
             if next.start() == portion.end() && portion.checked_extend(next.len()) {
@@ -187,41 +625,164 @@ impl DataPortionAllocator {
         portion
     }
 
-    // EndBasedFreePortionを用いて、
-    // フリーリスト内のいずれとも領域が重なっていないかどうかを検査する。
-    // 領域が重なっていない場合 <=> 返り値がtrue に限り、割当済みの領域であると判断する。
-    //
-    // メモ:
-    //    現在の実装では `next()` を用いているため、
-    //    フリーリスト内の相異なる部分領域が互いに素であるという前提が必要である。
-    //    ただしこの前提は通常のCannyLSの使用であれば成立する。
-    fn is_allocated_portion(&self, portion: &DataPortion) -> bool {
-        let key = EndBasedFreePortion(FreePortion::new(portion.start, 0));
-        if let Some(next) = self.end_to_free.range((Excluded(&key), Unbounded)).next() {
+    /// EndBasedFreePortionを用いて、
+    /// フリーリスト内および断片のいずれとも領域が重なっていないかどうかを検査する。
+    /// 領域が重なっていない場合 <=> 返り値がtrue に限り、割当済みの領域であると判断する。
+    ///
+    /// メモ:
+    ///    現在の実装では `next()` を用いているため、
+    ///    フリーリスト内の相異なる部分領域が互いに素であるという前提が必要である。
+    ///    ただしこの前提は通常のCannyLSの使用であれば成立する。
+    pub fn is_allocated_portion(&self, portion: &DataPortion) -> bool {
+        if let Some(next) = self.next_free_portion(portion.start.as_u64()) {
             // 終端位置が `portion.start` を超えるfree portionのうち最小のもの `next` については
-            // - portion.end() <= next.0.start() すなわち overlapしていないか
-            // - portion.end() > next.0.start() すなわち overlapしているか
+            // - portion.end() <= next.start() すなわち overlapしていないか
+            // - portion.end() > next.start() すなわち overlapしているか
             // を検査する
-            portion.end() <= next.0.start()
+            portion.end() <= next.start()
         } else {
             true
         }
     }
 }
 
+// フリーリスト内の空き領域と断片とを、終端位置の昇順に併せて走査するためのイテレータ.
+struct FreePortions<'a> {
+    listed: Peekable<btree_set::Iter<'a, EndBasedFreePortion>>,
+    fragments: Peekable<btree_set::Iter<'a, EndBasedFreePortion>>,
+}
+impl Iterator for FreePortions<'_> {
+    type Item = FreePortion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let take_fragment = match (self.listed.peek(), self.fragments.peek()) {
+            (Some(listed), Some(fragment)) => fragment < listed,
+            (None, Some(_)) => true,
+            _ => false,
+        };
+        if take_fragment {
+            self.fragments.next().map(|p| p.0)
+        } else {
+            self.listed.next().map(|p| p.0)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.listed.len() + self.fragments.len();
+        (len, Some(len))
+    }
+}
+impl ExactSizeIterator for FreePortions<'_> {}
+
+// エラー報告用に、除外された部分領域群の衝突相手を特定する.
+//
+// 衝突は稀であり、かつ報告対象の数も限られているため、割当済みの部分領域群を再走査して求める.
+fn resolve_conflicts<I>(portions: I, dropped: &[(LumpId, DataPortion)]) -> Vec<PortionConflict>
+where
+    I: Iterator<Item = (LumpId, DataPortion)>,
+{
+    const MAX_REPORTED_CONFLICTS: usize = 16;
+    let mut conflicts = dropped
+        .iter()
+        .take(MAX_REPORTED_CONFLICTS)
+        .map(|&(lump_id, portion)| {
+            (
+                portion,
+                PortionConflict {
+                    kept: None,
+                    dropped: lump_id,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    let dropped_ids = dropped
+        .iter()
+        .map(|&(lump_id, _)| lump_id)
+        .collect::<BTreeSet<_>>();
+    for (lump_id, portion) in portions.filter(|(id, _)| !dropped_ids.contains(id)) {
+        for (dropped_portion, conflict) in conflicts.iter_mut() {
+            if conflict.kept.is_none()
+                && portion.start < dropped_portion.end()
+                && dropped_portion.start < portion.end()
+            {
+                conflict.kept = Some(lump_id);
+            }
+        }
+    }
+    conflicts.into_iter().map(|(_, c)| c).collect()
+}
+
+/// `dropped`に含まれる部分領域群と、それらと重複している`portions`内の部分領域群を返す.
+fn involved_portions<I>(
+    portions: I,
+    dropped: &[(LumpId, DataPortion)],
+) -> BTreeMap<LumpId, DataPortion>
+where
+    I: Iterator<Item = (LumpId, DataPortion)>,
+{
+    let mut involved = dropped.iter().copied().collect::<BTreeMap<_, _>>();
+    let dropped_by_start = dropped
+        .iter()
+        .map(|&(_, portion)| (portion.start, portion))
+        .collect::<BTreeMap<_, _>>();
+    for (lump_id, portion) in portions {
+        // 部分領域の長さは`u16`に収まるので、その分だけ手前から探索すれば、重複しているものは全て見つかる
+        let from = Address::from_u64(portion.start.as_u64().saturating_sub(u64::from(u16::MAX)))
+            .expect("Never fails");
+        let overlapped = dropped_by_start
+            .range(from..portion.end())
+            .any(|(_, d)| portion.start < d.end() && d.start < portion.end());
+        if overlapped {
+            involved.entry(lump_id).or_insert(portion);
+        }
+    }
+    involved
+}
+
+/// 大きな部分領域の割当時のアライメントの設定.
+#[derive(Debug, Clone, Copy)]
+struct Alignment {
+    // アライメントの対象となる部分領域の最小サイズ(ブロック数)
+    threshold: u16,
+
+    // アライメント境界の間隔(ブロック数)
+    alignment: u64,
+}
+
+/// アロケータの構築時に検出された部分領域の衝突.
+#[derive(Debug)]
+struct PortionConflict {
+    // 衝突相手として残された部分領域を所有するlump (範囲外の場合は`None`)
+    kept: Option<LumpId>,
+
+    // 除外された部分領域を所有するlump
+    dropped: LumpId,
+}
+impl fmt::Display for PortionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(kept) = self.kept {
+            write!(f, "{} overlaps {}", self.dropped, kept)
+        } else {
+            write!(f, "{} is out of range", self.dropped)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use prometrics::metrics::MetricBuilder;
+    use std::collections::BTreeMap;
     use std::iter;
     use trackable::result::TestResult;
 
     use crate::block::BlockSize;
     use crate::lump::LumpId;
     use crate::metrics::DataAllocatorMetrics;
-    use crate::storage::allocator::DataPortionAllocator;
+    use crate::storage::allocator::{DataPortionAllocator, FreePortion};
     use crate::storage::index::LumpIndex;
     use crate::storage::portion::{DataPortion, Portion};
     use crate::storage::Address;
+    use crate::{ErrorKind, Result};
 
     #[test]
     fn it_works() -> TestResult {
@@ -308,6 +869,105 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn rebuild_large_capacity() -> TestResult {
+        // 24ビット長の上限を跨ぐ部分領域
+        let mut index = LumpIndex::new();
+        index.insert(lump_id("000"), Portion::Data(portion(0xFF_FFF0, 0x20)));
+
+        let capacity = Address::from(0x100_0100);
+        let mut allocator = track!(DataPortionAllocator::build(
+            metrics(capacity),
+            index.data_portions()
+        ))?;
+        assert_eq!(allocator.metrics().free_list_len(), 2);
+        assert!(allocator.is_allocated_portion(&portion(0xFF_FFF0, 0x20)));
+        assert!(!allocator.is_allocated_portion(&portion(0xFF_FFEF, 1)));
+        assert_eq!(allocator.allocate(0xFFFF), Some(portion(0, 0xFFFF)));
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_coalesces_free_portions() -> TestResult {
+        // 最初の`0xFF_FFFF`ブロックの末尾付近のみを空けておく
+        let mut index = LumpIndex::new();
+        for i in 0..0x100 {
+            index.insert(
+                LumpId::new(i),
+                Portion::Data(portion(i as u32 * 0xFFFF, 0xFFFF)),
+            );
+        }
+
+        let capacity = Address::from(0x100_0100);
+        let mut allocator = track!(DataPortionAllocator::build(
+            metrics(capacity),
+            index.data_portions()
+        ))?;
+
+        // 初期登録時の境界(`0xFF_FFFF`)を挟んだ空き領域同士は併合されている
+        assert_eq!(allocator.metrics().free_list_len(), 1);
+        assert_eq!(allocator.allocate(0x200), Some(portion(0xFF_FF00, 0x200)));
+        assert_eq!(allocator.allocate(1), None);
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_with_conflicts() -> TestResult {
+        let mut index = LumpIndex::new();
+        index.insert(lump_id("000"), Portion::Data(portion(0, 10)));
+        index.insert(lump_id("111"), Portion::Data(portion(5, 3)));
+        index.insert(lump_id("222"), Portion::Data(portion(15, 10)));
+
+        let capacity = Address::from(20);
+        let error = DataPortionAllocator::build(metrics(capacity), index.data_portions())
+            .err()
+            .unwrap();
+        assert_eq!(*error.kind(), ErrorKind::StorageCorrupted);
+
+        // 重複している部分領域同士では、順位が大きい(後に登録された)方が除外される
+        let order = |first: &str, second: &str| {
+            let first = lump_id(first);
+            let second = lump_id(second);
+            move |involved: &BTreeMap<LumpId, DataPortion>| -> Result<BTreeMap<LumpId, u64>> {
+                assert!(involved.contains_key(&first));
+                assert!(involved.contains_key(&second));
+                Ok(vec![(first, 0), (second, 1)].into_iter().collect())
+            }
+        };
+        let (mut allocator, dropped) = track!(DataPortionAllocator::build_dropping_conflicts(
+            metrics(capacity),
+            index.data_portions(),
+            order("000", "111")
+        ))?;
+        assert_eq!(dropped, vec![lump_id("111"), lump_id("222")]);
+        assert_eq!(allocator.metrics().allocated_portions(), 1);
+        assert_eq!(allocator.allocate(10), Some(portion(10, 10)));
+        assert_eq!(allocator.allocate(1), None);
+
+        // IDの大小は関係しない
+        let (mut allocator, dropped) = track!(DataPortionAllocator::build_dropping_conflicts(
+            metrics(capacity),
+            index.data_portions(),
+            order("111", "000")
+        ))?;
+        assert_eq!(dropped, vec![lump_id("000"), lump_id("222")]);
+        assert_eq!(allocator.metrics().allocated_portions(), 1);
+        assert_eq!(allocator.allocate(5), Some(portion(0, 5)));
+        assert_eq!(allocator.allocate(12), Some(portion(8, 12)));
+        assert_eq!(allocator.allocate(1), None);
+
+        // 重複が存在しない場合には、順位は参照されない
+        let (_, dropped) = track!(DataPortionAllocator::build_dropping_conflicts(
+            metrics(Address::from(40)),
+            index
+                .data_portions()
+                .filter(|(id, _)| *id != lump_id("111")),
+            |_: &BTreeMap<LumpId, DataPortion>| -> Result<BTreeMap<LumpId, u64>> { unreachable!() }
+        ))?;
+        assert!(dropped.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn allocate_and_release() -> TestResult {
         let capacity = Address::from(419431);
@@ -335,6 +995,142 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn fragments_are_isolated() -> TestResult {
+        let capacity = Address::from(100);
+        let mut allocator = track!(DataPortionAllocator::build(
+            metrics(capacity),
+            iter::empty()
+        ))?;
+        allocator.set_fragment_threshold(4);
+
+        let portions = (0..10)
+            .map(|_| allocator.allocate(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(portions[9], portion(9, 1));
+
+        // 併合できない小さな空き領域は、断片として隔離される
+        allocator.release(portions[1]);
+        allocator.release(portions[3]);
+        allocator.release(portions[5]);
+        assert_eq!(allocator.metrics().free_list_len(), 1);
+        assert_eq!(allocator.free_portions().len(), 4);
+        assert_eq!(allocator.metrics().fragment_portions(), 3);
+        assert_eq!(allocator.metrics().fragment_bytes(), 3 * 512);
+
+        // 断片は割当に使用されない
+        assert_eq!(allocator.allocate(1), Some(portion(10, 1)));
+
+        // 併合されて閾値以上のサイズになれば、探索対象に戻る
+        allocator.release(portions[2]);
+        assert_eq!(allocator.metrics().fragment_portions(), 2);
+        assert_eq!(allocator.metrics().fragment_bytes(), 4 * 512);
+        allocator.release(portions[4]);
+        assert_eq!(allocator.metrics().fragment_portions(), 0);
+        assert_eq!(allocator.metrics().fragment_bytes(), 0);
+        assert_eq!(allocator.allocate(5), Some(portion(1, 5)));
+
+        // 明示的に探索対象に戻すことも可能
+        allocator.release(portions[7]);
+        assert_eq!(allocator.metrics().fragment_portions(), 1);
+        assert_eq!(allocator.reclaim_fragments(), 1);
+        assert_eq!(allocator.metrics().fragment_portions(), 0);
+        assert_eq!(allocator.allocate(1), Some(portion(7, 1)));
+
+        // 閾値を下げると、既存の断片も探索対象に戻る
+        allocator.release(portions[0]);
+        assert_eq!(allocator.metrics().fragment_portions(), 1);
+        allocator.set_fragment_threshold(0);
+        assert_eq!(allocator.metrics().fragment_portions(), 0);
+        assert_eq!(allocator.allocate(1), Some(portion(0, 1)));
+        Ok(())
+    }
+
+    #[test]
+    fn free_list_is_capped() -> TestResult {
+        let capacity = Address::from(100);
+        let mut allocator = track!(DataPortionAllocator::build(
+            metrics(capacity),
+            iter::empty()
+        ))?;
+        allocator.set_max_free_portions(2);
+
+        let portions = (0..10)
+            .map(|i| allocator.allocate(i % 2 + 1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(portions[9], portion(13, 2));
+
+        // 上限を超えた分は、最小のものから順に断片として隔離される
+        allocator.release(portions[1]);
+        allocator.release(portions[4]);
+        allocator.release(portions[7]);
+        assert_eq!(allocator.metrics().free_list_len(), 2);
+        assert_eq!(allocator.metrics().fragment_portions(), 2);
+        assert_eq!(allocator.metrics().fragment_bytes(), 3 * 512);
+        assert_eq!(
+            allocator.free_portions().collect::<Vec<_>>(),
+            vec![
+                FreePortion::new(Address::from(1), 2),
+                FreePortion::new(Address::from(6), 1),
+                FreePortion::new(Address::from(10), 2),
+                FreePortion::new(Address::from(15), 85),
+            ]
+        );
+        assert!(!allocator.is_allocated_portion(&portion(6, 1)));
+
+        // 容量不足でも、断片は割当に使用されない
+        assert_eq!(allocator.allocate(2), Some(portion(10, 2)));
+        assert_eq!(allocator.allocate(85), Some(portion(15, 85)));
+        assert_eq!(allocator.allocate(1), None);
+
+        // 明示的に戻す場合にも、上限を超えない範囲で、サイズの大きいものから順に戻される
+        allocator.release(portions[8]);
+        assert_eq!(allocator.reclaim_fragments(), 1);
+        assert_eq!(allocator.metrics().free_list_len(), 2);
+        assert_eq!(allocator.metrics().fragment_portions(), 1);
+
+        // 解放された領域は、隣接する断片とも併合される
+        allocator.release(portions[5]);
+        assert_eq!(allocator.metrics().free_list_len(), 2);
+        assert_eq!(
+            allocator.free_portions().collect::<Vec<_>>(),
+            vec![
+                FreePortion::new(Address::from(1), 2),
+                FreePortion::new(Address::from(6), 3),
+                FreePortion::new(Address::from(12), 1),
+            ]
+        );
+        assert_eq!(allocator.allocate(1), Some(portion(1, 1)));
+        Ok(())
+    }
+
+    #[test]
+    fn large_portions_are_aligned() -> TestResult {
+        let capacity = Address::from(100);
+        let mut allocator = track!(DataPortionAllocator::build(
+            metrics(capacity),
+            iter::empty()
+        ))?;
+        allocator.set_alignment(8, 16);
+
+        // 閾値未満のサイズの割当は、通常通り
+        assert_eq!(allocator.allocate(3), Some(portion(0, 3)));
+
+        // 閾値以上のサイズの割当は、境界に揃えられる
+        assert_eq!(allocator.allocate(8), Some(portion(16, 8)));
+        assert_eq!(allocator.metrics().free_list_len(), 2);
+        assert_eq!(allocator.allocate(2), Some(portion(3, 2)));
+        assert_eq!(allocator.allocate(8), Some(portion(32, 8)));
+        assert_eq!(allocator.metrics().aligned_allocations(), 2);
+
+        // 境界に揃った空き領域がない場合には、アライメントなしで割り当てられる
+        assert_eq!(allocator.allocate(60), Some(portion(40, 60)));
+        assert_eq!(allocator.metrics().alignment_fallbacks(), 1);
+        assert_eq!(allocator.allocate(30), None);
+        assert_eq!(allocator.metrics().alignment_fallbacks(), 1);
+        Ok(())
+    }
+
     fn lump_id(id: &str) -> LumpId {
         id.parse().unwrap()
     }
@@ -5,6 +5,7 @@
 //!
 //! アロケータが担当するのは、領域の計算処理のみで、実際のデータの読み書き等を、この中で行うことは無い.
 pub use self::data_portion_allocator::DataPortionAllocator;
+pub use self::free_portion::FreePortion;
 
 mod data_portion_allocator;
 mod free_portion;
@@ -1,17 +1,27 @@
 use prometrics::metrics::MetricBuilder;
+use slog::{Discard, Logger};
 use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use uuid::Uuid;
 
 use crate::block::BlockSize;
+use crate::build_info::CrateVersion;
 use crate::metrics::{DataAllocatorMetrics, StorageMetrics};
-use crate::nvm::NonVolatileMemory;
+use crate::nvm::{AccessPattern, NonVolatileMemory};
 use crate::storage::allocator::DataPortionAllocator;
+use crate::storage::checkpoint::Checkpoint;
 use crate::storage::data_region::DataRegion;
-use crate::storage::header::FULL_HEADER_SIZE;
+use crate::storage::dedup::{ContentHashes, DedupTable};
 use crate::storage::index::LumpIndex;
-use crate::storage::journal::{JournalRegion, JournalRegionOptions};
+use crate::storage::index_shadow::IndexShadow;
+use crate::storage::journal::{
+    AdaptiveSyncOptions, AdmissionControlOptions, JournalAdmissionAction, JournalHeader,
+    JournalRegion, JournalRegionOptions,
+};
 use crate::storage::{
-    Storage, StorageHeader, MAJOR_VERSION, MAX_DATA_REGION_SIZE, MAX_JOURNAL_REGION_SIZE,
+    JournalChecksum, Storage, StorageConfig, StorageCreationParams, StorageHeader, UsageSummary,
+    MAJOR_VERSION, MAX_DATA_REGION_SIZE, MAX_JOURNAL_REGION_SIZE, MAX_LOGICAL_NAME_LEN,
     MINOR_VERSION,
 };
 use crate::{ErrorKind, Result};
@@ -21,8 +31,35 @@ use crate::{ErrorKind, Result};
 pub struct StorageBuilder {
     journal_region_ratio: f64,
     instance_uuid: Option<Uuid>,
+    logical_name: Option<String>,
     journal: JournalRegionOptions,
     metrics: MetricBuilder,
+    drop_overlapping_portions: bool,
+    discard_released_portions: bool,
+    scrub_released_portions: bool,
+    scrub_rate_limit: Option<u64>,
+    padding_fill_byte: Option<u8>,
+    sparse_lumps: bool,
+    verify_embedded_data: bool,
+    verify_data_writes: bool,
+    audit_trail: bool,
+    audit_retention: u64,
+    deduplication: bool,
+    skip_identical_overwrites: bool,
+    overwrite_in_place: bool,
+    free_fragment_threshold: u16,
+    max_free_portions: usize,
+    large_lump_alignment: Option<(u32, u32)>,
+    clamp_oversized_nvm: bool,
+    upgrade_minor_version: bool,
+    access_heatmap_buckets: usize,
+    read_ahead_size: usize,
+    write_slice_size: usize,
+    reembed_threshold: u16,
+    reembed_batch_size: usize,
+    index_shadow_file: Option<PathBuf>,
+    index_shadow_interval: Duration,
+    logger: Logger,
 }
 impl StorageBuilder {
     /// 新しい`StorageBuilder`インスタンスを生成する.
@@ -30,8 +67,35 @@ impl StorageBuilder {
         StorageBuilder {
             journal_region_ratio: 0.01,
             instance_uuid: None,
+            logical_name: None,
             journal: JournalRegionOptions::default(),
             metrics: MetricBuilder::new(),
+            drop_overlapping_portions: false,
+            discard_released_portions: false,
+            scrub_released_portions: false,
+            scrub_rate_limit: None,
+            padding_fill_byte: None,
+            sparse_lumps: false,
+            verify_embedded_data: false,
+            verify_data_writes: false,
+            audit_trail: false,
+            audit_retention: 1024,
+            deduplication: false,
+            skip_identical_overwrites: false,
+            overwrite_in_place: false,
+            free_fragment_threshold: 0,
+            max_free_portions: 0,
+            large_lump_alignment: None,
+            clamp_oversized_nvm: false,
+            upgrade_minor_version: true,
+            access_heatmap_buckets: 0,
+            read_ahead_size: 0,
+            write_slice_size: 0,
+            reembed_threshold: 0,
+            reembed_batch_size: 16,
+            index_shadow_file: None,
+            index_shadow_interval: Duration::from_secs(60),
+            logger: Logger::root(Discard, o!()),
         }
     }
 
@@ -50,6 +114,28 @@ impl StorageBuilder {
         self
     }
 
+    /// ストレージの論理名(e.g., クラスタIDやサーバ内のスロット名)を設定する.
+    ///
+    /// UUIDとは異なり、利用者が事前に決めておける値なので、
+    /// ディスクを誤ったサーバやスロットに取り付けた場合に、別のストレージのデータを使用してしまうことを防ぐのに利用できる.
+    ///
+    /// ストレージの作成時とオープン時で、指定した値の使われ方が異なる:
+    ///
+    /// - 作成時:
+    ///   - ここで指定した値が、ヘッダに記録される
+    ///   - 本メソッドが呼ばれていない場合は、名前は記録されない
+    ///   - 名前の長さが`MAX_LOGICAL_NAME_LEN`バイトを超えている場合には、作成に失敗する
+    /// - オープン時:
+    ///   - ここで指定した値と、ヘッダに記録されている名前が比較され、もし異なっている場合にはオープンに失敗する
+    ///     (名前が記録されていないストレージの場合も失敗する)
+    ///   - 本メソッドが呼ばれていない場合は、特にチェックは行われない
+    ///
+    /// オープン時のチェックは、ヘッダの読み込み直後(i.e., ストレージに対する一切の書き込みよりも前)に行われる.
+    pub fn logical_name(&mut self, name: &str) -> &mut Self {
+        self.logical_name = Some(name.to_owned());
+        self
+    }
+
     /// 利用可能な領域全体に占めるジャーナル領域の割合を設定する.
     ///
     /// 取り得る値は、0.0から1.0の間の小数である。
@@ -79,6 +165,31 @@ impl StorageBuilder {
         self
     }
 
+    /// 一回の補助タスク(`Storage::run_side_job_once`)の中で実行される、ジャーナル領域のGCの単位処理の回数、を設定する.
+    ///
+    /// この値が大きいほど、デバイスが暇な時間帯に、より積極的にGCが進められるようになる.
+    ///
+    /// デフォルト値は`64`.
+    pub fn journal_gc_batch_size(&mut self, size: usize) -> &mut Self {
+        self.journal.gc_batch_size = size;
+        self
+    }
+
+    /// レコードの追記毎に実行されるジャーナル領域のGCの量を、ジャーナル領域の使用率に応じて自動で調整するようにする.
+    ///
+    /// デフォルトでは、レコードを一つ追記する度にGCの単位処理が一回だけ実行されるため、
+    /// 書き込みが高い頻度で続くと、GCによる領域の回収が追いつかなくなることがある.
+    /// 有効にした場合には、追記毎の単位処理の回数が、ジャーナル領域の使用率に比例して
+    /// `1`から`max_steps`の範囲で増やされるようになる.
+    ///
+    /// GCの進み具合は`JournalRegionMetrics::gc_debt_bytes`で確認できる.
+    ///
+    /// デフォルトでは無効(i.e., 追記毎に一回).
+    pub fn adaptive_journal_gc(&mut self, max_steps: usize) -> &mut Self {
+        self.journal.adaptive_gc_max_steps = Some(max_steps);
+        self
+    }
+
     /// 物理デバイスへのジャーナルの同期間隔、を設定する.
     ///
     /// この値で指定された数のレコードがジャーナルに追加される度に、
@@ -91,6 +202,111 @@ impl StorageBuilder {
         self
     }
 
+    /// ジャーナルの同期間隔を、書き込みのレイテンシに応じて自動で調整するようにする.
+    ///
+    /// 有効にした場合には、ジャーナルへの追記を伴う書き込み操作(e.g., PUTやDELETE)の所要時間が計測され、
+    /// その99パーセンタイル値が`target_latency`以下に収まるように、同期間隔が
+    /// `min_interval`から`max_interval`の範囲で調整されるようになる.
+    /// 同期命令が遅いデバイスでは間隔が長くなり、速いデバイスでは(信頼性を優先して)短くなる.
+    ///
+    /// `journal_sync_interval`で指定された値は、同期間隔の初期値として使用される.
+    /// 実行中の同期間隔は`JournalRegionMetrics::sync_interval`で確認できる.
+    ///
+    /// デフォルトでは無効(i.e., 同期間隔は固定).
+    pub fn adaptive_journal_sync(
+        &mut self,
+        target_latency: Duration,
+        min_interval: usize,
+        max_interval: usize,
+    ) -> &mut Self {
+        self.journal.adaptive_sync = Some(AdaptiveSyncOptions {
+            target_latency,
+            min_interval,
+            max_interval,
+        });
+        self
+    }
+
+    /// ジャーナル領域のGCが追記に追いついていない場合に、PUTの受付を制限するようにする.
+    ///
+    /// GCによる領域の回収が追記に追いつかない状態が続くと、最終的にはジャーナル領域が満杯になり、
+    /// 以後の全ての書き込みが失敗するようになってしまう.
+    /// これを避けるために、ジャーナル領域の使用率が`high_watermark`(`0.0`から`1.0`の範囲)を超えており、
+    /// かつ、直近の`window`の期間でGCによる回収量が追記量を下回っていた場合には、
+    /// PUTの実行前に`action`で指定された対処が行われるようになる:
+    ///
+    /// - `JournalAdmissionAction::Reject`: PUTを`ErrorKind::JournalGcLagging`エラーで即座に失敗させる
+    /// - `JournalAdmissionAction::ForceGc`: ジャーナル領域全体のGCを同期的に実行した上で、PUTを続行する
+    ///
+    /// 対処が行われた回数は`JournalRegionMetrics::admission_rejections`および
+    /// `JournalRegionMetrics::admission_forced_gcs`で取得可能.
+    ///
+    /// `high_watermark`が範囲外の場合には、ストレージの作成ないしオープン時に`ErrorKind::InvalidInput`エラーとなる.
+    ///
+    /// デフォルトでは無効(i.e., 常にPUTを受け付ける).
+    pub fn journal_admission_control(
+        &mut self,
+        high_watermark: f64,
+        window: Duration,
+        action: JournalAdmissionAction,
+    ) -> &mut Self {
+        self.journal.admission_control = Some(AdmissionControlOptions {
+            high_watermark,
+            window,
+            action,
+        });
+        self
+    }
+
+    /// 起動時にジャーナルからエントリ群を復元する際の、NVMからの読み込みの単位(バイト数)を設定する.
+    ///
+    /// 復元時にはジャーナル全体がシーケンシャルに読み込まれるため、
+    /// 高スループットなデバイス(e.g., NVMe SSD)では、この値を大きくすることで起動時間を短縮できることがある.
+    /// 復元時の読み込みのスループットは`JournalRegionMetrics::restore_throughput_bytes_per_second`で確認できる.
+    ///
+    /// `0`が指定された場合には`1`として扱われる.
+    ///
+    /// デフォルト値は`1048576`(1MiB).
+    pub fn journal_restore_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.journal.restore_buffer_size = size.max(1);
+        self
+    }
+
+    /// ジャーナルの書き込みバッファのサイズの上限を設定する.
+    ///
+    /// ジャーナルへの追記は、同期命令の発行時まではメモリ上のバッファに蓄えられるが、
+    /// このバッファのサイズが上限を超えた場合には、その時点で(同期命令を伴わずに)NVMへの書き出しが行われる.
+    /// これによって、バッファが使用するメモリ量や、同期時にまとめて書き出されるデータ量を抑えることができる.
+    ///
+    /// 上限はバッファの書き出しの契機となるサイズであり、
+    /// 大きなレコードの追記時には、一時的にこの値を超えることがある点には注意が必要.
+    ///
+    /// NVMが非同期書き込みをサポートしている場合には(e.g., `FileNvmBuilder::async_write`)、この書き出しは非同期に行われ、
+    /// 書き出しの完了を待たずに、後続のレコードの追記を続けることができる.
+    ///
+    /// デフォルトでは上限は設けられていない.
+    pub fn journal_max_write_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.journal.max_write_buffer_size = Some(size);
+        self
+    }
+
+    /// ジャーナルの書き込みバッファのフラッシュ時に、書き出し順序を保証するかどうかを設定する.
+    ///
+    /// `true`が指定された場合には、複数ブロックに跨るバッファのフラッシュ時に、
+    /// 二ブロック目以降を書き出して同期命令を発行した後に、先頭ブロックを書き出すようになる.
+    /// これによって、書き出しの途中でクラッシュした場合に、
+    /// 新しいレコードのヘッダに続けて古いデータが読み込まれてしまうことを防ぐことができる
+    /// ([#27](https://github.com/frugalos/cannyls/issues/27)).
+    ///
+    /// その代わりに、同期命令の発行回数が増えるため、書き込み性能は低下する.
+    /// また、NVMが非同期書き込みをサポートしている場合でも、フラッシュは同期的に行われるようになる.
+    ///
+    /// デフォルト値は`false`.
+    pub fn journal_safe_flush(&mut self, enabled: bool) -> &mut Self {
+        self.journal.safe_flush = enabled;
+        self
+    }
+
     /// ストレージのブロックサイズを指定する.
     ///
     /// ここで指定した値は、ストレージの生成時にのみ使われる.
@@ -101,12 +317,41 @@ impl StorageBuilder {
     /// # 注意
     ///
     /// ストレージのブロックサイズには、それが使用するNVMのブロック境界に揃った値を指定する必要がある.
-    /// もしそうではない値が指定された場合には、ストレージの生成処理がエラーとなる.
+    /// もしそうではない値が指定された場合には、ストレージの生成処理が`ErrorKind::BlockSizeMismatch`エラーとなる.
     pub fn block_size(&mut self, block_size: BlockSize) -> &mut Self {
         self.journal.block_size = block_size;
         self
     }
 
+    /// ジャーナルレコードのチェックサムの計算に用いるアルゴリズムを指定する.
+    ///
+    /// ここで指定した値は、ストレージの生成時にのみ使われる.
+    /// (オープン時には、ヘッダに格納されている既存の値が使用される)
+    ///
+    /// デフォルト値は`JournalChecksum::Crc32c`.
+    pub fn journal_checksum(&mut self, checksum: JournalChecksum) -> &mut Self {
+        self.journal.checksum = checksum;
+        self
+    }
+
+    /// ジャーナル領域のヘッダを保持するスロットの数を指定する.
+    ///
+    /// `2`を指定した場合には、ジャーナルヘッダの更新は二つのスロットに交互に書き込まれ、
+    /// オープン時にはその中で有効かつ最新のものが使用される.
+    /// これにより、ヘッダの書き込み途中でクラッシュした場合でも、ストレージを復旧可能となる.
+    /// `1`を指定した場合には、旧来通り単一のヘッダのみが使用される.
+    ///
+    /// ここで指定した値は、ストレージの生成時にのみ使われる.
+    /// (オープン時には、ヘッダに格納されている既存の値が使用される)
+    ///
+    /// `1`ないし`2`以外の値が指定された場合には、ストレージの生成処理が`ErrorKind::InvalidInput`エラーとなる.
+    ///
+    /// デフォルト値は`2`.
+    pub fn journal_header_slots(&mut self, slots: u8) -> &mut Self {
+        self.journal.header_slots = slots;
+        self
+    }
+
     /// メトリクス用の共通設定を登録する.
     ///
     /// デフォルト値は`MetricBuilder::new()`.
@@ -115,6 +360,447 @@ impl StorageBuilder {
         self
     }
 
+    /// オープン時に、互いに重複しているデータ部分領域が見つかった場合の挙動を設定する.
+    ///
+    /// ジャーナルが破損している場合には、復元されたlump群のデータ部分領域が重複していることがある.
+    ///
+    /// `false`の場合には、ストレージのオープンは`ErrorKind::StorageCorrupted`エラーで失敗する.
+    /// `true`の場合には、重複している部分領域を持つlumpの内、ジャーナル上で後から登録された方を削除した上で、オープンを継続する.
+    /// 削除されたlumpの情報はジャーナルにも記録されるため、次回以降のオープン時に同じ重複が検出されることはない.
+    ///
+    /// なお、データ領域の範囲外を指している部分領域も、重複とみなされて同様に扱われる.
+    ///
+    /// デフォルト値は`false`.
+    pub fn drop_overlapping_portions(&mut self, enabled: bool) -> &mut Self {
+        self.drop_overlapping_portions = enabled;
+        self
+    }
+
+    /// lumpの削除等によって解放されたデータ領域を、NVMに破棄通知するかどうかを設定する.
+    ///
+    /// 有効な場合には、解放された部分領域に対して`NonVolatileMemory::discard`が呼び出される
+    /// (e.g., `FileNvm`ではファイルの穴あけ(`fallocate(2)`の`FALLOC_FL_PUNCH_HOLE`)が行われる).
+    /// これにより、シンプロビジョニングされたボリュームやSSDで、不要となった領域を回収することが可能となる.
+    ///
+    /// ただし、破棄通知は対応する削除がジャーナルに永続化された後に行う必要があるため、
+    /// 解放された部分領域は、次のジャーナル同期時(`Storage::journal_sync`や`Storage::run_side_job_once`の呼び出し時等)
+    /// まで再利用されなくなる.
+    /// また、破棄通知自体にもコストが掛かるため、有効にすると書き込みのレイテンシが悪化する可能性がある.
+    ///
+    /// デフォルト値は`false`.
+    pub fn discard_released_portions(&mut self, enabled: bool) -> &mut Self {
+        self.discard_released_portions = enabled;
+        self
+    }
+
+    /// lumpの削除等によって解放されたデータ領域を、再利用可能にする前にゼロ埋め(スクラブ)するかどうかを設定する.
+    ///
+    /// 有効な場合には、解放された部分領域は、対応する削除がジャーナルに永続化された後にスクラブ待ちとなり、
+    /// `Storage::run_side_job_once`の中で少しずつゼロ埋めされた上で、アロケータに返却される.
+    /// スクラブ待ちの部分領域は、ゼロ埋めが完了するまで再利用されない
+    /// (ただし、空き領域が不足した場合には、その場で全てのスクラブ待ちの部分領域のゼロ埋めが行われる).
+    ///
+    /// なお、スクラブの完了前にクラッシュした場合には、次回のオープン時にはジャーナルから状態が再構築されるため、
+    /// 未スクラブの部分領域はゼロ埋めされないままに再利用可能となる点には注意が必要.
+    ///
+    /// デフォルト値は`false`.
+    pub fn scrub_released_portions(&mut self, enabled: bool) -> &mut Self {
+        self.scrub_released_portions = enabled;
+        self
+    }
+
+    /// 補助タスクの中で行われるスクラブのスループットの上限(バイト/秒)を設定する.
+    ///
+    /// 詳細は`StorageBuilder::scrub_released_portions`を参照のこと.
+    ///
+    /// デフォルトでは上限は設けられていない.
+    pub fn scrub_rate_limit(&mut self, bytes_per_sec: u64) -> &mut Self {
+        self.scrub_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// データ領域に格納されるlumpデータの、ブロック境界までのパディング部分を埋めるバイトを設定する.
+    ///
+    /// 指定されていない場合には、パディング部分にはメモリ上のバッファの内容がそのまま書き込まれるため、
+    /// その値は不定となる.
+    ///
+    /// デフォルトでは指定されていない.
+    pub fn padding_fill_byte(&mut self, fill: u8) -> &mut Self {
+        self.padding_fill_byte = Some(fill);
+        self
+    }
+
+    /// lumpデータを、ゼロブロックを省いた疎な形式でデータ領域に格納するかどうかを指定する.
+    ///
+    /// 有効な場合には、lumpの格納時に、データ内の全てがゼロのブロックが検出され、
+    /// それによって使用ブロック数を削減できる場合には、非ゼロのブロック群とその位置情報のみが書き込まれる.
+    /// 読み込み時には、省かれたブロックはゼロで復元される.
+    /// 仮想マシンのイメージのような、ゼロ埋めされた部分が大きいデータの書き込み量を削減するのに有用.
+    ///
+    /// ここで指定した値は、ストレージの生成時にのみ使われ、ヘッダに記録される
+    /// (オープン時には、ヘッダに格納されている既存の値が使用される).
+    /// 疎な形式のデータはバージョン`1.7`より前の実装では読み込めないため、
+    /// 明示的に有効にしたストレージでのみ使用される.
+    ///
+    /// なお、lumpの格納に使用されるブロック数が減るため、`LumpHeader::approximate_data_size`の値は、
+    /// 実際のデータサイズよりも小さくなることがある.
+    ///
+    /// デフォルト値は`false`.
+    pub fn sparse_lumps(&mut self, enabled: bool) -> &mut Self {
+        self.sparse_lumps = enabled;
+        self
+    }
+
+    /// ジャーナル領域に埋め込まれたlumpデータの取得時に、チェックサムの検証を行うかどうかを設定する.
+    ///
+    /// 埋め込みデータは、それを含むジャーナルレコードのチェックサムによって保護されているが、
+    /// デフォルトでは、そのチェックサムが検証されるのはジャーナルの復元時やGC時のみである.
+    /// 有効にした場合には、`Storage::get`の際にもレコード全体が読み込まれて検証が行われるため、
+    /// ジャーナル領域上でのデータ破損(bit rot)を取得時に検出できるようになる.
+    ///
+    /// 検証に失敗した場合には、`ErrorKind::StorageCorrupted`エラーが返される.
+    ///
+    /// デフォルト値は`false`.
+    pub fn verify_embedded_data(&mut self, enabled: bool) -> &mut Self {
+        self.verify_embedded_data = enabled;
+        self
+    }
+
+    /// データ領域への書き込みの直後に、そのブロック群を読み戻して内容を検証するかどうかを設定する.
+    ///
+    /// 有効にした場合には、`Storage::put`でのデータ領域への書き込み毎に、同じサイズの読み込みが追加で発生し、
+    /// 内容が一致しなかった場合には、PUTの記録(ジャーナルへの書き込み)は行われずに`ErrorKind::StorageCorrupted`エラーが返される.
+    /// 信頼性の低いハードウェア上で、書き込みの失敗をPUTの完了前に検出したい場合に使用する.
+    ///
+    /// 検証に失敗した回数は`DataRegionMetrics::write_verification_failures`で取得可能.
+    ///
+    /// なお、読み込みがOSのページキャッシュから行われる場合には、媒体上の内容までは検証できないため、
+    /// 十分な効果を得るためには、Direct I/Oと組み合わせて使用する必要がある.
+    ///
+    /// デフォルト値は`false`.
+    pub fn verify_data_writes(&mut self, enabled: bool) -> &mut Self {
+        self.verify_data_writes = enabled;
+        self
+    }
+
+    /// 操作の監査証跡をジャーナルに記録するかどうかを設定する.
+    ///
+    /// 有効にした場合には、`Storage::put_with_audit`や`Storage::delete_with_audit`に渡されたメタデータが、
+    /// 監査レコード(`AuditRecord`)として、操作の記録と一つのレコードにまとめてジャーナルに書き込まれる.
+    /// 監査付きの操作のレコードは必須の拡張レコードとなるため、
+    /// 一度でも監査付きの操作を行ったストレージは、監査証跡に対応していないバージョンではオープンできなくなる.
+    ///
+    /// 無効な場合には、メタデータは検証された上で単に無視される.
+    ///
+    /// デフォルト値は`false`.
+    pub fn audit_trail(&mut self, enabled: bool) -> &mut Self {
+        self.audit_trail = enabled;
+        self
+    }
+
+    /// ジャーナルのGCによって回収せずに保持する、監査レコードの数を設定する.
+    ///
+    /// 通番(`AuditRecord::seqno`)が新しいものから`retention`個までの監査レコードは、
+    /// 対象の操作の記録が不要になった後も、ジャーナル内に保持され続ける.
+    /// 保持されている監査レコードはジャーナル領域を消費し続けるため、大きな値を指定する場合には、
+    /// ジャーナル領域のサイズ(`journal_region_ratio`)もそれに見合ったものにする必要がある.
+    ///
+    /// 監査証跡が無効な場合(`audit_trail`)には、この設定に関わらず、監査レコードは保持されない.
+    ///
+    /// デフォルト値は`1024`.
+    pub fn audit_retention(&mut self, retention: u64) -> &mut Self {
+        self.audit_retention = retention;
+        self
+    }
+
+    /// 内容が同一のlump同士で、データ領域の部分領域を共有する(重複排除)かどうかを設定する.
+    ///
+    /// 有効にした場合には、データ領域に格納されるlumpのPUT時にデータのハッシュ値が計算され、
+    /// 同じ内容のlumpが既に存在するなら、新たな領域を割り当てずに、その部分領域を共有するようになる.
+    /// 共有されている部分領域は参照数で管理され、参照している全てのlumpが削除された時点で解放される.
+    /// なお、ジャーナル領域に埋め込まれるlumpは対象外.
+    ///
+    /// 同一内容のデータを大量に格納するワークロードでは、データ領域の使用量を大幅に削減できるが、以下の点には注意が必要:
+    ///
+    /// - PUTの度にハッシュ値の計算が行われ、ハッシュ値が一致した場合には既存データの読み込みと比較も発生する
+    /// - 重複排除付きのPUTは、ハッシュ値を含む必須の拡張レコードとしてジャーナルに記録される.
+    ///   そのため、一度でも有効にして書き込みを行ったストレージは、重複排除に対応していないバージョンでは開けなくなる
+    /// - 重複排除付きのPUTの永続性は、通常のPUTと同様にジャーナルの同期に依存する.
+    ///   共有元のlumpのレコードの方が必ず先に記録されているため、クラッシュ時に共有先のみが復元されることはない
+    /// - ジャーナルのGCは、各lumpのレコードを個別に扱う.
+    ///   lump毎にレコードが存在するため、ジャーナル領域の使用量やGCのコストは、重複排除を行わない場合と変わらない
+    /// - 共有されている部分領域のデータが破損した場合には、それを参照する全てのlumpが影響を受ける
+    /// - 重複排除によって書き込まれたlumpが存在する間は、クローズ時のチェックポイントの書き出しが行われないため、
+    ///   次回のオープン時には、常にジャーナル全体の再生が必要となる
+    /// - `Storage::delete_secure`による上書きは、データが他のlumpと共有されていない場合にのみ行われる
+    ///
+    /// この設定は新規のPUTの挙動のみに影響し、無効にしてオープンした場合でも、
+    /// 既に共有されている部分領域の参照数の管理は継続される.
+    ///
+    /// デフォルト値は`false`.
+    pub fn deduplication(&mut self, enabled: bool) -> &mut Self {
+        self.deduplication = enabled;
+        self
+    }
+
+    /// 既存のlumpを同一の内容で上書きするPUTを検出して、その書き込みを省略するかどうかを設定する.
+    ///
+    /// 有効にした場合には、`Storage::put`の対象のlumpが既に存在し、かつ、その格納先の領域の種類
+    /// (ジャーナル領域ないしデータ領域)が同じであれば、既存のデータが読み込まれ、新しいデータとの比較が行われる.
+    /// 長さと内容が一致した場合には、データ領域への書き込みとジャーナルへの記録は共に省略され、
+    /// `PutReport::is_unchanged`が`true`となる結果が返される.
+    ///
+    /// 同一内容の再PUTが頻発するワークロード(e.g., 定期的な整合性回復処理)での、書き込み量の削減を目的としている.
+    /// 一方で、上書きとなるPUTの度に既存データの読み込みが発生するので、そうではないワークロードでは無効にしておくのが望ましい.
+    ///
+    /// 書き込みを省略したPUTの数は`StorageMetrics::unchanged_overwrites`で取得可能.
+    ///
+    /// デフォルト値は`false`.
+    pub fn skip_identical_overwrites(&mut self, enabled: bool) -> &mut Self {
+        self.skip_identical_overwrites = enabled;
+        self
+    }
+
+    /// 既存のlumpを同じブロック数のデータで上書きするPUTで、既存のデータ部分領域に直接書き込むかどうかを設定する.
+    ///
+    /// 有効にした場合には、`Storage::put`の対象のlumpが既にデータ領域に存在し、新しいデータの格納に必要なブロック数が
+    /// 既存の部分領域と等しければ、新しい部分領域の割当と古い部分領域の解放を行わずに、既存の部分領域の内容が書き換えられる.
+    /// その際には、上書き更新が行われたことを示す(インデックスには影響を与えない)レコードがジャーナルに追記される.
+    ///
+    /// 固定長のlumpの更新が頻発するワークロードでの、アロケータの負荷の削減を目的としている.
+    ///
+    /// ただし、通常のPUTとは異なり、書き込みの途中でクラッシュした場合には、
+    /// 対象のlumpの内容が新旧のデータの混在した(i.e., 古いデータも新しいデータも失われた)状態となり得る.
+    /// そのため、このような状態を許容できる場合にのみ有効にすること.
+    ///
+    /// なお、重複排除が有効な場合や、部分領域が別名(`Storage::link`)によって共有されている場合には、上書き更新は行われない.
+    ///
+    /// 上書き更新が行われたPUTの数は`StorageMetrics::in_place_overwrites`で取得可能.
+    ///
+    /// デフォルト値は`false`.
+    pub fn overwrite_in_place(&mut self, enabled: bool) -> &mut Self {
+        self.overwrite_in_place = enabled;
+        self
+    }
+
+    /// データ領域の空き領域の内で、割当時の探索対象から隔離する断片のサイズの閾値(ブロック数)を設定する.
+    ///
+    /// 小さなlumpの書き込みと削除が繰り返されると、データ領域の空き領域のリストが非常に長くなり、
+    /// 割当(BestFit戦略)や解放の際の処理コストが増加してしまう.
+    /// この値を指定した場合には、サイズが閾値未満の空き領域(断片)は探索対象のリストから外されて、別枠で管理されるようになる.
+    ///
+    /// 断片は、隣接する部分領域が解放された際には通常通りに併合され、閾値以上のサイズになれば探索対象に戻る.
+    /// それ以外では`Storage::reclaim_free_fragments`が呼ばれるまでは、
+    /// (断片以外に十分な空き領域がないためにPUTが失敗する場合でも)割当に使用されない.
+    ///
+    /// 隔離されている断片の数や合計サイズは、`DataAllocatorMetrics::fragment_portions`および
+    /// `DataAllocatorMetrics::fragment_bytes`で取得可能.
+    ///
+    /// デフォルト値は`0`(i.e., 隔離を行わない).
+    pub fn free_fragment_threshold(&mut self, blocks: u16) -> &mut Self {
+        self.free_fragment_threshold = blocks;
+        self
+    }
+
+    /// データ領域の空き領域のリストの長さの上限を設定する.
+    ///
+    /// リストの長さが上限を超えた場合には、リスト内のサイズが最小の空き領域から順に、
+    /// 断片として探索対象から隔離される(`free_fragment_threshold`とは異なり、サイズによらない).
+    /// 隔離された断片の扱いは`free_fragment_threshold`の場合と同様.
+    ///
+    /// デフォルト値は`0`(i.e., 上限なし).
+    pub fn max_free_portions(&mut self, max: usize) -> &mut Self {
+        self.max_free_portions = max;
+        self
+    }
+
+    /// サイズが`threshold`バイト以上のlumpを、データ領域内の`alignment`バイトの境界に揃えて配置するように設定する.
+    ///
+    /// 小さなlumpと大きなlumpとが入り混じって配置されていると、小さなlumpが削除された後の空き領域が
+    /// 大きなlumpの割当に使えずに、断片化が進行しやすくなる.
+    /// 大きなlumpの配置を一定の境界(e.g., 1MiB)に揃えることで、このような干渉を抑制することが目的.
+    ///
+    /// 境界はデータ領域の先頭からの相対位置で計算される.
+    /// 境界に揃った空き領域が存在しない場合には、PUTを失敗させることはせずに、境界を無視した割当が行われる.
+    /// それぞれの割当の回数は`DataAllocatorMetrics::aligned_allocations`および
+    /// `DataAllocatorMetrics::alignment_fallbacks`で取得可能.
+    ///
+    /// `alignment`はブロックサイズの倍数である必要があり、そうではない場合には、
+    /// ストレージの作成ないしオープン時に`ErrorKind::InvalidInput`エラーとなる.
+    ///
+    /// デフォルトでは、アライメントは行われない.
+    pub fn large_lump_alignment(&mut self, threshold: u32, alignment: u32) -> &mut Self {
+        self.large_lump_alignment = Some((threshold, alignment));
+        self
+    }
+
+    /// オープン時に、NVMの容量がヘッダに記載のストレージサイズよりも大きい場合に、
+    /// 超過部分を無視してオープンするかどうかを設定する.
+    ///
+    /// オープン時には、ヘッダに記載のストレージサイズ(`StorageHeader::storage_size`)とNVMの容量が比較される.
+    /// NVMの方が小さい場合(e.g., ファイルが切り詰められた)には、常に`ErrorKind::StorageCorrupted`エラーとなる.
+    /// NVMの方がブロックサイズ以上大きい場合には、誤ったNVMを指定している可能性があるため、
+    /// デフォルトでは`ErrorKind::InvalidInput`エラーとなるが、
+    /// この設定が有効な場合には、データ領域をヘッダに記載のサイズに制限した上でオープンする.
+    ///
+    /// デフォルト値は`false`.
+    pub fn clamp_oversized_nvm(&mut self, enabled: bool) -> &mut Self {
+        self.clamp_oversized_nvm = enabled;
+        self
+    }
+
+    /// オープン時に、ストレージのマイナーバージョンが古い場合に、ヘッダを最新のバージョンに更新するかどうかを設定する.
+    ///
+    /// 更新はヘッダ(およびそのバックアップ)の書き換えのみによって行われ、その旨はログに出力される.
+    ///
+    /// 無効にした場合には、ストレージは古いバージョンのままで扱われ、ヘッダは書き換えられない.
+    /// また、以後の書き込みは全てそのバージョンの形式で行われ、それより新しいバージョンで導入された形式
+    /// (e.g., ジャーナルのエポック、拡張レコード)のデータは書き込まれない.
+    /// そのため、バージョンによっては、一部の機能(e.g., 重複排除、監査証跡、クリーンシャットダウンの印)は無効となり、
+    /// `Storage::link`や`Storage::rename`がエラーとなることがある.
+    ///
+    /// なお、これはストレージを読み込み専用でオープンするものではなく、
+    /// 更新操作を行えば(古いバージョンの形式で)ジャーナル等への書き込みは行われる.
+    /// 更新操作を行わなければ、前回のクローズ時のチェックポイントの破棄(ジャーナルのヘッダの書き直し)を除いて、
+    /// 書き込みは行われないため、調査用のツール等、ストレージファイルを変更したくない場合に使用できる.
+    ///
+    /// いずれの場合でも、オープン時点でのバージョンは`StorageHeader::original_minor_version`で取得可能.
+    ///
+    /// デフォルト値は`true`.
+    pub fn upgrade_minor_version(&mut self, enabled: bool) -> &mut Self {
+        self.upgrade_minor_version = enabled;
+        self
+    }
+
+    /// データ領域へのアクセス回数を、領域を`buckets`個に等分割したバケット毎に数えるかどうかを設定する.
+    ///
+    /// 数えた結果は`DataRegionMetrics::access_heatmap`で取得できる.
+    /// カウンタの更新はアトミック変数に対する加算のみなので、有効にした場合のオーバヘッドは小さい.
+    ///
+    /// `0`の場合には数えない.
+    ///
+    /// デフォルト値は`0`.
+    pub fn access_heatmap(&mut self, buckets: usize) -> &mut Self {
+        self.access_heatmap_buckets = buckets;
+        self
+    }
+
+    /// データ領域に対する先読みのサイズ(バイト)を設定する.
+    ///
+    /// 連続して書き込まれたlump群を順番に読み込む場合等、
+    /// 直前に読み込んだ部分領域の直後に位置する部分領域に対する読み込みが行われた際に、
+    /// その後続の`size`バイト分も併せて読み込み、小さなバッファに保持しておく.
+    /// 以後の読み込み対象がバッファに含まれていれば、NVMへのアクセスは行われない.
+    ///
+    /// 先読みの効果は`DataRegionMetrics::read_ahead_hits`等で確認できる.
+    ///
+    /// サイズはブロック境界に切り捨てられる.
+    /// `0`の場合には先読みは行われず、各読み込みのI/Oサイズはlumpのサイズのみによって決まる.
+    ///
+    /// デフォルト値は`0`.
+    pub fn read_ahead_size(&mut self, size: usize) -> &mut Self {
+        self.read_ahead_size = size;
+        self
+    }
+
+    /// データ領域にlumpデータを書き込む際の、一度の書き込みの最大サイズ(バイト)を設定する.
+    ///
+    /// 巨大なlumpの書き込みは、一度の書き込みでディスクを長時間占有してしまい、
+    /// 後続の読み込みのレイテンシを悪化させる原因となる.
+    /// このサイズを超えるlumpデータは、複数回の書き込みに分割されるようになる.
+    ///
+    /// また、デバイス(`Device`)経由のPUTの場合には、各分割書き込みの合間に、
+    /// キューの先頭にある読み込み系のコマンド(e.g., GET)を一つだけ割り込ませて処理するようになる.
+    /// これによって、スループットを若干犠牲にする代わりに、大きなPUTの背後で待たされる読み込みのレイテンシが抑えられる.
+    /// ただし、以下のいずれかに該当する場合には、割り込みは行われない(書き込み自体は分割される):
+    ///
+    /// - 重複排除(`deduplication`)、同一内容の上書きの省略(`skip_identical_overwrites`)、
+    ///   上書き更新(`overwrite_in_place`)のいずれかが有効
+    /// - 疎な形式での格納(`sparse_lumps`)が有効
+    /// - `LumpData`が`Storage::allocate_lump_data`等によってブロック境界に揃えて生成されていない
+    /// - パディング部分の埋め直し(`padding_fill_byte`)が必要
+    ///
+    /// 分割書き込みの回数は`DataRegionMetrics::written_slices`で確認できる.
+    ///
+    /// サイズはブロック境界に切り捨てられる(ただし、最低でも1ブロック分となる).
+    /// 1MiB〜4MiB程度の値を指定するのが目安.
+    /// `0`の場合には、各lumpデータは一度に書き込まれる.
+    ///
+    /// デフォルト値は`0`.
+    pub fn write_slice_size(&mut self, size: usize) -> &mut Self {
+        self.write_slice_size = size;
+        self
+    }
+
+    /// データ領域に格納されているlumpの内で、サイズが`size`バイト以下のものを、
+    /// 補助的な処理(`Storage::run_side_job_once`)の中でジャーナル領域に埋め込む形に移し替えるように設定する.
+    ///
+    /// 大きなデータで作成された後に、小さなデータで上書きされたlumpが占有していたブロックを解放することが目的.
+    /// 移し替えの対象となるのはデータ部分領域のサイズ(ブロック数)が`size`バイト分以下のlumpのみであり、
+    /// 移し替えが行われたlumpの数は`StorageMetrics::reembedded_lumps`で取得可能.
+    ///
+    /// なお、別名(`Storage::link`)や重複排除によって部分領域が共有されているlumpは対象外となる.
+    ///
+    /// デフォルト値は`0`(i.e., 移し替えを行わない).
+    pub fn reembed_threshold(&mut self, size: u16) -> &mut Self {
+        self.reembed_threshold = size;
+        self
+    }
+
+    /// `reembed_threshold`による移し替えの際に、補助的な処理の一回当たりに走査するlumpの最大数を設定する.
+    ///
+    /// インデックスは前回の続きから走査され、末尾に達した場合には先頭に戻る.
+    ///
+    /// デフォルト値は`16`.
+    pub fn reembed_batch_size(&mut self, size: usize) -> &mut Self {
+        self.reembed_batch_size = size;
+        self
+    }
+
+    /// インデックスのシャドウファイルのパスを設定する.
+    ///
+    /// 指定された場合には、インデックスとアロケータの空き領域リストの内容が、
+    /// 補助的な処理(`Storage::run_side_job_once`)の中で定期的に(`index_shadow_interval`を参照)、
+    /// およびクローズ時に、このファイルに書き出されるようになる.
+    ///
+    /// オープン時には、このファイルがメモリマップ経由で読み込まれ、
+    /// 書き出し時点のジャーナルの状態が現在のものと一致する(i.e., 書き出し以降にジャーナルが更新されていない)場合には、
+    /// ジャーナルの再生およびアロケータの再構築が省略される.
+    /// ファイルが存在しない場合や、内容が古いないし壊れている場合には、自動的にジャーナルから状態が復元される.
+    /// シャドウファイルが使用されたかどうかは`StorageMetrics::index_shadow_restores`で、
+    /// ファイルが存在したにも関わらず使用されなかったかどうかは`StorageMetrics::index_shadow_failures`で確認できる.
+    ///
+    /// なお、重複排除によって共有されている部分領域が存在する場合には、書き出しは省略される.
+    /// また、ストレージの新規作成時には、指定パスに既に存在するファイルは削除される.
+    ///
+    /// デフォルトでは、シャドウファイルは使用されない.
+    pub fn index_shadow_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.index_shadow_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// インデックスのシャドウファイルを更新する最短の間隔を設定する.
+    ///
+    /// 前回の書き出し以降にジャーナルが更新されていて、かつ、この間隔が経過している場合に、
+    /// 補助的な処理の中でシャドウファイルが書き出される.
+    /// 書き出しの度にインデックス全体がエンコードされるため、あまり短い値は推奨されない.
+    ///
+    /// `index_shadow_file`が指定されていない場合には、この設定は無視される.
+    ///
+    /// デフォルト値は`60`秒.
+    pub fn index_shadow_interval(&mut self, interval: Duration) -> &mut Self {
+        self.index_shadow_interval = interval;
+        self
+    }
+
+    /// ログ出力に使用する logger を登録する.
+    ///
+    /// デフォルトでは何も出力しない.
+    pub fn logger(&mut self, logger: Logger) -> &mut Self {
+        self.logger = logger;
+        self
+    }
+
     /// 新規にストレージを生成する.
     pub fn create<N>(&self, mut nvm: N) -> Result<Storage<N>>
     where
@@ -125,9 +811,19 @@ impl StorageBuilder {
         // NVMのブロック境界に揃っているかを確認
         track_assert!(
             storage_block_size.contains(nvm.block_size()),
-            ErrorKind::InvalidInput; storage_block_size, nvm.block_size()
+            ErrorKind::BlockSizeMismatch,
+            "The storage block size is not aligned to the NVM: storage_block_size={}, nvm_block_size={}",
+            storage_block_size.as_u16(),
+            nvm.block_size().as_u16()
         );
 
+        if let Some((_, alignment)) = self.large_lump_alignment {
+            track_assert_eq!(
+                alignment % u32::from(storage_block_size.as_u16()),
+                0,
+                ErrorKind::InvalidInput
+            );
+        }
         let header = track!(self.make_header(nvm.capacity(), storage_block_size))?;
 
         track_io!(nvm.seek(SeekFrom::Start(0)))?;
@@ -136,11 +832,21 @@ impl StorageBuilder {
             track!(header.write_header_region_to(&mut temp_buf))?;
 
             // ジャーナル領域を初期化する
-            track!(JournalRegion::<N>::initialize(temp_buf, storage_block_size))?;
+            track!(JournalRegion::<N>::initialize(
+                temp_buf,
+                storage_block_size,
+                header.journal_checksum,
+                header.journal_header_slots
+            ))?;
 
             Ok(())
         }))?;
+        track!(write_backup_header(&mut nvm, &header))?;
         track!(nvm.sync())?;
+        if let Some(path) = self.index_shadow_file.as_ref() {
+            // 以前のストレージのシャドウファイルが残っていても、誤って使用されることがないようにする
+            track!(IndexShadow::remove_file(path))?;
+        }
 
         track!(self.open(nvm))
     }
@@ -150,21 +856,44 @@ impl StorageBuilder {
     where
         N: NonVolatileMemory,
     {
-        track_io!(nvm.seek(SeekFrom::Start(0)))?;
+        // ヘッダを読み込む(先頭のヘッダが壊れている場合には、末尾のバックアップを使用する)
+        let mut header = track!(StorageHeader::read_from_nvm(&mut nvm))?;
 
-        // ヘッダを読み込む(アライメントを保証するためにバッファを経由)
-        let buf = track!(nvm.aligned_read_bytes(FULL_HEADER_SIZE as usize))?;
-        let mut header = track!(StorageHeader::read_from(&buf[..]))?;
+        // 誤ったストレージを更新してしまわないように、識別子のチェックは最初に行う
+        if let Some(expected_uuid) = self.instance_uuid {
+            track_assert_eq!(header.instance_uuid, expected_uuid, ErrorKind::InvalidInput);
+        }
+        if let Some(expected_name) = self.logical_name.as_ref() {
+            track_assert!(
+                header.logical_name.as_ref() == Some(expected_name),
+                ErrorKind::InvalidInput,
+                "Logical name mismatch (the storage may belong to another server or cluster): \
+                 expected={:?}, actual={:?}, instance_uuid={}",
+                expected_name,
+                header.logical_name,
+                header.instance_uuid
+            );
+        }
 
         // ストレージのマイナーバージョンが古い場合には、最新に更新する
-        if header.minor_version < MINOR_VERSION {
+        if header.minor_version < MINOR_VERSION && self.upgrade_minor_version {
+            warn!(
+                self.logger,
+                "Upgrades the minor version of the storage";
+                "instance_uuid" => %header.instance_uuid,
+                "from" => format!("{}.{}", header.major_version, header.minor_version),
+                "to" => format!("{}.{}", header.major_version, MINOR_VERSION),
+                "writer_version" => header.writer_version.map(|v| v.to_string())
+            );
             header.minor_version = MINOR_VERSION;
+            header.writer_version = Some(CrateVersion::CURRENT);
 
             track_io!(nvm.seek(SeekFrom::Start(0)))?;
             track!(nvm.aligned_write_all(|temp_buf| {
                 track!(header.write_header_region_to(temp_buf))?;
                 Ok(())
             }))?;
+            track!(write_backup_header(&mut nvm, &header))?;
         }
 
         // `nvm`がストレージが採用しているブロックサイズに対応可能かを確認
@@ -173,56 +902,310 @@ impl StorageBuilder {
         // 完全一致ではなくても許容する
         track_assert!(
             header.block_size.contains(nvm.block_size()),
-            ErrorKind::InvalidInput
+            ErrorKind::BlockSizeMismatch,
+            "The storage block size is not aligned to the NVM: storage_block_size={}, nvm_block_size={}",
+            header.block_size.as_u16(),
+            nvm.block_size().as_u16()
         );
         let mut journal_options = self.journal.clone();
         journal_options.block_size = header.block_size;
+        journal_options.checksum = header.journal_checksum;
+        journal_options.header_slots = header.journal_header_slots;
+        journal_options.minor_version = header.minor_version;
+        journal_options.logger = self.logger.clone();
 
-        // UUIDをチェック
-        if let Some(expected_uuid) = self.instance_uuid {
-            track_assert_eq!(header.instance_uuid, expected_uuid, ErrorKind::InvalidInput);
-        }
+        // NVMの容量がヘッダの記載と整合しているかを確認
+        track!(self.check_nvm_capacity(&header, nvm.capacity()))?;
 
-        // ジャーナルからインデックスとアロケータの状態を復元する
-        let mut lump_index = LumpIndex::new();
-        let (journal_nvm, data_nvm) = track!(header.split_regions(nvm))?;
-        let journal_region = track!(JournalRegion::open(
+        let (journal_nvm, mut data_nvm) = track!(header.split_regions(nvm))?;
+        if data_nvm.capacity() > header.data_region_size {
+            // 超過部分は使用しない
+            let (nvm, _) = track!(data_nvm.split(header.data_region_size))?;
+            data_nvm = nvm;
+        }
+        let (mut journal_region, checkpoint_location) = track!(JournalRegion::open(
             journal_nvm,
-            &mut lump_index,
             &self.metrics,
             journal_options
         ))?;
+        let allocator_metrics =
+            DataAllocatorMetrics::new(&self.metrics, header.data_region_size, header.block_size);
 
-        let allocator = track!(DataPortionAllocator::build(
-            DataAllocatorMetrics::new(&self.metrics, header.data_region_size, header.block_size),
-            lump_index.data_portions(),
-        ))?;
+        // 前回のクローズ時に書き出されたチェックポイントが有効なら、そこから状態を復元する
+        let data_region_blocks = header.data_region_size / u64::from(header.block_size.as_u16());
+        let mut checkpoint = None;
+        let mut checkpoint_failed = false;
+        if let Some(location) = checkpoint_location {
+            match track!(Checkpoint::read_from(
+                &mut data_nvm,
+                header.block_size,
+                &location
+            )) {
+                Err(e) => {
+                    warn!(
+                        self.logger,
+                        "Cannot read the checkpoint; restores the state from the journal";
+                        "instance_uuid" => %header.instance_uuid,
+                        "error" => %e
+                    );
+                    checkpoint_failed = true;
+                }
+                Ok(c) => {
+                    if let Err(e) = track!(c.validate(data_region_blocks)) {
+                        // 重複の修復等は、ジャーナルの再生時に通常通りに行われる
+                        warn!(
+                            self.logger,
+                            "The checkpoint is inconsistent; restores the state from the journal";
+                            "instance_uuid" => %header.instance_uuid,
+                            "error" => %e
+                        );
+                        checkpoint_failed = true;
+                    } else if track!(journal_region.restore_from_checkpoint(&location))? {
+                        checkpoint = Some(c);
+                    }
+
+                    // チェックポイントは空き領域上に存在するため、以後の書き込みで上書きされうる
+                    track!(journal_region.clear_checkpoint())?;
+                }
+            }
+        }
+
+        // チェックポイントが使えない場合には、インデックスのシャドウファイルが有効であれば、そこから状態を復元する
+        let mut restored_from_shadow = false;
+        let mut shadow_failed = false;
+        if let (None, Some(path)) = (checkpoint.as_ref(), self.index_shadow_file.as_ref()) {
+            // ファイルが存在しないのは、一度も書き出されていない場合の通常の状態なので、失敗とはみなさない
+            if path.exists() {
+                match track!(IndexShadow::read_from_file(path, &header.instance_uuid)) {
+                    Err(e) => {
+                        warn!(
+                            self.logger,
+                            "Cannot read the index shadow file; restores the state from the journal";
+                            "instance_uuid" => %header.instance_uuid,
+                            "path" => %path.display(),
+                            "error" => %e
+                        );
+                        shadow_failed = true;
+                    }
+                    Ok(shadow) => {
+                        if track!(journal_region.restore_from_index_shadow(&shadow.stamp))? {
+                            checkpoint = Some(shadow.checkpoint);
+                            restored_from_shadow = true;
+                        } else {
+                            // 書き出し以降にジャーナルが更新されている
+                            let (head, tail) = journal_region.ring_buffer_position();
+                            info!(
+                                self.logger,
+                                "The index shadow file is stale; restores the state from the journal";
+                                "instance_uuid" => %header.instance_uuid,
+                                "path" => %path.display(),
+                                "shadow_head" => shadow.stamp.ring_buffer_head,
+                                "shadow_tail" => shadow.stamp.ring_buffer_tail,
+                                "journal_head" => head,
+                                "journal_tail" => tail
+                            );
+                            shadow_failed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        // 重複排除付きのPUTレコードは拡張レコードなので、それに対応していないバージョンでは無効にする
+        let deduplication = self.deduplication && journal_region.supports_extension_records();
+        let restored_from_snapshot = checkpoint.is_some();
+        let (mut lump_index, allocator, dedup) = if let Some(checkpoint) = checkpoint {
+            // チェックポイントおよびシャドウファイルは、重複排除されたlumpが存在しない場合にのみ書き出される
+            let allocated_portions = checkpoint.index.data_portions().count() as u64;
+            let allocator = track!(DataPortionAllocator::restore(
+                allocator_metrics,
+                checkpoint.free_portions.into_iter(),
+                allocated_portions
+            ))?;
+            let dedup = DedupTable::new(deduplication);
+            (checkpoint.index, allocator, dedup)
+        } else {
+            // ジャーナルからインデックスとアロケータの状態を復元する
+            let mut lump_index = LumpIndex::new();
+            let mut hashes = ContentHashes::new();
+            track!(journal_region.restore(&mut lump_index, &mut hashes))?;
+            let allocator = track!(self.build_allocator(
+                allocator_metrics,
+                &mut journal_region,
+                &mut lump_index,
+                &hashes
+            ))?;
+            let dedup = DedupTable::restore(deduplication, hashes, &lump_index);
+            (lump_index, allocator, dedup)
+        };
 
         // データ領域を準備
-        let data_region = DataRegion::new(&self.metrics, allocator, data_nvm);
+        //
+        // 通常運用時のデータ領域へのアクセスはランダムとなるので、その旨をNVMに通知しておく
+        track!(data_nvm.advise(0, 0, AccessPattern::Random))?;
+        let mut data_region = DataRegion::new(&self.metrics, allocator, data_nvm);
+        data_region.set_discard_mode(self.discard_released_portions);
+        data_region.set_scrub_mode(self.scrub_released_portions);
+        data_region.set_scrub_rate_limit(self.scrub_rate_limit);
+        data_region.set_fragment_threshold(self.free_fragment_threshold);
+        data_region.set_max_free_portions(self.max_free_portions);
+        if let Some((threshold, alignment)) = self.large_lump_alignment {
+            track!(data_region.set_large_lump_alignment(threshold, alignment))?;
+        }
+        data_region.set_padding_fill_byte(self.padding_fill_byte);
+        data_region.set_sparse_mode(header.sparse_lumps);
+        data_region.set_write_verification(self.verify_data_writes);
+        data_region.set_access_heatmap(self.access_heatmap_buckets);
+        data_region.set_read_ahead_size(self.read_ahead_size);
+        data_region.set_write_slice_size(self.write_slice_size);
+        journal_region.set_embedded_data_verification(self.verify_embedded_data);
+        journal_region.set_audit_trail(self.audit_trail);
+        journal_region.set_audit_retention(self.audit_retention);
+        if restored_from_snapshot && self.audit_trail {
+            // ジャーナルのエントリ群を読み込んでいないので、監査レコードの通番を別途求める
+            track!(journal_region.restore_audit_seqno())?;
+        }
 
+        let usage_summary = UsageSummary::new(header.block_size);
+        lump_index.attach_usage_summary(usage_summary.clone());
         let metrics = StorageMetrics::new(
             &self.metrics,
             &header,
             journal_region.metrics().clone(),
             data_region.metrics().clone(),
+            usage_summary,
         );
         metrics.put_lumps_at_starting.add_u64(lump_index.len());
+        if restored_from_shadow {
+            metrics.index_shadow_restores.increment();
+        }
+        if checkpoint_failed {
+            metrics.checkpoint_failures.increment();
+        }
+        if shadow_failed {
+            metrics.index_shadow_failures.increment();
+        }
+        let config = self.resolve_config(&header);
         Ok(Storage::new(
             header,
             journal_region,
             data_region,
             lump_index,
+            dedup,
             metrics,
+            config,
         ))
     }
 
+    fn resolve_config(&self, header: &StorageHeader) -> StorageConfig {
+        StorageConfig {
+            block_size: header.block_size,
+            storage_size: header.storage_size(),
+            journal_region_size: header.journal_region_size,
+            data_region_size: header.data_region_size,
+            journal_checksum: header.journal_checksum,
+            journal_header_slots: header.journal_header_slots,
+            sparse_lumps: header.sparse_lumps,
+            journal_sync_interval: self.journal.sync_interval,
+            journal_gc_queue_size: self.journal.gc_queue_size,
+            journal_gc_batch_size: self.journal.gc_batch_size,
+            journal_adaptive_gc_max_steps: self.journal.adaptive_gc_max_steps,
+            journal_max_write_buffer_size: self.journal.max_write_buffer_size,
+            journal_restore_buffer_size: self.journal.restore_buffer_size,
+            journal_safe_flush: self.journal.safe_flush,
+            journal_sync_target_latency: self.journal.adaptive_sync.map(|o| o.target_latency),
+            journal_admission_action: self.journal.admission_control.map(|o| o.action),
+            journal_background_gc_scan: false,
+            discard_released_portions: self.discard_released_portions,
+            scrub_released_portions: self.scrub_released_portions,
+            scrub_rate_limit: self.scrub_rate_limit,
+            verify_embedded_data: self.verify_embedded_data,
+            verify_data_writes: self.verify_data_writes,
+            audit_trail: self.audit_trail,
+            audit_retention: self.audit_retention,
+            deduplication: self.deduplication,
+            skip_identical_overwrites: self.skip_identical_overwrites,
+            overwrite_in_place: self.overwrite_in_place,
+            upgrade_minor_version: self.upgrade_minor_version,
+            free_fragment_threshold: self.free_fragment_threshold,
+            max_free_portions: self.max_free_portions,
+            large_lump_alignment: self.large_lump_alignment,
+            read_ahead_size: self.read_ahead_size,
+            write_slice_size: self.write_slice_size,
+            reembed_threshold: self.reembed_threshold,
+            index_shadow_file: self.index_shadow_file.clone(),
+            index_shadow_interval: self.index_shadow_interval,
+            reembed_batch_size: self.reembed_batch_size,
+            access_heatmap_buckets: self.access_heatmap_buckets,
+        }
+    }
+
+    fn build_allocator<N>(
+        &self,
+        metrics: DataAllocatorMetrics,
+        journal_region: &mut JournalRegion<N>,
+        lump_index: &mut LumpIndex,
+        hashes: &ContentHashes,
+    ) -> Result<DataPortionAllocator>
+    where
+        N: NonVolatileMemory,
+    {
+        // 重複排除によって共有されている部分領域は、一つのlumpのもののみをアロケータに渡す
+        let secondaries = hashes.secondary_references(lump_index);
+        let portions = lump_index
+            .data_portions()
+            .filter(|(lump_id, _)| !secondaries.contains(lump_id));
+        if self.drop_overlapping_portions {
+            // 重複している部分領域同士では、ジャーナル上で先に登録された方を残す
+            let (allocator, dropped) = track!(DataPortionAllocator::build_dropping_conflicts(
+                metrics,
+                portions,
+                |involved| journal_region.data_portion_order(involved)
+            ))?;
+            if !dropped.is_empty() {
+                for lump_id in &dropped {
+                    lump_index.remove(lump_id);
+                    track!(journal_region.records_delete(lump_index, lump_id))?;
+                }
+                track!(journal_region.sync())?;
+            }
+            Ok(allocator)
+        } else {
+            track!(DataPortionAllocator::build(metrics, portions))
+        }
+    }
+
+    fn check_nvm_capacity(&self, header: &StorageHeader, capacity: u64) -> Result<()> {
+        let storage_size = header.storage_size();
+        track_assert!(
+            capacity >= storage_size,
+            ErrorKind::StorageCorrupted,
+            "The NVM is smaller than the storage (truncated?): capacity={}, storage_size={}",
+            capacity,
+            storage_size
+        );
+
+        // 作成時のアライメントにより、ブロックサイズ未満の余りが生じるのは正常
+        let excess = capacity - storage_size;
+        track_assert!(
+            excess < u64::from(header.block_size.as_u16()) || self.clamp_oversized_nvm,
+            ErrorKind::InvalidInput,
+            "The NVM is larger than the storage: capacity={}, storage_size={}",
+            capacity,
+            storage_size
+        );
+        Ok(())
+    }
+
     fn make_header(&self, capacity: u64, block_size: BlockSize) -> Result<StorageHeader> {
+        // 先頭のヘッダ領域に加えて、末尾にバックアップヘッダ領域を確保する
+        let header_region_size = StorageHeader::calc_region_size(block_size) * 2;
         let journal_and_data_region_size = track_assert_some!(
-            capacity.checked_sub(StorageHeader::calc_region_size(block_size)),
-            ErrorKind::InvalidInput,
-            "Too small capacity: {}",
-            capacity
+            capacity.checked_sub(header_region_size),
+            ErrorKind::CapacityTooSmall,
+            "Too small capacity: capacity={}, required={}",
+            capacity,
+            header_region_size
         );
 
         track_assert!(
@@ -234,6 +1217,37 @@ impl StorageBuilder {
         let journal_region_size =
             (journal_and_data_region_size as f64 * self.journal_region_ratio) as u64;
         let journal_region_size = block_size.ceil_align(journal_region_size);
+
+        if let Some(name) = self.logical_name.as_ref() {
+            track_assert!(
+                name.len() <= MAX_LOGICAL_NAME_LEN,
+                ErrorKind::InvalidInput,
+                "Too long logical name: {} bytes (max={})",
+                name.len(),
+                MAX_LOGICAL_NAME_LEN
+            );
+        }
+
+        track_assert!(
+            matches!(self.journal.header_slots, 1 | 2),
+            ErrorKind::InvalidInput,
+            "Invalid journal header slots: {}",
+            self.journal.header_slots
+        );
+
+        // ジャーナル領域には、少なくともヘッダと終端レコード用のブロックが必要
+        let min_journal_region_size =
+            JournalHeader::slots_region_size(block_size, self.journal.header_slots)
+                + u64::from(block_size.as_u16());
+        track_assert!(
+            journal_region_size >= min_journal_region_size,
+            ErrorKind::CapacityTooSmall,
+            "Too small journal region: journal_region_size={}, required={} (capacity={}, ratio={})",
+            journal_region_size,
+            min_journal_region_size,
+            capacity,
+            self.journal_region_ratio
+        );
         track_assert!(
             journal_region_size <= MAX_JOURNAL_REGION_SIZE,
             ErrorKind::InvalidInput,
@@ -257,10 +1271,22 @@ impl StorageBuilder {
         Ok(StorageHeader {
             major_version: MAJOR_VERSION,
             minor_version: MINOR_VERSION,
+            original_minor_version: MINOR_VERSION,
             instance_uuid: self.instance_uuid.unwrap_or_else(Uuid::new_v4),
             block_size,
             journal_region_size,
             data_region_size,
+            journal_checksum: self.journal.checksum,
+            journal_header_slots: self.journal.header_slots,
+            backup_header: true,
+            writer_version: Some(CrateVersion::CURRENT),
+            logical_name: self.logical_name.clone(),
+            sparse_lumps: self.sparse_lumps,
+            creation_params: Some(StorageCreationParams {
+                journal_region_ratio: self.journal_region_ratio,
+                journal_gc_queue_size: self.journal.gc_queue_size,
+                journal_sync_interval: self.journal.sync_interval,
+            }),
         })
     }
 }
@@ -269,3 +1295,20 @@ impl Default for StorageBuilder {
         Self::new()
     }
 }
+
+/// ストレージの末尾にバックアップのヘッダを書き込む.
+///
+/// ストレージがバックアップを持たない場合には、何も行わない.
+pub(crate) fn write_backup_header<N>(nvm: &mut N, header: &StorageHeader) -> Result<()>
+where
+    N: NonVolatileMemory,
+{
+    if let Some(position) = header.backup_region_position() {
+        track_io!(nvm.seek(SeekFrom::Start(position)))?;
+        track!(nvm.aligned_write_all(|temp_buf| {
+            track!(header.write_header_region_to(temp_buf))?;
+            Ok(())
+        }))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,251 @@
+//! ストレージの整合性検査.
+use std::cmp;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
+
+use crate::lump::LumpId;
+use crate::nvm::NonVolatileMemory;
+use crate::storage::data_region::DataRegion;
+use crate::storage::dedup::DedupTable;
+use crate::storage::index::LumpIndex;
+use crate::storage::journal::{JournalCursor, JournalRegion};
+use crate::storage::portion::{DataPortion, Portion};
+use crate::Result;
+
+/// 整合性検査のレベル.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CheckLevel {
+    /// インデックスとデータ領域(アロケータ)の間の整合性のみを検査する.
+    ///
+    /// 具体的には、各lumpのデータ部分領域が「データ領域の範囲内に収まっているか」、
+    /// 「アロケータ上で割当済みとなっているか」、「他のlumpの部分領域と重複していないか」が検査される.
+    Index,
+
+    /// `Index`の検査に加えて、ジャーナルとインデックスの間の整合性も検査する.
+    ///
+    /// ジャーナルの内容を読み込んでインデックスを再構築し、
+    /// それが現在のインデックスと一致するかどうか
+    /// (i.e., この時点でストレージを開き直した場合に同じ状態が復元されるかどうか)が確認される.
+    Journal,
+}
+
+/// 整合性検査の結果.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    /// 実施された検査のレベル.
+    pub level: CheckLevel,
+
+    /// 検査対象となったlumpの数.
+    pub checked_lumps: u64,
+
+    /// データ部分領域がデータ領域の範囲外を指しているlumpのID群.
+    pub out_of_range_portions: Vec<LumpId>,
+
+    /// データ部分領域がアロケータ上では未割当(空き領域)となっているlumpのID群.
+    pub unallocated_portions: Vec<LumpId>,
+
+    /// データ部分領域が互いに重複しているlumpのIDの組.
+    pub overlapping_portions: Vec<(LumpId, LumpId)>,
+
+    /// インデックスから算出したデータ領域の使用量(バイト単位).
+    pub index_usage_bytes: u64,
+
+    /// アロケータが管理しているデータ領域の使用量(バイト単位).
+    ///
+    /// 整合性が取れている場合には`index_usage_bytes`と等しくなる.
+    pub allocator_usage_bytes: u64,
+
+    /// ジャーナルから再構築した内容と、現在のインデックスの内容が一致しないlumpのID群.
+    ///
+    /// 検査レベルが`CheckLevel::Journal`の場合にのみ設定される.
+    pub journal_mismatches: Vec<LumpId>,
+}
+impl CheckReport {
+    fn new(level: CheckLevel) -> Self {
+        CheckReport {
+            level,
+            checked_lumps: 0,
+            out_of_range_portions: Vec::new(),
+            unallocated_portions: Vec::new(),
+            overlapping_portions: Vec::new(),
+            index_usage_bytes: 0,
+            allocator_usage_bytes: 0,
+            journal_mismatches: Vec::new(),
+        }
+    }
+
+    /// 不整合が一つも検出されなかった場合には`true`を返す.
+    pub fn is_ok(&self) -> bool {
+        self.out_of_range_portions.is_empty()
+            && self.unallocated_portions.is_empty()
+            && self.overlapping_portions.is_empty()
+            && self.index_usage_bytes == self.allocator_usage_bytes
+            && self.journal_mismatches.is_empty()
+    }
+}
+
+/// 整合性検査を少しずつ進めるための状態.
+///
+/// [`Storage::check_step`]に繰り返し渡すことで、検査を複数回に分割して実行することができる.
+///
+/// 分割実行の途中でストレージの内容が更新された場合には、検査結果は不正確なものとなる可能性がある.
+///
+/// [`Storage::check_step`]: ./struct.Storage.html#method.check_step
+#[derive(Debug)]
+pub struct StorageChecker {
+    next: Option<LumpId>,
+    journal_cursor: Option<JournalCursor>,
+    journal_index: Option<LumpIndex>,
+    data_portions: BTreeMap<u64, (u64, LumpId)>,
+    report: CheckReport,
+    completed: bool,
+}
+impl StorageChecker {
+    /// 新しい`StorageChecker`インスタンスを生成する.
+    pub fn new(level: CheckLevel) -> Self {
+        let journal = level == CheckLevel::Journal;
+        StorageChecker {
+            next: Some(LumpId::new(0)),
+            journal_cursor: journal.then(JournalCursor::default),
+            journal_index: journal.then(LumpIndex::new),
+            data_portions: BTreeMap::new(),
+            report: CheckReport::new(level),
+            completed: false,
+        }
+    }
+
+    /// 検査が完了しているかどうかを判定する.
+    pub fn is_completed(&self) -> bool {
+        self.completed
+    }
+
+    /// 現時点での検査結果を返す.
+    pub fn report(&self) -> &CheckReport {
+        &self.report
+    }
+
+    /// 検査結果を取り出す.
+    pub fn into_report(self) -> CheckReport {
+        self.report
+    }
+
+    /// 検査を一単位分だけ進める.
+    ///
+    /// 一回の呼び出しで処理されるのは、最大`max_lumps`個のlumpないしジャーナルエントリ.
+    /// `CheckLevel::Journal`の場合には、まずジャーナルの読み込みが複数回に分けて行われ、
+    /// その完了後にインデックスの検査が開始される.
+    ///
+    /// 検査が完了した場合には`true`が返される.
+    pub(crate) fn step<N>(
+        &mut self,
+        index: &LumpIndex,
+        dedup: &DedupTable,
+        data_region: &DataRegion<N>,
+        journal_region: &mut JournalRegion<N>,
+        max_lumps: usize,
+    ) -> Result<bool>
+    where
+        N: NonVolatileMemory,
+    {
+        if self.completed {
+            return Ok(true);
+        }
+        let max_lumps = cmp::max(max_lumps, 1);
+        if let Some(cursor) = self.journal_cursor {
+            let journal_index = self.journal_index.as_mut().expect("Never fails");
+            self.journal_cursor = track!(journal_region.replay_unreleased_entries_step(
+                journal_index,
+                cursor,
+                max_lumps
+            ))?;
+            return Ok(false);
+        }
+
+        if let Some(start) = self.next {
+            let mut entries = index.entries_with_limit(start.., max_lumps.saturating_add(1));
+            self.next = if entries.len() > max_lumps {
+                entries.pop().map(|(lump_id, _)| lump_id)
+            } else {
+                None
+            };
+            for (lump_id, portion) in entries {
+                self.check_lump(dedup, data_region, lump_id, portion);
+            }
+        }
+
+        if self.next.is_none() {
+            // 返却が保留されている部分領域は、既にインデックスからは取り除かれている
+            self.report.allocator_usage_bytes =
+                data_region.metrics().usage_bytes() - data_region.unreleased_bytes();
+            if let Some(journal_index) = self.journal_index.take() {
+                // 現在のインデックスには存在しないが、ジャーナル上には存在するlump群
+                self.report.journal_mismatches.extend(journal_index.list());
+            }
+            self.completed = true;
+        }
+        Ok(self.completed)
+    }
+
+    fn check_lump<N>(
+        &mut self,
+        dedup: &DedupTable,
+        data_region: &DataRegion<N>,
+        lump_id: LumpId,
+        portion: Portion,
+    ) where
+        N: NonVolatileMemory,
+    {
+        self.report.checked_lumps += 1;
+        if let Portion::Data(portion) = portion {
+            if dedup.is_shared(&portion) && self.is_checked_portion(portion) {
+                // 重複排除によって共有されている部分領域は、既に検査済み
+                self.check_journal(lump_id, Portion::Data(portion));
+                return;
+            }
+            self.report.index_usage_bytes +=
+                u64::from(portion.len) * u64::from(data_region.block_size().as_u16());
+            if !data_region.contains(&portion) {
+                self.report.out_of_range_portions.push(lump_id);
+            } else if !data_region.is_allocated(&portion) {
+                self.report.unallocated_portions.push(lump_id);
+            }
+            self.check_overlap(lump_id, portion);
+        }
+        self.check_journal(lump_id, portion);
+    }
+
+    fn check_journal(&mut self, lump_id: LumpId, portion: Portion) {
+        if let Some(ref mut journal_index) = self.journal_index {
+            if journal_index.remove(&lump_id) != Some(portion) {
+                self.report.journal_mismatches.push(lump_id);
+            }
+        }
+    }
+
+    fn is_checked_portion(&self, portion: DataPortion) -> bool {
+        let start = portion.start.as_u64();
+        self.data_portions
+            .get(&start)
+            .is_some_and(|&(end, _)| end == portion.end().as_u64())
+    }
+
+    fn check_overlap(&mut self, lump_id: LumpId, portion: DataPortion) {
+        let start = portion.start.as_u64();
+        let end = portion.end().as_u64();
+        if let Some((_, &(prev_end, prev_id))) = self.data_portions.range(..=start).next_back() {
+            if start < prev_end {
+                self.report.overlapping_portions.push((prev_id, lump_id));
+            }
+        }
+        if let Some((&next_start, &(_, next_id))) = self
+            .data_portions
+            .range((Excluded(start), Unbounded))
+            .next()
+        {
+            if next_start < end {
+                self.report.overlapping_portions.push((next_id, lump_id));
+            }
+        }
+        self.data_portions.entry(start).or_insert((end, lump_id));
+    }
+}
@@ -0,0 +1,343 @@
+//! クリーンシャットダウン時に書き出されるチェックポイント.
+//!
+//! ストレージのクローズ時には、インデックスとアロケータの空き領域リストの内容が、
+//! データ領域内の空き領域(の中で最大のもの)に書き出され、その位置がジャーナルのヘッダに記録される.
+//!
+//! 次回のオープン時に、チェックポイントが有効であると判断された場合には、
+//! ジャーナルの再生およびアロケータの再構築を行わずに、その内容から直接状態が復元される.
+//!
+//! チェックポイントが有効とみなされるのは、以下の条件を全て満たす場合である:
+//!
+//! - チェックポイント作成時点から、ジャーナルに追記が行われていない
+//! - チェックポイントの内容のチェックサムが一致する
+//! - 復元された部分領域群が、互いに重複しておらず、データ領域の範囲内に収まっている
+//!
+//! 上記を満たさない場合(e.g., クラッシュ後)には、従来通りにジャーナルから状態が再構築される.
+//! ジャーナルの再生時には、重複している部分領域の扱いも`StorageBuilder::drop_overlapping_portions`に従う.
+//!
+//! なお、チェックポイントは空き領域に書き込まれており、以後の書き込みで上書きされうるため、
+//! 読み込めた場合には(利用したかどうかに関わらず)ジャーナルヘッダから即座に破棄される.
+use adler32::RollingAdler32;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, SeekFrom, Write};
+
+use crate::block::{AlignedBytes, BlockSize};
+use crate::lump::LumpId;
+use crate::nvm::NonVolatileMemory;
+use crate::storage::allocator::FreePortion;
+use crate::storage::index::LumpIndex;
+use crate::storage::portion::{DataPortion, JournalPortion, Portion};
+use crate::storage::Address;
+use crate::{ErrorKind, Result};
+
+/// チェックポイントの先頭に書き込まれるマジックナンバー.
+const CHECKPOINT_MAGIC_NUMBER: [u8; 4] = *b"lcpt";
+
+const PORTION_KIND_JOURNAL: u8 = 0;
+const PORTION_KIND_DATA: u8 = 1;
+
+/// ジャーナルヘッダに記録されるチェックポイントの位置情報.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointLocation {
+    /// チェックポイント作成時点でのジャーナルのリングバッファの終端位置.
+    pub ring_buffer_tail: u64,
+
+    /// データ領域内でのチェックポイントの開始位置(バイト単位).
+    pub offset: u64,
+
+    /// チェックポイントのサイズ(バイト単位).
+    pub size: u64,
+
+    /// チェックポイントの内容のチェックサム.
+    pub checksum: u32,
+}
+impl CheckpointLocation {
+    /// シリアライズ後のサイズ(バイト単位).
+    pub const SIZE: usize = 8 + 8 + 8 + 4;
+
+    pub(crate) fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_io!(writer.write_u64::<BigEndian>(self.ring_buffer_tail))?;
+        track_io!(writer.write_u64::<BigEndian>(self.offset))?;
+        track_io!(writer.write_u64::<BigEndian>(self.size))?;
+        track_io!(writer.write_u32::<BigEndian>(self.checksum))?;
+        Ok(())
+    }
+
+    pub(crate) fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let ring_buffer_tail = track_io!(reader.read_u64::<BigEndian>())?;
+        let offset = track_io!(reader.read_u64::<BigEndian>())?;
+        let size = track_io!(reader.read_u64::<BigEndian>())?;
+        let checksum = track_io!(reader.read_u32::<BigEndian>())?;
+        Ok(CheckpointLocation {
+            ring_buffer_tail,
+            offset,
+            size,
+            checksum,
+        })
+    }
+}
+
+/// チェックポイントから復元された内容.
+#[derive(Debug)]
+pub struct Checkpoint {
+    /// インデックス.
+    pub index: LumpIndex,
+
+    /// アロケータの空き領域リスト.
+    pub free_portions: Vec<FreePortion>,
+}
+impl Checkpoint {
+    /// インデックスと空き領域リストをチェックポイント用のバイト列にエンコードする.
+    pub fn encode<I>(index: &LumpIndex, free_portions: I) -> Vec<u8>
+    where
+        I: ExactSizeIterator<Item = FreePortion>,
+    {
+        let mut buf = Vec::with_capacity(
+            4 + 8 + index.len() as usize * (16 + 1 + 8 + 2) + 8 + free_portions.len() * (8 + 4),
+        );
+        buf.extend_from_slice(&CHECKPOINT_MAGIC_NUMBER);
+        buf.write_u64::<BigEndian>(index.len())
+            .expect("Never fails");
+        for (lump_id, portion) in index.entries() {
+            buf.write_u128::<BigEndian>(lump_id.as_u128())
+                .expect("Never fails");
+            let (kind, start, len) = match portion {
+                Portion::Journal(p) => (PORTION_KIND_JOURNAL, p.start, p.len),
+                Portion::Data(p) => (PORTION_KIND_DATA, p.start, p.len),
+            };
+            buf.write_u8(kind).expect("Never fails");
+            buf.write_u64::<BigEndian>(start.as_u64())
+                .expect("Never fails");
+            buf.write_u16::<BigEndian>(len).expect("Never fails");
+        }
+        buf.write_u64::<BigEndian>(free_portions.len() as u64)
+            .expect("Never fails");
+        for free in free_portions {
+            buf.write_u64::<BigEndian>(free.start().as_u64())
+                .expect("Never fails");
+            buf.write_u32::<BigEndian>(free.len()).expect("Never fails");
+        }
+        buf
+    }
+
+    /// チェックポイント用のバイト列をデコードする.
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        let mut magic_number = [0; 4];
+        track_io!(bytes.read_exact(&mut magic_number))?;
+        track_assert_eq!(
+            magic_number,
+            CHECKPOINT_MAGIC_NUMBER,
+            ErrorKind::StorageCorrupted
+        );
+
+        let mut index = LumpIndex::new();
+        let lumps = track_io!(bytes.read_u64::<BigEndian>())?;
+        for _ in 0..lumps {
+            let lump_id = LumpId::new(track_io!(bytes.read_u128::<BigEndian>())?);
+            let kind = track_io!(bytes.read_u8())?;
+            let start = track_io!(bytes.read_u64::<BigEndian>())?;
+            let start = track_assert_some!(Address::from_u64(start), ErrorKind::StorageCorrupted);
+            let len = track_io!(bytes.read_u16::<BigEndian>())?;
+            let portion = match kind {
+                PORTION_KIND_JOURNAL => Portion::Journal(JournalPortion { start, len }),
+                PORTION_KIND_DATA => Portion::Data(DataPortion { start, len }),
+                _ => track_panic!(
+                    ErrorKind::StorageCorrupted,
+                    "Unknown portion kind: {}",
+                    kind
+                ),
+            };
+            index.insert(lump_id, portion);
+        }
+
+        let count = track_io!(bytes.read_u64::<BigEndian>())?;
+        let mut free_portions = Vec::new();
+        for _ in 0..count {
+            let start = track_io!(bytes.read_u64::<BigEndian>())?;
+            let start = track_assert_some!(Address::from_u64(start), ErrorKind::StorageCorrupted);
+            let len = track_io!(bytes.read_u32::<BigEndian>())?;
+            track_assert!(len <= 0xFF_FFFF, ErrorKind::StorageCorrupted; len);
+            free_portions.push(FreePortion::new(start, len));
+        }
+        track_assert!(bytes.is_empty(), ErrorKind::StorageCorrupted);
+        Ok(Checkpoint {
+            index,
+            free_portions,
+        })
+    }
+
+    /// 復元された内容の整合性を検査する.
+    ///
+    /// インデックス内のデータ部分領域および空き領域の全てが、互いに重複しておらず、
+    /// かつ`capacity`(ブロック数)の範囲内に収まっているかが確認される.
+    ///
+    /// 不整合が見つかった場合には`ErrorKind::StorageCorrupted`エラーが返される.
+    pub fn validate(&self, capacity: u64) -> Result<()> {
+        let mut portions = self
+            .index
+            .data_portions()
+            .map(|(lump_id, p)| (p.start.as_u64(), p.end().as_u64(), Some(lump_id)))
+            .chain(
+                self.free_portions
+                    .iter()
+                    .map(|p| (p.start().as_u64(), p.end().as_u64(), None)),
+            )
+            .collect::<Vec<_>>();
+        portions.sort_unstable();
+
+        let mut prev: Option<(u64, u64, Option<LumpId>)> = None;
+        for (start, end, lump_id) in portions {
+            track_assert!(
+                end <= capacity,
+                ErrorKind::StorageCorrupted,
+                "Out of range portion: start={}, end={}, lump_id={:?}, capacity={}",
+                start,
+                end,
+                lump_id,
+                capacity
+            );
+            if let Some((prev_start, prev_end, prev_id)) = prev {
+                track_assert!(
+                    prev_end <= start,
+                    ErrorKind::StorageCorrupted,
+                    "Overlapping portions: ({}..{}, {:?}) and ({}..{}, {:?})",
+                    prev_start,
+                    prev_end,
+                    prev_id,
+                    start,
+                    end,
+                    lump_id
+                );
+            }
+            prev = Some((start, end, lump_id));
+        }
+        Ok(())
+    }
+
+    /// `nvm`の`location`で示される位置からチェックポイントを読み込む.
+    ///
+    /// チェックサムが一致しない場合には`ErrorKind::StorageCorrupted`エラーが返される.
+    pub fn read_from<N>(
+        nvm: &mut N,
+        block_size: BlockSize,
+        location: &CheckpointLocation,
+    ) -> Result<Self>
+    where
+        N: NonVolatileMemory,
+    {
+        track_assert!(
+            location.offset.saturating_add(location.size) <= nvm.capacity(),
+            ErrorKind::StorageCorrupted; location
+        );
+        let mut buf = AlignedBytes::new(location.size as usize, block_size);
+        buf.align();
+        track_io!(nvm.seek(SeekFrom::Start(location.offset)))?;
+        track_io!(nvm.read_exact(&mut buf))?;
+
+        let bytes = &buf[..location.size as usize];
+        track_assert_eq!(
+            checksum(bytes),
+            location.checksum,
+            ErrorKind::StorageCorrupted
+        );
+        track!(Self::decode(bytes))
+    }
+}
+
+/// チェックポイントの内容のチェックサムを計算する.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut adler32 = RollingAdler32::new();
+    adler32.update_buffer(bytes);
+    adler32.hash()
+}
+
+/// チェックポイントを書き込むための、ブロック境界に揃えたバッファを生成する.
+pub fn aligned_bytes(bytes: &[u8], block_size: BlockSize) -> AlignedBytes {
+    let mut buf = AlignedBytes::new(bytes.len(), block_size);
+    buf.align();
+    buf[..bytes.len()].copy_from_slice(bytes);
+    for b in &mut buf[bytes.len()..] {
+        *b = 0;
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_works() -> TestResult {
+        let mut index = LumpIndex::new();
+        index.insert(
+            LumpId::new(1),
+            Portion::Data(DataPortion {
+                start: Address::from(10),
+                len: 3,
+            }),
+        );
+        index.insert(
+            LumpId::new(2),
+            Portion::Journal(JournalPortion {
+                start: Address::from(20),
+                len: 5,
+            }),
+        );
+        let free_portions = vec![
+            FreePortion::new(Address::from(0), 10),
+            FreePortion::new(Address::from(13), 100),
+        ];
+
+        let bytes = Checkpoint::encode(&index, free_portions.iter().cloned());
+        let checkpoint = track!(Checkpoint::decode(&bytes))?;
+        assert_eq!(checkpoint.index.list(), index.list());
+        assert_eq!(
+            checkpoint.index.get(&LumpId::new(2)),
+            index.get(&LumpId::new(2))
+        );
+        assert_eq!(checkpoint.free_portions, free_portions);
+
+        // 末尾が欠けている
+        assert!(Checkpoint::decode(&bytes[..bytes.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_works() -> TestResult {
+        let data = |start: u32, len: u16| {
+            Portion::Data(DataPortion {
+                start: Address::from(start),
+                len,
+            })
+        };
+        let mut index = LumpIndex::new();
+        index.insert(LumpId::new(1), data(10, 3));
+        index.insert(LumpId::new(2), data(13, 2));
+        let checkpoint = Checkpoint {
+            index,
+            free_portions: vec![FreePortion::new(Address::from(0), 10)],
+        };
+        track!(checkpoint.validate(15))?;
+
+        // 範囲外
+        let e = checkpoint.validate(14).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::StorageCorrupted);
+
+        // lump同士の重複
+        let mut checkpoint = checkpoint;
+        checkpoint.index.insert(LumpId::new(3), data(14, 1));
+        let e = checkpoint.validate(15).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::StorageCorrupted);
+
+        // lumpと空き領域の重複
+        checkpoint.index.remove(&LumpId::new(3));
+        checkpoint
+            .free_portions
+            .push(FreePortion::new(Address::from(12), 1));
+        let e = checkpoint.validate(15).err().unwrap();
+        assert_eq!(*e.kind(), ErrorKind::StorageCorrupted);
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::block::BlockSize;
+use crate::storage::{JournalAdmissionAction, JournalChecksum};
+
+/// ストレージに実際に適用されている設定値.
+///
+/// `StorageBuilder`で指定された値やデフォルト値、
+/// ヘッダに記録された値(e.g., ジャーナル領域の割合から算出された各領域のサイズ)を解決した結果を保持する.
+///
+/// `Storage::config`によって取得される.
+/// `serde`フィーチャーが有効な場合には、シリアライズ可能となる.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StorageConfig {
+    /// ストレージのブロックサイズ.
+    pub block_size: BlockSize,
+
+    /// ストレージ全体のサイズ(バイト単位).
+    pub storage_size: u64,
+
+    /// ジャーナル領域のサイズ(バイト単位).
+    pub journal_region_size: u64,
+
+    /// データ領域のサイズ(バイト単位).
+    pub data_region_size: u64,
+
+    /// ジャーナルのレコードのチェックサムのアルゴリズム.
+    pub journal_checksum: JournalChecksum,
+
+    /// ジャーナル領域のヘッダを保持するスロットの数(`StorageBuilder::journal_header_slots`).
+    pub journal_header_slots: u8,
+
+    /// lumpデータを疎な形式で格納するかどうか(`StorageBuilder::sparse_lumps`).
+    pub sparse_lumps: bool,
+
+    /// ジャーナルバッファの同期間隔(`StorageBuilder::journal_sync_interval`).
+    ///
+    /// 同期間隔の自動調整が有効な場合には、その時点での値となる.
+    pub journal_sync_interval: usize,
+
+    /// 同期間隔の自動調整で目標とする書き込みのレイテンシ(`StorageBuilder::adaptive_journal_sync`).
+    pub journal_sync_target_latency: Option<Duration>,
+
+    /// 書き込みの受付制御の対処方法(`StorageBuilder::journal_admission_control`).
+    ///
+    /// 受付制御が無効な場合には`None`となる.
+    pub journal_admission_action: Option<JournalAdmissionAction>,
+
+    /// ジャーナル領域のGCキューの長さ(`StorageBuilder::journal_gc_queue_size`).
+    pub journal_gc_queue_size: usize,
+
+    /// 一回の補助タスクで実行されるジャーナル領域のGCの回数(`StorageBuilder::journal_gc_batch_size`).
+    pub journal_gc_batch_size: usize,
+
+    /// 追記毎のジャーナル領域のGCの回数の上限(`StorageBuilder::adaptive_journal_gc`).
+    ///
+    /// `None`の場合には、自動調整は行われない.
+    pub journal_adaptive_gc_max_steps: Option<usize>,
+
+    /// ジャーナルの書き込みバッファの上限(`StorageBuilder::journal_max_write_buffer_size`).
+    pub journal_max_write_buffer_size: Option<usize>,
+
+    /// 起動時のジャーナルの復元時の読み込みの単位(`StorageBuilder::journal_restore_buffer_size`).
+    pub journal_restore_buffer_size: usize,
+
+    /// ジャーナルバッファの安全なフラッシュが有効かどうか(`StorageBuilder::journal_safe_flush`).
+    pub journal_safe_flush: bool,
+
+    /// ジャーナル領域のGC対象エントリの読み込みが、専用のスレッドで行われているかどうか.
+    pub journal_background_gc_scan: bool,
+
+    /// 解放された部分領域の破棄が有効かどうか(`StorageBuilder::discard_released_portions`).
+    pub discard_released_portions: bool,
+
+    /// 解放された部分領域のゼロ埋めが有効かどうか(`StorageBuilder::scrub_released_portions`).
+    pub scrub_released_portions: bool,
+
+    /// ゼロ埋めの速度の上限(`StorageBuilder::scrub_rate_limit`).
+    pub scrub_rate_limit: Option<u64>,
+
+    /// ジャーナルに埋め込まれたデータの検証が有効かどうか(`StorageBuilder::verify_embedded_data`).
+    pub verify_embedded_data: bool,
+
+    /// データ領域への書き込みの検証が有効かどうか(`StorageBuilder::verify_data_writes`).
+    pub verify_data_writes: bool,
+
+    /// 監査ログの記録が有効かどうか(`StorageBuilder::audit_trail`).
+    pub audit_trail: bool,
+
+    /// GCによって回収せずに保持する監査レコードの数(`StorageBuilder::audit_retention`).
+    pub audit_retention: u64,
+
+    /// 重複排除が有効かどうか(`StorageBuilder::deduplication`).
+    pub deduplication: bool,
+
+    /// 同一内容での上書きの省略が有効かどうか(`StorageBuilder::skip_identical_overwrites`).
+    pub skip_identical_overwrites: bool,
+
+    /// データ部分領域への上書き更新が有効かどうか(`StorageBuilder::overwrite_in_place`).
+    pub overwrite_in_place: bool,
+
+    /// オープン時のマイナーバージョンの自動更新が有効かどうか(`StorageBuilder::upgrade_minor_version`).
+    pub upgrade_minor_version: bool,
+
+    /// データ領域の空き領域を断片として隔離する閾値(`StorageBuilder::free_fragment_threshold`).
+    pub free_fragment_threshold: u16,
+
+    /// データ領域の空き領域のリストの長さの上限(`StorageBuilder::max_free_portions`).
+    pub max_free_portions: usize,
+
+    /// アライメントの対象となるlumpのサイズの閾値とアライメント境界(`StorageBuilder::large_lump_alignment`).
+    pub large_lump_alignment: Option<(u32, u32)>,
+
+    /// データ領域の先読みのサイズ(`StorageBuilder::read_ahead_size`).
+    pub read_ahead_size: usize,
+
+    /// データ領域への一度の書き込みの最大サイズ(`StorageBuilder::write_slice_size`).
+    pub write_slice_size: usize,
+
+    /// ジャーナル領域への移し替えの対象となるlumpのサイズの上限(`StorageBuilder::reembed_threshold`).
+    pub reembed_threshold: u16,
+
+    /// 移し替えの際に一度に走査するlumpの最大数(`StorageBuilder::reembed_batch_size`).
+    pub reembed_batch_size: usize,
+
+    /// データ領域のアクセス分布の区間数(`StorageBuilder::access_heatmap`).
+    pub access_heatmap_buckets: usize,
+
+    /// インデックスのシャドウファイルのパス(`StorageBuilder::index_shadow_file`).
+    pub index_shadow_file: Option<PathBuf>,
+
+    /// インデックスのシャドウファイルを更新する最短の間隔(`StorageBuilder::index_shadow_interval`).
+    pub index_shadow_interval: Duration,
+}
@@ -1,16 +1,44 @@
 use byteorder::{BigEndian, ByteOrder};
 use prometrics::metrics::MetricBuilder;
-use std::io::{Read, SeekFrom, Write};
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{Read, SeekFrom};
+use std::mem;
+use std::time::Instant;
 
 use crate::block::{AlignedBytes, BlockSize};
-use crate::metrics::DataRegionMetrics;
+use crate::metrics::{AccessHeatmap, DataRegionMetrics, IoOrigin};
 use crate::nvm::NonVolatileMemory;
-use crate::storage::allocator::DataPortionAllocator;
+use crate::storage::allocator::{DataPortionAllocator, FreePortion};
+use crate::storage::checkpoint::{self, Checkpoint};
+use crate::storage::index::LumpIndex;
 use crate::storage::portion::DataPortion;
+use crate::storage::DataRegionStats;
 use crate::{ErrorKind, Result};
 
 /// 各データの末尾に埋め込まれる情報のサイズ.
-const LUMP_DATA_TRAILER_SIZE: usize = 2;
+pub(crate) const LUMP_DATA_TRAILER_SIZE: usize = 2;
+
+/// 疎な形式で格納されたデータであることを示すために、トレイラに格納される値.
+///
+/// 通常の形式のトレイラにはパディングのサイズ(ブロックサイズ未満)が格納されるため、この値と衝突することはない.
+pub(crate) const SPARSE_LUMP_DATA_MARKER: u16 = 0xFFFF;
+
+/// 疎な形式のデータの末尾に埋め込まれる情報のサイズ.
+///
+/// 内訳は、データサイズ(4バイト)とエクステント数(4バイト)、`SPARSE_LUMP_DATA_MARKER`(2バイト).
+pub(crate) const SPARSE_LUMP_DATA_TRAILER_SIZE: usize = 4 + 4 + LUMP_DATA_TRAILER_SIZE;
+
+/// 疎な形式のデータのエクステント(i.e., 格納されている非ゼロブロックの範囲)一つ当たりのサイズ.
+///
+/// 内訳は、開始ブロック番号(4バイト)とブロック数(4バイト).
+pub(crate) const SPARSE_EXTENT_SIZE: usize = 4 + 4;
+
+/// スクラブや上書きの際に、一度に書き込むブロック数の上限.
+const MAX_SCRUB_BLOCKS_PER_WRITE: u64 = 256;
+
+/// `get_many`で一度の読み込みにまとめる範囲の最大サイズ(バイト単位).
+const MAX_COALESCED_READ_SIZE: u64 = 1024 * 1024;
 
 /// ランプのデータを格納するための領域.
 #[derive(Debug)]
@@ -19,6 +47,21 @@ pub struct DataRegion<N> {
     nvm: N,
     block_size: BlockSize,
     metrics: DataRegionMetrics,
+    discard: bool,
+    pending_discards: Vec<DataPortion>,
+    scrub: bool,
+    scrub_rate_limit: Option<u64>,
+    scrub_budget: f64,
+    scrub_refilled_at: Instant,
+    pending_scrubs: VecDeque<DataPortion>,
+    scrubbed_blocks: u16,
+    padding_fill_byte: Option<u8>,
+    verify_writes: bool,
+    sparse: bool,
+    write_slice_size: usize,
+    read_ahead_size: u64,
+    last_read_end: Option<u64>,
+    read_ahead_buffer: Option<ReadAheadBuffer>,
 }
 impl<N> DataRegion<N>
 where
@@ -34,14 +77,196 @@ where
             nvm,
             block_size,
             metrics: DataRegionMetrics::new(metric_builder, capacity, allocator_metrics),
+            discard: false,
+            pending_discards: Vec::new(),
+            scrub: false,
+            scrub_rate_limit: None,
+            scrub_budget: 0.0,
+            scrub_refilled_at: Instant::now(),
+            pending_scrubs: VecDeque::new(),
+            scrubbed_blocks: 0,
+            padding_fill_byte: None,
+            verify_writes: false,
+            sparse: false,
+            write_slice_size: 0,
+            read_ahead_size: 0,
+            last_read_end: None,
+            read_ahead_buffer: None,
         }
     }
 
+    /// 解放された部分領域に対して、NVMへの破棄通知(i.e., `NonVolatileMemory::discard`)を行うかどうかを設定する.
+    ///
+    /// 有効な場合には、解放された部分領域は、`discard_pending_portions`が呼び出されるまで
+    /// アロケータへの返却が保留される.
+    pub fn set_discard_mode(&mut self, enable: bool) {
+        self.discard = enable;
+    }
+
+    /// 解放された部分領域を、アロケータに返却する前にゼロ埋め(スクラブ)するかどうかを設定する.
+    ///
+    /// 有効な場合には、解放された部分領域は、`discard_pending_portions`の呼び出し後にスクラブ待ちキューに移され、
+    /// `scrub_pending_portions`ないし`scrub_all_pending_portions`によってゼロ埋めされた後に、
+    /// (必要に応じて破棄通知が行われた上で)アロケータに返却される.
+    pub fn set_scrub_mode(&mut self, enable: bool) {
+        self.scrub = enable;
+    }
+
+    /// `scrub_pending_portions`によるスクラブのスループットの上限(バイト/秒)を設定する.
+    ///
+    /// `None`の場合には、上限は設けられない.
+    pub fn set_scrub_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.scrub_rate_limit = bytes_per_sec;
+        self.scrub_budget = 0.0;
+        self.scrub_refilled_at = Instant::now();
+    }
+
+    /// lumpデータの末尾のパディング部分を埋めるバイトを設定する.
+    ///
+    /// `None`の場合には、パディング部分の内容は不定となる.
+    pub fn set_padding_fill_byte(&mut self, fill: Option<u8>) {
+        self.padding_fill_byte = fill;
+    }
+
+    /// ゼロブロックを省いた疎な形式で、データを格納するかどうかを設定する.
+    ///
+    /// 有効な場合には、格納時にデータ内の全てがゼロのブロックが検出され、
+    /// それによって使用ブロック数を削減できる場合には、非ゼロのブロック群とその位置情報のみが書き込まれる.
+    ///
+    /// 読み込み時には、この設定に関わらず、疎な形式のデータも復元される.
+    pub fn set_sparse_mode(&mut self, enable: bool) {
+        self.sparse = enable;
+    }
+
+    /// データの格納直後に、書き込んだブロック群を読み戻して内容を検証するかどうかを設定する.
+    pub fn set_write_verification(&mut self, enable: bool) {
+        self.verify_writes = enable;
+    }
+
+    /// 空き領域を断片として隔離するための閾値(ブロック数)を設定する.
+    ///
+    /// 詳細は`DataPortionAllocator::set_fragment_threshold`を参照のこと.
+    pub fn set_fragment_threshold(&mut self, threshold: u16) {
+        self.allocator.set_fragment_threshold(threshold);
+    }
+
+    /// 空き領域のリストの長さの上限を設定する.
+    ///
+    /// 詳細は`DataPortionAllocator::set_max_free_portions`を参照のこと.
+    pub fn set_max_free_portions(&mut self, max: usize) {
+        self.allocator.set_max_free_portions(max);
+    }
+
+    /// 断片として隔離されている空き領域を、割当に使用可能な状態に戻す.
+    ///
+    /// 戻された空き領域の数が返される.
+    pub fn reclaim_free_fragments(&mut self) -> usize {
+        self.allocator.reclaim_fragments()
+    }
+
+    /// サイズが`threshold`バイト以上のlumpデータを、`alignment`バイトの境界に揃えて割り当てるように設定する.
+    ///
+    /// 境界はデータ領域の先頭からの相対位置で計算される.
+    /// `alignment`がブロックサイズ以下の場合には、アライメントは行われない.
+    /// 詳細は`DataPortionAllocator::set_alignment`を参照のこと.
+    ///
+    /// # エラー
+    ///
+    /// `alignment`がブロックサイズの倍数ではない場合には`ErrorKind::InvalidInput`エラーが返される.
+    pub fn set_large_lump_alignment(&mut self, threshold: u32, alignment: u32) -> Result<()> {
+        let block_size = u32::from(self.block_size.as_u16());
+        track_assert_eq!(alignment % block_size, 0, ErrorKind::InvalidInput; block_size);
+
+        let threshold = self.required_blocks(threshold as usize);
+        if threshold > u32::from(u16::MAX) {
+            // 対象となるlumpが存在しない
+            self.allocator.set_alignment(0, 0);
+        } else {
+            self.allocator
+                .set_alignment(threshold as u16, alignment / block_size);
+        }
+        Ok(())
+    }
+
+    /// データ領域へのアクセスを、`buckets`個のバケット毎に数えるかどうかを設定する.
+    ///
+    /// `0`の場合には数えない.
+    /// 結果は`DataRegionMetrics::access_heatmap`で取得できる.
+    pub fn set_access_heatmap(&mut self, buckets: usize) {
+        self.metrics.access_heatmap = if buckets == 0 {
+            None
+        } else {
+            Some(AccessHeatmap::new(self.metrics.capacity_bytes(), buckets))
+        };
+    }
+
+    /// 物理的に連続する部分領域に対する読み込みが続いた場合に、先読みを行うサイズ(バイト)を設定する.
+    ///
+    /// サイズはブロック境界に切り捨てられ、`0`の場合には先読みは行われない.
+    pub fn set_read_ahead_size(&mut self, size: usize) {
+        self.read_ahead_size = self.block_size.floor_align(size as u64);
+        self.read_ahead_buffer = None;
+    }
+
+    /// lumpデータを書き込む際の、一度の書き込みの最大サイズ(バイト)を設定する.
+    ///
+    /// サイズはブロック境界に切り捨てられる(ただし、最低でも1ブロック分となる).
+    /// `0`の場合には、各lumpデータは一度に書き込まれる.
+    pub fn set_write_slice_size(&mut self, size: usize) {
+        self.write_slice_size = if size == 0 {
+            0
+        } else {
+            let block_size = u64::from(self.block_size.as_u16());
+            cmp::max(block_size, self.block_size.floor_align(size as u64)) as usize
+        };
+    }
+
     /// データ領域のメトリクスを返す.
     pub fn metrics(&self) -> &DataRegionMetrics {
         &self.metrics
     }
 
+    /// データ領域の統計情報を返す.
+    pub fn stats(&self) -> DataRegionStats {
+        let block_size = u64::from(self.block_size.as_u16());
+        DataRegionStats {
+            capacity_bytes: self.metrics.capacity_bytes(),
+            usage_bytes: self.metrics.usage_bytes(),
+            free_portions: self.allocator.free_portions().len() as u64,
+            largest_free_portion_bytes: self
+                .allocator
+                .largest_free_portion()
+                .map_or(0, |p| u64::from(p.len()) * block_size),
+        }
+    }
+
+    /// データ領域が使用しているNVMで、Direct I/Oが通常のI/Oに切り替えられているかどうかを返す.
+    pub fn is_direct_io_degraded(&self) -> bool {
+        self.nvm.is_direct_io_degraded()
+    }
+
+    /// データ領域が使用しているNVMを識別するための、メトリクスのラベルを返す.
+    pub fn nvm_metric_labels(&self) -> Vec<(&'static str, String)> {
+        self.nvm.metric_labels()
+    }
+
+    /// データ領域のブロックサイズを返す.
+    pub fn block_size(&self) -> BlockSize {
+        self.block_size
+    }
+
+    /// 指定された部分領域が、データ領域の範囲内に収まっているかどうかを判定する.
+    pub fn contains(&self, portion: &DataPortion) -> bool {
+        let capacity =
+            self.allocator.metrics().capacity_bytes / u64::from(self.block_size.as_u16());
+        portion.end().as_u64() <= capacity
+    }
+
+    /// 指定された部分領域が、アロケータによって割当済みかどうかを判定する.
+    pub fn is_allocated(&self, portion: &DataPortion) -> bool {
+        self.allocator.is_allocated_portion(portion)
+    }
+
     /// データを格納する.
     ///
     /// 格納場所は`DataRegion`が決定する.
@@ -49,17 +274,92 @@ where
     ///
     /// 成功した場合には、格納場所が返される.
     pub fn put(&mut self, data: &DataRegionLumpData) -> Result<DataPortion> {
+        let portion = track!(self.write_lump_data(data, None))?;
+        Ok(portion.expect("Never fails"))
+    }
+
+    /// 既に割当済みの部分領域`portion`に、`data`を上書きする.
+    ///
+    /// `data`を格納するのに必要なブロック数が`portion`のそれと異なる場合には、何も行わずに`false`が返される.
+    ///
+    /// 新しい部分領域の割当と古い部分領域の解放を伴わない代わりに、
+    /// 書き込みの途中でクラッシュした場合には、部分領域の内容が新旧のデータの混在した状態となり得る.
+    pub fn overwrite_lump(
+        &mut self,
+        portion: DataPortion,
+        data: &DataRegionLumpData,
+    ) -> Result<bool> {
+        let written = track!(self.write_lump_data(data, Some(portion)))?;
+        Ok(written.is_some())
+    }
+
+    /// `data`をデータ領域に書き込む.
+    ///
+    /// `target`が`None`の場合には、書き込み先の部分領域が新たに割り当てられる.
+    fn write_lump_data(
+        &mut self,
+        data: &DataRegionLumpData,
+        target: Option<DataPortion>,
+    ) -> Result<Option<DataPortion>> {
         track_assert!(
             data.block_size().contains(self.block_size),
-            ErrorKind::InvalidInput
+            ErrorKind::BlockSizeMismatch,
+            "The data is not aligned to the storage block size: data_block_size={}, storage_block_size={}",
+            data.block_size().as_u16(),
+            self.block_size.as_u16()
         );
-        let block_size = self.block_count(data.as_external_bytes().len() as u32) as u16;
-        let portion =
-            track_assert_some!(self.allocator.allocate(block_size), ErrorKind::StorageFull);
+        let sparse = if self.sparse {
+            data.to_sparse_bytes(self.block_size, self.padding_fill_byte)
+        } else {
+            None
+        };
+
+        let filled;
+        let data = match self.padding_fill_byte {
+            Some(fill) if sparse.is_none() && data.padding().iter().any(|&b| b != fill) => {
+                let mut copied = data.clone();
+                copied.fill_padding(fill);
+                filled = copied;
+                &filled
+            }
+            _ => data,
+        };
+        let bytes = sparse
+            .as_ref()
+            .map_or_else(|| data.as_external_bytes(), |b| b.as_ref());
+
+        let block_size = self.block_count(bytes.len() as u32) as u16;
+        let portion = match target {
+            None => track_assert_some!(self.allocator.allocate(block_size), ErrorKind::StorageFull),
+            Some(portion) if portion.len == block_size => portion,
+            Some(_) => return Ok(None),
+        };
 
         let (offset, _size) = self.real_portion(&portion);
+        let written = u64::from(block_size) * u64::from(self.block_size.as_u16());
+        self.invalidate_read_ahead(offset, written);
         track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
-        track!(data.write_to(&mut self.nvm))?;
+        if self.write_slice_size == 0 || bytes.len() <= self.write_slice_size {
+            track_io!(self.nvm.write_all(bytes))?;
+        } else {
+            for slice in bytes.chunks(self.write_slice_size) {
+                track_io!(self.nvm.write_all(slice))?;
+                self.metrics.written_slices.increment();
+            }
+        }
+        self.metrics.written_bytes.add_u64(written);
+        if sparse.is_some() {
+            self.metrics.sparse_lumps.increment();
+            self.metrics
+                .sparse_hole_bytes
+                .add_u64((data.as_external_bytes().len() as u64).saturating_sub(written));
+        }
+        self.metrics
+            .nvm_written_bytes
+            .add(IoOrigin::Foreground, written);
+        if let Some(ref heatmap) = self.metrics.access_heatmap {
+            heatmap.record(offset, written, true);
+        }
 
         // NOTE:
         // この後にジャーナルへの書き込みが行われ、
@@ -67,21 +367,235 @@ where
         // この時点では`flush`のみに留める.
         track_io!(self.nvm.flush())?;
 
-        Ok(portion)
+        if self.verify_writes {
+            if let Err(e) = track!(self.verify_written_data(portion, bytes)) {
+                self.metrics.write_verification_failures.increment();
+                if target.is_none() {
+                    self.allocator.release(portion);
+                }
+                return Err(e);
+            }
+        }
+        Ok(Some(portion))
+    }
+
+    /// `data`を複数回に分けて書き込むための部分領域を割り当てる.
+    ///
+    /// 書き込み自体は`write_next_slice`によって行われる.
+    ///
+    /// 疎な形式での格納が有効な場合や、パディング部分の埋め直しが必要な場合等、
+    /// `data`をそのままの形で書き込むことができない場合には`None`が返される.
+    /// その場合には`put`を使用すること.
+    pub fn start_sliced_write(&mut self, data: &DataRegionLumpData) -> Result<Option<SlicedWrite>> {
+        track_assert!(
+            data.block_size().contains(self.block_size),
+            ErrorKind::BlockSizeMismatch,
+            "The data is not aligned to the storage block size: data_block_size={}, storage_block_size={}",
+            data.block_size().as_u16(),
+            self.block_size.as_u16()
+        );
+        let refill = self
+            .padding_fill_byte
+            .is_some_and(|fill| data.padding().iter().any(|&b| b != fill));
+        if self.sparse || refill {
+            return Ok(None);
+        }
+
+        let size = data.as_external_bytes().len();
+        let block_size = self.block_count(size as u32) as u16;
+        let portion =
+            track_assert_some!(self.allocator.allocate(block_size), ErrorKind::StorageFull);
+        Ok(Some(SlicedWrite {
+            portion,
+            size,
+            written: 0,
+        }))
+    }
+
+    /// `write`の未書き込み部分の先頭から、最大で`set_write_slice_size`で指定されたサイズ分だけ`data`を書き込む.
+    ///
+    /// `data`には`start_sliced_write`に渡したものと同じデータを指定する必要がある.
+    ///
+    /// 全体の書き込みが完了した場合には`true`が返される.
+    /// 書き込み先の部分領域は、エラーの場合も含めて、呼び出し元が管理する必要がある.
+    pub fn write_next_slice(
+        &mut self,
+        write: &mut SlicedWrite,
+        data: &DataRegionLumpData,
+    ) -> Result<bool> {
+        let bytes = data.as_external_bytes();
+        track_assert_eq!(bytes.len(), write.size, ErrorKind::InvalidInput);
+
+        let start = write.written;
+        let end = if self.write_slice_size == 0 {
+            bytes.len()
+        } else {
+            cmp::min(bytes.len(), start + self.write_slice_size)
+        };
+        let (offset, size) = self.real_portion(&write.portion);
+        let offset = offset + start as u64;
+        let len = (end - start) as u64;
+        self.invalidate_read_ahead(offset, len);
+        track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
+        track_io!(self.nvm.write_all(&bytes[start..end]))?;
+        write.written = end;
+        self.metrics.written_slices.increment();
+        self.metrics
+            .nvm_written_bytes
+            .add(IoOrigin::Foreground, len);
+        if let Some(ref heatmap) = self.metrics.access_heatmap {
+            heatmap.record(offset, len, true);
+        }
+        if !write.is_completed() {
+            return Ok(false);
+        }
+
+        self.metrics.written_bytes.add_u64(size as u64);
+        track_io!(self.nvm.flush())?;
+        if self.verify_writes {
+            if let Err(e) = track!(self.verify_written_data(write.portion, bytes)) {
+                self.metrics.write_verification_failures.increment();
+                return Err(e);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 書き込み直後の部分領域を読み戻して、その内容が`expected`と一致するかどうかを検証する.
+    fn verify_written_data(&mut self, portion: DataPortion, expected: &[u8]) -> Result<()> {
+        let (offset, size) = self.real_portion(&portion);
+        track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
+        let mut buf = AlignedBytes::new(size, self.block_size);
+        track_io!(self.nvm.read_exact(&mut buf))?;
+        self.metrics
+            .nvm_read_bytes
+            .add(IoOrigin::Foreground, size as u64);
+        track_assert!(
+            buf.as_ref() == expected,
+            ErrorKind::StorageCorrupted,
+            "Read-back verification failed: portion={:?}",
+            portion
+        );
+        Ok(())
     }
 
     /// 指定された領域に格納されているデータを取得する.
     ///
     /// `portion`で指定された領域が有効かどうかの判定は、このメソッド内では行われない.
+    ///
+    /// 先読みが有効な場合には、直前の読み込み範囲の直後に位置する部分領域が指定されると、
+    /// その後続部分の先読みも併せて行われる.
     pub fn get(&mut self, portion: DataPortion) -> Result<DataRegionLumpData> {
         let (offset, size) = self.real_portion(&portion);
-        track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
-
+        let sequential = self.last_read_end == Some(offset);
         let buf = AlignedBytes::new(size, self.block_size);
-        let data = track!(DataRegionLumpData::read_from(&mut self.nvm, buf))?;
+
+        let data = if let Some(bytes) = self
+            .read_ahead_buffer
+            .as_ref()
+            .and_then(|b| b.get(offset, size))
+        {
+            self.metrics.read_ahead_hits.increment();
+            track!(DataRegionLumpData::read_from(bytes, buf))?
+        } else {
+            let read_ahead_size = if sequential && self.read_ahead_size != 0 {
+                self.metrics.read_ahead_misses.increment();
+                let remaining = self.nvm.capacity().saturating_sub(offset + size as u64);
+                cmp::min(self.read_ahead_size, remaining) as usize
+            } else {
+                0
+            };
+
+            track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
+            let mut bytes = AlignedBytes::new(size + read_ahead_size, self.block_size);
+            track_io!(self.nvm.read_exact(&mut bytes))?;
+            self.metrics
+                .nvm_read_bytes
+                .add(IoOrigin::Foreground, bytes.len() as u64);
+            if read_ahead_size != 0 {
+                self.metrics
+                    .read_ahead_bytes
+                    .add_u64(read_ahead_size as u64);
+                self.read_ahead_buffer = Some(ReadAheadBuffer {
+                    offset: offset + size as u64,
+                    bytes: bytes[size..].to_vec(),
+                });
+            }
+            track!(DataRegionLumpData::read_from(&bytes[..size], buf))?
+        };
+        self.last_read_end = Some(offset + size as u64);
+        if let Some(ref heatmap) = self.metrics.access_heatmap {
+            heatmap.record(offset, size as u64, false);
+        }
         Ok(data)
     }
 
+    /// 指定された複数の領域に格納されているデータを、まとめて取得する.
+    ///
+    /// 領域群は位置順に並び替えられ、隣接ないし重複しているものは一度の読み込みにまとめられる
+    /// (ただし一度に読み込む範囲は`MAX_COALESCED_READ_SIZE`以下に制限される).
+    /// 結果は`portions`と同じ順番で返される.
+    ///
+    /// `get`とは異なり、先読みバッファは使用も更新もされない.
+    pub fn get_many(&mut self, portions: &[DataPortion]) -> Result<Vec<DataRegionLumpData>> {
+        let mut order = (0..portions.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| portions[i].start);
+
+        let mut results = vec![None; portions.len()];
+        let mut i = 0;
+        while i < order.len() {
+            let (start, size) = self.real_portion(&portions[order[i]]);
+            let mut end = start + size as u64;
+            let mut j = i + 1;
+            while j < order.len() {
+                let (offset, size) = self.real_portion(&portions[order[j]]);
+                let next_end = cmp::max(end, offset + size as u64);
+                if offset > end || next_end - start > MAX_COALESCED_READ_SIZE {
+                    break;
+                }
+                end = next_end;
+                j += 1;
+            }
+
+            track_io!(self.nvm.seek(SeekFrom::Start(start)))?;
+            let mut bytes = AlignedBytes::new((end - start) as usize, self.block_size);
+            track_io!(self.nvm.read_exact(&mut bytes))?;
+            self.metrics
+                .nvm_read_bytes
+                .add(IoOrigin::Foreground, bytes.len() as u64);
+            if let Some(ref heatmap) = self.metrics.access_heatmap {
+                heatmap.record(start, end - start, false);
+            }
+
+            for &k in &order[i..j] {
+                let (offset, size) = self.real_portion(&portions[k]);
+                let relative = (offset - start) as usize;
+                let buf = AlignedBytes::new(size, self.block_size);
+                let data = track!(DataRegionLumpData::read_from(
+                    &bytes[relative..relative + size],
+                    buf
+                ))?;
+                results[k] = Some(data);
+            }
+            i = j;
+        }
+        Ok(results
+            .into_iter()
+            .map(|data| data.expect("Never fails"))
+            .collect())
+    }
+
+    /// `offset`から`len`バイト分の範囲と重なる先読み結果を破棄する.
+    fn invalidate_read_ahead(&mut self, offset: u64, len: u64) {
+        if self
+            .read_ahead_buffer
+            .as_ref()
+            .is_some_and(|b| b.overlaps(offset, len))
+        {
+            self.read_ahead_buffer = None;
+        }
+    }
+
     /// 指定された領域に格納されているデータを削除する.
     ///
     /// # パニック
@@ -89,7 +603,259 @@ where
     /// `portion`で未割当の領域が指定された場合には、
     /// 現在の実行スレッドがパニックする.
     pub fn delete(&mut self, portion: DataPortion) {
+        if self.discard || self.scrub {
+            // 削除を記録したジャーナルが永続化される前に破棄通知を行ってしまうと、
+            // クラッシュ時に(ジャーナル上は存在する)lumpのデータが失われてしまう.
+            // そのため、ここではアロケータへの返却を保留するに留める.
+            assert!(
+                self.allocator.is_allocated_portion(&portion),
+                "{:?}",
+                portion
+            );
+            self.pending_discards.push(portion);
+        } else {
+            self.allocator.release(portion);
+        }
+    }
+
+    /// 破棄通知およびアロケータへの返却が保留されている部分領域が存在するかどうかを判定する.
+    pub fn has_pending_discards(&self) -> bool {
+        !self.pending_discards.is_empty()
+    }
+
+    /// 保留されている部分領域群に対してNVMへの破棄通知を行い、それらをアロケータに返却する.
+    ///
+    /// 呼び出し側は、事前に対応する削除操作がジャーナルに永続化されていることを保証する必要がある.
+    ///
+    /// 破棄通知に失敗した場合でも、全ての部分領域はアロケータに返却される.
+    ///
+    /// なおスクラブが有効な場合には、部分領域群は破棄通知およびアロケータへの返却は行われずに、
+    /// スクラブ待ちキューに移される.
+    pub fn discard_pending_portions(&mut self) -> Result<()> {
+        if self.scrub {
+            self.pending_scrubs.extend(self.pending_discards.drain(..));
+            self.update_pending_scrub_bytes();
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        for portion in mem::take(&mut self.pending_discards) {
+            result = self.release_portion(portion, result);
+        }
+        result
+    }
+
+    /// スクラブ待ちの部分領域が存在するかどうかを判定する.
+    pub fn has_pending_scrubs(&self) -> bool {
+        !self.pending_scrubs.is_empty()
+    }
+
+    /// スクラブ待ちの部分領域群の合計サイズ(バイト単位)を返す.
+    pub fn pending_scrub_bytes(&self) -> u64 {
+        let blocks = self
+            .pending_scrubs
+            .iter()
+            .map(|p| u64::from(p.len))
+            .sum::<u64>()
+            - u64::from(self.scrubbed_blocks);
+        blocks * u64::from(self.block_size.as_u16())
+    }
+
+    /// アロケータへの返却が保留されている部分領域群(スクラブ待ちのものを含む)の合計サイズ(バイト単位)を返す.
+    pub fn unreleased_bytes(&self) -> u64 {
+        self.pending_discards
+            .iter()
+            .chain(self.pending_scrubs.iter())
+            .map(|p| self.real_portion(p).1 as u64)
+            .sum()
+    }
+
+    /// スループットの上限の範囲内で、スクラブ待ちの部分領域群のゼロ埋めを進める.
+    ///
+    /// ゼロ埋めが完了した部分領域は、(破棄通知が有効ならそれを行った上で)アロケータに返却される.
+    ///
+    /// 結果として、今回の呼び出しでゼロ埋めしたバイト数が返される.
+    pub fn scrub_pending_portions(&mut self) -> Result<u64> {
+        if self.pending_scrubs.is_empty() {
+            return Ok(0);
+        }
+
+        // 一回の呼び出しが長時間に及ばないように、書き込み量は一度の書き込み分までに抑える
+        let max_bytes_per_call = MAX_SCRUB_BLOCKS_PER_WRITE * u64::from(self.block_size.as_u16());
+        let max_bytes = if let Some(rate) = self.scrub_rate_limit {
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.scrub_refilled_at).as_secs_f64();
+            self.scrub_refilled_at = now;
+
+            // 一秒分(ただし最低でも一ブロック分)を超えるバジェットは蓄積しない
+            let max_budget = cmp::max(rate, u64::from(self.block_size.as_u16())) as f64;
+            self.scrub_budget = (self.scrub_budget + elapsed * rate as f64).min(max_budget);
+            cmp::min(self.scrub_budget as u64, max_bytes_per_call)
+        } else {
+            max_bytes_per_call
+        };
+
+        let scrubbed = track!(self.scrub_portions(max_bytes))?;
+        if self.scrub_rate_limit.is_some() {
+            self.scrub_budget -= scrubbed as f64;
+        }
+        Ok(scrubbed)
+    }
+
+    /// スループットの上限を無視して、スクラブ待ちの全ての部分領域群のゼロ埋めを行う.
+    pub fn scrub_all_pending_portions(&mut self) -> Result<()> {
+        track!(self.scrub_portions(u64::MAX))?;
+        Ok(())
+    }
+
+    fn scrub_portions(&mut self, max_bytes: u64) -> Result<u64> {
+        let block_size = u64::from(self.block_size.as_u16());
+        let mut scrubbed = 0;
+        let mut completed = Vec::new();
+        while let Some(&portion) = self.pending_scrubs.front() {
+            let remaining_blocks = u64::from(portion.len - self.scrubbed_blocks);
+            let max_blocks = cmp::min(
+                (max_bytes - scrubbed) / block_size,
+                MAX_SCRUB_BLOCKS_PER_WRITE,
+            );
+            let blocks = cmp::min(remaining_blocks, max_blocks);
+            if blocks == 0 {
+                break;
+            }
+
+            let start = portion.start.as_u64() + u64::from(self.scrubbed_blocks);
+            track!(self.fill_blocks(start, blocks, 0, IoOrigin::Scrub))?;
+
+            scrubbed += blocks * block_size;
+            self.scrubbed_blocks += blocks as u16;
+            if self.scrubbed_blocks == portion.len {
+                self.pending_scrubs.pop_front();
+                self.scrubbed_blocks = 0;
+                completed.push(portion);
+            }
+        }
+        if scrubbed == 0 {
+            return Ok(0);
+        }
+
+        // ゼロ埋めが永続化される前に再利用されることがないように、返却前に同期を行う
+        track!(self.nvm.sync())?;
+        self.metrics.scrubbed_bytes.add_u64(scrubbed);
+        self.update_pending_scrub_bytes();
+
+        let mut result = Ok(());
+        for portion in completed {
+            result = self.release_portion(portion, result);
+        }
+        track!(result)?;
+        Ok(scrubbed)
+    }
+
+    /// 指定された部分領域の内容を`fill`で上書きし、それを永続化する.
+    ///
+    /// 部分領域のアロケータへの返却は行われないので、別途`delete`を呼び出す必要がある.
+    pub fn overwrite(&mut self, portion: DataPortion, fill: u8) -> Result<()> {
+        let mut start = portion.start.as_u64();
+        let end = portion.end().as_u64();
+        while start < end {
+            let blocks = cmp::min(end - start, MAX_SCRUB_BLOCKS_PER_WRITE);
+            track!(self.fill_blocks(start, blocks, fill, IoOrigin::Foreground))?;
+            start += blocks;
+        }
+        track!(self.nvm.sync())?;
+        Ok(())
+    }
+
+    /// `start`ブロック目から`blocks`ブロック分の領域を`fill`で埋める.
+    ///
+    /// 書き込んだバイト数は`origin`によるものとして計上される.
+    fn fill_blocks(&mut self, start: u64, blocks: u64, fill: u8, origin: IoOrigin) -> Result<()> {
+        let block_size = u64::from(self.block_size.as_u16());
+        let mut buf = AlignedBytes::new((blocks * block_size) as usize, self.block_size);
+        for b in buf.as_mut() {
+            *b = fill;
+        }
+        self.invalidate_read_ahead(start * block_size, buf.len() as u64);
+        track_io!(self.nvm.seek(SeekFrom::Start(start * block_size)))?;
+        track_io!(self.nvm.write_all(&buf))?;
+        self.metrics.nvm_written_bytes.add(origin, buf.len() as u64);
+        Ok(())
+    }
+
+    /// 必要に応じて破棄通知を行った上で、部分領域をアロケータに返却する.
+    ///
+    /// 破棄通知は`result`が成功の場合にのみ行われ、その結果が返される.
+    fn release_portion(&mut self, portion: DataPortion, result: Result<()>) -> Result<()> {
+        let result = if result.is_ok() && self.discard {
+            let (offset, size) = self.real_portion(&portion);
+            track!(self.nvm.discard(offset, size as u64))
+        } else {
+            result
+        };
         self.allocator.release(portion);
+        result
+    }
+
+    fn update_pending_scrub_bytes(&self) {
+        self.metrics
+            .pending_scrub_bytes
+            .set(self.pending_scrub_bytes() as f64);
+    }
+
+    /// インデックスと空き領域リストの内容を、チェックポイントとしてデータ領域内の最大の空き領域に書き込む.
+    ///
+    /// 成功した場合には、書き込み位置とサイズ(共にバイト単位)、および内容のチェックサムが返される.
+    /// 十分な大きさの空き領域が存在しない場合には`None`が返される.
+    ///
+    /// 書き込みは空き領域に対して行われるため、以後のデータの書き込みによって上書きされる可能性がある.
+    pub fn write_checkpoint(&mut self, index: &LumpIndex) -> Result<Option<(u64, u64, u32)>> {
+        let free = if let Some(free) = self.allocator.largest_free_portion() {
+            free
+        } else {
+            return Ok(None);
+        };
+        let bytes = Checkpoint::encode(index, self.allocator.free_portions());
+        let block_size = u64::from(self.block_size.as_u16());
+        if u64::from(free.len()) * block_size < self.block_size.ceil_align(bytes.len() as u64) {
+            return Ok(None);
+        }
+
+        let offset = free.start().as_u64() * block_size;
+        let buf = checkpoint::aligned_bytes(&bytes, self.block_size);
+        self.invalidate_read_ahead(offset, buf.len() as u64);
+        track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
+        track_io!(self.nvm.write_all(&buf))?;
+        track!(self.nvm.sync())?;
+        self.metrics
+            .nvm_written_bytes
+            .add(IoOrigin::Foreground, buf.len() as u64);
+        Ok(Some((
+            offset,
+            bytes.len() as u64,
+            checkpoint::checksum(&bytes),
+        )))
+    }
+
+    /// アロケータの空き領域リストを返す.
+    pub fn free_portions(&self) -> impl ExactSizeIterator<Item = FreePortion> + '_ {
+        self.allocator.free_portions()
+    }
+
+    /// データ領域の先頭ブロックを読み込み、読み込んだバイト数を返す.
+    ///
+    /// ウォームアップ用で、データ領域の容量が一ブロックに満たない場合には何も行わない.
+    pub fn touch_first_block(&mut self) -> Result<u64> {
+        let size = self.block_size.as_u16() as usize;
+        if self.nvm.capacity() < size as u64 {
+            return Ok(0);
+        }
+        let mut buf = AlignedBytes::new(size, self.block_size);
+        track_io!(self.nvm.seek(SeekFrom::Start(0)))?;
+        track_io!(self.nvm.read_exact(&mut buf))?;
+        self.metrics
+            .nvm_read_bytes
+            .add(IoOrigin::Foreground, size as u64);
+        Ok(size as u64)
     }
 
     /// 部分領域の単位をブロックからバイトに変換する.
@@ -99,9 +865,36 @@ where
         (offset, size)
     }
 
+    /// サイズが`data_size`のlumpデータを格納するのに必要なブロック数を返す.
+    pub fn required_blocks(&self, data_size: usize) -> u32 {
+        self.block_count((data_size + LUMP_DATA_TRAILER_SIZE) as u32)
+    }
+
     /// `size`分のデータをカバーするのに必要なブロック数.
     fn block_count(&self, size: u32) -> u32 {
-        (size + u32::from(self.block_size.as_u16()) - 1) / u32::from(self.block_size.as_u16())
+        size.div_ceil(u32::from(self.block_size.as_u16()))
+    }
+}
+
+/// 複数回に分けて書き込み中のlumpデータの状態.
+///
+/// `DataRegion::start_sliced_write`によって生成され、
+/// `DataRegion::write_next_slice`によって書き込みが進められる.
+#[derive(Debug)]
+pub struct SlicedWrite {
+    portion: DataPortion,
+    size: usize,
+    written: usize,
+}
+impl SlicedWrite {
+    /// 書き込み先の部分領域を返す.
+    pub fn portion(&self) -> DataPortion {
+        self.portion
+    }
+
+    /// 全体の書き込みが完了したかどうかを判定する.
+    pub fn is_completed(&self) -> bool {
+        self.written == self.size
     }
 }
 
@@ -135,20 +928,139 @@ impl DataRegionLumpData {
         &mut self.bytes[..self.data_size]
     }
 
+    /// データとトレイラの間に挟まれたパディング部分を返す.
+    fn padding(&self) -> &[u8] {
+        &self.bytes[self.data_size..self.bytes.len() - LUMP_DATA_TRAILER_SIZE]
+    }
+
+    fn fill_padding(&mut self, fill: u8) {
+        let end = self.bytes.len() - LUMP_DATA_TRAILER_SIZE;
+        for b in &mut self.bytes[self.data_size..end] {
+            *b = fill;
+        }
+    }
+
     /// 永続化用のバイト列を返す.
     fn as_external_bytes(&self) -> &[u8] {
         self.bytes.as_ref()
     }
 
-    fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
-        track_io!(writer.write_all(self.as_external_bytes()))
+    /// ゼロブロックを省いた、疎な形式の永続化用バイト列を返す.
+    ///
+    /// 形式は以下の通り(各整数はビッグエンディアン):
+    ///
+    /// ```text
+    /// [非ゼロブロック群][パディング][エクステント群: (開始ブロック番号: u32, ブロック数: u32) * N]
+    /// [データサイズ: u32][エクステント数N: u32][SPARSE_LUMP_DATA_MARKER: u16]
+    /// ```
+    ///
+    /// ブロックの単位は`block_size`.
+    /// 通常の形式と比べて、使用するブロック数が削減できない場合には`None`が返される.
+    fn to_sparse_bytes(&self, block_size: BlockSize, fill: Option<u8>) -> Option<AlignedBytes> {
+        let block_len = block_size.as_u16() as usize;
+        let data = self.as_bytes();
+
+        let mut extents: Vec<(u32, u32)> = Vec::new();
+        for (i, block) in data.chunks(block_len).enumerate() {
+            if block.iter().all(|&b| b == 0) {
+                continue;
+            }
+            match extents.last_mut() {
+                Some((start, count)) if (*start + *count) as usize == i => *count += 1,
+                _ => extents.push((i as u32, 1)),
+            }
+        }
+
+        let stored_blocks = extents.iter().map(|&(_, n)| n as usize).sum::<usize>();
+        let metadata_len = extents.len() * SPARSE_EXTENT_SIZE + SPARSE_LUMP_DATA_TRAILER_SIZE;
+        let sparse_len = block_size.ceil_align((stored_blocks * block_len + metadata_len) as u64);
+        if sparse_len >= self.bytes.len() as u64 {
+            return None;
+        }
+
+        let mut bytes = AlignedBytes::new(sparse_len as usize, block_size);
+        bytes.as_mut().fill(0);
+        let mut offset = 0;
+        for &(start, count) in &extents {
+            let from = start as usize * block_len;
+            let to = cmp::min(from + count as usize * block_len, data.len());
+            bytes[offset..][..to - from].copy_from_slice(&data[from..to]);
+            offset += count as usize * block_len;
+        }
+
+        let mut metadata_offset = bytes.len() - metadata_len;
+        if let Some(fill) = fill {
+            for b in &mut bytes[offset..metadata_offset] {
+                *b = fill;
+            }
+        }
+        for &(start, count) in &extents {
+            BigEndian::write_u32(&mut bytes[metadata_offset..], start);
+            BigEndian::write_u32(&mut bytes[metadata_offset + 4..], count);
+            metadata_offset += SPARSE_EXTENT_SIZE;
+        }
+        BigEndian::write_u32(&mut bytes[metadata_offset..], data.len() as u32);
+        BigEndian::write_u32(&mut bytes[metadata_offset + 4..], extents.len() as u32);
+        BigEndian::write_u16(&mut bytes[metadata_offset + 8..], SPARSE_LUMP_DATA_MARKER);
+        Some(bytes)
+    }
+
+    /// 疎な形式のバイト列から、データを復元する.
+    ///
+    /// ブロックの単位は`block_size`.
+    fn from_sparse_bytes(bytes: &[u8], block_size: BlockSize) -> Result<Self> {
+        let block_len = block_size.as_u16() as usize;
+        track_assert!(
+            bytes.len() >= SPARSE_LUMP_DATA_TRAILER_SIZE,
+            ErrorKind::StorageCorrupted
+        );
+        let trailer_offset = bytes.len() - SPARSE_LUMP_DATA_TRAILER_SIZE;
+        let data_size = BigEndian::read_u32(&bytes[trailer_offset..]) as usize;
+        let extent_count = BigEndian::read_u32(&bytes[trailer_offset + 4..]) as usize;
+        let extents_len = extent_count.saturating_mul(SPARSE_EXTENT_SIZE);
+        track_assert!(
+            extents_len <= trailer_offset,
+            ErrorKind::StorageCorrupted,
+            "Too many extents: {}",
+            extent_count
+        );
+        let extents_offset = trailer_offset - extents_len;
+
+        // 省かれたブロック(ホール)はゼロで埋める
+        let mut data = DataRegionLumpData::new(data_size, block_size);
+        data.as_bytes_mut().fill(0);
+        let mut offset = 0;
+        for extent in bytes[extents_offset..trailer_offset].chunks(SPARSE_EXTENT_SIZE) {
+            let start = BigEndian::read_u32(extent) as usize;
+            let count = BigEndian::read_u32(&extent[4..]) as usize;
+            let from = start.saturating_mul(block_len);
+            let len = count.saturating_mul(block_len);
+            track_assert!(
+                from < data_size
+                    && len <= extents_offset - offset
+                    && from.saturating_add(len) < data_size + block_len,
+                ErrorKind::StorageCorrupted,
+                "Invalid extent: start={}, count={}, data_size={}",
+                start,
+                count,
+                data_size
+            );
+            let to = cmp::min(from + len, data_size);
+            data.as_bytes_mut()[from..to].copy_from_slice(&bytes[offset..][..to - from]);
+            offset += len;
+        }
+        Ok(data)
     }
 
-    fn read_from<R: Read>(mut reader: R, mut buf: AlignedBytes) -> Result<Self> {
+    pub(crate) fn read_from<R: Read>(mut reader: R, mut buf: AlignedBytes) -> Result<Self> {
         track_assert!(buf.len() >= LUMP_DATA_TRAILER_SIZE, ErrorKind::InvalidInput);
         track_io!(reader.read_exact(&mut buf))?;
 
-        let padding_len = BigEndian::read_u16(&buf[buf.len() - LUMP_DATA_TRAILER_SIZE..]) as usize;
+        let marker = BigEndian::read_u16(&buf[buf.len() - LUMP_DATA_TRAILER_SIZE..]);
+        if marker == SPARSE_LUMP_DATA_MARKER {
+            return track!(Self::from_sparse_bytes(&buf, buf.block_size()));
+        }
+        let padding_len = marker as usize;
         let data_size = buf
             .len()
             .saturating_sub(LUMP_DATA_TRAILER_SIZE + padding_len);
@@ -160,6 +1072,26 @@ impl DataRegionLumpData {
     }
 }
 
+/// 先読みした内容を保持するバッファ.
+#[derive(Debug)]
+struct ReadAheadBuffer {
+    offset: u64,
+    bytes: Vec<u8>,
+}
+impl ReadAheadBuffer {
+    /// `offset`から`len`バイト分の範囲の内容を返す.
+    ///
+    /// 範囲の全体がバッファに含まれていない場合には`None`が返される.
+    fn get(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let start = offset.checked_sub(self.offset)? as usize;
+        self.bytes.get(start..start + len)
+    }
+
+    fn overlaps(&self, offset: u64, len: u64) -> bool {
+        offset < self.offset + self.bytes.len() as u64 && self.offset < offset + len
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use prometrics::metrics::MetricBuilder;
@@ -171,6 +1103,7 @@ mod tests {
     use crate::block::BlockSize;
     use crate::metrics::DataAllocatorMetrics;
     use crate::nvm::MemoryNvm;
+    use std::io::{self, Seek, Write};
 
     #[test]
     fn data_region_works() -> TestResult {
@@ -196,4 +1129,152 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn write_verification_works() -> TestResult {
+        let capacity = 10 * 1024;
+        let block_size = BlockSize::min();
+        let metrics = MetricBuilder::new();
+        let allocator = track!(DataPortionAllocator::build(
+            DataAllocatorMetrics::new(&metrics, capacity, block_size),
+            iter::empty(),
+        ))?;
+        let nvm = CorruptingNvm {
+            inner: MemoryNvm::new(vec![0; capacity as usize]),
+            corrupt: false,
+        };
+        let mut region = DataRegion::new(&metrics, allocator, nvm);
+        region.set_write_verification(true);
+
+        let mut data = DataRegionLumpData::new(3, block_size);
+        data.as_bytes_mut().copy_from_slice(b"foo");
+        track!(region.put(&data))?;
+        assert_eq!(region.metrics().write_verification_failures(), 0);
+
+        // 書き込み内容が化けた場合には、検証に失敗し、部分領域は解放される
+        region.nvm.corrupt = true;
+        let usage_bytes = region.metrics().usage_bytes();
+        assert_eq!(
+            region.put(&data).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+        assert_eq!(region.metrics().write_verification_failures(), 1);
+        assert_eq!(region.metrics().usage_bytes(), usage_bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_lump_data_works() -> TestResult {
+        let capacity = 64 * 1024;
+        let block_size = BlockSize::min();
+        let metrics = MetricBuilder::new();
+        let allocator = track!(DataPortionAllocator::build(
+            DataAllocatorMetrics::new(&metrics, capacity, block_size),
+            iter::empty(),
+        ))?;
+        let nvm = MemoryNvm::new(vec![0; capacity as usize]);
+        let mut region = DataRegion::new(&metrics, allocator, nvm);
+        region.set_sparse_mode(true);
+        region.set_padding_fill_byte(Some(0xAA));
+        region.set_write_verification(true);
+
+        // 十ブロック(+α)の内、非ゼロのブロックは三つ(末尾の端数ブロックを含む)
+        let mut data = DataRegionLumpData::new(10 * 512 + 100, block_size);
+        data.as_bytes_mut().fill(0);
+        data.as_bytes_mut()[512 + 3] = 1;
+        data.as_bytes_mut()[2 * 512..3 * 512].copy_from_slice(&[2; 512][..]);
+        data.as_bytes_mut()[10 * 512 + 99] = 3;
+        let sparse = track!(region.put(&data))?;
+        assert_eq!(sparse.len, 4);
+        assert_eq!(region.metrics().sparse_lumps(), 1);
+        assert_eq!(region.metrics().sparse_hole_bytes(), 7 * 512);
+
+        // 全てゼロ
+        let mut zeros = DataRegionLumpData::new(8 * 512, block_size);
+        zeros.as_bytes_mut().fill(0);
+        let empty = track!(region.put(&zeros))?;
+        assert_eq!(empty.len, 1);
+
+        // ゼロブロックを含まない場合には、通常の形式で格納される
+        let mut dense_data = DataRegionLumpData::new(3, block_size);
+        dense_data.as_bytes_mut().copy_from_slice(b"foo");
+        let dense = track!(region.put(&dense_data))?;
+        assert_eq!(dense.len, 1);
+        assert_eq!(region.metrics().sparse_lumps(), 2);
+
+        assert_eq!(track!(region.get(sparse))?.as_bytes(), data.as_bytes());
+        assert_eq!(track!(region.get(empty))?.as_bytes(), zeros.as_bytes());
+        assert_eq!(track!(region.get(dense))?.as_bytes(), b"foo");
+
+        let many = track!(region.get_many(&[dense, sparse, empty]))?;
+        assert_eq!(many[0].as_bytes(), b"foo");
+        assert_eq!(many[1].as_bytes(), data.as_bytes());
+        assert_eq!(many[2].as_bytes(), zeros.as_bytes());
+
+        // 壊れたエクステント情報
+        let mut bytes = data.to_sparse_bytes(block_size, None).expect("Never fails");
+        let extent_offset = bytes.len() - SPARSE_LUMP_DATA_TRAILER_SIZE - SPARSE_EXTENT_SIZE;
+        BigEndian::write_u32(&mut bytes[extent_offset..], 100);
+        assert_eq!(
+            DataRegionLumpData::from_sparse_bytes(&bytes, block_size)
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+        Ok(())
+    }
+
+    /// 書き込まれたデータの先頭バイトを化けさせることができる`NonVolatileMemory`の実装.
+    #[derive(Debug)]
+    struct CorruptingNvm {
+        inner: MemoryNvm,
+        corrupt: bool,
+    }
+    impl NonVolatileMemory for CorruptingNvm {
+        fn sync(&mut self) -> Result<()> {
+            self.inner.sync()
+        }
+        fn position(&self) -> u64 {
+            self.inner.position()
+        }
+        fn capacity(&self) -> u64 {
+            self.inner.capacity()
+        }
+        fn block_size(&self) -> BlockSize {
+            self.inner.block_size()
+        }
+        fn split(self, position: u64) -> Result<(Self, Self)> {
+            let corrupt = self.corrupt;
+            let (left, right) = track!(self.inner.split(position))?;
+            let wrap = |inner| CorruptingNvm { inner, corrupt };
+            Ok((wrap(left), wrap(right)))
+        }
+        fn discard(&mut self, offset: u64, size: u64) -> Result<()> {
+            self.inner.discard(offset, size)
+        }
+    }
+    impl Seek for CorruptingNvm {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    impl Read for CorruptingNvm {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+    impl Write for CorruptingNvm {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.corrupt {
+                let mut buf = buf.to_owned();
+                buf[0] ^= 0xFF;
+                self.inner.write(&buf)
+            } else {
+                self.inner.write(buf)
+            }
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
 }
@@ -0,0 +1,287 @@
+//! 複数のlump同士でデータ部分領域を共有するための重複排除および別名.
+//!
+//! 詳細は`StorageBuilder::deduplication`および`Storage::link`を参照のこと.
+use adler32::RollingAdler32;
+use std::collections::{HashMap, HashSet};
+
+use crate::lump::LumpId;
+use crate::storage::index::LumpIndex;
+use crate::storage::journal::{AuditRecord, DedupPutRecord, JournalRecord, LinkRecord};
+use crate::storage::portion::DataPortion;
+
+/// lumpのデータの内容から、重複検出用のハッシュ値を計算する.
+///
+/// ハッシュ値はジャーナルに永続化されるため、アルゴリズムは固定である必要がある
+/// (上位32ビットがCRC-32C、下位32ビットがAdler-32).
+///
+/// 暗号学的なハッシュ関数ではないので、衝突は起こり得る.
+/// そのため、ハッシュ値が一致した場合には、共有前に必ず実際のデータの比較が行われる.
+pub fn content_hash(data: &[u8]) -> u64 {
+    let crc = crc32c::crc32c(data);
+    let adler = RollingAdler32::from_buffer(data).hash();
+    (u64::from(crc) << 32) | u64::from(adler)
+}
+
+/// ジャーナルの復元時に、共有され得るデータ部分領域と、そのハッシュ値を収集するためのオブジェクト.
+///
+/// 別名によってのみ共有されている部分領域のハッシュ値は`None`となる.
+#[derive(Debug, Default)]
+pub struct ContentHashes(HashMap<DataPortion, Option<u64>>);
+impl ContentHashes {
+    /// 新しい`ContentHashes`インスタンスを生成する.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ジャーナルレコードを先頭から順に渡して、部分領域とハッシュ値の対応を更新する.
+    ///
+    /// 部分領域は解放後に別の内容で再利用され得るので、ハッシュ値は常に後のレコードの内容が優先される.
+    ///
+    /// なお、ジャーナルのGCによって、別名の参照元のPUTレコードが、別名作成レコードの後ろに再配置されることがある.
+    /// そのため、通常のPUTレコード(監査付きのものを含む)はハッシュ値を取り除くのみで、部分領域自体は共有され得るものとして残しておく
+    /// (実際の参照数は、復元後のインデックスから求められる).
+    pub fn observe(&mut self, record: &JournalRecord<Vec<u8>>) {
+        if let Some(record) = DedupPutRecord::from_journal_record(record) {
+            self.0.insert(record.portion, Some(record.hash));
+        } else if let Some(record) = LinkRecord::from_journal_record(record) {
+            self.0.entry(record.portion).or_insert(None);
+        } else {
+            let portion = match *record {
+                JournalRecord::Put(_, portion) => Some(portion),
+                _ => AuditRecord::audited_put(record).map(|(_, portion)| portion),
+            };
+            if let Some(hash) = portion.and_then(|p| self.0.get_mut(&p)) {
+                *hash = None;
+            }
+        }
+    }
+
+    /// 他のlumpと共有されている部分領域を参照しているlumpのうち、
+    /// 各部分領域について(ID順で)二番目以降のもののID群を返す.
+    ///
+    /// アロケータの構築時に、共有されている部分領域が重複とみなされないようにするために使用する.
+    pub fn secondary_references(&self, index: &LumpIndex) -> HashSet<LumpId> {
+        let mut seen = HashSet::new();
+        index
+            .data_portions()
+            .filter(|(_, portion)| self.0.contains_key(portion) && !seen.insert(*portion))
+            .map(|(lump_id, _)| lump_id)
+            .collect()
+    }
+}
+
+/// 重複排除用の部分領域の参照情報.
+#[derive(Debug, Clone, Copy)]
+struct SharedPortion {
+    hash: Option<u64>,
+    refs: u32,
+}
+
+/// 重複排除のための、ハッシュ値と部分領域の対応および、各部分領域の参照数を管理するテーブル.
+///
+/// 重複排除付きのPUTレコード(`DedupPutRecord`)によって書き込まれた部分領域と、
+/// 別名(`LinkRecord`)の参照先となった部分領域のみが管理対象となり、
+/// それ以外の部分領域は、従来通りに単一のlumpによって所有されているものとして扱われる.
+#[derive(Debug, Default)]
+pub struct DedupTable {
+    enabled: bool,
+    by_hash: HashMap<u64, DataPortion>,
+    portions: HashMap<DataPortion, SharedPortion>,
+}
+impl DedupTable {
+    /// 新しい`DedupTable`インスタンスを生成する.
+    ///
+    /// `enabled`が`false`の場合には、新規のPUTでの重複排除は行われないが、
+    /// 既存の共有部分領域の参照数の管理は継続して行われる.
+    pub fn new(enabled: bool) -> Self {
+        DedupTable {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    /// ジャーナルから収集したハッシュ値と、復元後のインデックスから、テーブルを再構築する.
+    pub fn restore(enabled: bool, hashes: ContentHashes, index: &LumpIndex) -> Self {
+        let mut table = Self::new(enabled);
+        for (_, portion) in index.data_portions() {
+            if let Some(&hash) = hashes.0.get(&portion) {
+                table.insert_ref(portion, hash);
+            }
+        }
+        table
+    }
+
+    /// 新規のPUTで重複排除を行うかどうかを返す.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 管理対象の部分領域が一つも存在しない場合には`true`を返す.
+    pub fn is_empty(&self) -> bool {
+        self.portions.is_empty()
+    }
+
+    /// 指定されたハッシュ値を持つ部分領域を検索する.
+    pub fn lookup(&self, hash: u64) -> Option<DataPortion> {
+        self.by_hash.get(&hash).cloned()
+    }
+
+    /// 部分領域が複数のlumpから参照されているかどうかを判定する.
+    pub fn is_shared(&self, portion: &DataPortion) -> bool {
+        self.portions.get(portion).is_some_and(|p| p.refs > 1)
+    }
+
+    /// 部分領域への参照を一つ追加する.
+    pub fn add_ref(&mut self, portion: DataPortion, hash: u64) {
+        self.insert_ref(portion, Some(hash));
+    }
+
+    /// 別名の作成に伴い、部分領域への参照を一つ追加する.
+    ///
+    /// 部分領域が管理対象外であった場合には、既存の参照元の分も含めて、参照数は`2`となる.
+    pub fn link(&mut self, portion: DataPortion) {
+        self.portions
+            .entry(portion)
+            .or_insert(SharedPortion {
+                hash: None,
+                refs: 1,
+            })
+            .refs += 1;
+    }
+
+    fn insert_ref(&mut self, portion: DataPortion, hash: Option<u64>) {
+        let entry = self
+            .portions
+            .entry(portion)
+            .or_insert(SharedPortion { hash, refs: 0 });
+        entry.refs += 1;
+        if let Some(hash) = hash {
+            // ハッシュ値が衝突している場合には、先に登録された方のみが重複排除の対象となる
+            self.by_hash.entry(hash).or_insert(portion);
+        }
+    }
+
+    /// 部分領域への参照を一つ取り除く.
+    ///
+    /// 部分領域が管理対象外であるか、最後の参照が取り除かれた場合には`true`が返される.
+    /// その場合には、呼び出し側で部分領域を解放する必要がある.
+    pub fn release(&mut self, portion: &DataPortion) -> bool {
+        let hash = match self.portions.get_mut(portion) {
+            None => return true,
+            Some(entry) if entry.refs > 1 => {
+                entry.refs -= 1;
+                return false;
+            }
+            Some(entry) => entry.hash,
+        };
+        self.portions.remove(portion);
+        if let Some(hash) = hash {
+            if self.by_hash.get(&hash) == Some(portion) {
+                self.by_hash.remove(&hash);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::portion::Portion;
+    use crate::storage::Address;
+
+    fn portion(start: u32, len: u16) -> DataPortion {
+        DataPortion {
+            start: Address::from(start),
+            len,
+        }
+    }
+
+    #[test]
+    fn dedup_table_works() {
+        let mut table = DedupTable::new(true);
+        let (p0, p1) = (portion(0, 1), portion(1, 1));
+        table.add_ref(p0, 10);
+        table.add_ref(p0, 10);
+        table.add_ref(p1, 10); // ハッシュ値の衝突
+        assert_eq!(table.lookup(10), Some(p0));
+        assert!(table.is_shared(&p0));
+        assert!(!table.is_shared(&p1));
+
+        assert!(!table.release(&p0));
+        assert!(table.release(&p0));
+        assert_eq!(table.lookup(10), None);
+        assert!(table.release(&p1));
+        assert!(table.is_empty());
+
+        // 管理対象外の部分領域は、常に解放可能
+        assert!(table.release(&portion(2, 1)));
+    }
+
+    #[test]
+    fn restore_works() {
+        let (p0, p1) = (portion(0, 1), portion(1, 1));
+        let mut hashes = ContentHashes::new();
+        hashes.observe(&DedupPutRecord::new(LumpId::new(0), p0, 10).to_journal_record());
+        hashes.observe(&DedupPutRecord::new(LumpId::new(1), p0, 10).to_journal_record());
+        hashes.observe(&DedupPutRecord::new(LumpId::new(2), p1, 20).to_journal_record());
+        hashes.observe(&JournalRecord::Put(LumpId::new(2), p1)); // 通常のPUTで再利用された
+
+        let index: LumpIndex = vec![
+            (LumpId::new(0), Portion::Data(p0)),
+            (LumpId::new(1), Portion::Data(p0)),
+            (LumpId::new(2), Portion::Data(p1)),
+        ]
+        .into_iter()
+        .collect();
+        let secondaries = hashes.secondary_references(&index);
+        assert_eq!(
+            secondaries.into_iter().collect::<Vec<_>>(),
+            [LumpId::new(1)]
+        );
+
+        let table = DedupTable::restore(false, hashes, &index);
+        assert!(!table.is_enabled());
+        assert!(table.is_shared(&p0));
+        assert_eq!(table.lookup(10), Some(p0));
+        assert_eq!(table.lookup(20), None);
+    }
+
+    #[test]
+    fn link_works() {
+        let (p0, p1) = (portion(0, 1), portion(1, 1));
+        let mut table = DedupTable::new(false);
+        table.link(p0);
+        assert!(table.is_shared(&p0));
+        assert!(!table.release(&p0));
+        assert!(table.release(&p0));
+
+        // GCによって参照元のPUTレコードが後ろに再配置されても、共有状態は復元される
+        let mut hashes = ContentHashes::new();
+        hashes.observe(&LinkRecord::new(LumpId::new(1), p1).to_journal_record());
+        hashes.observe(&JournalRecord::Put(LumpId::new(0), p1));
+        let index: LumpIndex = vec![
+            (LumpId::new(0), Portion::Data(p1)),
+            (LumpId::new(1), Portion::Data(p1)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            hashes
+                .secondary_references(&index)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [LumpId::new(1)]
+        );
+        let mut table = DedupTable::restore(false, hashes, &index);
+        assert!(table.is_shared(&p1));
+        assert!(!table.release(&p1));
+        assert!(table.release(&p1));
+    }
+
+    #[test]
+    fn content_hash_works() {
+        assert_eq!(content_hash(b"foo"), content_hash(b"foo"));
+        assert_ne!(content_hash(b"foo"), content_hash(b"bar"));
+    }
+}
@@ -1,166 +1,113 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use uuid::Uuid;
 
 use crate::block::BlockSize;
+use crate::format::header::FULL_HEADER_SIZE;
 use crate::nvm::NonVolatileMemory;
-use crate::storage::{
-    MAGIC_NUMBER, MAJOR_VERSION, MAX_DATA_REGION_SIZE, MAX_JOURNAL_REGION_SIZE, MINOR_VERSION,
-};
-use crate::{ErrorKind, Result};
-
-/// ヘッダを表現するのに必要なバイト数.
-const HEADER_SIZE: u16 =
-    2 /* major_version */ +
-    2 /* minor_version */ +
-    2 /* block_size */ +
-    16 /* UUID */ +
-    8 /* journal_region_size */ +
-    8 /* data_region_size */;
-
-/// **マジックナンバー** と **ヘッダサイズ** も含めたサイズ.
-pub(crate) const FULL_HEADER_SIZE: u16 = 4 + 2 + HEADER_SIZE;
-
-/// ストレージのヘッダ情報.
-///
-/// # 参考
-///
-/// - [ストレージフォーマット(v1.0)][format]
-///
-/// [format]: https://github.com/frugalos/cannyls/wiki/Storage-Format
-#[derive(Debug, Clone)]
-pub struct StorageHeader {
-    /// メジャーバージョン.
-    ///
-    /// メジャーバージョンが異なるストレージ同士のデータ形式には互換性が無い.
-    ///
-    /// 現在の最新バージョンは[`MAJOR_VERSION`](./constant.MAJOR_VERSION.html).
-    pub major_version: u16,
-
-    /// マイナーバージョン.
-    ///
-    /// マイナーバージョンには、後方互換性がある
-    /// (i.e., 古い形式で作成されたストレージを、新しいプログラムで扱うことが可能).
-    ///
-    /// 現在の最新バージョンは[`MINOR_VERSION`](./constant.MINOR_VERSION.html).
-    pub minor_version: u16,
+use crate::storage::{StorageHeader, MAGIC_NUMBER};
+use crate::Result;
 
-    /// ストレージのブロックサイズ.
-    pub block_size: BlockSize,
-
-    /// ストレージの特定のインスタンスを識別するためのUUID.
-    pub instance_uuid: Uuid,
-
-    /// ジャーナル領域のサイズ(バイト単位).
-    pub journal_region_size: u64,
+/// バックアップヘッダを探索する、NVMの末尾の範囲のサイズ(バイト数).
+///
+/// バックアップヘッダ領域のサイズとストレージの末尾以降の余りは、共にブロックサイズ未満なので、
+/// ブロックサイズの最大値の二倍分の範囲を探索すれば十分.
+const BACKUP_SEARCH_SIZE: u64 = 2 * (u16::MAX as u64 + 1);
 
-    /// データ領域のサイズ(バイト単位).
-    pub data_region_size: u64,
-}
 impl StorageHeader {
-    /// ストレージが使用する領域全体のサイズを返す.
-    ///
-    /// 内訳としては **ヘッダ領域** と **ジャーナル領域** 、 **データ領域** のサイズの合計となる.
-    pub fn storage_size(&self) -> u64 {
-        self.region_size() + self.journal_region_size + self.data_region_size
-    }
-
-    /// ヘッダ領域のサイズを返す.
-    ///
-    /// **ヘッダ領域** には、以下が含まれる:
-    ///
-    /// - マジックナンバー
-    /// - ヘッダ長
-    /// - `StorageHeader`
-    /// - 領域のサイズをブロック境界に揃えるためのパディング
-    pub fn region_size(&self) -> u64 {
-        Self::calc_region_size(self.block_size)
-    }
-
     /// 存在するLump Storageから
     /// 保存済みのストレージヘッダを取り出す。
     pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = track_io!(File::open(path))?;
+        track!(Self::read_from_file_at(path, 0))
+    }
+
+    /// ファイル内の`offset`の位置に格納されているLump Storageから、
+    /// 保存済みのストレージヘッダを取り出す。
+    pub fn read_from_file_at<P: AsRef<Path>>(path: P, offset: u64) -> Result<Self> {
+        let mut file = track_io!(File::open(path))?;
+        track_io!(file.seek(SeekFrom::Start(offset)))?;
         track!(Self::read_from(file))
     }
 
-    /// ヘッダ情報を`reader`から読み込む.
-    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
-        // magic number
-        let mut magic_number = [0; 4];
-        track_io!(reader.read_exact(&mut magic_number))?;
-        track_assert_eq!(magic_number, MAGIC_NUMBER, ErrorKind::InvalidInput);
-
-        // header size
-        let header_size = track_io!(reader.read_u16::<BigEndian>())?;
-        let mut reader = reader.take(u64::from(header_size));
-
-        // versions
-        let major_version = track_io!(reader.read_u16::<BigEndian>())?;
-        let minor_version = track_io!(reader.read_u16::<BigEndian>())?;
-        track_assert_eq!(
-            major_version,
-            MAJOR_VERSION,
-            ErrorKind::InvalidInput,
-            "Unsupported major version",
-        );
-        track_assert!(
-            minor_version <= MINOR_VERSION,
-            ErrorKind::InvalidInput,
-            "Unsupported minor version: actual={}, supported={}",
-            minor_version,
-            MINOR_VERSION
-        );
+    /// ファイル内の`offset`の位置に格納されているLump Storageから、
+    /// ストレージの末尾に保存されているバックアップのヘッダを取り出す.
+    ///
+    /// `len`にはストレージを格納している範囲の長さを指定する.
+    /// `None`の場合には、`offset`以降のファイル全体が対象となる.
+    ///
+    /// バックアップが見つからなかった場合には`None`が返される.
+    pub fn read_backup_from_file_at<P: AsRef<Path>>(
+        path: P,
+        offset: u64,
+        len: Option<u64>,
+    ) -> Result<Option<Self>> {
+        let mut file = track_io!(File::open(path))?;
+        let len = if let Some(len) = len {
+            len
+        } else {
+            let file_len = track_io!(file.metadata())?.len();
+            file_len.saturating_sub(offset)
+        };
+        let start = BlockSize::min().floor_align(len.saturating_sub(BACKUP_SEARCH_SIZE));
 
-        // block_size
-        let block_size = track_io!(reader.read_u16::<BigEndian>())?;
-        let block_size = track!(BlockSize::new(block_size), "block_size:{}", block_size)?;
-
-        // UUID
-        let mut instance_uuid = [0; 16];
-        track_io!(reader.read_exact(&mut instance_uuid))?;
-        let instance_uuid = Uuid::from_bytes(instance_uuid);
-
-        // region sizes
-        let journal_region_size = track_io!(reader.read_u64::<BigEndian>())?;
-        let data_region_size = track_io!(reader.read_u64::<BigEndian>())?;
-        track_assert!(
-            journal_region_size <= MAX_JOURNAL_REGION_SIZE,
-            ErrorKind::InvalidInput,
-            "journal_region_size:{}",
-            journal_region_size
-        );
-        track_assert!(
-            data_region_size <= MAX_DATA_REGION_SIZE,
-            ErrorKind::InvalidInput,
-            "data_region_size:{}",
-            data_region_size
-        );
+        let mut buf = Vec::new();
+        track_io!(file.seek(SeekFrom::Start(offset + start)))?;
+        track_io!(file.take(len - start).read_to_end(&mut buf))?;
+        Ok(Self::find_backup(&buf, start))
+    }
 
-        track_assert_eq!(reader.limit(), 0, ErrorKind::InvalidInput);
-        Ok(StorageHeader {
-            major_version,
-            minor_version,
-            instance_uuid,
-            block_size,
-            journal_region_size,
-            data_region_size,
-        })
+    /// NVMの先頭からヘッダを読み込む.
+    ///
+    /// 先頭のヘッダが壊れている場合には、末尾のバックアップ(存在すれば)が返される.
+    pub(crate) fn read_from_nvm<N: NonVolatileMemory>(nvm: &mut N) -> Result<Self> {
+        track_io!(nvm.seek(SeekFrom::Start(0)))?;
+
+        // アライメントを保証するためにバッファを経由する
+        let buf = track!(nvm.aligned_read_bytes(FULL_HEADER_SIZE as usize))?;
+        match Self::read_from(&buf[..]) {
+            Ok(header) => Ok(header),
+            Err(e) => {
+                if let Ok(Some(header)) = Self::read_backup_from_nvm(nvm) {
+                    Ok(header)
+                } else {
+                    Err(track!(e))
+                }
+            }
+        }
     }
 
-    /// ヘッダ情報を`writer`に書き込む.
-    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
-        track_io!(writer.write_all(&MAGIC_NUMBER[..]))?;
-        track_io!(writer.write_u16::<BigEndian>(HEADER_SIZE))?;
-        track_io!(writer.write_u16::<BigEndian>(self.major_version))?;
-        track_io!(writer.write_u16::<BigEndian>(self.minor_version))?;
-        track_io!(writer.write_u16::<BigEndian>(self.block_size.as_u16()))?;
-        track_io!(writer.write_all(self.instance_uuid.as_bytes()))?;
-        track_io!(writer.write_u64::<BigEndian>(self.journal_region_size))?;
-        track_io!(writer.write_u64::<BigEndian>(self.data_region_size))?;
-        Ok(())
+    /// NVMの末尾に保存されているバックアップのヘッダを取り出す.
+    ///
+    /// バックアップが見つからなかった場合には`None`が返される.
+    pub(crate) fn read_backup_from_nvm<N: NonVolatileMemory>(nvm: &mut N) -> Result<Option<Self>> {
+        let block_size = nvm.block_size();
+        let end = block_size.floor_align(nvm.capacity());
+        let start = block_size.floor_align(end.saturating_sub(BACKUP_SEARCH_SIZE));
+
+        track_io!(nvm.seek(SeekFrom::Start(start)))?;
+        let buf = track!(nvm.aligned_read_bytes((end - start) as usize))?;
+        Ok(Self::find_backup(&buf, start))
+    }
+
+    /// `buf`(ストレージ内の`offset`の位置から読み込まれたバイト列)の中から、バックアップのヘッダを探す.
+    ///
+    /// ブロック境界に位置し、かつ、その位置がヘッダの内容から算出されるバックアップヘッダ領域の位置と一致するものの内、
+    /// 最も後ろにあるものが返される.
+    fn find_backup(buf: &[u8], offset: u64) -> Option<Self> {
+        let first = (BlockSize::min().ceil_align(offset) - offset) as usize;
+        (first..buf.len())
+            .step_by(BlockSize::MIN as usize)
+            .rev()
+            .filter(|&i| buf[i..].starts_with(&MAGIC_NUMBER))
+            .filter_map(|i| {
+                let header = Self::read_from(&buf[i..]).ok()?;
+                if header.backup_region_position() == Some(offset + i as u64) {
+                    Some(header)
+                } else {
+                    None
+                }
+            })
+            .next()
     }
 
     /// ヘッダ領域を`writer`に書き込む.
@@ -175,11 +122,6 @@ impl StorageHeader {
         Ok(())
     }
 
-    /// 指定されたブロックサイズを有するストレージのために必要な、ヘッダ領域のサイズを計算する.
-    pub(crate) fn calc_region_size(block_size: BlockSize) -> u64 {
-        block_size.ceil_align(u64::from(FULL_HEADER_SIZE))
-    }
-
     /// 不揮発性メモリ全体の領域を分割して、ジャーナル領域およびデータ領域用のメモリを返す.
     pub(crate) fn split_regions<N: NonVolatileMemory>(&self, nvm: N) -> Result<(N, N)> {
         let header_tail = self.region_size();
@@ -191,28 +133,51 @@ impl StorageHeader {
 
 #[cfg(test)]
 mod tests {
+    use byteorder::{BigEndian, ByteOrder};
     use trackable::result::TestResult;
     use uuid::Uuid;
 
     use super::*;
     use crate::block::BlockSize;
+    use crate::build_info::CrateVersion;
+    use crate::format::header::HEADER_SIZE;
+    use crate::storage::{
+        JournalChecksum, StorageCreationParams, MAJOR_VERSION, MAX_LOGICAL_NAME_LEN, MINOR_VERSION,
+    };
+    use crate::ErrorKind;
 
     #[test]
     fn it_works() -> TestResult {
         let header = StorageHeader {
             major_version: MAJOR_VERSION,
             minor_version: MINOR_VERSION,
+            original_minor_version: MINOR_VERSION,
             block_size: BlockSize::min(),
             instance_uuid: Uuid::new_v4(),
             journal_region_size: 1024,
             data_region_size: 4096,
+            journal_checksum: JournalChecksum::Crc32c,
+            journal_header_slots: 2,
+            backup_header: true,
+            writer_version: Some(CrateVersion::CURRENT),
+            logical_name: Some("cluster0/slot1".to_owned()),
+            sparse_lumps: true,
+            creation_params: Some(StorageCreationParams {
+                journal_region_ratio: 0.25,
+                journal_gc_queue_size: 0x1000,
+                journal_sync_interval: 0x1000,
+            }),
         };
 
         // size
         assert_eq!(header.region_size(), u64::from(BlockSize::MIN));
         assert_eq!(
             header.storage_size(),
-            u64::from(BlockSize::MIN) + 1024 + 4096
+            u64::from(BlockSize::MIN) + 1024 + 4096 + u64::from(BlockSize::MIN)
+        );
+        assert_eq!(
+            header.backup_region_position(),
+            Some(u64::from(BlockSize::MIN) + 1024 + 4096)
         );
 
         // read/write
@@ -226,6 +191,166 @@ mod tests {
         assert_eq!(h.instance_uuid, header.instance_uuid);
         assert_eq!(h.journal_region_size, header.journal_region_size);
         assert_eq!(h.data_region_size, header.data_region_size);
+        assert_eq!(h.journal_checksum, header.journal_checksum);
+        assert_eq!(h.journal_header_slots, header.journal_header_slots);
+        assert_eq!(h.backup_header, header.backup_header);
+        assert_eq!(h.writer_version, Some(CrateVersion::CURRENT));
+        assert_eq!(h.logical_name, header.logical_name);
+        assert_eq!(h.sparse_lumps, header.sparse_lumps);
+        assert_eq!(h.creation_params, header.creation_params);
+
+        // バージョンが不明な場合
+        let mut header = header;
+        header.writer_version = None;
+        header.logical_name = None;
+        header.creation_params = None;
+        let mut buf = Vec::new();
+        track!(header.write_to(&mut buf))?;
+        let h = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(h.writer_version, None);
+        assert_eq!(h.logical_name, None);
+        assert_eq!(h.creation_params, None);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_header_uses_adler32() -> TestResult {
+        let h = header(MAJOR_VERSION, 3);
+        let mut buf = Vec::new();
+        track!(h.write_to(&mut buf))?;
+
+        // チェックサムのアルゴリズムを含まない、古い形式のヘッダに変換する
+        buf.truncate(buf.len() - 100);
+        BigEndian::write_u16(&mut buf[4..], HEADER_SIZE - 100);
+
+        let h = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(h.minor_version, 3);
+        assert_eq!(h.journal_checksum, JournalChecksum::Adler32);
+        assert_eq!(h.journal_header_slots, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_header_uses_single_journal_header_slot() -> TestResult {
+        let h = header(MAJOR_VERSION, 4);
+        let mut buf = Vec::new();
+        track!(h.write_to(&mut buf))?;
+
+        // ジャーナルのヘッダのスロット数を含まない、古い形式のヘッダに変換する
+        buf.truncate(buf.len() - 99);
+        BigEndian::write_u16(&mut buf[4..], HEADER_SIZE - 99);
+
+        let h = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(h.minor_version, 4);
+        assert_eq!(h.journal_checksum, JournalChecksum::Crc32c);
+        assert_eq!(h.journal_header_slots, 1);
+        assert!(!h.backup_header);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_header_has_no_backup() -> TestResult {
+        let h = header(MAJOR_VERSION, 5);
+        let mut buf = Vec::new();
+        track!(h.write_to(&mut buf))?;
+
+        // バックアップの有無を含まない、古い形式のヘッダに変換する
+        buf.truncate(buf.len() - 98);
+        BigEndian::write_u16(&mut buf[4..], HEADER_SIZE - 98);
+
+        let h = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(h.minor_version, 5);
+        assert_eq!(h.journal_header_slots, 2);
+        assert!(!h.backup_header);
+        assert_eq!(h.backup_region_position(), None);
+        assert_eq!(h.storage_size(), u64::from(BlockSize::MIN) + 1024 + 4096);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_header_has_no_version_1_7_fields() -> TestResult {
+        let h = header(MAJOR_VERSION, 6);
+        let mut buf = Vec::new();
+        track!(h.write_to(&mut buf))?;
+
+        // 書き込んだクレートのバージョン等を含まない、古い形式のヘッダに変換する
+        buf.truncate(buf.len() - 97);
+        BigEndian::write_u16(&mut buf[4..], HEADER_SIZE - 97);
+
+        let h = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(h.minor_version, 6);
+        assert!(h.backup_header);
+        assert_eq!(h.writer_version, None);
+        assert_eq!(h.logical_name, None);
+        assert!(!h.sparse_lumps);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_header_has_no_creation_params() -> TestResult {
+        let h = header(MAJOR_VERSION, 7);
+        let mut buf = Vec::new();
+        track!(h.write_to(&mut buf))?;
+
+        // 作成時のパラメータを含まない、古い形式のヘッダに変換する
+        buf.truncate(buf.len() - 25);
+        BigEndian::write_u16(&mut buf[4..], HEADER_SIZE - 25);
+
+        let h = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(h.minor_version, 7);
+        assert_eq!(h.writer_version, Some(CrateVersion::CURRENT));
+        assert_eq!(h.creation_params, None);
+        Ok(())
+    }
+
+    #[test]
+    fn logical_name_works() -> TestResult {
+        let mut h = header(MAJOR_VERSION, MINOR_VERSION);
+
+        // 最大長
+        h.logical_name = Some("a".repeat(MAX_LOGICAL_NAME_LEN));
+        let mut buf = Vec::new();
+        track!(h.write_to(&mut buf))?;
+        let decoded = track!(StorageHeader::read_from(&buf[..]))?;
+        assert_eq!(decoded.logical_name, h.logical_name);
+
+        // 長すぎる
+        h.logical_name = Some("a".repeat(MAX_LOGICAL_NAME_LEN + 1));
+        assert!(h.write_to(&mut Vec::new()).is_err());
+
+        // 長さが不正
+        let len_offset = FULL_HEADER_SIZE as usize - 27 - MAX_LOGICAL_NAME_LEN;
+        buf[len_offset] = MAX_LOGICAL_NAME_LEN as u8 + 1;
+        assert!(StorageHeader::read_from(&buf[..]).is_err());
+
+        // UTF-8として不正
+        buf[len_offset] = 1;
+        buf[len_offset + 1] = 0xFF;
+        assert!(StorageHeader::read_from(&buf[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn find_backup_works() -> TestResult {
+        let h = header(MAJOR_VERSION, MINOR_VERSION);
+        let position = track_assert_some!(h.backup_region_position(), ErrorKind::Other);
+
+        // ストレージ全体(および、その後ろの余り)を模したバイト列
+        let mut buf = vec![0; h.storage_size() as usize + 100];
+        track!(h.write_header_region_to(&mut buf[..]))?;
+        track!(h.write_header_region_to(&mut buf[position as usize..]))?;
+
+        let backup = track_assert_some!(StorageHeader::find_backup(&buf, 0), ErrorKind::Other);
+        assert_eq!(backup.instance_uuid, h.instance_uuid);
+
+        // 末尾のみを読み込んだ場合
+        let offset = 1024;
+        let backup = StorageHeader::find_backup(&buf[offset..], offset as u64);
+        assert_eq!(backup.map(|h| h.instance_uuid), Some(h.instance_uuid));
+
+        // 先頭のヘッダは、バックアップとしては扱われない
+        buf[position as usize] ^= 0xFF;
+        assert!(StorageHeader::find_backup(&buf, 0).is_none());
         Ok(())
     }
 
@@ -275,10 +400,22 @@ mod tests {
         StorageHeader {
             major_version,
             minor_version,
+            original_minor_version: minor_version,
             block_size: BlockSize::min(),
             instance_uuid: Uuid::new_v4(),
             journal_region_size: 1024,
             data_region_size: 4096,
+            journal_checksum: JournalChecksum::Crc32c,
+            journal_header_slots: 2,
+            backup_header: true,
+            writer_version: Some(CrateVersion::CURRENT),
+            logical_name: Some("cluster0/slot1".to_owned()),
+            sparse_lumps: true,
+            creation_params: Some(StorageCreationParams {
+                journal_region_ratio: 0.01,
+                journal_gc_queue_size: 0x1000,
+                journal_sync_interval: 0x1000,
+            }),
         }
     }
 }
@@ -1,11 +1,13 @@
 //! デバイスに格納されているlump群の情報を管理するためのインデックス.
 use std::collections::{btree_map, BTreeMap};
+use std::iter::{FromIterator, Peekable};
+use std::mem;
 use std::ops;
 
 use crate::block::BlockSize;
 use crate::lump::LumpId;
 use crate::storage::portion::{DataPortion, Portion, PortionU64};
-use crate::storage::StorageUsage;
+use crate::storage::{StorageUsage, UsageSummary};
 
 /// Lump群の位置情報を保持するインデックス.
 ///
@@ -13,77 +15,419 @@ use crate::storage::StorageUsage;
 ///
 /// このインデックス自体は永続化されることはないメモリ上のデータ構造であり、
 /// デバイスの起動時に、ジャーナルの情報を用いて毎回再構築される.
-#[derive(Debug, Clone, Default)]
+///
+/// `hide_range`で指定された範囲のlumpは、`drain_range`以外の操作からは存在しないものとして扱われる.
+/// 範囲削除を分割して実行する際に、削除途中の状態が観測されないようにするためのもの.
+#[derive(Debug, Default)]
 pub struct LumpIndex {
     // `BTreeMap`の方が`HashMap`よりもメモリ効率が良いので、こちらを採用
     map: BTreeMap<LumpId, PortionU64>,
+    usage: Option<UsageSummary>,
+    hidden: Option<ops::Range<LumpId>>,
 }
 impl LumpIndex {
     /// 新しい`LumpIndex`インスタンスを生成する.
     pub fn new() -> Self {
         LumpIndex {
             map: BTreeMap::new(),
+            usage: None,
+            hidden: None,
         }
     }
 
+    /// 以後のインデックスの更新を`usage`に反映するようにする.
+    ///
+    /// `usage`の内容は、現在のインデックスの内容で初期化される.
+    pub fn attach_usage_summary(&mut self, usage: UsageSummary) {
+        usage.reset(self.entries());
+        self.usage = Some(usage);
+    }
+
     /// 渡された範囲オブジェクトrangeを用いて、
     /// 登録されているlumpのうちrangeに含まれるもののストレージ使用量を返す。
-    pub fn usage_range(&self, range: ops::Range<LumpId>, block_size: BlockSize) -> StorageUsage {
-        StorageUsage::approximate(self.map.range(range).fold(0, |acc, (_, p)| {
+    pub fn usage_range<R>(&self, range: R, block_size: BlockSize) -> StorageUsage
+    where
+        R: ops::RangeBounds<LumpId>,
+    {
+        StorageUsage::approximate(self.visible_range(range).fold(0, |acc, (_, p)| {
             acc + Portion::from(*p).len(block_size) as u64
         }))
     }
 
     /// 指定されたlumpを検索する.
     pub fn get(&self, lump_id: &LumpId) -> Option<Portion> {
+        if self.is_hidden(lump_id) {
+            return None;
+        }
         self.map.get(lump_id).map(|p| (*p).into())
     }
 
     /// 新規lumpを登録する.
     pub fn insert(&mut self, lump_id: LumpId, portion: Portion) {
-        self.map.insert(lump_id, portion.into());
+        let old = self.map.insert(lump_id, portion.into());
+        if let Some(ref usage) = self.usage {
+            if let Some(old) = old {
+                usage.sub(&lump_id, &old.into());
+            }
+            usage.add(&lump_id, &portion);
+        }
     }
 
     /// インデックスのサイズ(i.e., 登録lump数)を返す.
     ///
     /// 結果は昇順にソートされている.
     pub fn remove(&mut self, lump_id: &LumpId) -> Option<Portion> {
-        self.map.remove(lump_id).map(std::convert::Into::into)
+        let portion = self.map.remove(lump_id).map(Portion::from);
+        if let (Some(usage), Some(portion)) = (self.usage.as_ref(), portion.as_ref()) {
+            usage.sub(lump_id, portion);
+        }
+        portion
     }
 
     /// 登録されているlumpのID一覧を返す.
     pub fn list(&self) -> Vec<LumpId> {
-        self.map.keys().cloned().collect()
+        self.visible_range(..).map(|(k, _)| *k).collect()
     }
 
     /// インデックスのサイズ(i.e., 登録lump数)を返す.
+    ///
+    /// 隠されている範囲が存在する場合には、その範囲内の要素数の分だけ計算のコストが増加する.
     pub fn len(&self) -> u64 {
-        self.map.len() as u64
+        let hidden = self
+            .hidden
+            .as_ref()
+            .map_or(0, |range| self.map.range(range.clone()).count());
+        (self.map.len() - hidden) as u64
     }
 
-    /// 割当済みのデータ部分領域を操作するためのイテレータを返す.
-    pub fn data_portions(&self) -> DataPortions {
-        DataPortions(self.map.values())
+    /// 割当済みのデータ部分領域を、その所有者であるlumpのIDと共に走査するためのイテレータを返す.
+    pub fn data_portions(&self) -> DataPortions<'_> {
+        DataPortions {
+            iter: self.map.iter(),
+            hidden: self.hidden.clone(),
+        }
     }
 
     /// 渡された範囲オブジェクトrangeを用いて、
     /// 登録されているlumpのうちrangeに含まれるものの一覧を返す。
-    pub fn list_range(&self, range: ops::Range<LumpId>) -> Vec<LumpId> {
-        let btree_range = self.map.range(range);
-        btree_range.map(|(k, _)| *k).collect()
+    pub fn list_range<R>(&self, range: R) -> Vec<LumpId>
+    where
+        R: ops::RangeBounds<LumpId>,
+    {
+        self.visible_range(range).map(|(k, _)| *k).collect()
+    }
+
+    /// 渡された範囲オブジェクトrangeに含まれるlumpを、インデックスから順に取り除くイテレータを返す.
+    ///
+    /// 削除は遅延的に行われ、イテレータから取り出された要素のみがインデックスから取り除かれる.
+    /// そのため`list_range`とは異なり、範囲内の全IDを事前に列挙する必要がない.
+    ///
+    /// 他の操作とは異なり、隠されている範囲のlumpも対象となる.
+    pub fn drain_range(&mut self, range: ops::Range<LumpId>) -> DrainRange<'_> {
+        DrainRange {
+            map: &mut self.map,
+            usage: self.usage.as_ref(),
+            range,
+        }
+    }
+
+    /// 渡された範囲オブジェクトrangeを用いて、
+    /// 登録されているlumpのうちrangeに含まれるものを、先頭から最大`limit`個返す.
+    pub fn list_range_with_limit<R>(&self, range: R, limit: usize) -> Vec<LumpId>
+    where
+        R: ops::RangeBounds<LumpId>,
+    {
+        self.visible_range(range)
+            .take(limit)
+            .map(|(k, _)| *k)
+            .collect()
+    }
+
+    /// 渡された範囲オブジェクトrangeを用いて、
+    /// 登録されているlumpのうちrangeに含まれるもののIDと格納位置を、先頭から最大`limit`個返す.
+    pub fn entries_with_limit<R>(&self, range: R, limit: usize) -> Vec<(LumpId, Portion)>
+    where
+        R: ops::RangeBounds<LumpId>,
+    {
+        self.visible_range(range)
+            .take(limit)
+            .map(|(k, v)| (*k, (*v).into()))
+            .collect()
+    }
+
+    /// 登録されている全てのlumpのIDと格納位置を、ID順に走査するイテレータを返す.
+    pub fn entries(&self) -> impl Iterator<Item = (LumpId, Portion)> + '_ {
+        self.visible_range(..).map(|(k, v)| (*k, (*v).into()))
+    }
+
+    /// 渡された範囲オブジェクトrangeに含まれるlumpのうち、`n`番目(0始まり)のもののIDを返す.
+    ///
+    /// 該当するlumpが存在しない場合には`None`が返される.
+    pub fn nth_in_range(&self, range: ops::Range<LumpId>, n: usize) -> Option<LumpId> {
+        self.visible_range(range).nth(n).map(|(k, _)| *k)
+    }
+
+    /// `range`に含まれるlumpを、以後`drain_range`以外の操作からは存在しないものとして扱う.
+    ///
+    /// 同時に隠すことができる範囲は一つのみであり、既存の範囲は上書きされる.
+    /// 隠された範囲のlumpは、`drain_range`で取り除かれることが想定されている.
+    pub fn hide_range(&mut self, range: ops::Range<LumpId>) {
+        self.hidden = Some(range);
+    }
+
+    /// `hide_range`で隠した範囲を元に戻す.
+    pub fn unhide_range(&mut self) {
+        self.hidden = None;
+    }
+
+    /// 隠されている範囲が存在するかどうかを判定する.
+    pub fn has_hidden_range(&self) -> bool {
+        self.hidden.is_some()
+    }
+
+    fn is_hidden(&self, lump_id: &LumpId) -> bool {
+        self.hidden
+            .as_ref()
+            .is_some_and(|range| range.contains(lump_id))
+    }
+
+    // 隠されている範囲を除いて、`range`に含まれる要素を走査する.
+    fn visible_range<R>(&self, range: R) -> impl Iterator<Item = (&LumpId, &PortionU64)> + '_
+    where
+        R: ops::RangeBounds<LumpId>,
+    {
+        self.map
+            .range(range)
+            .filter(move |(k, _)| !self.is_hidden(k))
+    }
+}
+
+impl FromIterator<(LumpId, Portion)> for LumpIndex {
+    /// 要素群から一括でインデックスを構築する.
+    ///
+    /// 同じIDが複数回現れた場合には、後のものが優先される.
+    /// 要素群が昇順に並んでいる場合には、個別に`insert`を呼び出すよりも高速に構築できる.
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (LumpId, Portion)>,
+    {
+        LumpIndex {
+            map: iter.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            usage: None,
+            hidden: None,
+        }
+    }
+}
+
+/// 大量の挿入・削除操作を、まとめて`LumpIndex`に反映するためのバッファ.
+///
+/// ジャーナルからの復元時のように、多数の操作を順に適用する場合に使用する.
+/// 操作はいったんバッファに溜められ、`flush`の呼び出し時にIDでソートされた上で、
+/// 同じIDに対する操作は最後のもののみが、インデックスに反映される.
+///
+/// 特にインデックスが空の場合には、`BTreeMap`の一括構築が使われるため、
+/// 操作毎に`LumpIndex::insert`を呼び出す場合に比べて、大幅に高速となる.
+#[derive(Debug, Default)]
+pub struct LumpIndexLoader {
+    // `None`は削除操作を表す
+    pending: Vec<(LumpId, Option<PortionU64>)>,
+}
+impl LumpIndexLoader {
+    /// 最大`capacity`個の操作を、再確保なしで保持可能な`LumpIndexLoader`インスタンスを生成する.
+    pub fn with_capacity(capacity: usize) -> Self {
+        LumpIndexLoader {
+            pending: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// lumpの登録操作を追加する.
+    pub fn insert(&mut self, lump_id: LumpId, portion: Portion) {
+        self.pending.push((lump_id, Some(portion.into())));
+    }
+
+    /// lumpの削除操作を追加する.
+    pub fn remove(&mut self, lump_id: LumpId) {
+        self.pending.push((lump_id, None));
+    }
+
+    /// 溜められている操作群を`index`に反映する.
+    ///
+    /// 呼び出し後には、バッファは空になる(確保済みの領域は再利用される).
+    pub fn flush(&mut self, index: &mut LumpIndex) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        // 安定ソートなので、同じID同士の操作の順番は保たれる
+        self.pending.sort_by_key(|&(lump_id, _)| lump_id);
+        let n = self.pending.len() as u64;
+        let ops = LastOps(self.pending.drain(..).peekable());
+        if index.map.is_empty() {
+            index.map = ops.filter_map(|(k, v)| v.map(|v| (k, v))).collect();
+        } else if n >= index.len() {
+            // 既存のインデックスと同程度以上の操作数なら、マージして再構築した方が速い
+            let old = mem::take(&mut index.map);
+            index.map = Merge {
+                old: old.into_iter().peekable(),
+                ops: ops.peekable(),
+            }
+            .collect();
+        } else {
+            for (lump_id, op) in ops {
+                if let Some(portion) = op {
+                    index.map.insert(lump_id, portion);
+                } else {
+                    index.map.remove(&lump_id);
+                }
+            }
+        }
+        if let Some(ref usage) = index.usage {
+            // 個々の操作を追跡する代わりに、反映後の内容から作り直す
+            usage.reset(index.entries());
+        }
+    }
+}
+
+/// ID順にソートされた操作列から、同じIDに対する最後の操作のみを取り出すイテレータ.
+struct LastOps<I: Iterator>(Peekable<I>);
+impl<I> Iterator for LastOps<I>
+where
+    I: Iterator<Item = (LumpId, Option<PortionU64>)>,
+{
+    type Item = I::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut last = self.0.next()?;
+        while let Some(next) = self.0.next_if(|next| next.0 == last.0) {
+            last = next;
+        }
+        Some(last)
+    }
+}
+
+/// 既存のインデックスの要素列に、ソート済みの操作列を適用した結果を、ID順に返すイテレータ.
+struct Merge<A: Iterator, B: Iterator> {
+    old: Peekable<A>,
+    ops: Peekable<B>,
+}
+impl<A, B> Iterator for Merge<A, B>
+where
+    A: Iterator<Item = (LumpId, PortionU64)>,
+    B: Iterator<Item = (LumpId, Option<PortionU64>)>,
+{
+    type Item = (LumpId, PortionU64);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let take_old = match (self.old.peek(), self.ops.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(a), Some(b)) => a.0 < b.0,
+            };
+            if take_old {
+                return self.old.next();
+            }
+            let (lump_id, op) = self.ops.next().expect("Never fails");
+            self.old.next_if(|a| a.0 == lump_id);
+            if let Some(portion) = op {
+                return Some((lump_id, portion));
+            }
+        }
     }
 }
 
+/// 範囲内のlumpをインデックスから取り除きながら走査するイテレータ.
 #[derive(Debug)]
-pub struct DataPortions<'a>(btree_map::Values<'a, LumpId, PortionU64>);
+pub struct DrainRange<'a> {
+    map: &'a mut BTreeMap<LumpId, PortionU64>,
+    usage: Option<&'a UsageSummary>,
+    range: ops::Range<LumpId>,
+}
+impl<'a> Iterator for DrainRange<'a> {
+    type Item = (LumpId, Portion);
+    fn next(&mut self) -> Option<Self::Item> {
+        let lump_id = *self.map.range(self.range.clone()).next()?.0;
+        let portion = Portion::from(self.map.remove(&lump_id).expect("Never fails"));
+        if let Some(usage) = self.usage {
+            usage.sub(&lump_id, &portion);
+        }
+        self.range.start = lump_id;
+        Some((lump_id, portion))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DataPortions<'a> {
+    iter: btree_map::Iter<'a, LumpId, PortionU64>,
+    hidden: Option<ops::Range<LumpId>>,
+}
 impl<'a> Iterator for DataPortions<'a> {
-    type Item = DataPortion;
+    type Item = (LumpId, DataPortion);
     fn next(&mut self) -> Option<Self::Item> {
-        for &portion in &mut self.0 {
+        for (&lump_id, &portion) in &mut self.iter {
+            if self.hidden.as_ref().is_some_and(|h| h.contains(&lump_id)) {
+                continue;
+            }
             if let Portion::Data(portion) = portion.into() {
-                return Some(portion);
+                return Some((lump_id, portion));
             }
         }
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::portion::JournalPortion;
+    use crate::storage::Address;
+
+    fn id(n: u128) -> LumpId {
+        LumpId::new(n)
+    }
+
+    fn portion(start: u32, len: u16) -> Portion {
+        Portion::Journal(JournalPortion {
+            start: Address::from(start),
+            len,
+        })
+    }
+
+    #[test]
+    fn loader_works() {
+        let ops = |loader: &mut LumpIndexLoader| {
+            loader.insert(id(3), portion(0, 1));
+            loader.insert(id(1), portion(1, 1));
+            loader.remove(id(3));
+            loader.insert(id(2), portion(2, 1));
+            loader.insert(id(1), portion(3, 1));
+            loader.remove(id(4));
+        };
+
+        // 空のインデックスへの反映
+        let mut index = LumpIndex::new();
+        let mut loader = LumpIndexLoader::with_capacity(4);
+        ops(&mut loader);
+        loader.flush(&mut index);
+        assert_eq!(index.list(), vec![id(1), id(2)]);
+        assert_eq!(index.get(&id(1)), Some(portion(3, 1)));
+
+        // 既存のインデックスへのマージ
+        let mut index: LumpIndex = vec![(id(3), portion(9, 9)), (id(4), portion(9, 9))]
+            .into_iter()
+            .collect();
+        ops(&mut loader);
+        loader.flush(&mut index);
+        assert_eq!(index.list(), vec![id(1), id(2)]);
+        assert_eq!(index.get(&id(1)), Some(portion(3, 1)));
+
+        // 既存のインデックスの方が大きい場合の個別反映
+        let mut index: LumpIndex = (0..100).map(|i| (id(i + 3), portion(9, 9))).collect();
+        ops(&mut loader);
+        loader.flush(&mut index);
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.get(&id(1)), Some(portion(3, 1)));
+        assert_eq!(index.get(&id(3)), None);
+        assert_eq!(index.get(&id(4)), None);
+        assert_eq!(index.get(&id(5)), Some(portion(9, 9)));
+    }
+}
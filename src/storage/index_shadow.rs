@@ -0,0 +1,382 @@
+//! インデックスのシャドウファイル.
+//!
+//! `StorageBuilder::index_shadow_file`が指定されている場合には、インデックスとアロケータの空き領域リストの内容が、
+//! ストレージとは別のファイル(シャドウファイル)に定期的に書き出される.
+//!
+//! シャドウファイルには、書き出し時点でのジャーナルの状態(始端位置、終端位置、エポック)も記録され、
+//! 次回のオープン時には、それらが現在のジャーナルの状態と一致する場合に限り、
+//! ジャーナルの再生およびアロケータの再構築を行わずに、その内容から直接状態が復元される.
+//!
+//! クリーンシャットダウン時のチェックポイント(`checkpoint`モジュール)とは異なり、
+//! クローズ以外の時点でも書き出されるため、クラッシュ後であっても、
+//! 最後の書き出し以降にジャーナルへの追記が行われていなければ利用可能となる.
+//!
+//! ファイルが存在しない場合や、内容が壊れている場合、書き出し以降にジャーナルが更新されている場合には、
+//! 従来通りにジャーナルから状態が再構築される.
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::storage::allocator::FreePortion;
+use crate::storage::checkpoint::{self, Checkpoint};
+use crate::storage::index::LumpIndex;
+use crate::{ErrorKind, Result};
+
+/// シャドウファイルの先頭に書き込まれるマジックナンバー.
+const INDEX_SHADOW_MAGIC_NUMBER: [u8; 4] = *b"lshw";
+
+/// シャドウファイルの内容の直前に置かれるヘッダ部分のサイズ.
+const INDEX_SHADOW_HEADER_SIZE: usize = 4 + 16 + 8 + 8 + 2 + 1 + 8 + 4;
+
+/// シャドウファイル書き出し時点でのジャーナルの状態.
+///
+/// オープン時には、これが現在のジャーナルの状態と一致するかどうかで、シャドウファイルの有効性が判定される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexShadowStamp {
+    /// ジャーナルヘッダに記録されているリングバッファの始端位置.
+    pub ring_buffer_head: u64,
+
+    /// リングバッファの終端位置.
+    pub ring_buffer_tail: u64,
+
+    /// 始端位置のエポック.
+    pub epoch: Option<u8>,
+
+    /// クローズ時に書き出されたものかどうか.
+    pub clean_shutdown: bool,
+}
+
+/// シャドウファイルから読み込まれた内容.
+#[derive(Debug)]
+pub struct IndexShadow {
+    /// 書き出し時点でのジャーナルの状態.
+    pub stamp: IndexShadowStamp,
+
+    /// インデックスと空き領域リスト.
+    pub checkpoint: Checkpoint,
+}
+impl IndexShadow {
+    /// `path`からシャドウファイルを読み込む.
+    ///
+    /// 読み込みにはメモリマップが使用される(未対応のプラットフォームでは、通常の読み込みとなる).
+    ///
+    /// ファイルが別のストレージのものである場合には`ErrorKind::InvalidInput`エラーが、
+    /// 内容が壊れている場合には`ErrorKind::StorageCorrupted`エラーが返される.
+    pub fn read_from_file<P: AsRef<Path>>(path: P, instance_uuid: &Uuid) -> Result<Self> {
+        let file = track_io!(File::open(path))?;
+        let bytes = track_io!(map_file(&file))?;
+        track!(Self::decode(&bytes, instance_uuid))
+    }
+
+    fn decode(bytes: &[u8], instance_uuid: &Uuid) -> Result<Self> {
+        track_assert!(
+            bytes.len() >= INDEX_SHADOW_HEADER_SIZE,
+            ErrorKind::StorageCorrupted
+        );
+        let (mut header, body) = bytes.split_at(INDEX_SHADOW_HEADER_SIZE);
+
+        let mut magic_number = [0; 4];
+        track_io!(header.read_exact(&mut magic_number))?;
+        track_assert_eq!(
+            magic_number,
+            INDEX_SHADOW_MAGIC_NUMBER,
+            ErrorKind::StorageCorrupted
+        );
+        let mut uuid = [0; 16];
+        track_io!(header.read_exact(&mut uuid))?;
+        track_assert_eq!(
+            Uuid::from_bytes(uuid),
+            *instance_uuid,
+            ErrorKind::InvalidInput
+        );
+
+        let ring_buffer_head = track_io!(header.read_u64::<BigEndian>())?;
+        let ring_buffer_tail = track_io!(header.read_u64::<BigEndian>())?;
+        let epoch = match track_io!(header.read_u8())? {
+            0 => {
+                track_io!(header.read_u8())?;
+                None
+            }
+            1 => Some(track_io!(header.read_u8())?),
+            flag => track_panic!(ErrorKind::StorageCorrupted, "Unknown epoch flag: {}", flag),
+        };
+        let clean_shutdown = track_io!(header.read_u8())? != 0;
+        let size = track_io!(header.read_u64::<BigEndian>())?;
+        let checksum = track_io!(header.read_u32::<BigEndian>())?;
+        track_assert_eq!(size, body.len() as u64, ErrorKind::StorageCorrupted);
+        track_assert_eq!(
+            checkpoint::checksum(body),
+            checksum,
+            ErrorKind::StorageCorrupted
+        );
+
+        let checkpoint = track!(Checkpoint::decode(body))?;
+        Ok(IndexShadow {
+            stamp: IndexShadowStamp {
+                ring_buffer_head,
+                ring_buffer_tail,
+                epoch,
+                clean_shutdown,
+            },
+            checkpoint,
+        })
+    }
+
+    /// インデックスと空き領域リストの内容を、シャドウファイルとして`path`に書き出す.
+    ///
+    /// 書き込み途中のファイルが読み込まれることがないように、
+    /// 一時ファイルに書き込んで同期した上で、`path`にリネームされる.
+    pub fn write_to_file<I>(
+        path: &Path,
+        instance_uuid: &Uuid,
+        stamp: &IndexShadowStamp,
+        index: &LumpIndex,
+        free_portions: I,
+    ) -> Result<()>
+    where
+        I: ExactSizeIterator<Item = FreePortion>,
+    {
+        let body = Checkpoint::encode(index, free_portions);
+        let mut buf = Vec::with_capacity(INDEX_SHADOW_HEADER_SIZE + body.len());
+        buf.extend_from_slice(&INDEX_SHADOW_MAGIC_NUMBER);
+        buf.extend_from_slice(instance_uuid.as_bytes());
+        buf.write_u64::<BigEndian>(stamp.ring_buffer_head)
+            .expect("Never fails");
+        buf.write_u64::<BigEndian>(stamp.ring_buffer_tail)
+            .expect("Never fails");
+        buf.write_u8(stamp.epoch.is_some() as u8)
+            .expect("Never fails");
+        buf.write_u8(stamp.epoch.unwrap_or(0)).expect("Never fails");
+        buf.write_u8(stamp.clean_shutdown as u8)
+            .expect("Never fails");
+        buf.write_u64::<BigEndian>(body.len() as u64)
+            .expect("Never fails");
+        buf.write_u32::<BigEndian>(checkpoint::checksum(&body))
+            .expect("Never fails");
+        buf.extend_from_slice(&body);
+
+        let temp_path = temp_path(path);
+        let mut file = track_io!(File::create(&temp_path))?;
+        track_io!(file.write_all(&buf))?;
+        track_io!(file.sync_all())?;
+        track_io!(fs::rename(&temp_path, path))?;
+        Ok(())
+    }
+
+    /// `path`に存在するシャドウファイルを削除する.
+    ///
+    /// ファイルが存在しない場合には何も行わない.
+    pub fn remove_file(path: &Path) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => track_io!(result),
+        }
+    }
+}
+
+/// シャドウファイルの書き出しを管理するための構造体.
+#[derive(Debug)]
+pub struct IndexShadowWriter {
+    path: PathBuf,
+    interval: Duration,
+    last_written_at: Instant,
+    last_position: Option<(u64, u64)>,
+}
+impl IndexShadowWriter {
+    /// 新しい`IndexShadowWriter`インスタンスを生成する.
+    ///
+    /// 最初の書き出しも、生成時点から`interval`が経過するまでは行われない.
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        IndexShadowWriter {
+            path,
+            interval,
+            last_written_at: Instant::now(),
+            last_position: None,
+        }
+    }
+
+    /// シャドウファイルのパスを返す.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// シャドウファイルを更新すべきかどうかを判定する.
+    ///
+    /// `position`は、ジャーナルのリングバッファの現在の始端位置と終端位置.
+    /// 前回の書き出し以降にそれらが変化していて、かつ、書き出しの間隔が経過している場合に`true`となる.
+    pub fn is_due(&self, position: (u64, u64)) -> bool {
+        self.last_position != Some(position) && self.last_written_at.elapsed() >= self.interval
+    }
+
+    /// シャドウファイルが書き出されたことを記録する.
+    pub fn written(&mut self, position: (u64, u64)) {
+        self.last_written_at = Instant::now();
+        self.last_position = Some(position);
+    }
+}
+
+/// 書き出し途中の内容を保持する一時ファイルのパスを返す.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+#[cfg(unix)]
+fn map_file(file: &File) -> io::Result<MappedFile> {
+    MappedFile::new(file)
+}
+
+#[cfg(not(unix))]
+fn map_file(mut file: &File) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// 読み込み専用でメモリマップされたファイル.
+///
+/// マップ中にファイルが切り詰められることは想定していない.
+#[cfg(unix)]
+struct MappedFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+#[cfg(unix)]
+impl MappedFile {
+    fn new(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // 長さ`0`のマップは作成できない
+            return Ok(MappedFile {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(MappedFile { ptr, len })
+    }
+}
+#[cfg(unix)]
+impl std::ops::Deref for MappedFile {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::lump::LumpId;
+    use crate::storage::portion::{DataPortion, Portion};
+    use crate::storage::Address;
+
+    fn stamp() -> IndexShadowStamp {
+        IndexShadowStamp {
+            ring_buffer_head: 512,
+            ring_buffer_tail: 4096,
+            epoch: Some(3),
+            clean_shutdown: false,
+        }
+    }
+
+    #[test]
+    fn write_and_read_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("index.shadow");
+        let uuid = Uuid::new_v4();
+
+        let mut index = LumpIndex::new();
+        let portion = DataPortion {
+            start: Address::from(10),
+            len: 2,
+        };
+        index.insert(LumpId::new(1), Portion::Data(portion));
+        let free = vec![FreePortion::new(Address::from(0), 10)];
+        track!(IndexShadow::write_to_file(
+            &path,
+            &uuid,
+            &stamp(),
+            &index,
+            free.into_iter()
+        ))?;
+        assert!(!temp_path(&path).exists());
+
+        let shadow = track!(IndexShadow::read_from_file(&path, &uuid))?;
+        assert_eq!(shadow.stamp, stamp());
+        assert_eq!(
+            shadow.checkpoint.index.get(&LumpId::new(1)),
+            Some(Portion::Data(portion))
+        );
+        assert_eq!(shadow.checkpoint.free_portions.len(), 1);
+
+        // 別のストレージのものとしては読み込めない
+        let e = IndexShadow::read_from_file(&path, &Uuid::new_v4()).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_shadow_is_rejected() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("index.shadow");
+        let uuid = Uuid::new_v4();
+        track!(IndexShadow::write_to_file(
+            &path,
+            &uuid,
+            &stamp(),
+            &LumpIndex::new(),
+            Vec::new().into_iter()
+        ))?;
+
+        let mut bytes = track_io!(fs::read(&path))?;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        track_io!(fs::write(&path, &bytes))?;
+        let e = IndexShadow::read_from_file(&path, &uuid).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::StorageCorrupted));
+
+        track_io!(fs::write(&path, b""))?;
+        assert!(IndexShadow::read_from_file(&path, &uuid).is_err());
+
+        track!(IndexShadow::remove_file(&path))?;
+        assert!(IndexShadow::read_from_file(&path, &uuid).is_err());
+        track!(IndexShadow::remove_file(&path))?;
+        Ok(())
+    }
+}
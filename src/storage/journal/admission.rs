@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+/// ジャーナル領域の使用量が閾値を超え、かつ、GCが追記に追いついていない場合の対処方法.
+///
+/// 詳細は`StorageBuilder::journal_admission_control`を参照のこと.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum JournalAdmissionAction {
+    /// PUTを`ErrorKind::JournalGcLagging`エラーで失敗させる.
+    Reject,
+
+    /// PUTの前に、ジャーナル領域全体のGC(`Storage::journal_gc`)を同期的に実行する.
+    ForceGc,
+}
+
+/// 書き込みの受付制御用のパラメータ.
+///
+/// 詳細は`StorageBuilder::journal_admission_control`を参照のこと.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdmissionControlOptions {
+    pub high_watermark: f64,
+    pub window: Duration,
+    pub action: JournalAdmissionAction,
+}
+
+/// ジャーナル領域のGCが追記に追いついているかどうかに基づいて、新たな書き込みを制限するためのコントローラ.
+///
+/// 時間窓(`window`)毎に、GCの負債(`JournalRegionMetrics::gc_debt_bytes`)の増減を調べ、
+/// 増えていた場合(i.e., GCによる回収量が追記量を下回った場合)には、GCが遅れているものと判定する.
+/// 判定結果は次の時間窓の終わりまで維持される.
+///
+/// GCが遅れていて、かつ、ジャーナル領域の使用率が`high_watermark`を超えている間は、書き込みが制限される.
+#[derive(Debug, Clone)]
+pub struct AdmissionController {
+    options: AdmissionControlOptions,
+    window_start: Option<(Instant, i64)>,
+    lagging: bool,
+}
+impl AdmissionController {
+    /// 新しい`AdmissionController`インスタンスを生成する.
+    pub fn new(options: AdmissionControlOptions) -> Self {
+        AdmissionController {
+            options,
+            window_start: None,
+            lagging: false,
+        }
+    }
+
+    /// 制限時の対処方法を返す.
+    pub fn action(&self) -> JournalAdmissionAction {
+        self.options.action
+    }
+
+    /// `now`時点でのジャーナル領域の使用量とGCの負債を観測し、新たな書き込みを制限すべきかどうかを返す.
+    pub fn should_throttle(
+        &mut self,
+        now: Instant,
+        usage: u64,
+        capacity: u64,
+        gc_debt: i64,
+    ) -> bool {
+        match self.window_start {
+            None => self.window_start = Some((now, gc_debt)),
+            Some((start, start_debt)) if now.duration_since(start) >= self.options.window => {
+                self.lagging = gc_debt > start_debt;
+                self.window_start = Some((now, gc_debt));
+            }
+            Some(_) => {}
+        }
+        self.lagging && usage as f64 > capacity as f64 * self.options.high_watermark
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admission_controller_works() {
+        let window = Duration::from_secs(10);
+        let mut c = AdmissionController::new(AdmissionControlOptions {
+            high_watermark: 0.5,
+            window,
+            action: JournalAdmissionAction::Reject,
+        });
+        let t = Instant::now();
+
+        // 最初の時間窓が終わるまでは判定できない
+        assert!(!c.should_throttle(t, 90, 100, 0));
+        assert!(!c.should_throttle(t + window / 2, 90, 100, 1000));
+
+        // 負債が増えたので、使用率が閾値を超えている間は制限される
+        assert!(c.should_throttle(t + window, 90, 100, 1000));
+        assert!(!c.should_throttle(t + window, 40, 100, 1000));
+        assert!(c.should_throttle(t + window * 3 / 2, 90, 100, 2000));
+
+        // 負債が減ったので、制限は解除される
+        assert!(!c.should_throttle(t + window * 2, 90, 100, 500));
+        assert_eq!(c.action(), JournalAdmissionAction::Reject);
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{BufReader, Seek, SeekFrom};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+use super::{JournalChecksum, JournalEntry, JournalNvmBuffer, JournalRecord};
+use crate::nvm::NonVolatileMemory;
+use crate::storage::Address;
+use crate::{ErrorKind, Result};
+
+/// GC対象となるジャーナルエントリ群の読み込みを、専用のスレッドで行うためのオブジェクト.
+///
+/// エントリ群の読み込みおよびデコードのみを別スレッドで行い、
+/// 回収可否の判定や再配置(i.e., 末尾への追記)は、従来通りに呼び出し元のスレッドで行われる.
+///
+/// 読み込み用スレッドからは、NVMに書き出し済みのデータのみが参照可能なので、
+/// 走査を要求する前には、対象範囲がフラッシュされている必要がある.
+#[derive(Debug)]
+pub struct GcScanner {
+    request_tx: Option<Sender<ScanRequest>>,
+    result_rx: Receiver<Result<ScanResult>>,
+    thread: Option<JoinHandle<()>>,
+    scanning: bool,
+}
+impl GcScanner {
+    /// 読み込み用のスレッドを起動する.
+    ///
+    /// `nvm`には、ジャーナル領域のリングバッファ部分を参照する読み込み用のインスタンスを渡す必要がある.
+    /// `checksum`は、レコードのチェックサムの検証に用いるアルゴリズム.
+    pub fn spawn<N>(mut nvm: JournalNvmBuffer<N>, checksum: JournalChecksum) -> Self
+    where
+        N: NonVolatileMemory + Send + 'static,
+    {
+        let (request_tx, request_rx) = mpsc::channel::<ScanRequest>();
+        let (result_tx, result_rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Ok(request) = request_rx.recv() {
+                let result = track!(scan(&mut nvm, &request, checksum));
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        GcScanner {
+            request_tx: Some(request_tx),
+            result_rx,
+            thread: Some(thread),
+            scanning: false,
+        }
+    }
+
+    /// 走査中(i.e., 結果の受け取り待ち)かどうかを判定する.
+    pub fn is_scanning(&self) -> bool {
+        self.scanning
+    }
+
+    /// リングバッファの`head`から`tail`までの範囲の走査を要求する.
+    ///
+    /// 一度の走査で読み込まれるエントリの数は、最大で`max_entries`となる.
+    ///
+    /// `epoch`は`head`の位置のレコードのエポック.
+    pub fn request(
+        &mut self,
+        head: u64,
+        tail: u64,
+        epoch: Option<u8>,
+        max_entries: usize,
+    ) -> Result<()> {
+        track_assert!(!self.scanning, ErrorKind::InconsistentState);
+        let request = ScanRequest {
+            head,
+            tail,
+            epoch,
+            max_entries,
+        };
+        let request_tx = self.request_tx.as_ref().expect("Never fails");
+        track_assert!(
+            request_tx.send(request).is_ok(),
+            ErrorKind::Other,
+            "GC scanner thread has terminated"
+        );
+        self.scanning = true;
+        Ok(())
+    }
+
+    /// 走査結果が得られていればそれを返す.
+    ///
+    /// まだ走査が完了していない場合には`None`が返される.
+    pub fn try_recv(&mut self) -> Result<Option<ScanResult>> {
+        track_assert!(self.scanning, ErrorKind::InconsistentState);
+        match self.result_rx.try_recv() {
+            Ok(result) => {
+                self.scanning = false;
+                track!(result).map(Some)
+            }
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => {
+                track_panic!(ErrorKind::Other, "GC scanner thread has terminated")
+            }
+        }
+    }
+
+    /// 走査が完了するまで待機して、その結果を返す.
+    pub fn recv(&mut self) -> Result<ScanResult> {
+        track_assert!(self.scanning, ErrorKind::InconsistentState);
+        let result = track_assert_some!(
+            self.result_rx.recv().ok(),
+            ErrorKind::Other,
+            "GC scanner thread has terminated"
+        );
+        self.scanning = false;
+        track!(result)
+    }
+}
+impl Drop for GcScanner {
+    fn drop(&mut self) {
+        // 要求用のチャンネルを閉じてスレッドを停止させる.
+        //
+        // スレッドが保持しているNVM(e.g., ファイル)が確実に解放されるように、終了を待機する.
+        self.request_tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// 走査結果.
+#[derive(Debug)]
+pub struct ScanResult {
+    /// 読み込まれたエントリ群.
+    pub entries: Vec<JournalEntry>,
+
+    /// 走査を終えた位置.
+    ///
+    /// 次回の走査はこの位置から開始される.
+    pub next_head: u64,
+}
+
+#[derive(Debug)]
+struct ScanRequest {
+    head: u64,
+    tail: u64,
+    epoch: Option<u8>,
+    max_entries: usize,
+}
+
+fn scan<N>(
+    nvm: &mut JournalNvmBuffer<N>,
+    request: &ScanRequest,
+    checksum: JournalChecksum,
+) -> Result<ScanResult>
+where
+    N: NonVolatileMemory,
+{
+    track_io!(nvm.seek(SeekFrom::Start(request.head)))?;
+    let mut reader = BufReader::new(nvm);
+    let mut current = request.head;
+    let mut epoch = request.epoch;
+    let mut is_second_lap = false;
+    let mut entries = Vec::new();
+
+    // `tail`以降の領域は、走査中にも書き込みが行われうるので、決して読み込まないようにする
+    while current != request.tail && entries.len() < request.max_entries {
+        match track!(JournalRecord::read_from_with(&mut reader, epoch, checksum))? {
+            JournalRecord::EndOfRecords => track_panic!(
+                ErrorKind::InconsistentState,
+                "Unexpected end of records: position={}, tail={}",
+                current,
+                request.tail
+            ),
+            JournalRecord::GoToFront => {
+                track_assert!(!is_second_lap, ErrorKind::StorageCorrupted);
+                track_io!(reader.seek(SeekFrom::Start(0)))?;
+                current = 0;
+                epoch = epoch.map(|e| e.wrapping_add(1));
+                is_second_lap = true;
+            }
+            record => {
+                let start =
+                    track_assert_some!(Address::from_u64(current), ErrorKind::InconsistentState);
+                current += record.external_size() as u64;
+                entries.push(JournalEntry { start, record });
+            }
+        }
+    }
+    Ok(ScanResult {
+        entries,
+        next_head: current,
+    })
+}
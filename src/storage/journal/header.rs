@@ -1,50 +1,183 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, SeekFrom, Write};
 
-use crate::Result;
+use crate::{ErrorKind, Result};
 
 use crate::block::{AlignedBytes, BlockSize};
 use crate::nvm::NonVolatileMemory;
+use crate::storage::checkpoint::CheckpointLocation;
 
 /// ジャーナルのヘッダ.
 #[derive(Debug, PartialEq, Eq)]
 pub struct JournalHeader {
     /// ジャーナルのリングバッファの始端位置.
     pub ring_buffer_head: u64,
+
+    /// クリーンシャットダウン時に書き出されたチェックポイントの位置.
+    ///
+    /// 古いバージョンで書き込まれたヘッダでは、この部分はゼロ埋めのパディングとなっているため`None`として扱われる.
+    pub checkpoint: Option<CheckpointLocation>,
+
+    /// リングバッファの始端位置のレコードが書き込まれた周回の番号(エポック).
+    ///
+    /// 各レコードのチェックサムには、それが書き込まれた周回のエポックが混ぜ込まれており、
+    /// リングバッファが一周する度にエポックは一つ進められる.
+    /// これにより、前の周回の(古い)レコードを誤って有効なものとして読み込んでしまうことが防止される.
+    ///
+    /// エポック導入以前のバージョンで書き込まれたヘッダでは`None`となる.
+    pub epoch: Option<u8>,
 }
 impl JournalHeader {
     /// ストレージ初期化時のヘッダを生成する.
     pub fn new() -> Self {
         JournalHeader {
             ring_buffer_head: 0,
+            checkpoint: None,
+            epoch: Some(0),
         }
     }
 
     /// ヘッダを書き込む.
     pub fn write_to<W: Write>(&self, mut writer: W, block_size: BlockSize) -> Result<()> {
-        let padding = vec![0; JournalHeader::region_size(block_size) - 8];
-        track_io!(writer.write_u64::<BigEndian>(self.ring_buffer_head))?;
+        track!(self.write_fields_to(&mut writer))?;
+        let padding = vec![0; JournalHeader::region_size(block_size) - Self::USED_SIZE];
+        track_io!(writer.write_all(&padding))?;
+        Ok(())
+    }
+
+    /// ストレージの初期化用に、`slots`個分のヘッダ領域全体を書き込む.
+    ///
+    /// スロットが複数ある場合には、全てのスロットに同じ内容のヘッダが書き込まれる.
+    pub fn write_region_to<W: Write>(
+        &self,
+        mut writer: W,
+        block_size: BlockSize,
+        slots: u8,
+    ) -> Result<()> {
+        if slots == 1 {
+            track!(self.write_to(&mut writer, block_size))?;
+        } else {
+            for sequence in 0..u64::from(slots) {
+                track!(self.write_slot_to(&mut writer, block_size, sequence))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// シーケンス番号とチェックサムを付与して、スロット形式でヘッダを書き込む.
+    pub fn write_slot_to<W: Write>(
+        &self,
+        mut writer: W,
+        block_size: BlockSize,
+        sequence: u64,
+    ) -> Result<()> {
+        let mut buf = Vec::with_capacity(Self::SLOT_USED_SIZE);
+        track!(self.write_fields_to(&mut buf))?;
+        track_io!(buf.write_u64::<BigEndian>(sequence))?;
+        let checksum = crc32c::crc32c(&buf);
+        track_io!(buf.write_u32::<BigEndian>(checksum))?;
+        track_io!(writer.write_all(&buf))?;
+
+        let padding = vec![0; JournalHeader::region_size(block_size) - Self::SLOT_USED_SIZE];
         track_io!(writer.write_all(&padding))?;
         Ok(())
     }
 
+    /// スロット形式で書き込まれたヘッダを読み込む.
+    ///
+    /// チェックサムが一致しない(e.g., 書き込み途中でクラッシュした)場合には`None`が返される.
+    /// 成功した場合には、ヘッダとそのシーケンス番号が返される.
+    pub fn read_slot_from<R: Read>(
+        mut reader: R,
+        block_size: BlockSize,
+    ) -> Result<Option<(Self, u64)>> {
+        let mut buf = vec![0; JournalHeader::region_size(block_size)];
+        track_io!(reader.read_exact(&mut buf))?;
+
+        let checksum_offset = Self::USED_SIZE + 8;
+        let mut checksum = &buf[checksum_offset..][..4];
+        let checksum = track_io!(checksum.read_u32::<BigEndian>())?;
+        if crc32c::crc32c(&buf[..checksum_offset]) != checksum {
+            return Ok(None);
+        }
+
+        let mut reader = &buf[..checksum_offset];
+        let header = track!(Self::read_fields_from(&mut reader))?;
+        let sequence = track_io!(reader.read_u64::<BigEndian>())?;
+        Ok(Some((header, sequence)))
+    }
+
+    fn write_fields_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_io!(writer.write_u64::<BigEndian>(self.ring_buffer_head))?;
+        if let Some(ref checkpoint) = self.checkpoint {
+            track_io!(writer.write_u8(1))?;
+            track!(checkpoint.write_to(&mut writer))?;
+        } else {
+            track_io!(writer.write_all(&[0; 1 + CheckpointLocation::SIZE]))?;
+        }
+        if let Some(epoch) = self.epoch {
+            track_io!(writer.write_u8(1))?;
+            track_io!(writer.write_u8(epoch))?;
+        } else {
+            track_io!(writer.write_all(&[0; 2]))?;
+        }
+        Ok(())
+    }
+
     /// ヘッダを読み込む.
     pub fn read_from<R: Read>(mut reader: R, block_size: BlockSize) -> Result<Self> {
-        let mut padding = vec![0; JournalHeader::region_size(block_size) - 8];
-        let ring_buffer_head = track_io!(reader.read_u64::<BigEndian>())?;
+        let header = track!(Self::read_fields_from(&mut reader))?;
+        let mut padding = vec![0; JournalHeader::region_size(block_size) - Self::USED_SIZE];
         track_io!(reader.read_exact(&mut padding))?;
-        Ok(JournalHeader { ring_buffer_head })
+        Ok(header)
+    }
+
+    fn read_fields_from<R: Read>(mut reader: R) -> Result<Self> {
+        let ring_buffer_head = track_io!(reader.read_u64::<BigEndian>())?;
+        let has_checkpoint = track_io!(reader.read_u8())? == 1;
+        let checkpoint = track!(CheckpointLocation::read_from(&mut reader))?;
+        let checkpoint = if has_checkpoint {
+            Some(checkpoint)
+        } else {
+            None
+        };
+        let has_epoch = track_io!(reader.read_u8())? == 1;
+        let epoch = track_io!(reader.read_u8())?;
+        let epoch = if has_epoch { Some(epoch) } else { None };
+        Ok(JournalHeader {
+            ring_buffer_head,
+            checkpoint,
+            epoch,
+        })
     }
 
-    /// ヘッダ領域のサイズ（バイト数）.
+    /// ヘッダの中で、パディング以外に使用されている部分のサイズ（バイト数）.
+    const USED_SIZE: usize = 8 + (1 + CheckpointLocation::SIZE) + (1 + 1);
+
+    /// スロット形式のヘッダの中で、パディング以外に使用されている部分のサイズ（バイト数）.
+    ///
+    /// ヘッダの後ろに、シーケンス番号(8バイト)とチェックサム(4バイト)が付与される.
+    const SLOT_USED_SIZE: usize = Self::USED_SIZE + 8 + 4;
+
+    /// ヘッダ(ないしその一つのスロット)のサイズ（バイト数）.
     pub fn region_size(block_size: BlockSize) -> usize {
         block_size.as_u16() as usize
     }
+
+    /// `slots`個のスロットから成るヘッダ領域全体のサイズ（バイト数）.
+    pub fn slots_region_size(block_size: BlockSize, slots: u8) -> u64 {
+        Self::region_size(block_size) as u64 * u64::from(slots)
+    }
 }
 
 /// ジャーナルのヘッダ領域.
 ///
-/// 先頭の一ブロックがヘッダ用に割り当てられる.
+/// スロット数が`1`の場合には、先頭の一ブロックがヘッダ用に割り当てられる.
+///
+/// スロット数が`2`の場合には、先頭の二ブロックがヘッダ用に割り当てられ、
+/// ヘッダはシーケンス番号とチェックサムを付与された上で、二つのブロック(スロット)に交互に書き込まれる.
+/// 読み込み時には、チェックサムが正しいものの内で、シーケンス番号が最大のものが使用されるため、
+/// 書き込み途中でクラッシュしたとしても、一つ前のヘッダから復元が可能となる.
 #[derive(Debug)]
 pub struct JournalHeaderRegion<N> {
     /// ヘッダ用の領域.
@@ -52,33 +185,75 @@ pub struct JournalHeaderRegion<N> {
 
     /// ストレージが採用しているブロックサイズ.
     block_size: BlockSize,
+
+    /// ヘッダ用のスロットの数.
+    slots: u8,
+
+    /// 最後に読み書きしたヘッダのシーケンス番号.
+    sequence: u64,
 }
 impl<N: NonVolatileMemory> JournalHeaderRegion<N> {
     /// ヘッダ領域管理用のインスタンスを生成する.
-    pub fn new(nvm: N, block_size: BlockSize) -> Self {
-        JournalHeaderRegion { nvm, block_size }
+    pub fn new(nvm: N, block_size: BlockSize, slots: u8) -> Self {
+        JournalHeaderRegion {
+            nvm,
+            block_size,
+            slots,
+            sequence: 0,
+        }
     }
 
     /// ヘッダを書き込む.
     pub fn write_header(&mut self, header: &JournalHeader) -> Result<()> {
-        let mut buf =
-            AlignedBytes::new(JournalHeader::region_size(self.block_size), self.block_size);
-        track!(header.write_to(&mut buf[..], self.block_size))?;
+        let slot_size = JournalHeader::region_size(self.block_size);
+        let mut buf = AlignedBytes::new(slot_size, self.block_size);
+        let offset = if self.slots == 1 {
+            track!(header.write_to(&mut buf[..], self.block_size))?;
+            0
+        } else {
+            let sequence = self.sequence + 1;
+            track!(header.write_slot_to(&mut buf[..], self.block_size, sequence))?;
+            self.sequence = sequence;
+            (sequence % u64::from(self.slots)) * slot_size as u64
+        };
 
-        track_io!(self.nvm.seek(SeekFrom::Start(0)))?;
+        track_io!(self.nvm.seek(SeekFrom::Start(offset)))?;
         track_io!(self.nvm.write_all(&buf))?;
         track!(self.nvm.sync())?;
         Ok(())
     }
 
     /// ヘッダを読み込む.
+    ///
+    /// スロットが複数ある場合に、有効なヘッダが一つも存在しなかった場合には、
+    /// `ErrorKind::StorageCorrupted`エラーが返される.
     pub fn read_header(&mut self) -> Result<JournalHeader> {
-        let mut buf =
-            AlignedBytes::new(JournalHeader::region_size(self.block_size), self.block_size);
+        let slot_size = JournalHeader::region_size(self.block_size);
+        let mut buf = AlignedBytes::new(slot_size * self.slots as usize, self.block_size);
         track_io!(self.nvm.seek(SeekFrom::Start(0)))?;
         track_io!(self.nvm.read_exact(&mut buf))?;
 
-        let header = track!(JournalHeader::read_from(&buf[..], self.block_size))?;
+        if self.slots == 1 {
+            let header = track!(JournalHeader::read_from(&buf[..], self.block_size))?;
+            return Ok(header);
+        }
+
+        let mut latest = None;
+        for slot in buf.chunks(slot_size) {
+            if let Some((header, sequence)) =
+                track!(JournalHeader::read_slot_from(slot, self.block_size))?
+            {
+                if latest.as_ref().is_none_or(|&(_, s)| s < sequence) {
+                    latest = Some((header, sequence));
+                }
+            }
+        }
+        let (header, sequence) = track_assert_some!(
+            latest,
+            ErrorKind::StorageCorrupted,
+            "No valid journal header slot"
+        );
+        self.sequence = sequence;
         Ok(header)
     }
 }
@@ -89,20 +264,93 @@ mod tests {
 
     use super::*;
     use crate::block::BlockSize;
+    use crate::nvm::MemoryNvm;
 
     #[test]
     fn it_works() -> TestResult {
         let block_size = BlockSize::min();
         let header = JournalHeader {
             ring_buffer_head: 1234,
+            checkpoint: None,
+            epoch: None,
+        };
+
+        let mut buf = Vec::new();
+        track!(header.write_to(&mut buf, block_size))?;
+        assert_eq!(
+            JournalHeader::read_from(&buf[..], block_size).ok(),
+            Some(header)
+        );
+
+        let header = JournalHeader {
+            ring_buffer_head: 1234,
+            checkpoint: Some(CheckpointLocation {
+                ring_buffer_tail: 5678,
+                offset: 512,
+                size: 100,
+                checksum: 0xABCD,
+            }),
+            epoch: Some(3),
         };
 
         let mut buf = Vec::new();
         track!(header.write_to(&mut buf, block_size))?;
+        assert_eq!(buf.len(), JournalHeader::region_size(block_size));
         assert_eq!(
             JournalHeader::read_from(&buf[..], block_size).ok(),
             Some(header)
         );
         Ok(())
     }
+
+    #[test]
+    fn header_slots_work() -> TestResult {
+        let block_size = BlockSize::min();
+        let slot_size = JournalHeader::region_size(block_size);
+        let header = |head| JournalHeader {
+            ring_buffer_head: head,
+            checkpoint: None,
+            epoch: Some(0),
+        };
+
+        let mut buf = Vec::new();
+        track!(header(0).write_region_to(&mut buf, block_size, 2))?;
+        assert_eq!(
+            buf.len() as u64,
+            JournalHeader::slots_region_size(block_size, 2)
+        );
+
+        // 書き込みは二つのスロットに交互に行われ、読み込み時には最新のものが使われる
+        let mut region = JournalHeaderRegion::new(MemoryNvm::new(buf), block_size, 2);
+        assert_eq!(track!(region.read_header())?, header(0));
+        track!(region.write_header(&header(10)))?;
+        track!(region.write_header(&header(20)))?;
+        track!(region.write_header(&header(30)))?;
+        assert_eq!(track!(region.read_header())?, header(30));
+
+        let bytes = region.nvm.as_bytes().to_owned();
+        let mut region = JournalHeaderRegion::new(MemoryNvm::new(bytes.clone()), block_size, 2);
+        assert_eq!(track!(region.read_header())?, header(30));
+
+        // 最新のスロットが壊れている場合には、一つ前のヘッダが使われる
+        // (初期化時のシーケンス番号は`0`と`1`なので、最新のヘッダ(シーケンス番号は`4`)は一つ目のスロットにある)
+        let latest = 0;
+        let mut corrupted = bytes.clone();
+        corrupted[latest] ^= 0xFF;
+        let mut region = JournalHeaderRegion::new(MemoryNvm::new(corrupted.clone()), block_size, 2);
+        assert_eq!(track!(region.read_header())?, header(20));
+
+        // 続く書き込みは、壊れたスロットに対して行われる
+        track!(region.write_header(&header(40)))?;
+        assert_eq!(track!(region.read_header())?, header(40));
+
+        // 全てのスロットが壊れている場合には、エラーとなる
+        corrupted[slot_size] ^= 0xFF;
+        let mut region = JournalHeaderRegion::new(MemoryNvm::new(corrupted), block_size, 2);
+        assert_eq!(
+            region.read_header().err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+        Ok(())
+    }
 }
@@ -1,15 +1,50 @@
+pub use self::admission::{AdmissionControlOptions, JournalAdmissionAction};
 pub use self::header::{JournalHeader, JournalHeaderRegion};
 pub use self::nvm_buffer::JournalNvmBuffer;
 pub use self::options::JournalRegionOptions;
-pub use self::record::{JournalEntry, JournalRecord};
-pub use self::region::JournalRegion;
+pub use self::record::{AuditOperation, AuditRecord, JournalChecksum, JournalEntry, JournalRecord};
+pub use self::region::{FullGc, JournalRegion};
+pub use self::sync_controller::AdaptiveSyncOptions;
 
+pub(crate) use self::record::{
+    DedupPutRecord, EmbeddedRenameRecord, InPlacePutRecord, LinkRecord, RenameRecord,
+};
+
+mod admission;
+mod gc_scanner;
 mod header;
 mod nvm_buffer;
 mod options;
 mod record;
 mod region;
 mod ring_buffer;
+mod sync_controller;
+
+/// ジャーナルに拡張レコードが書き込まれるようになったマイナーバージョン.
+pub(crate) const EXTENSION_RECORDS_MINOR_VERSION: u16 = 2;
+
+/// ジャーナルのレコードのチェックサムにエポックが混ぜ込まれるようになったマイナーバージョン.
+pub(crate) const EPOCH_MINOR_VERSION: u16 = 3;
+
+/// ジャーナルエントリ群を分割して読み込む際の、読み込み位置を表すカーソル.
+///
+/// `Storage::journal_snapshot_step`で使用される.
+/// `JournalCursor::default()`はジャーナルの始端位置を指す.
+///
+/// カーソルは、それを生成した`Storage`インスタンス内でのみ有効.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JournalCursor {
+    position: Option<u64>,
+    lap: u64,
+}
+impl JournalCursor {
+    /// カーソルが指しているリングバッファ内の位置を返す.
+    ///
+    /// 始端位置を指すカーソルの場合には`None`が返される.
+    pub fn position(&self) -> Option<u64> {
+        self.position
+    }
+}
 
 /// ジャーナル領域のスナップショット。
 pub struct JournalSnapshot {
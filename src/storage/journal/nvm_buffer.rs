@@ -1,9 +1,12 @@
 use std::cmp;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::ptr;
+use std::time::SystemTime;
 
 use crate::block::{AlignedBytes, BlockSize};
-use crate::nvm::NonVolatileMemory;
+use crate::metrics::{IoOrigin, NvmIoCounter};
+use crate::nvm::{AccessPattern, NonVolatileMemory};
 use crate::{ErrorKind, Result};
 
 /// ジャーナル領域用のバッファ.
@@ -54,6 +57,46 @@ pub struct JournalNvmBuffer<N: NonVolatileMemory> {
     // ジャーナル領域が発行した読み込み要求を、
     // 内部NVMのブロック境界に合うようにアライメントするために使用される。
     read_buf: AlignedBytes,
+
+    // 前回の同期(`sync`)以降に書き込まれたバイト数
+    //
+    // 書き込みバッファからフラッシュ済みであっても、同期命令が発行されるまでは未同期として扱う
+    unsynced_bytes: u64,
+
+    // 前回の同期以降で、最初に書き込みが行われた時刻
+    oldest_unsynced_write: Option<SystemTime>,
+
+    // 書き込みバッファのサイズの上限
+    //
+    // 書き込みによってバッファのサイズがこの値を超えた場合には、自動でフラッシュが行われる.
+    // `None`の場合は無制限.
+    max_write_buf_size: Option<usize>,
+
+    // これまでの書き込みバッファのサイズの最大値
+    write_buf_high_water: usize,
+
+    // 書き込みバッファのフラッシュ時に、書き出し順序を保証するかどうか
+    //
+    // `true`の場合には、バッファの二ブロック目以降を書き出して同期命令を発行した後に、先頭ブロックを書き出す.
+    // 詳細は`flush_write_buf`メソッドのコメントを参照のこと.
+    safe_flush: bool,
+
+    // 内部NVMに対して、完了を待機していない非同期書き込みが存在するかどうか
+    //
+    // 内部NVMが非同期書き込み(`AsyncNonVolatileMemory`)をサポートしている場合には、
+    // 書き込みバッファのフラッシュは、バッファ自体を内部NVMに引き渡すことで行われ、
+    // その書き込みが実行されている間にも、新しいバッファへの追記(i.e., 次のレコードのエンコード)が継続可能となる.
+    // 同時に発行される書き込みは高々一つで、次のフラッシュないし同期の前に、その完了が待機される.
+    async_write_in_flight: bool,
+
+    // 非同期書き込みが完了したバッファ (次のフラッシュ時に再利用される)
+    spare_write_buf: Option<AlignedBytes>,
+
+    // 読み込みおよび書き込みのバイト数を、発生元別に計上するためのカウンタ
+    io_counters: Option<(NvmIoCounter, NvmIoCounter)>,
+
+    // 現在の読み書きの発生元
+    io_origin: IoOrigin,
 }
 impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
     /// 新しい`JournalNvmBuffer`インスタンスを生成する.
@@ -65,7 +108,10 @@ impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
     ///
     /// ただし、シーク時には、シーク地点を含まない次のブロック境界までのデータは
     /// 上書きされてしまうので注意が必要.
-    pub fn new(nvm: N) -> Self {
+    ///
+    /// `safe_flush`が`true`の場合には、書き込みバッファのフラッシュ時に、
+    /// 先頭ブロックが他のブロックよりも後に永続化されることが保証される(その分、同期命令の発行回数は増える).
+    pub fn new(nvm: N, safe_flush: bool) -> Self {
         let block_size = nvm.block_size();
         JournalNvmBuffer {
             inner: nvm,
@@ -74,9 +120,72 @@ impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
             write_buf_offset: 0,
             write_buf: AlignedBytes::new(0, block_size),
             read_buf: AlignedBytes::new(0, block_size),
+            unsynced_bytes: 0,
+            oldest_unsynced_write: None,
+            max_write_buf_size: None,
+            write_buf_high_water: 0,
+            safe_flush,
+            async_write_in_flight: false,
+            spare_write_buf: None,
+            io_counters: None,
+            io_origin: IoOrigin::Foreground,
+        }
+    }
+
+    /// 読み込みおよび書き込みのバイト数を計上するためのカウンタを設定する.
+    ///
+    /// 読み込みは内部NVMから実際に読み込んだバイト数(アライメント分を含む)が、
+    /// 書き込みは書き込みバッファに追記されたバイト数が、それぞれ計上される.
+    pub fn set_io_counters(&mut self, read: NvmIoCounter, written: NvmIoCounter) {
+        self.io_counters = Some((read, written));
+    }
+
+    /// 以後の読み書きの発生元を設定し、以前の値を返す.
+    pub fn set_io_origin(&mut self, origin: IoOrigin) -> IoOrigin {
+        mem::replace(&mut self.io_origin, origin)
+    }
+
+    fn count_read_bytes(&self, size: usize) {
+        if let Some((ref read, _)) = self.io_counters {
+            read.add(self.io_origin, size as u64);
+        }
+    }
+
+    fn count_written_bytes(&self, size: usize) {
+        if let Some((_, ref written)) = self.io_counters {
+            written.add(self.io_origin, size as u64);
         }
     }
 
+    /// 書き込みバッファのサイズの上限を設定する.
+    ///
+    /// 書き込みによってバッファのサイズがこの値を超えた場合には、その時点で内部NVMへのフラッシュが行われる
+    /// (同期命令は発行されない).
+    /// なお、フラッシュ後もバッファには末尾の一ブロック分のデータが残るので、
+    /// ブロックサイズ未満の値を指定した場合には、書き込みの度にフラッシュが行われることになる.
+    ///
+    /// `None`の場合は無制限(デフォルト).
+    pub fn set_max_write_buf_size(&mut self, max: Option<usize>) {
+        self.max_write_buf_size = max;
+    }
+
+    /// これまでの書き込みバッファのサイズの最大値を返す.
+    pub fn write_buf_high_water(&self) -> usize {
+        self.write_buf_high_water
+    }
+
+    /// 前回の同期以降に書き込まれた(i.e., まだ永続化が保証されていない)バイト数を返す.
+    pub fn unsynced_bytes(&self) -> u64 {
+        self.unsynced_bytes
+    }
+
+    /// 未同期の書き込みの内で、最も古いものが行われた時刻を返す.
+    ///
+    /// 未同期の書き込みが存在しない場合には`None`が返される.
+    pub fn oldest_unsynced_write(&self) -> Option<SystemTime> {
+        self.oldest_unsynced_write
+    }
+
     #[cfg(test)]
     pub fn nvm(&self) -> &N {
         &self.inner
@@ -100,9 +209,29 @@ impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
             return Ok(());
         }
 
-        track_io!(self.inner.seek(SeekFrom::Start(self.write_buf_offset)))?;
-        track_io!(self.inner.write(&self.write_buf))?;
-        if self.write_buf.len() > self.block_size().as_u16() as usize {
+        let block_len = self.block_size().as_u16() as usize;
+        if !self.safe_flush && self.inner.as_async_mut().is_some() {
+            return track!(self.start_async_flush(block_len));
+        }
+        if self.safe_flush && self.write_buf.len() > block_len {
+            // バッファの先頭ブロックには、前回の書き込みの終端(i.e., `EndOfRecords`があった位置)から始まる
+            // 新しいレコードのヘッダが含まれている.
+            // もし先頭ブロックのみが永続化された状態でクラッシュすると、再起動時には、
+            // 新しいレコードのヘッダに続いて、古い(前周の)データが読み込まれることになってしまう.
+            //
+            // それを防ぐために、まず二ブロック目以降を書き出して同期し、最後に先頭ブロックを書き出す.
+            let next_offset = self.write_buf_offset + block_len as u64;
+            track_io!(self.inner.seek(SeekFrom::Start(next_offset)))?;
+            track_io!(self.inner.write(&self.write_buf[block_len..]))?;
+            track!(self.inner.sync())?;
+
+            track_io!(self.inner.seek(SeekFrom::Start(self.write_buf_offset)))?;
+            track_io!(self.inner.write(&self.write_buf[..block_len]))?;
+        } else {
+            track_io!(self.inner.seek(SeekFrom::Start(self.write_buf_offset)))?;
+            track_io!(self.inner.write(&self.write_buf))?;
+        }
+        if self.write_buf.len() > block_len {
             // このif節では、
             // バッファに末端のalignmentバイト分(= new_len)の情報を残す。
             // write_buf_offsetは、write_buf.len() - new_len(= drop_len)分だけ進められる。
@@ -112,7 +241,7 @@ impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
             // ブロック長でしか書き出すことができないため、その場合は次回の書き込み時に
             // NVMに一度アクセスしてブロック全体を取得しなくてはならない。
             // この読み込みを避けるため、現在の実装の形をとっている。
-            let new_len = self.block_size().as_u16() as usize;
+            let new_len = block_len;
             let drop_len = self.write_buf.len() - new_len;
             unsafe {
                 // This nonoverlappingness is guranteed by the callers.
@@ -130,6 +259,45 @@ impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
         Ok(())
     }
 
+    /// 書き込みバッファの内容の書き出しを、非同期に開始する.
+    ///
+    /// 書き出し中のバッファは内部NVMに引き渡され、
+    /// 以後の追記には、その末尾の一ブロック分をコピーした新しいバッファが使用される.
+    fn start_async_flush(&mut self, block_len: usize) -> Result<()> {
+        track!(self.wait_async_writes())?;
+
+        let block_size = self.block_size();
+        let keep_len = cmp::min(block_len, self.write_buf.len());
+        let drop_len = self.write_buf.len() - keep_len;
+        let mut next_buf = self
+            .spare_write_buf
+            .take()
+            .unwrap_or_else(|| AlignedBytes::new(0, block_size));
+        next_buf.resize(keep_len);
+        next_buf.copy_from_slice(&self.write_buf[drop_len..]);
+
+        let buf = mem::replace(&mut self.write_buf, next_buf);
+        let inner = self.inner.as_async_mut().expect("Never fails");
+        track!(inner.start_write(self.write_buf_offset, buf))?;
+        self.async_write_in_flight = true;
+
+        self.write_buf_offset += drop_len as u64;
+        self.maybe_dirty = false;
+        Ok(())
+    }
+
+    /// 発行済みの非同期書き込みの完了を待機する.
+    fn wait_async_writes(&mut self) -> Result<()> {
+        if !self.async_write_in_flight {
+            return Ok(());
+        }
+        self.async_write_in_flight = false;
+        let inner = self.inner.as_async_mut().expect("Never fails");
+        let mut bufs = track!(inner.wait_writes())?;
+        self.spare_write_buf = bufs.pop();
+        Ok(())
+    }
+
     fn check_overflow(&self, write_len: usize) -> Result<()> {
         let next_position = self.position() + write_len as u64;
         track_assert!(
@@ -146,7 +314,11 @@ impl<N: NonVolatileMemory> JournalNvmBuffer<N> {
 impl<N: NonVolatileMemory> NonVolatileMemory for JournalNvmBuffer<N> {
     fn sync(&mut self) -> Result<()> {
         track!(self.flush_write_buf())?;
-        self.inner.sync()
+        track!(self.wait_async_writes())?;
+        track!(self.inner.sync())?;
+        self.unsynced_bytes = 0;
+        self.oldest_unsynced_write = None;
+        Ok(())
     }
 
     fn position(&self) -> u64 {
@@ -161,9 +333,25 @@ impl<N: NonVolatileMemory> NonVolatileMemory for JournalNvmBuffer<N> {
         self.inner.block_size()
     }
 
+    fn advise(&mut self, offset: u64, size: u64, pattern: AccessPattern) -> Result<()> {
+        track!(self.inner.advise(offset, size, pattern))
+    }
+
     fn split(self, _: u64) -> Result<(Self, Self)> {
         unreachable!()
     }
+
+    fn try_clone_reader(&self) -> Result<Option<Self>> {
+        // 書き込みバッファの内容は複製されないため、
+        // 読み込み側からは、フラッシュ済みの範囲のみが参照可能となる
+        let reader = track!(self.inner.try_clone_reader())?;
+        Ok(reader.map(|reader| {
+            let mut reader = JournalNvmBuffer::new(reader, self.safe_flush);
+            reader.io_counters = self.io_counters.clone();
+            reader.io_origin = self.io_origin;
+            reader
+        }))
+    }
 }
 impl<N: NonVolatileMemory> Drop for JournalNvmBuffer<N> {
     fn drop(&mut self) {
@@ -192,11 +380,12 @@ impl<N: NonVolatileMemory> Read for JournalNvmBuffer<N> {
             .aligned_resize((aligned_end - aligned_start) as usize);
         self.inner.seek(SeekFrom::Start(aligned_start))?;
         let inner_read_size = self.inner.read(&mut self.read_buf)?;
+        self.count_read_bytes(inner_read_size);
 
         let start = (self.position - aligned_start) as usize;
         let end = cmp::min(inner_read_size, start + buf.len());
         let read_size = end - start;
-        (&mut buf[..read_size]).copy_from_slice(&self.read_buf[start..end]);
+        buf[..read_size].copy_from_slice(&self.read_buf[start..end]);
         self.position += read_size as u64;
         Ok(read_size)
     }
@@ -213,9 +402,21 @@ impl<N: NonVolatileMemory> Write for JournalNvmBuffer<N> {
             let start = (self.position - self.write_buf_offset) as usize;
             let end = start + buf.len();
             self.write_buf.aligned_resize(end);
-            (&mut self.write_buf[start..end]).copy_from_slice(buf);
+            self.write_buf[start..end].copy_from_slice(buf);
             self.position += buf.len() as u64;
             self.maybe_dirty = true;
+            self.unsynced_bytes += buf.len() as u64;
+            self.count_written_bytes(buf.len());
+            if self.oldest_unsynced_write.is_none() {
+                self.oldest_unsynced_write = Some(SystemTime::now());
+            }
+            self.write_buf_high_water = cmp::max(self.write_buf_high_water, self.write_buf.len());
+            if self
+                .max_write_buf_size
+                .is_some_and(|max| self.write_buf.len() > max)
+            {
+                track!(self.flush_write_buf())?;
+            }
             Ok(buf.len())
         } else {
             // 領域に重複がないので、一度バッファの中身を書き戻す
@@ -231,6 +432,7 @@ impl<N: NonVolatileMemory> Write for JournalNvmBuffer<N> {
                 self.write_buf.aligned_resize(size as usize);
                 self.inner.seek(SeekFrom::Start(self.write_buf_offset))?;
                 self.inner.read_exact(&mut self.write_buf)?;
+                self.count_read_bytes(self.write_buf.len());
             }
             self.write(buf)
         }
@@ -364,8 +566,82 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn unsynced_bytes_works() -> TestResult {
+        let mut buffer = new_buffer();
+        assert_eq!(buffer.unsynced_bytes(), 0);
+        assert!(buffer.oldest_unsynced_write().is_none());
+
+        track_io!(buffer.write_all(b"foo"))?;
+        track_io!(buffer.seek(SeekFrom::Start(1024)))?;
+        track_io!(buffer.write_all(b"bar"))?;
+        assert_eq!(buffer.unsynced_bytes(), 6);
+        let oldest = buffer.oldest_unsynced_write();
+        assert!(oldest.is_some());
+
+        // フラッシュされても、同期されるまでは未同期として扱われる
+        track_io!(buffer.flush())?;
+        track_io!(buffer.write_all(b"baz"))?;
+        assert_eq!(buffer.unsynced_bytes(), 9);
+        assert_eq!(buffer.oldest_unsynced_write(), oldest);
+
+        track!(buffer.sync())?;
+        assert_eq!(buffer.unsynced_bytes(), 0);
+        assert!(buffer.oldest_unsynced_write().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn max_write_buf_size_works() -> TestResult {
+        let mut buffer = new_buffer();
+        buffer.set_max_write_buf_size(Some(1024));
+
+        track_io!(buffer.write_all(&[b'a'; 1000]))?;
+        assert_eq!(&buffer.nvm().as_bytes()[0..3], &[0; 3][..]);
+
+        // 上限を超えたので、自動でフラッシュされる
+        track_io!(buffer.write_all(&[b'b'; 100]))?;
+        assert_eq!(&buffer.nvm().as_bytes()[0..1000], &[b'a'; 1000][..]);
+        assert_eq!(&buffer.nvm().as_bytes()[1000..1100], &[b'b'; 100][..]);
+        assert_eq!(buffer.write_buf_high_water(), 1536);
+
+        // フラッシュ後も、続きから書き込める
+        track_io!(buffer.write_all(&[b'c'; 100]))?;
+        track_io!(buffer.flush())?;
+        assert_eq!(&buffer.nvm().as_bytes()[1100..1200], &[b'c'; 100][..]);
+        assert_eq!(buffer.write_buf_high_water(), 1536);
+        Ok(())
+    }
+
+    #[test]
+    fn safe_flush_works() -> TestResult {
+        for &safe_flush in &[false, true] {
+            let nvm = MemoryNvm::new(vec![0; 10 * 1024]);
+            let mut buffer = JournalNvmBuffer::new(nvm, safe_flush);
+
+            // 複数ブロックに跨る書き込み
+            track_io!(buffer.write_all(&[b'a'; 1500]))?;
+            track_io!(buffer.flush())?;
+            assert_eq!(&buffer.nvm().as_bytes()[0..1500], &[b'a'; 1500][..]);
+            assert_eq!(&buffer.nvm().as_bytes()[1500..1536], &[0; 36][..]);
+
+            // 一ブロックに収まる書き込み
+            track_io!(buffer.write_all(&[b'b'; 10]))?;
+            track_io!(buffer.flush())?;
+            assert_eq!(&buffer.nvm().as_bytes()[1500..1510], &[b'b'; 10][..]);
+
+            // 末尾ブロックを含む、複数ブロックに跨る書き込み
+            track_io!(buffer.write_all(&[b'c'; 1000]))?;
+            track!(buffer.sync())?;
+            assert_eq!(&buffer.nvm().as_bytes()[0..1500], &[b'a'; 1500][..]);
+            assert_eq!(&buffer.nvm().as_bytes()[1500..1510], &[b'b'; 10][..]);
+            assert_eq!(&buffer.nvm().as_bytes()[1510..2510], &[b'c'; 1000][..]);
+        }
+        Ok(())
+    }
+
     fn new_buffer() -> JournalNvmBuffer<MemoryNvm> {
         let nvm = MemoryNvm::new(vec![0; 10 * 1024]);
-        JournalNvmBuffer::new(nvm)
+        JournalNvmBuffer::new(nvm, false)
     }
 }
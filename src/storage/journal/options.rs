@@ -1,4 +1,10 @@
+use slog::{Discard, Logger};
+
+use super::admission::AdmissionControlOptions;
+use super::sync_controller::AdaptiveSyncOptions;
+use super::JournalChecksum;
 use crate::block::BlockSize;
+use crate::storage::MINOR_VERSION;
 
 /// ジャーナル領域の挙動を調整するためのパラメータ群.
 ///
@@ -6,15 +12,41 @@ use crate::block::BlockSize;
 #[derive(Debug, Clone)]
 pub struct JournalRegionOptions {
     pub gc_queue_size: usize,
+    pub gc_batch_size: usize,
+    pub adaptive_gc_max_steps: Option<usize>,
     pub sync_interval: usize,
     pub block_size: BlockSize,
+    pub checksum: JournalChecksum,
+    pub max_write_buffer_size: Option<usize>,
+    pub restore_buffer_size: usize,
+    pub safe_flush: bool,
+    pub adaptive_sync: Option<AdaptiveSyncOptions>,
+    pub admission_control: Option<AdmissionControlOptions>,
+    pub header_slots: u8,
+
+    /// オープン対象のストレージのマイナーバージョン.
+    ///
+    /// これより新しいバージョンで導入された形式(e.g., エポック)への移行は行われない.
+    pub minor_version: u16,
+    pub logger: Logger,
 }
 impl Default for JournalRegionOptions {
     fn default() -> Self {
         JournalRegionOptions {
             gc_queue_size: 0x1000,
+            gc_batch_size: 64,
+            adaptive_gc_max_steps: None,
             sync_interval: 0x1000,
             block_size: BlockSize::min(),
+            checksum: JournalChecksum::default(),
+            max_write_buffer_size: None,
+            restore_buffer_size: 1024 * 1024,
+            safe_flush: false,
+            adaptive_sync: None,
+            admission_control: None,
+            header_slots: 2,
+            minor_version: MINOR_VERSION,
+            logger: Logger::root(Discard, o!()),
         }
     }
 }
@@ -1,29 +1,23 @@
-use adler32::RollingAdler32;
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
-use std::io::{Read, Write};
-use std::ops::Range;
+use byteorder::{BigEndian, ByteOrder};
 
+pub(crate) use crate::format::record::{
+    CHECKSUM_SIZE, EMBEDDED_DATA_OFFSET, END_OF_RECORDS_SIZE, LENGTH_SIZE, PORTION_SIZE, TAG_SIZE,
+};
+pub use crate::format::{JournalChecksum, JournalRecord};
+
+use crate::format::record::{
+    TAG_AUDIT, TAG_AUDITED_DELETE, TAG_AUDITED_PUT, TAG_CLEAN_SHUTDOWN, TAG_DEDUP_PUT,
+    TAG_EMBEDDED_RENAME, TAG_IN_PLACE_PUT, TAG_LINK, TAG_RENAME,
+};
+#[cfg(feature = "arbitrary")]
+use crate::format::record::{TAG_ESSENTIAL_FLAG, TAG_EXTENSION_MIN};
 use crate::lump::LumpId;
 use crate::storage::portion::DataPortion;
 use crate::storage::Address;
 use crate::{ErrorKind, Result};
 
-pub const TAG_SIZE: usize = 1;
-pub const CHECKSUM_SIZE: usize = 4;
-pub const LENGTH_SIZE: usize = 2;
-pub const PORTION_SIZE: usize = 5;
-pub const END_OF_RECORDS_SIZE: usize = CHECKSUM_SIZE + TAG_SIZE;
-pub const EMBEDDED_DATA_OFFSET: usize = CHECKSUM_SIZE + TAG_SIZE + LumpId::SIZE + LENGTH_SIZE;
-
-const TAG_END_OF_RECORDS: u8 = 0;
-const TAG_GO_TO_FRONT: u8 = 1;
-const TAG_PUT: u8 = 3;
-const TAG_EMBED: u8 = 4;
-const TAG_DELETE: u8 = 5;
-const TAG_DELETE_RANGE: u8 = 6;
-
 /// ジャーナル領域のリングバッファのエントリ.
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct JournalEntry {
     /// ジャーナル内でのレコードの開始位置.
     pub start: Address,
@@ -38,166 +32,555 @@ impl JournalEntry {
     }
 }
 
-/// ジャーナル領域のリングバッファに追記されていくレコード.
-#[allow(missing_docs)]
-#[derive(Debug, PartialEq, Eq)]
-pub enum JournalRecord<T> {
-    EndOfRecords,
-    GoToFront,
-    Put(LumpId, DataPortion),
-    Embed(LumpId, T),
-    Delete(LumpId),
-    DeleteRange(Range<LumpId>),
-}
-impl<T: AsRef<[u8]>> JournalRecord<T> {
-    /// 読み書き時のサイズ（バイト数）を返す.
-    pub(crate) fn external_size(&self) -> usize {
-        let record_size = match *self {
-            JournalRecord::EndOfRecords | JournalRecord::GoToFront => 0,
-            JournalRecord::Put(..) => LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE,
-            JournalRecord::Embed(_, ref data) => LumpId::SIZE + LENGTH_SIZE + data.as_ref().len(),
-            JournalRecord::Delete(..) => LumpId::SIZE,
-            JournalRecord::DeleteRange(..) => LumpId::SIZE * 2,
+/// 読み込み時にエラーとならない(i.e., `write_to_with`と`read_from_with`で往復可能な)レコードを生成する.
+///
+/// 拡張レコードとしては、読み飛ばし可能なものと、既知の必須レコード(e.g., `LinkRecord`)のみが生成される.
+/// `DeleteRange`の範囲は、常に`start <= end`となる.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for JournalRecord<Vec<u8>> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let bytes = |u: &mut arbitrary::Unstructured<'a>| -> arbitrary::Result<Vec<u8>> {
+            let len = u.int_in_range(0..=std::cmp::min(u.len(), 0xFFFF))?;
+            Ok(u.bytes(len)?.to_owned())
         };
-        CHECKSUM_SIZE + TAG_SIZE + record_size
-    }
-
-    /// `writer`にレコードを書き込む.
-    pub(crate) fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
-        track_io!(writer.write_u32::<BigEndian>(self.checksum()))?;
-        match *self {
-            JournalRecord::EndOfRecords => {
-                track_io!(writer.write_u8(TAG_END_OF_RECORDS))?;
-            }
-            JournalRecord::GoToFront => {
-                track_io!(writer.write_u8(TAG_GO_TO_FRONT))?;
+        let record = match u.int_in_range(0..=10)? {
+            0 => JournalRecord::EndOfRecords,
+            1 => JournalRecord::GoToFront,
+            2 => JournalRecord::Put(u.arbitrary()?, u.arbitrary()?),
+            3 => JournalRecord::Embed(u.arbitrary()?, bytes(u)?),
+            4 => JournalRecord::Delete(u.arbitrary()?),
+            5 => {
+                let (a, b): (LumpId, LumpId) = (u.arbitrary()?, u.arbitrary()?);
+                JournalRecord::DeleteRange(std::cmp::min(a, b)..std::cmp::max(a, b))
             }
-            JournalRecord::Put(ref lump_id, portion) => {
-                track_io!(writer.write_u8(TAG_PUT))?;
-                track_io!(writer.write_u128::<BigEndian>(lump_id.as_u128()))?;
-                track_io!(writer.write_u16::<BigEndian>(portion.len))?;
-                track_io!(writer.write_uint::<BigEndian>(portion.start.as_u64(), PORTION_SIZE))?;
+            6 => {
+                let tag = u.int_in_range(
+                    TAG_EXTENSION_MIN..=TAG_EXTENSION_MIN | (TAG_ESSENTIAL_FLAG - 1),
+                )?;
+                JournalRecord::Extension(tag, bytes(u)?)
             }
-            JournalRecord::Embed(ref lump_id, ref data) => {
-                debug_assert!(data.as_ref().len() <= 0xFFFF);
-                track_io!(writer.write_u8(TAG_EMBED))?;
-                track_io!(writer.write_u128::<BigEndian>(lump_id.as_u128()))?;
-                track_io!(writer.write_u16::<BigEndian>(data.as_ref().len() as u16))?;
-                track_io!(writer.write_all(data.as_ref()))?;
-            }
-            JournalRecord::Delete(ref lump_id) => {
-                track_io!(writer.write_u8(TAG_DELETE))?;
-                track_io!(writer.write_u128::<BigEndian>(lump_id.as_u128()))?;
-            }
-            JournalRecord::DeleteRange(ref range) => {
-                track_io!(writer.write_u8(TAG_DELETE_RANGE))?;
-                track_io!(writer.write_u128::<BigEndian>(range.start.as_u128()))?;
-                track_io!(writer.write_u128::<BigEndian>(range.end.as_u128()))?;
+            7 => DedupPutRecord::new(u.arbitrary()?, u.arbitrary()?, u.arbitrary()?)
+                .to_journal_record(),
+            8 => LinkRecord::new(u.arbitrary()?, u.arbitrary()?).to_journal_record(),
+            9 => RenameRecord::new(u.arbitrary()?, u.arbitrary()?, u.arbitrary()?)
+                .to_journal_record(),
+            _ => {
+                let mut data = bytes(u)?;
+                data.truncate(EmbeddedRenameRecord::MAX_DATA_SIZE);
+                EmbeddedRenameRecord::new(u.arbitrary()?, u.arbitrary()?, &data).to_journal_record()
             }
+        };
+        Ok(record)
+    }
+}
+
+/// 監査対象の操作の種類.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuditOperation {
+    /// PUT操作.
+    Put,
+
+    /// DELETE操作.
+    Delete,
+}
+impl AuditOperation {
+    fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(AuditOperation::Put),
+            1 => Some(AuditOperation::Delete),
+            _ => None,
         }
-        Ok(())
     }
 
-    fn checksum(&self) -> u32 {
-        let mut adler32 = RollingAdler32::new();
-        match *self {
-            JournalRecord::EndOfRecords => {
-                adler32.update(TAG_END_OF_RECORDS);
-            }
-            JournalRecord::GoToFront => {
-                adler32.update(TAG_GO_TO_FRONT);
-            }
-            JournalRecord::Put(ref lump_id, portion) => {
-                adler32.update(TAG_PUT);
-                adler32.update_buffer(&lump_id_to_u128(lump_id)[..]);
-                let mut buf = [0; 7];
-                BigEndian::write_u16(&mut buf, portion.len);
-                BigEndian::write_uint(&mut buf[2..], portion.start.as_u64(), PORTION_SIZE);
-                adler32.update_buffer(&buf);
+    fn as_u8(self) -> u8 {
+        match self {
+            AuditOperation::Put => 0,
+            AuditOperation::Delete => 1,
+        }
+    }
+}
+
+/// 操作の実施者や時刻等の、呼び出し側が指定したメタデータを記録するための監査レコード.
+///
+/// 監査証跡が有効になっている場合(`StorageBuilder::audit_trail`)には、`Storage::put_with_audit`等の操作の記録
+/// (i.e., PUTないしDELETEレコード)と一つにまとめられた形で、ジャーナルに書き込まれる.
+/// そのため、操作と監査レコードのどちらか一方のみが永続化された状態が生じることはない.
+///
+/// 新しいものから`StorageBuilder::audit_retention`個までの監査レコードは、ジャーナルのGCによって回収されない.
+/// 操作の記録が不要になった後も保持期間内にある監査レコードは、GCの際に単独の監査レコードとして書き直される.
+/// 書き込まれたレコードは、いずれの形式であっても、`Storage::journal_snapshot`等で取得したエントリ群から
+/// `from_journal_record`を用いて取り出せる.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// 監査レコードの通番.
+    ///
+    /// ジャーナルへの書き込み時に、昇順に割り当てられる(`new`で生成した時点では`0`).
+    /// GCによって書き直された監査レコードは、元の通番を維持する.
+    pub seqno: u64,
+
+    /// 操作の種類.
+    pub operation: AuditOperation,
+
+    /// 操作対象のlumpのID.
+    pub lump_id: LumpId,
+
+    /// 呼び出し側が指定したメタデータ.
+    pub metadata: Vec<u8>,
+}
+impl AuditRecord {
+    /// メタデータの最小長(バイト単位).
+    pub const MIN_METADATA_SIZE: usize = 16;
+
+    /// メタデータの最大長(バイト単位).
+    pub const MAX_METADATA_SIZE: usize = 32;
+
+    const SEQNO_SIZE: usize = 8;
+
+    /// 新しい`AuditRecord`インスタンスを生成する.
+    ///
+    /// # Errors
+    ///
+    /// `metadata`の長さが`MIN_METADATA_SIZE`以上`MAX_METADATA_SIZE`以下ではない場合には、
+    /// `ErrorKind::InvalidInput`エラーが返される.
+    pub fn new(operation: AuditOperation, lump_id: LumpId, metadata: &[u8]) -> Result<Self> {
+        track_assert!(
+            metadata.len() >= Self::MIN_METADATA_SIZE && metadata.len() <= Self::MAX_METADATA_SIZE,
+            ErrorKind::InvalidInput,
+            "Invalid audit metadata size: {} bytes",
+            metadata.len()
+        );
+        Ok(AuditRecord {
+            seqno: 0,
+            operation,
+            lump_id,
+            metadata: metadata.to_owned(),
+        })
+    }
+
+    /// ジャーナルレコードが監査レコード(あるいは監査付きのPUTないしDELETEレコード)であれば、その内容を返す.
+    pub fn from_journal_record(record: &JournalRecord<Vec<u8>>) -> Option<Self> {
+        let (operation, payload, trailer_offset) = match *record {
+            JournalRecord::Extension(TAG_AUDIT, ref payload) if !payload.is_empty() => {
+                let operation = AuditOperation::from_u8(payload[0])?;
+                (operation, &payload[1..], LumpId::SIZE)
             }
-            JournalRecord::Embed(ref lump_id, ref data) => {
-                debug_assert!(data.as_ref().len() <= 0xFFFF);
-                adler32.update(TAG_EMBED);
-                adler32.update_buffer(&lump_id_to_u128(lump_id)[..]);
-                let mut buf = [0; 2];
-                BigEndian::write_u16(&mut buf, data.as_ref().len() as u16);
-                adler32.update_buffer(&buf);
-                adler32.update_buffer(data.as_ref());
+            JournalRecord::Extension(TAG_AUDITED_PUT, ref payload) => (
+                AuditOperation::Put,
+                &payload[..],
+                LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE,
+            ),
+            JournalRecord::Extension(TAG_AUDITED_DELETE, ref payload) => {
+                (AuditOperation::Delete, &payload[..], LumpId::SIZE)
             }
-            JournalRecord::Delete(ref lump_id) => {
-                adler32.update(TAG_DELETE);
-                adler32.update_buffer(&lump_id_to_u128(lump_id)[..]);
+            _ => return None,
+        };
+        if payload.len() < trailer_offset + Self::SEQNO_SIZE {
+            return None;
+        }
+        let lump_id = LumpId::new(BigEndian::read_u128(payload));
+        let trailer = &payload[trailer_offset..];
+        Some(AuditRecord {
+            seqno: BigEndian::read_u64(trailer),
+            operation,
+            lump_id,
+            metadata: trailer[Self::SEQNO_SIZE..].to_owned(),
+        })
+    }
+
+    /// ジャーナルレコードが監査付きのPUTレコードであれば、その対象のlumpのIDと部分領域を返す.
+    pub(crate) fn audited_put<T: AsRef<[u8]>>(
+        record: &JournalRecord<T>,
+    ) -> Option<(LumpId, DataPortion)> {
+        match *record {
+            JournalRecord::Extension(TAG_AUDITED_PUT, ref payload)
+                if payload.as_ref().len()
+                    >= LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE + Self::SEQNO_SIZE =>
+            {
+                let payload = payload.as_ref();
+                let lump_id = LumpId::new(BigEndian::read_u128(payload));
+                let payload = &payload[LumpId::SIZE..];
+                let len = BigEndian::read_u16(payload);
+                let start = BigEndian::read_uint(&payload[LENGTH_SIZE..], PORTION_SIZE);
+                let portion = DataPortion {
+                    start: Address::from_u64(start)?,
+                    len,
+                };
+                Some((lump_id, portion))
             }
-            JournalRecord::DeleteRange(ref range) => {
-                adler32.update(TAG_DELETE_RANGE);
-                adler32.update_buffer(&lump_id_to_u128(&range.start)[..]);
-                adler32.update_buffer(&lump_id_to_u128(&range.end)[..]);
+            _ => None,
+        }
+    }
+
+    /// ジャーナルレコードが監査付きのDELETEレコードであれば、その対象のlumpのIDを返す.
+    pub(crate) fn audited_delete<T: AsRef<[u8]>>(record: &JournalRecord<T>) -> Option<LumpId> {
+        match *record {
+            JournalRecord::Extension(TAG_AUDITED_DELETE, ref payload)
+                if payload.as_ref().len() >= LumpId::SIZE + Self::SEQNO_SIZE =>
+            {
+                Some(LumpId::new(BigEndian::read_u128(payload.as_ref())))
             }
+            _ => None,
         }
-        adler32.hash()
+    }
+
+    /// 単独の監査レコードを表すジャーナルレコードに変換する.
+    pub(crate) fn to_journal_record(&self) -> JournalRecord<Vec<u8>> {
+        let mut payload = vec![self.operation.as_u8()];
+        payload.extend_from_slice(&self.lump_id.as_u128().to_be_bytes());
+        self.write_trailer(&mut payload);
+        JournalRecord::Extension(TAG_AUDIT, payload)
+    }
+
+    /// `portion`へのPUT操作と一つにまとめた、監査付きのPUTレコードに変換する.
+    pub(crate) fn to_audited_put_record(&self, portion: DataPortion) -> JournalRecord<Vec<u8>> {
+        debug_assert_eq!(self.operation, AuditOperation::Put);
+        let mut payload = vec![0; LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE];
+        BigEndian::write_u128(&mut payload, self.lump_id.as_u128());
+        let buf = &mut payload[LumpId::SIZE..];
+        BigEndian::write_u16(buf, portion.len);
+        BigEndian::write_uint(
+            &mut buf[LENGTH_SIZE..],
+            portion.start.as_u64(),
+            PORTION_SIZE,
+        );
+        self.write_trailer(&mut payload);
+        JournalRecord::Extension(TAG_AUDITED_PUT, payload)
+    }
+
+    /// DELETE操作と一つにまとめた、監査付きのDELETEレコードに変換する.
+    pub(crate) fn to_audited_delete_record(&self) -> JournalRecord<Vec<u8>> {
+        debug_assert_eq!(self.operation, AuditOperation::Delete);
+        let mut payload = self.lump_id.as_u128().to_be_bytes().to_vec();
+        self.write_trailer(&mut payload);
+        JournalRecord::Extension(TAG_AUDITED_DELETE, payload)
+    }
+
+    // 各形式に共通する後半部分(通番およびメタデータ)を書き込む
+    fn write_trailer(&self, payload: &mut Vec<u8>) {
+        payload.extend_from_slice(&self.seqno.to_be_bytes());
+        payload.extend_from_slice(&self.metadata);
     }
 }
-impl JournalRecord<Vec<u8>> {
-    /// `reader`からレコードを読み込む.
-    pub(crate) fn read_from<R: Read>(mut reader: R) -> Result<Self> {
-        let checksum = track_io!(reader.read_u32::<BigEndian>())?;
-        let tag = track_io!(reader.read_u8())?;
-        let record = match tag {
-            TAG_END_OF_RECORDS => JournalRecord::EndOfRecords,
-            TAG_GO_TO_FRONT => JournalRecord::GoToFront,
-            TAG_PUT => {
-                let lump_id = track!(read_lump_id(&mut reader))?;
-                let data_len = track_io!(reader.read_u16::<BigEndian>())?;
-                let data_offset = track_io!(reader.read_uint::<BigEndian>(PORTION_SIZE))?;
-                let portion = DataPortion {
-                    start: Address::from_u64(data_offset).unwrap(),
-                    len: data_len,
-                };
-                JournalRecord::Put(lump_id, portion)
+
+/// ストレージが正常にクローズされたことを示すレコード.
+///
+/// `Storage::close`の際にジャーナルの末尾に書き込まれ、次回の起動時に、
+/// これが最後のレコードとなっているかどうかでクリーンシャットダウンの有無が判定される.
+/// ペイロードは持たない.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CleanShutdownRecord;
+impl CleanShutdownRecord {
+    /// ジャーナルレコードがクリーンシャットダウンの印かどうかを判定する.
+    pub fn is_clean_shutdown_record<T>(record: &JournalRecord<T>) -> bool {
+        matches!(*record, JournalRecord::Extension(TAG_CLEAN_SHUTDOWN, _))
+    }
+
+    /// クリーンシャットダウンの印を表すジャーナルレコードに変換する.
+    pub fn to_journal_record(self) -> JournalRecord<Vec<u8>> {
+        JournalRecord::Extension(TAG_CLEAN_SHUTDOWN, Vec::new())
+    }
+}
+
+/// 既存のlumpのデータ部分領域への上書き更新(`StorageBuilder::overwrite_in_place`)が行われたことを示すレコード.
+///
+/// 上書き更新ではlumpの格納先の部分領域が変わらないため、インデックスの復元には使用されない.
+/// 障害調査等のために、部分領域の内容が書き換えられた(ため、クラッシュ時には新旧のデータが混在し得る)ことを記録する目的で書き込まれる.
+///
+/// ジャーナル上では、必須ではない拡張レコードとして書き込まれ、GCの際には常に回収対象となる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct InPlacePutRecord {
+    pub lump_id: LumpId,
+    pub portion: DataPortion,
+}
+impl InPlacePutRecord {
+    const PAYLOAD_SIZE: usize = LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE;
+
+    pub fn new(lump_id: LumpId, portion: DataPortion) -> Self {
+        InPlacePutRecord { lump_id, portion }
+    }
+
+    /// ジャーナルレコードが上書き更新の印であれば、その内容を返す.
+    #[allow(dead_code)]
+    pub fn from_journal_record(record: &JournalRecord<Vec<u8>>) -> Option<Self> {
+        match *record {
+            JournalRecord::Extension(TAG_IN_PLACE_PUT, ref payload)
+                if payload.len() == Self::PAYLOAD_SIZE =>
+            {
+                let lump_id = LumpId::new(BigEndian::read_u128(payload));
+                let payload = &payload[LumpId::SIZE..];
+                let len = BigEndian::read_u16(payload);
+                let start = BigEndian::read_uint(&payload[LENGTH_SIZE..], PORTION_SIZE);
+                Some(InPlacePutRecord {
+                    lump_id,
+                    portion: DataPortion {
+                        start: Address::from_u64(start)?,
+                        len,
+                    },
+                })
             }
-            TAG_EMBED => {
-                let lump_id = track!(read_lump_id(&mut reader))?;
-                let data_len = track_io!(reader.read_u16::<BigEndian>())?;
-                let mut data = vec![0; data_len as usize];
-                track_io!(reader.read_exact(&mut data))?;
-                JournalRecord::Embed(lump_id, data)
+            _ => None,
+        }
+    }
+
+    /// 上書き更新の印を表すジャーナルレコードに変換する.
+    pub fn to_journal_record(self) -> JournalRecord<Vec<u8>> {
+        let mut payload = vec![0; Self::PAYLOAD_SIZE];
+        BigEndian::write_u128(&mut payload, self.lump_id.as_u128());
+        let buf = &mut payload[LumpId::SIZE..];
+        BigEndian::write_u16(buf, self.portion.len);
+        BigEndian::write_uint(
+            &mut buf[LENGTH_SIZE..],
+            self.portion.start.as_u64(),
+            PORTION_SIZE,
+        );
+        JournalRecord::Extension(TAG_IN_PLACE_PUT, payload)
+    }
+}
+
+/// 重複排除モード(`StorageBuilder::deduplication`)でのPUT操作を表すレコード.
+///
+/// 通常のPUTレコードの内容に加えて、データのハッシュ値を保持している.
+/// 同じ部分領域を指すレコードが複数存在し得る点が、通常のPUTレコードとは異なる.
+///
+/// ジャーナル上では、必須の拡張レコードとして書き込まれる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DedupPutRecord {
+    pub lump_id: LumpId,
+    pub portion: DataPortion,
+    pub hash: u64,
+}
+impl DedupPutRecord {
+    const PAYLOAD_SIZE: usize = LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE + 8;
+
+    pub fn new(lump_id: LumpId, portion: DataPortion, hash: u64) -> Self {
+        DedupPutRecord {
+            lump_id,
+            portion,
+            hash,
+        }
+    }
+
+    /// ジャーナルレコードが重複排除付きのPUTレコードであれば、その内容を返す.
+    pub fn from_journal_record(record: &JournalRecord<Vec<u8>>) -> Option<Self> {
+        match *record {
+            JournalRecord::Extension(TAG_DEDUP_PUT, ref payload)
+                if payload.len() == Self::PAYLOAD_SIZE =>
+            {
+                let lump_id = LumpId::new(BigEndian::read_u128(payload));
+                let payload = &payload[LumpId::SIZE..];
+                let len = BigEndian::read_u16(payload);
+                let start = BigEndian::read_uint(&payload[LENGTH_SIZE..], PORTION_SIZE);
+                let hash = BigEndian::read_u64(&payload[LENGTH_SIZE + PORTION_SIZE..]);
+                Some(DedupPutRecord {
+                    lump_id,
+                    portion: DataPortion {
+                        start: Address::from_u64(start)?,
+                        len,
+                    },
+                    hash,
+                })
             }
-            TAG_DELETE => {
-                let lump_id = track!(read_lump_id(&mut reader))?;
-                JournalRecord::Delete(lump_id)
+            _ => None,
+        }
+    }
+
+    /// 重複排除付きのPUTレコードを表すジャーナルレコードに変換する.
+    pub(crate) fn to_journal_record(self) -> JournalRecord<Vec<u8>> {
+        let mut payload = vec![0; Self::PAYLOAD_SIZE];
+        BigEndian::write_u128(&mut payload, self.lump_id.as_u128());
+        let buf = &mut payload[LumpId::SIZE..];
+        BigEndian::write_u16(buf, self.portion.len);
+        BigEndian::write_uint(
+            &mut buf[LENGTH_SIZE..],
+            self.portion.start.as_u64(),
+            PORTION_SIZE,
+        );
+        BigEndian::write_u64(&mut buf[LENGTH_SIZE + PORTION_SIZE..], self.hash);
+        JournalRecord::Extension(TAG_DEDUP_PUT, payload)
+    }
+}
+
+/// 既存のlumpのデータ部分領域を参照する別名(`Storage::link`)の作成操作を表すレコード.
+///
+/// 内容は通常のPUTレコードと同様だが、既に他のlumpが参照している部分領域を指している点が異なる.
+///
+/// ジャーナル上では、必須の拡張レコードとして書き込まれる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LinkRecord {
+    pub lump_id: LumpId,
+    pub portion: DataPortion,
+}
+impl LinkRecord {
+    const PAYLOAD_SIZE: usize = LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE;
+
+    pub fn new(lump_id: LumpId, portion: DataPortion) -> Self {
+        LinkRecord { lump_id, portion }
+    }
+
+    /// ジャーナルレコードが別名作成レコードであれば、その内容を返す.
+    pub fn from_journal_record(record: &JournalRecord<Vec<u8>>) -> Option<Self> {
+        match *record {
+            JournalRecord::Extension(TAG_LINK, ref payload)
+                if payload.len() == Self::PAYLOAD_SIZE =>
+            {
+                let lump_id = LumpId::new(BigEndian::read_u128(payload));
+                let payload = &payload[LumpId::SIZE..];
+                let len = BigEndian::read_u16(payload);
+                let start = BigEndian::read_uint(&payload[LENGTH_SIZE..], PORTION_SIZE);
+                Some(LinkRecord {
+                    lump_id,
+                    portion: DataPortion {
+                        start: Address::from_u64(start)?,
+                        len,
+                    },
+                })
             }
-            TAG_DELETE_RANGE => {
-                let start = track!(read_lump_id(&mut reader))?;
-                let end = track!(read_lump_id(&mut reader))?;
-                JournalRecord::DeleteRange(Range { start, end })
+            _ => None,
+        }
+    }
+
+    /// 別名作成レコードを表すジャーナルレコードに変換する.
+    pub(crate) fn to_journal_record(self) -> JournalRecord<Vec<u8>> {
+        let mut payload = vec![0; Self::PAYLOAD_SIZE];
+        BigEndian::write_u128(&mut payload, self.lump_id.as_u128());
+        let buf = &mut payload[LumpId::SIZE..];
+        BigEndian::write_u16(buf, self.portion.len);
+        BigEndian::write_uint(
+            &mut buf[LENGTH_SIZE..],
+            self.portion.start.as_u64(),
+            PORTION_SIZE,
+        );
+        JournalRecord::Extension(TAG_LINK, payload)
+    }
+}
+
+/// データ領域に格納されているlumpのIDの変更操作(`Storage::rename`)を表すレコード.
+///
+/// 復元時には、`old_id`のlumpがインデックスから取り除かれ、`new_id`のlumpとして`portion`が登録される.
+///
+/// ジャーナル上では、必須の拡張レコードとして書き込まれる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RenameRecord {
+    pub old_id: LumpId,
+    pub new_id: LumpId,
+    pub portion: DataPortion,
+}
+impl RenameRecord {
+    const PAYLOAD_SIZE: usize = LumpId::SIZE * 2 + LENGTH_SIZE + PORTION_SIZE;
+
+    pub fn new(old_id: LumpId, new_id: LumpId, portion: DataPortion) -> Self {
+        RenameRecord {
+            old_id,
+            new_id,
+            portion,
+        }
+    }
+
+    /// ジャーナルレコードが改名レコードであれば、その内容を返す.
+    pub fn from_journal_record(record: &JournalRecord<Vec<u8>>) -> Option<Self> {
+        match *record {
+            JournalRecord::Extension(TAG_RENAME, ref payload)
+                if payload.len() == Self::PAYLOAD_SIZE =>
+            {
+                let old_id = LumpId::new(BigEndian::read_u128(payload));
+                let new_id = LumpId::new(BigEndian::read_u128(&payload[LumpId::SIZE..]));
+                let payload = &payload[LumpId::SIZE * 2..];
+                let len = BigEndian::read_u16(payload);
+                let start = BigEndian::read_uint(&payload[LENGTH_SIZE..], PORTION_SIZE);
+                Some(RenameRecord {
+                    old_id,
+                    new_id,
+                    portion: DataPortion {
+                        start: Address::from_u64(start)?,
+                        len,
+                    },
+                })
             }
-            _ => track_panic!(
-                ErrorKind::StorageCorrupted,
-                "Unknown journal record tag: {}",
-                tag
-            ),
-        };
-        track_assert_eq!(record.checksum(), checksum, ErrorKind::StorageCorrupted);
-        Ok(record)
+            _ => None,
+        }
+    }
+
+    /// 改名レコードを表すジャーナルレコードに変換する.
+    pub(crate) fn to_journal_record(self) -> JournalRecord<Vec<u8>> {
+        let mut payload = vec![0; Self::PAYLOAD_SIZE];
+        BigEndian::write_u128(&mut payload, self.old_id.as_u128());
+        BigEndian::write_u128(&mut payload[LumpId::SIZE..], self.new_id.as_u128());
+        let buf = &mut payload[LumpId::SIZE * 2..];
+        BigEndian::write_u16(buf, self.portion.len);
+        BigEndian::write_uint(
+            &mut buf[LENGTH_SIZE..],
+            self.portion.start.as_u64(),
+            PORTION_SIZE,
+        );
+        JournalRecord::Extension(TAG_RENAME, payload)
     }
 }
 
-fn read_lump_id<R: Read>(reader: &mut R) -> Result<LumpId> {
-    let id = track_io!(reader.read_u128::<BigEndian>())?;
-    Ok(LumpId::new(id))
+/// ジャーナル領域に埋め込まれているlumpのIDの変更操作(`Storage::rename`)を表すレコード.
+///
+/// 改名後のIDに対する埋め込みPUTレコードと、改名前のIDに対するDELETEレコードを一つにまとめたもので、
+/// 復元時には、`old_id`のlumpがインデックスから取り除かれ、`new_id`のlumpとして`data`が登録される.
+///
+/// ペイロードは`new_id`、`data`、`old_id`の順に配置される.
+/// これによって、レコード内でのデータの開始位置が`JournalRecord::Embed`と同じ(`EMBEDDED_DATA_OFFSET`)になる.
+///
+/// ジャーナル上では、必須の拡張レコードとして書き込まれる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EmbeddedRenameRecord<'a> {
+    pub old_id: LumpId,
+    pub new_id: LumpId,
+    pub data: &'a [u8],
 }
+impl<'a> EmbeddedRenameRecord<'a> {
+    /// 改名レコードに格納可能なデータの最大長.
+    ///
+    /// ペイロード長の上限から、二つのIDの分を差し引いた値となる.
+    pub const MAX_DATA_SIZE: usize = 0xFFFF - LumpId::SIZE * 2;
+
+    pub fn new(old_id: LumpId, new_id: LumpId, data: &'a [u8]) -> Self {
+        debug_assert!(data.len() <= Self::MAX_DATA_SIZE);
+        EmbeddedRenameRecord {
+            old_id,
+            new_id,
+            data,
+        }
+    }
+
+    /// ジャーナルレコードが埋め込みデータの改名レコードであれば、その内容を返す.
+    pub fn from_journal_record<T: AsRef<[u8]>>(record: &'a JournalRecord<T>) -> Option<Self> {
+        match *record {
+            JournalRecord::Extension(TAG_EMBEDDED_RENAME, ref payload)
+                if payload.as_ref().len() >= LumpId::SIZE * 2 =>
+            {
+                let payload = payload.as_ref();
+                let (payload, old_id) = payload.split_at(payload.len() - LumpId::SIZE);
+                Some(EmbeddedRenameRecord {
+                    old_id: LumpId::new(BigEndian::read_u128(old_id)),
+                    new_id: LumpId::new(BigEndian::read_u128(payload)),
+                    data: &payload[LumpId::SIZE..],
+                })
+            }
+            _ => None,
+        }
+    }
 
-fn lump_id_to_u128(id: &LumpId) -> [u8; LumpId::SIZE] {
-    let mut bytes = [0; LumpId::SIZE];
-    BigEndian::write_u128(&mut bytes, id.as_u128());
-    bytes
+    /// 埋め込みデータの改名レコードを表すジャーナルレコードに変換する.
+    pub(crate) fn to_journal_record(self) -> JournalRecord<Vec<u8>> {
+        let mut payload = Vec::with_capacity(LumpId::SIZE * 2 + self.data.len());
+        payload.extend_from_slice(&self.new_id.as_u128().to_be_bytes());
+        payload.extend_from_slice(self.data);
+        payload.extend_from_slice(&self.old_id.as_u128().to_be_bytes());
+        JournalRecord::Extension(TAG_EMBEDDED_RENAME, payload)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::ops::Range;
     use trackable::result::TestResult;
 
     use super::*;
+    use crate::format::record::TAG_ESSENTIAL_FLAG;
     use crate::lump::LumpId;
     use crate::storage::portion::DataPortion;
     use crate::storage::Address;
@@ -228,6 +611,14 @@ mod tests {
                 start: lump_id("123"),
                 end: lump_id("456"),
             }),
+            JournalRecord::Extension(0x80, b"foo".to_vec()),
+            JournalRecord::Extension(0xBF, vec![]),
+            track!(AuditRecord::new(
+                AuditOperation::Put,
+                lump_id("444"),
+                &[1; 16]
+            ))?
+            .to_journal_record(),
         ];
         for e0 in records {
             let mut buf = Vec::new();
@@ -256,6 +647,193 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn epoch_works() -> TestResult {
+        let e: JournalRecord<Vec<u8>> = JournalRecord::Delete(lump_id("000"));
+        let mut buf = Vec::new();
+        track!(e.write_to_with(&mut buf, Some(3), JournalChecksum::Adler32))?;
+        assert_eq!(
+            track!(JournalRecord::read_from_with(
+                &buf[..],
+                Some(3),
+                JournalChecksum::Adler32
+            ))?,
+            e
+        );
+
+        // 異なるエポックでは読み込めない
+        assert!(
+            JournalRecord::read_from_with(&buf[..], Some(2), JournalChecksum::Adler32).is_err()
+        );
+        assert!(JournalRecord::read_from(&buf[..]).is_err());
+
+        // エポック`0`は、エポック導入以前の形式と互換性がある
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+        assert_eq!(
+            track!(JournalRecord::read_from_with(
+                &buf[..],
+                Some(0),
+                JournalChecksum::Adler32
+            ))?,
+            e
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_extension_record_works() -> TestResult {
+        // 非必須の拡張レコードは読み飛ばし可能
+        let e: JournalRecord<Vec<u8>> = JournalRecord::Extension(0xA0, b"foo".to_vec());
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to(&mut buf))?;
+
+        let mut reader = &buf[..];
+        let e1 = track!(JournalRecord::read_from(&mut reader))?;
+        assert_eq!(e1.external_size(), e.external_size());
+        assert_eq!(e1, e);
+        let e2 = track!(JournalRecord::read_from(&mut reader))?;
+        assert_eq!(e2, JournalRecord::EndOfRecords);
+
+        // 必須の拡張レコードは解釈できなければエラー
+        let e: JournalRecord<Vec<u8>> = JournalRecord::Extension(0xE0, b"foo".to_vec());
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+        assert_eq!(
+            JournalRecord::read_from(&buf[..]).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dedup_put_record_works() -> TestResult {
+        let portion = DataPortion {
+            start: Address::from(0x12_3456),
+            len: 3,
+        };
+        let record = DedupPutRecord::new(lump_id("111"), portion, 0x0123_4567_89AB_CDEF);
+        let e = record.to_journal_record();
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+
+        // 必須の拡張レコードだが、既知のものなので読み込める
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(DedupPutRecord::from_journal_record(&e), Some(record));
+        assert_eq!(
+            DedupPutRecord::from_journal_record(&JournalRecord::Put(lump_id("111"), portion)),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn audit_record_works() -> TestResult {
+        let portion = DataPortion {
+            start: Address::from(0x12_3456),
+            len: 3,
+        };
+        let mut record = track!(AuditRecord::new(
+            AuditOperation::Put,
+            lump_id("111"),
+            &[7; 20]
+        ))?;
+        record.seqno = 0x0123_4567_89AB_CDEF;
+
+        // 監査付きのPUTレコードは、必須の拡張レコードだが、既知のものなので読み込める
+        let mut buf = Vec::new();
+        track!(record.to_audited_put_record(portion).write_to(&mut buf))?;
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(AuditRecord::from_journal_record(&e), Some(record.clone()));
+        assert_eq!(
+            AuditRecord::audited_put(&e),
+            Some((lump_id("111"), portion))
+        );
+
+        // 単独の監査レコードとして書き直しても、内容は変わらない
+        let e = record.to_journal_record();
+        assert_eq!(AuditRecord::from_journal_record(&e), Some(record.clone()));
+        assert_eq!(AuditRecord::audited_put(&e), None);
+
+        record.operation = AuditOperation::Delete;
+        let mut buf = Vec::new();
+        track!(record.to_audited_delete_record().write_to(&mut buf))?;
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(AuditRecord::from_journal_record(&e), Some(record));
+        assert_eq!(AuditRecord::audited_delete(&e), Some(lump_id("111")));
+        assert_eq!(
+            AuditRecord::from_journal_record(&JournalRecord::Delete(lump_id("111"))),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn link_record_works() -> TestResult {
+        let portion = DataPortion {
+            start: Address::from(0x12_3456),
+            len: 3,
+        };
+        let record = LinkRecord::new(lump_id("222"), portion);
+        let e = record.to_journal_record();
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(LinkRecord::from_journal_record(&e), Some(record));
+        assert_eq!(DedupPutRecord::from_journal_record(&e), None);
+        Ok(())
+    }
+
+    #[test]
+    fn in_place_put_record_works() -> TestResult {
+        let portion = DataPortion {
+            start: Address::from(0x12_3456),
+            len: 3,
+        };
+        let record = InPlacePutRecord::new(lump_id("333"), portion);
+        let e = record.to_journal_record();
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(InPlacePutRecord::from_journal_record(&e), Some(record));
+        assert_eq!(LinkRecord::from_journal_record(&e), None);
+
+        // 必須レコードではない
+        assert!(matches!(e, JournalRecord::Extension(tag, _) if tag & TAG_ESSENTIAL_FLAG == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn embedded_rename_record_works() -> TestResult {
+        let record = EmbeddedRenameRecord::new(lump_id("111"), lump_id("222"), b"foo");
+        let e = record.to_journal_record();
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+
+        // データの開始位置は、埋め込みPUTレコードと同じ
+        assert_eq!(&buf[EMBEDDED_DATA_OFFSET..][..3], b"foo");
+
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(EmbeddedRenameRecord::from_journal_record(&e), Some(record));
+        assert_eq!(RenameRecord::from_journal_record(&e), None);
+        assert!(matches!(e, JournalRecord::Extension(tag, _) if tag & TAG_ESSENTIAL_FLAG != 0));
+
+        let record = EmbeddedRenameRecord::new(
+            lump_id("111"),
+            lump_id("222"),
+            &[1; EmbeddedRenameRecord::MAX_DATA_SIZE],
+        );
+        let e = record.to_journal_record();
+        let mut buf = Vec::new();
+        track!(e.write_to(&mut buf))?;
+        let e = track!(JournalRecord::read_from(&buf[..]))?;
+        assert_eq!(EmbeddedRenameRecord::from_journal_record(&e), Some(record));
+        Ok(())
+    }
+
     fn lump_id(id: &str) -> LumpId {
         id.parse().unwrap()
     }
@@ -1,24 +1,36 @@
-use prometrics::metrics::MetricBuilder;
-use std::collections::VecDeque;
+use prometrics::metrics::{Gauge, MetricBuilder};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
 use std::io::Write;
 use std::ops::Range;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+use super::admission::{AdmissionController, JournalAdmissionAction};
+use super::gc_scanner::GcScanner;
 use super::options::JournalRegionOptions;
-use super::record::{JournalEntry, JournalRecord, EMBEDDED_DATA_OFFSET};
+use super::record::{
+    AuditRecord, CleanShutdownRecord, DedupPutRecord, EmbeddedRenameRecord, InPlacePutRecord,
+    JournalChecksum, JournalEntry, JournalRecord, LinkRecord, RenameRecord, CHECKSUM_SIZE,
+    EMBEDDED_DATA_OFFSET, LENGTH_SIZE, PORTION_SIZE, TAG_SIZE,
+};
 use super::ring_buffer::JournalRingBuffer;
-use super::{JournalHeader, JournalHeaderRegion};
+use super::sync_controller::SyncIntervalController;
+use super::{
+    JournalCursor, JournalHeader, JournalHeaderRegion, EPOCH_MINOR_VERSION,
+    EXTENSION_RECORDS_MINOR_VERSION,
+};
 use crate::block::BlockSize;
 use crate::lump::LumpId;
-use crate::metrics::JournalRegionMetrics;
-use crate::nvm::NonVolatileMemory;
-use crate::storage::index::LumpIndex;
+use crate::metrics::{IoOrigin, JournalRegionMetrics};
+use crate::nvm::{AccessPattern, NonVolatileMemory};
+use crate::storage::checkpoint::CheckpointLocation;
+use crate::storage::dedup::ContentHashes;
+use crate::storage::index::{LumpIndex, LumpIndexLoader};
+use crate::storage::index_shadow::IndexShadowStamp;
 use crate::storage::portion::{DataPortion, JournalPortion, Portion};
-use crate::storage::Address;
+use crate::storage::{Address, JournalStats};
 use crate::{ErrorKind, Result};
 
-// 一回の空き時間処理で実行するGC回数
-const GC_COUNT_IN_SIDE_JOB: usize = 64;
-
 /// デバイスに操作を記録するためのジャーナル領域.
 ///
 /// ジャーナル領域はリングバッファ形式で管理されている.
@@ -37,64 +49,251 @@ pub struct JournalRegion<N: NonVolatileMemory> {
     metrics: JournalRegionMetrics,
     gc_queue: VecDeque<JournalEntry>,
     sync_countdown: usize, // `0`になったら`sync()`を呼び出す
+    sync_deferral: bool,
+    deferred_appends: usize, // 同期が先送りされている間に追記されたレコードの数
     options: JournalRegionOptions,
     gc_after_append: bool,
+    gc_scanner: Option<GcScanner>,
+    sync_controller: Option<SyncIntervalController>,
+    admission_controller: Option<AdmissionController>,
+    verify_embedded_data: bool,
+    audit_trail: bool,
+    audit_retention: u64,
+    next_audit_seqno: u64,
+    clean_shutdown: bool,
+    metric_builder: MetricBuilder,
+    #[allow(dead_code)] // メトリクスの登録を維持するために保持する
+    gc_config_metric: Gauge,
 }
 impl<N> JournalRegion<N>
 where
     N: NonVolatileMemory,
 {
     pub fn journal_entries(&mut self) -> Result<(u64, u64, u64, Vec<JournalEntry>)> {
-        self.ring_buffer.journal_entries()
+        track!(self.with_sequential_access(|this| this.ring_buffer.journal_entries()))
+    }
+
+    /// `cursor`が指す位置から、最大`max_entries`個のジャーナルエントリを読み込む.
+    pub fn journal_entries_step(
+        &mut self,
+        cursor: JournalCursor,
+        max_entries: usize,
+    ) -> Result<(Vec<JournalEntry>, Option<JournalCursor>)> {
+        track!(self.ring_buffer.read_entries_step(cursor, max_entries))
     }
 
     /// ジャーナル領域の初期化を行う.
     ///
     /// 具体的には`nmヘッダと最初のエントリ(EndOfEntries)を書き込む
-    pub fn initialize<W: Write>(mut writer: W, block_size: BlockSize) -> Result<()> {
-        track!(JournalHeader::new().write_to(&mut writer, block_size))?;
-        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to(&mut writer))?;
+    pub fn initialize<W: Write>(
+        mut writer: W,
+        block_size: BlockSize,
+        checksum: JournalChecksum,
+        header_slots: u8,
+    ) -> Result<()> {
+        let header = JournalHeader::new();
+        track!(header.write_region_to(&mut writer, block_size, header_slots))?;
+        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to_with(
+            &mut writer,
+            header.epoch,
+            checksum
+        ))?;
         Ok(())
     }
 
     /// ジャーナル領域を開く。
     ///
-    /// この関数の中で`index`の再構築も行われる.
+    /// 結果には、ヘッダに記録されていたチェックポイントの位置も含まれる.
+    ///
+    /// この関数の中ではエントリ群の復元は行われないため、
+    /// 呼び出し後には`restore`ないし`restore_from_checkpoint`のいずれかを呼び出す必要がある.
     pub fn open(
         nvm: N,
-        index: &mut LumpIndex,
         metric_builder: &MetricBuilder,
         options: JournalRegionOptions,
-    ) -> Result<JournalRegion<N>>
+    ) -> Result<(JournalRegion<N>, Option<CheckpointLocation>)>
     where
         N: NonVolatileMemory,
     {
         track_assert!(
             options.block_size.contains(nvm.block_size()),
-            ErrorKind::InvalidInput; options.block_size, nvm.block_size()
+            ErrorKind::BlockSizeMismatch,
+            "The journal block size is not aligned to the NVM: journal_block_size={}, nvm_block_size={}",
+            options.block_size.as_u16(),
+            nvm.block_size().as_u16()
         );
+        if let Some(ref admission) = options.admission_control {
+            track_assert!(
+                (0.0..=1.0).contains(&admission.high_watermark),
+                ErrorKind::InvalidInput,
+                "Journal admission high watermark out of range: {}",
+                admission.high_watermark
+            );
+        }
         let block_size = options.block_size;
 
-        let (header_nvm, ring_buffer_nvm) =
-            track!(nvm.split(JournalHeader::region_size(block_size) as u64))?;
+        let (header_nvm, ring_buffer_nvm) = track!(nvm.split(JournalHeader::slots_region_size(
+            block_size,
+            options.header_slots
+        )))?;
 
-        let mut header_region = JournalHeaderRegion::new(header_nvm, block_size);
+        let mut header_region =
+            JournalHeaderRegion::new(header_nvm, block_size, options.header_slots);
         let header = track!(header_region.read_header())?;
-        let ring_buffer =
-            JournalRingBuffer::new(ring_buffer_nvm, header.ring_buffer_head, metric_builder);
+        let mut ring_buffer = JournalRingBuffer::new(
+            ring_buffer_nvm,
+            header.ring_buffer_head,
+            header.epoch,
+            options.checksum,
+            options.safe_flush,
+            metric_builder,
+        );
+        ring_buffer.set_max_write_buffer_size(options.max_write_buffer_size);
 
         let metrics = JournalRegionMetrics::new(metric_builder, ring_buffer.metrics().clone());
-        let mut journal = JournalRegion {
+        ring_buffer.set_io_counters(
+            metrics.nvm_read_bytes.clone(),
+            metrics.nvm_written_bytes.clone(),
+        );
+        let gc_config_metric = gc_config_metric(metric_builder, &options);
+        let mut options = options;
+        let sync_controller = options
+            .adaptive_sync
+            .map(|o| SyncIntervalController::new(o, options.sync_interval));
+        if let Some(ref controller) = sync_controller {
+            options.sync_interval = controller.interval();
+        }
+        metrics.sync_interval.set(options.sync_interval as f64);
+        let admission_controller = options.admission_control.map(AdmissionController::new);
+        let journal = JournalRegion {
             header_region,
             ring_buffer,
             metrics,
             gc_queue: VecDeque::new(),
             sync_countdown: options.sync_interval,
+            sync_deferral: false,
+            deferred_appends: 0,
             options,
             gc_after_append: true,
+            gc_scanner: None,
+            sync_controller,
+            admission_controller,
+            verify_embedded_data: false,
+            audit_trail: false,
+            audit_retention: 0,
+            next_audit_seqno: 0,
+            clean_shutdown: false,
+            metric_builder: metric_builder.clone(),
+            gc_config_metric,
         };
-        track!(journal.restore(index))?;
-        Ok(journal)
+        Ok((journal, header.checkpoint))
+    }
+
+    /// チェックポイントを用いて、エントリ群を読み込まずにリングバッファの状態を復元する.
+    ///
+    /// チェックポイント作成後にジャーナルへの追記が行われていた場合には、何もせずに`false`を返す.
+    ///
+    /// チェックポイントはクローズ時にのみ書き出されるため、復元に成功した場合にはクリーンシャットダウンとして扱われる.
+    pub fn restore_from_checkpoint(&mut self, checkpoint: &CheckpointLocation) -> Result<bool> {
+        let tail = checkpoint.ring_buffer_tail;
+        let restored = track!(self.with_io_origin(IoOrigin::Restore, |this| this
+            .ring_buffer
+            .restore_tail(tail)))?;
+        self.clean_shutdown = restored;
+        Ok(restored)
+    }
+
+    /// インデックスのシャドウファイルに記録されていた状態を用いて、エントリ群を読み込まずにリングバッファの状態を復元する.
+    ///
+    /// ジャーナルヘッダの始端位置ないしエポックが記録時点のものと異なる場合や、
+    /// 記録後にジャーナルへの追記が行われていた場合には、何もせずに`false`を返す.
+    pub fn restore_from_index_shadow(&mut self, stamp: &IndexShadowStamp) -> Result<bool> {
+        let head = self.ring_buffer.unreleased_head();
+        if head != stamp.ring_buffer_head || self.ring_buffer.epoch_at(head) != stamp.epoch {
+            return Ok(false);
+        }
+        let tail = stamp.ring_buffer_tail;
+        let restored = track!(self.with_io_origin(IoOrigin::Restore, |this| this
+            .ring_buffer
+            .restore_tail(tail)))?;
+        self.clean_shutdown = restored && stamp.clean_shutdown;
+        Ok(restored)
+    }
+
+    /// インデックスのシャドウファイルに記録するための、ジャーナルの現在の状態を返す.
+    ///
+    /// 記録された状態がオープン時に検証可能となるように、バッファ内のレコード群は事前に同期される.
+    pub fn index_shadow_stamp(&mut self, clean_shutdown: bool) -> Result<IndexShadowStamp> {
+        track!(self.sync())?;
+        let ring_buffer_head = self.ring_buffer.unreleased_head();
+        Ok(IndexShadowStamp {
+            ring_buffer_head,
+            ring_buffer_tail: self.ring_buffer.tail(),
+            epoch: self.ring_buffer.epoch_at(ring_buffer_head),
+            clean_shutdown,
+        })
+    }
+
+    /// 永続化済みのリングバッファの始端位置と、現在の終端位置を返す.
+    pub fn ring_buffer_position(&self) -> (u64, u64) {
+        (self.ring_buffer.unreleased_head(), self.ring_buffer.tail())
+    }
+
+    /// 前回のクローズが正常に行われていたかどうかを返す.
+    ///
+    /// ジャーナルの最後のレコードがクリーンシャットダウンの印(あるいはレコードが一つも存在しない)の場合や、
+    /// チェックポイントから状態が復元された場合に`true`となる.
+    pub fn was_clean_shutdown(&self) -> bool {
+        self.clean_shutdown
+    }
+
+    /// クリーンシャットダウンの印となるレコードをジャーナルに追記する.
+    ///
+    /// 印の後ろに他のレコードが続かないように、GCは行わない.
+    /// ジャーナル領域に空きがない場合には、印は書き込まれず、
+    /// 次回の起動時にはクリーンシャットダウンではなかったものとして扱われる.
+    ///
+    /// 拡張レコードを書き込めない場合(`supports_extension_records`)にも、印は書き込まれない.
+    pub fn records_clean_shutdown(&mut self, index: &mut LumpIndex) -> Result<()> {
+        if !self.supports_extension_records() {
+            return Ok(());
+        }
+        let record = CleanShutdownRecord.to_journal_record();
+        if let Err(e) = track!(self.append_record(index, &record)) {
+            if *e.kind() != ErrorKind::StorageFull {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// データ領域に書き込まれたチェックポイントの位置を、ジャーナルヘッダに記録する.
+    ///
+    /// この時点でのリングバッファの終端位置も合わせて記録される.
+    pub fn write_checkpoint(&mut self, offset: u64, size: u64, checksum: u32) -> Result<()> {
+        track!(self.sync())?;
+        let checkpoint = CheckpointLocation {
+            ring_buffer_tail: self.ring_buffer.tail(),
+            offset,
+            size,
+            checksum,
+        };
+
+        // GCキュー内のエントリが失われないように、(`head`ではなく)既に永続化済みの始端位置を用いる
+        let ring_buffer_head = self.ring_buffer.unreleased_head();
+        let header = JournalHeader {
+            ring_buffer_head,
+            checkpoint: Some(checkpoint),
+            epoch: self.ring_buffer.epoch_at(ring_buffer_head),
+        };
+        track!(self.header_region.write_header(&header))?;
+        Ok(())
+    }
+
+    /// ジャーナルヘッダに記録されているチェックポイントを破棄する.
+    pub fn clear_checkpoint(&mut self) -> Result<()> {
+        let ring_buffer_head = self.ring_buffer.unreleased_head();
+        track!(self.write_journal_header(ring_buffer_head))
     }
 
     /// PUT操作をジャーナルに記録する.
@@ -109,6 +308,62 @@ where
         Ok(())
     }
 
+    /// 重複排除付きのPUT操作をジャーナルに記録する.
+    pub fn records_dedup_put(
+        &mut self,
+        index: &mut LumpIndex,
+        record: &DedupPutRecord,
+    ) -> Result<()> {
+        track!(self.check_extension_records_supported())?;
+        let record = record.to_journal_record();
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
+    /// 別名の作成操作をジャーナルに記録する.
+    pub fn records_link(&mut self, index: &mut LumpIndex, record: &LinkRecord) -> Result<()> {
+        track!(self.check_extension_records_supported())?;
+        let record = record.to_journal_record();
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
+    /// データ部分領域への上書き更新が行われたことをジャーナルに記録する.
+    ///
+    /// 拡張レコードを書き込めない場合には何も行わない.
+    pub fn records_in_place_put(
+        &mut self,
+        index: &mut LumpIndex,
+        record: &InPlacePutRecord,
+    ) -> Result<()> {
+        if !self.supports_extension_records() {
+            return Ok(());
+        }
+        let record = record.to_journal_record();
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
+    /// lumpのIDの変更操作をジャーナルに記録する.
+    pub fn records_rename(&mut self, index: &mut LumpIndex, record: &RenameRecord) -> Result<()> {
+        track!(self.check_extension_records_supported())?;
+        let record = record.to_journal_record();
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
+    /// ジャーナル領域に埋め込まれているlumpのIDの変更操作をジャーナルに記録する.
+    pub fn records_embedded_rename(
+        &mut self,
+        index: &mut LumpIndex,
+        record: &EmbeddedRenameRecord,
+    ) -> Result<()> {
+        track!(self.check_extension_records_supported())?;
+        let record = record.to_journal_record();
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
     /// 埋め込みPUT操作をジャーナルに記録する.
     pub fn records_embed(
         &mut self,
@@ -128,6 +383,56 @@ where
         Ok(())
     }
 
+    /// 操作を伴わない、単独の監査レコードをジャーナルに記録する.
+    ///
+    /// 監査証跡が無効な場合(`is_audit_trail_enabled`)には何も行わない.
+    pub fn records_audit(&mut self, index: &mut LumpIndex, record: &AuditRecord) -> Result<()> {
+        if self.is_audit_trail_enabled() {
+            let record = self.assign_audit_seqno(record).to_journal_record();
+            track!(self.append_record_with_gc(index, &record))?;
+        }
+        Ok(())
+    }
+
+    /// 監査付きのPUT操作をジャーナルに記録する.
+    ///
+    /// PUT操作と監査レコードは、一つのレコードとして書き込まれる.
+    pub fn records_audited_put(
+        &mut self,
+        index: &mut LumpIndex,
+        record: &AuditRecord,
+        portion: DataPortion,
+    ) -> Result<()> {
+        track!(self.check_extension_records_supported())?;
+        let record = self
+            .assign_audit_seqno(record)
+            .to_audited_put_record(portion);
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
+    /// 監査付きのDELETE操作をジャーナルに記録する.
+    ///
+    /// DELETE操作と監査レコードは、一つのレコードとして書き込まれる.
+    pub fn records_audited_delete(
+        &mut self,
+        index: &mut LumpIndex,
+        record: &AuditRecord,
+    ) -> Result<()> {
+        track!(self.check_extension_records_supported())?;
+        let record = self.assign_audit_seqno(record).to_audited_delete_record();
+        track!(self.append_record_with_gc(index, &record))?;
+        Ok(())
+    }
+
+    /// 監査レコードに通番を割り当てる.
+    fn assign_audit_seqno(&mut self, record: &AuditRecord) -> AuditRecord {
+        let mut record = record.clone();
+        record.seqno = self.next_audit_seqno;
+        self.next_audit_seqno += 1;
+        record
+    }
+
     // RANGE-DELETE操作をジャーナルに記録する。
     pub fn records_delete_range(
         &mut self,
@@ -140,21 +445,94 @@ where
     }
 
     /// ジャーナル領域に埋め込まれたデータを取得する.
+    ///
+    /// 検証が有効になっている場合には、データを含むレコードのチェックサムの検証も行われる.
     pub fn get_embedded_data(&mut self, portion: JournalPortion) -> Result<Vec<u8>> {
         let offset = portion.start.as_u64();
+        if self.verify_embedded_data {
+            return track!(self
+                .ring_buffer
+                .read_verified_embedded_data(offset, portion.len));
+        }
         let mut buf = vec![0; portion.len as usize];
         track!(self.ring_buffer.read_embedded_data(offset, &mut buf))?;
         Ok(buf)
     }
 
+    /// 埋め込みデータの取得時に、チェックサムの検証を行うかどうかを設定する.
+    pub fn set_embedded_data_verification(&mut self, enabled: bool) {
+        self.verify_embedded_data = enabled;
+    }
+
+    /// 監査レコードをジャーナルに記録するかどうかを設定する.
+    pub fn set_audit_trail(&mut self, enabled: bool) {
+        self.audit_trail = enabled;
+    }
+
+    /// 監査レコードの記録が有効かどうかを返す.
+    ///
+    /// 監査証跡の設定が有効で、かつ、拡張レコードを書き込める場合に`true`となる.
+    pub fn is_audit_trail_enabled(&self) -> bool {
+        self.audit_trail && self.supports_extension_records()
+    }
+
+    /// GCによって回収せずに保持する監査レコードの数を設定する.
+    ///
+    /// 通番が新しいものから`retention`個までの監査レコードが保持の対象となる.
+    /// 監査証跡が無効な場合には、監査レコードは保持されない.
+    pub fn set_audit_retention(&mut self, retention: u64) {
+        self.audit_retention = retention;
+    }
+
+    /// ジャーナル上の監査レコード群を走査して、次に割り当てる通番を求める.
+    ///
+    /// エントリ群を読み込まずに状態が復元された場合(e.g., `restore_from_checkpoint`)に、
+    /// 既存の監査レコードと通番が重複しないようにするために使用する.
+    pub fn restore_audit_seqno(&mut self) -> Result<()> {
+        track!(self.with_sequential_access(|this| {
+            this.with_io_origin(IoOrigin::Restore, |this| {
+                let mut cursor = Some(JournalCursor::default());
+                while let Some(c) = cursor {
+                    let (entries, next) = track!(this
+                        .ring_buffer
+                        .read_unreleased_entries_step(c, RESTORE_SCAN_BATCH_SIZE))?;
+                    for entry in entries {
+                        if let Some(seqno) = next_audit_seqno(&entry.record) {
+                            this.next_audit_seqno = cmp::max(this.next_audit_seqno, seqno);
+                        }
+                    }
+                    cursor = next;
+                }
+                Ok(())
+            })
+        }))
+    }
+
+    /// レコードが、保持期間内の監査レコード(あるいは監査付きのPUTないしDELETEレコード)かどうかを判定する.
+    fn is_retained_audit_record(&self, record: &JournalRecord<Vec<u8>>) -> bool {
+        let oldest = self.next_audit_seqno.saturating_sub(self.audit_retention);
+        self.audit_trail
+            && AuditRecord::from_journal_record(record).is_some_and(|r| r.seqno >= oldest)
+    }
+
     /// 補助タスクを一単位実行する.
-    pub fn run_side_job_once(&mut self, index: &mut LumpIndex) -> Result<()> {
+    ///
+    /// `deadline`が指定されている場合には、その時刻を過ぎた時点でGC処理を打ち切る.
+    pub fn run_side_job_once(
+        &mut self,
+        index: &mut LumpIndex,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
         if self.gc_queue.is_empty() {
-            track!(self.fill_gc_queue())?;
+            track!(self.fill_gc_queue(false))?;
         } else if self.sync_countdown != self.options.sync_interval {
             track!(self.sync())?;
         } else {
-            for _ in 0..GC_COUNT_IN_SIDE_JOB {
+            for i in 0..self.options.gc_batch_size {
+                // 制限時間が極端に短い場合でもGCが停滞しないように、最低でも一レコード分は処理する
+                if i > 0 && deadline.is_some_and(|d| d <= Instant::now()) {
+                    break;
+                }
                 track!(self.gc_once(index))?;
             }
             track!(self.try_sync())?;
@@ -162,6 +540,66 @@ where
         Ok(())
     }
 
+    /// 書き込みの受付制御(`StorageBuilder::journal_admission_control`)を行う.
+    ///
+    /// ジャーナル領域の使用率が閾値を超えており、かつ、GCが追記に追いついていない場合には、
+    /// 設定に応じて`ErrorKind::JournalGcLagging`エラーを返すか、ジャーナル領域全体のGCを実行する.
+    pub fn check_admission(&mut self, index: &mut LumpIndex) -> Result<()> {
+        let usage = self.ring_buffer.usage();
+        let capacity = self.ring_buffer.capacity();
+        let gc_debt = self.metrics.gc_debt_bytes();
+        let action = match self.admission_controller.as_mut() {
+            None => return Ok(()),
+            Some(c) => {
+                if !c.should_throttle(Instant::now(), usage, capacity, gc_debt) {
+                    return Ok(());
+                }
+                c.action()
+            }
+        };
+        match action {
+            JournalAdmissionAction::Reject => {
+                self.metrics.admission_rejections.increment();
+                track_panic!(
+                    ErrorKind::JournalGcLagging,
+                    "Journal GC cannot keep up with appends: usage={}, capacity={}, gc_debt={}",
+                    usage,
+                    capacity,
+                    gc_debt
+                );
+            }
+            JournalAdmissionAction::ForceGc => {
+                self.metrics.admission_forced_gcs.increment();
+                track!(self.gc_all_entries(index))
+            }
+        }
+    }
+
+    /// リングバッファの先頭ブロックを読み込み、読み込んだバイト数を返す.
+    ///
+    /// ウォームアップ用.
+    pub fn touch_first_block(&mut self) -> Result<u64> {
+        let size = cmp::min(
+            u64::from(self.options.block_size.as_u16()),
+            self.ring_buffer.capacity(),
+        );
+        let mut buf = vec![0; size as usize];
+        track!(self.ring_buffer.read_embedded_data(0, &mut buf))?;
+        Ok(size)
+    }
+
+    /// ジャーナル領域の統計情報を返す.
+    pub fn stats(&self) -> JournalStats {
+        JournalStats {
+            capacity_bytes: self.ring_buffer.capacity(),
+            usage_bytes: self.ring_buffer.usage(),
+            unreleased_head: self.ring_buffer.unreleased_head(),
+            head: self.ring_buffer.head(),
+            tail: self.ring_buffer.tail(),
+            unsynced_bytes: self.ring_buffer.unsynced_bytes(),
+        }
+    }
+
     /// ジャーナル領域用のメトリクスを返す.
     pub fn metrics(&self) -> &JournalRegionMetrics {
         &self.metrics
@@ -169,23 +607,65 @@ where
 
     /// GC処理を一単位実行する.
     fn gc_once(&mut self, index: &mut LumpIndex) -> Result<()> {
+        track!(self.with_io_origin(IoOrigin::Gc, |this| this.gc_once_with_origin(index)))
+    }
+
+    fn gc_once_with_origin(&mut self, index: &mut LumpIndex) -> Result<()> {
         if self.gc_queue.is_empty() && self.ring_buffer.capacity() < self.ring_buffer.usage() * 2 {
             // 空き領域が半分を切った場合には、`run_side_job_once()`以外でもGCを開始する
             // ("半分"という閾値に深い意味はない)
-            track!(self.fill_gc_queue())?;
+            //
+            // 別スレッドでの走査中であっても、空き領域が四分の一を切った場合には、
+            // 追記によってジャーナル領域が溢れないように、走査の完了を待機してGCを進める
+            let wait = self.ring_buffer.capacity() * 3 < self.ring_buffer.usage() * 4;
+            track!(self.fill_gc_queue(wait))?;
         }
         while let Some(entry) = self.gc_queue.pop_front() {
             self.metrics.gc_dequeued_records.increment();
-            if !self.is_garbage(index, &entry) {
-                // まだ回収できない場合には、ジャーナル領域の「末尾に」追加する
-                track!(self.append_record(index, &entry.record))?;
-                break;
-            }
+            // まだ回収できない場合には、ジャーナル領域の「末尾に」追加する
+            let rewritten_bytes = if !self.is_garbage(index, &entry) {
+                if let Some(r) = RenameRecord::from_journal_record(&entry.record) {
+                    // 改名レコードをそのまま末尾に移すと、復元時に、その後に作成された改名前のIDのlumpまで
+                    // 削除されてしまうので、改名後のIDに対するPUTレコードとして書き直す
+                    let record = JournalRecord::Put(r.new_id, r.portion);
+                    track!(self.append_record::<[_; 0]>(index, &record))?;
+                    record.external_size()
+                } else if let Some(r) = EmbeddedRenameRecord::from_journal_record(&entry.record) {
+                    // 同様の理由で、改名後のIDに対する埋め込みPUTレコードとして書き直す
+                    let record = JournalRecord::Embed(r.new_id, r.data);
+                    track!(self.append_record(index, &record))?;
+                    record.external_size()
+                } else if let Some((lump_id, portion)) = AuditRecord::audited_put(&entry.record)
+                    .filter(|_| !self.is_retained_audit_record(&entry.record))
+                {
+                    // 保持期間を過ぎた監査レコードは取り除いて、通常のPUTレコードとして書き直す
+                    let record = JournalRecord::Put(lump_id, portion);
+                    track!(self.append_record::<[_; 0]>(index, &record))?;
+                    record.external_size()
+                } else {
+                    track!(self.append_record(index, &entry.record))?;
+                    entry.record.external_size()
+                }
+            } else if self.is_retained_audit_record(&entry.record) {
+                // 操作の記録としては不要でも、保持期間内の監査レコードは回収せずに、単独の監査レコードとして書き直す
+                let record = AuditRecord::from_journal_record(&entry.record)
+                    .expect("Never fails")
+                    .to_journal_record();
+                track!(self.append_record(index, &record))?;
+                record.external_size()
+            } else {
+                continue;
+            };
+            self.metrics
+                .gc_rewritten_bytes
+                .add_u64(rewritten_bytes as u64);
+            break;
         }
 
         Ok(())
     }
 
+    #[allow(clippy::nonminimal_bool)]
     fn between(x: u64, y: u64, z: u64) -> bool {
         (x <= y && y <= z) || (z <= x && x <= y) || (y <= z && z <= x)
     }
@@ -198,36 +678,138 @@ where
     }
 
     pub fn gc_all_entries(&mut self, index: &mut LumpIndex) -> Result<()> {
-        let current_tail_position = self.ring_buffer.tail();
+        let mut gc = track!(self.start_full_gc(index))?;
+        while !track!(self.full_gc_step(index, &mut gc))? {}
+        Ok(())
+    }
 
-        loop {
-            let before_head = self.ring_buffer.head();
-            if self.gc_queue.is_empty() {
-                track!(self.fill_gc_queue())?;
-            }
-            track!(self.gc_all_entries_in_queue(index))?;
-            if Self::between(before_head, current_tail_position, self.ring_buffer.head()) {
-                break;
-            }
+    /// 全体GC(`gc_all_entries`)を、複数回に分けて実行するための準備を行う.
+    ///
+    /// 以後、`full_gc_step`を完了まで繰り返し呼び出すことで、`gc_all_entries`と同様のGCが行われる.
+    /// 各ステップの合間には、通常のGC(`run_side_job_once`等)が行われても構わない.
+    pub fn start_full_gc(&mut self, index: &mut LumpIndex) -> Result<FullGc> {
+        // GCキューに残っているエントリ群は、既に`head`の移動が済んでいるため、
+        // 各ステップの中で処理すると進捗がないものと判定されてしまう.
+        // そのため、事前に処理しておく.
+        track!(self.gc_all_entries_in_queue(index))?;
+        Ok(FullGc {
+            head: self.ring_buffer.head(),
+            tail: self.ring_buffer.tail(),
+        })
+    }
+
+    /// `start_full_gc`で開始した全体GCを、GCキュー一杯分だけ進める.
+    ///
+    /// 開始時点での末尾までのエントリ群の処理が完了した場合には`true`が返される.
+    pub fn full_gc_step(&mut self, index: &mut LumpIndex, gc: &mut FullGc) -> Result<bool> {
+        if self.gc_queue.is_empty() {
+            track!(self.fill_gc_queue(true))?;
+        }
+        track!(self.gc_all_entries_in_queue(index))?;
+
+        // 前回のステップ以降の`head`の移動(合間の通常のGCによるものを含む)が、開始時点の末尾を跨いだかを判定する
+        let head = self.ring_buffer.head();
+        if !Self::between(gc.head, gc.tail, head) {
+            gc.head = head;
+            return Ok(false);
         }
 
         // `gc_all_entries_in_queue`は`gc_queue`が空になるまで処理を行うため、
-        // 上のloopを抜けた後では
-        // `unreleased_head`と`head`の間のエントリは全て再配置済みである。
+        // この時点では`unreleased_head`と`head`の間のエントリは全て再配置済みである。
         // そこで現在の`head`の値をジャーナルエントリ開始位置として永続化し、
         // `unreleased_head`も更新する。
-        let ring_buffer_head = self.ring_buffer.head();
-        track!(self.write_journal_header(ring_buffer_head))?;
+        track!(self.write_journal_header(head))?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// ジャーナル内の生存しているレコード群を、リングバッファの先頭に詰めて書き直す.
+    ///
+    /// まず全体GCによって不要なレコード群を取り除いた上で、残ったレコード群を先頭から書き込み、
+    /// 最後にジャーナルヘッダを更新して、それを新しい開始位置とする.
+    /// 先頭の領域が生存レコード群と重なっている場合には、全体GCを繰り返してレコード群を後方に移動させる.
+    ///
+    /// ヘッダが更新されるまでは既存のレコード群は変更されないため、途中で中断された場合でも、
+    /// ジャーナルは書き直し前の状態のまま有効であり、再度呼び出すことで処理をやり直すことができる.
+    pub fn rewrite(&mut self, index: &mut LumpIndex) -> Result<()> {
+        let mut moved_bytes = 0;
+        loop {
+            track!(self.gc_all_entries(index))?;
+            track!(self.sync())?;
+            if self.ring_buffer.head() == 0 {
+                // 既に先頭に詰められている
+                return Ok(());
+            }
+            if self.ring_buffer.is_empty()
+                && self.ring_buffer.head() < u64::from(self.options.block_size.as_u16())
+            {
+                // レコードが存在せず、開始位置も先頭ブロック内にあるので、書き直す必要はない
+                return Ok(());
+            }
+
+            let written = track!(
+                self.with_io_origin(IoOrigin::Gc, |this| this.ring_buffer.write_to_front())
+            )?;
+            if let Some((tail, entries)) = written {
+                let header = JournalHeader {
+                    ring_buffer_head: 0,
+                    checkpoint: None,
+                    epoch: self.ring_buffer.epoch_at(0),
+                };
+                track!(self.header_region.write_header(&header))?;
+                self.ring_buffer.reset_to_front(tail);
+                for entry in entries {
+                    let embedded = match entry.record {
+                        JournalRecord::Embed(lump_id, ref data) => Some((lump_id, &data[..])),
+                        _ => EmbeddedRenameRecord::from_journal_record(&entry.record)
+                            .map(|r| (r.new_id, r.data)),
+                    };
+                    if let Some((lump_id, data)) = embedded {
+                        // 埋め込みデータの位置が変わったので、インデックスを更新する
+                        let portion = embedded_portion(entry.start, data);
+                        index.insert(lump_id, Portion::Journal(portion));
+                    }
+                }
+                return Ok(());
+            }
+
+            // 全体GCによってレコード群は後方に移動するので、先頭が空くまでそれを繰り返す
+            moved_bytes += self.ring_buffer.usage();
+            track_assert!(
+                moved_bytes < self.ring_buffer.capacity() * 2,
+                ErrorKind::StorageFull,
+                "Not enough free space in the journal region to rewrite: usage={}, capacity={}",
+                self.ring_buffer.usage(),
+                self.ring_buffer.capacity()
+            );
+        }
     }
 
     /// `ring_buffer_head`をジャーナルエントリ開始位置として永続化し、
     /// `unreleased_head`を`ring_buffer_head`に移動する。
     fn write_journal_header(&mut self, ring_buffer_head: u64) -> Result<()> {
-        let header = JournalHeader { ring_buffer_head };
+        let header = JournalHeader {
+            ring_buffer_head,
+            checkpoint: None,
+            epoch: self.ring_buffer.epoch_at(ring_buffer_head),
+        };
         track!(self.header_region.write_header(&header))?;
+        let usage = self.ring_buffer.usage();
         self.ring_buffer.release_bytes_until(ring_buffer_head);
+        self.metrics
+            .gc_debt_bytes
+            .subtract((usage - self.ring_buffer.usage()) as f64);
+
+        if self.options.minor_version >= EPOCH_MINOR_VERSION && self.ring_buffer.enable_epoch() {
+            // エポック導入以前のジャーナルを、エポックを用いる形式に移行する.
+            // ストレージのバージョンがエポック導入以前のままの場合には、古い読み手が扱えるように移行は行わない.
+            // 以降に書き込まれるレコードは(周回を跨ぐと)旧形式とは互換性がなくなるので、即座にヘッダに反映する.
+            let header = JournalHeader {
+                ring_buffer_head,
+                checkpoint: None,
+                epoch: self.ring_buffer.epoch_at(ring_buffer_head),
+            };
+            track!(self.header_region.write_header(&header))?;
+        }
         Ok(())
     }
 
@@ -235,6 +817,77 @@ where
         self.gc_after_append = enable;
     }
 
+    /// 別スレッドで実行中のGC対象エントリ群の走査があれば、その完了を待機して、結果をGCキューに取り込む.
+    pub fn wait_background_gc_scan(&mut self) -> Result<()> {
+        if self.gc_scanner.as_ref().is_some_and(|s| s.is_scanning()) {
+            track!(self.receive_gc_scan_result(true))?;
+        }
+        Ok(())
+    }
+
+    /// ジャーナルに拡張レコードを書き込めるかどうかを判定する.
+    ///
+    /// ストレージのバージョンが拡張レコードの導入以前のままの場合には、古い読み手が扱えないので`false`となる.
+    pub fn supports_extension_records(&self) -> bool {
+        self.options.minor_version >= EXTENSION_RECORDS_MINOR_VERSION
+    }
+
+    fn check_extension_records_supported(&self) -> Result<()> {
+        track_assert!(
+            self.supports_extension_records(),
+            ErrorKind::InvalidInput,
+            "Extension records are not supported by the storage: minor_version={}",
+            self.options.minor_version
+        );
+        Ok(())
+    }
+
+    /// ジャーナル領域の現在のオプションを返す.
+    pub fn options(&self) -> &JournalRegionOptions {
+        &self.options
+    }
+
+    /// GC対象エントリ群の読み込みが、専用のスレッドで行われているかどうかを返す.
+    pub fn is_background_gc_scan_enabled(&self) -> bool {
+        self.gc_scanner.is_some()
+    }
+
+    /// GCキューの長さを変更する.
+    pub fn set_gc_queue_size(&mut self, size: usize) {
+        self.options.gc_queue_size = size;
+        self.update_gc_config_metric();
+    }
+
+    /// 一回の補助タスクの中で実行されるGCの単位処理の回数を変更する.
+    pub fn set_gc_batch_size(&mut self, size: usize) {
+        self.options.gc_batch_size = size;
+        self.update_gc_config_metric();
+    }
+
+    fn update_gc_config_metric(&mut self) {
+        // ラベルの値は変更できないので、メトリクスを作り直す(古い方は破棄時に登録解除される)
+        self.gc_config_metric = gc_config_metric(&self.metric_builder, &self.options);
+    }
+
+    /// GC対象エントリ群の読み込みを、専用のスレッドで行うようにする.
+    ///
+    /// NVMが別スレッドからの読み込みをサポートしていない場合には、何もせずに`false`を返す.
+    pub fn enable_background_gc_scan(&mut self) -> Result<bool>
+    where
+        N: Send + 'static,
+    {
+        if self.gc_scanner.is_some() {
+            return Ok(true);
+        }
+        if let Some(mut reader) = track!(self.ring_buffer.try_clone_reader())? {
+            reader.set_io_origin(IoOrigin::Gc);
+            self.gc_scanner = Some(GcScanner::spawn(reader, self.ring_buffer.checksum()));
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     fn append_record_with_gc<B>(
         &mut self,
         index: &mut LumpIndex,
@@ -243,27 +896,117 @@ where
     where
         B: AsRef<[u8]>,
     {
+        let start = self.sync_controller.as_ref().map(|_| Instant::now());
         track!(self.append_record(index, record))?;
         if self.gc_after_append {
-            track!(self.gc_once(index))?; // レコード追記に合わせてGCを一単位行うことでコストを償却する
+            // レコード追記に合わせてGCを行うことでコストを償却する
+            for _ in 0..self.gc_steps_per_append() {
+                track!(self.gc_once(index))?;
+                if self.gc_queue.is_empty() {
+                    break;
+                }
+            }
         }
         track!(self.try_sync())?;
+        if let (Some(start), Some(controller)) = (start, self.sync_controller.as_mut()) {
+            if let Some(interval) = controller.record(start.elapsed()) {
+                self.set_sync_interval(interval);
+            }
+        }
         Ok(())
     }
 
+    /// レコードの追記一回毎に実行する、GCの単位処理の回数を返す.
+    ///
+    /// GCの自動調整が無効な場合には常に`1`.
+    /// 有効な場合には、ジャーナル領域の使用率に比例した回数(最小`1`、最大`adaptive_gc_max_steps`)となる.
+    fn gc_steps_per_append(&self) -> usize {
+        let max_steps = match self.options.adaptive_gc_max_steps {
+            None => return 1,
+            Some(max_steps) => cmp::max(max_steps, 1),
+        };
+        let capacity = cmp::max(self.ring_buffer.capacity(), 1);
+        let steps = (max_steps as u128 * u128::from(self.ring_buffer.usage()))
+            .div_ceil(u128::from(capacity));
+        cmp::min(cmp::max(steps as usize, 1), max_steps)
+    }
+
+    /// 同期間隔を変更する.
+    ///
+    /// 未同期のレコードの数は維持され、それが新しい間隔以上の場合には、次の追記時に同期が行われる.
+    fn set_sync_interval(&mut self, interval: usize) {
+        let unsynced = self.options.sync_interval - self.sync_countdown;
+        self.options.sync_interval = interval;
+        self.sync_countdown = interval.saturating_sub(unsynced);
+        self.metrics.sync_interval.set(interval as f64);
+    }
+
     fn append_record<B>(&mut self, index: &mut LumpIndex, record: &JournalRecord<B>) -> Result<()>
     where
         B: AsRef<[u8]>,
     {
+        let (tail, laps) = (self.ring_buffer.tail(), self.ring_buffer.laps());
         let embedded = track!(self.ring_buffer.enqueue(record))?;
+        if self.ring_buffer.laps() != laps {
+            self.record_wrap_around(tail, record.external_size());
+        }
+        self.metrics
+            .written_bytes
+            .add_u64(record.external_size() as u64);
+        self.metrics
+            .gc_debt_bytes
+            .add(record.external_size() as f64);
         if let Some((lump_id, portion)) = embedded {
             index.insert(lump_id, Portion::Journal(portion));
         }
+        self.update_nvm_buffer_metrics();
         Ok(())
     }
 
+    /// 追記によって、リングバッファの終端位置が先頭に戻ったこと(`GoToFront`)を記録する.
+    ///
+    /// 先頭に戻る際には、リングバッファの末尾の余りの部分が消費される上に、
+    /// 先頭部分の上書きに備えたGCが必要となるため、レイテンシの悪化の原因となり得る.
+    fn record_wrap_around(&self, tail: u64, record_size: usize) {
+        self.metrics.wrap_arounds.increment();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        self.metrics.last_wrap_around_timestamp.set(now);
+        debug!(
+            self.options.logger,
+            "The journal ring buffer wrapped around to the front";
+            "tail" => tail,
+            "record_size" => record_size,
+            "capacity" => self.ring_buffer.capacity(),
+            "laps" => self.ring_buffer.laps()
+        );
+    }
+
+    fn update_nvm_buffer_metrics(&self) {
+        let timestamp = self
+            .ring_buffer
+            .oldest_unsynced_write()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0.0, |d| d.as_secs_f64());
+        self.metrics
+            .unsynced_bytes
+            .set(self.ring_buffer.unsynced_bytes() as f64);
+        self.metrics.oldest_unsynced_record_timestamp.set(timestamp);
+        self.metrics
+            .write_buffer_high_water_bytes
+            .set(self.ring_buffer.write_buffer_high_water() as f64);
+    }
+
     fn try_sync(&mut self) -> Result<()> {
         if self.sync_countdown == 0 {
+            if self.sync_deferral && self.deferred_appends < self.options.sync_interval {
+                if self.deferred_appends == 0 {
+                    self.metrics.deferred_syncs.increment();
+                }
+                self.deferred_appends += 1;
+                return Ok(());
+            }
             track!(self.sync())?;
         } else {
             self.sync_countdown -= 1;
@@ -271,6 +1014,15 @@ where
         Ok(())
     }
 
+    /// 同期間隔(`sync_interval`)に基づく定期的な同期を、先送りするかどうかを設定する.
+    ///
+    /// 有効な間は、同期の時期に達した追記が行われても同期は行われず、次に無効な状態で追記が行われた時点や、
+    /// 補助タスク(`run_side_job_once`)ないし明示的な`sync`の呼び出し時まで先送りされる.
+    /// ただし、先送りされる追記の数は最大で`sync_interval`個であり、それを超えた場合には同期が行われる.
+    pub fn set_sync_deferral(&mut self, enabled: bool) {
+        self.sync_deferral = enabled;
+    }
+
     /// ジャーナルバッファをディスクへ確実に書き出すために
     /// 同期命令を発行する。
     ///
@@ -279,7 +1031,24 @@ where
     pub fn sync(&mut self) -> Result<()> {
         track!(self.ring_buffer.sync())?;
         self.sync_countdown = self.options.sync_interval;
+        self.deferred_appends = 0;
         self.metrics.syncs.increment();
+        self.update_nvm_buffer_metrics();
+        Ok(())
+    }
+
+    /// 致命的なエラーによる停止の直前に、可能な範囲でジャーナルの状態を永続化する.
+    ///
+    /// バッファ内のレコード群を同期した上で、GCによって再配置済みのエントリ群が存在する場合には、
+    /// 現在の始端位置をジャーナルヘッダに書き込む.
+    #[cfg(feature = "device")]
+    pub fn sync_before_stop(&mut self) -> Result<()> {
+        track!(self.sync())?;
+        let is_scanning = self.gc_scanner.as_ref().is_some_and(|s| s.is_scanning());
+        if self.gc_queue.is_empty() && !is_scanning {
+            let ring_buffer_head = self.ring_buffer.head();
+            track!(self.write_journal_header(ring_buffer_head))?;
+        }
         Ok(())
     }
 
@@ -290,12 +1059,30 @@ where
                 index.get(lump_id) != Some(Portion::Data(*portion))
             }
             JournalRecord::Embed(ref lump_id, ref data) => {
-                let portion = JournalPortion {
-                    start: entry.start + Address::from(EMBEDDED_DATA_OFFSET as u32),
-                    len: data.len() as u16,
-                };
+                let portion = embedded_portion(entry.start, data);
                 index.get(lump_id) != Some(Portion::Journal(portion))
             }
+            JournalRecord::Extension(..) => {
+                if let Some(r) = EmbeddedRenameRecord::from_journal_record(&entry.record) {
+                    let portion = embedded_portion(entry.start, r.data);
+                    return index.get(&r.new_id) != Some(Portion::Journal(portion));
+                }
+                let target = DedupPutRecord::from_journal_record(&entry.record)
+                    .map(|r| (r.lump_id, r.portion))
+                    .or_else(|| {
+                        LinkRecord::from_journal_record(&entry.record)
+                            .map(|r| (r.lump_id, r.portion))
+                    })
+                    .or_else(|| {
+                        RenameRecord::from_journal_record(&entry.record)
+                            .map(|r| (r.new_id, r.portion))
+                    })
+                    .or_else(|| AuditRecord::audited_put(&entry.record));
+                match target {
+                    Some((lump_id, portion)) => index.get(&lump_id) != Some(Portion::Data(portion)),
+                    None => true,
+                }
+            }
             _ => true,
         }
     }
@@ -303,8 +1090,14 @@ where
     /// GC用のキューの内容を補填する.
     ///
     /// 必要に応じて、ジャーナルヘッダの更新も行う.
-    fn fill_gc_queue(&mut self) -> Result<()> {
+    ///
+    /// エントリ群の読み込みが別スレッドで行われている場合には、
+    /// `wait`が`false`なら、読み込みが完了していない限りキューは空のままとなる.
+    fn fill_gc_queue(&mut self, wait: bool) -> Result<()> {
         assert!(self.gc_queue.is_empty());
+        if self.gc_scanner.as_ref().is_some_and(|s| s.is_scanning()) {
+            return track!(self.receive_gc_scan_result(wait));
+        }
 
         // GCキューが空 `gc_queue.is_empty() == true`
         // すなわち `unreleased_head` と `head` の間のレコード群は全て再配置済みであるため、
@@ -317,43 +1110,282 @@ where
             return Ok(());
         }
 
-        for result in track!(self.ring_buffer.dequeue_iter())?.take(self.options.gc_queue_size) {
-            let entry = track!(result)?;
-            self.gc_queue.push_back(entry);
+        if self.gc_scanner.is_some() {
+            // 読み込み用のスレッドからは、NVMに書き出し済みのデータのみが参照可能
+            track!(self.sync())?;
+            let head = self.ring_buffer.head();
+            let tail = self.ring_buffer.tail();
+            let epoch = self.ring_buffer.epoch_at(head);
+            let scanner = self.gc_scanner.as_mut().expect("Never fails");
+            track!(scanner.request(head, tail, epoch, self.options.gc_queue_size))?;
+            return track!(self.receive_gc_scan_result(wait));
         }
 
+        track!(self.with_io_origin(IoOrigin::Gc, |this| {
+            let entries = track!(this.ring_buffer.dequeue_iter())?;
+            for result in entries.take(this.options.gc_queue_size) {
+                let entry = track!(result)?;
+                this.gc_queue.push_back(entry);
+            }
+            Ok(())
+        }))?;
+
         self.metrics
             .gc_enqueued_records
             .add_u64(self.gc_queue.len() as u64);
         Ok(())
     }
 
-    /// リングバッファおよびインデックスを前回の状態に復元する.
-    fn restore(&mut self, index: &mut LumpIndex) -> Result<()> {
-        for result in track!(self.ring_buffer.restore_entries())? {
-            let JournalEntry { start, record } = track!(result)?;
-            match record {
-                JournalRecord::Put(lump_id, portion) => {
-                    index.insert(lump_id, Portion::Data(portion));
-                }
-                JournalRecord::Embed(lump_id, data) => {
-                    let portion = JournalPortion {
-                        start: start + Address::from(EMBEDDED_DATA_OFFSET as u32),
-                        len: data.len() as u16,
-                    };
-                    index.insert(lump_id, Portion::Journal(portion));
-                }
-                JournalRecord::Delete(lump_id) => {
-                    index.remove(&lump_id);
+    fn receive_gc_scan_result(&mut self, wait: bool) -> Result<()> {
+        let scanner = self.gc_scanner.as_mut().expect("Never fails");
+        let result = if wait {
+            Some(track!(scanner.recv())?)
+        } else {
+            track!(scanner.try_recv())?
+        };
+        if let Some(result) = result {
+            self.ring_buffer
+                .mark_dequeued(&result.entries, result.next_head);
+            self.metrics
+                .gc_enqueued_records
+                .add_u64(result.entries.len() as u64);
+            self.gc_queue.extend(result.entries);
+        }
+        Ok(())
+    }
+
+    /// ジャーナル上の未解放のエントリ群を`cursor`が指す位置から最大`max_entries`個読み込んで、その内容を`index`に反映する.
+    ///
+    /// 結果として、次の読み込み開始位置を指すカーソルが返され、末尾位置まで読み込んだ場合には`None`となる.
+    /// 末尾位置まで繰り返し呼び出すことで、この時点でストレージを開き直した場合に復元されるインデックスと同等のものが得られる.
+    pub fn replay_unreleased_entries_step(
+        &mut self,
+        index: &mut LumpIndex,
+        cursor: JournalCursor,
+        max_entries: usize,
+    ) -> Result<Option<JournalCursor>> {
+        let (entries, next) = track!(self
+            .ring_buffer
+            .read_unreleased_entries_step(cursor, max_entries))?;
+        let mut loader = LumpIndexLoader::with_capacity(entries.len());
+        for entry in entries {
+            Self::apply_entry(index, &mut loader, entry);
+        }
+        loader.flush(index);
+        Ok(next)
+    }
+
+    /// `portions`に含まれる各lumpについて、その部分領域を登録したジャーナルレコードの順番を返す.
+    ///
+    /// 順番は、未解放の始端位置から数えたエントリの通し番号で表される.
+    /// 同じlumpに同じ部分領域が複数回登録されている場合には、最後のものが採用される.
+    /// 該当するレコードが見つからなかったlumpは、結果に含まれない.
+    ///
+    /// オープン時に、重複している部分領域同士の内のどちらを除外するかを決めるために使用される.
+    pub fn data_portion_order(
+        &mut self,
+        portions: &BTreeMap<LumpId, DataPortion>,
+    ) -> Result<BTreeMap<LumpId, u64>> {
+        track!(self.with_sequential_access(|this| {
+            this.with_io_origin(IoOrigin::Restore, |this| {
+                let mut order = BTreeMap::new();
+                let mut seqno = 0;
+                let mut cursor = Some(JournalCursor::default());
+                while let Some(c) = cursor {
+                    let (entries, next) = track!(this
+                        .ring_buffer
+                        .read_unreleased_entries_step(c, RESTORE_SCAN_BATCH_SIZE))?;
+                    for entry in entries {
+                        let registered = match entry.record {
+                            JournalRecord::Put(lump_id, portion) => Some((lump_id, portion)),
+                            _ => DedupPutRecord::from_journal_record(&entry.record)
+                                .map(|r| (r.lump_id, r.portion))
+                                .or_else(|| {
+                                    LinkRecord::from_journal_record(&entry.record)
+                                        .map(|r| (r.lump_id, r.portion))
+                                })
+                                .or_else(|| {
+                                    RenameRecord::from_journal_record(&entry.record)
+                                        .map(|r| (r.new_id, r.portion))
+                                })
+                                .or_else(|| AuditRecord::audited_put(&entry.record)),
+                        };
+                        if let Some((lump_id, portion)) = registered {
+                            if portions.get(&lump_id) == Some(&portion) {
+                                order.insert(lump_id, seqno);
+                            }
+                        }
+                        seqno += 1;
+                    }
+                    cursor = next;
                 }
-                JournalRecord::DeleteRange(range) => {
-                    for lump_id in index.list_range(range) {
-                        index.remove(&lump_id);
+                Ok(order)
+            })
+        }))
+    }
+
+    /// リングバッファおよびインデックスを前回の状態に復元する.
+    ///
+    /// インデックスへの反映は`LumpIndexLoader`を介して一括で行われるため、
+    /// 大量のレコードが存在する場合でも、一件ずつ反映するよりも高速に復元できる.
+    ///
+    /// 重複排除用のハッシュ値は`hashes`に収集される.
+    pub fn restore(&mut self, index: &mut LumpIndex, hashes: &mut ContentHashes) -> Result<()> {
+        // 実際のレコード数は読み込むまで分からないので、リングバッファの容量から上限を見積もる
+        let max_records = self.ring_buffer.capacity() / PUT_RECORD_SIZE as u64;
+        let capacity = cmp::min(max_records, MAX_RESTORE_PRESIZE as u64) as usize;
+        let mut loader = LumpIndexLoader::with_capacity(capacity);
+        let buffer_size = self.options.restore_buffer_size;
+        let read_bytes = self.metrics.nvm_read_bytes.restore();
+        let start = Instant::now();
+        let mut audit_seqno = 0;
+        let clean_shutdown = track!(self.with_sequential_access(|this| {
+            this.with_io_origin(IoOrigin::Restore, |this| {
+                let mut clean_shutdown = true;
+                for result in track!(this.ring_buffer.restore_entries(buffer_size))? {
+                    let entry = track!(result)?;
+                    clean_shutdown = CleanShutdownRecord::is_clean_shutdown_record(&entry.record);
+                    hashes.observe(&entry.record);
+                    if let Some(seqno) = next_audit_seqno(&entry.record) {
+                        audit_seqno = cmp::max(audit_seqno, seqno);
                     }
+                    Self::apply_entry(index, &mut loader, entry);
                 }
-                JournalRecord::EndOfRecords | JournalRecord::GoToFront => unreachable!(),
-            }
+                Ok(clean_shutdown)
+            })
+        }))?;
+        let elapsed = start.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            let read_bytes = self.metrics.nvm_read_bytes.restore() - read_bytes;
+            self.metrics
+                .restore_throughput_bytes_per_second
+                .set(read_bytes as f64 / elapsed);
         }
+        loader.flush(index);
+        self.clean_shutdown = clean_shutdown;
+        self.next_audit_seqno = cmp::max(self.next_audit_seqno, audit_seqno);
         Ok(())
     }
+
+    /// ジャーナルを先頭から順に読み込む処理を実行する.
+    ///
+    /// 実行中はNVMにシーケンシャルアクセスのヒントを通知し、
+    /// 完了後は通常運用時のパターン(埋め込みデータの読み込みが主となるため、ランダムアクセス)に戻す.
+    fn with_sequential_access<F, T>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        track!(self.ring_buffer.advise(AccessPattern::Sequential))?;
+        let result = f(self);
+        track!(self.ring_buffer.advise(AccessPattern::Random))?;
+        result
+    }
+
+    /// `f`の実行中に発生したリングバッファへの読み書きを、`origin`によるものとしてメトリクスに計上する.
+    fn with_io_origin<F, T>(&mut self, origin: IoOrigin, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let previous = self.ring_buffer.set_io_origin(origin);
+        let result = f(self);
+        self.ring_buffer.set_io_origin(previous);
+        result
+    }
+
+    /// ジャーナルエントリの内容を`index`に反映する.
+    ///
+    /// 登録・削除操作は`loader`に溜められるので、最後に`loader.flush(index)`を呼び出す必要がある.
+    fn apply_entry(index: &mut LumpIndex, loader: &mut LumpIndexLoader, entry: JournalEntry) {
+        let JournalEntry { start, record } = entry;
+        match record {
+            JournalRecord::Put(lump_id, portion) => {
+                loader.insert(lump_id, Portion::Data(portion));
+            }
+            JournalRecord::Embed(lump_id, data) => {
+                let portion = embedded_portion(start, &data);
+                loader.insert(lump_id, Portion::Journal(portion));
+            }
+            JournalRecord::Delete(lump_id) => {
+                loader.remove(lump_id);
+            }
+            JournalRecord::DeleteRange(range) => {
+                // 先行する操作群を反映した上で、範囲内のlumpを全てインデックスから取り除く
+                loader.flush(index);
+                for _ in index.drain_range(range) {}
+            }
+            JournalRecord::Extension(..) => {
+                if let Some(r) = DedupPutRecord::from_journal_record(&record) {
+                    loader.insert(r.lump_id, Portion::Data(r.portion));
+                } else if let Some(r) = LinkRecord::from_journal_record(&record) {
+                    loader.insert(r.lump_id, Portion::Data(r.portion));
+                } else if let Some(r) = RenameRecord::from_journal_record(&record) {
+                    loader.remove(r.old_id);
+                    loader.insert(r.new_id, Portion::Data(r.portion));
+                } else if let Some(r) = EmbeddedRenameRecord::from_journal_record(&record) {
+                    loader.remove(r.old_id);
+                    loader.insert(r.new_id, Portion::Journal(embedded_portion(start, r.data)));
+                } else if let Some((lump_id, portion)) = AuditRecord::audited_put(&record) {
+                    loader.insert(lump_id, Portion::Data(portion));
+                } else if let Some(lump_id) = AuditRecord::audited_delete(&record) {
+                    loader.remove(lump_id);
+                }
+                // それ以外の(読み飛ばし可能な)拡張レコードは無視する
+            }
+            JournalRecord::EndOfRecords | JournalRecord::GoToFront => unreachable!(),
+        }
+    }
+}
+
+/// 複数回に分けて実行中の全体GCの状態.
+///
+/// `JournalRegion::start_full_gc`によって生成され、`JournalRegion::full_gc_step`によって処理が進められる.
+#[derive(Debug)]
+pub struct FullGc {
+    // 前回のステップの完了時点での`head`の位置
+    head: u64,
+    // 開始時点での`tail`の位置
+    tail: u64,
+}
+
+/// レコードが監査レコードであれば、その次の通番を返す.
+fn next_audit_seqno(record: &JournalRecord<Vec<u8>>) -> Option<u64> {
+    AuditRecord::from_journal_record(record).map(|r| r.seqno + 1)
+}
+
+/// `start`から始まるレコードに埋め込まれている`data`の、ジャーナル内での位置を返す.
+fn embedded_portion(start: Address, data: &[u8]) -> JournalPortion {
+    JournalPortion {
+        start: start + Address::from(EMBEDDED_DATA_OFFSET as u32),
+        len: data.len() as u16,
+    }
+}
+
+/// PUTレコードのサイズ(バイト数).
+const PUT_RECORD_SIZE: usize = CHECKSUM_SIZE + TAG_SIZE + LumpId::SIZE + LENGTH_SIZE + PORTION_SIZE;
+
+/// `data_portion_order`や`restore_audit_seqno`で、一度に読み込むエントリの最大数.
+const RESTORE_SCAN_BATCH_SIZE: usize = 4096;
+
+/// ジャーナルからの復元時に、事前に確保しておく操作バッファの最大要素数.
+const MAX_RESTORE_PRESIZE: usize = 1024 * 1024;
+
+/// GC関連の設定値を、ラベルとして公開するためのメトリクスを生成する.
+///
+/// # Prometheus
+///
+/// ```prometheus
+/// cannyls_journal_region_gc_config { queue_size="<SIZE>", batch_size="<SIZE>" } 1
+/// ```
+fn gc_config_metric(builder: &MetricBuilder, options: &JournalRegionOptions) -> Gauge {
+    let mut builder = builder.clone();
+    builder.namespace("cannyls").subsystem("journal_region");
+    let gauge = builder
+        .gauge("gc_config")
+        .help("Effective GC parameters of the journal region")
+        .label("queue_size", &options.gc_queue_size.to_string())
+        .label("batch_size", &options.gc_batch_size.to_string())
+        .finish()
+        .expect("Never fails");
+    gauge.set(1.0);
+    gauge
 }
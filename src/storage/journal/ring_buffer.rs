@@ -1,11 +1,13 @@
 use prometrics::metrics::MetricBuilder;
 use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::time::SystemTime;
 
-use super::record::{EMBEDDED_DATA_OFFSET, END_OF_RECORDS_SIZE};
-use super::{JournalEntry, JournalNvmBuffer, JournalRecord};
+use super::record::{JournalChecksum, CHECKSUM_SIZE, EMBEDDED_DATA_OFFSET, END_OF_RECORDS_SIZE};
+use super::{EmbeddedRenameRecord, JournalCursor, JournalEntry, JournalNvmBuffer, JournalRecord};
+use crate::format::record::TAG_EMBEDDED_RENAME;
 use crate::lump::LumpId;
-use crate::metrics::JournalQueueMetrics;
-use crate::nvm::NonVolatileMemory;
+use crate::metrics::{IoOrigin, JournalQueueMetrics, NvmIoCounter};
+use crate::nvm::{AccessPattern, NonVolatileMemory};
 use crate::storage::portion::JournalPortion;
 use crate::storage::Address;
 use crate::{ErrorKind, Result};
@@ -33,41 +35,230 @@ pub struct JournalRingBuffer<N: NonVolatileMemory> {
     /// 不変項: `unreleased_head <= head <= tail`
     tail: u64,
 
+    /// `unreleased_head`の位置のレコードが書き込まれた周回の番号(エポック).
+    ///
+    /// `unreleased_head`から`tail`までの範囲は、高々一度しか周回を跨がないので、
+    /// 範囲内の任意の位置のエポックは、この値から導出可能(`epoch_at`メソッドを参照).
+    ///
+    /// エポック導入以前のジャーナルでは`None`となる.
+    epoch: Option<u8>,
+
+    /// レコードのチェックサムの計算に用いるアルゴリズム.
+    checksum: JournalChecksum,
+
+    /// 終端位置が先頭に戻った回数(`1`始まり).
+    ///
+    /// エポックとは異なりメモリ上にのみ保持され、`JournalCursor`の有効性の判定に使用される.
+    /// `tail`以前の位置は`laps`周目、`tail`よりも後ろの位置は`laps - 1`周目に書き込まれたものとなる.
+    laps: u64,
+
     metrics: JournalQueueMetrics,
 }
 impl<N: NonVolatileMemory> JournalRingBuffer<N> {
+    pub fn unreleased_head(&self) -> u64 {
+        self.unreleased_head
+    }
     pub fn head(&self) -> u64 {
         self.head
     }
     pub fn tail(&self) -> u64 {
         self.tail
     }
+    pub fn checksum(&self) -> JournalChecksum {
+        self.checksum
+    }
+    /// 終端位置が先頭に戻った回数(`1`始まり)を返す.
+    pub fn laps(&self) -> u64 {
+        self.laps
+    }
+    pub fn unsynced_bytes(&self) -> u64 {
+        self.nvm.unsynced_bytes()
+    }
+    pub fn oldest_unsynced_write(&self) -> Option<SystemTime> {
+        self.nvm.oldest_unsynced_write()
+    }
+    pub fn write_buffer_high_water(&self) -> usize {
+        self.nvm.write_buf_high_water()
+    }
+    pub fn set_max_write_buffer_size(&mut self, max: Option<usize>) {
+        self.nvm.set_max_write_buf_size(max);
+    }
+    pub fn advise(&mut self, pattern: AccessPattern) -> Result<()> {
+        track!(self.nvm.advise(0, 0, pattern))
+    }
+
+    /// 未解放部分を含むリングバッファ内の位置`position`に書き込まれている(ないし書き込まれる)レコードのエポックを返す.
+    ///
+    /// エポック導入以前のジャーナルの場合には`None`が返される.
+    pub fn epoch_at(&self, position: u64) -> Option<u8> {
+        self.epoch.map(|epoch| {
+            if position < self.unreleased_head {
+                // 先頭に戻った後の位置
+                epoch.wrapping_add(1)
+            } else {
+                epoch
+            }
+        })
+    }
+
+    /// エポック導入以前のジャーナルに対して、エポックの利用を開始する.
+    ///
+    /// 未解放のレコード群が周回を跨いでいない場合にのみ開始可能で、その場合は`true`が返される.
+    /// 既存のレコード群のチェックサムは、エポック`0`で書き込まれたものと互換性がある.
+    pub fn enable_epoch(&mut self) -> bool {
+        if self.epoch.is_some() || self.tail < self.unreleased_head {
+            return false;
+        }
+        self.epoch = Some(0);
+        true
+    }
 
     pub fn journal_entries(&mut self) -> Result<(u64, u64, u64, Vec<JournalEntry>)> {
         track_io!(self.nvm.seek(SeekFrom::Start(self.head)))?;
+        let epoch = self.epoch_at(self.head);
         let result: Result<Vec<JournalEntry>> =
-            ReadEntries::new(&mut self.nvm, self.head).collect();
+            ReadEntries::new(&mut self.nvm, self.head, epoch, self.checksum).collect();
         result.map(|r| (self.unreleased_head, self.head, self.tail, r))
     }
 
+    /// `cursor`が指す位置から、最大`max_entries`個のエントリを読み込む.
+    ///
+    /// `cursor`が始端位置よりも前(i.e., GCによって取り出し済み)や、
+    /// 既に上書きされた位置を指している場合には、現在の始端位置から読み込みが開始される.
+    ///
+    /// 結果として、読み込んだエントリ群と、次の読み込み開始位置を指すカーソルが返される.
+    /// 末尾位置まで読み込んだ場合には、カーソルは`None`となる.
+    pub fn read_entries_step(
+        &mut self,
+        cursor: JournalCursor,
+        max_entries: usize,
+    ) -> Result<(Vec<JournalEntry>, Option<JournalCursor>)> {
+        let head = self.head;
+        track!(self.read_entries_step_from(head, cursor, max_entries))
+    }
+
+    /// `read_entries_step`の、未解放の位置(`unreleased_head`)を始端とするバージョン.
+    ///
+    /// `read_entries_step`とは異なり、GCキューに取り出し済みのエントリ群も結果に含まれる.
+    pub fn read_unreleased_entries_step(
+        &mut self,
+        cursor: JournalCursor,
+        max_entries: usize,
+    ) -> Result<(Vec<JournalEntry>, Option<JournalCursor>)> {
+        let head = self.unreleased_head;
+        track!(self.read_entries_step_from(head, cursor, max_entries))
+    }
+
+    fn read_entries_step_from(
+        &mut self,
+        head: u64,
+        cursor: JournalCursor,
+        max_entries: usize,
+    ) -> Result<(Vec<JournalEntry>, Option<JournalCursor>)> {
+        let start = match cursor.position {
+            Some(position) if self.is_live_position(head, position, cursor.lap) => position,
+            _ => head,
+        };
+        if start == self.tail {
+            return Ok((Vec::new(), None));
+        }
+
+        track_io!(self.nvm.seek(SeekFrom::Start(start)))?;
+        let epoch = self.epoch_at(start);
+        let mut reader = ReadEntries::new(&mut self.nvm, start, epoch, self.checksum);
+        let entries = (&mut reader)
+            .take(max_entries)
+            .collect::<Result<Vec<_>>>()?;
+        let next = reader.current;
+        if next == self.tail {
+            return Ok((entries, None));
+        }
+        let lap = if next > self.tail {
+            self.laps - 1
+        } else {
+            self.laps
+        };
+        let cursor = JournalCursor {
+            position: Some(next),
+            lap,
+        };
+        Ok((entries, Some(cursor)))
+    }
+
+    /// `position`が、`lap`周目に書き込まれた`head`から`tail`までの範囲に含まれるかどうかを判定する.
+    fn is_live_position(&self, head: u64, position: u64, lap: u64) -> bool {
+        if head <= self.tail {
+            head <= position && position <= self.tail && lap == self.laps
+        } else if head <= position {
+            lap == self.laps - 1
+        } else {
+            position <= self.tail && lap == self.laps
+        }
+    }
+
     /// `JournalRingBuffer`インスタンスを生成する.
-    pub fn new(nvm: N, head: u64, metric_builder: &MetricBuilder) -> Self {
+    ///
+    /// `epoch`は`head`の位置のレコードのエポック.
+    pub fn new(
+        nvm: N,
+        head: u64,
+        epoch: Option<u8>,
+        checksum: JournalChecksum,
+        safe_flush: bool,
+        metric_builder: &MetricBuilder,
+    ) -> Self {
         let metrics = JournalQueueMetrics::new(metric_builder);
         metrics.capacity_bytes.set(nvm.capacity() as f64);
         JournalRingBuffer {
-            nvm: JournalNvmBuffer::new(nvm),
+            nvm: JournalNvmBuffer::new(nvm, safe_flush),
             unreleased_head: head,
             head,
             tail: head,
+            epoch,
+            checksum,
+            laps: 1,
             metrics,
         }
     }
 
     /// NVMから以前のエントリ群を復元し、それらを操作するためのイテレータを返す.
     ///
+    /// NVMからの読み込みは、最大`buffer_size`バイト単位で行われる.
+    ///
     /// インスタンス生成直後に一度だけ呼ばれることを想定.
-    pub fn restore_entries(&mut self) -> Result<RestoredEntries<N>> {
-        track!(RestoredEntries::new(self))
+    pub fn restore_entries(&mut self, buffer_size: usize) -> Result<RestoredEntries<'_, N>> {
+        track!(RestoredEntries::new(self, buffer_size))
+    }
+
+    /// チェックポイントに記録されていた終端位置を用いて、エントリ群を読み込まずにリングバッファの状態を復元する.
+    ///
+    /// 指定位置に終端を示すレコードが存在しない場合(i.e., チェックポイント作成後に追記が行われている場合)には、
+    /// 状態は変更せずに`false`を返す.
+    ///
+    /// インスタンス生成直後に一度だけ呼ばれることを想定.
+    pub fn restore_tail(&mut self, tail: u64) -> Result<bool> {
+        track_assert_eq!(
+            self.unreleased_head,
+            self.head,
+            ErrorKind::InconsistentState
+        );
+        track_assert_eq!(self.head, self.tail, ErrorKind::InconsistentState);
+        if tail >= self.capacity() {
+            return Ok(false);
+        }
+
+        // 終端位置が始端位置よりも前にある場合には、先頭に戻った後の位置を指している
+        let epoch = self.epoch_at(tail);
+        track_io!(self.nvm.seek(SeekFrom::Start(tail)))?;
+        match ReadEntries::new(&mut self.nvm, tail, epoch, self.checksum).read_record() {
+            Ok(None) => {}
+            _ => return Ok(false),
+        }
+        self.tail = tail;
+        self.metrics
+            .consumed_bytes_at_starting
+            .add_u64(self.usage());
+        Ok(true)
     }
 
     /// リングバッファ内に要素が存在するかどうかを判定する.
@@ -96,13 +287,58 @@ impl<N: NonVolatileMemory> JournalRingBuffer<N> {
 
     /// 指定位置に埋め込まれたlumpデータの読み込みを行う.
     ///
-    /// データの妥当性検証は`cannyls`内では行わない.
+    /// データの妥当性検証は`cannyls`内では行わない
+    /// (検証が必要な場合には`read_verified_embedded_data`を使用すること).
     pub fn read_embedded_data(&mut self, position: u64, buf: &mut [u8]) -> Result<()> {
         track_io!(self.nvm.seek(SeekFrom::Start(position)))?;
         track_io!(self.nvm.read_exact(buf))?;
         Ok(())
     }
 
+    /// 指定位置に埋め込まれたlumpデータを、それを含むレコード全体のチェックサムを検証した上で読み込む.
+    ///
+    /// `position`および`len`は、`enqueue`メソッドが返した位置情報と一致している必要がある.
+    /// データが破損している場合には`ErrorKind::StorageCorrupted`エラーが返される.
+    pub fn read_verified_embedded_data(&mut self, position: u64, len: u16) -> Result<Vec<u8>> {
+        let offset = EMBEDDED_DATA_OFFSET as u64;
+        track_assert!(position >= offset, ErrorKind::InconsistentState; position);
+        let record_start = position - offset;
+
+        let mut buf = vec![0; EMBEDDED_DATA_OFFSET + len as usize];
+        track_io!(self.nvm.seek(SeekFrom::Start(record_start)))?;
+        track_io!(self.nvm.read_exact(&mut buf))?;
+        if buf[CHECKSUM_SIZE] == TAG_EMBEDDED_RENAME {
+            // 埋め込みデータの改名レコードでは、データの後ろに改名前のIDが続く
+            buf.resize(buf.len() + LumpId::SIZE, 0);
+            track_io!(self
+                .nvm
+                .read_exact(&mut buf[EMBEDDED_DATA_OFFSET + len as usize..]))?;
+        }
+
+        let epoch = self.epoch_at(record_start);
+        let record = track!(JournalRecord::read_from_with(
+            &buf[..],
+            epoch,
+            self.checksum
+        ))?;
+        let data = match record {
+            JournalRecord::Embed(_, ref data) => Some(&data[..]),
+            _ => EmbeddedRenameRecord::from_journal_record(&record).map(|r| r.data),
+        };
+        match data {
+            Some(data) => {
+                track_assert_eq!(data.len(), len as usize, ErrorKind::StorageCorrupted);
+                Ok(data.to_owned())
+            }
+            None => track_panic!(
+                ErrorKind::StorageCorrupted,
+                "Not an embedded record: position={}, record={:?}",
+                position,
+                record
+            ),
+        }
+    }
+
     /// 物理デバイスに同期命令を発行する.
     pub fn sync(&mut self) -> Result<()> {
         track!(self.nvm.sync())
@@ -110,7 +346,8 @@ impl<N: NonVolatileMemory> JournalRingBuffer<N> {
 
     /// レコードをジャーナルの末尾に追記する.
     ///
-    /// レコードが`JournalRecord::Embed`だった場合には、データを埋め込んだ位置を結果として返す.
+    /// レコードが`JournalRecord::Embed`ないし`EmbeddedRenameRecord`だった場合には、
+    /// データを埋め込んだ位置を結果として返す.
     pub fn enqueue<B: AsRef<[u8]>>(
         &mut self,
         record: &JournalRecord<B>,
@@ -120,22 +357,29 @@ impl<N: NonVolatileMemory> JournalRingBuffer<N> {
 
         // 2. リングバッファの終端チェック
         if self.will_overflow(record) {
+            let epoch = self.epoch_at(self.tail);
             track_io!(self.nvm.seek(SeekFrom::Start(self.tail)))?;
-            track!(JournalRecord::GoToFront::<[_; 0]>.write_to(&mut self.nvm))?;
+            track!(JournalRecord::GoToFront::<[_; 0]>.write_to_with(
+                &mut self.nvm,
+                epoch,
+                self.checksum
+            ))?;
 
             // 先頭に戻って再試行
             self.metrics
                 .consumed_bytes_at_running
                 .add_u64(self.nvm.capacity() - self.tail);
             self.tail = 0;
+            self.laps += 1;
             debug_assert!(!self.will_overflow(record));
             return self.enqueue(record);
         }
 
         // 3. レコードを書き込む
         let prev_tail = self.tail;
+        let epoch = self.epoch_at(self.tail);
         track_io!(self.nvm.seek(SeekFrom::Start(self.tail)))?;
-        track!(record.write_to(&mut self.nvm))?;
+        track!(record.write_to_with(&mut self.nvm, epoch, self.checksum))?;
         self.metrics.enqueued_records_at_running.increment(record);
 
         // 4. 終端を示すレコードも書き込む
@@ -143,18 +387,24 @@ impl<N: NonVolatileMemory> JournalRingBuffer<N> {
         self.metrics
             .consumed_bytes_at_running
             .add_u64(self.tail - prev_tail);
-        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to(&mut self.nvm))?;
-
-        // 5. 埋め込みPUTの場合には、インデックスに位置情報を返す
-        if let JournalRecord::Embed(ref lump_id, ref data) = *record {
+        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to_with(
+            &mut self.nvm,
+            epoch,
+            self.checksum
+        ))?;
+
+        // 5. 埋め込みPUT(ないし埋め込みデータの改名)の場合には、インデックスに位置情報を返す
+        let embedded = match *record {
+            JournalRecord::Embed(ref lump_id, ref data) => Some((*lump_id, data.as_ref())),
+            _ => EmbeddedRenameRecord::from_journal_record(record).map(|r| (r.new_id, r.data)),
+        };
+        Ok(embedded.map(|(lump_id, data)| {
             let portion = JournalPortion {
                 start: Address::from_u64(prev_tail + EMBEDDED_DATA_OFFSET as u64).unwrap(),
-                len: data.as_ref().len() as u16,
+                len: data.len() as u16,
             };
-            Ok(Some((*lump_id, portion)))
-        } else {
-            Ok(None)
-        }
+            (lump_id, portion)
+        }))
     }
 
     /// リングバッファの先頭からエントリ群を取り出す.
@@ -162,10 +412,103 @@ impl<N: NonVolatileMemory> JournalRingBuffer<N> {
     /// `EndOfRecords`に到達した時点で走査は終了する.
     ///
     /// `EndOfRecords`および`GoToFront`は、走査対象には含まれない.
-    pub fn dequeue_iter(&mut self) -> Result<DequeuedEntries<N>> {
+    pub fn dequeue_iter(&mut self) -> Result<DequeuedEntries<'_, N>> {
         track!(DequeuedEntries::new(self))
     }
 
+    /// NVMへの読み書きのバイト数を計上するためのカウンタを設定する.
+    pub fn set_io_counters(&mut self, read: NvmIoCounter, written: NvmIoCounter) {
+        self.nvm.set_io_counters(read, written);
+    }
+
+    /// 以後のNVMへの読み書きの発生元を設定し、以前の値を返す.
+    pub fn set_io_origin(&mut self, origin: IoOrigin) -> IoOrigin {
+        self.nvm.set_io_origin(origin)
+    }
+
+    /// 別スレッドからエントリ群を読み込むための、NVMの読み込み用インスタンスを生成する.
+    ///
+    /// NVMがこれをサポートしていない場合には`None`が返される.
+    pub fn try_clone_reader(&self) -> Result<Option<JournalNvmBuffer<N>>> {
+        track!(self.nvm.try_clone_reader())
+    }
+
+    /// `dequeue_iter`を使わずに(i.e., 別スレッドで)読み込まれたエントリ群を、取り出し済みとして扱う.
+    ///
+    /// `head`は`next_head`の位置に移動する.
+    pub fn mark_dequeued(&mut self, entries: &[JournalEntry], next_head: u64) {
+        for entry in entries {
+            self.metrics.dequeued_records.increment(&entry.record);
+        }
+        self.head = next_head;
+    }
+
+    /// `head`から`tail`までのエントリ群を、リングバッファの先頭から詰めて書き込む.
+    ///
+    /// 書き込み先が未解放の範囲(`unreleased_head`から`tail`まで)と重なってしまう場合には、何もせずに`None`を返す.
+    /// 書き込みに成功した場合には、書き込み後の終端位置と、新しい位置に書き込まれたエントリ群が返される.
+    ///
+    /// 書き込まれた内容は、`reset_to_front`が呼び出されるまでは、リングバッファの一部としては扱われない.
+    pub fn write_to_front(&mut self) -> Result<Option<(u64, Vec<JournalEntry>)>> {
+        track_assert_eq!(
+            self.unreleased_head,
+            self.head,
+            ErrorKind::InconsistentState
+        );
+        if self.tail < self.unreleased_head {
+            return Ok(None);
+        }
+
+        let (_, _, _, entries) = track!(self.journal_entries())?;
+        let size = entries
+            .iter()
+            .map(|e| e.record.external_size() as u64)
+            .sum::<u64>()
+            + END_OF_RECORDS_SIZE as u64;
+        if self.nvm.block_size().ceil_align(size) > self.unreleased_head {
+            return Ok(None);
+        }
+
+        let epoch = self.epoch_at(0);
+        let mut rewritten = Vec::with_capacity(entries.len());
+        track_io!(self.nvm.seek(SeekFrom::Start(0)))?;
+        for entry in entries {
+            let start = track_assert_some!(
+                Address::from_u64(self.nvm.position()),
+                ErrorKind::InconsistentState
+            );
+            track!(entry
+                .record
+                .write_to_with(&mut self.nvm, epoch, self.checksum))?;
+            rewritten.push(JournalEntry {
+                start,
+                record: entry.record,
+            });
+        }
+        let tail = self.nvm.position();
+        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to_with(
+            &mut self.nvm,
+            epoch,
+            self.checksum
+        ))?;
+        track!(self.nvm.sync())?;
+        Ok(Some((tail, rewritten)))
+    }
+
+    /// `write_to_front`によって先頭に書き込まれた内容を、リングバッファの内容として採用する.
+    ///
+    /// 呼び出し側は、事前に先頭位置をジャーナルヘッダに永続化しておく必要がある.
+    pub fn reset_to_front(&mut self, tail: u64) {
+        self.metrics.released_bytes.add_u64(self.usage());
+        self.metrics.consumed_bytes_at_running.add_u64(tail);
+
+        self.epoch = self.epoch_at(0);
+        self.unreleased_head = 0;
+        self.head = 0;
+        self.tail = tail;
+        self.laps += 1;
+    }
+
     pub fn release_bytes_until(&mut self, point: u64) {
         let released_bytes = if self.unreleased_head <= point {
             point - self.unreleased_head
@@ -174,6 +517,7 @@ impl<N: NonVolatileMemory> JournalRingBuffer<N> {
         };
         self.metrics.released_bytes.add_u64(released_bytes);
 
+        self.epoch = self.epoch_at(point);
         self.unreleased_head = point;
     }
 
@@ -225,7 +569,7 @@ pub struct RestoredEntries<'a, N: 'a + NonVolatileMemory> {
 }
 impl<'a, N: 'a + NonVolatileMemory> RestoredEntries<'a, N> {
     #[allow(clippy::new_ret_no_self)]
-    fn new(ring: &'a mut JournalRingBuffer<N>) -> Result<Self> {
+    fn new(ring: &'a mut JournalRingBuffer<N>, buffer_size: usize) -> Result<Self> {
         // 生成直後の呼び出しかどうかを簡易チェック
         track_assert_eq!(
             ring.unreleased_head,
@@ -237,7 +581,13 @@ impl<'a, N: 'a + NonVolatileMemory> RestoredEntries<'a, N> {
         track_io!(ring.nvm.seek(SeekFrom::Start(ring.head)))?;
         let capacity = ring.nvm.capacity();
         Ok(RestoredEntries {
-            entries: ReadEntries::with_capacity(&mut ring.nvm, ring.head, 1024 * 1024),
+            entries: ReadEntries::with_capacity(
+                &mut ring.nvm,
+                ring.head,
+                ring.epoch,
+                ring.checksum,
+                buffer_size,
+            ),
             head: ring.head,
             tail: &mut ring.tail,
             capacity,
@@ -280,8 +630,9 @@ impl<'a, N: 'a + NonVolatileMemory> DequeuedEntries<'a, N> {
     #[allow(clippy::new_ret_no_self)]
     fn new(ring: &'a mut JournalRingBuffer<N>) -> Result<Self> {
         track_io!(ring.nvm.seek(SeekFrom::Start(ring.head)))?;
+        let epoch = ring.epoch_at(ring.head);
         Ok(DequeuedEntries {
-            entries: ReadEntries::new(&mut ring.nvm, ring.head),
+            entries: ReadEntries::new(&mut ring.nvm, ring.head, epoch, ring.checksum),
             head: &mut ring.head,
             metrics: &ring.metrics,
         })
@@ -303,30 +654,52 @@ impl<'a, N: 'a + NonVolatileMemory> Iterator for DequeuedEntries<'a, N> {
 struct ReadEntries<'a, N: 'a + NonVolatileMemory> {
     reader: BufReader<&'a mut JournalNvmBuffer<N>>,
     current: u64,
+    epoch: Option<u8>,
+    checksum: JournalChecksum,
     is_second_lap: bool,
 }
 impl<'a, N: 'a + NonVolatileMemory> ReadEntries<'a, N> {
-    fn new(nvm: &'a mut JournalNvmBuffer<N>, head: u64) -> Self {
+    fn new(
+        nvm: &'a mut JournalNvmBuffer<N>,
+        head: u64,
+        epoch: Option<u8>,
+        checksum: JournalChecksum,
+    ) -> Self {
         ReadEntries {
             reader: BufReader::new(nvm),
             current: head,
+            epoch,
+            checksum,
             is_second_lap: false,
         }
     }
-    fn with_capacity(nvm: &'a mut JournalNvmBuffer<N>, head: u64, capacity: usize) -> Self {
+    fn with_capacity(
+        nvm: &'a mut JournalNvmBuffer<N>,
+        head: u64,
+        epoch: Option<u8>,
+        checksum: JournalChecksum,
+        capacity: usize,
+    ) -> Self {
         ReadEntries {
             reader: BufReader::with_capacity(capacity, nvm),
             current: head,
+            epoch,
+            checksum,
             is_second_lap: false,
         }
     }
     fn read_record(&mut self) -> Result<Option<JournalRecord<Vec<u8>>>> {
-        match track!(JournalRecord::read_from(&mut self.reader))? {
+        match track!(JournalRecord::read_from_with(
+            &mut self.reader,
+            self.epoch,
+            self.checksum
+        ))? {
             JournalRecord::EndOfRecords => Ok(None),
             JournalRecord::GoToFront => {
                 track_assert!(!self.is_second_lap, ErrorKind::StorageCorrupted);
                 track_io!(self.reader.seek(SeekFrom::Start(0)))?;
                 self.current = 0;
+                self.epoch = self.epoch.map(|e| e.wrapping_add(1));
                 self.is_second_lap = true;
                 self.read_record()
             }
@@ -364,7 +737,14 @@ mod tests {
     #[test]
     fn append_and_read_records() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024]);
-        let mut ring = JournalRingBuffer::new(nvm, 0, &MetricBuilder::new());
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            0,
+            Some(0),
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
 
         let records = vec![
             record_put("000", 30, 5),
@@ -397,7 +777,14 @@ mod tests {
     #[test]
     fn read_embedded_data() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024]);
-        let mut ring = JournalRingBuffer::new(nvm, 0, &MetricBuilder::new());
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            0,
+            Some(0),
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
 
         track!(ring.enqueue(&record_put("000", 30, 5)))?;
         track!(ring.enqueue(&record_delete("111")))?;
@@ -409,13 +796,28 @@ mod tests {
         let mut buf = vec![0; portion.len as usize];
         track!(ring.read_embedded_data(portion.start.as_u64(), &mut buf))?;
         assert_eq!(buf, b"foo");
+
+        let data = track!(ring.read_verified_embedded_data(portion.start.as_u64(), portion.len))?;
+        assert_eq!(data, b"foo");
+
+        // 埋め込みレコード以外の位置を指定した場合には、検証に失敗する
+        assert!(ring
+            .read_verified_embedded_data(EMBEDDED_DATA_OFFSET as u64, 3)
+            .is_err());
         Ok(())
     }
 
     #[test]
     fn go_round_ring_buffer() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024]);
-        let mut ring = JournalRingBuffer::new(nvm, 512, &MetricBuilder::new());
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            512,
+            Some(0),
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
         assert_eq!(ring.head, 512);
         assert_eq!(ring.tail, 512);
 
@@ -430,10 +832,76 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn epoch_advances_on_wrap_around() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024]);
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            512,
+            Some(7),
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
+        let record = record_delete("000");
+        for _ in 0..(512 / record.external_size()) + 1 {
+            track!(ring.enqueue(&record))?;
+        }
+        assert_eq!(ring.tail, 21);
+        assert_eq!(ring.epoch_at(ring.head), Some(7));
+        assert_eq!(ring.epoch_at(ring.tail), Some(8));
+
+        let (_, _, _, entries) = track!(ring.journal_entries())?;
+        assert_eq!(entries.len(), 512 / record.external_size() + 1);
+
+        // 終端を示すレコードが失われ、前の周回のレコードが見えてしまっている状況を再現する
+        let stale = record_delete("111");
+        track_io!(ring.nvm.seek(SeekFrom::Start(ring.tail)))?;
+        track!(stale.write_to_with(&mut ring.nvm, Some(7), JournalChecksum::Crc32c))?;
+        track!(JournalRecord::EndOfRecords::<[_; 0]>.write_to_with(
+            &mut ring.nvm,
+            Some(7),
+            JournalChecksum::Crc32c
+        ))?;
+
+        // エポックが異なるので、古いレコードは有効なものとして扱われない
+        assert!(ring.journal_entries().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_journal_can_enable_epoch() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024]);
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            0,
+            None,
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
+        track!(ring.enqueue(&record_delete("000")))?;
+        assert_eq!(ring.epoch_at(ring.tail), None);
+
+        // 旧形式で書き込まれたレコードは、エポック`0`として読み込める
+        assert!(ring.enable_epoch());
+        assert!(!ring.enable_epoch());
+        let (_, _, _, entries) = track!(ring.journal_entries())?;
+        assert_eq!(entries.len(), 1);
+        Ok(())
+    }
+
     #[test]
     fn full() -> TestResult {
         let nvm = MemoryNvm::new(vec![0; 1024]);
-        let mut ring = JournalRingBuffer::new(nvm, 0, &MetricBuilder::new());
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            0,
+            Some(0),
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
 
         let record = record_put("000", 1, 2);
         while ring.tail <= 1024 - record.external_size() as u64 {
@@ -464,7 +932,14 @@ mod tests {
     #[test]
     fn too_large_record() {
         let nvm = MemoryNvm::new(vec![0; 1024]);
-        let mut ring = JournalRingBuffer::new(nvm, 0, &MetricBuilder::new());
+        let mut ring = JournalRingBuffer::new(
+            nvm,
+            0,
+            Some(0),
+            JournalChecksum::Crc32c,
+            false,
+            &MetricBuilder::new(),
+        );
 
         let record = record_embed("000", &[0; 997]);
         assert_eq!(record.external_size(), 1020);
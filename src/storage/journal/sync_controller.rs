@@ -0,0 +1,137 @@
+use std::cmp;
+use std::time::Duration;
+
+/// 同期間隔の調整の判断に用いる、書き込みのレイテンシのサンプル数.
+const WINDOW_SIZE: usize = 128;
+
+/// 同期間隔の自動調整用のパラメータ.
+///
+/// 詳細は`StorageBuilder::adaptive_journal_sync`を参照のこと.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveSyncOptions {
+    pub target_latency: Duration,
+    pub min_interval: usize,
+    pub max_interval: usize,
+}
+
+/// 書き込みのレイテンシの計測結果に基づいて、ジャーナルの同期間隔を調整するためのコントローラ.
+///
+/// 直近`WINDOW_SIZE`回分の書き込みのレイテンシの99パーセンタイル値を求め、
+/// それが目標値を超えている場合には同期間隔を倍にし、目標値の半分未満の場合には半分にする.
+///
+/// 同期命令が遅いデバイス(e.g., HDD)では、同期を伴う書き込みの割合が減るように間隔が長くなり、
+/// 同期命令が速いデバイス(e.g., NVMe)では、信頼性を優先して間隔が短くなる.
+#[derive(Debug, Clone)]
+pub struct SyncIntervalController {
+    options: AdaptiveSyncOptions,
+    interval: usize,
+    samples: Vec<Duration>,
+}
+impl SyncIntervalController {
+    /// 新しい`SyncIntervalController`インスタンスを生成する.
+    ///
+    /// 同期間隔の初期値は`initial_interval`を上下限の範囲に収めたものとなる.
+    pub fn new(options: AdaptiveSyncOptions, initial_interval: usize) -> Self {
+        let max_interval = cmp::max(options.min_interval, options.max_interval);
+        let options = AdaptiveSyncOptions {
+            max_interval,
+            ..options
+        };
+        SyncIntervalController {
+            interval: initial_interval.clamp(options.min_interval, max_interval),
+            options,
+            samples: Vec::with_capacity(WINDOW_SIZE),
+        }
+    }
+
+    /// 現在の同期間隔を返す.
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// 書き込みのレイテンシを記録する.
+    ///
+    /// その結果として同期間隔が変更された場合には、新しい値が返される.
+    pub fn record(&mut self, latency: Duration) -> Option<usize> {
+        self.samples.push(latency);
+        if self.samples.len() < WINDOW_SIZE {
+            return None;
+        }
+
+        self.samples.sort_unstable();
+        let p99 = self.samples[(self.samples.len() * 99).div_ceil(100) - 1];
+        self.samples.clear();
+
+        let old = self.interval;
+        if p99 > self.options.target_latency {
+            self.interval = cmp::max(self.interval.saturating_mul(2), self.interval + 1)
+                .min(self.options.max_interval);
+        } else if p99 < self.options.target_latency / 2 {
+            self.interval = cmp::max(self.interval / 2, self.options.min_interval);
+        }
+        if self.interval != old {
+            Some(self.interval)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_interval_controller_works() {
+        let options = AdaptiveSyncOptions {
+            target_latency: Duration::from_millis(10),
+            min_interval: 4,
+            max_interval: 64,
+        };
+        let controller = SyncIntervalController::new(options, 1000);
+        assert_eq!(controller.interval(), 64);
+
+        // 遅い場合には、上限まで間隔が伸びる
+        let mut controller = SyncIntervalController::new(options, 16);
+        assert_eq!(record_window(&mut controller, 20), Some(32));
+        assert_eq!(record_window(&mut controller, 20), Some(64));
+        assert_eq!(record_window(&mut controller, 20), None);
+
+        // 目標値付近では、間隔は変更されない
+        assert_eq!(record_window(&mut controller, 8), None);
+
+        // 速い場合には、下限まで間隔が縮む
+        assert_eq!(record_window(&mut controller, 1), Some(32));
+        assert_eq!(record_window(&mut controller, 1), Some(16));
+        assert_eq!(record_window(&mut controller, 1), Some(8));
+        assert_eq!(record_window(&mut controller, 1), Some(4));
+        assert_eq!(record_window(&mut controller, 1), None);
+    }
+
+    #[test]
+    fn p99_ignores_rare_outliers() {
+        let options = AdaptiveSyncOptions {
+            target_latency: Duration::from_millis(10),
+            min_interval: 0,
+            max_interval: 64,
+        };
+        let mut controller = SyncIntervalController::new(options, 0);
+        for i in 0..WINDOW_SIZE {
+            // 一回だけ目標値を大きく超える
+            let latency = if i == 0 { 100 } else { 7 };
+            assert_eq!(controller.record(Duration::from_millis(latency)), None);
+        }
+        assert_eq!(controller.interval(), 0);
+
+        // 間隔が`0`でも、伸ばすことができる
+        assert_eq!(record_window(&mut controller, 100), Some(1));
+    }
+
+    fn record_window(controller: &mut SyncIntervalController, millis: u64) -> Option<usize> {
+        let mut result = None;
+        for _ in 0..WINDOW_SIZE {
+            result = controller.record(Duration::from_millis(millis));
+        }
+        result
+    }
+}
@@ -13,57 +13,63 @@
 //! [Device]: ../device/struct.Device.html
 //! [format]: https://github.com/frugalos/cannyls/wiki/Storage-Format
 //! [gc]: https://github.com/frugalos/cannyls/wiki/Journal-Region-GC
-pub use self::address::Address;
 pub use self::builder::StorageBuilder;
-pub use self::header::StorageHeader;
-pub use self::journal::{JournalEntry, JournalRecord, JournalSnapshot};
+pub use self::check::{CheckLevel, CheckReport, StorageChecker};
+pub use self::config::StorageConfig;
+pub use self::journal::{
+    AuditOperation, AuditRecord, JournalAdmissionAction, JournalChecksum, JournalCursor,
+    JournalEntry, JournalRecord, JournalSnapshot,
+};
+pub use self::portion::DataPortion;
+pub use self::scan::StorageScan;
+pub use self::stats::{DataRegionStats, JournalStats, StorageStats, WarmUpReport};
+pub use self::usage_summary::UsageSummary;
+pub use crate::format::{
+    Address, StorageCreationParams, StorageHeader, MAGIC_NUMBER, MAJOR_VERSION,
+    MAX_DATA_REGION_SIZE, MAX_JOURNAL_REGION_SIZE, MAX_LOGICAL_NAME_LEN, MINOR_VERSION,
+};
 
 pub(crate) use self::data_region::DataRegionLumpData; // `lump`モジュール用に公開
 
-use self::data_region::DataRegion;
+use self::data_region::{DataRegion, SlicedWrite};
+use self::dedup::DedupTable;
 use self::index::LumpIndex;
-use self::journal::JournalRegion;
+use self::index_shadow::{IndexShadow, IndexShadowWriter};
+use self::journal::{
+    DedupPutRecord, EmbeddedRenameRecord, FullGc, InPlacePutRecord, JournalRegion, LinkRecord,
+    RenameRecord,
+};
 use self::portion::Portion;
-use crate::block::BlockSize;
-use crate::lump::{LumpData, LumpDataInner, LumpHeader, LumpId};
+use crate::deadline::Deadline;
+use crate::lump::{LumpData, LumpDataInner, LumpHeader, LumpId, LumpRange};
 use crate::metrics::StorageMetrics;
 use crate::nvm::NonVolatileMemory;
-use crate::Result;
-use std::ops::Range;
+use crate::{ErrorKind, Result};
+use std::cmp;
+use std::collections::BTreeSet;
+use std::hint;
+use std::io::SeekFrom;
+use std::mem;
+use std::ops::{Bound, Range};
+use std::time::{Duration, Instant};
 
-mod address;
 mod allocator;
 mod builder;
+mod check;
+mod checkpoint;
+mod config;
 mod data_region;
+mod dedup;
 mod header;
 mod index;
-mod journal;
+mod index_shadow;
+pub(crate) mod journal;
 mod portion;
-
-/// ストレージの先頭に書き込まれるマジックナンバー.
-///
-/// "**LU**mp **S**torage **F**ormat"の略.
-pub const MAGIC_NUMBER: [u8; 4] = *b"lusf";
-
-/// ストレージフォーマットの現在のメジャーバージョン.
-///
-/// メジャーバージョンが異なるストレージ同士のデータ形式には互換性が無い.
-pub const MAJOR_VERSION: u16 = 1;
-
-/// ストレージフォーマットの現在のマイナーバージョン.
-///
-/// マイナーバージョンには、後方互換性がある.
-pub const MINOR_VERSION: u16 = 1;
-
-/// ジャーナル領域の最大サイズ(バイト単位).
-///
-/// およそ1TB.
-pub const MAX_JOURNAL_REGION_SIZE: u64 = Address::MAX;
-
-/// データ領域の最大サイズ(バイト単位).
-///
-/// およそ512TB.
-pub const MAX_DATA_REGION_SIZE: u64 = Address::MAX * BlockSize::MIN as u64;
+#[cfg(feature = "recovery")]
+pub mod recovery;
+mod scan;
+mod stats;
+mod usage_summary;
 
 /// Lumpを格納するためのストレージ.
 ///
@@ -82,7 +88,11 @@ where
     journal_region: JournalRegion<N>,
     data_region: DataRegion<N>,
     lump_index: LumpIndex,
+    dedup: DedupTable,
     metrics: StorageMetrics,
+    config: StorageConfig,
+    reembed_cursor: Option<LumpId>,
+    index_shadow: Option<IndexShadowWriter>,
 }
 impl<N> Storage<N>
 where
@@ -93,14 +103,24 @@ where
         journal_region: JournalRegion<N>,
         data_region: DataRegion<N>,
         lump_index: LumpIndex,
+        dedup: DedupTable,
         metrics: StorageMetrics,
+        config: StorageConfig,
     ) -> Self {
+        let index_shadow = config
+            .index_shadow_file
+            .clone()
+            .map(|path| IndexShadowWriter::new(path, config.index_shadow_interval));
         Storage {
             header,
             journal_region,
             data_region,
             lump_index,
+            dedup,
             metrics,
+            config,
+            reembed_cursor: None,
+            index_shadow,
         }
     }
 
@@ -114,6 +134,29 @@ where
         track!(StorageBuilder::new().open(nvm))
     }
 
+    /// ストレージの末尾に格納されているバックアップを用いて、先頭のヘッダを修復する.
+    ///
+    /// 先頭のヘッダが壊れている場合でも、バックアップが存在すれば`Storage::open`は成功するが、
+    /// 先頭のヘッダ自体は壊れたまま残るため、この関数を用いて書き直すことが推奨される.
+    ///
+    /// 修復に使用されたヘッダ情報が返される.
+    /// バックアップが見つからない場合には`ErrorKind::StorageCorrupted`エラーが返される.
+    pub fn repair_header(nvm: &mut N) -> Result<StorageHeader> {
+        let header = track_assert_some!(
+            track!(StorageHeader::read_backup_from_nvm(nvm))?,
+            ErrorKind::StorageCorrupted,
+            "No backup header is found"
+        );
+
+        track_io!(nvm.seek(SeekFrom::Start(0)))?;
+        track!(nvm.aligned_write_all(|temp_buf| {
+            track!(header.write_header_region_to(temp_buf))?;
+            Ok(())
+        }))?;
+        track!(nvm.sync())?;
+        Ok(header)
+    }
+
     /// ストレージのヘッダ情報を返す.
     pub fn header(&self) -> &StorageHeader {
         &self.header
@@ -124,9 +167,62 @@ where
         &self.metrics
     }
 
+    /// ストレージに実際に適用されている設定値を返す.
+    ///
+    /// 構築後に変更された値(e.g., `set_journal_gc_queue_size`)も反映される.
+    pub fn config(&self) -> StorageConfig {
+        let options = self.journal_region.options();
+        StorageConfig {
+            journal_sync_interval: options.sync_interval,
+            journal_gc_queue_size: options.gc_queue_size,
+            journal_gc_batch_size: options.gc_batch_size,
+            journal_background_gc_scan: self.journal_region.is_background_gc_scan_enabled(),
+            ..self.config.clone()
+        }
+    }
+
+    /// ストレージが使用しているNVMで、Direct I/Oが要求されたが使用できずに、
+    /// 通常のI/Oに切り替えられているかどうかを返す.
+    ///
+    /// 詳細は`NonVolatileMemory::is_direct_io_degraded`を参照のこと.
+    pub fn is_direct_io_degraded(&self) -> bool {
+        self.data_region.is_direct_io_degraded()
+    }
+
+    /// ストレージが使用しているNVMを識別するための、メトリクスのラベルを返す.
+    ///
+    /// 詳細は`NonVolatileMemory::metric_labels`を参照のこと.
+    pub fn nvm_metric_labels(&self) -> Vec<(&'static str, String)> {
+        self.data_region.nvm_metric_labels()
+    }
+
+    /// 前回のクローズが正常に行われていたかどうかを返す.
+    ///
+    /// `Storage::close`(デバイス経由の場合には`Device::stop`)によってクローズされた後、
+    /// ジャーナルへの追記が行われずにオープンされた場合に`true`となる.
+    /// `close`を呼ばずにインスタンスが破棄された場合や、プロセスがクラッシュした場合には`false`となるため、
+    /// 上位レイヤーでは、その結果を元に整合性検査(`check`)を行うかどうか等を判断できる.
+    ///
+    /// 新規に作成されたストレージの場合には`true`となる.
+    pub fn was_clean_shutdown(&self) -> bool {
+        self.journal_region.was_clean_shutdown()
+    }
+
+    /// ストレージの統計情報のスナップショットを返す.
+    ///
+    /// メトリクスとは異なり、全ての値が同一時点のものであることが保証される.
+    pub fn stats(&self) -> StorageStats {
+        StorageStats {
+            lumps: self.lump_index.len(),
+            journal: self.journal_region.stats(),
+            data: self.data_region.stats(),
+        }
+    }
+
     /// ストレージに保存されている中で、指定された範囲が占有するバイト数を返す.
-    pub fn usage_range(&self, range: Range<LumpId>) -> StorageUsage {
-        self.lump_index.usage_range(range, self.header.block_size)
+    pub fn usage_range<R: Into<LumpRange>>(&self, range: R) -> StorageUsage {
+        self.lump_index
+            .usage_range(range.into(), self.header.block_size)
     }
 
     /// 指定されたIDのlumpを取得する.
@@ -149,7 +245,10 @@ where
                     }
                     Portion::Data(portion) => {
                         self.metrics.get_data_lumps.increment();
-                        track!(self.data_region.get(portion).map(LumpData::from))?
+                        track!(self
+                            .data_region
+                            .get(portion)
+                            .map(LumpData::from_data_region))?
                     }
                 };
                 Ok(Some(data))
@@ -177,46 +276,375 @@ where
     }
 
     /// ストレージに保存されている中で、指定された範囲に含まれるLumpIdの一覧を返す.
-    pub fn list_range(&mut self, range: Range<LumpId>) -> Vec<LumpId> {
-        self.lump_index.list_range(range)
+    pub fn list_range<R: Into<LumpRange>>(&mut self, range: R) -> Vec<LumpId> {
+        self.lump_index.list_range(range.into())
+    }
+
+    /// `list`の処理量を制限したバージョン.
+    ///
+    /// `start`以降(`start`自身を含む)に保存されているlumpのIDを、最大`max_lumps`個返す.
+    /// 呼び出し後の`start`は、次に走査を開始すべき位置に更新され、
+    /// 全ての走査が完了した場合には`None`となる.
+    /// なお`max_lumps`が`0`の場合には`1`が指定されたものとして扱われる.
+    pub fn list_step(&self, start: &mut Option<LumpId>, max_lumps: usize) -> Vec<LumpId> {
+        let max_lumps = cmp::max(max_lumps, 1);
+        let mut ids = if let Some(start) = *start {
+            self.lump_index
+                .list_range_with_limit(start.., max_lumps.saturating_add(1))
+        } else {
+            Vec::new()
+        };
+        *start = if ids.len() > max_lumps {
+            ids.pop()
+        } else {
+            None
+        };
+        ids
+    }
+
+    /// `list_range`の処理量を制限したバージョン.
+    ///
+    /// `range`の先頭から最大`max_lumps`個のlumpのIDを返す.
+    /// 呼び出し後の`range.start`は、未処理部分の開始位置に更新される.
+    /// なお`max_lumps`が`0`の場合には`1`が指定されたものとして扱われる.
+    pub fn list_range_step(&self, range: &mut Range<LumpId>, max_lumps: usize) -> Vec<LumpId> {
+        let max_lumps = cmp::max(max_lumps, 1);
+        let mut ids = self
+            .lump_index
+            .list_range_with_limit(range.clone(), max_lumps.saturating_add(1));
+        if ids.len() > max_lumps {
+            range.start = ids.pop().expect("Never fails");
+        } else {
+            range.start = range.end;
+        }
+        ids
+    }
+
+    /// 指定された範囲に含まれるlumpのIDとデータを、ID順に走査するイテレータを返す.
+    ///
+    /// 内部的には`scan_step`を用いて一定数毎にまとめて読み込みが行われるため、
+    /// `list_range`の結果に対して個々に`get`を呼び出すよりも、物理的な読み込み回数が少なくて済む.
+    ///
+    /// イテレータがエラーを返した場合には、以後の走査は行われない.
+    pub fn scan<R: Into<LumpRange>>(&mut self, range: R) -> StorageScan<'_, N> {
+        StorageScan::new(self, range.into())
+    }
+
+    /// `scan`の処理量を制限したバージョン.
+    ///
+    /// `range`の先頭から最大`max_lumps`個のlumpのIDとデータを返す.
+    /// 呼び出し後の`range`は未処理部分に更新され、全ての走査が完了した場合には`None`となる.
+    /// なお`max_lumps`が`0`の場合には`1`が指定されたものとして扱われる.
+    ///
+    /// データ領域に格納されているlump群は、隣接しているもの同士がまとめて読み込まれる.
+    ///
+    /// # Error Handlings
+    ///
+    /// エラーが返された場合には`range`は更新されないので、同じ引数で再度呼び出すことが可能.
+    /// それ以外は`get`と同様.
+    pub fn scan_step(
+        &mut self,
+        range: &mut Option<LumpRange>,
+        max_lumps: usize,
+    ) -> Result<Vec<(LumpId, LumpData)>> {
+        let current = match *range {
+            None => return Ok(Vec::new()),
+            Some(current) => current,
+        };
+        let max_lumps = cmp::max(max_lumps, 1);
+        let mut entries = self
+            .lump_index
+            .entries_with_limit(current, max_lumps.saturating_add(1));
+        let next = if entries.len() > max_lumps {
+            entries
+                .pop()
+                .map(|(lump_id, _)| current.with_start(lump_id))
+        } else {
+            None
+        };
+
+        let data_portions = entries
+            .iter()
+            .filter_map(|&(_, portion)| match portion {
+                Portion::Data(portion) => Some(portion),
+                Portion::Journal(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mut data_lumps = track!(self.data_region.get_many(&data_portions))?.into_iter();
+
+        let mut lumps = Vec::with_capacity(entries.len());
+        for (lump_id, portion) in entries {
+            let data = match portion {
+                Portion::Journal(portion) => {
+                    self.metrics.get_journal_lumps.increment();
+                    let bytes = track!(self.journal_region.get_embedded_data(portion))?;
+                    track!(LumpData::new_embedded(bytes))?
+                }
+                Portion::Data(_) => {
+                    self.metrics.get_data_lumps.increment();
+                    LumpData::from_data_region(data_lumps.next().expect("Never fails"))
+                }
+            };
+            lumps.push((lump_id, data));
+        }
+        *range = next;
+        Ok(lumps)
     }
 
     /// lumpを保存する.
     ///
     /// 既に同じIDのlumpが存在する場合にはデータが上書きされる.
     ///
-    /// 新規追加の場合には`Ok(true)`が、上書きの場合には`Ok(false)`が返される.
+    /// 結果として、書き込み内容に関する情報を保持する`PutReport`が返される.
+    /// 新規追加か上書きかは`PutReport::is_new`で判定可能.
     ///
     /// # Error Handlings
     ///
-    /// このメソッドが`ErrorKind::{Full, InvalidInput}`以外のエラーを返した場合には、
+    /// このメソッドが`ErrorKind::{Full, InvalidInput, BlockSizeMismatch, JournalGcLagging}`以外のエラーを返した場合には、
     /// 不整合ないしI/O周りで致命的な問題が発生している可能性があるので、
     /// 以後はこのインスタンスの使用を中止するのが望ましい.
     ///
+    /// `StorageBuilder::journal_admission_control`が有効な場合には、GCが追記に追いついていない時に、
+    /// `ErrorKind::JournalGcLagging`エラーが返されることがある(この場合には、PUTは実行されていない).
+    ///
     /// # 性能上の注意
     ///
     /// 引数に渡される`LumpData`が、`LumpData::new`関数経由で生成されている場合には、
     /// NVMへの書き込み前に、データをブロック境界にアライメントするためのメモリコピーが余分に発生してしまう.
     /// それを避けたい場合には、`Storage::allocate_lump_data`メソッドを使用して`LumpData`を生成すると良い.
-    pub fn put(&mut self, lump_id: &LumpId, data: &LumpData) -> Result<bool> {
+    pub fn put(&mut self, lump_id: &LumpId, data: &LumpData) -> Result<PutReport> {
+        if self.config.skip_identical_overwrites
+            && track!(self.is_identical_overwrite(lump_id, data))?
+        {
+            self.metrics.unchanged_overwrites.increment();
+            return Ok(PutReport {
+                is_new: false,
+                embedded: matches!(data.as_inner(), LumpDataInner::JournalRegion(_)),
+                allocated_blocks: 0,
+                deduplicated: false,
+                journal_synced: false,
+                unchanged: true,
+                in_place: false,
+            });
+        }
+
+        track!(self.journal_region.check_admission(&mut self.lump_index))?;
+        let syncs = self.journal_region.metrics().syncs();
+        if self.config.overwrite_in_place && track!(self.overwrite_in_place(lump_id, data))? {
+            self.metrics.in_place_overwrites.increment();
+            self.metrics.put_lumps_at_running.increment();
+            self.metrics
+                .logical_written_bytes
+                .add_u64(data.as_bytes().len() as u64);
+            return Ok(PutReport {
+                is_new: false,
+                embedded: false,
+                allocated_blocks: 0,
+                deduplicated: false,
+                journal_synced: self.journal_region.metrics().syncs() != syncs,
+                unchanged: false,
+                in_place: true,
+            });
+        }
+
         let updated = track!(self.delete_if_exists(lump_id, false))?;
-        match data.as_inner() {
+        let hash = match data.as_inner() {
+            LumpDataInner::JournalRegion(_) => None,
+            _ if self.dedup.is_enabled() => Some(dedup::content_hash(data.as_bytes())),
+            _ => None,
+        };
+        let deduplicated = match hash {
+            Some(hash) => track!(self.put_deduplicated(lump_id, data.as_bytes(), hash))?,
+            None => false,
+        };
+        let allocated_blocks = match data.as_inner() {
+            _ if deduplicated => Some(0),
             LumpDataInner::JournalRegion(data) => {
                 track!(self
                     .journal_region
                     .records_embed(&mut self.lump_index, lump_id, data))?;
+                None
             }
             LumpDataInner::DataRegion(data) => {
-                track!(self.put_lump_to_data_region(lump_id, data))?;
+                let portion = track!(self.put_lump_to_data_region(lump_id, data, hash, None))?;
+                Some(portion.len)
             }
             LumpDataInner::DataRegionUnaligned(data) => {
                 let mut aligned_data = DataRegionLumpData::new(data.len(), self.header.block_size);
                 aligned_data.as_bytes_mut().copy_from_slice(data);
-                track!(self.put_lump_to_data_region(lump_id, &aligned_data))?;
+                let portion =
+                    track!(self.put_lump_to_data_region(lump_id, &aligned_data, hash, None))?;
+                Some(portion.len)
             }
-        }
+        };
         self.metrics.put_lumps_at_running.increment();
-        Ok(!updated)
+        self.metrics
+            .logical_written_bytes
+            .add_u64(data.as_bytes().len() as u64);
+        Ok(PutReport {
+            is_new: !updated,
+            embedded: allocated_blocks.is_none(),
+            allocated_blocks: allocated_blocks.unwrap_or(0),
+            deduplicated,
+            journal_synced: self.journal_region.metrics().syncs() != syncs,
+            unchanged: false,
+            in_place: false,
+        })
+    }
+
+    /// バイト列を、`LumpData`を経由せずにlumpとして保存する.
+    ///
+    /// `bytes`はストレージのブロック境界にアライメントされたバッファに一度だけコピーされた上で、
+    /// データ領域に書き込まれる.
+    /// `LumpData::new`で生成したデータを`put`する場合とは異なり、余分なメモリコピーは発生しない.
+    ///
+    /// その他の挙動は`put`と同様.
+    ///
+    /// # Errors
+    ///
+    /// `bytes`のサイズが`LumpData::MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
+    pub fn put_bytes(&mut self, lump_id: &LumpId, bytes: &[u8]) -> Result<PutReport> {
+        let data = track!(self.allocate_lump_data_with_bytes(bytes))?;
+        track!(self.put(lump_id, &data))
+    }
+
+    /// 書き込みの緊急度を表すデッドラインを考慮して、lumpを保存する.
+    ///
+    /// ジャーナルの同期の扱いが、`deadline`に応じて以下のように変わる:
+    ///
+    /// - `Deadline::Immediate`:
+    ///   - 保存後に、必ずジャーナルの同期(`journal_sync`)を行ってから結果を返す
+    ///   - つまり、このメソッドが成功した時点で、書き込みは永続化されている(`PutReport::journal_synced`は常に`true`)
+    /// - `Deadline::Infinity`:
+    ///   - 同期間隔(`StorageBuilder::journal_sync_interval`)に基づく定期的な同期の時期に達しても、同期を行わない
+    ///   - 先送りされた同期は、以下のいずれかの時点で行われる:
+    ///     - `Deadline::Infinity`以外が指定されたPUT等、ジャーナルへの追記を伴う別の操作の実行時
+    ///     - 補助タスク(`run_side_job_once`)の実行時
+    ///     - `journal_sync`の呼び出し時
+    ///   - ただし、先送りの間に追記されたレコードの数が同期間隔に達した場合には、その時点で同期が行われる.
+    ///     そのため、未同期のレコードの数が同期間隔の二倍を超えることはない
+    ///   - 同期が先送りされた回数は`JournalRegionMetrics::deferred_syncs`で取得可能
+    /// - `Deadline::Within`と`Deadline::At`:
+    ///   - `put`と同様(i.e., 定期的な同期の時期に達した場合にのみ同期を行う)
+    ///
+    /// なお、`Deadline::At`が指定された場合でも、期限切れかどうかの判定は行われない
+    /// (期限の判定はデバイスのスケジューリング時に行われる).
+    ///
+    /// その他の挙動は`put`と同様.
+    pub fn put_with_deadline(
+        &mut self,
+        lump_id: &LumpId,
+        data: &LumpData,
+        deadline: Deadline,
+    ) -> Result<PutReport> {
+        track!(self.with_put_deadline(deadline, |s| s.put(lump_id, data)))
+    }
+
+    /// 大きなlumpの保存を、複数回に分けて実行するための準備を行う.
+    ///
+    /// `StorageBuilder::write_slice_size`が有効で、かつ、`data`がそのサイズを超えている場合には、
+    /// 書き込み先の部分領域を割り当てた上で、PUTの進行状況を表す`SlicedPut`を返す.
+    /// 以後、`sliced_put_step`を完了まで繰り返し呼び出すことで、`put_with_deadline`と同様にlumpが保存される.
+    ///
+    /// 分割できない場合(詳細は`StorageBuilder::write_slice_size`を参照)には、何も行わずに`None`が返される.
+    /// その場合には`put_with_deadline`を使用すること.
+    ///
+    /// なお、`SlicedPut`が完了するまでの間に許されるのは、ストレージの状態を変更しない操作(e.g., `get`)のみである.
+    ///
+    /// # Error Handlings
+    ///
+    /// `put`と同様.
+    pub fn start_sliced_put(
+        &mut self,
+        lump_id: &LumpId,
+        data: &LumpData,
+        deadline: Deadline,
+    ) -> Result<Option<SlicedPut>> {
+        let slice_size = self.config.write_slice_size;
+        let data = match data.as_inner() {
+            LumpDataInner::DataRegion(d)
+                if slice_size != 0 && data.as_bytes().len() > slice_size =>
+            {
+                d
+            }
+            _ => return Ok(None),
+        };
+        if self.dedup.is_enabled()
+            || self.config.skip_identical_overwrites
+            || self.config.overwrite_in_place
+        {
+            return Ok(None);
+        }
+
+        track!(self.journal_region.check_admission(&mut self.lump_index))?;
+        let syncs = self.journal_region.metrics().syncs();
+        let write = track!(self.retry_if_full(|r| r.start_sliced_write(data)))?;
+        Ok(write.map(|write| SlicedPut {
+            lump_id: *lump_id,
+            write,
+            deadline,
+            syncs,
+        }))
+    }
+
+    /// `start_sliced_put`で開始したPUTの処理を、一回の書き込み分だけ進める.
+    ///
+    /// `data`には`start_sliced_put`に渡したものと同じデータを指定する必要がある.
+    ///
+    /// 全てのデータの書き込みが完了した場合には、ジャーナルへの記録を行った上で、その結果が返される.
+    /// それ以外の場合には`None`が返される.
+    ///
+    /// # Error Handlings
+    ///
+    /// `put`と同様.
+    /// エラーが発生した場合には、割り当てられていた部分領域は解放され、以後`put`を再開することはできない.
+    pub fn sliced_put_step(
+        &mut self,
+        put: &mut SlicedPut,
+        data: &LumpData,
+    ) -> Result<Option<PutReport>> {
+        let portion = put.write.portion();
+        let result = match data.as_inner() {
+            LumpDataInner::DataRegion(data) => {
+                track!(self.data_region.write_next_slice(&mut put.write, data))
+            }
+            _ => track_panic!(ErrorKind::InvalidInput, "Unexpected lump data: {:?}", data),
+        };
+        match result {
+            Ok(false) => return Ok(None),
+            Ok(true) => {}
+            Err(e) => {
+                self.data_region.delete(portion);
+                return Err(e);
+            }
+        }
+
+        let (lump_id, syncs) = (put.lump_id, put.syncs);
+        let size = data.as_bytes().len() as u64;
+        let report = track!(self.with_put_deadline(put.deadline, |s| {
+            let updated = track!(s.delete_if_exists(&lump_id, false))?;
+            if let Err(e) =
+                track!(s
+                    .journal_region
+                    .records_put(&mut s.lump_index, &lump_id, portion))
+            {
+                s.data_region.delete(portion);
+                return Err(e);
+            }
+            s.lump_index.insert(lump_id, Portion::Data(portion));
+            s.metrics.put_lumps_at_running.increment();
+            s.metrics.logical_written_bytes.add_u64(size);
+            Ok(PutReport {
+                is_new: !updated,
+                embedded: false,
+                allocated_blocks: portion.len,
+                deduplicated: false,
+                journal_synced: s.journal_region.metrics().syncs() != syncs,
+                unchanged: false,
+                in_place: false,
+            })
+        }))?;
+        Ok(Some(report))
     }
 
     /// 指定されたIDのlumpを削除する.
@@ -232,500 +660,4402 @@ where
         track!(self.delete_if_exists(lump_id, true))
     }
 
-    /// LumpIdのrange [start..end) を用いて、これに含まれるLumpIdを全て削除する。
+    /// 指定されたIDのlumpが存在し、かつその内容が`condition`を満たす場合にのみ、それを削除する.
     ///
-    /// 返り値がOk(vec)の場合、このvecは実際に削除したlump id全体となっている。
-    /// （注意: rangeには、lusf上にないlump idが一般には含まれている）
+    /// 同じlumpが並行して上書きされうる場合に、意図しない(新しい方の)データを削除してしまうことを防ぐために使用する.
+    /// 条件の判定にはlumpのデータの読み込みが必要となる.
+    ///
+    /// lumpが削除された場合には`Ok(true)`が、存在しないか条件を満たさなかった場合には`Ok(false)`が返される.
+    ///
+    /// # Error Handlings
+    ///
+    /// `delete`と同様.
+    pub fn delete_if(&mut self, lump_id: &LumpId, condition: DeleteCondition) -> Result<bool> {
+        if !track!(self.satisfies(lump_id, condition))? {
+            return Ok(false);
+        }
+        track!(self.delete_if_exists(lump_id, true))
+    }
+
+    /// 指定されたIDのlumpが存在し、かつその内容が`condition`を満たすかどうかを判定する.
+    pub(crate) fn satisfies(
+        &mut self,
+        lump_id: &LumpId,
+        condition: DeleteCondition,
+    ) -> Result<bool> {
+        let bytes = match self.lump_index.get(lump_id) {
+            None => return Ok(false),
+            Some(Portion::Journal(portion)) => {
+                track!(self.journal_region.get_embedded_data(portion))?
+            }
+            Some(Portion::Data(portion)) => {
+                track!(self.data_region.get(portion))?.as_bytes().to_owned()
+            }
+        };
+        Ok(condition.is_satisfied_by(&bytes))
+    }
+
+    /// `src`のlumpと同じデータを参照する別名として、`dst`のlumpを作成する.
+    ///
+    /// データ領域に格納されているlumpの場合には、データのコピーは行われず、
+    /// `dst`は`src`と同じ部分領域を(参照カウント付きで)共有する.
+    /// 部分領域は、それを参照する全てのlumpが削除(ないし上書き)されるまで解放されない.
+    /// ジャーナル領域に埋め込まれているlumpの場合には、データが`dst`用に再度埋め込まれる.
+    ///
+    /// 既に`dst`のlumpが存在する場合には、`put`と同様に上書きされる.
+    ///
+    /// 別名が作成された場合には`Ok(true)`が、`src`のlumpが存在しない場合には`Ok(false)`が、返される.
+    ///
+    /// なお、共有されている部分領域が存在する間は、`close`時のチェックポイントの書き込みは行われない.
+    ///
+    /// # Errors
+    ///
+    /// ストレージのバージョンが拡張レコードの導入(`1.2`)以前のまま(`StorageBuilder::upgrade_minor_version`)で、
+    /// `src`のlumpがデータ領域に格納されている場合には、`ErrorKind::InvalidInput`エラーが返される.
     ///
     /// # Error Handlings
     ///
     /// このメソッドがエラーを返した場合には、
     /// 不整合ないしI/O周りで致命的な問題が発生している可能性があるので、
     /// 以後はこのインスタンスの使用を中止するのが望ましい.
-    ///
-    /// # 注意
-    ///
-    /// `range`が大量の要素を含む場合には、
-    /// このメソッドは巨大なLumpIdの配列を返しうることに注意されたい。
-    pub fn delete_range(&mut self, range: Range<LumpId>) -> Result<Vec<LumpId>> {
-        let targets = self.lump_index.list_range(range.clone());
-
-        // ジャーナル領域に範囲削除レコードを一つ書き込むため、一度のディスクアクセスが起こる。
-        // 削除レコードを範囲分書き込むわけ *ではない* ため、複数回のディスクアクセスは発生しない。
-        track!(self
-            .journal_region
-            .records_delete_range(&mut self.lump_index, range))?;
-
-        for lump_id in &targets {
-            if let Some(portion) = self.lump_index.remove(lump_id) {
-                self.metrics.delete_lumps.increment();
+    pub fn link(&mut self, src: &LumpId, dst: &LumpId) -> Result<bool> {
+        let portion = match self.lump_index.get(src) {
+            None => return Ok(false),
+            Some(_) if src == dst => return Ok(true),
+            Some(portion) => portion,
+        };
+        match portion {
+            Portion::Journal(portion) => {
+                let data = track!(self.journal_region.get_embedded_data(portion))?;
+                track!(self.delete_if_exists(dst, false))?;
+                track!(self
+                    .journal_region
+                    .records_embed(&mut self.lump_index, dst, &data))?;
+            }
+            Portion::Data(portion) => {
+                track_assert!(
+                    self.journal_region.supports_extension_records(),
+                    ErrorKind::InvalidInput,
+                    "Linking requires a newer storage version: minor_version={}",
+                    self.header.minor_version
+                );
 
-                if let Portion::Data(portion) = portion {
-                    // DataRegion::deleteはメモリアロケータに対する解放要求をするのみで
-                    // ディスクにアクセスすることはない。
-                    // （管理領域から外すだけで、例えばディスク上の値を0クリアするようなことはない）
-                    self.data_region.delete(portion);
-                }
+                // `dst`が同じ部分領域を参照していた場合に備えて、先に参照を追加しておく
+                self.dedup.link(portion);
+                track!(self.delete_if_exists(dst, false))?;
+                let record = LinkRecord::new(*dst, portion);
+                track!(self
+                    .journal_region
+                    .records_link(&mut self.lump_index, &record))?;
+                self.lump_index.insert(*dst, Portion::Data(portion));
             }
         }
-
-        Ok(targets)
+        self.metrics.linked_lumps.increment();
+        Ok(true)
     }
 
-    /// ストレージのブロック境界にアライメントされたメモリ領域を保持する`LumpData`インスタンスを返す.
+    /// `old_id`のlumpのIDを`new_id`に変更する.
     ///
-    /// `LumpData::new`関数に比べて、このメソッドが返した`LumpData`インスタンスは、
-    /// 事前に適切なアライメントが行われているため、`Storage::put`による保存時に余計なメモリコピーが
-    /// 発生することがなく、より効率的となる.
+    /// データ領域に格納されているlumpの場合には、データの読み書きは行われず、
+    /// 単一のジャーナルレコードの追記のみで、インデックス上の対応が付け替えられる.
+    /// そのため、サイズの大きなlumpであっても、IDの変更(e.g., スキーマ移行時のキーの付け替え)を低コストで行うことができる.
+    /// ジャーナル領域に埋め込まれているlumpの場合には、データを`new_id`用に再度埋め込んだ改名レコードが一つ追記される
+    /// (拡張レコードの導入以前のストレージでは、埋め込みPUTとDELETEの二つのレコードとなる).
     ///
-    /// # 注意
+    /// 既に`new_id`のlumpが存在する場合には、`put`と同様に上書きされる.
     ///
-    /// このストレージが返した`LumpData`インスタンスを、別の(ブロックサイズが異なる)ストレージに
-    /// 保存しようとした場合には、エラーが発生する.
+    /// IDが変更された場合には`Ok(true)`が、`old_id`のlumpが存在しない場合には`Ok(false)`が、返される.
     ///
     /// # Errors
     ///
-    /// 指定されたサイズが`MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
-    pub fn allocate_lump_data(&self, size: usize) -> Result<LumpData> {
-        track!(LumpData::aligned_allocate(size, self.header.block_size))
-    }
-
-    /// `allocate_lump_data`メソッドにデータの初期化を加えたメソッド.
+    /// ストレージのバージョンが拡張レコードの導入(`1.2`)以前のまま(`StorageBuilder::upgrade_minor_version`)で、
+    /// `old_id`のlumpがデータ領域に格納されている場合には、`ErrorKind::InvalidInput`エラーが返される.
     ///
-    /// このメソッドの呼び出しは、以下のコードと等価となる:
-    /// ```ignore
-    /// let mut data = track!(self.allocate_lump_data(bytes.len()))?;
-    /// data.as_bytes_mut().copy_from_slice(bytes);
-    /// ```
+    /// # Error Handlings
     ///
-    /// 詳細な挙動に関しては`allocate_lump_data`のドキュメントを参照のこと.
-    pub fn allocate_lump_data_with_bytes(&self, bytes: &[u8]) -> Result<LumpData> {
-        let mut data = track!(self.allocate_lump_data(bytes.len()))?;
-        data.as_bytes_mut().copy_from_slice(bytes);
-        Ok(data)
+    /// このメソッドがエラーを返した場合には、
+    /// 不整合ないしI/O周りで致命的な問題が発生している可能性があるので、
+    /// 以後はこのインスタンスの使用を中止するのが望ましい.
+    pub fn rename(&mut self, old_id: &LumpId, new_id: &LumpId) -> Result<bool> {
+        let portion = match self.lump_index.get(old_id) {
+            None => return Ok(false),
+            Some(_) if old_id == new_id => return Ok(true),
+            Some(portion) => portion,
+        };
+        match portion {
+            Portion::Journal(portion) => {
+                let data = track!(self.journal_region.get_embedded_data(portion))?;
+                track!(self.delete_if_exists(new_id, false))?;
+                if self.journal_region.supports_extension_records()
+                    && data.len() <= EmbeddedRenameRecord::MAX_DATA_SIZE
+                {
+                    // 改名前のIDの埋め込みレコードが、GCによって再配置されないように、先にインデックスから取り除く
+                    self.lump_index.remove(old_id);
+                    let record = EmbeddedRenameRecord::new(*old_id, *new_id, &data);
+                    track!(self
+                        .journal_region
+                        .records_embedded_rename(&mut self.lump_index, &record))?;
+                } else {
+                    // 拡張レコードを書き込めない場合や、データが大き過ぎて改名レコードに収まらない場合には、
+                    // 埋め込みPUTとDELETEの二つのレコードで代用する
+                    track!(self
+                        .journal_region
+                        .records_embed(&mut self.lump_index, new_id, &data))?;
+                    self.lump_index.remove(old_id);
+                    track!(self
+                        .journal_region
+                        .records_delete(&mut self.lump_index, old_id))?;
+                }
+            }
+            Portion::Data(portion) => {
+                track_assert!(
+                    self.journal_region.supports_extension_records(),
+                    ErrorKind::InvalidInput,
+                    "Renaming requires a newer storage version: minor_version={}",
+                    self.header.minor_version
+                );
+
+                // `old_id`の部分領域への参照は、そのまま`new_id`に引き継がれるので、参照数は変わらない
+                // (`new_id`が同じ部分領域を共有していた場合には、その分の参照のみが取り除かれる)
+                track!(self.delete_if_exists(new_id, false))?;
+                self.lump_index.remove(old_id);
+                let record = RenameRecord::new(*old_id, *new_id, portion);
+                track!(self
+                    .journal_region
+                    .records_rename(&mut self.lump_index, &record))?;
+                self.lump_index.insert(*new_id, Portion::Data(portion));
+            }
+        }
+        self.metrics.renamed_lumps.increment();
+        Ok(true)
     }
 
-    /// 補助的な処理を一単位実行する.
+    /// 監査レコードを伴う`put`を行う.
     ///
-    /// このメソッドを呼ばなくても動作上は問題はないが、
-    /// リソースが空いているタイミングで実行することによって、
-    /// 全体的な性能を改善できる可能性がある.
-    pub fn run_side_job_once(&mut self) -> Result<()> {
-        track!(self.journal_region.run_side_job_once(&mut self.lump_index))?;
-        Ok(())
-    }
+    /// `metadata`には、操作の実施者や時刻等の任意の情報を指定する.
+    /// PUT操作の記録と監査レコードは、一つのレコードとしてジャーナルに書き込まれるため、
+    /// 片方のみが永続化されることはない.
+    ///
+    /// 監査付きのPUTレコードはデータ領域の部分領域のみを参照可能なため、`data`は常にデータ領域に書き込まれる.
+    /// また、重複排除や上書き更新(`StorageBuilder::{deduplication, overwrite_in_place}`)も行われない.
+    /// 同一内容での上書きが省略された場合(`StorageBuilder::skip_identical_overwrites`)には、単独の監査レコードのみが記録される.
+    ///
+    /// 監査証跡が無効な場合(`StorageBuilder::audit_trail`)には、`put`と同様に振る舞う.
+    ///
+    /// # Errors
+    ///
+    /// `metadata`の長さが`AuditRecord::MIN_METADATA_SIZE`以上`AuditRecord::MAX_METADATA_SIZE`以下ではない場合には、
+    /// `ErrorKind::InvalidInput`エラーが返される.
+    ///
+    /// それ以外のエラー時の扱いは`put`と同様.
+    pub fn put_with_audit(
+        &mut self,
+        lump_id: &LumpId,
+        data: &LumpData,
+        metadata: &[u8],
+    ) -> Result<PutReport> {
+        let record = track!(AuditRecord::new(AuditOperation::Put, *lump_id, metadata))?;
+        if !self.journal_region.is_audit_trail_enabled() {
+            return track!(self.put(lump_id, data));
+        }
+        if self.config.skip_identical_overwrites
+            && track!(self.is_identical_overwrite(lump_id, data))?
+        {
+            track!(self
+                .journal_region
+                .records_audit(&mut self.lump_index, &record))?;
+            self.metrics.unchanged_overwrites.increment();
+            return Ok(PutReport {
+                is_new: false,
+                embedded: matches!(data.as_inner(), LumpDataInner::JournalRegion(_)),
+                allocated_blocks: 0,
+                deduplicated: false,
+                journal_synced: false,
+                unchanged: true,
+                in_place: false,
+            });
+        }
 
-    /// メモリにバッファされているジャーナルをディスクに書き出す。
-    /// 副作用として、バッファはクリアされる。
-    pub fn journal_sync(&mut self) -> Result<()> {
-        self.journal_region.sync()
+        track!(self.journal_region.check_admission(&mut self.lump_index))?;
+        let syncs = self.journal_region.metrics().syncs();
+        let updated = track!(self.delete_if_exists(lump_id, false))?;
+        let portion = match data.as_inner() {
+            LumpDataInner::DataRegion(data) => {
+                track!(self.put_lump_to_data_region(lump_id, data, None, Some(&record)))?
+            }
+            LumpDataInner::DataRegionUnaligned(data) | LumpDataInner::JournalRegion(data) => {
+                let mut aligned_data = DataRegionLumpData::new(data.len(), self.header.block_size);
+                aligned_data.as_bytes_mut().copy_from_slice(data);
+                track!(self.put_lump_to_data_region(lump_id, &aligned_data, None, Some(&record)))?
+            }
+        };
+        self.metrics.put_lumps_at_running.increment();
+        self.metrics
+            .logical_written_bytes
+            .add_u64(data.as_bytes().len() as u64);
+        Ok(PutReport {
+            is_new: !updated,
+            embedded: false,
+            allocated_blocks: portion.len,
+            deduplicated: false,
+            journal_synced: self.journal_region.metrics().syncs() != syncs,
+            unchanged: false,
+            in_place: false,
+        })
     }
 
-    /// ジャーナル領域に対するGCを実行する。
+    /// 監査レコードを伴う`delete`を行う.
     ///
-    /// ここで実行するGCは、ジャーナル領域のHEADからTAILの間の値を全て検査し、
-    /// `journal_gc` を呼び出した段階で破棄できる全エントリを削除する。
+    /// 監査レコードは、実際に削除が行われた場合にのみ、DELETE操作の記録と一つのレコードとしてジャーナルに書き込まれる.
     ///
-    /// 通常のstorageの使用では、各エントリをジャーナルに追加する際に、
-    /// 小規模のGCが走る（正確には `JournalRegion::gc_once`）ので、
-    /// このGCを手動で呼び出す必要はない。
-    pub fn journal_gc(&mut self) -> Result<()> {
-        self.journal_region.gc_all_entries(&mut self.lump_index)
+    /// `metadata`の扱いは`put_with_audit`と同様.
+    pub fn delete_with_audit(&mut self, lump_id: &LumpId, metadata: &[u8]) -> Result<bool> {
+        let record = track!(AuditRecord::new(AuditOperation::Delete, *lump_id, metadata))?;
+        if !self.journal_region.is_audit_trail_enabled() {
+            return track!(self.delete(lump_id));
+        }
+        track!(self.delete_if_exists_with(lump_id, true, Some(&record)))
     }
 
-    /// ジャーナル領域のスナップショットを取得する。
-    pub fn journal_snapshot(&mut self) -> Result<JournalSnapshot> {
-        let (unreleased_head, head, tail, entries) = track!(self.journal_region.journal_entries())?;
-        Ok(JournalSnapshot {
-            unreleased_head,
-            head,
-            tail,
-            entries,
-        })
+    /// 監査レコードを伴う`delete_secure`を行う.
+    ///
+    /// 監査レコードの扱いは`delete_with_audit`と同様.
+    pub fn delete_secure_with_audit(
+        &mut self,
+        lump_id: &LumpId,
+        fill: u8,
+        metadata: &[u8],
+    ) -> Result<bool> {
+        let record = track!(AuditRecord::new(AuditOperation::Delete, *lump_id, metadata))?;
+        if !self.journal_region.is_audit_trail_enabled() {
+            return track!(self.delete_secure(lump_id, fill));
+        }
+        track!(self.overwrite_before_delete(lump_id, fill))?;
+        track!(self.delete_if_exists_with(lump_id, true, Some(&record)))
     }
 
-    /// ジャーナル領域に対する自動小規模GCの有無を切り替えることができる（ユニットテスト用メソッド）。
+    /// 指定されたIDのlumpのデータを`fill`で上書きした上で、そのlumpを削除する.
     ///
-    /// デフォルトの設定では、ジャーナル領域への変更操作が行われた際に、
-    /// 1-stepのGC(`JournalRegion::gc_once`)が実行されるが、
-    /// `set_automatic_gc_mode(false)`を呼び出すことで
-    /// この小規模のGCを実行しないようにできる。
-    #[allow(dead_code)]
-    pub(crate) fn set_automatic_gc_mode(&mut self, enable: bool) {
-        self.journal_region.set_automatic_gc_mode(enable);
+    /// データ領域に格納されているlumpの場合には、上書きが永続化された後に、
+    /// 削除のジャーナルへの記録および部分領域の解放が行われる.
+    /// そのため、グローバルなスクラブ(`StorageBuilder::scrub_released_portions`)を有効にせずとも、
+    /// 特定のlumpのデータのみを確実に消去することができる.
+    ///
+    /// ただし、ジャーナル領域に埋め込まれているlumpの場合には、レコードの上書きは行われず、
+    /// データはジャーナルのGCによってレコードが回収されるまで残り続ける点には注意が必要.
+    /// 同様に、重複排除によって他のlumpとデータを共有している場合にも、上書きは行われない.
+    ///
+    /// 結果の意味およびエラー時の扱いは`delete`と同様.
+    pub fn delete_secure(&mut self, lump_id: &LumpId, fill: u8) -> Result<bool> {
+        track!(self.overwrite_before_delete(lump_id, fill))?;
+        track!(self.delete_if_exists(lump_id, true))
     }
 
-    fn put_lump_to_data_region(
+    /// `delete_secure`での削除に先立って、lumpのデータを`fill`で上書きする.
+    fn overwrite_before_delete(&mut self, lump_id: &LumpId, fill: u8) -> Result<()> {
+        if let Some(Portion::Data(portion)) = self.lump_index.get(lump_id) {
+            if !self.dedup.is_shared(&portion) {
+                track!(self.data_region.overwrite(portion, fill))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// LumpIdの範囲を用いて、これに含まれるLumpIdを全て削除する。
+    ///
+    /// 返り値がOk(vec)の場合、このvecは実際に削除したlump id全体となっている。
+    /// （注意: rangeには、lusf上にないlump idが一般には含まれている）
+    ///
+    /// # Error Handlings
+    ///
+    /// このメソッドがエラーを返した場合には、
+    /// 不整合ないしI/O周りで致命的な問題が発生している可能性があるので、
+    /// 以後はこのインスタンスの使用を中止するのが望ましい.
+    ///
+    /// # 注意
+    ///
+    /// `range`が大量の要素を含む場合には、
+    /// このメソッドは巨大なLumpIdの配列を返しうることに注意されたい。
+    /// 一度の呼び出しで処理する量を制限したい場合には`start_sliced_delete_range`を使用すること.
+    pub fn delete_range<R: Into<LumpRange>>(&mut self, range: R) -> Result<Vec<LumpId>> {
+        let mut delete = track!(self.start_sliced_delete_range(range))?;
+        Ok(self
+            .sliced_delete_range_step(&mut delete, usize::MAX)
+            .expect("Never fails"))
+    }
+
+    /// 指定されたIDのlump群を削除する.
+    ///
+    /// 結果は`lump_ids`と同じ順番で、各lumpが存在して削除されたかどうかを示す.
+    /// 同じIDが複数回指定された場合には、最初のもの以外は`false`となる.
+    ///
+    /// 削除のジャーナルへの記録は、IDが連続している部分は範囲削除レコードにまとめて、
+    /// それ以外はlump毎の削除レコードとして行われる.
+    ///
+    /// # Error Handlings
+    ///
+    /// `delete_range`と同様.
+    pub fn delete_many(&mut self, lump_ids: &[LumpId]) -> Result<Vec<bool>> {
+        let mut targets = BTreeSet::new();
+        let existence = lump_ids
+            .iter()
+            .map(|id| self.lump_index.get(id).is_some() && targets.insert(*id))
+            .collect();
+
+        let mut targets = targets.into_iter().peekable();
+        while let Some(start) = targets.next() {
+            let mut end = start;
+            while let Some(&next) = targets.peek() {
+                if next.as_u128() != end.as_u128() + 1 {
+                    break;
+                }
+                end = next;
+                targets.next();
+            }
+            if start == end {
+                track!(self.delete_if_exists(&start, true))?;
+            } else {
+                track!(self.delete_range(start..=end))?;
+            }
+        }
+        Ok(existence)
+    }
+
+    /// 範囲削除を、複数回に分けて実行するための準備を行う.
+    ///
+    /// `delete_range`と同様に、範囲削除レコードを一つだけジャーナルに書き込んだ上で、範囲の進行状況を表す`SlicedDeleteRange`を返す.
+    /// 以後、`sliced_delete_range_step`を完了まで繰り返し呼び出すことで、
+    /// 範囲内のlumpがインデックスから取り除かれ、割当済みのデータ領域が解放される.
+    ///
+    /// 範囲内のlumpは、このメソッドの呼び出し時点で全て削除されたものとして扱われる
+    /// (i.e., 完了前でも`get`等からは参照できない)ため、削除途中の状態が観測されることはない.
+    /// ただし`UsageSummary`の値は、インデックスから取り除かれる度に更新される.
+    ///
+    /// なお、`SlicedDeleteRange`が完了するまでの間に許されるのは、ストレージの状態を変更しない操作(e.g., `get`)のみである.
+    ///
+    /// # Error Handlings
+    ///
+    /// `delete_range`と同様.
+    /// また、完了していない`SlicedDeleteRange`が既に存在する場合には`ErrorKind::InvalidInput`エラーが返される.
+    pub fn start_sliced_delete_range<R: Into<LumpRange>>(
         &mut self,
-        lump_id: &LumpId,
-        data: &DataRegionLumpData,
-    ) -> Result<()> {
-        let portion = track!(self.data_region.put(data))?;
+        range: R,
+    ) -> Result<SlicedDeleteRange> {
+        track_assert!(
+            !self.lump_index.has_hidden_range(),
+            ErrorKind::InvalidInput,
+            "Another sliced delete_range is in progress"
+        );
+
+        // 範囲削除レコードは終端を含まない範囲しか表現できないため、
+        // `LumpId::MAX`が範囲に含まれる場合には、それのみ個別に削除する.
+        let (range, include_max) = range.into().split_max();
+        let max_deleted = include_max && track!(self.delete(&LumpId::MAX))?;
+
+        // ジャーナル領域に範囲削除レコードを一つ書き込むため、一度のディスクアクセスが起こる。
+        // 削除レコードを範囲分書き込むわけ *ではない* ため、複数回のディスクアクセスは発生しない。
         track!(self
             .journal_region
-            .records_put(&mut self.lump_index, lump_id, portion)
-            .map_err(|e| {
-                self.data_region.delete(portion);
-                e
-            }))?;
-        self.lump_index.insert(*lump_id, Portion::Data(portion));
-        Ok(())
+            .records_delete_range(&mut self.lump_index, range.clone()))?;
+        self.lump_index.hide_range(range.clone());
+        Ok(SlicedDeleteRange {
+            range,
+            deleted: Vec::new(),
+            max_deleted,
+        })
     }
 
-    fn delete_if_exists(&mut self, lump_id: &LumpId, do_record: bool) -> Result<bool> {
-        if let Some(portion) = self.lump_index.remove(lump_id) {
+    /// `start_sliced_delete_range`で開始した範囲削除を、最大`max_lumps`個のlump分だけ進める.
+    ///
+    /// なお`max_lumps`が`0`の場合には`1`が指定されたものとして扱われる.
+    ///
+    /// 範囲内の全てのlumpの処理が完了した場合には、削除されたlumpのIDの一覧(昇順)が返される.
+    /// それ以外の場合には`None`が返される.
+    pub fn sliced_delete_range_step(
+        &mut self,
+        delete: &mut SlicedDeleteRange,
+        max_lumps: usize,
+    ) -> Option<Vec<LumpId>> {
+        let max_lumps = cmp::max(max_lumps, 1);
+        let released = self.release_range(delete.range.clone(), max_lumps);
+        let done = released.len() < max_lumps;
+        if let Some(&last) = released.last() {
+            delete.range.start = last;
+        }
+        delete.deleted.extend(released);
+        if !done {
+            return None;
+        }
+
+        self.lump_index.unhide_range();
+        let mut deleted = mem::take(&mut delete.deleted);
+        if delete.max_deleted {
+            deleted.push(LumpId::MAX);
+        }
+        Some(deleted)
+    }
+
+    /// `delete_range`の処理量を制限したバージョン.
+    ///
+    /// `range`の先頭から最大`max_lumps`個のlumpを削除し、削除したlumpのIDを返す.
+    /// 呼び出し後の`range.start`は、未処理部分の開始位置に更新される.
+    /// なお`max_lumps`が`0`の場合には`1`が指定されたものとして扱われる.
+    ///
+    /// `range`が空になるまで繰り返し呼び出すことで、`delete_range`と同等の結果が得られる.
+    /// 各呼び出しは、それぞれ独立した範囲削除としてジャーナルに記録される.
+    /// 範囲全体を一つの範囲削除として扱いたい場合には`start_sliced_delete_range`を使用すること.
+    ///
+    /// # Error Handlings
+    ///
+    /// `delete_range`と同様.
+    pub fn delete_range_step(
+        &mut self,
+        range: &mut Range<LumpId>,
+        max_lumps: usize,
+    ) -> Result<Vec<LumpId>> {
+        if range.start >= range.end {
+            return Ok(Vec::new());
+        }
+
+        let step_end = self
+            .lump_index
+            .nth_in_range(range.clone(), cmp::max(max_lumps, 1))
+            .unwrap_or(range.end);
+        let step = Range {
+            start: range.start,
+            end: step_end,
+        };
+        track!(self
+            .journal_region
+            .records_delete_range(&mut self.lump_index, step.clone()))?;
+        range.start = step_end;
+
+        Ok(self.release_range(step, usize::MAX))
+    }
+
+    /// 範囲内のlumpを先頭から最大`max_lumps`個、インデックスから取り除き、割当済みのデータ領域を解放する.
+    ///
+    /// 削除の記録は事前にジャーナルに書き込まれている必要がある.
+    fn release_range(&mut self, range: Range<LumpId>, max_lumps: usize) -> Vec<LumpId> {
+        let mut deleted = Vec::new();
+        for (lump_id, portion) in self.lump_index.drain_range(range).take(max_lumps) {
             self.metrics.delete_lumps.increment();
-            if do_record {
-                track!(self
-                    .journal_region
-                    .records_delete(&mut self.lump_index, lump_id,))?;
-            }
+
             if let Portion::Data(portion) = portion {
-                self.data_region.delete(portion);
+                // DataRegion::deleteはメモリアロケータに対する解放要求をするのみで
+                // ディスクにアクセスすることはない。
+                // （管理領域から外すだけで、例えばディスク上の値を0クリアするようなことはない）
+                if self.dedup.release(&portion) {
+                    self.data_region.delete(portion);
+                }
             }
-            Ok(true)
-        } else {
-            Ok(false)
+            deleted.push(lump_id);
         }
+        deleted
     }
-}
 
-/// ストレージ使用量。
-#[derive(Debug, Clone)]
-pub enum StorageUsage {
-    /// 取得に失敗したなど不明であることを表す。
-    Unknown,
-    /// 近似値。
-    Approximate(u64),
-}
-impl StorageUsage {
-    /// 近似値として `StorageUsage` を生成する。
-    pub fn approximate(usage: u64) -> Self {
-        StorageUsage::Approximate(usage)
+    /// ストレージのブロック境界にアライメントされたメモリ領域を保持する`LumpData`インスタンスを返す.
+    ///
+    /// `LumpData::new`関数に比べて、このメソッドが返した`LumpData`インスタンスは、
+    /// 事前に適切なアライメントが行われているため、`Storage::put`による保存時に余計なメモリコピーが
+    /// 発生することがなく、より効率的となる.
+    ///
+    /// # 注意
+    ///
+    /// このストレージが返した`LumpData`インスタンスを、別の(ブロックサイズが異なる)ストレージに
+    /// 保存しようとした場合には、エラーが発生する.
+    ///
+    /// # Errors
+    ///
+    /// 指定されたサイズが`MAX_SIZE`を超えている場合は、`ErrorKind::InvalidInput`エラーが返される.
+    pub fn allocate_lump_data(&self, size: usize) -> Result<LumpData> {
+        track!(LumpData::aligned_allocate(size, self.header.block_size))
     }
 
-    /// 使用量不明として `StorageUsage` を生成する。
-    pub fn unknown() -> Self {
-        StorageUsage::Unknown
+    /// `allocate_lump_data`メソッドにデータの初期化を加えたメソッド.
+    ///
+    /// このメソッドの呼び出しは、以下のコードと等価となる:
+    /// ```ignore
+    /// let mut data = track!(self.allocate_lump_data(bytes.len()))?;
+    /// data.as_bytes_mut().copy_from_slice(bytes);
+    /// ```
+    ///
+    /// 詳細な挙動に関しては`allocate_lump_data`のドキュメントを参照のこと.
+    pub fn allocate_lump_data_with_bytes(&self, bytes: &[u8]) -> Result<LumpData> {
+        let mut data = track!(self.allocate_lump_data(bytes.len()))?;
+        data.as_bytes_mut().copy_from_slice(bytes);
+        Ok(data)
     }
 
-    /// バイト数として近似値を返す。
-    pub fn bytecount(&self) -> Option<u64> {
-        match *self {
-            StorageUsage::Unknown => None,
-            StorageUsage::Approximate(bytes) => Some(bytes),
+    /// 断片として隔離されているデータ領域の空き領域を、割当に使用可能な状態に戻す.
+    ///
+    /// 戻された空き領域の数が返される.
+    ///
+    /// 空き領域のリストの長さの上限(`StorageBuilder::max_free_portions`)が設定されている場合には、
+    /// 上限に達するまで、サイズの大きいものから順に戻される.
+    ///
+    /// 詳細は`StorageBuilder::free_fragment_threshold`を参照のこと.
+    pub fn reclaim_free_fragments(&mut self) -> usize {
+        self.data_region.reclaim_free_fragments()
+    }
+
+    /// 補助的な処理を一単位実行する.
+    ///
+    /// このメソッドを呼ばなくても動作上は問題はないが、
+    /// リソースが空いているタイミングで実行することによって、
+    /// 全体的な性能を改善できる可能性がある.
+    pub fn run_side_job_once(&mut self) -> Result<()> {
+        track!(self.flush_pending_discards())?;
+        track!(self.data_region.scrub_pending_portions())?;
+        track!(self
+            .journal_region
+            .run_side_job_once(&mut self.lump_index, None))?;
+        track!(self.reembed_small_lumps())?;
+        track!(self.update_index_shadow())?;
+        Ok(())
+    }
+
+    /// `run_side_job_once`の実行時間を制限したバージョン.
+    ///
+    /// ジャーナルGCの開始から`limit`で指定された時間が経過した時点で、補助的な処理を打ち切る.
+    /// ただし、処理の最小単位(e.g., 一レコード分のGC)の途中で打ち切られることはないため、
+    /// 実際の実行時間は`limit`を多少超過する可能性がある.
+    ///
+    /// `limit`が極端に短い場合でも、ジャーナルGCは毎回最低でも一単位は実行される.
+    /// また、保留中の破棄通知やスクラブは`limit`の対象外であり、常に実行される.
+    pub fn run_side_job_once_within(&mut self, limit: Duration) -> Result<()> {
+        track!(self.flush_pending_discards())?;
+        track!(self.data_region.scrub_pending_portions())?;
+        let deadline = Instant::now() + limit;
+        track!(self
+            .journal_region
+            .run_side_job_once(&mut self.lump_index, Some(deadline)))?;
+        if Instant::now() < deadline {
+            track!(self.reembed_small_lumps())?;
+        }
+        if Instant::now() < deadline {
+            track!(self.update_index_shadow())?;
         }
+        Ok(())
     }
-}
-impl Default for StorageUsage {
-    fn default() -> Self {
-        StorageUsage::Unknown
+
+    /// メモリにバッファされているジャーナルをディスクに書き出す。
+    /// 副作用として、バッファはクリアされる。
+    pub fn journal_sync(&mut self) -> Result<()> {
+        track!(self.journal_region.sync())?;
+        if self.data_region.has_pending_discards() {
+            // 同期によって削除が永続化されたので、保留中の破棄通知を行う
+            track!(self.data_region.discard_pending_portions())?;
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::fs::OpenOptions;
-    use std::mem;
-    use tempdir::TempDir;
-    use trackable::result::TestResult;
+    /// 致命的なエラーによってデバイスが停止する直前に、可能な範囲でジャーナルを永続化する.
+    ///
+    /// `journal_sync`とは異なり、保留中の破棄通知等の付随処理は行わない.
+    #[cfg(feature = "device")]
+    pub(crate) fn sync_journal_before_stop(&mut self) -> Result<()> {
+        track!(self.journal_region.sync_before_stop())
+    }
 
-    use super::*;
-    use crate::block::BlockSize;
-    use crate::lump::{LumpData, LumpId};
-    use crate::nvm::{FileNvm, SharedMemoryNvm};
-    use crate::ErrorKind;
+    /// ストレージをクローズする.
+    ///
+    /// バッファされているジャーナルを同期した上で、次回のオープンを高速化するためのチェックポイントを書き出す.
+    /// チェックポイントが有効な場合には、次回のオープン時に、ジャーナルの再生およびアロケータの再構築が省略される.
+    ///
+    /// なお、データ領域にチェックポイントを格納できるだけの空きがない場合や、
+    /// 重複排除ないし別名によって共有され得る部分領域が存在する場合には、その書き出しは省略される.
+    ///
+    /// `StorageBuilder::index_shadow_file`が指定されている場合には、インデックスのシャドウファイルも更新される.
+    ///
+    /// また、ジャーナルの末尾にはクリーンシャットダウンの印が書き込まれ、
+    /// 次回のオープン時の`was_clean_shutdown`の結果に反映される.
+    ///
+    /// このメソッドを呼ばずに`Storage`インスタンスを破棄した場合には、
+    /// 次回のオープン時には、(有効なシャドウファイルが存在しない限り)ジャーナルから状態が再構築される.
+    pub fn close(mut self) -> Result<()> {
+        track!(self
+            .journal_region
+            .records_clean_shutdown(&mut self.lump_index))?;
+        track!(self.journal_sync())?;
 
-    #[test]
-    fn it_works() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        // スクラブ待ちの部分領域はチェックポイント上では空き領域として扱えないため、ここで全て処理しておく
+        track!(self.data_region.scrub_all_pending_portions())?;
+        if !self.dedup.is_empty() {
+            // チェックポイントには重複排除用のハッシュ値が含まれないため、書き出さない
+            return Ok(());
+        }
+        if self.index_shadow.is_some() {
+            track!(self.write_index_shadow(true))?;
+        }
+        if let Some((offset, size, checksum)) =
+            track!(self.data_region.write_checkpoint(&self.lump_index))?
+        {
+            track!(self.journal_region.write_checkpoint(offset, size, checksum))?;
+        }
+        Ok(())
+    }
 
-        // create
-        let nvm = track!(FileNvm::create(
-            dir.path().join("test.lusf"),
-            BlockSize::min().ceil_align(1024 * 1024)
-        ))?;
-        let mut storage = track!(Storage::create(nvm))?;
+    /// ジャーナル領域に対するGCを実行する。
+    ///
+    /// ここで実行するGCは、ジャーナル領域のHEADからTAILの間の値を全て検査し、
+    /// `journal_gc` を呼び出した段階で破棄できる全エントリを削除する。
+    ///
+    /// 通常のstorageの使用では、各エントリをジャーナルに追加する際に、
+    /// 小規模のGCが走る（正確には `JournalRegion::gc_once`）ので、
+    /// このGCを手動で呼び出す必要はない。
+    pub fn journal_gc(&mut self) -> Result<()> {
+        self.journal_region.gc_all_entries(&mut self.lump_index)
+    }
 
-        assert!(storage.get(&id("000"))?.is_none());
-        assert!(storage.put(&id("000"), &data("hello"))?);
-        assert!(!storage.put(&id("000"), &data("hello"))?);
-        assert_eq!(storage.get(&id("000"))?, Some(data("hello")));
-        assert_eq!(
-            storage.head(&id("000")).map(|h| h.approximate_data_size),
-            Some(5)
-        );
-        assert!(storage.delete(&id("000"))?);
-        assert!(!storage.delete(&id("000"))?);
-        assert!(storage.get(&id("000"))?.is_none());
-        assert!(storage.head(&id("000")).is_none());
+    /// ジャーナル領域に対する全体GC(`journal_gc`)を、複数回に分けて実行するための準備を行う.
+    ///
+    /// 以後、`sliced_journal_gc_step`を完了まで繰り返し呼び出すことで、`journal_gc`と同様のGCが行われる.
+    ///
+    /// `SlicedPut`等とは異なり、各ステップの合間には、ストレージの状態を変更する操作(e.g., `put`や`run_side_job_once`)を行っても構わない.
+    /// ただし、GCの対象となるのは開始時点でジャーナルに存在したエントリ群のみである.
+    pub fn start_sliced_journal_gc(&mut self) -> Result<SlicedJournalGc> {
+        let gc = track!(self.journal_region.start_full_gc(&mut self.lump_index))?;
+        Ok(SlicedJournalGc { gc })
+    }
 
-        assert!(storage.put(&id("000"), &data("hello"))?);
-        assert!(storage.put(&id("111"), &data("world"))?);
-        for _ in 0..10 {
-            track!(storage.run_side_job_once())?;
-            assert!(storage.put(&id("222"), &data("quux"))?);
-            assert!(storage.delete(&id("222"))?);
-        }
-        mem::drop(storage);
+    /// `start_sliced_journal_gc`で開始した全体GCを、GCキュー一杯分(`StorageBuilder::journal_gc_queue_size`)だけ進める.
+    ///
+    /// GCが完了した場合には`true`が返される.
+    pub fn sliced_journal_gc_step(&mut self, gc: &mut SlicedJournalGc) -> Result<bool> {
+        track!(self
+            .journal_region
+            .full_gc_step(&mut self.lump_index, &mut gc.gc))
+    }
 
-        // open
-        let nvm = track!(FileNvm::open(dir.path().join("test.lusf")))?;
+    /// ジャーナル領域を、生存しているレコード群のみを先頭に詰めた状態に書き直す.
+    ///
+    /// 全体GC(`journal_gc`)で不要なエントリ群を取り除いた上で、残ったエントリ群をリングバッファの先頭から
+    /// 連続して書き込み、開始位置と終端位置をリセットする.
+    /// これによって、周回を跨ぐことによる断片化が解消され、次回のオープン時のジャーナルの再生も高速になる.
+    ///
+    /// ジャーナル領域全体の読み書きを伴うため、メンテナンス時間帯等に実行することを想定している.
+    ///
+    /// 書き直しが途中で中断された場合でも、ジャーナルは書き直し前の状態のまま有効であり、
+    /// 再度このメソッドを呼び出すことで、処理をやり直すことができる.
+    ///
+    /// # Errors
+    ///
+    /// ジャーナル領域の空きが不足していて、生存しているエントリ群を先頭に移動できない場合には、
+    /// `ErrorKind::StorageFull`エラーが返される.
+    pub fn rewrite_journal(&mut self) -> Result<()> {
+        track!(self.journal_region.rewrite(&mut self.lump_index))
+    }
+
+    /// ジャーナル領域のスナップショットを取得する。
+    pub fn journal_snapshot(&mut self) -> Result<JournalSnapshot> {
+        let (unreleased_head, head, tail, entries) = track!(self.journal_region.journal_entries())?;
+        Ok(JournalSnapshot {
+            unreleased_head,
+            head,
+            tail,
+            entries,
+        })
+    }
+
+    /// `journal_snapshot`の処理量を制限したバージョン.
+    ///
+    /// `cursor`が指す位置から、最大`max_entries`個のジャーナルエントリを返す.
+    /// 呼び出し後の`cursor`は、次に読み込みを開始すべき位置に更新され、
+    /// 末尾位置まで読み込んだ場合には`None`となる.
+    /// なお`max_entries`が`0`の場合には`1`が指定されたものとして扱われる.
+    ///
+    /// 巨大なジャーナル領域を、他の操作を長時間妨げることなく走査するために使用する.
+    ///
+    /// # 注意
+    ///
+    /// 呼び出しの間にGC等によってジャーナルが更新された場合には、
+    /// 走査結果に含まれないエントリや、重複して含まれるエントリが生じ得る.
+    /// 既に取り出し済みの位置を`cursor`が指している場合には、その時点の始端位置から読み込みが再開される.
+    pub fn journal_snapshot_step(
+        &mut self,
+        cursor: &mut Option<JournalCursor>,
+        max_entries: usize,
+    ) -> Result<Vec<JournalEntry>> {
+        let max_entries = cmp::max(max_entries, 1);
+        let (entries, next) = if let Some(c) = *cursor {
+            track!(self.journal_region.journal_entries_step(c, max_entries))?
+        } else {
+            (Vec::new(), None)
+        };
+        *cursor = next;
+        Ok(entries)
+    }
+
+    /// 起動直後のリクエストが、コールドスタートによる遅延の影響を受けないように、ウォームアップを行う.
+    ///
+    /// インデックス全体を走査して、そのメモリ領域を事前に参照しておく.
+    /// `touch_regions`が`true`の場合には、加えてジャーナル領域およびデータ領域の先頭ブロックの読み込みも行う.
+    ///
+    /// ストレージの状態は変更されない.
+    pub fn warm_up(&mut self, touch_regions: bool) -> Result<WarmUpReport> {
+        let start = Instant::now();
+        let mut lumps = 0;
+        for entry in self.lump_index.entries() {
+            hint::black_box(entry);
+            lumps += 1;
+        }
+        let touched_bytes = if touch_regions {
+            track!(self.journal_region.touch_first_block())?
+                + track!(self.data_region.touch_first_block())?
+        } else {
+            0
+        };
+        Ok(WarmUpReport {
+            lumps,
+            touched_bytes,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// ストレージの整合性検査を行う.
+    ///
+    /// 検査内容については[`CheckLevel`]を参照のこと.
+    ///
+    /// 検査対象のlump数に比例した時間がかかるので、呼び出す際には注意が必要.
+    /// 処理を分割して実行したい場合には`check_step`を使用すること.
+    ///
+    /// [`CheckLevel`]: ./enum.CheckLevel.html
+    pub fn check(&mut self, level: CheckLevel) -> Result<CheckReport> {
+        let mut checker = StorageChecker::new(level);
+        while !track!(self.check_step(&mut checker, usize::MAX))? {}
+        Ok(checker.into_report())
+    }
+
+    /// `check`の処理量を制限したバージョン.
+    ///
+    /// 一回の呼び出しで、最大`max_lumps`個のlumpの検査を行う.
+    /// `CheckLevel::Journal`の場合には、ジャーナルの読み込みも同様に、一回あたり最大`max_lumps`個のエントリずつ行われる.
+    /// `true`が返されるまで繰り返し呼び出すことで、`check`と同等の結果が`checker`に得られる.
+    pub fn check_step(&mut self, checker: &mut StorageChecker, max_lumps: usize) -> Result<bool> {
+        track!(checker.step(
+            &self.lump_index,
+            &self.dedup,
+            &self.data_region,
+            &mut self.journal_region,
+            max_lumps
+        ))
+    }
+
+    /// ジャーナル領域のGCキューの長さを変更する.
+    ///
+    /// 詳細は`StorageBuilder::journal_gc_queue_size`を参照のこと.
+    pub fn set_journal_gc_queue_size(&mut self, size: usize) {
+        self.journal_region.set_gc_queue_size(size);
+    }
+
+    /// 一回の補助タスクの中で実行される、ジャーナル領域のGCの単位処理の回数を変更する.
+    ///
+    /// 詳細は`StorageBuilder::journal_gc_batch_size`を参照のこと.
+    pub fn set_journal_gc_batch_size(&mut self, size: usize) {
+        self.journal_region.set_gc_batch_size(size);
+    }
+
+    /// ジャーナル領域のGC対象エントリ群の読み込みを、専用のスレッドで行うようにする.
+    ///
+    /// 有効にした場合には、GCの際のジャーナル領域の走査(読み込みおよびデコード)が別スレッドで実行され、
+    /// エントリの回収可否の判定や再配置のみが、従来通りに呼び出し元のスレッドで行われるようになる.
+    ///
+    /// NVMが別スレッドからの読み込みをサポートしていない場合
+    /// (i.e., `NonVolatileMemory::try_clone_reader`が`None`を返す場合)には、何もせずに`false`を返す.
+    pub fn enable_background_gc_scan(&mut self) -> Result<bool>
+    where
+        N: Send + 'static,
+    {
+        track!(self.journal_region.enable_background_gc_scan())
+    }
+
+    /// ジャーナル領域に対する自動小規模GCの有無を切り替えることができる（ユニットテスト用メソッド）。
+    ///
+    /// デフォルトの設定では、ジャーナル領域への変更操作が行われた際に、
+    /// 1-stepのGC(`JournalRegion::gc_once`)が実行されるが、
+    /// `set_automatic_gc_mode(false)`を呼び出すことで
+    /// この小規模のGCを実行しないようにできる。
+    #[allow(dead_code)]
+    pub(crate) fn set_automatic_gc_mode(&mut self, enable: bool) {
+        self.journal_region.set_automatic_gc_mode(enable);
+    }
+
+    /// 別スレッドで実行中のジャーナル領域の走査があれば、その完了を待機する（ユニットテスト用メソッド）。
+    #[allow(dead_code)]
+    pub(crate) fn wait_background_gc_scan(&mut self) -> Result<()> {
+        track!(self.journal_region.wait_background_gc_scan())
+    }
+
+    /// `lump_id`のlumpが既に存在し、その内容が`data`と同一かどうかを判定する.
+    ///
+    /// 格納先の領域の種類が異なる場合や、長さが異なる場合には、既存データの読み込みは行われない.
+    fn is_identical_overwrite(&mut self, lump_id: &LumpId, data: &LumpData) -> Result<bool> {
+        let bytes = data.as_bytes();
+        match (self.lump_index.get(lump_id), data.as_inner()) {
+            (Some(Portion::Journal(portion)), LumpDataInner::JournalRegion(_)) => {
+                if usize::from(portion.len) != bytes.len() {
+                    return Ok(false);
+                }
+                let existing = track!(self.journal_region.get_embedded_data(portion))?;
+                Ok(existing == bytes)
+            }
+            (Some(Portion::Data(portion)), LumpDataInner::DataRegion(_))
+            | (Some(Portion::Data(portion)), LumpDataInner::DataRegionUnaligned(_)) => {
+                if u32::from(portion.len) != self.data_region.required_blocks(bytes.len()) {
+                    return Ok(false);
+                }
+                let existing = track!(self.data_region.get(portion))?;
+                Ok(existing.as_bytes() == bytes)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// `lump_id`のlumpが格納されているデータ部分領域に、`data`を直接上書きする.
+    ///
+    /// 既存のlumpと`data`が共にデータ領域に格納されるもので、必要なブロック数が等しく、
+    /// かつ、部分領域が他のlumpと共有されていない場合にのみ上書きが行われ、その場合には`true`が返される.
+    fn overwrite_in_place(&mut self, lump_id: &LumpId, data: &LumpData) -> Result<bool> {
+        let portion = match (self.lump_index.get(lump_id), data.as_inner()) {
+            (Some(Portion::Data(portion)), LumpDataInner::DataRegion(_))
+            | (Some(Portion::Data(portion)), LumpDataInner::DataRegionUnaligned(_)) => portion,
+            _ => return Ok(false),
+        };
+
+        // 重複排除が有効な場合には、部分領域の内容とハッシュ値の対応が崩れないように、上書きは行わない
+        if self.dedup.is_enabled() || self.dedup.is_shared(&portion) {
+            return Ok(false);
+        }
+        let overwritten = match data.as_inner() {
+            LumpDataInner::DataRegion(data) => {
+                track!(self.data_region.overwrite_lump(portion, data))?
+            }
+            LumpDataInner::DataRegionUnaligned(data) => {
+                let mut aligned_data = DataRegionLumpData::new(data.len(), self.header.block_size);
+                aligned_data.as_bytes_mut().copy_from_slice(data);
+                track!(self.data_region.overwrite_lump(portion, &aligned_data))?
+            }
+            LumpDataInner::JournalRegion(_) => unreachable!(),
+        };
+        if !overwritten {
+            return Ok(false);
+        }
+
+        // インデックスの内容は変わらないが、上書き更新が行われたことをジャーナルに残しておく
+        let record = InPlacePutRecord::new(*lump_id, portion);
+        track!(self
+            .journal_region
+            .records_in_place_put(&mut self.lump_index, &record))?;
+        Ok(true)
+    }
+
+    /// 内容が同一のlumpが既に存在する場合には、そのデータ部分領域を共有する形でlumpを追加する.
+    ///
+    /// 共有が行われた場合には`true`が返される.
+    fn put_deduplicated(&mut self, lump_id: &LumpId, data: &[u8], hash: u64) -> Result<bool> {
+        let portion = match self.dedup.lookup(hash) {
+            None => return Ok(false),
+            Some(portion) => portion,
+        };
+
+        // ハッシュ値は衝突し得るので、実際の内容も比較する
+        if track!(self.data_region.get(portion))?.as_bytes() != data {
+            return Ok(false);
+        }
+        let record = DedupPutRecord::new(*lump_id, portion, hash);
+        track!(self
+            .journal_region
+            .records_dedup_put(&mut self.lump_index, &record))?;
+        self.lump_index.insert(*lump_id, Portion::Data(portion));
+        self.dedup.add_ref(portion, hash);
+        self.metrics.deduplicated_lumps.increment();
+        Ok(true)
+    }
+
+    /// `deadline`に応じてジャーナルの同期の扱いを切り替えた上で、`f`によるPUTを実行する.
+    ///
+    /// 詳細は`put_with_deadline`を参照のこと.
+    fn with_put_deadline<F>(&mut self, deadline: Deadline, f: F) -> Result<PutReport>
+    where
+        F: FnOnce(&mut Self) -> Result<PutReport>,
+    {
+        self.journal_region
+            .set_sync_deferral(deadline == Deadline::Infinity);
+        let result = track!(f(self));
+        self.journal_region.set_sync_deferral(false);
+
+        let report = result?;
+        if deadline == Deadline::Immediate && !report.journal_synced() {
+            track!(self.journal_sync())?;
+            return Ok(report.with_journal_synced());
+        }
+        Ok(report)
+    }
+
+    /// データ領域に対する`f`の実行が容量不足で失敗した場合に、
+    /// 解放が保留されている部分領域等を解放した上で、再試行する.
+    fn retry_if_full<T, F>(&mut self, mut f: F) -> Result<T>
+    where
+        F: FnMut(&mut DataRegion<N>) -> Result<T>,
+    {
+        match f(&mut self.data_region) {
+            Err(ref e)
+                if *e.kind() == ErrorKind::StorageFull
+                    && (self.data_region.has_pending_discards()
+                        || self.data_region.has_pending_scrubs()) =>
+            {
+                // 返却が保留されている部分領域を解放した上で、再試行する
+                // (隔離されている断片は、明示的に`reclaim_free_fragments`が呼ばれるまでは使用しない)
+                track!(self.flush_pending_discards())?;
+                track!(self.data_region.scrub_all_pending_portions())?;
+                track!(f(&mut self.data_region))
+            }
+            result => track!(result),
+        }
+    }
+
+    /// データ領域にlumpを書き込む.
+    ///
+    /// `hash`が指定されている場合には、重複排除付きのPUTとしてジャーナルに記録される.
+    /// `audit`が指定されている場合には、監査付きのPUTとしてジャーナルに記録される(`hash`との併用は不可).
+    fn put_lump_to_data_region(
+        &mut self,
+        lump_id: &LumpId,
+        data: &DataRegionLumpData,
+        hash: Option<u64>,
+        audit: Option<&AuditRecord>,
+    ) -> Result<DataPortion> {
+        debug_assert!(hash.is_none() || audit.is_none());
+        let portion = track!(self.retry_if_full(|r| r.put(data)))?;
+        let result = if let Some(record) = audit {
+            self.journal_region
+                .records_audited_put(&mut self.lump_index, record, portion)
+        } else if let Some(hash) = hash {
+            let record = DedupPutRecord::new(*lump_id, portion, hash);
+            self.journal_region
+                .records_dedup_put(&mut self.lump_index, &record)
+        } else {
+            self.journal_region
+                .records_put(&mut self.lump_index, lump_id, portion)
+        };
+        track!(result.inspect_err(|_e| {
+            self.data_region.delete(portion);
+        }))?;
+        self.lump_index.insert(*lump_id, Portion::Data(portion));
+        if let Some(hash) = hash {
+            self.dedup.add_ref(portion, hash);
+        }
+        Ok(portion)
+    }
+
+    fn flush_pending_discards(&mut self) -> Result<()> {
+        if self.data_region.has_pending_discards() {
+            track!(self.journal_sync())?;
+        }
+        Ok(())
+    }
+
+    /// 前回の書き出し以降にジャーナルが更新されていて、かつ、書き出しの間隔が経過している場合には、
+    /// インデックスのシャドウファイルを更新する.
+    ///
+    /// 解放待ちの部分領域が存在する場合には、それらが空き領域リストに含まれないため、更新は次回以降に持ち越される.
+    fn update_index_shadow(&mut self) -> Result<()> {
+        let position = self.journal_region.ring_buffer_position();
+        let is_due = self
+            .index_shadow
+            .as_ref()
+            .is_some_and(|s| s.is_due(position));
+        if !is_due
+            || !self.dedup.is_empty()
+            || self.data_region.has_pending_discards()
+            || self.data_region.has_pending_scrubs()
+        {
+            return Ok(());
+        }
+        track!(self.write_index_shadow(false))
+    }
+
+    /// インデックスのシャドウファイルを書き出す.
+    fn write_index_shadow(&mut self, clean_shutdown: bool) -> Result<()> {
+        let stamp = track!(self.journal_region.index_shadow_stamp(clean_shutdown))?;
+        let writer = self.index_shadow.as_mut().expect("Never fails");
+        track!(IndexShadow::write_to_file(
+            writer.path(),
+            &self.header.instance_uuid,
+            &stamp,
+            &self.lump_index,
+            self.data_region.free_portions()
+        ))?;
+        writer.written((stamp.ring_buffer_head, stamp.ring_buffer_tail));
+        self.metrics.index_shadow_writes.increment();
+        Ok(())
+    }
+
+    /// データ領域に格納されている小さなlumpを、ジャーナル領域に埋め込む形に移し替える.
+    ///
+    /// 一度の呼び出しで走査されるのは、前回の続きから最大`reembed_batch_size`個のlumpのみ.
+    /// 詳細は`StorageBuilder::reembed_threshold`を参照のこと.
+    fn reembed_small_lumps(&mut self) -> Result<()> {
+        let threshold = usize::from(self.config.reembed_threshold);
+        let limit = self.config.reembed_batch_size;
+        if threshold == 0 || limit == 0 {
+            return Ok(());
+        }
+
+        let start = self
+            .reembed_cursor
+            .map_or(Bound::Unbounded, Bound::Excluded);
+        let entries = self
+            .lump_index
+            .entries_with_limit((start, Bound::Unbounded), limit);
+        self.reembed_cursor = if entries.len() < limit {
+            None // 末尾に達したので、次回は先頭から走査する
+        } else {
+            entries.last().map(|&(lump_id, _)| lump_id)
+        };
+
+        let max_blocks = self.data_region.required_blocks(threshold);
+        for (lump_id, portion) in entries {
+            let portion = match portion {
+                Portion::Data(portion)
+                    if u32::from(portion.len) <= max_blocks && !self.dedup.is_shared(&portion) =>
+                {
+                    portion
+                }
+                _ => continue,
+            };
+
+            // ブロック数だけでは実際のサイズは分からないので、読み込んだ上で判定する
+            let data = track!(self.data_region.get(portion))?;
+            if data.as_bytes().len() > threshold {
+                continue;
+            }
+            track!(self.journal_region.records_embed(
+                &mut self.lump_index,
+                &lump_id,
+                data.as_bytes()
+            ))?;
+            if self.dedup.release(&portion) {
+                self.data_region.delete(portion);
+            }
+            self.metrics.reembedded_lumps.increment();
+        }
+        Ok(())
+    }
+
+    fn delete_if_exists(&mut self, lump_id: &LumpId, do_record: bool) -> Result<bool> {
+        track!(self.delete_if_exists_with(lump_id, do_record, None))
+    }
+
+    /// `audit`が指定されている場合には、削除は監査付きのDELETEとしてジャーナルに記録される.
+    fn delete_if_exists_with(
+        &mut self,
+        lump_id: &LumpId,
+        do_record: bool,
+        audit: Option<&AuditRecord>,
+    ) -> Result<bool> {
+        if let Some(portion) = self.lump_index.remove(lump_id) {
+            self.metrics.delete_lumps.increment();
+            if let (true, Some(record)) = (do_record, audit) {
+                track!(self
+                    .journal_region
+                    .records_audited_delete(&mut self.lump_index, record))?;
+            } else if do_record {
+                track!(self
+                    .journal_region
+                    .records_delete(&mut self.lump_index, lump_id,))?;
+            }
+            if let Portion::Data(portion) = portion {
+                // 重複排除によって共有されている場合には、最後の参照が無くなるまで解放しない
+                if self.dedup.release(&portion) {
+                    self.data_region.delete(portion);
+                }
+            }
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// ストレージ使用量。
+#[derive(Debug, Clone, Default)]
+pub enum StorageUsage {
+    /// 取得に失敗したなど不明であることを表す。
+    #[default]
+    Unknown,
+    /// 近似値。
+    Approximate(u64),
+}
+impl StorageUsage {
+    /// 近似値として `StorageUsage` を生成する。
+    pub fn approximate(usage: u64) -> Self {
+        StorageUsage::Approximate(usage)
+    }
+
+    /// 使用量不明として `StorageUsage` を生成する。
+    pub fn unknown() -> Self {
+        StorageUsage::Unknown
+    }
+
+    /// バイト数として近似値を返す。
+    pub fn bytecount(&self) -> Option<u64> {
+        match *self {
+            StorageUsage::Unknown => None,
+            StorageUsage::Approximate(bytes) => Some(bytes),
+        }
+    }
+}
+
+/// 条件付き削除(`Storage::delete_if`)の条件.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteCondition {
+    /// lumpのデータサイズが指定の値と一致すること.
+    Size(u32),
+
+    /// lumpのデータのCRC-32Cチェックサムが指定の値と一致すること.
+    Crc32c(u32),
+}
+impl DeleteCondition {
+    /// `data`のCRC-32Cチェックサムと一致することを条件とする`DeleteCondition`を生成する.
+    pub fn checksum_of(data: &[u8]) -> Self {
+        DeleteCondition::Crc32c(crc32c::crc32c(data))
+    }
+
+    /// `data`が条件を満たすかどうかを判定する.
+    pub fn is_satisfied_by(&self, data: &[u8]) -> bool {
+        match *self {
+            DeleteCondition::Size(size) => data.len() as u64 == u64::from(size),
+            DeleteCondition::Crc32c(checksum) => crc32c::crc32c(data) == checksum,
+        }
+    }
+}
+
+/// `Storage::put`の結果.
+///
+/// 対象lumpの書き込み先や、その際に発生した処理に関する情報を保持する.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PutReport {
+    is_new: bool,
+    embedded: bool,
+    allocated_blocks: u16,
+    deduplicated: bool,
+    journal_synced: bool,
+    unchanged: bool,
+    in_place: bool,
+}
+impl PutReport {
+    /// 新規追加の場合には`true`が、上書きの場合には`false`が返される.
+    ///
+    /// 以前の`put`の返り値(`bool`)に相当する.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// lumpのデータがジャーナル領域に埋め込まれた場合には`true`が、データ領域に書き込まれた場合には`false`が返される.
+    pub fn is_embedded(&self) -> bool {
+        self.embedded
+    }
+
+    /// データ領域内に割り当てられたブロック数を返す.
+    ///
+    /// ジャーナル領域に埋め込まれた場合や、重複排除によって既存のデータを共有した場合には`0`となる.
+    pub fn allocated_blocks(&self) -> u16 {
+        self.allocated_blocks
+    }
+
+    /// 重複排除によって、内容が同一の既存のlumpとデータを共有した場合には`true`が返される.
+    ///
+    /// 詳細は`StorageBuilder::deduplication`を参照のこと.
+    pub fn is_deduplicated(&self) -> bool {
+        self.deduplicated
+    }
+
+    /// `put`の処理中にジャーナルの同期(i.e., ディスクへの書き出し)が行われたかどうかを返す.
+    pub fn journal_synced(&self) -> bool {
+        self.journal_synced
+    }
+
+    /// 既存のlumpと同一の内容での上書きであったために、書き込みが省略された場合には`true`が返される.
+    ///
+    /// 詳細は`StorageBuilder::skip_identical_overwrites`を参照のこと.
+    pub fn is_unchanged(&self) -> bool {
+        self.unchanged
+    }
+
+    /// 既存のlumpのデータ部分領域への上書き更新が行われた場合には`true`が返される.
+    ///
+    /// 詳細は`StorageBuilder::overwrite_in_place`を参照のこと.
+    pub fn is_in_place(&self) -> bool {
+        self.in_place
+    }
+
+    pub(crate) fn with_journal_synced(mut self) -> Self {
+        self.journal_synced = true;
+        self
+    }
+}
+
+/// 複数回に分けて実行中の範囲削除の状態.
+///
+/// `Storage::start_sliced_delete_range`によって生成され、`Storage::sliced_delete_range_step`によって処理が進められる.
+///
+/// 完了前に破棄された場合には、範囲内のlumpは削除済みとして扱われたまま、
+/// その部分領域は(次回のオープンまで)解放されずに残る.
+#[derive(Debug)]
+pub struct SlicedDeleteRange {
+    range: Range<LumpId>,
+    deleted: Vec<LumpId>,
+    max_deleted: bool,
+}
+
+/// 複数回に分けて実行中のジャーナルの全体GCの状態.
+///
+/// `Storage::start_sliced_journal_gc`によって生成され、`Storage::sliced_journal_gc_step`によって処理が進められる.
+#[derive(Debug)]
+pub struct SlicedJournalGc {
+    gc: FullGc,
+}
+
+/// 複数回に分けて実行中のPUTの状態.
+///
+/// `Storage::start_sliced_put`によって生成され、`Storage::sliced_put_step`によって処理が進められる.
+///
+/// 完了前に破棄された場合には、割り当てられていた部分領域は(次回のオープンまで)解放されずに残る.
+#[derive(Debug)]
+pub struct SlicedPut {
+    lump_id: LumpId,
+    write: SlicedWrite,
+    deadline: Deadline,
+    syncs: u64,
+}
+impl SlicedPut {
+    /// 保存対象のlumpのIDを返す.
+    pub fn lump_id(&self) -> &LumpId {
+        &self.lump_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometrics::metrics::MetricBuilder;
+    use prometrics::Gatherer;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::mem;
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    use super::journal::{JournalHeader, EPOCH_MINOR_VERSION, EXTENSION_RECORDS_MINOR_VERSION};
+    use super::*;
+    use crate::block::BlockSize;
+    use crate::lump::{LumpData, LumpId, LumpRange};
+    use crate::nvm::{FileNvm, FileNvmBuilder, MemoryNvm, SharedMemoryNvm};
+    use crate::ErrorKind;
+
+    #[test]
+    fn it_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        // create
+        let nvm = track!(FileNvm::create(
+            dir.path().join("test.lusf"),
+            BlockSize::min().ceil_align(1024 * 1024)
+        ))?;
+        let mut storage = track!(Storage::create(nvm))?;
+
+        assert!(storage.get(&id("000"))?.is_none());
+        assert!(storage.put(&id("000"), &data("hello"))?.is_new());
+        assert!(!storage.put(&id("000"), &data("hello"))?.is_new());
+        assert_eq!(storage.get(&id("000"))?, Some(data("hello")));
+        assert_eq!(
+            storage.head(&id("000")).map(|h| h.approximate_data_size),
+            Some(5)
+        );
+        assert!(storage.delete(&id("000"))?);
+        assert!(!storage.delete(&id("000"))?);
+        assert!(storage.get(&id("000"))?.is_none());
+        assert!(storage.head(&id("000")).is_none());
+
+        assert!(storage.put(&id("000"), &data("hello"))?.is_new());
+        assert!(storage.put(&id("111"), &data("world"))?.is_new());
+        for _ in 0..10 {
+            track!(storage.run_side_job_once())?;
+            assert!(storage.put(&id("222"), &data("quux"))?.is_new());
+            assert!(storage.delete(&id("222"))?);
+        }
+        mem::drop(storage);
+
+        // open
+        let nvm = track!(FileNvm::open(dir.path().join("test.lusf")))?;
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![id("000"), id("111")]);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_async_write_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let nvm = track!(FileNvmBuilder::new()
+            .direct_io(false)
+            .async_write(true)
+            .create(&path, BlockSize::min().ceil_align(1024 * 1024)))?;
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.5)
+            .journal_max_write_buffer_size(1024)
+            .journal_sync_interval(100)
+            .create(nvm))?;
+        for i in 0..300 {
+            let id = LumpId::new(i);
+            let data = track!(LumpData::new_embedded(vec![i as u8; 100]))?;
+            track!(storage.put(&id, &data))?;
+            if i % 3 == 0 {
+                assert!(track!(storage.delete(&id))?);
+            }
+            if i % 50 == 0 {
+                track!(storage.run_side_job_once())?;
+            }
+        }
+        for i in 0..300 {
+            let data = track!(storage.get(&LumpId::new(i)))?;
+            if i % 3 == 0 {
+                assert!(data.is_none());
+            } else {
+                assert_eq!(data.map(|d| d.into_bytes()), Some(vec![i as u8; 100]));
+            }
+        }
+        mem::drop(storage);
+
+        let nvm = track!(FileNvm::open(&path))?;
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list().len(), 200);
+        for i in (1..300).filter(|i| i % 3 != 0) {
+            let data = track!(storage.get(&LumpId::new(i)))?;
+            assert_eq!(data.map(|d| d.into_bytes()), Some(vec![i as u8; 100]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn full() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        let nvm = track!(FileNvm::create(
+            dir.path().join("test.lusf"),
+            BlockSize::min().ceil_align(1024 * 1024)
+        ))?;
+        let mut storage = track!(Storage::create(nvm))?;
+
+        assert!(track!(storage.put(&id("000"), &zeroed_data(512 * 1024)))?.is_new(),);
+        assert_eq!(
+            storage
+                .put(&id("000"), &zeroed_data(512 * 1024))
+                .ok()
+                .map(|r| r.is_new()),
+            Some(false)
+        );
+        assert_eq!(
+            storage
+                .put(&id("111"), &zeroed_data(512 * 1024))
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::StorageFull)
+        );
+
+        assert_eq!(storage.delete(&id("000")).ok(), Some(true));
+        assert_eq!(
+            storage
+                .put(&id("111"), &zeroed_data(512 * 1024))
+                .ok()
+                .map(|r| r.is_new()),
+            Some(true)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn max_size_lump() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        let nvm = track!(FileNvm::create(
+            dir.path().join("test.lusf"),
+            BlockSize::min().ceil_align(100 * 1024 * 1024)
+        ))?;
+        let mut storage = track!(Storage::create(nvm))?;
+
+        let data = zeroed_data(LumpData::MAX_SIZE);
+        assert!(track!(storage.put(&id("000"), &data))?.is_new());
+        assert_eq!(track!(storage.get(&id("000")))?, Some(data));
+        Ok(())
+    }
+
+    #[test]
+    fn put_report_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().journal_sync_interval(1).create(nvm))?;
+
+        // ジャーナル領域への埋め込み
+        let report = track!(storage.put(&id("000"), &data("hello")))?;
+        assert!(report.is_new());
+        assert!(report.is_embedded());
+        assert_eq!(report.allocated_blocks(), 0);
+        assert!(!report.journal_synced());
+
+        // データ領域への書き込み
+        let report = track!(storage.put(&id("000"), &zeroed_data(1024)))?;
+        assert!(!report.is_new());
+        assert!(!report.is_embedded());
+        assert_eq!(report.allocated_blocks(), 3); // ヘッダ分を含む
+        assert!(report.journal_synced());
+        Ok(())
+    }
+
+    fn id(id: &str) -> LumpId {
+        id.parse().unwrap()
+    }
+
+    fn data(data: &str) -> LumpData {
+        LumpData::new_embedded(Vec::from(data)).unwrap()
+    }
+
+    fn zeroed_data(size: usize) -> LumpData {
+        let mut data = LumpData::aligned_allocate(size, BlockSize::min()).unwrap();
+        for v in data.as_bytes_mut() {
+            *v = 0;
+        }
+        data
+    }
+
+    #[test]
+    fn open_older_compatible_version_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        // create
+        let mut header = {
+            let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+            let storage = track!(Storage::create(nvm))?;
+            let header = storage.header().clone();
+            assert_eq!(header.major_version, MAJOR_VERSION);
+            assert_eq!(header.minor_version, MINOR_VERSION);
+            assert_eq!(header.writer_version, Some(crate::CrateVersion::CURRENT));
+            header
+        };
+
+        // マイナーバージョンを減らして、ヘッダを上書きする
+        {
+            header.minor_version = header
+                .minor_version
+                .checked_sub(1)
+                .expect("このテストは`MINOR_VERSION >= 1`であることを前提としている");
+            header.writer_version = None;
+            let file = track_any_err!(OpenOptions::new().write(true).open(&path))?;
+            track!(header.write_to(file))?;
+        }
+
+        // open: 更新を無効にした場合には、古いバージョンのまま扱われる
+        {
+            let nvm = track!(FileNvm::open(&path))?;
+            let storage = track!(StorageBuilder::new().upgrade_minor_version(false).open(nvm))?;
+            let header = storage.header().clone();
+            assert_eq!(header.minor_version, MINOR_VERSION - 1);
+            assert_eq!(header.original_minor_version, MINOR_VERSION - 1);
+        }
+        {
+            let file = track_any_err!(OpenOptions::new().read(true).open(&path))?;
+            let header = track!(StorageHeader::read_from(file))?;
+            assert_eq!(header.minor_version, MINOR_VERSION - 1);
+        }
+
+        // open: マイナーバージョンが最新のものに調整されている
+        {
+            let nvm = track!(FileNvm::open(&path))?;
+            let storage = track!(Storage::open(nvm))?;
+            let header = storage.header().clone();
+            assert_eq!(header.major_version, MAJOR_VERSION);
+            assert_eq!(header.minor_version, MINOR_VERSION);
+            assert_eq!(header.original_minor_version, MINOR_VERSION - 1);
+        }
+
+        // ファイル上のヘッダも更新されている
+        {
+            let file = track_any_err!(OpenOptions::new().read(true).open(&path))?;
+            let header = track!(StorageHeader::read_from(file))?;
+            assert_eq!(header.major_version, MAJOR_VERSION);
+            assert_eq!(header.minor_version, MINOR_VERSION);
+            assert_eq!(header.writer_version, Some(crate::CrateVersion::CURRENT));
+        }
+        Ok(())
+    }
+
+    /// マイナーバージョンが`minor_version`の(エポック導入以前の形式の)ストレージを模したものを作成する.
+    fn create_legacy_storage(minor_version: u16) -> Result<SharedMemoryNvm> {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_checksum(JournalChecksum::Adler32)
+            .journal_header_slots(1)
+            .create(nvm.clone()))?;
+        for i in 0..10 {
+            track!(storage.put(&id(&i.to_string()), &data(&i.to_string())))?;
+        }
+        track!(storage.journal_sync())?;
+        let mut header = storage.header().clone();
+        mem::drop(storage);
+
+        // エポック導入以前のジャーナルヘッダに書き換える
+        // (エポック`0`で書き込まれたレコードは、エポック導入以前のものと互換性がある)
+        let mut journal_header = track!(read_journal_header(&nvm))?;
+        journal_header.epoch = None;
+
+        let journal_offset = header.region_size() as usize;
+        let mut bytes = nvm.to_bytes();
+        header.minor_version = minor_version;
+        track!(header.write_to(&mut bytes[..]))?;
+        track!(journal_header.write_to(&mut bytes[journal_offset..], header.block_size))?;
+
+        let size = journal_offset + JournalHeader::region_size(header.block_size);
+        let mut writer = nvm.clone();
+        track_io!(writer.seek(SeekFrom::Start(0)))?;
+        track_io!(writer.write_all(&bytes[..size]))?;
+        Ok(nvm)
+    }
+
+    fn read_journal_header(nvm: &SharedMemoryNvm) -> Result<JournalHeader> {
+        let bytes = nvm.to_bytes();
+        let header = track!(StorageHeader::read_from(&bytes[..]))?;
+        let offset = header.region_size() as usize;
+        track!(JournalHeader::read_from(
+            &bytes[offset..],
+            header.block_size
+        ))
+    }
+
+    #[test]
+    fn legacy_journal_is_not_migrated_to_epochs() -> TestResult {
+        let nvm = track!(create_legacy_storage(EPOCH_MINOR_VERSION - 1))?;
+        assert_eq!(track!(read_journal_header(&nvm))?.epoch, None);
+
+        // バージョンを更新しない場合には、ヘッダが書き直されてもエポックは導入されない
+        let mut storage = track!(StorageBuilder::new()
+            .upgrade_minor_version(false)
+            .open(nvm.clone()))?;
+        track!(storage.delete(&id("0")))?;
+        track!(storage.journal_gc())?;
+        mem::drop(storage);
+        let header = track!(read_journal_header(&nvm))?;
+        assert_ne!(header.ring_buffer_head, 0);
+        assert_eq!(header.epoch, None);
+
+        // バージョンが更新された場合には、エポックを用いる形式に移行する
+        let mut storage = track!(Storage::open(nvm.clone()))?;
+        track!(storage.journal_gc())?;
+        assert_eq!(storage.list().len(), 9);
+        mem::drop(storage);
+        assert_eq!(track!(read_journal_header(&nvm))?.epoch, Some(0));
+
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list().len(), 9);
+        Ok(())
+    }
+
+    #[test]
+    fn opening_without_upgrade_keeps_older_format() -> TestResult {
+        let nvm = track!(create_legacy_storage(EXTENSION_RECORDS_MINOR_VERSION - 1))?;
+        let header = track!(StorageHeader::read_from(&nvm.to_bytes()[..]))?;
+        let ring_buffer_offset =
+            (header.region_size() + JournalHeader::region_size(header.block_size) as u64) as usize;
+        let mut builder = StorageBuilder::new();
+        builder
+            .upgrade_minor_version(false)
+            .audit_trail(true)
+            .deduplication(true);
+
+        // 参照のみの場合には、一切書き込みは行われない
+        let before = nvm.to_bytes();
+        let mut storage = track!(builder.open(nvm.clone()))?;
+        assert_eq!(storage.list().len(), 10);
+        assert_eq!(track!(storage.get(&id("3")))?, Some(data("3")));
+        mem::drop(storage);
+        assert!(nvm.to_bytes() == before);
+
+        // 更新操作を行っても、古いバージョンの形式のデータのみが書き込まれる
+        let tail = track!(builder.open(nvm.clone()))?.journal_snapshot()?.tail as usize;
+        let mut storage = track!(builder.open(nvm.clone()))?;
+        track!(storage.put_with_audit(&id("100"), &zeroed_data(1024), &[0; 16]))?;
+        track!(storage.put(&id("101"), &zeroed_data(1024)))?;
+        track!(storage.delete(&id("0")))?;
+        assert_eq!(
+            storage
+                .rename(&id("100"), &id("102"))
+                .map_err(|e| *e.kind()),
+            Err(ErrorKind::InvalidInput)
+        );
+        assert_eq!(
+            storage.link(&id("100"), &id("102")).map_err(|e| *e.kind()),
+            Err(ErrorKind::InvalidInput)
+        );
+        track!(storage.close())?;
+
+        let after = nvm.to_bytes();
+        assert!(after[..header.region_size() as usize] == before[..header.region_size() as usize]);
+        assert!(
+            after[ring_buffer_offset..][..tail] == before[ring_buffer_offset..][..tail],
+            "Existing journal records must not be rewritten"
+        );
+        assert_eq!(track!(read_journal_header(&nvm))?.epoch, None);
+
+        let mut storage = track!(builder.open(nvm))?;
+        assert_eq!(
+            storage.header().minor_version,
+            EXTENSION_RECORDS_MINOR_VERSION - 1
+        );
+        assert_eq!(storage.list().len(), 11);
+        let snapshot = track!(storage.journal_snapshot())?;
+        assert!(!snapshot
+            .entries
+            .iter()
+            .any(|e| matches!(e.record, JournalRecord::Extension(..))));
+        Ok(())
+    }
+
+    #[test]
+    fn creation_params_are_recorded() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+        let expected = Some(StorageCreationParams {
+            journal_region_ratio: 0.5,
+            journal_gc_queue_size: 64,
+            journal_sync_interval: 8,
+        });
+
+        // create: 作成時のパラメータがヘッダに記録される
+        {
+            let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+            let storage = track!(StorageBuilder::new()
+                .journal_region_ratio(0.5)
+                .journal_gc_queue_size(64)
+                .journal_sync_interval(8)
+                .create(nvm))?;
+            assert_eq!(storage.header().creation_params, expected);
+        }
+
+        // open: オープン時のパラメータには影響されない
+        {
+            let nvm = track!(FileNvm::open(&path))?;
+            let storage = track!(StorageBuilder::new().journal_sync_interval(1).open(nvm))?;
+            assert_eq!(storage.header().creation_params, expected);
+            assert_eq!(storage.config().journal_sync_interval, 1);
+        }
+        let header = track!(StorageHeader::read_from_file(&path))?;
+        assert_eq!(header.creation_params, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn logical_name_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        // 長すぎる名前は指定できない
+        let nvm = track!(FileNvm::create(
+            dir.path().join("too_long.lusf"),
+            1024 * 1024
+        ))?;
+        let e = StorageBuilder::new()
+            .logical_name(&"a".repeat(MAX_LOGICAL_NAME_LEN + 1))
+            .create(nvm)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+        let storage = track!(StorageBuilder::new()
+            .logical_name("cluster0/slot1")
+            .create(nvm))?;
+        assert_eq!(
+            storage.header().logical_name.as_deref(),
+            Some("cluster0/slot1")
+        );
+        mem::drop(storage);
+
+        // 名前が一致する
+        let nvm = track!(FileNvm::open(&path))?;
+        mem::drop(track!(StorageBuilder::new()
+            .logical_name("cluster0/slot1")
+            .open(nvm))?);
+
+        // 名前を指定しない場合には、チェックは行われない
+        let nvm = track!(FileNvm::open(&path))?;
+        mem::drop(track!(Storage::open(nvm))?);
+
+        // 名前が異なる
+        let nvm = track!(FileNvm::open(&path))?;
+        let e = StorageBuilder::new()
+            .logical_name("cluster1/slot1")
+            .open(nvm)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        // 名前が記録されていない
+        let path = dir.path().join("unnamed.lusf");
+        let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+        mem::drop(track!(Storage::create(nvm))?);
+        let nvm = track!(FileNvm::open(&path))?;
+        let e = StorageBuilder::new()
+            .logical_name("cluster0/slot1")
+            .open(nvm)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_lumps_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let mut bytes = vec![0; 64 * 1024];
+        bytes[10] = 1;
+        bytes[40 * 1024..41 * 1024].copy_from_slice(&[2; 1024][..]);
+        let data = track!(LumpData::new(bytes.clone()))?;
+
+        let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+        let mut storage = track!(StorageBuilder::new().sparse_lumps(true).create(nvm))?;
+        assert!(storage.header().sparse_lumps);
+        assert!(storage.config().sparse_lumps);
+        track!(storage.put(&id("0"), &data))?;
+        assert_eq!(storage.metrics().data_region().sparse_lumps(), 1);
+        assert!(storage.metrics().data_region().written_bytes() < 8 * 1024);
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.into_bytes()),
+            Some(bytes.clone())
+        );
+        mem::drop(storage);
+
+        // オープン時には、ヘッダの値が使用される
+        let nvm = track!(FileNvm::open(&path))?;
+        let mut storage = track!(Storage::open(nvm))?;
+        assert!(storage.config().sparse_lumps);
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.into_bytes()),
+            Some(bytes.clone())
+        );
+        track!(storage.put(&id("1"), &data))?;
+        assert_eq!(storage.metrics().data_region().sparse_lumps(), 1);
+        mem::drop(storage);
+
+        // デフォルトでは無効
+        let nvm = track!(FileNvm::create(dir.path().join("dense.lusf"), 1024 * 1024))?;
+        let mut storage = track!(Storage::create(nvm))?;
+        assert!(!storage.header().sparse_lumps);
+        track!(storage.put(&id("0"), &data))?;
+        assert_eq!(storage.metrics().data_region().sparse_lumps(), 0);
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.into_bytes()),
+            Some(bytes)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overwrite_in_place_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+        let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+        let mut storage = track!(StorageBuilder::new().overwrite_in_place(true).create(nvm))?;
+        assert!(storage.config().overwrite_in_place);
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[1; 1000]))?;
+        assert!(!track!(storage.put(&id("0"), &data))?.is_in_place());
+        let portion = storage.lump_index.get(&id("0"));
+        let usage = storage.data_region.metrics().allocator().usage_bytes();
+
+        // 必要なブロック数が同じなら、既存の部分領域に上書きされる
+        let data = track!(LumpData::new(vec![2; 900]))?;
+        let report = track!(storage.put(&id("0"), &data))?;
+        assert!(!report.is_new());
+        assert!(report.is_in_place());
+        assert_eq!(report.allocated_blocks(), 0);
+        assert_eq!(storage.lump_index.get(&id("0")), portion);
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            usage
+        );
+        assert_eq!(storage.metrics().in_place_overwrites(), 1);
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.into_bytes()),
+            Some(vec![2; 900])
+        );
+        let records = track!(storage.journal_snapshot())?
+            .entries
+            .iter()
+            .filter_map(|e| InPlacePutRecord::from_journal_record(&e.record))
+            .count();
+        assert_eq!(records, 1);
+
+        // ブロック数が異なる場合には、通常通りに新しい部分領域が割り当てられる
+        let data = track!(LumpData::new(vec![3; 2000]))?;
+        let report = track!(storage.put(&id("0"), &data))?;
+        assert!(!report.is_in_place());
+        assert_ne!(report.allocated_blocks(), 0);
+
+        // 別名によって共有されている部分領域には上書きされない
+        assert!(track!(storage.link(&id("0"), &id("1")))?);
+        let data = track!(LumpData::new(vec![4; 2000]))?;
+        assert!(!track!(storage.put(&id("0"), &data))?.is_in_place());
+        assert_eq!(
+            track!(storage.get(&id("1")))?.map(|d| d.into_bytes()),
+            Some(vec![3; 2000])
+        );
+
+        // 上書き更新の印はGCで回収されるが、データは保持される
+        let data = track!(LumpData::new(vec![5; 2000]))?;
+        assert!(track!(storage.put(&id("0"), &data))?.is_in_place());
+        track!(storage.journal_gc())?;
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        mem::drop(storage);
+
+        let nvm = track!(FileNvm::open(&path))?;
+        let mut storage = track!(Storage::open(nvm))?;
+        assert!(!storage.config().overwrite_in_place);
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.into_bytes()),
+            Some(vec![5; 2000])
+        );
+        assert_eq!(
+            track!(storage.get(&id("1")))?.map(|d| d.into_bytes()),
+            Some(vec![3; 2000])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reembed_small_lumps_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+        let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+        let mut storage = track!(StorageBuilder::new()
+            .reembed_threshold(1024)
+            .reembed_batch_size(2)
+            .create(nvm))?;
+        assert_eq!(storage.config().reembed_threshold, 1024);
+
+        // 大きなデータで作成された後に、小さなデータで上書きされたlump
+        track!(storage.put(&id("0"), &track!(LumpData::new(vec![0; 10000]))?))?;
+        track!(storage.put(&id("0"), &track!(LumpData::new(vec![1; 100]))?))?;
+        track!(storage.put(&id("1"), &track!(LumpData::new(vec![2; 5000]))?))?;
+        track!(storage.put(&id("2"), &track!(LumpData::new(vec![3; 200]))?))?;
+        assert!(track!(storage.link(&id("2"), &id("3")))?);
+        let usage = storage.data_region.metrics().allocator().usage_bytes();
+
+        // 一回目: "0"と"1"が走査され、閾値以下の"0"のみが移し替えられる
+        track!(storage.run_side_job_once())?;
+        assert_eq!(storage.metrics().reembedded_lumps(), 1);
+        assert!(matches!(
+            storage.lump_index.get(&id("0")),
+            Some(Portion::Journal(_))
+        ));
+        assert!(storage.data_region.metrics().allocator().usage_bytes() < usage);
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.into_bytes()),
+            Some(vec![1; 100])
+        );
+
+        // 二回目: "2"と"3"が走査されるが、部分領域が共有されているので移し替えられない
+        track!(storage.run_side_job_once())?;
+        assert_eq!(storage.metrics().reembedded_lumps(), 1);
+        assert!(matches!(
+            storage.lump_index.get(&id("2")),
+            Some(Portion::Data(_))
+        ));
+
+        // 共有が解消されれば、先頭からの再走査時に移し替えられる
+        assert!(track!(storage.delete(&id("3")))?);
+        for _ in 0..3 {
+            track!(storage.run_side_job_once())?;
+        }
+        assert_eq!(storage.metrics().reembedded_lumps(), 2);
+        assert!(matches!(
+            storage.lump_index.get(&id("1")),
+            Some(Portion::Data(_))
+        ));
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        mem::drop(storage);
+
+        let nvm = track!(FileNvm::open(&path))?;
+        let mut storage = track!(Storage::open(nvm))?;
+        assert!(matches!(
+            storage.lump_index.get(&id("2")),
+            Some(Portion::Journal(_))
+        ));
+        assert_eq!(
+            track!(storage.get(&id("2")))?.map(|d| d.into_bytes()),
+            Some(vec![3; 200])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn backup_header_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let header = {
+            let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+            let mut storage = track!(Storage::create(nvm))?;
+            track!(storage.put(&id("0"), &zeroed_data(42)))?;
+            storage.header().clone()
+        };
+        assert!(header.backup_header);
+        assert!(header.storage_size() <= 1024 * 1024);
+
+        // 先頭のヘッダを壊す
+        {
+            let mut file = track_any_err!(OpenOptions::new().write(true).open(&path))?;
+            track_io!(file.write_all(b"broken"))?;
+        }
+        assert!(StorageHeader::read_from_file(&path).is_err());
+        let backup = track!(StorageHeader::read_backup_from_file_at(&path, 0, None))?;
+        assert_eq!(backup.map(|h| h.instance_uuid), Some(header.instance_uuid));
+
+        // バックアップを使ってオープンできる
+        {
+            let nvm = track!(FileNvm::open(&path))?;
+            let storage = track!(Storage::open(nvm))?;
+            assert_eq!(storage.header().instance_uuid, header.instance_uuid);
+            assert_eq!(storage.list(), vec![id("0")]);
+        }
+
+        // 先頭のヘッダを修復する
+        let mut nvm = track!(FileNvm::open(&path))?;
+        let repaired = track!(Storage::repair_header(&mut nvm))?;
+        assert_eq!(repaired.instance_uuid, header.instance_uuid);
+        let primary = track!(StorageHeader::read_from_file(&path))?;
+        assert_eq!(primary.instance_uuid, header.instance_uuid);
+
+        // バックアップを持たないストレージは修復できない
+        let mut nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        assert_eq!(
+            Storage::repair_header(&mut nvm).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn journal_checksum_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        for &checksum in &[JournalChecksum::Adler32, JournalChecksum::Crc32c] {
+            let path = dir.path().join(format!("{:?}.lusf", checksum));
+            {
+                let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
+                let mut storage =
+                    track!(StorageBuilder::new().journal_checksum(checksum).create(nvm))?;
+                assert_eq!(storage.header().journal_checksum, checksum);
+                track!(storage.put(
+                    &id("000"),
+                    &track!(storage.allocate_lump_data_with_bytes(b"foo"))?
+                ))?;
+                track!(storage.delete(&id("111")))?;
+            }
+
+            // 再オープン時には、ヘッダに記録されているアルゴリズムが使われる
+            let nvm = track!(FileNvm::open(&path))?;
+            let mut storage = track!(StorageBuilder::new()
+                .journal_checksum(JournalChecksum::Adler32)
+                .open(nvm))?;
+            assert_eq!(storage.header().journal_checksum, checksum);
+            assert_eq!(
+                track!(storage.get(&id("000")))?.map(|d| d.as_bytes().to_owned()),
+                Some(b"foo".to_vec())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn block_size_check_when_create() -> TestResult {
+        // [OK] ストレージとNVMのブロックサイズが等しい
+        let nvm_block_size = track!(BlockSize::new(1024))?;
+        let storage_block_size = track!(BlockSize::new(1024))?;
+
+        let storage = track!(StorageBuilder::new()
+            .block_size(storage_block_size)
+            .create(memory_nvm(nvm_block_size)))?;
+        assert_eq!(storage.header().block_size, storage_block_size);
+
+        // [OK] ストレージがNVMのブロックサイズを包含する
+        let nvm_block_size = track!(BlockSize::new(512))?;
+        let storage_block_size = track!(BlockSize::new(1024))?;
+
+        let storage = track!(StorageBuilder::new()
+            .block_size(storage_block_size)
+            .create(memory_nvm(nvm_block_size)))?;
+        assert_eq!(storage.header().block_size, storage_block_size);
+
+        // [NG] NVMのブロックサイズが、ストレージのブロックサイズよりも大きい
+        let nvm_block_size = track!(BlockSize::new(1024))?;
+        let storage_block_size = track!(BlockSize::new(512))?;
+
+        assert_eq!(
+            StorageBuilder::new()
+                .block_size(storage_block_size)
+                .create(memory_nvm(nvm_block_size))
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::BlockSizeMismatch)
+        );
+
+        // [NG] ストレージのブロック境界が、NVMのブロック境界に揃っていない
+        let nvm_block_size = track!(BlockSize::new(1024))?;
+        let storage_block_size = track!(BlockSize::new(1536))?;
+
+        assert_eq!(
+            StorageBuilder::new()
+                .block_size(storage_block_size)
+                .create(memory_nvm(nvm_block_size))
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::BlockSizeMismatch)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_size_check_when_open() -> TestResult {
+        // 事前準備: ストレージとNVMのブロックサイズを等しくして、ストレージの初期化(生成)を実施
+        let initial_nvm_block_size = track!(BlockSize::new(1536))?;
+        let storage_block_size = track!(BlockSize::new(1536))?;
+        let mut nvm = memory_nvm(initial_nvm_block_size);
+        assert!(StorageBuilder::new()
+            .block_size(storage_block_size)
+            .create(nvm.clone())
+            .is_ok());
+
+        // [OK]: NVMとストレージのブロックサイズが等しい
+        let storage = track!(Storage::open(nvm.clone()))?;
+        assert_eq!(storage.header().block_size, storage_block_size);
+
+        // [OK] ストレージのブロック境界がNVMのブロック境界に揃っている
+        nvm.set_block_size(track!(BlockSize::new(512))?);
+        let storage = track!(Storage::open(nvm.clone()))?;
+        assert_eq!(storage.header().block_size, storage_block_size);
+
+        // [NG] NVMのブロックサイズが、ストレージのブロックサイズよりも大きい
+        nvm.set_block_size(track!(BlockSize::new(2048))?);
+        assert_eq!(
+            Storage::open(nvm.clone()).err().map(|e| *e.kind()),
+            Some(ErrorKind::BlockSizeMismatch)
+        );
+
+        // [NG] ストレージのブロック境界が、NVMのブロック境界に揃っていない
+        nvm.set_block_size(track!(BlockSize::new(1024))?);
+        assert_eq!(
+            Storage::open(nvm).err().map(|e| *e.kind()),
+            Some(ErrorKind::BlockSizeMismatch)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn capacity_check_when_create() {
+        // ストレージのヘッダすら格納できない
+        let nvm = MemoryNvm::new(vec![0; 256]);
+        assert_eq!(
+            Storage::create(nvm).err().map(|e| *e.kind()),
+            Some(ErrorKind::CapacityTooSmall)
+        );
+
+        // ジャーナル領域を初期化できない
+        let nvm = MemoryNvm::new(vec![0; 1024]);
+        assert_eq!(
+            Storage::create(nvm).err().map(|e| *e.kind()),
+            Some(ErrorKind::CapacityTooSmall)
+        );
+    }
+
+    #[test]
+    fn journal_header_slots_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        assert_eq!(storage.header().journal_header_slots, 2);
+
+        // 再オープンを繰り返しても、ジャーナルの内容は保持される
+        for i in 0..5 {
+            track!(storage.put(&id(&i.to_string()), &zeroed_data(42)))?;
+            track!(storage.journal_sync())?;
+            std::mem::drop(storage);
+            storage = track!(Storage::open(nvm.clone()))?;
+            assert_eq!(storage.header().journal_header_slots, 2);
+            assert_eq!(storage.list().len(), i + 1);
+        }
+
+        // 単一スロットも選択可能
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(StorageBuilder::new()
+            .journal_header_slots(1)
+            .create(nvm.clone()))?;
+        assert_eq!(storage.header().journal_header_slots, 1);
+        std::mem::drop(storage);
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.header().journal_header_slots, 1);
+
+        // 不正なスロット数
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        assert_eq!(
+            StorageBuilder::new()
+                .journal_header_slots(3)
+                .create(nvm)
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    fn memory_nvm(block_size: BlockSize) -> SharedMemoryNvm {
+        SharedMemoryNvm::with_block_size(vec![0; 1024 * 1024], block_size)
+    }
+
+    fn is_put_with(entry: &JournalEntry, id: &LumpId) -> bool {
+        if let JournalRecord::Put(id_, _) = entry.record {
+            id_ == *id
+        } else {
+            false
+        }
+    }
+
+    fn is_delete_with(entry: &JournalEntry, id: &LumpId) -> bool {
+        if let JournalRecord::Delete(id_) = entry.record {
+            id_ == *id
+        } else {
+            false
+        }
+    }
+
+    #[test]
+    fn full_gc_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        let nvm = track!(FileNvm::create(
+            dir.path().join("test.lusf"),
+            BlockSize::min().ceil_align(1024 * 1024)
+        ))?;
+        let mut storage = track!(Storage::create(nvm))?;
+
+        // ストレージへの操作で、小規模GCが自動で発生しないようにする
+        storage.set_automatic_gc_mode(false);
+
+        assert!(storage.put(&id("000"), &zeroed_data(42))?.is_new());
+        assert!(storage.put(&id("010"), &zeroed_data(42))?.is_new());
+
+        let entries = storage.journal_snapshot().unwrap().entries;
+
+        assert_eq!(entries.len(), 2);
+        assert!(is_put_with(entries.first().unwrap(), &id("000")));
+        assert!(is_put_with(entries.get(1).unwrap(), &id("010")));
+
+        storage.journal_gc().unwrap();
+
+        let new_entries = storage.journal_snapshot().unwrap().entries;
+
+        for (e1, e2) in entries.iter().zip(new_entries.iter()) {
+            assert_eq!(e1.record, e2.record);
+            // 注意
+            // GCによりジャーナル領域内でのエントリ移動が生じているため、次は成立しない
+            // assert_eq!(e1.start, e2.start);
+        }
+
+        assert!(storage.delete(&id("000"))?);
+        assert!(storage.delete(&id("010"))?);
+
+        let entries = storage.journal_snapshot().unwrap().entries;
+
+        assert_eq!(entries.len(), 4);
+
+        assert!(is_put_with(entries.first().unwrap(), &id("000")));
+        assert!(is_put_with(entries.get(1).unwrap(), &id("010")));
+        assert!(is_delete_with(entries.get(2).unwrap(), &id("000")));
+        assert!(is_delete_with(entries.get(3).unwrap(), &id("010")));
+
+        storage.journal_gc().unwrap();
+
+        let entries = storage.journal_snapshot().unwrap().entries;
+
+        assert_eq!(entries.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_journal_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.01)
+            .create(nvm.clone()))?;
+        storage.set_automatic_gc_mode(false);
+
+        // 周回を跨ぐまで、追記と全体GCを繰り返す
+        for i in 0..200 {
+            track!(storage.put(&id("000"), &data(&i.to_string())))?;
+            track!(storage.put(&id("001"), &zeroed_data(10)))?;
+            track!(storage.delete(&id("001")))?;
+            track!(storage.journal_gc())?;
+        }
+        track!(storage.put(&id("002"), &zeroed_data(10)))?;
+        assert_ne!(track!(storage.journal_snapshot())?.head, 0);
+
+        track!(storage.rewrite_journal())?;
+        let snapshot = track!(storage.journal_snapshot())?;
+        assert_eq!(snapshot.unreleased_head, 0);
+        assert_eq!(snapshot.head, 0);
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(
+            snapshot.tail,
+            snapshot.entries.last().map_or(0, |e| e.end().as_u64())
+        );
+        assert_eq!(track!(storage.get(&id("000")))?, Some(data("199")));
+
+        // 既に書き直し済みの場合は何も変わらない
+        track!(storage.rewrite_journal())?;
+        assert_eq!(track!(storage.journal_snapshot())?.tail, snapshot.tail);
+
+        // 書き直し後のジャーナルから状態を復元できる
+        track!(storage.put(&id("003"), &data("bar")))?;
+        track!(storage.journal_sync())?;
+        mem::drop(storage);
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![id("000"), id("002"), id("003")]);
+        assert_eq!(track!(storage.get(&id("000")))?, Some(data("199")));
+        assert_eq!(track!(storage.get(&id("003")))?, Some(data("bar")));
+        Ok(())
+    }
+
+    #[test]
+    fn background_gc_scan_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let nvm = track!(FileNvm::create(
+            dir.path().join("test.lusf"),
+            BlockSize::min().ceil_align(1024 * 1024)
+        ))?;
+        let mut storage = track!(Storage::create(nvm))?;
+        storage.set_automatic_gc_mode(false);
+        assert!(track!(storage.enable_background_gc_scan())?);
+
+        for i in 0..100 {
+            track!(storage.put(&id("000"), &data(&i.to_string())))?;
+        }
+        let usage = storage.metrics().journal_region().queue().usage_bytes();
+
+        // 走査は別スレッドで行われるので、補助タスクの実行毎に走査の完了を待機して、結果を反映させる
+        for _ in 0..100 {
+            track!(storage.run_side_job_once())?;
+            track!(storage.wait_background_gc_scan())?;
+            if storage.metrics().journal_region().queue().usage_bytes() < usage / 2 {
+                break;
+            }
+        }
+        assert!(storage.metrics().journal_region().queue().usage_bytes() < usage / 2);
+
+        // 全体GCは、走査の完了を待機して実行される
+        track!(storage.put(&id("111"), &data("foo")))?;
+        track!(storage.delete(&id("111")))?;
+        track!(storage.journal_gc())?;
+        let entries = track!(storage.journal_snapshot())?.entries;
+        assert_eq!(entries.len(), 1);
+        mem::drop(storage);
+
+        // 読み込み用スレッドが保持していたファイルも閉じられているので、再度オープン可能
+        let nvm = track!(FileNvm::open(dir.path().join("test.lusf")))?;
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![id("000")]);
+        assert_eq!(track!(storage.get(&id("000")))?, Some(data("99")));
+        Ok(())
+    }
+
+    #[test]
+    fn background_gc_scan_does_not_overflow_journal() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let nvm = track!(FileNvm::create(
+            dir.path().join("test.lusf"),
+            BlockSize::min().ceil_align(1024 * 1024)
+        ))?;
+        let mut storage = track!(StorageBuilder::new().journal_region_ratio(0.01).create(nvm))?;
+        assert!(track!(storage.enable_background_gc_scan())?);
+
+        // 補助タスクを実行しなくても、追記に伴うGCだけでジャーナル領域が溢れることはない
+        for i in 0..10_000 {
+            track!(storage.put(&id("000"), &data(&i.to_string())))?;
+        }
+        assert_eq!(track!(storage.get(&id("000")))?, Some(data("9999")));
+        Ok(())
+    }
+
+    #[test]
+    fn journal_gc_config_metric_works() -> TestResult {
+        let mut gatherer = Gatherer::new();
+        let mut metrics = MetricBuilder::new();
+        metrics.registry(gatherer.registry());
+
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .metrics(metrics)
+            .journal_gc_batch_size(32)
+            .create(nvm))?;
+        let text = gatherer.gather().to_text();
+        assert!(text
+            .contains(r#"cannyls_journal_region_gc_config{batch_size="32",queue_size="4096"} 1"#));
+
+        // 設定変更後は、新しい値のみが公開される
+        storage.set_journal_gc_queue_size(128);
+        let text = gatherer.gather().to_text();
+        assert!(text
+            .contains(r#"cannyls_journal_region_gc_config{batch_size="32",queue_size="128"} 1"#));
+        assert!(!text.contains(r#"queue_size="4096""#));
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_step_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        for i in 0..10 {
+            let lump_id = LumpId::new(i);
+            assert!(track!(storage.put(&lump_id, &zeroed_data(42)))?.is_new());
+        }
+
+        let mut range = LumpId::new(2)..LumpId::new(9);
+        let deleted = track!(storage.delete_range_step(&mut range, 3))?;
+        assert_eq!(
+            deleted,
+            vec![LumpId::new(2), LumpId::new(3), LumpId::new(4)]
+        );
+        assert_eq!(range, LumpId::new(5)..LumpId::new(9));
+
+        let deleted = track!(storage.delete_range_step(&mut range, 3))?;
+        assert_eq!(
+            deleted,
+            vec![LumpId::new(5), LumpId::new(6), LumpId::new(7)]
+        );
+
+        let deleted = track!(storage.delete_range_step(&mut range, 3))?;
+        assert_eq!(deleted, vec![LumpId::new(8)]);
+        assert!(range.start >= range.end);
+
+        let deleted = track!(storage.delete_range_step(&mut range, 3))?;
+        assert!(deleted.is_empty());
+
+        assert_eq!(
+            storage.list(),
+            vec![LumpId::new(0), LumpId::new(1), LumpId::new(9)]
+        );
+
+        // 再オープン後も同じ状態が復元される
+        track!(storage.journal_sync())?;
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(
+            storage.list(),
+            vec![LumpId::new(0), LumpId::new(1), LumpId::new(9)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sliced_delete_range_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        for i in 0..10 {
+            let lump_id = LumpId::new(i);
+            assert!(track!(storage.put(&lump_id, &zeroed_data(42)))?.is_new());
+        }
+        let records = track!(storage.journal_snapshot())?.entries.len();
+
+        let mut delete = track!(storage.start_sliced_delete_range(LumpId::new(2)..LumpId::new(9)))?;
+
+        // 開始時点で、範囲内のlumpは全て参照不可能になる
+        assert_eq!(
+            storage.list(),
+            vec![LumpId::new(0), LumpId::new(1), LumpId::new(9)]
+        );
+        assert_eq!(track!(storage.get(&LumpId::new(2)))?, None);
+        assert!(storage.head(&LumpId::new(8)).is_none());
+
+        // 完了していない間は、次の範囲削除は開始できない
+        assert!(storage
+            .start_sliced_delete_range(LumpId::new(0)..LumpId::new(1))
+            .is_err());
+
+        assert!(storage.sliced_delete_range_step(&mut delete, 3).is_none());
+        assert_eq!(track!(storage.get(&LumpId::new(6)))?, None);
+        assert!(storage.sliced_delete_range_step(&mut delete, 3).is_none());
+        assert_eq!(
+            storage.sliced_delete_range_step(&mut delete, 3),
+            Some((2..9).map(LumpId::new).collect())
+        );
+
+        // 範囲削除レコードは一つだけ書き込まれる
+        assert_eq!(
+            track!(storage.journal_snapshot())?.entries.len(),
+            records + 1
+        );
+
+        // 再オープン後も同じ状態が復元される
+        track!(storage.journal_sync())?;
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(
+            storage.list(),
+            vec![LumpId::new(0), LumpId::new(1), LumpId::new(9)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sliced_journal_gc_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_gc_queue_size(4)
+            .create(nvm.clone()))?;
+        storage.set_automatic_gc_mode(false);
+        for i in 0..20 {
+            track!(storage.put(&LumpId::new(i), &zeroed_data(10)))?;
+        }
+        for i in 0..10 {
+            track!(storage.delete(&LumpId::new(i)))?;
+        }
+
+        let mut gc = track!(storage.start_sliced_journal_gc())?;
+        let mut steps = 0;
+        while !track!(storage.sliced_journal_gc_step(&mut gc))? {
+            // 各ステップの合間には、状態を変更する操作を行っても構わない
+            track!(storage.put(&LumpId::new(100 + steps), &zeroed_data(10)))?;
+            track!(storage.run_side_job_once())?;
+            steps += 1;
+        }
+        assert!(steps > 0);
+
+        let entries = track!(storage.journal_snapshot())?.entries;
+        assert_eq!(entries.len(), 10 + steps as usize);
+        assert!(entries
+            .iter()
+            .all(|e| matches!(e.record, JournalRecord::Put(..))));
+
+        track!(storage.journal_sync())?;
+        let storage = track!(Storage::open(nvm))?;
+        let mut expected = (10..20).map(LumpId::new).collect::<Vec<_>>();
+        expected.extend((0..steps).map(|i| LumpId::new(100 + i)));
+        assert_eq!(storage.list(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn storage_is_send() {
+        // デバイススレッドへの移動(`DeviceBuilder::spawn_with_storage`)が可能なこと
+        fn assert_send<T: Send>() {}
+        assert_send::<Storage<MemoryNvm>>();
+        assert_send::<Storage<SharedMemoryNvm>>();
+        assert_send::<Storage<FileNvm>>();
+    }
+
+    #[test]
+    fn config_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.1)
+            .journal_sync_interval(1)
+            .read_ahead_size(4096)
+            .create(nvm))?;
+
+        let config = storage.config();
+        assert_eq!(config.block_size, BlockSize::min());
+        assert_eq!(config.storage_size, storage.header().storage_size());
+        assert_eq!(
+            config.journal_region_size,
+            storage.header().journal_region_size
+        );
+        assert_eq!(config.data_region_size, storage.header().data_region_size);
+        assert_eq!(config.journal_sync_interval, 1);
+        assert_eq!(config.read_ahead_size, 4096);
+        assert!(!config.deduplication);
+
+        // 構築後の変更も反映される
+        storage.set_journal_gc_queue_size(10);
+        assert_eq!(storage.config().journal_gc_queue_size, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn adaptive_journal_sync_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.5)
+            .journal_sync_interval(64)
+            .adaptive_journal_sync(Duration::from_secs(10), 8, 128)
+            .create(nvm))?;
+        let metrics = storage.metrics().journal_region().clone();
+        assert_eq!(metrics.sync_interval(), 64);
+
+        // メモリ上のNVMへの書き込みは十分に速いので、同期間隔は下限まで縮む
+        for i in 0..1024 {
+            let data = track!(LumpData::new_embedded(vec![1; 10]))?;
+            track!(storage.put(&LumpId::new(i), &data))?;
+        }
+        assert_eq!(metrics.sync_interval(), 8);
+        assert_eq!(storage.config().journal_sync_interval, 8);
+        Ok(())
+    }
+
+    #[test]
+    fn delete_if_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        track!(storage.put(&id("000"), &track!(LumpData::new(b"foo".to_vec()))?))?;
+        track!(storage.put(
+            &id("001"),
+            &track!(LumpData::new_embedded(b"bar".to_vec()))?
+        ))?;
+
+        // 条件を満たさない場合には削除されない
+        assert!(!track!(
+            storage.delete_if(&id("000"), DeleteCondition::Size(4))
+        )?);
+        assert!(!track!(
+            storage.delete_if(&id("000"), DeleteCondition::checksum_of(b"bar"))
+        )?);
+        assert!(!track!(
+            storage.delete_if(&id("002"), DeleteCondition::Size(3))
+        )?);
+        assert_eq!(storage.list(), vec![id("000"), id("001")]);
+
+        assert!(track!(
+            storage.delete_if(&id("000"), DeleteCondition::checksum_of(b"foo"))
+        )?);
+        assert!(track!(
+            storage.delete_if(&id("001"), DeleteCondition::Size(3))
+        )?);
+        assert!(storage.list().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn delete_many_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        for &i in &[0, 1, 2, 5, 9] {
+            track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?;
+        }
+
+        let ids = [2, 0, 1, 7, 9, 0];
+        let ids = ids.iter().map(|&i| LumpId::new(i)).collect::<Vec<_>>();
+        let deleted = track!(storage.delete_many(&ids))?;
+        assert_eq!(deleted, vec![true, true, true, false, true, false]);
+        assert_eq!(storage.list(), vec![LumpId::new(5)]);
+
+        // 連続する`0..=2`は範囲削除レコードに、`9`は削除レコードにまとめられる
+        let (_, records) = storage
+            .metrics()
+            .journal_region()
+            .queue()
+            .enqueued_records();
+        assert_eq!(records.delete_range(), 1);
+        assert_eq!(records.delete(), 1);
+
+        track!(storage.journal_sync())?;
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![LumpId::new(5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn lump_range_including_max_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        let ids = [LumpId::new(1), LumpId::new(u128::MAX - 1), LumpId::MAX];
+        for lump_id in &ids {
+            track!(storage.put(lump_id, &zeroed_data(42)))?;
+        }
+
+        assert_eq!(storage.list_range(LumpRange::full()), ids.to_vec());
+        assert_eq!(
+            storage.list_range(LumpId::new(2)..LumpId::MAX),
+            vec![ids[1]]
+        );
+        assert_eq!(storage.list_range(LumpId::new(2)..), ids[1..].to_vec());
+        assert_eq!(
+            storage.list_range(track!(LumpRange::prefix(0xff, 8))?),
+            ids[1..].to_vec()
+        );
+        assert_ne!(storage.usage_range(LumpId::MAX..).bytecount(), Some(0));
+
+        let deleted = track!(storage.delete_range(LumpId::new(2)..=LumpId::MAX))?;
+        assert_eq!(deleted, ids[1..].to_vec());
+        assert_eq!(storage.list(), vec![ids[0]]);
+
+        // 再オープン後も同じ状態が復元される
+        track!(storage.journal_sync())?;
+        let storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![ids[0]]);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_trail_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().audit_trail(true).create(nvm.clone()))?;
+        let metadata = [7; AuditRecord::MIN_METADATA_SIZE];
+
+        // 操作と監査レコードは、一つのレコードとして書き込まれる
+        let records = track!(storage.journal_snapshot())?.entries.len();
+        track!(storage.put_with_audit(&LumpId::new(1), &zeroed_data(42), &metadata))?;
+        assert!(track!(
+            storage.delete_with_audit(&LumpId::new(1), &metadata)
+        )?);
+        assert_eq!(
+            track!(storage.journal_snapshot())?.entries.len(),
+            records + 2
+        );
+        assert!(!track!(
+            storage.delete_with_audit(&LumpId::new(1), &metadata)
+        )?);
+
+        // メタデータの長さは検証される
+        assert_eq!(
+            storage
+                .put_with_audit(&LumpId::new(2), &zeroed_data(42), &[0; 8])
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert_eq!(
+            storage
+                .put_with_audit(&LumpId::new(2), &zeroed_data(42), &[0; 33])
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+
+        // 監査レコードはジャーナルから取り出せて、再オープンにも影響しない
+        track!(storage.journal_sync())?;
+        let mut storage = track!(Storage::open(nvm))?;
+        let audits = track!(storage.journal_snapshot())?
+            .entries
+            .iter()
+            .filter_map(|e| AuditRecord::from_journal_record(&e.record))
+            .collect::<Vec<_>>();
+        let mut put = track!(AuditRecord::new(
+            AuditOperation::Put,
+            LumpId::new(1),
+            &metadata
+        ))?;
+        let mut delete = track!(AuditRecord::new(
+            AuditOperation::Delete,
+            LumpId::new(1),
+            &metadata
+        ))?;
+        put.seqno = 0;
+        delete.seqno = 1;
+        assert_eq!(audits, vec![put, delete]);
+        assert!(storage.list().is_empty());
+
+        // 無効な場合には記録されない
+        track!(storage.put_with_audit(&LumpId::new(3), &zeroed_data(42), &metadata))?;
+        let audits = track!(storage.journal_snapshot())?
+            .entries
+            .iter()
+            .filter_map(|e| AuditRecord::from_journal_record(&e.record))
+            .count();
+        assert_eq!(audits, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_records_are_retained() -> TestResult {
+        fn audit_seqnos<N: NonVolatileMemory>(storage: &mut Storage<N>) -> Result<Vec<u64>> {
+            let mut seqnos = track!(storage.journal_snapshot())?
+                .entries
+                .iter()
+                .filter_map(|e| AuditRecord::from_journal_record(&e.record))
+                .map(|r| r.seqno)
+                .collect::<Vec<_>>();
+            seqnos.sort_unstable();
+            Ok(seqnos)
+        }
+
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut builder = StorageBuilder::new();
+        builder.audit_trail(true).audit_retention(3);
+        let mut storage = track!(builder.create(nvm.clone()))?;
+        storage.set_automatic_gc_mode(false);
+        let metadata = [7; AuditRecord::MIN_METADATA_SIZE];
+        for i in 0..3 {
+            let lump_id = LumpId::new(i);
+            track!(storage.put_with_audit(&lump_id, &zeroed_data(42), &metadata))?;
+            assert!(track!(storage.delete_with_audit(&lump_id, &metadata))?);
+        }
+        track!(storage.put_with_audit(&LumpId::new(9), &zeroed_data(42), &metadata))?;
+
+        // 操作の記録が不要になっても、新しい方から三つの監査レコードはGCで回収されない
+        track!(storage.journal_gc())?;
+        assert_eq!(track!(audit_seqnos(&mut storage))?, vec![4, 5, 6]);
+        assert_eq!(storage.list(), vec![LumpId::new(9)]);
+
+        // 保持期間を過ぎた監査付きのPUTレコード(`LumpId::new(9)`)は、通常のPUTレコードとして書き直される
+        for _ in 0..3 {
+            track!(storage.delete_with_audit(&LumpId::new(0), &metadata))?;
+            track!(storage.put_with_audit(&LumpId::new(0), &zeroed_data(42), &metadata))?;
+        }
+        track!(storage.journal_gc())?;
+        track!(storage.journal_gc())?;
+        assert_eq!(track!(audit_seqnos(&mut storage))?, vec![9, 10, 11]);
+        assert_eq!(storage.list(), vec![LumpId::new(0), LumpId::new(9)]);
+
+        // 再オープン後も、通番は引き続き割り当てられる
+        track!(storage.close())?;
+        let mut storage = track!(builder.open(nvm.clone()))?;
+        assert_eq!(storage.list(), vec![LumpId::new(0), LumpId::new(9)]);
+        track!(storage.delete_with_audit(&LumpId::new(9), &metadata))?;
+        assert_eq!(track!(audit_seqnos(&mut storage))?, vec![9, 10, 11, 12]);
+
+        // 監査証跡が無効な場合には、保持されない
+        track!(storage.journal_sync())?;
+        let mut storage = track!(Storage::open(nvm))?;
+        storage.set_automatic_gc_mode(false);
+        track!(storage.journal_gc())?;
+        assert_eq!(track!(audit_seqnos(&mut storage))?, Vec::<u64>::new());
+        assert_eq!(storage.list(), vec![LumpId::new(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn nvm_capacity_is_validated_at_open() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        track!(storage.put(&LumpId::new(1), &zeroed_data(42)))?;
+        track!(storage.journal_sync())?;
+        let bytes = nvm.to_bytes();
+
+        // 切り詰められたNVM
+        let truncated = MemoryNvm::new(bytes[..bytes.len() - 512].to_vec());
+        assert_eq!(
+            Storage::open(truncated).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+
+        // ヘッダの記載よりも大きなNVM
+        let mut oversized = bytes.clone();
+        oversized.resize(bytes.len() + 1024 * 1024, 0);
+        assert_eq!(
+            Storage::open(MemoryNvm::new(oversized.clone()))
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+
+        let storage = track!(StorageBuilder::new()
+            .clamp_oversized_nvm(true)
+            .open(MemoryNvm::new(oversized)))?;
+        assert_eq!(storage.list(), vec![LumpId::new(1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_portions_are_detected_at_open() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        assert!(track!(storage.put(&LumpId::new(0), &zeroed_data(42)))?.is_new());
+        assert!(track!(storage.put(&LumpId::new(2), &zeroed_data(42)))?.is_new());
+
+        // 破損したジャーナルを模倣して、lump 2と同じ部分領域を指すlump 1を記録する
+        let portion = match storage.lump_index.get(&LumpId::new(2)) {
+            Some(Portion::Data(portion)) => portion,
+            _ => unreachable!(),
+        };
+        track!(storage.journal_region.records_put(
+            &mut storage.lump_index,
+            &LumpId::new(1),
+            portion
+        ))?;
+        track!(storage.journal_sync())?;
+
+        // デフォルトではオープンに失敗する
+        assert_eq!(
+            Storage::open(nvm.clone()).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+
+        // 重複を除去してオープンする(IDの大小に関わらず、ジャーナル上で後から登録されたlump 1が除去される)
+        let storage = track!(StorageBuilder::new()
+            .drop_overlapping_portions(true)
+            .open(nvm.clone()))?;
+        assert_eq!(storage.list(), vec![LumpId::new(0), LumpId::new(2)]);
+
+        // 除去結果はジャーナルに記録されている
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![LumpId::new(0), LumpId::new(2)]);
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        for i in 0..10 {
+            assert!(track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?.is_new());
+        }
+        assert!(track!(storage.put(&LumpId::new(10), &data("foo")))?.is_new());
+        assert!(track!(storage.delete(&LumpId::new(3)))?);
+        let usage_bytes = storage.data_region.metrics().allocator().usage_bytes();
+        track!(storage.close())?;
+
+        // チェックポイントから復元される(ジャーナルのエントリは読み込まれない)
+        let mut storage = track!(Storage::open(nvm.clone()))?;
+        let records_at_starting = storage
+            .metrics()
+            .journal_region()
+            .queue()
+            .enqueued_records_at_starting
+            .put();
+        assert_eq!(records_at_starting, 0);
+        assert_eq!(storage.list().len(), 10);
+        assert!(!storage.list().contains(&LumpId::new(3)));
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            usage_bytes
+        );
+        assert_eq!(
+            track!(storage.get(&LumpId::new(10)))?.map(|d| d.as_bytes().to_owned()),
+            Some(b"foo".to_vec())
+        );
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+
+        // クローズせずに更新後に破棄した場合には、ジャーナルから復元される
+        assert!(track!(storage.put(&LumpId::new(3), &zeroed_data(42)))?.is_new());
+        assert!(track!(storage.delete(&LumpId::new(4)))?);
+        track!(storage.journal_sync())?;
+        mem::drop(storage);
+
+        let mut storage = track!(Storage::open(nvm))?;
+        let records_at_starting = storage
+            .metrics()
+            .journal_region()
+            .queue()
+            .enqueued_records_at_starting
+            .put();
+        assert_ne!(records_at_starting, 0);
+        assert_eq!(storage.list().len(), 10);
+        assert!(storage.list().contains(&LumpId::new(3)));
+        assert!(!storage.list().contains(&LumpId::new(4)));
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_checkpoint_is_ignored() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        for i in 0..10 {
+            assert!(track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?.is_new());
+        }
+        track!(storage.close())?;
+
+        // チェックポイントの中身を壊す
+        let bytes = nvm.to_bytes();
+        let offset = bytes.windows(4).position(|w| w == b"lcpt").unwrap();
+        let block_start = offset / 512 * 512;
+        let mut block = bytes[block_start..][..512].to_vec();
+        block[offset - block_start + 8] ^= 0xFF;
+        let mut writer = nvm.clone();
+        track_io!(writer.seek(SeekFrom::Start(block_start as u64)))?;
+        track_io!(writer.write_all(&block))?;
+
+        // チェックポイントは使用されずに、ジャーナルから復元される
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.metrics().checkpoint_failures(), 1);
+        let records_at_starting = storage
+            .metrics()
+            .journal_region()
+            .queue()
+            .enqueued_records_at_starting
+            .put();
+        assert_ne!(records_at_starting, 0);
+        assert_eq!(storage.list().len(), 10);
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn stale_or_broken_index_shadow_is_rejected() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let shadow_path = dir.path().join("index.shadow");
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut builder = StorageBuilder::new();
+        builder
+            .index_shadow_file(&shadow_path)
+            .index_shadow_interval(Duration::from_secs(0));
+
+        let mut storage = track!(builder.create(nvm.clone()))?;
+        storage.set_automatic_gc_mode(false);
+        track!(storage.put(&LumpId::new(0), &zeroed_data(42)))?;
+        for i in 1..10 {
+            track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?;
+            assert!(track!(storage.delete(&LumpId::new(i)))?);
+        }
+        track!(storage.update_index_shadow())?;
+        assert_eq!(storage.metrics().index_shadow_writes(), 1);
+        let (shadow_head, _) = storage.journal_region.ring_buffer_position();
+
+        // GCによってジャーナルの始端位置が進められた後は、シャドウファイルは古いものとして扱われる
+        track!(storage.journal_gc())?;
+        track!(storage.journal_sync())?;
+        let (head, _) = storage.journal_region.ring_buffer_position();
+        assert_ne!(head, shadow_head);
+        mem::drop(storage);
+
+        let storage = track!(builder.open(nvm.clone()))?;
+        assert_eq!(storage.metrics().index_shadow_restores(), 0);
+        assert_eq!(storage.metrics().index_shadow_failures(), 1);
+        assert_eq!(storage.list(), vec![LumpId::new(0)]);
+        mem::drop(storage);
+
+        // 内容が壊れている場合にも、ジャーナルから復元される
+        track_io!(std::fs::write(&shadow_path, b"broken"))?;
+        let storage = track!(builder.open(nvm))?;
+        assert_eq!(storage.metrics().index_shadow_restores(), 0);
+        assert_eq!(storage.metrics().index_shadow_failures(), 1);
+        assert_eq!(storage.list(), vec![LumpId::new(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn index_shadow_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let shadow_path = dir.path().join("index.shadow");
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut builder = StorageBuilder::new();
+        builder
+            .index_shadow_file(&shadow_path)
+            .index_shadow_interval(Duration::from_secs(0));
+        let records_at_starting = |storage: &Storage<SharedMemoryNvm>| {
+            storage
+                .metrics()
+                .journal_region()
+                .queue()
+                .enqueued_records_at_starting
+                .put()
+        };
+
+        let mut storage = track!(builder.create(nvm.clone()))?;
+        assert_eq!(
+            storage.config().index_shadow_file,
+            Some(shadow_path.clone())
+        );
+        for i in 0..10 {
+            assert!(track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?.is_new());
+        }
+        assert!(track!(storage.delete(&LumpId::new(3)))?);
+        track!(storage.run_side_job_once())?;
+        let writes = storage.metrics().index_shadow_writes();
+        assert_ne!(writes, 0);
+
+        // ジャーナルが更新されていなければ、再度書き出されることはない
+        track!(storage.update_index_shadow())?;
+        assert_eq!(storage.metrics().index_shadow_writes(), writes);
+        let usage_bytes = storage.data_region.metrics().allocator().usage_bytes();
+        mem::drop(storage);
+
+        // クローズしていなくても、シャドウファイルから復元される(ジャーナルのエントリは読み込まれない)
+        let mut storage = track!(builder.open(nvm.clone()))?;
+        assert_eq!(storage.metrics().index_shadow_restores(), 1);
+        assert_eq!(storage.metrics().index_shadow_failures(), 0);
+        assert_eq!(records_at_starting(&storage), 0);
+        assert!(!storage.journal_region.was_clean_shutdown());
+        assert_eq!(storage.list().len(), 9);
+        assert!(!storage.list().contains(&LumpId::new(3)));
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            usage_bytes
+        );
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+
+        // シャドウファイルの書き出し後にジャーナルが更新されている場合には、ジャーナルから復元される
+        assert!(track!(storage.put(&LumpId::new(3), &zeroed_data(42)))?.is_new());
+        track!(storage.journal_sync())?;
+        mem::drop(storage);
+
+        let mut storage = track!(builder.open(nvm.clone()))?;
+        assert_eq!(storage.metrics().index_shadow_restores(), 0);
+        assert_eq!(storage.metrics().index_shadow_failures(), 1);
+        assert_ne!(records_at_starting(&storage), 0);
+        assert_eq!(storage.list().len(), 10);
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+
+        // シャドウファイルが存在しない場合にも、ジャーナルから復元される
+        assert!(track!(storage.delete(&LumpId::new(4)))?);
+        track!(storage.run_side_job_once())?;
+        assert_ne!(storage.metrics().index_shadow_writes(), 0);
+        mem::drop(storage);
+        track_io!(std::fs::remove_file(&shadow_path))?;
+
+        let storage = track!(builder.open(nvm.clone()))?;
+        assert_eq!(storage.metrics().index_shadow_restores(), 0);
+        assert_eq!(storage.metrics().index_shadow_failures(), 0);
+        assert_eq!(storage.list().len(), 9);
+
+        // ストレージの新規作成時には、既存のシャドウファイルは削除される
+        track!(storage.close())?;
+        assert!(shadow_path.exists());
+        let storage = track!(builder.create(nvm))?;
+        assert!(storage.list().is_empty());
+        assert!(!shadow_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn discard_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .discard_released_portions(true)
+            .create(nvm.clone()))?;
+        let contains_pattern = || nvm.to_bytes().windows(1000).any(|w| w == &[0xAB; 1000][..]);
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        assert!(track!(storage.put(&LumpId::new(0), &data))?.is_new());
+        assert!(track!(storage.delete(&LumpId::new(0)))?);
+
+        // ジャーナルが同期されるまでは、破棄通知およびアロケータへの返却は保留される
+        assert!(contains_pattern());
+        assert_ne!(storage.data_region.metrics().allocator().usage_bytes(), 0);
+        assert!(track!(storage.check(CheckLevel::Index))?.is_ok());
+
+        track!(storage.journal_sync())?;
+        assert!(!contains_pattern());
+        assert_eq!(storage.data_region.metrics().allocator().usage_bytes(), 0);
+        assert!(track!(storage.check(CheckLevel::Index))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn scrub_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .scrub_released_portions(true)
+            .padding_fill_byte(0xCD)
+            .create(nvm.clone()))?;
+        let contains_pattern = || nvm.to_bytes().windows(1000).any(|w| w == &[0xAB; 1000][..]);
+        let metrics = storage.data_region.metrics().clone();
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        assert!(track!(storage.put(&LumpId::new(0), &data))?.is_new());
+        assert!(nvm.to_bytes().windows(10).any(|w| w == &[0xCD; 10][..]));
+        assert!(track!(storage.delete(&LumpId::new(0)))?);
+
+        // ジャーナルが同期されると、スクラブ待ちとなる
+        track!(storage.journal_sync())?;
+        assert!(contains_pattern());
+        assert_eq!(metrics.pending_scrub_bytes(), 1024);
+        assert_ne!(metrics.allocator().usage_bytes(), 0);
+        assert!(track!(storage.check(CheckLevel::Index))?.is_ok());
+
+        // 補助タスクの中でゼロ埋めされ、アロケータに返却される
+        track!(storage.run_side_job_once())?;
+        assert!(!contains_pattern());
+        assert_eq!(metrics.pending_scrub_bytes(), 0);
+        assert_eq!(metrics.scrubbed_bytes(), 1024);
+        assert_eq!(metrics.nvm_written_bytes().scrub(), 1024);
+        assert_eq!(metrics.allocator().usage_bytes(), 0);
+        assert!(track!(storage.check(CheckLevel::Index))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn delete_secure_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        let contains_pattern = |p: u8| nvm.to_bytes().windows(1000).any(|w| w == &[p; 1000][..]);
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        track!(storage.put(&LumpId::new(0), &data))?;
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xEF; 1000]))?;
+        track!(storage.put(&LumpId::new(1), &data))?;
+
+        assert!(track!(storage.delete_secure(&LumpId::new(0), 0))?);
+        assert!(!contains_pattern(0xAB));
+        assert!(!track!(storage.delete_secure(&LumpId::new(0), 0))?);
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            1024
+        );
+
+        // 通常の削除では、データはそのまま残る
+        assert!(track!(storage.delete(&LumpId::new(1)))?);
+        assert!(contains_pattern(0xEF));
+        assert!(track!(storage.check(CheckLevel::Index))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn skip_identical_overwrites_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .skip_identical_overwrites(true)
+            .create(nvm))?;
+        assert!(storage.config().skip_identical_overwrites);
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        let report = track!(storage.put(&id("0"), &data))?;
+        assert!(report.is_new());
+        assert!(!report.is_unchanged());
+
+        // 同一内容での上書きは、データ領域にもジャーナルにも書き込まれない
+        let tail = track!(storage.journal_snapshot())?.tail;
+        let usage = storage.data_region.metrics().allocator().usage_bytes();
+        let report = track!(storage.put(&id("0"), &data))?;
+        assert!(!report.is_new());
+        assert!(report.is_unchanged());
+        assert_eq!(report.allocated_blocks(), 0);
+        assert_eq!(track!(storage.journal_snapshot())?.tail, tail);
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            usage
+        );
+        assert_eq!(storage.metrics().unchanged_overwrites(), 1);
+
+        // 長さないし内容が異なる場合には、通常通りに上書きされる
+        let other = track!(storage.allocate_lump_data_with_bytes(&[0xEF; 1000]))?;
+        assert!(!track!(storage.put(&id("0"), &other))?.is_unchanged());
+        let other = track!(storage.allocate_lump_data_with_bytes(&[0xEF; 2000]))?;
+        assert!(!track!(storage.put(&id("0"), &other))?.is_unchanged());
+        assert_eq!(
+            track!(storage.get(&id("0")))?.map(|d| d.as_bytes().to_owned()),
+            Some(vec![0xEF; 2000])
+        );
+
+        // ジャーナル領域に埋め込まれたlumpも対象となる
+        let embedded = track!(LumpData::new_embedded(vec![1, 2, 3]))?;
+        assert!(!track!(storage.put(&id("1"), &embedded))?.is_unchanged());
+        let report = track!(storage.put(&id("1"), &embedded))?;
+        assert!(report.is_unchanged());
+        assert!(report.is_embedded());
+
+        // 格納先の領域の種類が異なる場合には、内容が同一でも上書きされる
+        let data = track!(storage.allocate_lump_data_with_bytes(&[1, 2, 3]))?;
+        let report = track!(storage.put(&id("1"), &data))?;
+        assert!(!report.is_unchanged());
+        assert!(!report.is_embedded());
+        assert_eq!(storage.metrics().unchanged_overwrites(), 2);
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+
+        // 無効な場合(デフォルト)には、常に書き込まれる
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        track!(storage.put(&id("0"), &embedded))?;
+        assert!(!track!(storage.put(&id("0"), &embedded))?.is_unchanged());
+        assert_eq!(storage.metrics().unchanged_overwrites(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn free_fragment_threshold_works() -> TestResult {
+        // 空き領域全体が閾値未満なので、断片として隔離される
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .free_fragment_threshold(u16::MAX)
+            .create(nvm))?;
+        assert_eq!(storage.config().free_fragment_threshold, u16::MAX);
+        let metrics = storage.data_region.metrics().allocator().clone();
+        assert_eq!(metrics.fragment_portions(), 1);
+        let fragment_bytes = metrics.fragment_bytes();
+        assert!(fragment_bytes > 0);
+
+        // 容量不足でも、断片は自動的には割当に使用されない
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        assert_eq!(
+            storage.put(&id("0"), &data).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageFull)
+        );
+        assert_eq!(metrics.free_list_len(), 0);
+        assert_eq!(metrics.fragment_portions(), 1);
+
+        // 明示的に探索対象に戻した後は、割当に使用される
+        // (割当後の残りの空き領域は、再び断片となる)
+        assert_eq!(storage.reclaim_free_fragments(), 1);
+        assert_eq!(metrics.fragment_portions(), 0);
+        assert_eq!(track!(storage.put(&id("0"), &data))?.allocated_blocks(), 2);
+        assert_eq!(metrics.fragment_portions(), 1);
+        assert_eq!(metrics.fragment_bytes(), fragment_bytes - 1024);
+
+        // 解放された領域は、隣接する断片と併合される
+        assert!(track!(storage.delete(&id("0")))?);
+        assert_eq!(metrics.free_list_len(), 0);
+        assert_eq!(metrics.fragment_portions(), 1);
+        assert_eq!(metrics.fragment_bytes(), fragment_bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn max_free_portions_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().max_free_portions(1).create(nvm))?;
+        assert_eq!(storage.config().max_free_portions, 1);
+        let metrics = storage.data_region.metrics().allocator().clone();
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        for i in 0..4 {
+            track!(storage.put(&id(&i.to_string()), &data))?;
+        }
+        assert!(track!(storage.delete(&id("0")))?);
+        assert!(track!(storage.delete(&id("2")))?);
+        assert_eq!(metrics.free_list_len(), 1);
+        assert_eq!(metrics.fragment_portions(), 2);
+
+        // 断片も空き領域としては扱われる
+        assert_eq!(storage.data_region.free_portions().len(), 3);
+        assert_eq!(storage.reclaim_free_fragments(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn large_lump_alignment_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .large_lump_alignment(10_000, 64 * 1024)
+            .create(nvm))?;
+        assert_eq!(
+            storage.config().large_lump_alignment,
+            Some((10_000, 64 * 1024))
+        );
+
+        let small = track!(storage.allocate_lump_data_with_bytes(&[1; 1000]))?;
+        let large = track!(storage.allocate_lump_data_with_bytes(&[2; 20_000]))?;
+        track!(storage.put(&id("0"), &small))?;
+        track!(storage.put(&id("1"), &large))?;
+        track!(storage.put(&id("2"), &small))?;
+
+        let start = |storage: &Storage<_>, lump_id| match storage.lump_index.get(&lump_id) {
+            Some(Portion::Data(portion)) => portion.start.as_u64(),
+            _ => unreachable!(),
+        };
+        assert_eq!(start(&storage, id("0")), 0);
+        assert_eq!(start(&storage, id("1")), 128);
+        assert_eq!(start(&storage, id("2")), 2);
+        assert_eq!(
+            storage
+                .data_region
+                .metrics()
+                .allocator()
+                .aligned_allocations(),
+            1
+        );
+
+        // アライメントはブロックサイズの倍数である必要がある
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        assert_eq!(
+            StorageBuilder::new()
+                .large_lump_alignment(10_000, 1000)
+                .create(nvm)
+                .err()
+                .map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deduplication_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .deduplication(true)
+            .create(nvm.clone()))?;
+
+        let data = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        let report = track!(storage.put(&LumpId::new(0), &data))?;
+        assert!(!report.is_deduplicated());
+        assert_eq!(report.allocated_blocks(), 2);
+
+        // 同じ内容のlumpは、既存の部分領域を共有する
+        let report = track!(storage.put(&LumpId::new(1), &data))?;
+        assert!(report.is_deduplicated());
+        assert_eq!(report.allocated_blocks(), 0);
+        assert_eq!(storage.metrics().deduplicated_lumps(), 1);
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            1024
+        );
+
+        // 内容が異なる場合には、通常通りに割り当てられる
+        let other = track!(storage.allocate_lump_data_with_bytes(&[0xEF; 1000]))?;
+        assert!(!track!(storage.put(&LumpId::new(2), &other))?.is_deduplicated());
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+
+        // 参照が残っている間は、部分領域は解放されない
+        assert!(track!(storage.delete(&LumpId::new(0)))?);
+        track!(storage.journal_sync())?;
+        assert_eq!(
+            track!(storage.get(&LumpId::new(1)))?.map(|d| d.as_bytes().to_owned()),
+            Some(vec![0xAB; 1000])
+        );
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            2048
+        );
+
+        // 再オープン後も、共有状態はジャーナルから復元される
+        assert!(track!(storage.put(&LumpId::new(3), &data))?.is_deduplicated());
+        track!(storage.journal_sync())?;
+        mem::drop(storage);
+
+        let mut storage = track!(StorageBuilder::new().deduplication(true).open(nvm))?;
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            2048
+        );
+        assert!(track!(storage.delete(&LumpId::new(1)))?);
+        assert_eq!(
+            track!(storage.get(&LumpId::new(3)))?.map(|d| d.as_bytes().to_owned()),
+            Some(vec![0xAB; 1000])
+        );
+        assert!(track!(storage.delete(&LumpId::new(3)))?);
+        track!(storage.journal_sync())?;
+        assert_eq!(
+            storage.data_region.metrics().allocator().usage_bytes(),
+            1024
+        );
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn link_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        let usage_bytes = |s: &Storage<_>| s.data_region.metrics().allocator().usage_bytes();
+
+        let lump = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        track!(storage.put(&LumpId::new(0), &lump))?;
+        track!(storage.put(&LumpId::new(1), &lump))?;
+        track!(storage.put(&LumpId::new(10), &data("foo")))?;
+        assert_eq!(usage_bytes(&storage), 2048);
+
+        // 別名はデータをコピーせずに部分領域を共有する
+        assert!(track!(storage.link(&LumpId::new(0), &LumpId::new(2)))?);
+        assert!(track!(storage.link(&LumpId::new(2), &LumpId::new(3)))?);
+        assert!(track!(storage.link(&LumpId::new(10), &LumpId::new(11)))?);
+        assert!(!track!(storage.link(&LumpId::new(100), &LumpId::new(4)))?);
+        assert_eq!(storage.metrics().linked_lumps(), 3);
+
+        // 既存のlumpは上書きされる
+        assert!(track!(storage.link(&LumpId::new(0), &LumpId::new(1)))?);
+        track!(storage.journal_sync())?;
+        assert_eq!(usage_bytes(&storage), 1024);
+        assert_eq!(
+            track!(storage.get(&LumpId::new(11)))?.map(|d| d.as_bytes().to_owned()),
+            Some(b"foo".to_vec())
+        );
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+
+        // 全ての参照が無くなるまでは解放されない
+        for i in 0..3 {
+            assert!(track!(storage.delete(&LumpId::new(i)))?);
+        }
+        track!(storage.journal_sync())?;
+        assert_eq!(usage_bytes(&storage), 1024);
+
+        // GC後に再オープンしても、共有状態は復元される
+        assert!(track!(storage.link(&LumpId::new(3), &LumpId::new(4)))?);
+        track!(storage.journal_gc())?;
+        track!(storage.journal_sync())?;
+        mem::drop(storage);
+
+        let mut storage = track!(Storage::open(nvm))?;
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        assert_eq!(usage_bytes(&storage), 1024);
+        assert!(track!(storage.delete(&LumpId::new(3)))?);
+        assert_eq!(
+            track!(storage.get(&LumpId::new(4)))?.map(|d| d.as_bytes().to_owned()),
+            Some(vec![0xAB; 1000])
+        );
+        assert!(track!(storage.delete(&LumpId::new(4)))?);
+        track!(storage.journal_sync())?;
+        assert_eq!(usage_bytes(&storage), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rename_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        let usage_bytes = |s: &Storage<_>| s.data_region.metrics().allocator().usage_bytes();
+        let get = |s: &mut Storage<_>, i| -> Result<Option<Vec<u8>>> {
+            Ok(track!(s.get(&LumpId::new(i)))?.map(|d| d.as_bytes().to_owned()))
+        };
+
+        let lump = track!(storage.allocate_lump_data_with_bytes(&[0xAB; 1000]))?;
+        track!(storage.put(&LumpId::new(0), &lump))?;
+        track!(storage.put(&LumpId::new(1), &zeroed_data(1000)))?;
+        track!(storage.put(&LumpId::new(10), &data("foo")))?;
+        assert_eq!(usage_bytes(&storage), 2048);
+
+        // データのコピーは行われない
+        let (read_bytes, written_bytes) = {
+            let m = storage.data_region.metrics();
+            (m.nvm_read_bytes().total(), m.nvm_written_bytes().total())
+        };
+        assert!(track!(storage.rename(&LumpId::new(0), &LumpId::new(2)))?);
+        assert_eq!(
+            storage.data_region.metrics().nvm_read_bytes().total(),
+            read_bytes
+        );
+        assert_eq!(
+            storage.data_region.metrics().nvm_written_bytes().total(),
+            written_bytes
+        );
+        assert!(track!(storage.rename(&LumpId::new(10), &LumpId::new(11)))?);
+        assert!(!track!(storage.rename(&LumpId::new(100), &LumpId::new(3)))?);
+        assert_eq!(storage.metrics().renamed_lumps(), 2);
+        assert_eq!(
+            storage.list(),
+            vec![LumpId::new(1), LumpId::new(2), LumpId::new(11)]
+        );
+        assert_eq!(track!(get(&mut storage, 2))?, Some(vec![0xAB; 1000]));
+        assert_eq!(track!(get(&mut storage, 11))?, Some(b"foo".to_vec()));
+
+        // 既存のlumpは上書きされる
+        assert!(track!(storage.rename(&LumpId::new(2), &LumpId::new(1)))?);
+        assert_eq!(usage_bytes(&storage), 1024);
+        assert_eq!(track!(get(&mut storage, 1))?, Some(vec![0xAB; 1000]));
+
+        // 改名前のIDで新たに作成されたlumpは、GCによる改名レコードの再配置後も残り続ける
+        track!(storage.run_side_job_once())?; // GCキューへの補填
+        track!(storage.put(&LumpId::new(2), &data("bar")))?;
+        for _ in 0..2 {
+            track!(storage.run_side_job_once())?;
+        }
+        track!(storage.journal_sync())?;
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        mem::drop(storage);
+
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(
+            storage.list(),
+            vec![LumpId::new(1), LumpId::new(2), LumpId::new(11)]
+        );
+        assert_eq!(usage_bytes(&storage), 1024);
+        assert_eq!(track!(get(&mut storage, 1))?, Some(vec![0xAB; 1000]));
+        assert_eq!(track!(get(&mut storage, 2))?, Some(b"bar".to_vec()));
+        assert_eq!(track!(get(&mut storage, 11))?, Some(b"foo".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn rename_embedded_lump_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .verify_embedded_data(true)
+            .create(nvm.clone()))?;
+        let get = |s: &mut Storage<_>, i| -> Result<Option<Vec<u8>>> {
+            Ok(track!(s.get(&LumpId::new(i)))?.map(|d| d.as_bytes().to_owned()))
+        };
+        track!(storage.put(&LumpId::new(0), &data("foo")))?;
+
+        // 改名レコードが一つだけ追記される
+        let entries = track!(storage.journal_snapshot())?.entries.len();
+        assert!(track!(storage.rename(&LumpId::new(0), &LumpId::new(1)))?);
+        let snapshot = track!(storage.journal_snapshot())?;
+        assert_eq!(snapshot.entries.len(), entries + 1);
+        let record = EmbeddedRenameRecord::from_journal_record(
+            &snapshot.entries.last().expect("Never fails").record,
+        );
+        assert_eq!(
+            record,
+            Some(EmbeddedRenameRecord::new(
+                LumpId::new(0),
+                LumpId::new(1),
+                b"foo"
+            ))
+        );
+        assert_eq!(storage.list(), vec![LumpId::new(1)]);
+        assert_eq!(track!(get(&mut storage, 1))?, Some(b"foo".to_vec()));
+
+        // 復元時にも改名が反映される
+        track!(storage.journal_sync())?;
+        mem::drop(storage);
+        let mut storage = track!(StorageBuilder::new()
+            .verify_embedded_data(true)
+            .open(nvm.clone()))?;
+        assert_eq!(storage.list(), vec![LumpId::new(1)]);
+        assert_eq!(track!(get(&mut storage, 1))?, Some(b"foo".to_vec()));
+
+        // 改名前のIDで新たに作成されたlumpは、GCや書き直しによる改名レコードの再配置後も残り続ける
+        track!(storage.put(&LumpId::new(0), &data("bar")))?;
+        track!(storage.rewrite_journal())?;
+        assert_eq!(track!(get(&mut storage, 1))?, Some(b"foo".to_vec()));
+        track!(storage.journal_gc())?;
+        track!(storage.journal_sync())?;
+        assert!(track!(storage.check(CheckLevel::Journal))?.is_ok());
+        mem::drop(storage);
+
+        let mut storage = track!(Storage::open(nvm))?;
+        assert_eq!(storage.list(), vec![LumpId::new(0), LumpId::new(1)]);
+        assert_eq!(track!(get(&mut storage, 0))?, Some(b"bar".to_vec()));
+        assert_eq!(track!(get(&mut storage, 1))?, Some(b"foo".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn run_side_job_once_within_makes_gc_progress_with_zero_limit() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        for i in 0..10 {
+            track!(storage.put(&LumpId::new(i), &data("foo")))?;
+            assert!(track!(storage.delete(&LumpId::new(i)))?);
+        }
+
+        // 制限時間が`0`でも、GCは毎回最低でも一レコード分は進む
+        for _ in 0..5 {
+            track!(storage.run_side_job_once_within(Duration::from_secs(0)))?;
+        }
+        let metrics = storage.metrics().journal_region();
+        assert!(metrics.gc_enqueued_records() > 0);
+        assert_eq!(metrics.gc_dequeued_records(), metrics.gc_enqueued_records());
+        Ok(())
+    }
+
+    #[test]
+    fn stats_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        let stats = storage.stats();
+        assert_eq!(stats.lumps, 0);
+        assert_eq!(stats.data.usage_bytes, 0);
+        assert_eq!(stats.data.free_portions, 1);
+        assert_eq!(stats.data.fragmentation(), 0.0);
+
+        for i in 0..4 {
+            track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?;
+        }
+        track!(storage.put(&LumpId::new(10), &data("foo")))?;
+        assert!(track!(storage.delete(&LumpId::new(1)))?);
+
+        let stats = storage.stats();
+        assert_eq!(stats.lumps, 4);
+        assert_eq!(stats.journal.usage_bytes, stats.journal.tail);
+        assert!(stats.journal.usage_bytes < stats.journal.capacity_bytes);
+        assert_eq!(stats.data.usage_bytes, 512 * 3);
+        assert_eq!(stats.data.free_portions, 2);
+        assert_eq!(
+            stats.data.largest_free_portion_bytes,
+            stats.data.free_bytes() - 512
+        );
+        assert!(stats.data.fragmentation() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn clean_shutdown_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        assert!(storage.was_clean_shutdown());
+        track!(storage.put(&id("000"), &data("foo")))?;
+        track!(storage.put(&id("001"), &zeroed_data(42)))?;
+        track!(storage.close())?;
+
+        // チェックポイントから復元される
+        let storage = track!(Storage::open(nvm.clone()))?;
+        assert!(storage.was_clean_shutdown());
+        std::mem::drop(storage);
+
+        // 追記が行われていなければ、ジャーナルから復元された場合でもクリーンシャットダウンとして扱われる
+        let mut storage = track!(Storage::open(nvm.clone()))?;
+        assert!(storage.was_clean_shutdown());
+
+        // クローズせずに破棄した場合
+        track!(storage.delete(&id("000")))?;
+        track!(storage.journal_sync())?;
+        std::mem::drop(storage);
+
+        let storage = track!(Storage::open(nvm.clone()))?;
+        assert!(!storage.was_clean_shutdown());
+        assert_eq!(storage.list(), vec![id("001")]);
+        track!(storage.close())?;
+
+        let storage = track!(Storage::open(nvm))?;
+        assert!(storage.was_clean_shutdown());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_snapshot_step_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().journal_region_ratio(0.01).create(nvm))?;
+        storage.set_automatic_gc_mode(false);
+
+        // 周回を跨ぐまで、追記と全体GCを繰り返す
+        for i in 0..200 {
+            track!(storage.put(&id("000"), &data(&i.to_string())))?;
+            track!(storage.put(&id("001"), &zeroed_data(10)))?;
+            track!(storage.delete(&id("001")))?;
+            track!(storage.journal_gc())?;
+        }
+        for i in 0..10 {
+            track!(storage.put(&LumpId::new(i + 10), &data("foo")))?;
+        }
+
+        // 分割して読み込んだ結果は、一括で読み込んだ結果と一致する
+        let expected = track!(storage.journal_snapshot())?.entries;
+        assert!(expected.len() > 3);
+        let mut cursor = Some(JournalCursor::default());
+        let mut entries = Vec::new();
+        while cursor.is_some() {
+            let step = track!(storage.journal_snapshot_step(&mut cursor, 3))?;
+            assert!(step.len() <= 3);
+            entries.extend(step);
+        }
+        assert_eq!(entries, expected);
+
+        // 走査完了後は何も返さない
+        assert!(track!(storage.journal_snapshot_step(&mut cursor, 3))?.is_empty());
+
+        // GCによって取り出し済みとなった位置を指すカーソルは、始端位置から読み込みを再開する
+        let mut cursor = Some(JournalCursor::default());
+        track!(storage.journal_snapshot_step(&mut cursor, 1))?;
+        let stale = cursor;
+        track!(storage.journal_gc())?;
+        let expected = track!(storage.journal_snapshot())?.entries;
+
+        let mut cursor = stale;
+        let mut entries = Vec::new();
+        while cursor.is_some() {
+            entries.extend(track!(storage.journal_snapshot_step(&mut cursor, 0))?);
+        }
+        assert_eq!(entries, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_embedded_data_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .verify_embedded_data(true)
+            .create(nvm.clone()))?;
+        let payload = b"embedded payload to be verified";
+        let data = track!(LumpData::new_embedded(payload.to_vec()))?;
+        track!(storage.put(&id("000"), &data))?;
+        track!(storage.journal_sync())?;
+        assert_eq!(
+            track!(storage.get(&id("000")))?.map(|d| d.as_bytes().to_owned()),
+            Some(payload.to_vec())
+        );
+
+        // ジャーナル上の埋め込みデータを破損させる
+        let bytes = nvm.to_bytes();
+        let offset = track_assert_some!(
+            bytes.windows(payload.len()).position(|w| w == &payload[..]),
+            ErrorKind::Other
+        );
+        let block_start = BlockSize::min().floor_align(offset as u64);
+        let mut block = bytes[block_start as usize..][..BlockSize::MIN as usize].to_vec();
+        block[offset - block_start as usize] ^= 0xFF;
+        let mut writer = nvm.clone();
+        track_io!(writer.seek(SeekFrom::Start(block_start)))?;
+        track_io!(writer.write_all(&block))?;
+
+        assert_eq!(
+            storage.get(&id("000")).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageCorrupted)
+        );
+
+        // 検証が無効なら、破損したデータがそのまま返される
+        storage.journal_region.set_embedded_data_verification(false);
+        let corrupted = track!(storage.get(&id("000")))?.map(|d| d.as_bytes().to_owned());
+        assert!(corrupted.is_some());
+        assert_ne!(corrupted, Some(payload.to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn unsynced_journal_metrics_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        let metrics = storage.metrics().journal_region().clone();
+        assert_eq!(metrics.unsynced_bytes(), 0);
+        assert!(metrics.oldest_unsynced_record_age().is_none());
+
+        track!(storage.put(&id("000"), &zeroed_data(42)))?;
+        track!(storage.delete(&id("000")))?;
+        assert_ne!(metrics.unsynced_bytes(), 0);
+        assert!(metrics.oldest_unsynced_record_age().is_some());
+
+        track!(storage.journal_sync())?;
+        assert_eq!(metrics.unsynced_bytes(), 0);
+        assert!(metrics.oldest_unsynced_record_age().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_max_write_buffer_size_works() -> TestResult {
+        fn put_embedded<N: NonVolatileMemory>(storage: &mut Storage<N>) -> Result<()> {
+            for i in 0..100 {
+                let data = track!(LumpData::new_embedded(vec![1; 100]))?;
+                track!(storage.put(&LumpId::new(i), &data))?;
+            }
+            Ok(())
+        }
+
+        // 上限なし
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().journal_region_ratio(0.5).create(nvm))?;
+        track!(put_embedded(&mut storage))?;
+        let metrics = storage.metrics().journal_region().clone();
+        assert!(metrics.write_buffer_high_water_bytes() > 10_000);
+
+        // 上限あり
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.5)
+            .journal_max_write_buffer_size(2048)
+            .create(nvm.clone()))?;
+        track!(put_embedded(&mut storage))?;
+        let metrics = storage.metrics().journal_region().clone();
+        assert!(metrics.write_buffer_high_water_bytes() <= 2048 + 512);
+
+        // 書き出されたデータが正しく読み込めることを確認
+        track!(storage.journal_sync())?;
+        let mut storage = track!(Storage::open(nvm))?;
+        for i in 0..100 {
+            let data = track!(storage.get(&LumpId::new(i)))?;
+            assert_eq!(data.map(|d| d.as_bytes().to_owned()), Some(vec![1; 100]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn journal_restore_buffer_size_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.5)
+            .create(nvm.clone()))?;
+        for i in 0..100 {
+            let data = track!(LumpData::new_embedded(vec![1; 100]))?;
+            track!(storage.put(&LumpId::new(i), &data))?;
+        }
+        track!(storage.journal_sync())?;
+
+        // 小さな読み込み単位でも正しく復元できる
+        for &size in &[512, 4 * 1024 * 1024] {
+            let mut storage = track!(StorageBuilder::new()
+                .journal_restore_buffer_size(size)
+                .open(nvm.clone()))?;
+            assert_eq!(storage.config().journal_restore_buffer_size, size);
+            assert!(
+                storage
+                    .metrics()
+                    .journal_region()
+                    .restore_throughput_bytes_per_second()
+                    > 0.0
+            );
+            for i in 0..100 {
+                let data = track!(storage.get(&LumpId::new(i)))?;
+                assert_eq!(data.map(|d| d.as_bytes().to_owned()), Some(vec![1; 100]));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn journal_wrap_around_metrics_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().journal_region_ratio(0.01).create(nvm))?;
+        let metrics = storage.metrics().journal_region().clone();
+        assert_eq!(metrics.wrap_arounds(), 0);
+        assert!(metrics.last_wrap_around_time().is_none());
+
+        // ジャーナルが何周かするまで書き込む
+        let capacity = storage.journal_region.stats().capacity_bytes;
+        while metrics.written_bytes() < capacity * 3 {
+            track!(storage.put(&LumpId::new(0), &data("foo")))?;
+        }
+        assert!(metrics.wrap_arounds() >= 2);
+        assert!(metrics.last_wrap_around_time().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_safe_flush_works() -> TestResult {
+        for &safe_flush in &[false, true] {
+            let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+            let mut storage = track!(StorageBuilder::new()
+                .journal_safe_flush(safe_flush)
+                .journal_sync_interval(3)
+                .create(nvm.clone()))?;
+
+            // ジャーナルが何周かするまで書き込む
+            for i in 0..300 {
+                let data = track!(LumpData::new_embedded(vec![i as u8; 100]))?;
+                track!(storage.put(&LumpId::new(i % 10), &data))?;
+            }
+            track!(storage.journal_sync())?;
+
+            let mut storage = track!(Storage::open(nvm))?;
+            for i in 290..300 {
+                let data = track!(storage.get(&LumpId::new(i % 10)))?;
+                assert_eq!(
+                    data.map(|d| d.as_bytes().to_owned()),
+                    Some(vec![i as u8; 100])
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn write_amplification_metrics_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        let metrics = storage.metrics().clone();
+        assert_eq!(metrics.logical_written_bytes(), 0);
+        assert_eq!(metrics.write_amplification(), None);
+
+        // データ領域: 10バイトのデータでも、一ブロック(512バイト)が書き込まれる
+        track!(storage.put(&id("000"), &zeroed_data(10)))?;
+        assert_eq!(metrics.logical_written_bytes(), 10);
+        assert_eq!(metrics.data_region().written_bytes(), 512);
+        let journal_bytes = metrics.journal_region().written_bytes();
+        assert_ne!(journal_bytes, 0);
+
+        // ジャーナル領域: 埋め込みデータはレコードの一部として書き込まれる
+        track!(storage.put(&id("001"), &track!(LumpData::new_embedded(vec![1; 10]))?))?;
+        assert_eq!(metrics.logical_written_bytes(), 20);
+        assert_eq!(metrics.data_region().written_bytes(), 512);
+        assert!(metrics.journal_region().written_bytes() > journal_bytes + 10);
+
+        assert_eq!(
+            metrics.physical_written_bytes(),
+            metrics.data_region().written_bytes() + metrics.journal_region().written_bytes()
+        );
+        assert!(metrics.write_amplification().unwrap() > 1.0);
+        assert_eq!(metrics.journal_region().gc_rewritten_bytes(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn nvm_io_metrics_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        let journal = storage.metrics().journal_region().clone();
+        let data = storage.metrics().data_region().clone();
+
+        track!(storage.put(&id("000"), &zeroed_data(10)))?;
+        assert!(track!(storage.get(&id("000")))?.is_some());
+        assert_eq!(data.nvm_written_bytes().foreground(), 512);
+        assert_eq!(data.nvm_read_bytes().foreground(), 512);
+        assert_eq!(data.nvm_written_bytes().total(), 512);
+        assert_ne!(journal.nvm_written_bytes().foreground(), 0);
+        assert_eq!(journal.nvm_written_bytes().gc(), 0);
+        assert_eq!(journal.nvm_read_bytes().gc(), 0);
+
+        // GCによる読み書きは、利用者のリクエストとは区別される
+        let foreground = journal.nvm_written_bytes().foreground();
+        track!(storage.journal_gc())?;
+        assert_ne!(journal.nvm_read_bytes().gc(), 0);
+        assert_ne!(journal.nvm_written_bytes().gc(), 0);
+        assert_eq!(journal.nvm_written_bytes().foreground(), foreground);
+
+        // 開き直した際の読み込みは、復元によるものとして計上される
+        track!(storage.close())?;
         let storage = track!(Storage::open(nvm))?;
-        assert_eq!(storage.list(), vec![id("000"), id("111")]);
+        let journal = storage.metrics().journal_region();
+        assert_ne!(journal.nvm_read_bytes().restore(), 0);
+        assert_eq!(journal.nvm_read_bytes().foreground(), 0);
+        assert_eq!(journal.nvm_read_bytes().gc(), 0);
         Ok(())
     }
 
     #[test]
-    fn full() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
-
-        let nvm = track!(FileNvm::create(
-            dir.path().join("test.lusf"),
-            BlockSize::min().ceil_align(1024 * 1024)
-        ))?;
+    fn put_bytes_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
         let mut storage = track!(Storage::create(nvm))?;
 
-        assert!(track!(storage.put(&id("000"), &zeroed_data(512 * 1024)))?,);
+        let report = track!(storage.put_bytes(&id("000"), b"foo"))?;
+        assert!(report.is_new());
+        assert!(!report.is_embedded());
         assert_eq!(
-            storage.put(&id("000"), &zeroed_data(512 * 1024)).ok(),
-            Some(false)
+            track!(storage.get(&id("000")))?.map(|d| d.into_bytes()),
+            Some(b"foo".to_vec())
         );
+
+        let too_large = vec![0; LumpData::MAX_SIZE + 1];
         assert_eq!(
             storage
-                .put(&id("111"), &zeroed_data(512 * 1024))
+                .put_bytes(&id("001"), &too_large)
                 .err()
                 .map(|e| *e.kind()),
-            Some(ErrorKind::StorageFull)
-        );
-
-        assert_eq!(storage.delete(&id("000")).ok(), Some(true));
-        assert_eq!(
-            storage.put(&id("111"), &zeroed_data(512 * 1024)).ok(),
-            Some(true)
+            Some(ErrorKind::InvalidInput)
         );
         Ok(())
     }
 
     #[test]
-    fn max_size_lump() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
-
-        let nvm = track!(FileNvm::create(
-            dir.path().join("test.lusf"),
-            BlockSize::min().ceil_align(100 * 1024 * 1024)
-        ))?;
-        let mut storage = track!(Storage::create(nvm))?;
-
-        let data = zeroed_data(LumpData::MAX_SIZE);
-        assert!(track!(storage.put(&id("000"), &data))?);
-        assert_eq!(track!(storage.get(&id("000")))?, Some(data));
-        Ok(())
-    }
+    fn access_heatmap_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = track!(Storage::create(nvm))?;
+        assert!(storage.metrics().data_region().access_heatmap().is_none());
 
-    fn id(id: &str) -> LumpId {
-        id.parse().unwrap()
-    }
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().access_heatmap(4).create(nvm))?;
+        let heatmap = track_assert_some!(
+            storage.metrics().data_region().access_heatmap().cloned(),
+            ErrorKind::Other
+        );
+        assert_eq!(heatmap.buckets(), 4);
+        let capacity = storage.metrics().data_region().capacity_bytes();
+        assert_eq!(heatmap.bucket_range(0).start, 0);
+        assert_eq!(heatmap.bucket_range(3).end, capacity);
+        assert_eq!(heatmap.bucket_range(0).end, heatmap.bucket_range(1).start);
 
-    fn data(data: &str) -> LumpData {
-        LumpData::new_embedded(Vec::from(data)).unwrap()
-    }
+        track!(storage.put(&id("000"), &zeroed_data(1000)))?;
+        track!(storage.put(&id("001"), &zeroed_data(10)))?;
+        track!(storage.get(&id("000")))?;
+        track!(storage.get(&id("000")))?;
+        assert_eq!(heatmap.writes(), vec![2, 0, 0, 0]);
+        assert_eq!(heatmap.reads(), vec![2, 0, 0, 0]);
 
-    fn zeroed_data(size: usize) -> LumpData {
-        let mut data = LumpData::aligned_allocate(size, BlockSize::min()).unwrap();
-        for v in data.as_bytes_mut() {
-            *v = 0;
-        }
-        data
+        heatmap.reset();
+        assert_eq!(heatmap.reads(), vec![0; 4]);
+        Ok(())
     }
 
     #[test]
-    fn open_older_compatible_version_works() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
-        let path = dir.path().join("test.lusf");
-
-        // create
-        let mut header = {
-            let nvm = track!(FileNvm::create(&path, 1024 * 1024))?;
-            let storage = track!(Storage::create(nvm))?;
-            let header = storage.header().clone();
-            assert_eq!(header.major_version, MAJOR_VERSION);
-            assert_eq!(header.minor_version, MINOR_VERSION);
-            header
-        };
-
-        // マイナーバージョンを減らして、ヘッダを上書きする
-        {
-            header.minor_version = header
-                .minor_version
-                .checked_sub(1)
-                .expect("このテストは`MINOR_VERSION >= 1`であることを前提としている");
-            let file = track_any_err!(OpenOptions::new().write(true).open(&path))?;
-            track!(header.write_to(file))?;
+    fn read_ahead_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().read_ahead_size(4096).create(nvm))?;
+        for i in 0..4 {
+            let data = track!(LumpData::new(vec![i as u8; 1000]))?;
+            track!(storage.put(&LumpId::new(i), &data))?;
         }
 
-        // open: マイナーバージョンが最新のものに調整されている
-        {
-            let nvm = track!(FileNvm::open(&path))?;
-            let storage = track!(Storage::open(nvm))?;
-            let header = storage.header().clone();
-            assert_eq!(header.major_version, MAJOR_VERSION);
-            assert_eq!(header.minor_version, MINOR_VERSION);
+        // 二つ目の読み込みで連続性が検出されて、後続の二つは先読みした内容から読み込まれる
+        for i in 0..4 {
+            let data = track!(storage.get(&LumpId::new(i)))?;
+            assert_eq!(data.map(|d| d.into_bytes()), Some(vec![i as u8; 1000]));
         }
+        let metrics = storage.metrics().data_region();
+        assert_eq!(metrics.read_ahead_misses(), 1);
+        assert_eq!(metrics.read_ahead_hits(), 2);
+        assert_eq!(metrics.read_ahead_bytes(), 4096);
 
-        // ファイル上のヘッダも更新されている
-        {
-            let file = track_any_err!(OpenOptions::new().read(true).open(&path))?;
-            let header = track!(StorageHeader::read_from(file))?;
-            assert_eq!(header.major_version, MAJOR_VERSION);
-            assert_eq!(header.minor_version, MINOR_VERSION);
-        }
+        // 先読みした範囲への書き込みが行われた場合には、先読みした内容は破棄される
+        let data = track!(LumpData::new(vec![4; 1000]))?;
+        track!(storage.put(&LumpId::new(4), &data))?;
+        let data = track!(storage.get(&LumpId::new(4)))?;
+        assert_eq!(data.map(|d| d.into_bytes()), Some(vec![4; 1000]));
+        let metrics = storage.metrics().data_region();
+        assert_eq!(metrics.read_ahead_misses(), 2);
+        assert_eq!(metrics.read_ahead_hits(), 2);
         Ok(())
     }
 
     #[test]
-    fn block_size_check_when_create() -> TestResult {
-        // [OK] ストレージとNVMのブロックサイズが等しい
-        let nvm_block_size = track!(BlockSize::new(1024))?;
-        let storage_block_size = track!(BlockSize::new(1024))?;
+    fn warm_up_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        track!(storage.put(&id("000"), &zeroed_data(42)))?;
+        track!(storage.put(&id("001"), &zeroed_data(1000)))?;
 
-        let storage = track!(StorageBuilder::new()
-            .block_size(storage_block_size)
-            .create(memory_nvm(nvm_block_size)))?;
-        assert_eq!(storage.header().block_size, storage_block_size);
+        let report = track!(storage.warm_up(false))?;
+        assert_eq!(report.lumps, 2);
+        assert_eq!(report.touched_bytes, 0);
 
-        // [OK] ストレージがNVMのブロックサイズを包含する
-        let nvm_block_size = track!(BlockSize::new(512))?;
-        let storage_block_size = track!(BlockSize::new(1024))?;
+        let before = storage
+            .metrics()
+            .data_region()
+            .nvm_read_bytes()
+            .foreground();
+        let report = track!(storage.warm_up(true))?;
+        assert_eq!(report.lumps, 2);
+        assert_eq!(report.touched_bytes, 512 * 2);
+        assert_eq!(
+            storage
+                .metrics()
+                .data_region()
+                .nvm_read_bytes()
+                .foreground(),
+            before + 512
+        );
+        assert_eq!(storage.list().len(), 2);
+        Ok(())
+    }
 
-        let storage = track!(StorageBuilder::new()
-            .block_size(storage_block_size)
-            .create(memory_nvm(nvm_block_size)))?;
-        assert_eq!(storage.header().block_size, storage_block_size);
+    #[test]
+    fn list_step_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        for i in 0..5 {
+            assert!(track!(storage.put(&LumpId::new(i), &zeroed_data(42)))?.is_new());
+        }
 
-        // [NG] NVMのブロックサイズが、ストレージのブロックサイズよりも大きい
-        let nvm_block_size = track!(BlockSize::new(1024))?;
-        let storage_block_size = track!(BlockSize::new(512))?;
+        let mut start = Some(LumpId::new(0));
+        assert_eq!(
+            storage.list_step(&mut start, 2),
+            vec![LumpId::new(0), LumpId::new(1)]
+        );
+        assert_eq!(start, Some(LumpId::new(2)));
+        assert_eq!(
+            storage.list_step(&mut start, 2),
+            vec![LumpId::new(2), LumpId::new(3)]
+        );
+        assert_eq!(storage.list_step(&mut start, 2), vec![LumpId::new(4)]);
+        assert_eq!(start, None);
+        assert!(storage.list_step(&mut start, 2).is_empty());
 
-        assert!(StorageBuilder::new()
-            .block_size(storage_block_size)
-            .create(memory_nvm(nvm_block_size))
-            .is_err());
+        let mut range = LumpId::new(1)..LumpId::new(4);
+        assert_eq!(
+            storage.list_range_step(&mut range, 2),
+            vec![LumpId::new(1), LumpId::new(2)]
+        );
+        assert_eq!(storage.list_range_step(&mut range, 2), vec![LumpId::new(3)]);
+        assert!(range.start >= range.end);
+        Ok(())
+    }
 
-        // [NG] ストレージのブロック境界が、NVMのブロック境界に揃っていない
-        let nvm_block_size = track!(BlockSize::new(1024))?;
-        let storage_block_size = track!(BlockSize::new(1536))?;
+    #[test]
+    fn adaptive_journal_gc_works() -> TestResult {
+        let run = |adaptive: bool, live_lumps: u128| -> Result<(u64, i64)> {
+            let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+            let mut builder = StorageBuilder::new();
+            builder.journal_region_ratio(0.05);
+            if adaptive {
+                builder.adaptive_journal_gc(8);
+            }
+            let mut storage = track!(builder.create(nvm))?;
+            let data = track!(LumpData::new_embedded(vec![0; 32]))?;
+            for i in 0..2000 {
+                track!(storage.put(&LumpId::new(i % live_lumps), &data))?;
+            }
+            let metrics = storage.metrics().journal_region();
+            Ok((metrics.gc_dequeued_records(), metrics.gc_debt_bytes()))
+        };
 
-        assert!(StorageBuilder::new()
-            .block_size(storage_block_size)
-            .create(memory_nvm(nvm_block_size))
-            .is_err());
+        let (fixed_dequeued, fixed_debt) = track!(run(false, 200))?;
+        let (adaptive_dequeued, adaptive_debt) = track!(run(true, 200))?;
+        assert!(adaptive_dequeued > fixed_dequeued);
+        assert!(0 < adaptive_debt && adaptive_debt < fixed_debt);
 
+        // 生存しているレコードが多い場合には、追記毎に一回のGCでは回収が追いつかない
+        assert_eq!(
+            run(false, 250).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageFull)
+        );
+        assert!(run(true, 250).is_ok());
         Ok(())
     }
 
     #[test]
-    fn block_size_check_when_open() -> TestResult {
-        // 事前準備: ストレージとNVMのブロックサイズを等しくして、ストレージの初期化(生成)を実施
-        let initial_nvm_block_size = track!(BlockSize::new(1536))?;
-        let storage_block_size = track!(BlockSize::new(1536))?;
-        let mut nvm = memory_nvm(initial_nvm_block_size);
-        assert!(StorageBuilder::new()
-            .block_size(storage_block_size)
-            .create(nvm.clone())
-            .is_ok());
-
-        // [OK]: NVMとストレージのブロックサイズが等しい
-        let storage = track!(Storage::open(nvm.clone()))?;
-        assert_eq!(storage.header().block_size, storage_block_size);
+    fn put_with_deadline_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().journal_sync_interval(2).create(nvm))?;
+        let data = track!(LumpData::new_embedded(vec![0; 32]))?;
+        let syncs = |storage: &Storage<MemoryNvm>| storage.metrics().journal_region().syncs();
 
-        // [OK] ストレージのブロック境界がNVMのブロック境界に揃っている
-        nvm.set_block_size(track!(BlockSize::new(512))?);
-        let storage = track!(Storage::open(nvm.clone()))?;
-        assert_eq!(storage.header().block_size, storage_block_size);
+        // 同期の時期に達しても、`Infinity`の場合には先送りされる
+        for i in 0..4 {
+            let report =
+                track!(storage.put_with_deadline(&LumpId::new(i), &data, Deadline::Infinity))?;
+            assert!(!report.journal_synced());
+        }
+        assert_eq!(syncs(&storage), 0);
+        assert_eq!(storage.metrics().journal_region().deferred_syncs(), 1);
 
-        // [NG] NVMのブロックサイズが、ストレージのブロックサイズよりも大きい
-        nvm.set_block_size(track!(BlockSize::new(2048))?);
-        assert!(Storage::open(nvm.clone()).is_err());
+        // 先送りできるのは同期間隔分まで
+        let report = track!(storage.put_with_deadline(&LumpId::new(4), &data, Deadline::Infinity))?;
+        assert!(report.journal_synced());
+        assert_eq!(syncs(&storage), 1);
 
-        // [NG] ストレージのブロック境界が、NVMのブロック境界に揃っていない
-        nvm.set_block_size(track!(BlockSize::new(1024))?);
-        assert!(Storage::open(nvm).is_err());
+        // 先送りされた同期は、`Infinity`以外のPUTで行われる
+        for i in 5..8 {
+            track!(storage.put_with_deadline(&LumpId::new(i), &data, Deadline::Infinity))?;
+        }
+        assert_eq!(syncs(&storage), 1);
+        let deadline = Deadline::Within(Duration::from_secs(1));
+        let report = track!(storage.put_with_deadline(&LumpId::new(8), &data, deadline))?;
+        assert!(report.journal_synced());
+        assert_eq!(syncs(&storage), 2);
+        assert_eq!(storage.metrics().journal_region().deferred_syncs(), 2);
 
+        // `Immediate`の場合には常に同期される
+        let report =
+            track!(storage.put_with_deadline(&LumpId::new(9), &data, Deadline::Immediate))?;
+        assert!(report.journal_synced());
+        assert_eq!(syncs(&storage), 3);
         Ok(())
     }
 
-    fn memory_nvm(block_size: BlockSize) -> SharedMemoryNvm {
-        SharedMemoryNvm::with_block_size(vec![0; 1024 * 1024], block_size)
-    }
+    #[test]
+    fn write_slice_size_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().write_slice_size(1000).create(nvm))?;
+        assert_eq!(storage.config().write_slice_size, 1000);
+        let slices =
+            |storage: &Storage<MemoryNvm>| storage.metrics().data_region().written_slices();
 
-    fn is_put_with(entry: &JournalEntry, id: &LumpId) -> bool {
-        if let JournalRecord::Put(id_, _) = entry.record {
-            id_ == *id
-        } else {
-            false
-        }
-    }
+        // 通常のPUTでも、サイズを超えるデータは分割して書き込まれる(サイズはブロック境界に切り捨て)
+        track!(storage.put_bytes(&LumpId::new(0), &[1; 2000]))?;
+        assert_eq!(slices(&storage), 4);
+        track!(storage.put_bytes(&LumpId::new(1), &[1; 100]))?;
+        assert_eq!(slices(&storage), 4);
+        assert_eq!(
+            track!(storage.get(&LumpId::new(0)))?.map(|d| d.into_bytes()),
+            Some(vec![1; 2000])
+        );
 
-    fn is_delete_with(entry: &JournalEntry, id: &LumpId) -> bool {
-        if let JournalRecord::Delete(id_) = entry.record {
-            id_ == *id
-        } else {
-            false
+        // 小さなデータや、アライメントされていないデータは分割PUTの対象外
+        let small = track!(storage.allocate_lump_data_with_bytes(&[2; 100]))?;
+        let unaligned = track!(LumpData::new(vec![2; 2000]))?;
+        for data in &[small, unaligned] {
+            let put = track!(storage.start_sliced_put(&LumpId::new(0), data, Deadline::Infinity))?;
+            assert!(put.is_none());
         }
+
+        // 分割PUTの途中では、既存のデータが参照される
+        let data = track!(storage.allocate_lump_data_with_bytes(&[3; 2000]))?;
+        let mut put =
+            track!(storage.start_sliced_put(&LumpId::new(0), &data, Deadline::Immediate))?
+                .expect("Never fails");
+        assert_eq!(put.lump_id(), &LumpId::new(0));
+        let mut steps = 0;
+        let report = loop {
+            steps += 1;
+            if let Some(report) = track!(storage.sliced_put_step(&mut put, &data))? {
+                break report;
+            }
+            assert_eq!(
+                track!(storage.get(&LumpId::new(0)))?.map(|d| d.into_bytes()),
+                Some(vec![1; 2000])
+            );
+        };
+        assert_eq!(steps, 4);
+        assert!(!report.is_new());
+        assert_eq!(report.allocated_blocks(), 4);
+        assert!(report.journal_synced());
+        assert_eq!(slices(&storage), 8);
+        assert_eq!(
+            track!(storage.get(&LumpId::new(0)))?.map(|d| d.into_bytes()),
+            Some(vec![3; 2000])
+        );
+        assert_eq!(storage.list(), vec![LumpId::new(0), LumpId::new(1)]);
+
+        // 古いデータの部分領域は解放されている
+        let usage = storage.metrics().data_region().usage_bytes();
+        assert_eq!(usage, 512 * 5);
+        Ok(())
     }
 
     #[test]
-    fn full_gc_works() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
+    fn journal_admission_control_works() -> TestResult {
+        let create = |action: Option<JournalAdmissionAction>| -> Result<Storage<MemoryNvm>> {
+            let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+            let mut builder = StorageBuilder::new();
+            builder.journal_region_ratio(0.05);
+            if let Some(action) = action {
+                builder.journal_admission_control(0.5, Duration::from_secs(0), action);
+            }
+            track!(builder.create(nvm))
+        };
+        let run = |storage: &mut Storage<MemoryNvm>| -> Result<()> {
+            let data = track!(LumpData::new_embedded(vec![0; 32]))?;
+            for i in 0..2000 {
+                track!(storage.put(&LumpId::new(i % 250), &data))?;
+            }
+            Ok(())
+        };
 
-        let nvm = track!(FileNvm::create(
-            dir.path().join("test.lusf"),
-            BlockSize::min().ceil_align(1024 * 1024)
-        ))?;
-        let mut storage = track!(Storage::create(nvm))?;
+        // 受付制御が無効な場合には、GCが追いつかずにジャーナル領域が満杯になる
+        let mut storage = track!(create(None))?;
+        assert_eq!(
+            run(&mut storage).err().map(|e| *e.kind()),
+            Some(ErrorKind::StorageFull)
+        );
 
-        // ストレージへの操作で、小規模GCが自動で発生しないようにする
-        storage.set_automatic_gc_mode(false);
+        // 全体GCを強制する場合には、満杯にはならない
+        let mut storage = track!(create(Some(JournalAdmissionAction::ForceGc)))?;
+        assert_eq!(
+            storage.config().journal_admission_action,
+            Some(JournalAdmissionAction::ForceGc)
+        );
+        track!(run(&mut storage))?;
+        assert!(storage.metrics().journal_region().admission_forced_gcs() > 0);
+        assert_eq!(storage.metrics().journal_region().admission_rejections(), 0);
 
-        assert!(storage.put(&id("000"), &zeroed_data(42))?);
-        assert!(storage.put(&id("010"), &zeroed_data(42))?);
+        // 拒否する場合には、満杯になる前に専用のエラーで失敗する
+        let mut storage = track!(create(Some(JournalAdmissionAction::Reject)))?;
+        assert_eq!(
+            run(&mut storage).err().map(|e| *e.kind()),
+            Some(ErrorKind::JournalGcLagging)
+        );
+        assert_eq!(storage.metrics().journal_region().admission_rejections(), 1);
 
-        let entries = storage.journal_snapshot().unwrap().entries;
+        // GCが進めば、再び受け付けられるようになる
+        track!(storage.journal_gc())?;
+        let data = track!(LumpData::new_embedded(vec![1; 32]))?;
+        track!(storage.put(&LumpId::new(0), &data))?;
 
-        assert_eq!(entries.len(), 2);
-        assert!(is_put_with(entries.get(0).unwrap(), &id("000")));
-        assert!(is_put_with(entries.get(1).unwrap(), &id("010")));
+        // 閾値が範囲外
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let result = StorageBuilder::new()
+            .journal_admission_control(1.5, Duration::from_secs(1), JournalAdmissionAction::Reject)
+            .create(nvm);
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
 
-        storage.journal_gc().unwrap();
+    #[test]
+    fn usage_summary_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm.clone()))?;
+        let prefixed = |prefix: u128, n: u128| LumpId::new((prefix << 120) | n);
+        for i in 0..10 {
+            track!(storage.put(&prefixed(1, i), &zeroed_data(600)))?;
+            track!(storage.put(&prefixed(2, i), &zeroed_data(10)))?;
+        }
+        let embedded = track!(LumpData::new_embedded(vec![0; 10]))?;
+        track!(storage.put(&prefixed(2, 0), &embedded))?;
+        track!(storage.delete(&prefixed(1, 0)))?;
+        track!(storage.delete_range(prefixed(2, 5)..prefixed(2, 8)))?;
 
-        let new_entries = storage.journal_snapshot().unwrap().entries;
+        let check = |storage: &mut Storage<SharedMemoryNvm>| -> Result<()> {
+            let summary = storage.metrics().usage_summary().clone();
+            for prefix in 0..4 {
+                let range = track!(LumpRange::prefix(prefix, 8))?;
+                let usage = track_assert_some!(summary.usage_range(range), ErrorKind::Other);
+                assert_eq!(usage.bytecount(), storage.usage_range(range).bytecount());
+                assert_eq!(
+                    summary.lumps_prefix(prefix as u8),
+                    storage.list_range(range).len() as u64
+                );
+            }
+            let all = track_assert_some!(summary.usage_range(..), ErrorKind::Other);
+            assert_eq!(all.bytecount(), storage.usage_range(..).bytecount());
+            Ok(())
+        };
+        track!(check(&mut storage))?;
+        assert_eq!(storage.metrics().usage_summary().lumps_prefix(1), 9);
+        assert_eq!(
+            storage
+                .metrics()
+                .usage_summary()
+                .usage_prefix(2)
+                .bytecount(),
+            Some(10 + 6 * 512)
+        );
+        assert!(storage
+            .metrics()
+            .usage_summary()
+            .usage_range(prefixed(1, 0)..prefixed(1, 5))
+            .is_none());
 
-        for (e1, e2) in entries.iter().zip(new_entries.iter()) {
-            assert_eq!(e1.record, e2.record);
-            // 注意
-            // GCによりジャーナル領域内でのエントリ移動が生じているため、次は成立しない
-            // assert_eq!(e1.start, e2.start);
-        }
+        // 再起動後には、復元されたインデックスから再構築される
+        let mut storage = track!(Storage::open(nvm))?;
+        track!(check(&mut storage))?;
+        Ok(())
+    }
 
-        assert!(storage.delete(&id("000"))?);
-        assert!(storage.delete(&id("010"))?);
+    #[test]
+    fn scan_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        for i in 0..5 {
+            track!(storage.put(&LumpId::new(i), &zeroed_data(i as usize + 1)))?;
+        }
+        let embedded = track!(LumpData::new_embedded(vec![7; 3]))?;
+        track!(storage.put(&LumpId::new(10), &embedded))?;
+        track!(storage.put(&LumpId::MAX, &zeroed_data(4)))?;
 
-        let entries = storage.journal_snapshot().unwrap().entries;
+        // 隣接するデータ領域のlump群はまとめて読み込まれる
+        let data = storage.metrics().data_region().clone();
+        let lumps = track!(storage.scan(..).collect::<Result<Vec<_>>>())?;
+        assert_eq!(
+            lumps.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 10, u128::MAX]
+                .into_iter()
+                .map(LumpId::new)
+                .collect::<Vec<_>>()
+        );
+        for (lump_id, data) in &lumps {
+            let expected = track_assert_some!(track!(storage.get(lump_id))?, ErrorKind::Other);
+            assert_eq!(data.as_bytes(), expected.as_bytes());
+        }
+        assert_eq!(data.nvm_read_bytes().foreground(), 6 * 512 * 2);
 
-        assert_eq!(entries.len(), 4);
+        let mut range = Some(LumpRange::new(LumpId::new(1), LumpId::new(11)));
+        let lumps = track!(storage.scan_step(&mut range, 3))?;
+        assert_eq!(lumps.len(), 3);
+        assert_eq!(range, Some(LumpRange::new(LumpId::new(4), LumpId::new(11))));
+        let lumps = track!(storage.scan_step(&mut range, 3))?;
+        assert_eq!(lumps[1].0, LumpId::new(10));
+        assert_eq!(lumps[1].1.as_bytes(), &[7; 3][..]);
+        assert_eq!(range, None);
+        assert!(track!(storage.scan_step(&mut range, 3))?.is_empty());
 
-        assert!(is_put_with(entries.get(0).unwrap(), &id("000")));
-        assert!(is_put_with(entries.get(1).unwrap(), &id("010")));
-        assert!(is_delete_with(entries.get(2).unwrap(), &id("000")));
-        assert!(is_delete_with(entries.get(3).unwrap(), &id("010")));
+        let lumps = track!(storage
+            .scan(LumpId::MAX..=LumpId::MAX)
+            .collect::<Result<Vec<_>>>())?;
+        assert_eq!(lumps.len(), 1);
+        Ok(())
+    }
 
-        storage.journal_gc().unwrap();
+    #[test]
+    fn check_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let mut storage = track!(Storage::create(nvm))?;
+        for i in 0..100 {
+            let data = if i % 2 == 0 {
+                zeroed_data(42)
+            } else {
+                track!(storage.allocate_lump_data_with_bytes(b"foo"))?
+            };
+            track!(storage.put(&LumpId::new(i), &data))?;
+        }
+        for i in 0..30 {
+            track!(storage.delete(&LumpId::new(i * 3)))?;
+        }
+        for _ in 0..10 {
+            track!(storage.run_side_job_once())?;
+        }
 
-        let entries = storage.journal_snapshot().unwrap().entries;
+        let report = track!(storage.check(CheckLevel::Journal))?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.checked_lumps, 70);
 
-        assert_eq!(entries.len(), 0);
+        // インデックスを改竄して、データ部分領域を重複させる
+        let portion = storage.lump_index.get(&LumpId::new(2)).unwrap();
+        storage.lump_index.insert(LumpId::new(1000), portion);
 
+        // ジャーナルの読み込みも、インデックスの検査と同様に`7`エントリずつ分割して行われる
+        let mut checker = StorageChecker::new(CheckLevel::Journal);
+        let mut steps = 0;
+        while !track!(storage.check_step(&mut checker, 7))? {
+            steps += 1;
+        }
+        assert!(steps >= (100 + 30) / 7 + 71 / 7, "steps={}", steps);
+        let report = checker.into_report();
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.overlapping_portions,
+            vec![(LumpId::new(2), LumpId::new(1000))]
+        );
+        assert_eq!(report.journal_mismatches, vec![LumpId::new(1000)]);
+        assert!(report.index_usage_bytes > report.allocator_usage_bytes);
         Ok(())
     }
 
@@ -737,7 +5067,11 @@ mod tests {
             dir.path().join("test.lusf"),
             BlockSize::min().ceil_align(1024 * 400)
         ))?;
-        let mut storage = track!(StorageBuilder::new().journal_region_ratio(0.01).create(nvm))?;
+        // 以降のジャーナルの位置は、単一のヘッダスロットを前提としている
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.01)
+            .journal_header_slots(1)
+            .create(nvm))?;
         storage.set_automatic_gc_mode(false);
 
         {
@@ -746,7 +5080,7 @@ mod tests {
         }
 
         for i in 0..60 {
-            assert!(storage.put(&id(&i.to_string()), &zeroed_data(42))?);
+            assert!(storage.put(&id(&i.to_string()), &zeroed_data(42))?.is_new());
         }
         for i in 0..20 {
             assert!(storage.delete(&id(&i.to_string()))?);
@@ -795,7 +5129,11 @@ mod tests {
             dir.path().join("test.lusf"),
             BlockSize::min().ceil_align(1024 * 100 * 4)
         ))?;
-        let mut storage = track!(StorageBuilder::new().journal_region_ratio(0.01).create(nvm))?;
+        // 以降のジャーナルの位置は、単一のヘッダスロットを前提としている
+        let mut storage = track!(StorageBuilder::new()
+            .journal_region_ratio(0.01)
+            .journal_header_slots(1)
+            .create(nvm))?;
         assert_eq!(storage.header().journal_region_size, 4096);
         // putやdeleteなどに伴う自動GCをoffにする（コードと説明の簡単さのためでonのままでも再現できる）。
         storage.set_automatic_gc_mode(false);
@@ -1,26 +1,10 @@
 //! Data Portion, Journal Portion, and Portion
 
+pub use crate::format::DataPortion;
+
 use crate::block::BlockSize;
 use crate::storage::Address;
 
-/// データ領域内の部分領域を示すための構造体.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct DataPortion {
-    /// 部分領域の開始位置（ブロック単位）
-    pub start: Address,
-
-    /// 部分領域の長さ（ブロック単位）
-    pub len: u16,
-}
-impl DataPortion {
-    /// 部分領域の終端位置を返す.  
-    /// **注意**: DataPortionは [start, end) の領域を用いるため、
-    /// end部には書き込みは行われていない。
-    pub fn end(&self) -> Address {
-        self.start + Address::from(u32::from(self.len))
-    }
-}
-
 /// ジャーナル領域内の部分領域を示すための構造体.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct JournalPortion {
@@ -0,0 +1,283 @@
+//! ジャーナル領域が失われたストレージから、lumpのデータを救出するための機能.
+//!
+//! このモジュールの機能は、通常のストレージの操作からは一切使用されない.
+//! ジャーナル(i.e., インデックスの情報源)が破壊され、`Storage::open`が行えなくなった場合の、
+//! 最後の手段としてのデータ救出ツールでの利用を想定している.
+//!
+//! データ領域に格納されている各lumpの末尾には、そのサイズを復元するためのトレイラが埋め込まれているので、
+//! データ領域をブロック単位で走査し、妥当なトレイラを有するブロックを探すことで、
+//! lumpが格納されている可能性がある部分領域(候補)を列挙する.
+//!
+//! ただし、トレイラの判定は経験則に基づくものであるため、以下の点には注意が必要:
+//!
+//! - lumpのIDは(ジャーナルにのみ記録されているため)復元できない
+//! - 削除済みのlumpのデータや、任意のデータのブロックが、偶然に候補として検出されることがある
+//! - 通常の形式のlumpの開始位置はトレイラからは分からないため、直前の候補の直後から始まるものとみなされる.
+//!   そのため、複数のブロックに跨るlumpの途中で候補が区切られてしまうことがある
+//! - 全てのバイトがゼロのブロックは、未使用のブロックとみなされる
+//!
+//! なお、疎な形式(`StorageBuilder::sparse_lumps`)のlumpについては、トレイラから正確な開始位置が分かるため、
+//! その範囲に含まれる誤検出された候補は取り除かれる.
+use byteorder::{BigEndian, ByteOrder};
+use std::cmp;
+use std::io::SeekFrom;
+
+use crate::block::{AlignedBytes, BlockSize};
+use crate::nvm::NonVolatileMemory;
+use crate::storage::data_region::{
+    DataRegionLumpData, LUMP_DATA_TRAILER_SIZE, SPARSE_EXTENT_SIZE, SPARSE_LUMP_DATA_MARKER,
+    SPARSE_LUMP_DATA_TRAILER_SIZE,
+};
+use crate::storage::Address;
+use crate::storage::{DataPortion, StorageHeader};
+use crate::Result;
+
+/// データ領域の走査時に、一度に読み込むブロック数.
+const SCAN_CHUNK_BLOCKS: u64 = 2048;
+
+/// データ領域内で見つかった、lumpのデータが格納されている可能性がある部分領域.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortionCandidate {
+    /// データ領域内での部分領域.
+    pub portion: DataPortion,
+
+    /// トレイラから算出されたデータのサイズ(バイト単位).
+    pub data_size: usize,
+
+    /// 疎な形式(`StorageBuilder::sparse_lumps`)のトレイラを有するかどうか.
+    pub sparse: bool,
+}
+
+/// `nvm`のデータ領域を走査して、lumpのデータが格納されている可能性がある部分領域の一覧を返す.
+///
+/// ジャーナル領域の内容は一切参照しない.
+/// ヘッダが壊れている場合には、末尾のバックアップ(`StorageBuilder::backup_header`)が使用される.
+///
+/// 結果は部分領域の位置順に並んでいる.
+pub fn scan_portion_candidates<N>(mut nvm: N) -> Result<Vec<PortionCandidate>>
+where
+    N: NonVolatileMemory,
+{
+    let header = track!(StorageHeader::read_from_nvm(&mut nvm))?;
+    let (_, mut data_nvm) = track!(header.split_regions(nvm))?;
+    let block_len = u64::from(header.block_size.as_u16());
+    let total_blocks = header.data_region_size / block_len;
+
+    let mut candidates = Vec::new();
+    let mut start = 0; // 次の候補の開始位置
+    let mut position = 0;
+    track_io!(data_nvm.seek(SeekFrom::Start(0)))?;
+    while position < total_blocks {
+        let blocks = cmp::min(SCAN_CHUNK_BLOCKS, total_blocks - position);
+        let buf = track!(data_nvm.aligned_read_bytes((blocks * block_len) as usize))?;
+        for (i, block) in buf.chunks(block_len as usize).enumerate() {
+            let end = position + i as u64 + 1;
+            if block.iter().all(|&b| b == 0) {
+                start = end;
+                continue;
+            }
+
+            // 部分領域の長さはu16で表現可能な範囲に限られる
+            start = cmp::max(start, end.saturating_sub(u64::from(u16::MAX)));
+            if let Some(candidate) = inspect_trailer(block, header.block_size, start, end) {
+                // 疎な形式の場合には正確な開始位置が分かるので、それと重なる候補は誤検出として取り除く
+                let candidate_start = candidate.portion.start;
+                while candidates
+                    .last()
+                    .is_some_and(|c: &PortionCandidate| c.portion.end() > candidate_start)
+                {
+                    candidates.pop();
+                }
+                candidates.push(candidate);
+                start = end;
+            }
+        }
+        position += blocks;
+    }
+    Ok(candidates)
+}
+
+/// `scan_portion_candidates`で見つかった候補の部分領域から、lumpのデータを読み込む.
+///
+/// 疎な形式の場合には、省かれていたゼロブロックも復元された上で返される.
+pub fn read_portion_candidate<N>(mut nvm: N, candidate: &PortionCandidate) -> Result<Vec<u8>>
+where
+    N: NonVolatileMemory,
+{
+    let header = track!(StorageHeader::read_from_nvm(&mut nvm))?;
+    let (_, mut data_nvm) = track!(header.split_regions(nvm))?;
+    let block_len = u64::from(header.block_size.as_u16());
+
+    let portion = candidate.portion;
+    track_io!(data_nvm.seek(SeekFrom::Start(portion.start.as_u64() * block_len)))?;
+    let buf = AlignedBytes::new(
+        (u64::from(portion.len) * block_len) as usize,
+        header.block_size,
+    );
+    let data = track!(DataRegionLumpData::read_from(&mut data_nvm, buf))?;
+    Ok(data.as_bytes().to_owned())
+}
+
+/// `[start..end)`の範囲のブロック群の末尾ブロック`block`が、妥当なトレイラを有しているかどうかを判定する.
+///
+/// 妥当な場合には、対応する候補が返される.
+fn inspect_trailer(
+    block: &[u8],
+    block_size: BlockSize,
+    start: u64,
+    end: u64,
+) -> Option<PortionCandidate> {
+    let block_len = block.len();
+    let marker = BigEndian::read_u16(&block[block_len - LUMP_DATA_TRAILER_SIZE..]);
+    if marker != SPARSE_LUMP_DATA_MARKER {
+        // パディングは常にブロックサイズ未満
+        let padding_len = usize::from(marker);
+        let len = end - start;
+        let size = len as usize * block_len;
+        if padding_len >= block_len || padding_len + LUMP_DATA_TRAILER_SIZE > size {
+            return None;
+        }
+        let data_size = size - LUMP_DATA_TRAILER_SIZE - padding_len;
+        return Some(candidate(start, len, data_size, false));
+    }
+
+    let trailer_offset = block_len - SPARSE_LUMP_DATA_TRAILER_SIZE;
+    let data_size = BigEndian::read_u32(&block[trailer_offset..]) as usize;
+    let extent_count = BigEndian::read_u32(&block[trailer_offset + 4..]) as usize;
+    let data_blocks = data_size.div_ceil(block_len);
+    if data_size == 0 || extent_count == 0 || extent_count > data_blocks {
+        return None;
+    }
+
+    let metadata_len = extent_count * SPARSE_EXTENT_SIZE + SPARSE_LUMP_DATA_TRAILER_SIZE;
+    if metadata_len > block_len {
+        // エクステント群が末尾ブロックに収まっていないので、範囲全体を候補とする
+        let len = end - start;
+        if metadata_len as u64 > len * block_len as u64 {
+            return None;
+        }
+        return Some(candidate(start, len, data_size, true));
+    }
+
+    // エクステント群の内容から、部分領域の正確な長さが分かる
+    let mut stored_blocks = 0;
+    let mut next_block = 0;
+    let extents = &block[trailer_offset - extent_count * SPARSE_EXTENT_SIZE..trailer_offset];
+    for extent in extents.chunks(SPARSE_EXTENT_SIZE) {
+        let first = BigEndian::read_u32(extent) as usize;
+        let count = BigEndian::read_u32(&extent[4..]) as usize;
+        if count == 0 || first < next_block || first + count > data_blocks {
+            return None;
+        }
+        next_block = first + count;
+        stored_blocks += count;
+    }
+    let len =
+        block_size.ceil_align((stored_blocks * block_len + metadata_len) as u64) / block_len as u64;
+    if len > end {
+        return None;
+    }
+    Some(candidate(end - len, len, data_size, true))
+}
+
+fn candidate(start: u64, len: u64, data_size: usize, sparse: bool) -> PortionCandidate {
+    PortionCandidate {
+        portion: DataPortion {
+            start: Address::from_u64(start).expect("Never fails"),
+            len: len as u16,
+        },
+        data_size,
+        sparse,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::lump::{LumpData, LumpId};
+    use crate::nvm::{MemoryNvm, SharedMemoryNvm};
+    use crate::storage::portion::Portion;
+    use crate::storage::{Storage, StorageBuilder};
+
+    #[test]
+    fn scan_portion_candidates_works() -> TestResult {
+        let nvm = SharedMemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = track!(StorageBuilder::new().sparse_lumps(true).create(nvm.clone()))?;
+        let lumps = vec![
+            (LumpId::new(0), vec![1; 100]),
+            (LumpId::new(1), vec![2; 3000]),
+            (LumpId::new(2), {
+                // 先頭と末尾のみが非ゼロのデータは疎な形式で格納される
+                let mut data = vec![0; 10000];
+                data[0] = 3;
+                data[9999] = 3;
+                data
+            }),
+            (LumpId::new(3), vec![4; 511]),
+        ];
+        let mut portions = Vec::new();
+        for (lump_id, bytes) in &lumps {
+            track!(storage.put(lump_id, &track!(LumpData::new(bytes.clone()))?))?;
+            match storage.lump_index.get(lump_id) {
+                Some(Portion::Data(portion)) => portions.push(portion),
+                _ => panic!(),
+            }
+        }
+        let header = storage.header().clone();
+        std::mem::drop(storage);
+
+        // ジャーナル領域を破壊する
+        let mut nvm = nvm;
+        track_io!(nvm.seek(SeekFrom::Start(header.region_size())))?;
+        track_io!(nvm.write_all(&vec![0; header.journal_region_size as usize]))?;
+        let bytes = nvm.to_bytes();
+        assert!(Storage::open(MemoryNvm::new(bytes.clone())).map_or(true, |s| s.list().is_empty()));
+
+        let candidates = track!(scan_portion_candidates(MemoryNvm::new(bytes.clone())))?;
+        assert_eq!(candidates.len(), lumps.len());
+        for ((candidate, portion), (_, data)) in candidates.iter().zip(&portions).zip(&lumps) {
+            assert_eq!(candidate.portion, *portion);
+            assert_eq!(candidate.data_size, data.len());
+            let recovered = track!(read_portion_candidate(
+                MemoryNvm::new(bytes.clone()),
+                candidate
+            ))?;
+            assert_eq!(recovered, *data);
+        }
+        assert!(!candidates[0].sparse);
+        assert!(candidates[2].sparse);
+        Ok(())
+    }
+
+    #[test]
+    fn inspect_trailer_works() {
+        let block_size = BlockSize::min();
+        let mut block = vec![0xFF; 512];
+
+        // パディングのサイズがブロックサイズ以上のトレイラは妥当ではない
+        BigEndian::write_u16(&mut block[510..], 512);
+        assert_eq!(inspect_trailer(&block, block_size, 0, 2), None);
+
+        // パディングが直前のブロックに跨る場合には、一ブロックのみの候補にはならない
+        BigEndian::write_u16(&mut block[510..], 511);
+        assert_eq!(inspect_trailer(&block, block_size, 0, 1), None);
+        assert!(inspect_trailer(&block, block_size, 0, 2).is_some());
+
+        BigEndian::write_u16(&mut block[510..], 10);
+        let candidate = inspect_trailer(&block, block_size, 3, 5).unwrap();
+        assert_eq!(candidate.portion.start.as_u64(), 3);
+        assert_eq!(candidate.portion.len, 2);
+        assert_eq!(candidate.data_size, 1024 - 2 - 10);
+
+        // エクステントの範囲がデータサイズを超えている疎な形式のトレイラは妥当ではない
+        BigEndian::write_u32(&mut block[494..], 0);
+        BigEndian::write_u32(&mut block[498..], 3);
+        BigEndian::write_u32(&mut block[502..], 1000);
+        BigEndian::write_u32(&mut block[506..], 1);
+        BigEndian::write_u16(&mut block[510..], SPARSE_LUMP_DATA_MARKER);
+        assert_eq!(inspect_trailer(&block, block_size, 0, 5), None);
+    }
+}
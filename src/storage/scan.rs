@@ -0,0 +1,57 @@
+use crate::lump::{LumpData, LumpId, LumpRange};
+use crate::nvm::NonVolatileMemory;
+use crate::storage::Storage;
+use crate::Result;
+use std::vec;
+
+/// `StorageScan`が一度の`Storage::scan_step`呼び出しで読み込むlumpの最大数.
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// ストレージに保存されているlumpのIDとデータを、ID順に走査するイテレータ.
+///
+/// `Storage::scan`メソッドによって生成される.
+#[derive(Debug)]
+pub struct StorageScan<'a, N>
+where
+    N: NonVolatileMemory,
+{
+    storage: &'a mut Storage<N>,
+    range: Option<LumpRange>,
+    batch: vec::IntoIter<(LumpId, LumpData)>,
+}
+impl<'a, N> StorageScan<'a, N>
+where
+    N: NonVolatileMemory,
+{
+    pub(crate) fn new(storage: &'a mut Storage<N>, range: LumpRange) -> Self {
+        StorageScan {
+            storage,
+            range: Some(range),
+            batch: Vec::new().into_iter(),
+        }
+    }
+}
+impl<'a, N> Iterator for StorageScan<'a, N>
+where
+    N: NonVolatileMemory,
+{
+    type Item = Result<(LumpId, LumpData)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(lump) = self.batch.next() {
+                return Some(Ok(lump));
+            }
+            self.range?;
+            match track!(self.storage.scan_step(&mut self.range, SCAN_BATCH_SIZE)) {
+                Err(e) => {
+                    self.range = None;
+                    return Some(Err(e));
+                }
+                Ok(lumps) => {
+                    self.batch = lumps.into_iter();
+                }
+            }
+        }
+    }
+}
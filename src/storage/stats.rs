@@ -0,0 +1,96 @@
+//! ストレージの統計情報.
+use std::time::Duration;
+
+/// ストレージの統計情報のスナップショット.
+///
+/// `Storage::stats`によって取得される.
+/// 各値は同一時点のものであり、メトリクスを個別に読み出した場合とは異なり、互いに整合している.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageStats {
+    /// 保存されているlumpの数.
+    pub lumps: u64,
+
+    /// ジャーナル領域の統計情報.
+    pub journal: JournalStats,
+
+    /// データ領域の統計情報.
+    pub data: DataRegionStats,
+}
+
+/// ジャーナル領域の統計情報.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalStats {
+    /// ジャーナル領域の容量(バイト単位).
+    pub capacity_bytes: u64,
+
+    /// ジャーナル領域の使用量(バイト単位).
+    pub usage_bytes: u64,
+
+    /// リングバッファの未開放開始位置.
+    pub unreleased_head: u64,
+
+    /// リングバッファの開始位置.
+    pub head: u64,
+
+    /// リングバッファの末尾位置.
+    pub tail: u64,
+
+    /// まだNVMに同期されていないバイト数.
+    pub unsynced_bytes: u64,
+}
+
+/// データ領域の統計情報.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataRegionStats {
+    /// データ領域の容量(バイト単位).
+    pub capacity_bytes: u64,
+
+    /// データ領域の使用量(バイト単位).
+    ///
+    /// 解放が保留されている部分領域の分も含まれる.
+    pub usage_bytes: u64,
+
+    /// 空き領域の数.
+    pub free_portions: u64,
+
+    /// 最大の空き領域のサイズ(バイト単位).
+    ///
+    /// 一度に格納可能なlumpのサイズの上限の目安となる.
+    pub largest_free_portion_bytes: u64,
+}
+impl DataRegionStats {
+    /// 空き容量(バイト単位)を返す.
+    pub fn free_bytes(&self) -> u64 {
+        self.capacity_bytes.saturating_sub(self.usage_bytes)
+    }
+
+    /// 空き領域の断片化の度合いを、`0.0`以上`1.0`以下の値で返す.
+    ///
+    /// 空き容量の内、最大の空き領域に含まれない部分の割合として計算される.
+    /// 空き容量が存在しない場合には`0.0`となる.
+    pub fn fragmentation(&self) -> f64 {
+        let free = self.free_bytes();
+        if free == 0 {
+            0.0
+        } else {
+            1.0 - (self.largest_free_portion_bytes.min(free) as f64 / free as f64)
+        }
+    }
+}
+
+/// ウォームアップの結果.
+///
+/// `Storage::warm_up`によって取得される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmUpReport {
+    /// 走査したインデックス上のlumpの数.
+    pub lumps: u64,
+
+    /// ジャーナル領域およびデータ領域の先頭ブロックから読み込んだバイト数.
+    ///
+    /// 領域の読み込みが指定されていない場合は`0`となる.
+    pub touched_bytes: u64,
+
+    /// ウォームアップに要した時間.
+    pub elapsed: Duration,
+}
@@ -0,0 +1,127 @@
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::block::BlockSize;
+use crate::lump::{LumpId, LumpRange};
+use crate::storage::portion::Portion;
+use crate::storage::StorageUsage;
+
+/// 接頭辞として扱う、`LumpId`の上位ビットの数.
+const PREFIX_BITS: u32 = 8;
+
+/// 接頭辞の種類数.
+const PREFIXES: usize = 1 << PREFIX_BITS;
+
+/// `LumpId`の接頭辞(最上位バイト)毎の、ストレージ使用量の要約.
+///
+/// ストレージ内のインデックスの更新(PUTやDELETE等)に合わせて逐次更新されるため、
+/// 接頭辞の境界に沿った範囲の使用量であれば、インデックスを走査することなく取得することができる.
+///
+/// インスタンスは`StorageMetrics::usage_summary`経由で取得でき、複数のスレッドから共有可能なので、
+/// 例えばデバイスを利用している側のスレッドから、コマンドを発行せずに参照することもできる
+/// (`DeviceHandle::cached_usage_range`も参照のこと).
+///
+/// # 一貫性
+///
+/// 各値は、ストレージ(デバイスの場合はその実行スレッド)が処理を完了した更新のみを反映する.
+/// そのため、ある時点での値と、同時点で`Storage::usage_range`が返す値との差は、
+/// その時点で処理中の更新系コマンドが対象とするlumpのサイズ(上書きの場合には新旧の差分)の合計以下となる.
+/// 処理中のコマンドが無い場合には、両者は一致する.
+///
+/// また複数の接頭辞にまたがる範囲の使用量は、接頭辞毎の値を個別に読み込んで合計したものであり、
+/// ある単一の時点でのスナップショットとなることは保証されない.
+#[derive(Debug, Clone)]
+pub struct UsageSummary {
+    inner: Arc<Inner>,
+}
+impl UsageSummary {
+    pub(crate) fn new(block_size: BlockSize) -> Self {
+        UsageSummary {
+            inner: Arc::new(Inner {
+                block_size,
+                bytes: (0..PREFIXES).map(|_| AtomicU64::new(0)).collect(),
+                lumps: (0..PREFIXES).map(|_| AtomicU64::new(0)).collect(),
+            }),
+        }
+    }
+
+    /// 最上位バイトが`prefix`のlump群が占有するバイト数を返す.
+    pub fn usage_prefix(&self, prefix: u8) -> StorageUsage {
+        StorageUsage::approximate(self.inner.bytes[prefix as usize].load(Ordering::Relaxed))
+    }
+
+    /// 最上位バイトが`prefix`のlumpの数を返す.
+    pub fn lumps_prefix(&self, prefix: u8) -> u64 {
+        self.inner.lumps[prefix as usize].load(Ordering::Relaxed)
+    }
+
+    /// 指定された範囲のlump群が占有するバイト数を返す.
+    ///
+    /// 範囲の両端が接頭辞の境界に揃っていない場合には`None`が返される.
+    /// その場合には`Storage::usage_range`(ないし`DeviceRequest::usage_range`)を使用すること.
+    pub fn usage_range<R: Into<LumpRange>>(&self, range: R) -> Option<StorageUsage> {
+        let range = range.into();
+        if range.is_empty() {
+            return Some(StorageUsage::approximate(0));
+        }
+        let shift = 128 - PREFIX_BITS;
+        let low_mask = (1u128 << shift) - 1;
+        let start = range.start().as_u128();
+        if start & low_mask != 0 {
+            return None;
+        }
+        let last = match range.end() {
+            Bound::Included(end) if end.as_u128() & low_mask == low_mask => end.as_u128() >> shift,
+            Bound::Excluded(end) if end.as_u128() & low_mask == 0 => (end.as_u128() >> shift) - 1,
+            _ => return None,
+        };
+        let bytes = ((start >> shift)..=last)
+            .map(|prefix| self.inner.bytes[prefix as usize].load(Ordering::Relaxed))
+            .sum();
+        Some(StorageUsage::approximate(bytes))
+    }
+
+    pub(crate) fn add(&self, lump_id: &LumpId, portion: &Portion) {
+        let i = Self::index(lump_id);
+        let len = u64::from(portion.len(self.inner.block_size));
+        self.inner.bytes[i].fetch_add(len, Ordering::Relaxed);
+        self.inner.lumps[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sub(&self, lump_id: &LumpId, portion: &Portion) {
+        let i = Self::index(lump_id);
+        let len = u64::from(portion.len(self.inner.block_size));
+        self.inner.bytes[i].fetch_sub(len, Ordering::Relaxed);
+        self.inner.lumps[i].fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// 全ての値を`entries`の内容で置き換える.
+    pub(crate) fn reset<I>(&self, entries: I)
+    where
+        I: Iterator<Item = (LumpId, Portion)>,
+    {
+        let mut bytes = [0; PREFIXES];
+        let mut lumps = [0; PREFIXES];
+        for (lump_id, portion) in entries {
+            let i = Self::index(&lump_id);
+            bytes[i] += u64::from(portion.len(self.inner.block_size));
+            lumps[i] += 1;
+        }
+        for i in 0..PREFIXES {
+            self.inner.bytes[i].store(bytes[i], Ordering::Relaxed);
+            self.inner.lumps[i].store(lumps[i], Ordering::Relaxed);
+        }
+    }
+
+    fn index(lump_id: &LumpId) -> usize {
+        (lump_id.as_u128() >> (128 - PREFIX_BITS)) as usize
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    block_size: BlockSize,
+    bytes: Vec<AtomicU64>,
+    lumps: Vec<AtomicU64>,
+}